@@ -0,0 +1,53 @@
+//! An L2 example, exchanging Ethernet frames without the IP stack
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use async_dpdk::ether::{EthPacket, Ethernet, ETHER_BROADCAST};
+use async_dpdk::{eal, net_dev};
+
+/// A local experimental Ether type.
+const ETHER_TYPE: u16 = 0x88b5;
+
+const MSG: &str = "Hello from L2!";
+
+async fn sender() {
+    // Bind to the second NIC.
+    let eth = Ethernet::bind(IpAddr::from([10, 2, 3, 1]), None).unwrap();
+    let mut payload = eth.alloc_mbuf().unwrap();
+    payload
+        .append(MSG.len())
+        .unwrap()
+        .copy_from_slice(MSG.as_bytes());
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    // Broadcast the frame.
+    let pkt = EthPacket::new(ETHER_BROADCAST, eth.mac_addr(), ETHER_TYPE, payload);
+    let _ = eth.send(pkt).await.unwrap();
+    eth.flush().await.unwrap();
+}
+
+async fn receiver() {
+    // Bind to the first NIC, only receiving frames with our Ether type.
+    let eth = Ethernet::bind(IpAddr::from([10, 2, 3, 0]), Some(ETHER_TYPE)).unwrap();
+    let pkt = eth.recv().await.unwrap();
+    assert_eq!(pkt.ether_type(), ETHER_TYPE);
+    assert_eq!(pkt.payload().data_slice(), MSG.as_bytes());
+}
+
+#[tokio::main]
+async fn main() {
+    // Enter DPDK EAL.
+    eal::Config::new()
+        // Assign IP addresses for two of the NICs, which are used to look up the devices.
+        .device_probe(&["10.2.3.0", "10.2.3.1"])
+        .unwrap()
+        .enter()
+        .unwrap();
+    // Let the devices start polling.
+    net_dev::device_start_all().unwrap();
+    let rcv = tokio::task::spawn(receiver());
+    sender().await;
+    rcv.await.unwrap();
+    // Stop the polling threads.
+    net_dev::device_stop_all().unwrap();
+}
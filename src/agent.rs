@@ -1,74 +1,757 @@
 //! RX/TX agent thread, which polls queues in background.
 
+use crate::bpf;
+use crate::capture;
+use crate::dispatch;
+use crate::dump::{self, Direction};
+use crate::eth_dev::RTE_ETH_TX_OFFLOAD_IPV4_CKSUM;
+use crate::eventdev::{self, Ingress};
+use crate::exception;
+use crate::gso::{self, RTE_ETH_TX_OFFLOAD_UDP_TSO};
+use crate::headers::{EtherHdr, Ipv4Hdr, Ipv6Hdr};
 use crate::mbuf::Mbuf;
+use crate::meter::{PoliceAction, PoliceState, Policer};
 use crate::proto::{
+    arp::{handle_arp, ARP_HDR_LEN},
+    icmp::handle_icmp,
     socket::{self, RecvResult},
-    udp::handle_ipv4_udp,
-    L3Protocol, L4Protocol, Protocol, ETHER_HDR_LEN, IP_NEXT_PROTO_UDP,
+    tcp::handle_ipv4_tcp,
+    udp::{handle_ipv4_udp, handle_ipv6_udp},
+    L3Protocol, L4Protocol, Protocol, ETHER_HDR_LEN, IPV6_FRAG_HDR_LEN, IPV6_NEXT_PROTO_DSTOPTS,
+    IPV6_NEXT_PROTO_FRAGMENT, IPV6_NEXT_PROTO_HOPOPTS, IPV6_NEXT_PROTO_ROUTING, IP_NEXT_PROTO_ICMP,
+    IP_NEXT_PROTO_TCP, IP_NEXT_PROTO_UDP,
+};
+use crate::ring::{Ring, SyncMode};
+use crate::service::{Service, SOCKET_ID_ANY};
+use crate::shaper::{RateLimit, TokenBucket};
+use crate::timestamp;
+use crate::{
+    trace::{debug, enter_span, error, info, trace, warn},
+    Error, Result,
 };
-use crate::{Error, Result};
 use dpdk_sys::{
+    rte_eal_remote_launch, rte_eal_wait_lcore, rte_epoll_event, rte_epoll_wait,
+    rte_eth_dev_rx_intr_ctl_q, rte_eth_dev_rx_intr_disable, rte_eth_dev_rx_intr_enable,
     rte_eth_rx_burst, rte_eth_tx_burst, rte_ether_addr_copy, rte_ether_hdr, rte_free,
-    rte_get_tsc_hz, rte_ip_frag_death_row, rte_ip_frag_table_create, rte_ip_frag_table_destroy,
+    rte_get_main_lcore, rte_get_tsc_hz, rte_ip_frag_death_row, rte_ip_frag_free_death_row,
+    rte_ip_frag_table_create, rte_ip_frag_table_del_expired_entries, rte_ip_frag_table_destroy,
     rte_ip_frag_tbl, rte_ipv4_frag_pkt_is_fragmented, rte_ipv4_frag_reassemble_packet,
-    rte_ipv4_fragment_packet, rte_ipv4_hdr, rte_ipv6_fragment_packet, rte_ipv6_hdr, rte_mbuf,
-    rte_mbuf_buf_addr, rte_pktmbuf_adj, rte_pktmbuf_prepend, rte_rdtsc, rte_zmalloc_socket,
-    RTE_ETHER_MTU, RTE_ETHER_TYPE_ARP, RTE_ETHER_TYPE_IPV4, RTE_ETHER_TYPE_IPV6, RTE_PTYPE_L3_IPV4,
+    rte_ipv4_fragment_packet, rte_ipv6_frag_reassemble_packet, rte_ipv6_fragment_ext,
+    rte_ipv6_fragment_packet, rte_ipv6_hdr, rte_lcore_is_enabled, rte_mbuf, rte_mbuf_buf_addr,
+    rte_pktmbuf_adj, rte_pktmbuf_free, rte_pktmbuf_prepend, rte_rdtsc, rte_zmalloc_socket,
+    RTE_EPOLL_PER_THREAD, RTE_ETHER_TYPE_ARP, RTE_ETHER_TYPE_IPV4, RTE_ETHER_TYPE_IPV6,
+    RTE_INTR_EVENT_ADD, RTE_INTR_EVENT_DEL, RTE_MBUF_F_TX_TCP_SEG, RTE_PTYPE_L3_IPV4,
     RTE_PTYPE_L3_IPV6, RTE_PTYPE_L3_MASK,
 };
-use log::{debug, error, info, trace, warn};
+use lazy_static::lazy_static;
+use std::cell::{Cell, RefCell};
 use std::collections::{btree_map::Entry, BTreeMap, BTreeSet, VecDeque};
-use std::ffi::CString;
+use std::ffi::{c_int, c_void, CString};
 use std::mem;
 use std::ptr::{self, NonNull};
-use std::sync::atomic::AtomicI32;
+use std::sync::atomic::{self, AtomicI32, AtomicU16, AtomicU32, AtomicU64, AtomicUsize};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc, Mutex,
+    mpsc as std_mpsc, Arc, Condvar, Mutex, PoisonError, RwLock,
 };
+use std::time::{Duration, Instant};
 use tokio::task::LocalSet;
 use tokio::{
-    runtime::Builder,
-    sync::mpsc,
+    runtime::{Builder, Runtime},
+    sync::{mpsc, oneshot, Notify},
     task::{self, JoinHandle},
+    time::{self, Interval, MissedTickBehavior},
 };
 
-/// Burst size for `rte_tx_burst` and `rte_rx_burst`.
+/// Default burst size for `rte_rx_burst`.
 const MAX_PKT_BURST: u16 = 32;
 
-/// Channel size for `TxAgent`.
+/// Maximum rx burst size, the capacity of the array of `Mbuf` pointers reused by the polling
+/// loop.
+pub(crate) const RX_BURST_CAPACITY: u16 = 512;
+
+/// Default channel size for `TxAgent`.
 const TX_CHAN_SIZE: usize = 256;
 
-/// The capacity of a `TxBuffer`.
+/// Default capacity of a `TxBuffer`.
 const TX_BUF_SIZE: usize = 1024;
 
-/// Number of buckets in the hash table.
+/// How long an `RxAgent` sleeps at most waiting for RX interrupts, before it checks whether it's
+/// stopped or new queues are registered.
+const RX_INTR_WAIT_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// How long unregistering a queue waits at most for the polling loop to stop polling it.
+const RX_UNREGISTER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Default interval of flushing the packets left in a `TxBuffer`.
+const TX_FLUSH_INTERVAL: Duration = Duration::from_micros(100);
+
+/// Number of traffic classes of a `TxBuffer`, by the IP precedence of packets.
+pub(crate) const TX_CLASSES: usize = 4;
+
+/// Longest backoff between the timed retries of a `TxBuffer` whose device takes no packet.
+const TX_MAX_BACKOFF: Duration = Duration::from_millis(10);
+
+/// How long a `TxBuffer` makes no progress before it rejects new packets with `Error::Busy`.
+const TX_STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a `TxBuffer` keeps retrying to send its buffered packets when flushed.
+const TX_DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Default number of buckets in the hash table.
 const IP_FRAG_TABLE_BUCKET_NUM: u32 = 128;
 
-/// Number of entries per bucket (e.g. hash associativity). Should be power of two.
+/// Default number of entries per bucket (e.g. hash associativity). Should be power of two.
 const IP_FRAG_TABLE_BUCKET_SIZE: u32 = 16;
 
-/// Maximum number of entries that could be stored in the table. The value should be less
+/// Default maximum number of entries that could be stored in the table. The value should be less
 /// or equal then `bucket_num` * `bucket_entries`.
 const IP_FRAG_TABLE_MAX_ENTRIES: u32 = 2048;
 
+/// Default time for fragments to wait for the rest of their datagram in the reassembly table.
+const IP_FRAG_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often expired entries are removed from the reassembly table.
+const IP_FRAG_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Number of `Mbuf`s on the death row above which they are freed without waiting for the end of
+/// the burst, which keeps the death row from overflowing.
+const DEATH_ROW_FLUSH_THRESHOLD: u32 = 64;
+
+/// Maximum number of IPv6 extension headers skipped in a packet.
+const IPV6_MAX_EXT_HDRS: usize = 8;
+
+/// Number of `Mbuf`s to prefetch ahead when freeing the death row.
+const DEATH_ROW_PREFETCH: u32 = 3;
+
+/// Number of datagrams reassembled from IP fragments.
+static FRAG_REASSEMBLED: AtomicU64 = AtomicU64::new(0);
+
+/// Number of IP fragments dropped as invalid or evicted from the reassembly table.
+static FRAG_FAILED: AtomicU64 = AtomicU64::new(0);
+
+/// Number of IP fragments dropped for the rest of their datagram not arriving in time.
+static FRAG_TIMED_OUT: AtomicU64 = AtomicU64::new(0);
+
+/// Statistics of IP reassembly, summed over all agents polling rx queues.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FragStats {
+    /// Number of datagrams reassembled from fragments.
+    pub reassembled: u64,
+    /// Number of fragments dropped as invalid, e.g. overlapping or too many, or evicted from a full
+    /// reassembly table.
+    pub failed: u64,
+    /// Number of fragments dropped for the rest of their datagram not arriving in time.
+    pub timed_out: u64,
+}
+
+/// Get the statistics of IP reassembly.
+pub(crate) fn frag_stats() -> FragStats {
+    FragStats {
+        reassembled: FRAG_REASSEMBLED.load(Ordering::Relaxed),
+        failed: FRAG_FAILED.load(Ordering::Relaxed),
+        timed_out: FRAG_TIMED_OUT.load(Ordering::Relaxed),
+    }
+}
+
+/// Statistics of a queue, counted by the agents polling it and sending on it.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Number of non-empty bursts received from the rx queue.
+    pub rx_bursts: u64,
+    /// Number of packets received from the rx queue.
+    pub rx_packets: u64,
+    /// Number of received packets dropped by the classifier, e.g. malformed, matching no socket
+    /// or rejected by the BPF filter of the device.
+    pub rx_dropped: u64,
+    /// Number of packets sent on the tx queue.
+    pub tx_packets: u64,
+    /// Number of packets buffered for the tx queue but dropped before sent, e.g. when the device
+    /// stops.
+    pub tx_dropped: u64,
+    /// Number of packets buffered for the tx queue and not sent yet, as of the last attempt.
+    pub tx_pending: u64,
+    /// Number of packets dropped by the policer of the tx queue.
+    pub tx_policed: u64,
+}
+
+/// Counters of a queue behind `QueueStats`.
+#[derive(Debug, Default)]
+pub(crate) struct QueueCounters {
+    /// Number of non-empty bursts received.
+    rx_bursts: AtomicU64,
+    /// Number of packets received.
+    rx_packets: AtomicU64,
+    /// Number of received packets dropped by the classifier.
+    rx_dropped: AtomicU64,
+    /// Number of packets sent.
+    tx_packets: AtomicU64,
+    /// Number of packets dropped before sent.
+    tx_dropped: AtomicU64,
+    /// Number of packets buffered and not sent yet.
+    tx_pending: AtomicU64,
+    /// Number of packets dropped by the policer.
+    tx_policed: AtomicU64,
+}
+
+impl QueueCounters {
+    /// Take a snapshot of the counters.
+    fn stats(&self) -> QueueStats {
+        QueueStats {
+            rx_bursts: self.rx_bursts.load(Ordering::Relaxed),
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            rx_dropped: self.rx_dropped.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            tx_dropped: self.tx_dropped.load(Ordering::Relaxed),
+            tx_pending: self.tx_pending.load(Ordering::Relaxed),
+            tx_policed: self.tx_policed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+lazy_static! {
+    /// Counters of all queues ever polled or sent on, indexed by (`port_id`, `queue_id`).
+    static ref QUEUE_COUNTERS: RwLock<BTreeMap<(u16, u16), Arc<QueueCounters>>> =
+        RwLock::new(BTreeMap::new());
+}
+
+thread_local! {
+    /// Number of packets dropped by the classifier on the current thread, which is attributed to
+    /// the queue being polled.
+    static CLASSIFIER_DROPPED: Cell<u64> = const { Cell::new(0) };
+
+    /// Workers of the `TxAgent` services run by the current service lcore, keyed by their ids.
+    static TX_SERVICE_WORKERS: RefCell<BTreeMap<u64, TxServiceWorker>> =
+        RefCell::new(BTreeMap::new());
+}
+
+/// Id of the next `TxAgent` service.
+static NEXT_TX_SERVICE: AtomicU64 = AtomicU64::new(0);
+
+/// Get the counters of a queue, which are created on first use.
+fn queue_counters(port_id: u16, queue_id: u16) -> Arc<QueueCounters> {
+    let mut counters = QUEUE_COUNTERS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    Arc::clone(counters.entry((port_id, queue_id)).or_default())
+}
+
+/// Get the statistics of all queues of a port, indexed by `queue_id`.
+pub(crate) fn queue_stats(port_id: u16) -> BTreeMap<u16, QueueStats> {
+    QUEUE_COUNTERS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .range((port_id, 0)..=(port_id, u16::MAX))
+        .map(|(&(_, queue_id), counters)| (queue_id, counters.stats()))
+        .collect()
+}
+
+/// Count a received packet dropped by the classifier.
+pub(crate) fn classifier_dropped() {
+    CLASSIFIER_DROPPED.with(|dropped| dropped.set(dropped.get().wrapping_add(1)));
+}
+
+/// Sizes of the buffers and tables used by the agents, which are applied to agents and queues
+/// created afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AgentConf {
+    /// Default max number of packets received from an rx queue in a burst.
+    pub(crate) rx_burst: u16,
+    /// Number of requests buffered in the channel to a tx queue.
+    pub(crate) tx_chan_size: usize,
+    /// Number of packets held by the ring carrying packets to a tx queue, or `None` if packets
+    /// are sent through the channel.
+    pub(crate) tx_ring_size: Option<u32>,
+    /// Number of packets buffered for a tx queue before they are put onto the wire.
+    pub(crate) tx_buf_size: usize,
+    /// Interval of flushing the packets left in the buffer of a tx queue.
+    pub(crate) tx_flush_interval: Duration,
+    /// Number of buckets in the IP reassembly table.
+    pub(crate) frag_bucket_num: u32,
+    /// Number of entries per bucket in the IP reassembly table.
+    pub(crate) frag_bucket_size: u32,
+    /// Maximum number of entries in the IP reassembly table.
+    pub(crate) frag_max_entries: u32,
+    /// Time for fragments to wait for the rest of their datagram in the reassembly table.
+    pub(crate) frag_timeout: Duration,
+}
+
+impl Default for AgentConf {
+    fn default() -> Self {
+        Self {
+            rx_burst: MAX_PKT_BURST,
+            tx_chan_size: TX_CHAN_SIZE,
+            tx_ring_size: None,
+            tx_buf_size: TX_BUF_SIZE,
+            tx_flush_interval: TX_FLUSH_INTERVAL,
+            frag_bucket_num: IP_FRAG_TABLE_BUCKET_NUM,
+            frag_bucket_size: IP_FRAG_TABLE_BUCKET_SIZE,
+            frag_max_entries: IP_FRAG_TABLE_MAX_ENTRIES,
+            frag_timeout: IP_FRAG_TIMEOUT,
+        }
+    }
+}
+
+impl AgentConf {
+    /// Check whether the sizes are valid.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let frag_capacity = self.frag_bucket_num.checked_mul(self.frag_bucket_size);
+        if self.rx_burst == 0
+            || self.rx_burst > RX_BURST_CAPACITY
+            || self.tx_chan_size == 0
+            || self.tx_ring_size == Some(0)
+            || self.tx_buf_size == 0
+            || self.tx_flush_interval.is_zero()
+            || self.frag_bucket_num == 0
+            || !self.frag_bucket_size.is_power_of_two()
+            || self.frag_max_entries == 0
+            || self.frag_timeout.is_zero()
+            || frag_capacity.is_some_and(|capacity| capacity < self.frag_max_entries)
+        {
+            return Err(Error::InvalidArg);
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    /// Sizes of the buffers and tables used by the agents.
+    static ref AGENT_CONF: RwLock<AgentConf> = RwLock::new(AgentConf::default());
+}
+
+/// Set the sizes of the buffers and tables used by agents created afterwards.
+///
+/// # Errors
+///
+/// - `Error::InvalidArg`: some of the sizes are invalid.
+/// - Lock poisoned.
+pub(crate) fn set_agent_conf(conf: AgentConf) -> Result<()> {
+    conf.validate()?;
+    *AGENT_CONF.write().map_err(Error::from)? = conf;
+    Ok(())
+}
+
+/// The sizes of the buffers and tables used by the agents.
+fn agent_conf() -> AgentConf {
+    *AGENT_CONF.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Settings of a port shared by its `EthDev` and the agents polling its queues, which may be
+/// changed at runtime.
+#[derive(Debug)]
+pub(crate) struct PortConf {
+    /// MTU of the port. Larger packets are fragmented before sent.
+    pub(crate) mtu: AtomicU16,
+    /// Whether large IPv4 UDP datagrams are fragmented with GSO.
+    pub(crate) gso: AtomicBool,
+    /// Whether received IPv4 fragments of UDP datagrams are merged with GRO.
+    pub(crate) gro: AtomicBool,
+    /// Max number of packets received from a queue of the port in a burst.
+    pub(crate) rx_burst: AtomicU16,
+    /// TX offloads enabled on the port.
+    pub(crate) tx_offloads: u64,
+    /// With RX interrupts enabled on the port, how long its queues stay idle before the agent
+    /// polling them sleeps.
+    pub(crate) rx_intr: Option<Duration>,
+    /// Weights of the traffic classes of the tx queues, or `None` to send packets in order.
+    pub(crate) tx_weights: Option<[u32; TX_CLASSES]>,
+    /// Rate limit of each tx queue of the port, if any.
+    pub(crate) tx_rate_limit: Option<RateLimit>,
+    /// Policer of each tx queue of the port, if any.
+    pub(crate) tx_policer: Option<Policer>,
+    /// Whether received packets not timestamped by the NIC are timestamped with the TSC.
+    pub(crate) rx_timestamp: bool,
+}
+
+impl PortConf {
+    /// Create the settings of a port with GSO and GRO disabled.
+    pub(crate) fn new(mtu: u16, tx_offloads: u64, rx_intr: Option<Duration>) -> Self {
+        Self {
+            mtu: AtomicU16::new(mtu),
+            gso: AtomicBool::new(false),
+            gro: AtomicBool::new(false),
+            rx_burst: AtomicU16::new(agent_conf().rx_burst),
+            tx_offloads,
+            rx_intr,
+            tx_weights: None,
+            tx_rate_limit: None,
+            tx_policer: None,
+            rx_timestamp: false,
+        }
+    }
+}
+
+/// Where the agent thread polling rx queues runs.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RxExec {
+    /// A thread of the tokio blocking pool, without core affinity.
+    #[default]
+    Blocking,
+    /// A worker lcore launched by EAL, which should be enabled with `eal::Config::corelist` or
+    /// `eal::Config::coremask` and not used by anything else.
+    Lcore(u32),
+    /// A native thread pinned to the given CPU core.
+    Pinned(usize),
+    /// A DPDK service run by the given service lcore, which should be reserved with
+    /// `eal::Config::service_corelist`. The lcore may run other services in turn.
+    Service(u32),
+}
+
+/// Threads of the `TxAgent` of a device, which serve its tx queues.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TxExec {
+    /// The given number of threads without core affinity, no more than the tx queues, where tx
+    /// queue `queue_id` is served by the thread `queue_id % n`.
+    Threads(usize),
+    /// A dedicated thread per tx queue, without core affinity.
+    PerQueue,
+    /// `n` threads pinned to CPU cores `first..first + n`, no more than the tx queues, where tx
+    /// queue `queue_id` is served by the thread on core `first + queue_id % n`.
+    Pinned {
+        /// The first CPU core.
+        first: usize,
+        /// Number of threads.
+        n: usize,
+    },
+    /// A DPDK service run by the given service lcore serving all tx queues, which should be
+    /// reserved with `eal::Config::service_corelist`. The lcore may run other services in turn.
+    Service(u32),
+}
+
+impl TxExec {
+    /// Check the number of threads, which serve no more than `n_queues` tx queues, and the range
+    /// of CPU cores.
+    fn validate(self, n_queues: u16) -> Result<()> {
+        let max = usize::from(n_queues.max(1));
+        let valid = match self {
+            TxExec::Threads(n) => n > 0 && n <= max,
+            #[allow(clippy::cast_sign_loss)] // a positive constant
+            TxExec::Pinned { first, n } => {
+                n > 0
+                    && n <= max
+                    && first
+                        .checked_add(n)
+                        .is_some_and(|end| end <= libc::CPU_SETSIZE as usize)
+            }
+            TxExec::PerQueue | TxExec::Service(_) => true,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidArg)
+        }
+    }
+}
+
+impl Default for TxExec {
+    /// A single thread serving all tx queues.
+    #[inline]
+    fn default() -> Self {
+        Self::Threads(1)
+    }
+}
+
+/// Handle to the polling loop of an `RxAgent`.
+#[allow(variant_size_differences)] // one per agent
+#[derive(Debug)]
+enum RxHandle {
+    /// Running on a thread of the tokio blocking pool, which is detached.
+    Blocking,
+    /// Running on a pinned native thread.
+    Pinned(std::thread::JoinHandle<Result<()>>),
+    /// Running on the given worker lcore.
+    Lcore(u32),
+    /// Running as a service on a service lcore.
+    Service(Service),
+}
+
 /// An agent thread continuously receives.
 pub(crate) struct RxAgent {
     /// Whether the thread is running.
     running: AtomicBool,
-    /// A set of queues to be polled.
-    tasks: Mutex<BTreeSet<(u16, u16)>>,
+    /// `socket_id` to allocate the IP reassembly table on.
+    socket_id: i32,
+    /// Snapshot of the queues to be polled, along with the settings of their ports. It's replaced
+    /// as a whole on changes, and the polling loop only takes a new snapshot once `version`
+    /// changes, so that registration never stalls packet processing.
+    tasks: RwLock<Arc<RxTaskMap>>,
+    /// Version of `tasks`, increased on each change.
+    version: AtomicU64,
+    /// Version of `tasks` the polling loop is working on, or `u64::MAX` once it terminates.
+    seen: Mutex<u64>,
+    /// Notified when `seen` changes.
+    seen_changed: Condvar,
+    /// Handle to the polling loop, which is waited for when the agent is stopped.
+    handle: Mutex<Option<RxHandle>>,
+}
+
+/// A queue polled by an `RxAgent`.
+#[derive(Debug, Clone)]
+struct RxTask {
+    /// Settings of the port.
+    conf: Arc<PortConf>,
+    /// Counters of the queue.
+    counters: Arc<QueueCounters>,
+}
+
+/// Queues polled by an `RxAgent`.
+type RxTaskMap = BTreeMap<(u16, u16), RxTask>;
+
+/// Where an `RxAgent` runs, along with the port whose queues it polls if it's not shared by
+/// devices.
+type RxAgentKey = (RxExec, Option<u16>);
+
+lazy_static! {
+    /// Running `RxAgent`s started by `RxAgent::attach`.
+    static ref RX_AGENTS: Mutex<BTreeMap<RxAgentKey, Arc<RxAgent>>> = Mutex::new(BTreeMap::new());
 }
 
 /// A map to store the spawned tx tasks.
 type TaskSetType = Arc<Mutex<BTreeMap<(u16, u16), JoinHandle<Result<()>>>>>;
 
-/// An agent thread doing sending.
+/// Agent threads doing sending.
 pub(crate) struct TxAgent {
-    /// Task sender
-    sender: mpsc::Sender<TxTask>,
+    /// Task senders to each thread.
+    workers: Vec<mpsc::Sender<TxTask>>,
     /// For each queue registered, there's a Task polling it.
     tasks: TaskSetType,
+    /// Senders to each registered queue, used to stop the Task.
+    senders: Mutex<BTreeMap<(u16, u16), mpsc::Sender<TxRequest>>>,
+    /// The service running the worker if it runs on a service lcore, which is stopped after the
+    /// tasks are cancelled.
+    service: Option<TxService>,
+}
+
+/// A request to the Task polling a tx queue.
+#[derive(Debug)]
+pub(crate) enum TxRequest {
+    /// Send an `Mbuf` holding a whole Ethernet frame, then notify whether it's buffered
+    /// successfully if a completion channel is given.
+    Send(Mbuf, Option<oneshot::Sender<Result<()>>>),
+    /// Send a burst of `Mbuf`s together, then notify the number of `Mbuf`s buffered.
+    SendBatch(Vec<Mbuf>, oneshot::Sender<Result<usize>>),
+    /// Send all buffered `Mbuf`s, then notify the number of `Mbuf`s still unsent.
+    Flush(oneshot::Sender<usize>),
+    /// Send all buffered `Mbuf`s and stop the Task, then notify the number of dropped `Mbuf`s.
+    Stop(std_mpsc::SyncSender<usize>),
+    /// Stop sending to the queue, which is driven directly meanwhile, if `true`, or resume
+    /// sending if `false`, then notify once done.
+    Pause(bool, std_mpsc::SyncSender<()>),
+}
+
+/// The way to the Task polling a tx queue, which is returned by `TxAgent::register`.
+#[derive(Debug, Clone)]
+pub(crate) struct TxChannel {
+    /// Sends requests to the Task.
+    pub(crate) requests: mpsc::Sender<TxRequest>,
+    /// Carries packets to the Task instead of `requests`, if enabled.
+    pub(crate) ring: Option<Arc<TxRing>>,
+}
+
+/// Distinguishes the names of `TxRing`s, since a ring may outlive its queue.
+static TX_RING_SEQ: AtomicU32 = AtomicU32::new(0);
+
+/// A lockless ring carrying packets from the senders to the Task polling a tx queue, which takes
+/// them in bursts. Unlike a request per packet, enqueuing allocates nothing, and either side is
+/// woken up only if the ring was empty or full.
+#[derive(Debug)]
+pub(crate) struct TxRing {
+    /// Packets to be sent, enqueued by any sender and dequeued by the Task only.
+    ring: Ring<Mbuf>,
+    /// Wakes the Task waiting for packets.
+    ready: Notify,
+    /// Whether the Task waits for packets.
+    parked: AtomicBool,
+    /// Wakes the senders waiting for room.
+    room: Notify,
+    /// Number of senders waiting for room.
+    waiters: AtomicUsize,
+    /// Whether the Task has stopped, after which packets are refused.
+    closed: AtomicBool,
+}
+
+impl TxRing {
+    /// Create a ring holding up to `size` packets sent to tx queue `queue_id` of port `port_id`.
+    fn create(port_id: u16, queue_id: u16, size: u32) -> Result<Arc<Self>> {
+        let seq = TX_RING_SEQ.fetch_add(1, Ordering::Relaxed);
+        let name = format!("tx_{port_id}_{queue_id}_{seq}");
+        Ok(Arc::new(Self {
+            ring: Ring::create(&name, size, SyncMode::Multi, SyncMode::Single)?,
+            ready: Notify::new(),
+            parked: AtomicBool::new(false),
+            room: Notify::new(),
+            waiters: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+        }))
+    }
+
+    /// Enqueue a packet without waiting.
+    ///
+    /// It fails with `Error::TempUnavail` if the ring is full, or `Error::BrokenPipe` if the Task
+    /// has stopped.
+    pub(crate) fn try_push(&self, m: Mbuf) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::BrokenPipe);
+        }
+        self.ring.enqueue(m).ok().ok_or(Error::TempUnavail)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Enqueue a packet, and wait for room if the ring is full.
+    ///
+    /// It fails with `Error::BrokenPipe` if the Task has stopped.
+    pub(crate) async fn push(&self, m: Mbuf) -> Result<()> {
+        let mut m = m;
+        loop {
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(Error::BrokenPipe);
+            }
+            match self.ring.enqueue(m) {
+                Ok(()) => {
+                    self.wake();
+                    return Ok(());
+                }
+                Err(back) => m = back,
+            }
+            self.wait_room().await;
+        }
+    }
+
+    /// Enqueue a burst of packets, and wait for room whenever the ring is full. Returns the
+    /// number of packets enqueued, which falls short only if the Task has stopped.
+    ///
+    /// It fails with `Error::BrokenPipe` if no packet is enqueued.
+    pub(crate) async fn push_batch(&self, batch: Vec<Mbuf>) -> Result<usize> {
+        let mut batch = batch;
+        let mut pushed = 0_usize;
+        while !batch.is_empty() {
+            if self.closed.load(Ordering::SeqCst) {
+                break;
+            }
+            let n = self.ring.enqueue_burst(&mut batch);
+            if n > 0 {
+                pushed = pushed.wrapping_add(n);
+                self.wake();
+            } else {
+                self.wait_room().await;
+            }
+        }
+        if pushed == 0 && !batch.is_empty() {
+            return Err(Error::BrokenPipe);
+        }
+        Ok(pushed)
+    }
+
+    /// Whether the ring is full, in which case `try_push` fails.
+    pub(crate) fn is_full(&self) -> bool {
+        self.ring.is_full()
+    }
+
+    /// Wait until the ring has room for a packet.
+    ///
+    /// It fails with `Error::BrokenPipe` if the Task has stopped.
+    pub(crate) async fn ready(&self) -> Result<()> {
+        loop {
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(Error::BrokenPipe);
+            }
+            if !self.ring.is_full() {
+                return Ok(());
+            }
+            self.wait_room().await;
+        }
+    }
+
+    /// Wait until the Task dequeues packets, if the ring is still full.
+    async fn wait_room(&self) {
+        // A `Notified` receives `notify_waiters` as soon as it's created, so that the wakeup
+        // between the check below and the first poll is not lost.
+        let room = self.room.notified();
+        let _waiters = self.waiters.fetch_add(1, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        if self.ring.is_full() && !self.closed.load(Ordering::SeqCst) {
+            room.await;
+        }
+        // A cancelled sender leaves the count behind, which only costs spurious wakeups.
+        let _waiters = self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Wake the Task if it waits for packets.
+    fn wake(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if self.parked.load(Ordering::Relaxed) && self.parked.swap(false, Ordering::AcqRel) {
+            self.ready.notify_one();
+        }
+    }
+
+    /// Get the Task ready to wait for packets, which returns `false` if some arrive meanwhile.
+    fn park(&self) -> bool {
+        self.parked.store(true, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        if self.ring.is_empty() {
+            return true;
+        }
+        self.parked.store(false, Ordering::Relaxed);
+        false
+    }
+
+    /// Move up to `max` packets to `txbuf` in a burst, as many as it has room for, and send them.
+    /// Returns the number of packets dequeued.
+    fn pull(&self, txbuf: &mut TxBuffer, max: usize) -> usize {
+        let room = txbuf.capacity.saturating_sub(txbuf.len()).min(max);
+        let mut pkts = Vec::with_capacity(room);
+        let n = self.ring.dequeue_burst(&mut pkts, room);
+        if n == 0 {
+            return 0;
+        }
+        atomic::fence(Ordering::SeqCst);
+        if self.waiters.load(Ordering::Relaxed) > 0 {
+            self.room.notify_waiters();
+        }
+        let (port_id, queue_id) = (txbuf.port_id, txbuf.queue_id);
+        match txbuf.buffer_batch(pkts) {
+            Ok(buffered) if buffered < n => {
+                let dropped = n.wrapping_sub(buffered);
+                warn!("Failed to send {dropped} packets on {port_id}:{queue_id}");
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to send {n} packets on {port_id}:{queue_id}: {e:?}"),
+        }
+        n
+    }
+
+    /// Refuse packets from now on, and free those left in the ring. Returns the number of them.
+    fn close(&self) -> usize {
+        self.closed.store(true, Ordering::SeqCst);
+        self.room.notify_waiters();
+        let mut pkts = Vec::new();
+        let mut n = 0_usize;
+        loop {
+            let burst = self
+                .ring
+                .dequeue_burst(&mut pkts, usize::from(MAX_PKT_BURST));
+            if burst == 0 {
+                return n;
+            }
+            n = n.wrapping_add(burst);
+            pkts.clear();
+        }
+    }
+}
+
+/// The `TxRing` of a Task, which is closed once the Task ends, even if it's aborted, so that no
+/// sender waits for room forever.
+struct TxRingGuard(Arc<TxRing>);
+
+impl Drop for TxRingGuard {
+    fn drop(&mut self) {
+        let _dropped = self.0.close();
+    }
 }
 
 /// Table holding fragmented packets.
@@ -87,15 +770,21 @@ struct IpFragDeathRow {
 impl IpFragmentTable {
     /// Create an `IpFragmentTable`.
     fn new(socket_id: i32) -> Result<Self> {
+        let conf = agent_conf();
         // SAFETY: ffi
-        let max_cycles = unsafe { rte_get_tsc_hz() }; // 1s
+        let hz = unsafe { rte_get_tsc_hz() };
+        let max_cycles = u128::from(hz)
+            .saturating_mul(conf.frag_timeout.as_nanos())
+            .checked_div(1_000_000_000)
+            .and_then(|cycles| u64::try_from(cycles).ok())
+            .unwrap_or(u64::MAX);
 
         // SAFETY: pointer checked later
         let ptr = unsafe {
             rte_ip_frag_table_create(
-                IP_FRAG_TABLE_BUCKET_NUM,
-                IP_FRAG_TABLE_BUCKET_SIZE,
-                IP_FRAG_TABLE_MAX_ENTRIES,
+                conf.frag_bucket_num,
+                conf.frag_bucket_size,
+                conf.frag_max_entries,
                 max_cycles,
                 socket_id,
             )
@@ -107,6 +796,17 @@ impl IpFragmentTable {
     fn as_mut_ptr(&mut self) -> *mut rte_ip_frag_tbl {
         self.tbl.as_ptr()
     }
+
+    /// Move the fragments waiting for too long onto the death row.
+    fn expire(&mut self, dr: &mut IpFragDeathRow) {
+        let len = dr.len();
+        // SAFETY: pointers checked upon their allocation
+        unsafe {
+            rte_ip_frag_table_del_expired_entries(self.as_mut_ptr(), dr.as_mut_ptr(), rte_rdtsc());
+        }
+        let _prev =
+            FRAG_TIMED_OUT.fetch_add(u64::from(dr.len().saturating_sub(len)), Ordering::Relaxed);
+    }
 }
 
 #[allow(unsafe_code)]
@@ -139,11 +839,26 @@ impl IpFragDeathRow {
     fn as_mut_ptr(&mut self) -> *mut rte_ip_frag_death_row {
         self.dr.as_ptr()
     }
+
+    /// Number of `Mbuf`s on the death row.
+    fn len(&self) -> u32 {
+        // SAFETY: pointer checked upon its allocation
+        unsafe { (*self.dr.as_ptr()).cnt }
+    }
+
+    /// Free the `Mbuf`s on the death row.
+    fn free(&mut self) {
+        if self.len() > 0 {
+            // SAFETY: pointer checked upon its allocation
+            unsafe { rte_ip_frag_free_death_row(self.as_mut_ptr(), DEATH_ROW_PREFETCH) };
+        }
+    }
 }
 
 #[allow(unsafe_code)]
 impl Drop for IpFragDeathRow {
     fn drop(&mut self) {
+        self.free();
         // SAFETY: pointer validity check in `IpFragDeathRow::new`
         unsafe {
             rte_free(self.as_mut_ptr().cast());
@@ -158,26 +873,28 @@ impl Drop for IpFragDeathRow {
 fn parse_ether_proto(m: &Mbuf) -> Option<(u32, u8)> {
     // SAFETY: *rte_mbuf checked
     let raw_mbuf = unsafe { &mut (*m.as_ptr()) };
-    let data = m.data_slice();
-    let remain = data.len().checked_sub(ETHER_HDR_LEN as _)?;
-    // SAFETY: allowed in DPDK
-    #[allow(clippy::cast_ptr_alignment)]
-    let ether_hdr = unsafe { &*(data.as_ptr().cast::<rte_ether_hdr>()) };
-    let ether_type = u32::from(ether_hdr.ether_type.to_be());
+    let remain = m.data_len().checked_sub(EtherHdr::LEN)?;
+    let ether_type = u32::from(EtherHdr::from_mbuf(m, 0).ok()?.ether_type());
 
     match ether_type {
         RTE_ETHER_TYPE_IPV4 => {
-            if remain < mem::size_of::<rte_ipv4_hdr>() {
+            if remain < Ipv4Hdr::LEN {
                 warn!("Receive a unexpectedly short IPv4 packet");
                 return None;
             }
         }
         RTE_ETHER_TYPE_IPV6 => {
-            if remain < mem::size_of::<rte_ipv6_hdr>() {
+            if remain < Ipv6Hdr::LEN {
                 warn!("Receive a unexpectedly short IPv6 packet");
                 return None;
             }
         }
+        RTE_ETHER_TYPE_ARP => {
+            if remain < ARP_HDR_LEN {
+                warn!("Receive a unexpectedly short ARP packet");
+                return None;
+            }
+        }
         _ => return None,
     }
     let proto_id = match ether_type {
@@ -189,14 +906,7 @@ fn parse_ether_proto(m: &Mbuf) -> Option<(u32, u8)> {
                     .tx_offload_struct
                     .set_l3_len(L3Protocol::Ipv4.length());
             }
-            // SAFETY: remain mbuf data size is greater than `rte_ipv4_hdr` size
-            #[allow(trivial_casts)]
-            let ip_hdr = unsafe {
-                &*((ether_hdr as *const rte_ether_hdr)
-                    .add(1)
-                    .cast::<rte_ipv4_hdr>())
-            };
-            ip_hdr.next_proto_id
+            Ipv4Hdr::from_mbuf(m, EtherHdr::LEN).ok()?.next_proto_id()
         }
         RTE_ETHER_TYPE_IPV6 => {
             // SAFETY: set bitfields
@@ -206,15 +916,9 @@ fn parse_ether_proto(m: &Mbuf) -> Option<(u32, u8)> {
                     .tx_offload_struct
                     .set_l3_len(L3Protocol::Ipv6.length());
             }
-            // SAFETY: remain mbuf data size is greater than `rte_ipv6_hdr` size
-            #[allow(trivial_casts)]
-            let ip_hdr = unsafe {
-                &*((ether_hdr as *const rte_ether_hdr)
-                    .add(1)
-                    .cast::<rte_ipv6_hdr>())
-            };
-            ip_hdr.proto
+            Ipv6Hdr::from_mbuf(m, EtherHdr::LEN).ok()?.proto()
         }
+        RTE_ETHER_TYPE_ARP => 0,
         ether_type => {
             debug!("Unrecognized ether type {ether_type}");
             0
@@ -228,157 +932,516 @@ fn parse_ether_proto(m: &Mbuf) -> Option<(u32, u8)> {
                 .tx_offload_struct
                 .set_l4_len(L4Protocol::Udp.length());
         }
+    } else if proto_id == IP_NEXT_PROTO_TCP {
+        unsafe {
+            raw_mbuf
+                .tx_offload_union
+                .tx_offload_struct
+                .set_l4_len(L4Protocol::Tcp.length());
+        }
+    } else {
+        trace!("L4 length of proto id {proto_id} unknown");
     };
     Some((ether_type, proto_id))
 }
 
+/// Take the result of IP reassembly, where `mo` is returned by the reassembly function on `m`,
+/// which put `dropped` fragments onto the death row.
+///
+/// `None` is returned if more fragments are needed.
+fn reassembled(m: Mbuf, mo: *mut rte_mbuf, dropped: u32) -> Option<Mbuf> {
+    if dropped > 0 {
+        let _prev = FRAG_FAILED.fetch_add(u64::from(dropped), Ordering::Relaxed);
+    }
+    if mo.is_null() {
+        #[allow(clippy::mem_forget)] // later dropped by head
+        mem::forget(m);
+        None // in need of more fragments
+    } else if mo != m.as_ptr() {
+        #[allow(clippy::mem_forget)] // later dropped by head
+        mem::forget(m);
+        let _prev = FRAG_REASSEMBLED.fetch_add(1, Ordering::Relaxed);
+        let new_m = Mbuf::new_with_ptr(mo).ok()?;
+        Some(new_m) // fragmented ip packet
+    } else {
+        Some(m) // unfragmented ip packet
+    }
+}
+
+/// Strip the IPv6 extension headers preceding the fragment header or the upper-layer header of a
+/// packet, whose Ethernet header is stripped, so that the IPv6 header is directly followed by
+/// them as the reassembly library and the upper layers expect. `proto_id` is the next header of
+/// the IPv6 header, and the new one is returned.
+///
+/// `None` is returned if the extension headers are malformed, not in the first segment, or
+/// include a routing header with segments left, which should be forwarded.
+fn strip_ipv6_ext_hdrs(m: &mut Mbuf, mut proto_id: u8) -> Option<u8> {
+    let is_ext_hdr = |proto_id| {
+        matches!(
+            proto_id,
+            IPV6_NEXT_PROTO_HOPOPTS | IPV6_NEXT_PROTO_ROUTING | IPV6_NEXT_PROTO_DSTOPTS
+        )
+    };
+    let ip_hdr_len = usize::from(L3Protocol::Ipv6.length());
+    let mut ext_len = 0_usize;
+    for _ in 0..IPV6_MAX_EXT_HDRS {
+        if !is_ext_hdr(proto_id) {
+            break;
+        }
+        let offset = ip_hdr_len.checked_add(ext_len)?;
+        let [next_proto, hdr_ext_len, _, segments_left, ..] = *m.data_slice().get(offset..)? else {
+            return None;
+        };
+        if proto_id == IPV6_NEXT_PROTO_ROUTING && segments_left != 0 {
+            debug!("IPv6 routing header with {segments_left} segments left dropped");
+            return None;
+        }
+        proto_id = next_proto;
+        // In 8-octet units, not including the first 8 octets.
+        ext_len = ext_len.checked_add(usize::from(hdr_ext_len).checked_add(1)?.checked_mul(8)?)?;
+    }
+    if is_ext_hdr(proto_id) {
+        warn!("Too many IPv6 extension headers");
+        return None;
+    }
+    if ext_len == 0 {
+        return Some(proto_id);
+    }
+    let data = m.data_slice_mut();
+    if data.len() < ip_hdr_len.checked_add(ext_len)? {
+        warn!("IPv6 extension headers across segments");
+        return None;
+    }
+    // Move the IPv6 header right before the header following the extension headers.
+    data.copy_within(..ip_hdr_len, ext_len);
+    let ip_hdr = data.get_mut(ext_len..ext_len.checked_add(ip_hdr_len)?)?;
+    let payload_len = ip_hdr.get(4..6)?;
+    let payload_len = u16::from_be_bytes([*payload_len.first()?, *payload_len.get(1)?])
+        .checked_sub(u16::try_from(ext_len).ok()?)?;
+    ip_hdr
+        .get_mut(4..6)?
+        .copy_from_slice(&payload_len.to_be_bytes());
+    *ip_hdr.get_mut(6)? = proto_id;
+    m.adj(ext_len).ok()?;
+    Some(proto_id)
+}
+
 /// Handle L2 frame and parse the Ethernet header.
 ///
 /// The protocols of Network and Transport Layer (L3 & L4) will be resolved, and the
-/// packet will be dispatched to the corresponding L3 & L4 handling function.
+/// packet will be dispatched to the handler installed on it by `dispatch::register` if any, or
+/// the corresponding L3 & L4 handling function. Frames not handled are forwarded to the kernel
+/// if an exception path is attached.
 #[inline]
-#[allow(unsafe_code)]
+#[allow(unsafe_code, clippy::too_many_lines)]
 fn handle_ether(
     mut m: Mbuf,
     tbl: &mut IpFragmentTable,
     dr: &mut IpFragDeathRow,
 ) -> Option<(i32, RecvResult)> {
     // l3 protocol, l4 protocol
-    if let Some((ether_type, proto_id)) = parse_ether_proto(&m) {
-        m.adj(ETHER_HDR_LEN as _).ok()?;
-        match ether_type {
-            RTE_ETHER_TYPE_IPV4 => {
-                let ip_hdr = m.data_slice_mut().as_mut_ptr();
-                // SAFETY: *rte_mbuf checked
-                let m = if unsafe { rte_ipv4_frag_pkt_is_fragmented(ip_hdr.cast()) } == 0 {
-                    Some(m)
-                } else {
-                    log::debug!("Packet need fragmentation");
-                    // SAFETY: pointers checked
-                    let mo = unsafe {
-                        rte_ipv4_frag_reassemble_packet(
-                            tbl.as_mut_ptr(),
-                            dr.as_mut_ptr(),
-                            m.as_ptr(),
-                            rte_rdtsc(),
-                            ip_hdr.cast(),
-                        )
-                    };
-                    if mo.is_null() {
-                        #[allow(clippy::mem_forget)] // later dropped by head
-                        mem::forget(m);
-                        None // in need of more fragments
-                    } else if mo != m.as_ptr() {
-                        #[allow(clippy::mem_forget)] // later dropped by head
-                        mem::forget(m);
-                        let new_m = Mbuf::new_with_ptr(mo).ok()?;
-                        Some(new_m) // fragmented ip packet
-                    } else {
-                        Some(m) // unfragmented ip packet
-                    }
-                }?;
-                return if proto_id == IP_NEXT_PROTO_UDP {
-                    handle_ipv4_udp(m)
-                } else {
-                    debug!("Unrecognized proto id {proto_id}");
-                    None
+    let Some((ether_type, proto_id)) = parse_ether_proto(&m) else {
+        dispatch_l2(m);
+        return None;
+    };
+    #[allow(clippy::cast_possible_truncation)] // Ether type is 16 bits
+    let l2_proto = ether_type as u16;
+    m.adj(ETHER_HDR_LEN as _).ok()?;
+    match ether_type {
+        RTE_ETHER_TYPE_IPV4 => {
+            let ip_hdr = m.data_slice_mut().as_mut_ptr();
+            // SAFETY: *rte_mbuf checked
+            let m = if unsafe { rte_ipv4_frag_pkt_is_fragmented(ip_hdr.cast()) } == 0 {
+                Some(m)
+            } else {
+                debug!("Packet need fragmentation");
+                enter_span!("reassemble", pkt_len = m.pkt_len());
+                let len = dr.len();
+                // SAFETY: pointers checked
+                let mo = unsafe {
+                    rte_ipv4_frag_reassemble_packet(
+                        tbl.as_mut_ptr(),
+                        dr.as_mut_ptr(),
+                        m.as_ptr(),
+                        rte_rdtsc(),
+                        ip_hdr.cast(),
+                    )
                 };
-            }
-            RTE_ETHER_TYPE_IPV6 | RTE_ETHER_TYPE_ARP => {}
-            ether_type => error!("Unsupported ether type {ether_type:x}"),
+                reassembled(m, mo, dr.len().saturating_sub(len))
+            }?;
+            let m = dispatch::dispatch(l2_proto, Some(proto_id), m)?;
+            return if proto_id == IP_NEXT_PROTO_UDP {
+                handle_ipv4_udp(m)
+            } else if proto_id == IP_NEXT_PROTO_TCP {
+                handle_ipv4_tcp(m)
+            } else if proto_id == IP_NEXT_PROTO_ICMP {
+                if let Some(m) = handle_icmp(m) {
+                    to_kernel(m);
+                }
+                None
+            } else {
+                debug!("Unrecognized proto id {proto_id}");
+                to_kernel(m);
+                None
+            };
+        }
+        RTE_ETHER_TYPE_IPV6 => {
+            let proto_id = strip_ipv6_ext_hdrs(&mut m, proto_id)?;
+            let ip_hdr = m.data_slice_mut().as_mut_ptr().cast::<rte_ipv6_hdr>();
+            let (mut m, proto_id) = if proto_id == IPV6_NEXT_PROTO_FRAGMENT {
+                debug!("Packet need fragmentation");
+                enter_span!("reassemble", pkt_len = m.pkt_len());
+                let frag_hdr_end = L3Protocol::Ipv6.length().wrapping_add(IPV6_FRAG_HDR_LEN);
+                if m.data_len() < frag_hdr_end as usize {
+                    warn!("Receive a unexpectedly short IPv6 fragment");
+                    return None;
+                }
+                // SAFETY: remain mbuf data size is greater than the fragment header end
+                let frag_hdr = unsafe { ip_hdr.add(1).cast::<rte_ipv6_fragment_ext>() };
+                // SAFETY: checked above
+                let next_proto = unsafe { (*frag_hdr).next_header };
+                // SAFETY: set bitfields
+                unsafe {
+                    (*m.as_ptr())
+                        .tx_offload_union
+                        .tx_offload_struct
+                        .set_l3_len(frag_hdr_end);
+                }
+                let len = dr.len();
+                // SAFETY: pointers checked
+                let mo = unsafe {
+                    rte_ipv6_frag_reassemble_packet(
+                        tbl.as_mut_ptr(),
+                        dr.as_mut_ptr(),
+                        m.as_ptr(),
+                        rte_rdtsc(),
+                        ip_hdr,
+                        frag_hdr,
+                    )
+                };
+                (
+                    reassembled(m, mo, dr.len().saturating_sub(len))?,
+                    next_proto,
+                )
+            } else {
+                (m, proto_id)
+            };
+            // Extension headers may follow the fragment header.
+            let proto_id = strip_ipv6_ext_hdrs(&mut m, proto_id)?;
+            let m = dispatch::dispatch(l2_proto, Some(proto_id), m)?;
+            return if proto_id == IP_NEXT_PROTO_UDP {
+                handle_ipv6_udp(m)
+            } else {
+                debug!("Unrecognized proto id {proto_id}");
+                to_kernel(m);
+                None
+            };
+        }
+        RTE_ETHER_TYPE_ARP => {
+            let m = dispatch::dispatch(l2_proto, None, m)?;
+            handle_arp(&m);
+            // The kernel keeps its own neighbor table.
+            to_kernel(m);
+        }
+        ether_type => {
+            debug!("Unsupported ether type {ether_type:x}");
+            to_kernel(m);
         }
     }
     None
 }
 
+/// Pass a frame not handled by the stack to the handler installed on its Ether type if any, or
+/// forward it to the kernel.
+fn dispatch_l2(mut m: Mbuf) {
+    let Some(&[hi, lo]) = m.data_slice().get(12..usize::from(ETHER_HDR_LEN)) else {
+        exception::to_kernel(m);
+        return;
+    };
+    if m.adj(ETHER_HDR_LEN as _).is_ok() {
+        if let Some(m) = dispatch::dispatch(u16::from_be_bytes([hi, lo]), None, m) {
+            to_kernel(m);
+        }
+    }
+}
+
+/// Forward a frame, whose Ethernet header is stripped, to the kernel.
+fn to_kernel(mut m: Mbuf) {
+    if m.prepend(ETHER_HDR_LEN as _).is_ok() {
+        exception::to_kernel(m);
+    }
+}
+
 #[allow(unsafe_code)]
 impl RxAgent {
-    /// Start an `RxAgent`, spawn a thread to do the polling job.
-    pub(crate) fn start(socket_id: i32) -> Arc<Self> {
-        let running = AtomicBool::new(true);
+    /// Start an `RxAgent`, running the polling loop as `exec` specifies.
+    ///
+    /// # Errors
+    ///
+    /// - `Error::InvalidArg`: the lcore, service lcore or CPU core in `exec` is not available.
+    /// - `Error::Busy`: the lcore in `exec` is running something else.
+    /// - Unable to spawn or pin the thread, or to register the service.
+    pub(crate) fn start(socket_id: i32, exec: RxExec) -> Result<Arc<Self>> {
         let this = Arc::new(RxAgent {
-            running,
-            tasks: Mutex::new(BTreeSet::new()),
+            running: AtomicBool::new(true),
+            socket_id,
+            tasks: RwLock::new(Arc::new(BTreeMap::new())),
+            version: AtomicU64::new(0),
+            seen: Mutex::new(0),
+            seen_changed: Condvar::new(),
+            handle: Mutex::new(None),
         });
         let that = Arc::clone(&this);
-        let _handle = task::spawn_blocking(move || {
-            let mut frag_tbl = IpFragmentTable::new(socket_id)?;
-            let mut death_row = IpFragDeathRow::new(socket_id)?;
-            while that.running.load(Ordering::Acquire) {
-                let tasks = that.tasks.lock().map_err(Error::from)?;
-                let task_iter = tasks.iter();
-                for &(port_id, queue_id) in task_iter {
-                    let mut ptrs = vec![ptr::null_mut(); MAX_PKT_BURST as usize];
-                    // SAFETY: `n` packets at the front are valid
-                    let n = unsafe {
-                        rte_eth_rx_burst(port_id, queue_id, ptrs.as_mut_ptr(), MAX_PKT_BURST)
-                    };
-                    trace!("{n} packets received");
-                    for ptr in ptrs.into_iter().take(n as _) {
-                        let m = Mbuf::new_with_ptr(ptr)?;
-                        if let Some((sockfd, res)) = handle_ether(m, &mut frag_tbl, &mut death_row)
-                        {
-                            let res = socket::put_mailbox(sockfd, res);
-                            if let Err(e) = res {
-                                error!("An error {e} occurred in `put_mailbox`");
-                            }
+        let handle = match exec {
+            RxExec::Blocking => {
+                let _handle = task::spawn_blocking(move || that.run());
+                RxHandle::Blocking
+            }
+            RxExec::Pinned(core) => {
+                let (pinned_tx, pinned_rx) = std_mpsc::sync_channel(1);
+                let handle = std::thread::Builder::new()
+                    .name(format!("rx-agent-{core}"))
+                    .spawn(move || {
+                        let res = pin_to_core(core);
+                        let ok = res.is_ok();
+                        _ = pinned_tx.send(res);
+                        if ok {
+                            that.run()
+                        } else {
+                            Ok(())
                         }
-                    }
+                    })
+                    .map_err(|e| Error::from(e.raw_os_error().unwrap_or(libc::EAGAIN)))?;
+                pinned_rx.recv().map_err(Error::from)??;
+                RxHandle::Pinned(handle)
+            }
+            RxExec::Lcore(lcore_id) => {
+                // SAFETY: ffi
+                if unsafe { rte_lcore_is_enabled(lcore_id) } == 0
+                    || lcore_id == unsafe { rte_get_main_lcore() }
+                {
+                    return Err(Error::InvalidArg);
+                }
+                let arg = Arc::into_raw(that).cast_mut().cast::<c_void>();
+                // SAFETY: `arg` is taken back by `rx_lcore_main`
+                let errno = unsafe { rte_eal_remote_launch(Some(rx_lcore_main), arg, lcore_id) };
+                if let Err(e) = Error::from_ret(errno, "rte_eal_remote_launch") {
+                    // SAFETY: `arg` is not taken since the lcore is not launched
+                    drop(unsafe { Arc::from_raw(arg.cast::<RxAgent>()) });
+                    return Err(e);
                 }
+                RxHandle::Lcore(lcore_id)
             }
-            info!("RxAgent thread terminated");
-            Result::<()>::Ok(())
-        });
-        this
+            RxExec::Service(lcore_id) => {
+                let mut poller = None;
+                let name = format!("rx-agent-{lcore_id}");
+                let callback = Box::new(move || that.serve(&mut poller));
+                RxHandle::Service(Service::start(&name, lcore_id, socket_id, callback)?)
+            }
+        };
+        *this.handle.lock().map_err(Error::from)? = Some(handle);
+        Ok(this)
     }
 
-    /// Stop the `RxAgent`.
-    pub(crate) fn stop(self: &Arc<Self>) {
-        self.running.store(false, Ordering::Release);
+    /// Run the polling loop until the agent is stopped, logging the error it fails with.
+    fn run(&self) -> Result<()> {
+        let res = self.poll();
+        // Nobody waits for the loop to take new snapshots any longer.
+        self.see(u64::MAX);
+        match res {
+            Ok(()) => info!("RxAgent thread terminated"),
+            Err(ref e) => error!("RxAgent thread terminated with an error {e}"),
+        }
+        res
     }
 
-    /// Register a (`port_id`, `queue_id`) to an `RxAgent`.
-    ///
-    /// Adds a (`port_id`, `queue_id`) pair to the set to be polled.
-    ///
-    /// # Errors
-    ///
-    /// - Returns an `Error::NotStart` if the agent had already been stopped.
-    /// - Returns an `Error::Already` if the pair had already been registered.
-    pub(crate) fn register(self: &Arc<Self>, port_id: u16, queue_id: u16) -> Result<()> {
+    /// The polling loop.
+    fn poll(&self) -> Result<()> {
+        let mut poller = RxPoller::new(self.socket_id)?;
+        while self.running.load(Ordering::Acquire) {
+            let _received = poller.round(self, true)?;
+        }
+        Ok(())
+    }
+
+    /// Run a round of the polling loop as a service, creating its state on the service lcore in
+    /// the first round. Returns whether packets are received.
+    fn serve(&self, poller: &mut Option<RxPoller>) -> bool {
         if !self.running.load(Ordering::Acquire) {
-            return Err(Error::NotStart);
+            return false;
         }
-        if !self
-            .tasks
-            .lock()
-            .map_err(Error::from)?
-            .insert((port_id, queue_id))
-        {
-            return Err(Error::Already);
+        let res = match *poller {
+            Some(ref mut poller) => poller.round(self, false),
+            None => RxPoller::new(self.socket_id)
+                .and_then(|new_poller| poller.insert(new_poller).round(self, false)),
+        };
+        match res {
+            Ok(received) => received > 0,
+            Err(e) => {
+                error!("RxAgent service terminated with an error {e}");
+                self.running.store(false, Ordering::Release);
+                self.see(u64::MAX);
+                false
+            }
+        }
+    }
+
+    /// Stop the `RxAgent`, waiting for the polling loop to terminate unless it runs on the tokio
+    /// blocking pool.
+    pub(crate) fn stop(self: &Arc<Self>) {
+        self.running.store(false, Ordering::Release);
+        let handle = match self.handle.lock() {
+            Ok(mut handle) => handle.take(),
+            Err(_) => None,
+        };
+        match handle {
+            Some(RxHandle::Pinned(handle)) => {
+                if handle.join().is_err() {
+                    error!("RxAgent thread panicked");
+                }
+            }
+            Some(RxHandle::Lcore(lcore_id)) => {
+                // SAFETY: ffi
+                let _ret = unsafe { rte_eal_wait_lcore(lcore_id) };
+            }
+            Some(RxHandle::Service(service)) => {
+                drop(service);
+                self.see(u64::MAX);
+                info!("RxAgent service terminated");
+            }
+            Some(RxHandle::Blocking) | None => {}
+        }
+    }
+
+    /// Register a (`port_id`, `queue_id`) to an `RxAgent`.
+    ///
+    /// Adds a (`port_id`, `queue_id`) pair to the set to be polled. Received fragments are
+    /// merged with GRO if it's enabled in `conf`.
+    ///
+    /// # Errors
+    ///
+    /// - Returns an `Error::NotStart` if the agent had already been stopped.
+    /// - Returns an `Error::Already` if the pair had already been registered.
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        port_id: u16,
+        queue_id: u16,
+        conf: Arc<PortConf>,
+    ) -> Result<()> {
+        if !self.running.load(Ordering::Acquire) {
+            return Err(Error::NotStart);
         }
+        let _version = self.update(|tasks| match tasks.entry((port_id, queue_id)) {
+            Entry::Occupied(_) => Err(Error::Already),
+            Entry::Vacant(entry) => {
+                let counters = queue_counters(port_id, queue_id);
+                let _task = entry.insert(RxTask { conf, counters });
+                Ok(())
+            }
+        })?;
         Ok(())
     }
 
     /// Unregister a (`port_id`, `queue_id`) from an `RxAgent`.
     ///
-    /// Removes the (`port_id`, `queue_id`) pair from the polled set.
+    /// Removes the (`port_id`, `queue_id`) pair from the polled set, and waits for the polling
+    /// loop to stop polling it, for `RX_UNREGISTER_TIMEOUT` at most.
     ///
     /// # Errors
     ///
+    /// - Lock poisoned.
     /// - Returns an `Error::NotStart` if the agent had already been stopped.
     /// - Returns an `Error::NotExist` if the pair had not been registered.
+    /// - Returns an `Error::TimedOut` if the polling loop doesn't respond in time, in which case
+    ///   the pair is registered again.
     pub(crate) fn unregister(self: &Arc<Self>, port_id: u16, queue_id: u16) -> Result<()> {
         if !self.running.load(Ordering::Acquire) {
             return Err(Error::NotStart);
         }
-        if !self
-            .tasks
-            .lock()
-            .map_err(Error::from)?
-            .remove(&(port_id, queue_id))
-        {
-            return Err(Error::NotExist);
+        let mut removed = None;
+        let version = self.update(|tasks| {
+            removed = Some(tasks.remove(&(port_id, queue_id)).ok_or(Error::NotExist)?);
+            Ok(())
+        })?;
+        let task = removed.ok_or(Error::NotExist)?;
+        let seen = self.seen.lock().map_err(Error::from)?;
+        let (seen, res) = self
+            .seen_changed
+            .wait_timeout_while(seen, RX_UNREGISTER_TIMEOUT, |seen| *seen < version)
+            .map_err(Error::from)?;
+        drop(seen);
+        if res.timed_out() {
+            warn!("RxAgent not responding, {port_id}:{queue_id} is still polled");
+            let _version = self.update(move |tasks| {
+                let _task = tasks.insert((port_id, queue_id), task);
+                Ok(())
+            })?;
+            return Err(Error::TimedOut);
+        }
+        Ok(())
+    }
+
+    /// Record that the polling loop works on `version` of `tasks`, waking up those waiting for
+    /// it.
+    fn see(&self, version: u64) {
+        // A poisoned lock still holds a version.
+        *self.seen.lock().unwrap_or_else(PoisonError::into_inner) = version;
+        self.seen_changed.notify_all();
+    }
+
+    /// Publish a new snapshot of `tasks` changed by `f`, returning its version.
+    fn update(&self, f: impl FnOnce(&mut RxTaskMap) -> Result<()>) -> Result<u64> {
+        let mut tasks = self.tasks.write().map_err(Error::from)?;
+        let mut new_tasks = RxTaskMap::clone(&tasks);
+        f(&mut new_tasks)?;
+        *tasks = Arc::new(new_tasks);
+        Ok(self.version.fetch_add(1, Ordering::AcqRel).wrapping_add(1))
+    }
+
+    /// Register a (`port_id`, `queue_id`) to the `RxAgent` running as `exec` specifies, which is
+    /// started if there's none.
+    ///
+    /// Agents on lcores, service lcores or CPU cores are shared by all devices, while agents on the
+    /// tokio blocking pool are shared by queues of the same device only.
+    ///
+    /// # Errors
+    ///
+    /// - Lock poisoned.
+    /// - Failed to start the `RxAgent`.
+    /// - Returns an `Error::Already` if the pair had already been registered.
+    pub(crate) fn attach(
+        socket_id: i32,
+        exec: RxExec,
+        port_id: u16,
+        queue_id: u16,
+        conf: Arc<PortConf>,
+    ) -> Result<Arc<Self>> {
+        let key = match exec {
+            RxExec::Blocking => (exec, Some(port_id)),
+            RxExec::Lcore(_) | RxExec::Pinned(_) | RxExec::Service(_) => (exec, None),
+        };
+        let mut agents = RX_AGENTS.lock().map_err(Error::from)?;
+        let agent = match agents.entry(key) {
+            Entry::Occupied(entry) => Arc::clone(entry.get()),
+            Entry::Vacant(entry) => Arc::clone(entry.insert(Self::start(socket_id, exec)?)),
+        };
+        agent.register(port_id, queue_id, conf)?;
+        Ok(agent)
+    }
+
+    /// Unregister a (`port_id`, `queue_id`) from an `RxAgent` got by `attach`, and stop the agent
+    /// if no queues are left.
+    ///
+    /// # Errors
+    ///
+    /// - Lock poisoned.
+    /// - Returns an `Error::NotExist` if the pair had not been registered.
+    pub(crate) fn detach(self: &Arc<Self>, port_id: u16, queue_id: u16) -> Result<()> {
+        let mut agents = RX_AGENTS.lock().map_err(Error::from)?;
+        self.unregister(port_id, queue_id)?;
+        if self.tasks.read().map_err(Error::from)?.is_empty() {
+            agents.retain(|_, agent| !Arc::ptr_eq(agent, self));
+            self.stop();
         }
         Ok(())
     }
@@ -390,6 +1453,362 @@ impl Drop for RxAgent {
     }
 }
 
+/// State of the stack processing received packets, run by the polling loop of an `RxAgent`, or
+/// by a worker of an `eventdev::Pipeline` on the packets injected by the agents.
+pub(crate) struct RxWorker {
+    /// Table reassembling IP fragments.
+    frag_tbl: IpFragmentTable,
+    /// Fragments to be freed.
+    death_row: IpFragDeathRow,
+    /// When expired fragments were freed last time.
+    last_expire: Instant,
+    /// Packets delivered to sockets in a burst.
+    delivered: Vec<(i32, RecvResult)>,
+}
+
+// SAFETY: the tables and the packets are only touched by the thread running a burst at a time.
+#[allow(unsafe_code)]
+unsafe impl Send for RxWorker {}
+
+impl RxWorker {
+    /// Create the state of the stack, allocating the tables on `socket_id`.
+    pub(crate) fn new(socket_id: i32) -> Result<Self> {
+        Ok(Self {
+            frag_tbl: IpFragmentTable::new(socket_id)?,
+            death_row: IpFragDeathRow::new(socket_id)?,
+            last_expire: Instant::now(),
+            delivered: Vec::new(),
+        })
+    }
+
+    /// Free the expired fragments if they are not freed for a while.
+    fn expire(&mut self) {
+        if self.last_expire.elapsed() >= IP_FRAG_EXPIRE_INTERVAL {
+            self.frag_tbl.expire(&mut self.death_row);
+            self.death_row.free();
+            self.last_expire = Instant::now();
+        }
+    }
+
+    /// Pass a packet received from `port_id` to the taps and the stack.
+    fn ingest(&mut self, port_id: u16, m: Mbuf) {
+        capture::mirror(port_id, Direction::Inbound, &m);
+        dump::observe(port_id, Direction::Inbound, &m);
+        socket::put_raw(port_id, &m);
+        if let Some(res) = handle_ether(m, &mut self.frag_tbl, &mut self.death_row) {
+            self.delivered.push(res);
+        }
+        if self.death_row.len() >= DEATH_ROW_FLUSH_THRESHOLD {
+            self.death_row.free();
+        }
+    }
+
+    /// Finish a burst, delivering its datagrams to each mailbox in one shot.
+    fn finish(&mut self) {
+        self.death_row.free();
+        if let Err(e) = socket::put_mailboxes(&mut self.delivered) {
+            error!("An error {e} occurred in `put_mailboxes`");
+        }
+    }
+
+    /// Process a burst of packets pulled out of an event pipeline. Returns the number of them
+    /// dropped by the classifier.
+    pub(crate) fn process(&mut self, pkts: impl IntoIterator<Item = Mbuf>) -> u64 {
+        self.expire();
+        for m in pkts {
+            self.ingest(m.port(), m);
+        }
+        self.finish();
+        CLASSIFIER_DROPPED.with(Cell::take)
+    }
+}
+
+/// State of the polling loop of an `RxAgent`, kept across its rounds.
+struct RxPoller {
+    /// The stack processing the packets received.
+    worker: RxWorker,
+    /// Sleeper waiting for RX interrupts while the queues are idle.
+    sleeper: RxSleeper,
+    /// Version of `tasks`.
+    version: u64,
+    /// Snapshot of the queues polled.
+    tasks: Arc<RxTaskMap>,
+    /// Packets received in a burst, reused by all bursts.
+    ptrs: [*mut rte_mbuf; RX_BURST_CAPACITY as usize],
+    /// Generation of the event pipeline `ingress` is taken from, or `None` if it's to be taken
+    /// again.
+    pipeline: Option<u64>,
+    /// Port of the event pipeline the packets received are injected into, if any.
+    ingress: Option<Ingress>,
+}
+
+// SAFETY: the packets are only touched by the thread running a round at a time.
+#[allow(unsafe_code)]
+unsafe impl Send for RxPoller {}
+
+#[allow(unsafe_code)]
+impl RxPoller {
+    /// Create the state of a polling loop, allocating the tables on `socket_id`.
+    fn new(socket_id: i32) -> Result<Self> {
+        Ok(Self {
+            worker: RxWorker::new(socket_id)?,
+            sleeper: RxSleeper::new(),
+            version: 0,
+            tasks: Arc::new(BTreeMap::new()),
+            ptrs: [ptr::null_mut(); RX_BURST_CAPACITY as usize],
+            pipeline: None,
+            ingress: None,
+        })
+    }
+
+    /// Poll each queue of `agent` once, waiting for RX interrupts before if `sleep` is set and
+    /// the queues are idle. Returns the number of packets received.
+    ///
+    /// While an event pipeline is running, the packets passing the filters are injected into it
+    /// instead of processed here.
+    fn round(&mut self, agent: &RxAgent, sleep: bool) -> Result<usize> {
+        let socket_id = agent.socket_id;
+        let latest = agent.version.load(Ordering::Acquire);
+        if latest != self.version {
+            // A poisoned lock still holds a consistent snapshot.
+            self.tasks = Arc::clone(&agent.tasks.read().unwrap_or_else(PoisonError::into_inner));
+            self.version = latest;
+            agent.see(latest);
+        }
+        if sleep && self.sleeper.idle(&self.tasks) {
+            // The port is given back before sleeping, so that a pipeline stopping in the meantime
+            // doesn't wait for the agent to wake up.
+            if self.ingress.take().is_some() {
+                self.pipeline = None;
+            }
+            self.sleeper.sleep(&self.tasks);
+        }
+        let generation = eventdev::generation();
+        if self.pipeline != Some(generation) {
+            // The port of a stopped pipeline is given back first.
+            self.ingress = None;
+            self.ingress = eventdev::ingress();
+            self.pipeline = Some(generation);
+        }
+        self.worker.expire();
+        let &mut Self {
+            ref mut worker,
+            ref mut sleeper,
+            ref tasks,
+            ref mut ptrs,
+            ref mut ingress,
+            ..
+        } = self;
+        let mut received = 0_usize;
+        for (&(port_id, queue_id), task) in tasks.iter() {
+            let conf = &task.conf;
+            let burst = conf.rx_burst.load(Ordering::Relaxed).min(RX_BURST_CAPACITY);
+            // SAFETY: `burst` fits in `ptrs`, and `n` packets at the front are valid
+            let mut n = unsafe { rte_eth_rx_burst(port_id, queue_id, ptrs.as_mut_ptr(), burst) };
+            trace!("{n} packets received");
+            if n == 0 {
+                continue;
+            }
+            enter_span!("rx_burst", port = port_id, queue = queue_id, n);
+            received = received.wrapping_add(usize::from(n));
+            sleeper.received();
+            let _bursts = task.counters.rx_bursts.fetch_add(1, Ordering::Relaxed);
+            let _packets = task
+                .counters
+                .rx_packets
+                .fetch_add(u64::from(n), Ordering::Relaxed);
+            if conf.gro.load(Ordering::Relaxed) {
+                if let Some(pkts) = ptrs.get_mut(..n as usize) {
+                    #[allow(clippy::cast_possible_truncation)] // no more than `n`
+                    {
+                        n = gso::reassemble(pkts, socket_id) as u16;
+                    }
+                }
+            }
+            let filter = bpf::port_filter(port_id);
+            // Packets of a burst are timestamped at once.
+            let rx_tsc = conf.rx_timestamp.then(timestamp::tsc);
+            // Packets of a burst are delivered to each mailbox in one shot.
+            for &ptr in ptrs.iter().take(n as _) {
+                let mut m = Mbuf::new_with_ptr(ptr)?;
+                if let Some(tsc) = rx_tsc {
+                    timestamp::stamp_rx(&mut m, tsc);
+                }
+                let Some(m) = exception::from_kernel(m) else {
+                    continue;
+                };
+                if filter.as_ref().map_or(false, |prog| !prog.matches_mbuf(&m)) {
+                    classifier_dropped();
+                    continue;
+                }
+                match *ingress {
+                    Some(ref mut pipeline) => pipeline.push(m),
+                    None => worker.ingest(port_id, m),
+                }
+            }
+            match *ingress {
+                Some(ref mut pipeline) => pipeline.flush(),
+                None => worker.finish(),
+            }
+            let dropped = CLASSIFIER_DROPPED.with(Cell::take);
+            if dropped > 0 {
+                let _dropped = task
+                    .counters
+                    .rx_dropped
+                    .fetch_add(dropped, Ordering::Relaxed);
+            }
+        }
+        Ok(received)
+    }
+}
+
+/// Sleeper of an `RxAgent`, which waits for RX interrupts once all the queues polled are idle
+/// for a while, and goes back to busy polling once a packet arrives.
+#[derive(Debug)]
+struct RxSleeper {
+    /// Queues whose RX interrupts are added to the epoll instance of the polling thread.
+    added: BTreeSet<(u16, u16)>,
+    /// When the last packet is received.
+    last_rx: Instant,
+}
+
+#[allow(unsafe_code)]
+impl RxSleeper {
+    /// Create a sleeper on the polling thread.
+    fn new() -> Self {
+        Self {
+            added: BTreeSet::new(),
+            last_rx: Instant::now(),
+        }
+    }
+
+    /// Mark that packets are received.
+    fn received(&mut self) {
+        self.last_rx = Instant::now();
+    }
+
+    /// Whether interrupts are enabled on all the queues `tasks` and they are idle for long
+    /// enough to sleep.
+    fn idle(&self, tasks: &RxTaskMap) -> bool {
+        let Some(idle) = tasks
+            .values()
+            .map(|task| task.conf.rx_intr)
+            .try_fold(Duration::MAX, |idle, rx_intr| {
+                rx_intr.map(|rx_intr| idle.min(rx_intr))
+            })
+        else {
+            return false;
+        };
+        !tasks.is_empty() && self.last_rx.elapsed() >= idle
+    }
+
+    /// Sleep until an RX interrupt fires or `RX_INTR_WAIT_TIMEOUT` expires on the queues `tasks`,
+    /// which are `idle`.
+    ///
+    /// Packets arriving right before the interrupts are enabled may be left in the queues until
+    /// the timeout expires.
+    fn sleep(&mut self, tasks: &RxTaskMap) {
+        // Queues unregistered are removed from the epoll instance.
+        self.added.retain(|&(port_id, queue_id)| {
+            if !tasks.contains_key(&(port_id, queue_id)) {
+                // SAFETY: ffi
+                let _errno = unsafe { Self::ctl(port_id, queue_id, RTE_INTR_EVENT_DEL) };
+                return false;
+            }
+            true
+        });
+        for &(port_id, queue_id) in tasks.keys() {
+            if self.added.contains(&(port_id, queue_id)) {
+                continue;
+            }
+            // SAFETY: ffi
+            let errno = unsafe { Self::ctl(port_id, queue_id, RTE_INTR_EVENT_ADD) };
+            if let Err(e) = Error::from_ret(errno, "rte_eth_dev_rx_intr_ctl_q") {
+                warn!("Failed to add RX interrupt of {port_id}:{queue_id}: {e:?}");
+                self.received();
+                return;
+            }
+            let _new = self.added.insert((port_id, queue_id));
+        }
+        for &(port_id, queue_id) in tasks.keys() {
+            // SAFETY: ffi
+            let _errno = unsafe { rte_eth_dev_rx_intr_enable(port_id, queue_id) };
+        }
+        // SAFETY: all zeros is an empty event
+        let mut events: Vec<rte_epoll_event> =
+            (0..tasks.len()).map(|_| unsafe { mem::zeroed() }).collect();
+        #[allow(clippy::cast_possible_truncation)] // less than a second
+        // SAFETY: `events` holds `tasks.len()` events
+        let n = unsafe {
+            rte_epoll_wait(
+                RTE_EPOLL_PER_THREAD,
+                events.as_mut_ptr(),
+                events.len().try_into().unwrap_or(c_int::MAX),
+                RX_INTR_WAIT_TIMEOUT.as_millis() as c_int,
+            )
+        };
+        trace!("RxAgent woken up with {n} RX interrupts");
+        for &(port_id, queue_id) in tasks.keys() {
+            // SAFETY: ffi
+            let _errno = unsafe { rte_eth_dev_rx_intr_disable(port_id, queue_id) };
+        }
+        self.received();
+    }
+
+    /// Add or remove the RX interrupt of a queue to or from the epoll instance of the current
+    /// thread.
+    unsafe fn ctl(port_id: u16, queue_id: u16, op: u32) -> c_int {
+        #[allow(clippy::cast_possible_wrap)] // small constants
+        // SAFETY: ffi
+        unsafe {
+            rte_eth_dev_rx_intr_ctl_q(
+                port_id,
+                queue_id,
+                RTE_EPOLL_PER_THREAD,
+                op as c_int,
+                ptr::null_mut(),
+            )
+        }
+    }
+}
+
+/// Entry of a worker lcore running the polling loop of an `RxAgent`, whose `Arc` is passed in
+/// `arg`.
+#[allow(unsafe_code)]
+unsafe extern "C" fn rx_lcore_main(arg: *mut c_void) -> c_int {
+    // SAFETY: `arg` is leaked from an `Arc<RxAgent>` in `RxAgent::start`
+    let this = unsafe { Arc::from_raw(arg.cast::<RxAgent>()) };
+    match this.run() {
+        Ok(()) => 0,
+        Err(e) => e.errno().saturating_neg(),
+    }
+}
+
+/// Pin the current thread to the CPU `core`.
+#[allow(unsafe_code)]
+pub(crate) fn pin_to_core(core: usize) -> Result<()> {
+    #[allow(clippy::cast_sign_loss)] // a positive constant
+    if core >= libc::CPU_SETSIZE as usize {
+        return Err(Error::InvalidArg);
+    }
+    // SAFETY: all zeros is an empty CPU set
+    let mut set = unsafe { mem::zeroed::<libc::cpu_set_t>() };
+    // SAFETY: `core` is less than `CPU_SETSIZE`
+    unsafe { libc::CPU_SET(core, &mut set) };
+    // SAFETY: `set` is valid
+    let ret = unsafe {
+        libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), ptr::addr_of!(set))
+    };
+    if ret != 0 {
+        return Err(Error::from(
+            std::io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::EINVAL),
+        ));
+    }
+    Ok(())
+}
+
 /// Tell the background Runtime that a new task's arrival.
 struct TxTask {
     /// Port id
@@ -397,123 +1816,259 @@ struct TxTask {
     /// Queue id
     queue_id: u16,
     /// For the newly spawned task to hear requests
-    rx: mpsc::Receiver<Mbuf>,
+    rx: mpsc::Receiver<TxRequest>,
+    /// For the newly spawned task to take packets from, if enabled
+    ring: Option<Arc<TxRing>>,
+    /// Settings of the port
+    conf: Arc<PortConf>,
     /// Notify caller the result
     done: Arc<AtomicI32>,
 }
 
 #[allow(unsafe_code)]
 impl TxAgent {
-    /// Start a `TxBuffer`, spawn a thread to do the sending job.
-    pub(crate) fn start() -> Arc<Self> {
-        let (sender, mut receiver) = mpsc::channel::<TxTask>(64);
-
+    /// Start a `TxAgent` with threads as `exec` specifies, to serve `n_queues` tx queues.
+    ///
+    /// # Errors
+    ///
+    /// - `Error::InvalidArg`: no thread, or more threads than tx queues, are specified, or a CPU
+    ///   core in `exec` is out of range or not available.
+    /// - Unable to spawn or pin the threads.
+    pub(crate) fn start(exec: TxExec, n_queues: u16) -> Result<Arc<Self>> {
+        exec.validate(n_queues)?;
         let tasks = Arc::new(Mutex::new(BTreeMap::new()));
-        let tasks1 = Arc::clone(&tasks);
-
-        #[allow(clippy::unwrap_used)] // impossible to panic since io and timer disabled
-        let rt = Builder::new_current_thread().build().unwrap();
-
-        let _handle = std::thread::spawn(move || {
-            /// spawn a new task doing polling on the given queue.
-            fn spawn_new_task(
-                tasks: &TaskSetType,
-                port_id: u16,
-                queue_id: u16,
-                mut rx: mpsc::Receiver<Mbuf>,
-            ) -> Result<()> {
-                let mut tasks = tasks.lock().map_err(Error::from)?;
-                let entry = tasks.entry((port_id, queue_id));
-                if matches!(entry, Entry::Occupied(_)) {
-                    Err(Error::Already)?;
-                }
+        let cores: Vec<Option<usize>> = match exec {
+            TxExec::Threads(n) => vec![None; n],
+            TxExec::PerQueue => vec![None; usize::from(n_queues.max(1))],
+            TxExec::Pinned { first, n } => (first..first.saturating_add(n)).map(Some).collect(),
+            TxExec::Service(lcore_id) => {
+                let (worker, service) = Self::start_service(lcore_id, Arc::clone(&tasks))?;
+                return Ok(Arc::new(TxAgent {
+                    workers: vec![worker],
+                    tasks,
+                    senders: Mutex::new(BTreeMap::new()),
+                    service: Some(service),
+                }));
+            }
+        };
+        let workers = cores
+            .into_iter()
+            .enumerate()
+            .map(|(index, core)| Self::spawn_worker(index, core, Arc::clone(&tasks)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Arc::new(TxAgent {
+            workers,
+            tasks,
+            senders: Mutex::new(BTreeMap::new()),
+            service: None,
+        }))
+    }
 
-                let handle = task::spawn_local(async move {
-                    let mut txbuf = TxBuffer::new(port_id, queue_id);
-                    while let Some(m) = rx.recv().await {
-                        txbuf.buffer(m)?;
-                    }
-                    Result::Ok(())
-                });
+    /// Spawn a thread, pinned to `core` if given, to do the sending job. Returns the sender of
+    /// queues to be served by it.
+    ///
+    /// The thread returns once the sender is dropped and all its tasks have returned.
+    fn spawn_worker(
+        index: usize,
+        core: Option<usize>,
+        tasks: TaskSetType,
+    ) -> Result<mpsc::Sender<TxTask>> {
+        let (sender, receiver) = mpsc::channel::<TxTask>(64);
 
-                #[allow(clippy::let_underscore_future)] // this is only a ref to a Future
-                {
-                    _ = entry.or_insert(handle);
+        let rt = Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .map_err(|e| Error::from(e.raw_os_error().unwrap_or(libc::EAGAIN)))?;
+
+        let (pinned_tx, pinned_rx) = std_mpsc::sync_channel(1);
+        let _handle = std::thread::Builder::new()
+            .name(format!("tx-agent-{index}"))
+            .spawn(move || {
+                if let Some(core) = core {
+                    let res = pin_to_core(core);
+                    let ok = res.is_ok();
+                    _ = pinned_tx.send(res);
+                    if !ok {
+                        return;
+                    }
                 }
-                Ok(())
-            }
 
-            let local = LocalSet::new();
+                let local = LocalSet::new();
+                let _main_task = local.spawn_local(serve_tx_tasks(receiver, tasks));
 
-            let _main_task = local.spawn_local(async move {
-                while let Some(TxTask {
-                    port_id,
-                    queue_id,
-                    rx,
-                    done,
-                }) = receiver.recv().await
-                {
-                    let val = match spawn_new_task(&tasks1, port_id, queue_id, rx) {
-                        Ok(()) => 0,
-                        Err(e) => (e as i32).saturating_neg(),
-                    };
-                    done.store(val, Ordering::Release);
-                }
-            });
+                // return once sender is dropped and all spawned tasks have returned.
+                // sender is dropped before self's drop.
+                rt.block_on(local);
+            })
+            .map_err(|e| Error::from(e.raw_os_error().unwrap_or(libc::EAGAIN)))?;
+        if core.is_some() {
+            pinned_rx.recv().map_err(Error::from)??;
+        }
+        Ok(sender)
+    }
 
-            // return once sender is dropped and all spawned tasks have returned.
-            // sender is dropped before self's drop.
-            rt.block_on(local);
-        });
-        let this = TxAgent { sender, tasks };
-        Arc::new(this)
+    /// Start a service on service lcore `lcore_id` to do the sending job, which drives the tasks
+    /// a step on each round. Returns the sender of queues to be served by it, along with the
+    /// service.
+    ///
+    /// The runtime driving the tasks is created by the service lcore in the first round, and
+    /// dropped there once the service is being stopped.
+    fn start_service(
+        lcore_id: u32,
+        tasks: TaskSetType,
+    ) -> Result<(mpsc::Sender<TxTask>, TxService)> {
+        let (sender, receiver) = mpsc::channel::<TxTask>(64);
+        let id = NEXT_TX_SERVICE.fetch_add(1, Ordering::Relaxed);
+        let state = Arc::new(TxServiceState::default());
+        let shared = Arc::clone(&state);
+        let mut receiver = Some(receiver);
+        let name = format!("tx-agent-{lcore_id}");
+        let callback = Box::new(move || tx_service_round(id, &mut receiver, &tasks, &shared));
+        let service = Service::start(&name, lcore_id, SOCKET_ID_ANY, callback)?;
+        Ok((sender, TxService { state, service }))
     }
 
     /// Register a (`port_id`, `queue_id`) to a `TxAgent`.
     ///
-    /// It will spawn a new `Task` polling the given queue.
+    /// It will spawn a new `Task` polling the given queue. Packets larger than the MTU in `conf`
+    /// are fragmented before sent, with GSO if it's enabled in `conf`. Packets are carried by a
+    /// `TxRing` if its size is set in the `AgentConf`.
     ///
     /// # Errors
     ///
     /// - `Error::Already`: if the caller tries to register a queue that is
     /// already registered.
+    /// - The `TxRing` fails to be created.
     pub(crate) fn register(
         self: &Arc<Self>,
         port_id: u16,
         queue_id: u16,
-    ) -> Result<mpsc::Sender<Mbuf>> {
-        let (tx, rx) = mpsc::channel::<Mbuf>(TX_CHAN_SIZE);
+        conf: Arc<PortConf>,
+    ) -> Result<TxChannel> {
+        let agent_conf = agent_conf();
+        let (tx, rx) = mpsc::channel::<TxRequest>(agent_conf.tx_chan_size);
+        let ring = agent_conf
+            .tx_ring_size
+            .map(|size| TxRing::create(port_id, queue_id, size))
+            .transpose()?;
         let done = Arc::new(AtomicI32::new(1));
         let task = TxTask {
             port_id,
             queue_id,
             rx,
+            ring: ring.clone(),
+            conf,
             done: Arc::clone(&done),
         };
-        self.sender.try_send(task).map_err(Error::from)?;
+        let worker = usize::from(queue_id)
+            .checked_rem(self.workers.len())
+            .and_then(|index| self.workers.get(index))
+            .ok_or(Error::NotStart)?;
+        worker.try_send(task).map_err(Error::from)?;
         while done.load(Ordering::Acquire) == 1 {}
         let errno = done.load(Ordering::Relaxed);
-        Error::from_ret(errno)?;
-        Ok(tx)
+        Error::from_ret(errno, "spawn_tx_task")?;
+        let _prev = self
+            .senders
+            .lock()
+            .map_err(Error::from)?
+            .insert((port_id, queue_id), tx.clone());
+        Ok(TxChannel { requests: tx, ring })
     }
 
     /// Unregister a (`port_id`, `queue_id`) from a `TxAgent`.
     ///
-    /// It will aborts the specific `Task` doing polling.
+    /// The specific `Task` doing polling keeps sending its buffered packets until they are all
+    /// sent or a timeout expires, then it's stopped. Returns the number of dropped packets.
+    ///
+    /// It blocks the calling thread until the `Task` reports, for `3 * TX_DRAIN_TIMEOUT` at most.
     ///
     /// # Errors
     ///
     /// - `Error::NotExist`: if the caller tries to unregister a queue that is
     /// not registered.
-    pub(crate) fn unregister(self: &Arc<Self>, port_id: u16, queue_id: u16) -> Result<()> {
+    pub(crate) fn unregister(self: &Arc<Self>, port_id: u16, queue_id: u16) -> Result<usize> {
         let handle = self
             .tasks
             .lock()
             .map_err(Error::from)?
             .remove(&(port_id, queue_id))
             .ok_or(Error::NotExist)?;
-        handle.abort();
-        Ok(())
+        let sender = self
+            .senders
+            .lock()
+            .map_err(Error::from)?
+            .remove(&(port_id, queue_id));
+        let (done_tx, done_rx) = std_mpsc::sync_channel(1);
+        let deadline = Instant::now()
+            .checked_add(TX_DRAIN_TIMEOUT)
+            .ok_or(Error::InvalidArg)?;
+        let mut req = TxRequest::Stop(done_tx);
+        // Wait for the requests ahead to be handled if the channel is full.
+        while let Some(ref sender) = sender {
+            match sender.try_send(req) {
+                Err(mpsc::error::TrySendError::Full(r)) if Instant::now() < deadline => {
+                    req = r;
+                    // The `Task` takes requests between bursts.
+                    std::thread::sleep(TX_FLUSH_INTERVAL);
+                }
+                Ok(()) | Err(_) => break,
+            }
+        }
+        // The `Task` waits for `TX_DRAIN_TIMEOUT` at most before it reports.
+        let dropped = done_rx
+            .recv_timeout(TX_DRAIN_TIMEOUT.saturating_mul(2))
+            .unwrap_or_else(|_| {
+                warn!("Tx task on {port_id}:{queue_id} not responding, aborted");
+                handle.abort();
+                0
+            });
+        if dropped > 0 {
+            warn!("{dropped} packets dropped on {port_id}:{queue_id}");
+        }
+        Ok(dropped)
+    }
+
+    /// Stop sending to a (`port_id`, `queue_id`) while it's driven directly if `paused`, or
+    /// resume sending otherwise. Packets sent meanwhile are buffered until it's resumed.
+    ///
+    /// It blocks the calling thread until the `Task` has stopped or resumed sending, for
+    /// `2 * TX_DRAIN_TIMEOUT` at most.
+    ///
+    /// # Errors
+    ///
+    /// - `Error::NotExist`: the queue is not registered.
+    /// - `Error::TimedOut`: the `Task` doesn't respond in `TX_DRAIN_TIMEOUT`.
+    pub(crate) fn pause(self: &Arc<Self>, port_id: u16, queue_id: u16, paused: bool) -> Result<()> {
+        let sender = self
+            .senders
+            .lock()
+            .map_err(Error::from)?
+            .get(&(port_id, queue_id))
+            .cloned()
+            .ok_or(Error::NotExist)?;
+        let (done_tx, done_rx) = std_mpsc::sync_channel(1);
+        let deadline = Instant::now()
+            .checked_add(TX_DRAIN_TIMEOUT)
+            .ok_or(Error::InvalidArg)?;
+        let mut req = TxRequest::Pause(paused, done_tx);
+        // Wait for the requests ahead to be handled if the channel is full.
+        loop {
+            match sender.try_send(req) {
+                Err(mpsc::error::TrySendError::Full(r)) if Instant::now() < deadline => {
+                    req = r;
+                    // The `Task` takes requests between bursts.
+                    std::thread::sleep(TX_FLUSH_INTERVAL);
+                }
+                Err(mpsc::error::TrySendError::Full(_)) => return Err(Error::TimedOut),
+                Err(mpsc::error::TrySendError::Closed(_)) => return Err(Error::NotExist),
+                Ok(()) => break,
+            }
+        }
+        #[allow(clippy::map_err_ignore)]
+        done_rx
+            .recv_timeout(TX_DRAIN_TIMEOUT)
+            .map_err(|_| Error::TimedOut)
     }
 }
 
@@ -526,6 +2081,265 @@ impl Drop for TxAgent {
             }
             tasks.clear();
         }
+        drop(self.service.take());
+    }
+}
+
+/// Spawn a task on the current `LocalSet` serving tx queue `queue_id` of port `port_id`, which
+/// handles the requests from `rx`, and the packets from `ring` if any.
+fn spawn_tx_task(
+    tasks: &TaskSetType,
+    port_id: u16,
+    queue_id: u16,
+    mut rx: mpsc::Receiver<TxRequest>,
+    ring: Option<Arc<TxRing>>,
+    conf: Arc<PortConf>,
+) -> Result<()> {
+    let mut tasks = tasks.lock().map_err(Error::from)?;
+    let entry = tasks.entry((port_id, queue_id));
+    if matches!(entry, Entry::Occupied(_)) {
+        Err(Error::Already)?;
+    }
+
+    let handle = task::spawn_local(async move {
+        let mut txbuf = TxBuffer::new(port_id, queue_id, conf);
+        let mut ticker = time::interval(agent_conf().tx_flush_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let ring = ring.map(TxRingGuard);
+        let ring = ring.as_ref().map(|guard| &*guard.0);
+        while let Some(req) = next_request(&mut rx, &mut txbuf, &mut ticker, ring).await {
+            match req {
+                TxRequest::Send(m, done) => {
+                    let res = txbuf.buffer(m);
+                    if let Some(done) = done {
+                        _ = done.send(res);
+                    } else if let Err(e) = res {
+                        warn!("Failed to send packet on {port_id}:{queue_id}: {e:?}");
+                    }
+                }
+                TxRequest::SendBatch(batch, done) => {
+                    _ = done.send(txbuf.buffer_batch(batch));
+                }
+                TxRequest::Flush(done) => {
+                    let left = match ring {
+                        Some(ring) => drain_ring(ring, &mut txbuf, TX_DRAIN_TIMEOUT),
+                        None => txbuf.drain(TX_DRAIN_TIMEOUT),
+                    };
+                    _ = done.send(left);
+                }
+                TxRequest::Stop(done) => {
+                    let mut dropped = match ring {
+                        Some(ring) => {
+                            _ = drain_ring(ring, &mut txbuf, TX_DRAIN_TIMEOUT);
+                            let left = ring.close();
+                            let _dropped = txbuf
+                                .counters
+                                .tx_dropped
+                                .fetch_add(left as u64, Ordering::Relaxed);
+                            left
+                        }
+                        None => {
+                            _ = txbuf.drain(TX_DRAIN_TIMEOUT);
+                            0
+                        }
+                    };
+                    dropped = dropped.saturating_add(txbuf.clear());
+                    _ = done.send(dropped);
+                    break;
+                }
+                TxRequest::Pause(paused, done) => {
+                    txbuf.paused = paused;
+                    _ = txbuf.flush();
+                    _ = done.send(());
+                }
+            }
+        }
+        Result::Ok(())
+    });
+
+    #[allow(clippy::let_underscore_future)] // this is only a ref to a Future
+    {
+        _ = entry.or_insert(handle);
+    }
+    Ok(())
+}
+
+/// Spawn a task for each queue received from `receiver` on the current `LocalSet`, until the
+/// sender is dropped.
+async fn serve_tx_tasks(mut receiver: mpsc::Receiver<TxTask>, tasks: TaskSetType) {
+    while let Some(TxTask {
+        port_id,
+        queue_id,
+        rx,
+        ring,
+        conf,
+        done,
+    }) = receiver.recv().await
+    {
+        let val = match spawn_tx_task(&tasks, port_id, queue_id, rx, ring, conf) {
+            Ok(()) => 0,
+            Err(e) => e.errno().saturating_neg(),
+        };
+        done.store(val, Ordering::Release);
+    }
+}
+
+/// The tasks of a `TxAgent` driven by a service, along with their runtime, which live on the
+/// service lcore.
+struct TxServiceWorker {
+    /// The tasks serving the tx queues, which are dropped before the runtime.
+    local: LocalSet,
+    /// The runtime driving `local`.
+    rt: Runtime,
+}
+
+impl TxServiceWorker {
+    /// Create the runtime on the calling thread, along with the task taking the queues sent
+    /// through `receiver`.
+    fn new(receiver: mpsc::Receiver<TxTask>, tasks: TaskSetType) -> Result<Self> {
+        let rt = Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .map_err(|e| Error::from(e.raw_os_error().unwrap_or(libc::EAGAIN)))?;
+        let local = LocalSet::new();
+        let _main_task = local.spawn_local(serve_tx_tasks(receiver, tasks));
+        Ok(Self { local, rt })
+    }
+
+    /// Run the tasks ready, and the timers due.
+    fn step(&self) {
+        self.rt.block_on(self.local.run_until(task::yield_now()));
+    }
+}
+
+/// Teardown of a `TxAgent` service, shared with the service lcore running it.
+#[derive(Debug, Default)]
+struct TxServiceState {
+    /// Set once the service is being stopped.
+    stopping: AtomicBool,
+    /// Set by the service lcore once the worker is dropped.
+    stopped: AtomicBool,
+}
+
+/// The service running the worker of a `TxAgent`, which has the service lcore drop the worker
+/// before the service is stopped.
+struct TxService {
+    /// Teardown state shared with the service lcore.
+    state: Arc<TxServiceState>,
+    /// The service, stopped after the worker is dropped.
+    service: Service,
+}
+
+impl Drop for TxService {
+    fn drop(&mut self) {
+        self.state.stopping.store(true, Ordering::Release);
+        let start = Instant::now();
+        while !self.state.stopped.load(Ordering::Acquire) {
+            if start.elapsed() >= TX_DRAIN_TIMEOUT {
+                warn!("{:?} didn't drop its TxAgent worker in time", self.service);
+                break;
+            }
+            std::thread::sleep(TX_FLUSH_INTERVAL);
+        }
+    }
+}
+
+/// Run a round of the `TxAgent` service `id` on the service lcore, creating its worker in the
+/// first round and dropping it once the service is being stopped. Returns whether the tasks are
+/// driven.
+fn tx_service_round(
+    id: u64,
+    receiver: &mut Option<mpsc::Receiver<TxTask>>,
+    tasks: &TaskSetType,
+    state: &TxServiceState,
+) -> bool {
+    TX_SERVICE_WORKERS.with(|workers| {
+        let mut workers = workers.borrow_mut();
+        if state.stopping.load(Ordering::Acquire) {
+            drop(workers.remove(&id));
+            state.stopped.store(true, Ordering::Release);
+            return false;
+        }
+        let worker = match workers.entry(id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let Some(receiver) = receiver.take() else {
+                    return false;
+                };
+                match TxServiceWorker::new(receiver, Arc::clone(tasks)) {
+                    Ok(worker) => entry.insert(worker),
+                    Err(e) => {
+                        error!("TxAgent service failed to start with an error {e}");
+                        return false;
+                    }
+                }
+            }
+        };
+        worker.step();
+        true
+    })
+}
+
+/// Receive the next request to the Task polling a tx queue. Meanwhile, packets left in `txbuf`
+/// are flushed on each tick of `ticker`, so that they are not held until the next request.
+///
+/// With a `ring`, its packets are moved to `txbuf` in bursts, and requests are checked between
+/// bursts. The Task only waits for packets once the ring is empty.
+async fn next_request(
+    rx: &mut mpsc::Receiver<TxRequest>,
+    txbuf: &mut TxBuffer,
+    ticker: &mut Interval,
+    ring: Option<&TxRing>,
+) -> Option<TxRequest> {
+    let Some(ring) = ring else {
+        loop {
+            if txbuf.len() == 0 || txbuf.paused {
+                return rx.recv().await;
+            }
+            tokio::select! {
+                req = rx.recv() => return req,
+                _ = ticker.tick() => txbuf.retry(ticker.period()),
+            }
+        }
+    };
+    loop {
+        if ring.pull(txbuf, usize::from(MAX_PKT_BURST)) > 0 {
+            match rx.try_recv() {
+                Ok(req) => return Some(req),
+                Err(mpsc::error::TryRecvError::Disconnected) => return None,
+                // Let the other Tasks on this thread run between bursts.
+                Err(mpsc::error::TryRecvError::Empty) => task::yield_now().await,
+            }
+            continue;
+        }
+        // Packets left in the ring wait until `txbuf` has room for them.
+        let has_room = txbuf.len() < txbuf.capacity;
+        if has_room && !ring.park() {
+            continue;
+        }
+        let idle = txbuf.len() == 0 || txbuf.paused;
+        tokio::select! {
+            req = rx.recv() => return req,
+            () = ring.ready.notified(), if has_room => {}
+            _ = ticker.tick(), if !idle => txbuf.retry(ticker.period()),
+        }
+        ring.parked.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Move the packets in `ring` to `txbuf` and send them along with those buffered, until all of
+/// them are sent or `timeout` expires. Packets enqueued afterwards are left in the ring. Returns
+/// the number of packets unsent.
+fn drain_ring(ring: &TxRing, txbuf: &mut TxBuffer, timeout: Duration) -> usize {
+    let start = Instant::now();
+    let mut pending = usize::try_from(ring.ring.len()).unwrap_or(usize::MAX);
+    loop {
+        let pulled = ring.pull(txbuf, pending);
+        pending = pending.saturating_sub(pulled);
+        let left = txbuf.drain(timeout.saturating_sub(start.elapsed()));
+        if pending == 0 || txbuf.paused || start.elapsed() >= timeout {
+            return left.saturating_add(pending);
+        }
     }
 }
 
@@ -537,8 +2351,27 @@ struct TxBuffer {
     port_id: u16,
     /// `queue_id` that the mbufs are sent to.
     queue_id: u16,
-    /// `mbuf`s held.
-    mbufs: VecDeque<*mut rte_mbuf>,
+    /// `mbuf`s held in each traffic class, the first of which has the highest priority. There's
+    /// a single class unless the port has weights of traffic classes.
+    classes: Vec<VecDeque<*mut rte_mbuf>>,
+    /// Max number of `mbuf`s held.
+    capacity: usize,
+    /// Settings of the port, which may be changed at runtime.
+    conf: Arc<PortConf>,
+    /// Counters of the queue.
+    counters: Arc<QueueCounters>,
+    /// Since when the device takes no packet, or `None` if the last flush made progress.
+    stalled_since: Option<Instant>,
+    /// Backoff between timed retries while the device takes no packet.
+    backoff: Duration,
+    /// When the next timed retry is due.
+    retry_at: Instant,
+    /// Token bucket enforcing the rate limit of the queue, if any.
+    shaper: Option<TokenBucket>,
+    /// Meter policing the packets sent on the queue, if any.
+    policer: Option<PoliceState>,
+    /// Whether sending is paused while the queue is driven directly.
+    paused: bool,
 }
 
 // SAFETY: `TxBuffer` is globally accessed.
@@ -548,11 +2381,31 @@ unsafe impl Send for TxBuffer {}
 #[allow(unsafe_code)]
 impl TxBuffer {
     /// Allocate a `TxBuffer` on the given port and queue.
-    fn new(port_id: u16, queue_id: u16) -> Self {
+    fn new(port_id: u16, queue_id: u16, conf: Arc<PortConf>) -> Self {
+        let capacity = agent_conf().tx_buf_size;
+        let n_classes = if conf.tx_weights.is_some() {
+            TX_CLASSES
+        } else {
+            1
+        };
         Self {
             port_id,
             queue_id,
-            mbufs: VecDeque::with_capacity(TX_BUF_SIZE),
+            classes: (0..n_classes)
+                .map(|_| VecDeque::with_capacity(capacity))
+                .collect(),
+            capacity,
+            counters: queue_counters(port_id, queue_id),
+            stalled_since: None,
+            backoff: Duration::ZERO,
+            retry_at: Instant::now(),
+            shaper: conf.tx_rate_limit.map(TokenBucket::new),
+            // The profile is checked when the port is configured.
+            policer: conf
+                .tx_policer
+                .and_then(|policer| PoliceState::new(policer).ok()),
+            paused: false,
+            conf,
         }
     }
 
@@ -572,13 +2425,50 @@ impl TxBuffer {
         }
     }
 
+    /// Number of `mbuf`s held.
+    fn len(&self) -> usize {
+        self.classes.iter().map(VecDeque::len).sum()
+    }
+
+    /// The `mbuf`s held in traffic class `class`.
+    fn class_mut(&mut self, class: usize) -> Result<&mut VecDeque<*mut rte_mbuf>> {
+        self.classes.get_mut(class).ok_or(Error::OutOfRange)
+    }
+
+    /// The traffic class of an Ethernet frame by its IP precedence, i.e. the top 3 bits of DSCP,
+    /// where network control traffic goes first. It's always 0 with a single class.
+    fn class_of(&self, m: &Mbuf) -> usize {
+        if self.classes.len() < TX_CLASSES {
+            return 0;
+        }
+        let ether_type = EtherHdr::from_mbuf(m, 0).map_or(0, |hdr| hdr.ether_type());
+        let l3_off = usize::from(ETHER_HDR_LEN);
+        #[allow(clippy::cast_possible_truncation)] // traffic class of 8 bits
+        let tos = match u32::from(ether_type) {
+            RTE_ETHER_TYPE_IPV4 => {
+                Ipv4Hdr::from_mbuf(m, l3_off).map_or(0, |hdr| hdr.type_of_service())
+            }
+            RTE_ETHER_TYPE_IPV6 => {
+                Ipv6Hdr::from_mbuf(m, l3_off).map_or(0, |hdr| (hdr.vtc_flow() >> 20) as u8)
+            }
+            _ => 0,
+        };
+        let precedence = usize::from(tos >> 5);
+        7_usize.wrapping_sub(precedence) / 2
+    }
+
     /// Do IP fragmentation and buffer them.
     #[inline]
-    fn do_fragment(&mut self, m: Mbuf) -> Result<()> {
-        // need fragment
-        let exp_nb_frags = m.pkt_len().wrapping_div(RTE_ETHER_MTU as _).wrapping_add(1);
+    fn do_fragment(&mut self, m: Mbuf, mtu: u16, class: usize) -> Result<()> {
+        // need fragment, each fragment carries at least `mtu - 48` bytes (IPv6 and fragment
+        // headers) of payload, rounded down to a multiple of 8.
+        let frag_size = usize::from(mtu).saturating_sub(48) & !7;
+        if frag_size == 0 {
+            return Err(Error::InvalidArg);
+        }
+        let exp_nb_frags = m.pkt_len().div_ceil(frag_size);
         // Ensure there's enough buffer to hold fragmented data.
-        if TX_BUF_SIZE.wrapping_sub(self.mbufs.len()) < exp_nb_frags.wrapping_add(1) {
+        if self.capacity.saturating_sub(self.len()) < exp_nb_frags.wrapping_add(1) {
             return Err(Error::NoBuf);
         }
         let mut frags: Vec<*mut rte_mbuf> = vec![ptr::null_mut(); exp_nb_frags];
@@ -596,22 +2486,20 @@ impl TxBuffer {
         let errno = unsafe {
             let l3_type = (*pm).packet_type_union.packet_type & RTE_PTYPE_L3_MASK;
             if l3_type == RTE_PTYPE_L3_IPV4 {
-                #[allow(clippy::cast_possible_truncation)] // MTU = 1500 < u16::MAX
                 rte_ipv4_fragment_packet(
                     pm,
                     frags.as_mut_ptr(),
                     exp_nb_frags.try_into().map_err(Error::from)?,
-                    RTE_ETHER_MTU as _,
+                    mtu,
                     (*pm).pool,
                     (*pm).pool,
                 )
             } else if l3_type == RTE_PTYPE_L3_IPV6 {
-                #[allow(clippy::cast_possible_truncation)] // MTU = 1500 < u16::MAX
                 rte_ipv6_fragment_packet(
                     pm,
                     frags.as_mut_ptr(),
                     exp_nb_frags.try_into().map_err(Error::from)?,
-                    RTE_ETHER_MTU as _,
+                    mtu,
                     (*pm).pool,
                     (*pm).pool,
                 )
@@ -619,105 +2507,611 @@ impl TxBuffer {
                 -1
             }
         };
-        Error::from_ret(errno)?;
+        Error::from_ret(errno, "rte_ipv6_fragment_packet")?;
         #[allow(clippy::cast_sign_loss)] // errno checked
         let nb_frags = errno as usize;
-        log::trace!("tx: nb_frags={nb_frags}");
+        trace!("tx: nb_frags={nb_frags}");
 
-        Self::populate_ether_hdr(ether_src, frags.get(..nb_frags).ok_or(Error::OutOfRange)?);
-        for mb in &frags {
-            self.mbufs.push_back(*mb);
+        let frags = frags.get(..nb_frags).ok_or(Error::OutOfRange)?;
+        Self::populate_ether_hdr(ether_src, frags);
+        timestamp::pass_tx_request(timestamp::tx_request(pm), frags);
+        self.class_mut(class)?.extend(frags);
+        #[allow(clippy::mem_forget)] // later dropped by `eth_tx_burst`
+        mem::forget(m);
+        Ok(())
+    }
+
+    /// Fragment an IPv4 UDP datagram with GSO and buffer the fragments.
+    ///
+    /// The datagram is left to the NIC if it supports UDP fragmentation offload.
+    fn do_segment(&mut self, m: Mbuf, mtu: u16, class: usize) -> Result<()> {
+        let tx_offloads = self.conf.tx_offloads;
+        if tx_offloads & RTE_ETH_TX_OFFLOAD_UDP_TSO != 0 {
+            gso::set_udp_seg(&m, mtu);
+            return self.push(m, class);
         }
+        let room = self.capacity.saturating_sub(self.len());
+        let request = timestamp::tx_request(m.as_ptr());
+        let segs = gso::segment(
+            m,
+            mtu,
+            room,
+            tx_offloads & RTE_ETH_TX_OFFLOAD_IPV4_CKSUM != 0,
+        )?;
+        trace!("tx: nb_segs={}", segs.len());
+        timestamp::pass_tx_request(request, &segs);
+        self.class_mut(class)?.extend(segs);
+        Ok(())
+    }
+
+    /// Put a packet at the end of buffer as it is.
+    fn push(&mut self, m: Mbuf, class: usize) -> Result<()> {
+        if self.capacity < self.len() {
+            return Err(Error::NoBuf);
+        }
+        self.class_mut(class)?.push_back(m.as_ptr());
         #[allow(clippy::mem_forget)] // later dropped by `eth_tx_burst`
         mem::forget(m);
         Ok(())
     }
 
-    /// Send any packets queued up for transmission on a port and HW queue.
+    /// Buffer a packet and send any packets queued up for transmission on a port and HW queue.
     #[inline]
     fn buffer(&mut self, m: Mbuf) -> Result<()> {
-        // Put the new mbuf at the end of buffer.
-        if m.pkt_len() < RTE_ETHER_MTU as usize {
-            if TX_BUF_SIZE < self.mbufs.len() {
-                return Err(Error::NoBuf);
+        enter_span!(
+            "tx_buffer",
+            port = self.port_id,
+            queue = self.queue_id,
+            pkt_len = m.pkt_len()
+        );
+        self.check_stalled()?;
+        self.enqueue(m)?;
+        _ = self.flush();
+        Ok(())
+    }
+
+    /// Buffer a burst of packets and send them together, returning the number of packets
+    /// buffered. Packets after the first failing one are dropped.
+    ///
+    /// It fails only if no packet is buffered.
+    fn buffer_batch(&mut self, batch: Vec<Mbuf>) -> Result<usize> {
+        enter_span!(
+            "tx_buffer",
+            port = self.port_id,
+            queue = self.queue_id,
+            n = batch.len()
+        );
+        self.check_stalled()?;
+        let mut buffered = 0_usize;
+        let mut res = Ok(());
+        for m in batch {
+            res = self.enqueue(m);
+            if res.is_err() {
+                break;
             }
-            self.mbufs.push_back(m.as_ptr());
-            #[allow(clippy::mem_forget)] // later dropped by `eth_tx_burst`
-            mem::forget(m);
+            buffered = buffered.wrapping_add(1);
+        }
+        _ = self.flush();
+        match res {
+            Err(e) if buffered == 0 => Err(e),
+            Ok(()) | Err(_) => Ok(buffered),
+        }
+    }
+
+    /// Put a packet at the end of buffer, fragmenting it if necessary, unless it's dropped by the
+    /// policer. TCP segments to be split by the hardware are left as they are.
+    fn enqueue(&mut self, m: Mbuf) -> Result<()> {
+        let action = self
+            .policer
+            .as_mut()
+            .map(|policer| policer.police(m.pkt_len()));
+        if action == Some(PoliceAction::Drop) {
+            let _policed = self.counters.tx_policed.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        capture::mirror(self.port_id, Direction::Outbound, &m);
+        dump::observe(self.port_id, Direction::Outbound, &m);
+        // Put the new mbuf at the end of buffer.
+        let class = if action == Some(PoliceAction::Demote) {
+            self.classes.len().saturating_sub(1)
+        } else {
+            self.class_of(&m)
+        };
+        let mtu = self.conf.mtu.load(Ordering::Relaxed);
+        if m.pkt_len() <= usize::from(mtu).saturating_add(ETHER_HDR_LEN as usize)
+            || m.ol_flags() & RTE_MBUF_F_TX_TCP_SEG != 0
+        {
+            self.push(m, class)
+        } else if self.conf.gso.load(Ordering::Relaxed) && gso::is_ipv4_udp(&m) {
+            self.do_segment(m, mtu, class)
         } else {
             // need fragmentation
-            self.do_fragment(m)?;
-        }
-
-        let (msg1, msg2) = self.mbufs.as_mut_slices();
-        let mut sent = 0_u16;
-        let mut unsent = true;
-
-        if !msg1.is_empty() {
-            #[allow(clippy::cast_possible_truncation)]
-            // SAFETY: msg1 length checked
-            let sent1 = unsafe {
-                rte_eth_tx_burst(
-                    self.port_id,
-                    self.queue_id,
-                    msg1.as_mut_ptr(),
-                    msg1.len() as _,
-                )
-            };
-            if sent1 as usize == msg1.len() {
-                unsent = false;
-            }
-            sent = sent.wrapping_add(sent1);
-        }
-        if !unsent && !msg2.is_empty() {
-            #[allow(clippy::cast_possible_truncation)]
-            // SAFETY: msg2 length checked
-            let sent2 = unsafe {
-                rte_eth_tx_burst(
-                    self.port_id,
-                    self.queue_id,
-                    msg2.as_mut_ptr(),
-                    msg2.len() as _,
-                )
-            };
-            sent = sent.wrapping_add(sent2);
+            self.do_fragment(m, mtu, class)
         }
+    }
 
-        for _ in 0..sent {
-            _ = self.mbufs.pop_front(); // sent messages
+    /// Fail with `Error::Busy` if the device has taken no packet for `TX_STALL_TIMEOUT`, even
+    /// after another try.
+    fn check_stalled(&mut self) -> Result<()> {
+        if self
+            .stalled_since
+            .is_some_and(|since| since.elapsed() >= TX_STALL_TIMEOUT)
+        {
+            _ = self.flush();
+            if self.stalled_since.is_some() {
+                return Err(Error::Busy);
+            }
         }
         Ok(())
     }
+
+    /// Send packets queued up for transmission, returning the number of packets left.
+    ///
+    /// Packets are sent until the device takes no more, or the rate limit of the queue is
+    /// reached. With multiple traffic classes, they are sent in rounds, where each class sends as
+    /// many packets as its weight in turn from the first one, and packets of the same class are
+    /// sent in order. Nothing is sent while paused.
+    fn flush(&mut self) -> usize {
+        if self.paused {
+            return self.len();
+        }
+        enter_span!(
+            "tx_flush",
+            port = self.port_id,
+            queue = self.queue_id,
+            pending = self.len()
+        );
+        let weights = self.conf.tx_weights;
+        let mut sent = 0_u64;
+        let mut limited = false;
+        'rounds: while self.len() > 0 {
+            for (class, mbufs) in self.classes.iter_mut().enumerate() {
+                let mut quota = weights
+                    .and_then(|weights| weights.get(class).copied())
+                    .map_or(usize::MAX, |weight| weight.max(1) as usize);
+                if let Some(shaper) = self.shaper.as_mut() {
+                    let admitted = admit(shaper, mbufs, quota);
+                    limited = admitted < quota.min(mbufs.len());
+                    quota = admitted;
+                }
+                let (sent1, full) = tx_burst(self.port_id, self.queue_id, mbufs, quota);
+                sent = sent.wrapping_add(sent1);
+                if let Some(shaper) = self.shaper.as_mut() {
+                    // Packets admitted but not taken by the device are still at the front.
+                    let unsent = quota.saturating_sub(usize::try_from(sent1).unwrap_or(quota));
+                    for &m in mbufs.iter().take(unsent) {
+                        shaper.give_back(frame_len(m));
+                    }
+                }
+                if full || limited {
+                    break 'rounds;
+                }
+            }
+        }
+        let _sent = self.counters.tx_packets.fetch_add(sent, Ordering::Relaxed);
+        let left = self.len();
+        self.counters
+            .tx_pending
+            .store(left as u64, Ordering::Relaxed);
+        // A queue held back by its rate limit is not stalled.
+        self.stalled_since = if left == 0 || sent > 0 || limited {
+            None
+        } else {
+            self.stalled_since.or_else(|| Some(Instant::now()))
+        };
+        left
+    }
+
+    /// Retry sending packets left on a timer tick every `interval`. While the device takes no
+    /// packet, retries back off exponentially up to `TX_MAX_BACKOFF`.
+    fn retry(&mut self, interval: Duration) {
+        let now = Instant::now();
+        if now < self.retry_at {
+            return;
+        }
+        _ = self.flush();
+        self.backoff = if self.stalled_since.is_some() {
+            self.backoff
+                .saturating_mul(2)
+                .clamp(interval, TX_MAX_BACKOFF.max(interval))
+        } else {
+            Duration::ZERO
+        };
+        self.retry_at = now.checked_add(self.backoff).unwrap_or(now);
+    }
+
+    /// Keep sending packets queued up until all of them are sent or `timeout` expires, returning
+    /// the number of packets left.
+    ///
+    /// It sleeps between the retries while the device takes no packet, backing off from
+    /// `TX_FLUSH_INTERVAL` to `TX_MAX_BACKOFF`.
+    fn drain(&mut self, timeout: Duration) -> usize {
+        let start = Instant::now();
+        let mut last = usize::MAX;
+        let mut backoff = TX_FLUSH_INTERVAL;
+        loop {
+            let left = self.flush();
+            let elapsed = start.elapsed();
+            if left == 0 || self.paused || elapsed >= timeout {
+                return left;
+            }
+            if left < last {
+                backoff = TX_FLUSH_INTERVAL;
+            } else {
+                std::thread::sleep(backoff.min(timeout.saturating_sub(elapsed)));
+                backoff = backoff.saturating_mul(2).min(TX_MAX_BACKOFF);
+            }
+            last = left;
+        }
+    }
+
+    /// Free all packets queued up, returning the number of them.
+    fn clear(&mut self) -> usize {
+        let n = self.len();
+        self.counters.tx_pending.store(0, Ordering::Relaxed);
+        self.stalled_since = None;
+        for m in self.classes.iter_mut().flat_map(|mbufs| mbufs.drain(..)) {
+            // SAFETY: buffered mbufs are valid and owned by `TxBuffer`
+            unsafe { rte_pktmbuf_free(m) };
+        }
+        let _dropped = self
+            .counters
+            .tx_dropped
+            .fetch_add(n as u64, Ordering::Relaxed);
+        n
+    }
+}
+
+/// Send up to `quota` packets at the front of `mbufs` on the given port and queue. Returns the
+/// number of packets sent, and whether the device takes no more.
+#[allow(unsafe_code)]
+fn tx_burst(
+    port_id: u16,
+    queue_id: u16,
+    mbufs: &mut VecDeque<*mut rte_mbuf>,
+    quota: usize,
+) -> (u64, bool) {
+    let mut sent = 0_u64;
+    let mut quota = quota;
+    while quota > 0 {
+        let (front, _) = mbufs.as_mut_slices();
+        if front.is_empty() {
+            break;
+        }
+        let n = u16::try_from(front.len().min(quota)).unwrap_or(u16::MAX);
+        // Read before the mbufs are owned by the device.
+        let requests = timestamp::tx_requests(front.get(..usize::from(n)).unwrap_or_default());
+        // SAFETY: `front` holds at least `n` valid mbufs
+        let sent1 = unsafe { rte_eth_tx_burst(port_id, queue_id, front.as_mut_ptr(), n) };
+        if !requests.is_empty() {
+            timestamp::tx_complete(&requests, usize::from(sent1));
+        }
+        // Sent mbufs are owned by the device.
+        let _sent = mbufs.drain(..usize::from(sent1));
+        sent = sent.wrapping_add(u64::from(sent1));
+        quota = quota.saturating_sub(usize::from(sent1));
+        if sent1 < n {
+            return (sent, true);
+        }
+    }
+    (sent, false)
+}
+
+/// Take the tokens of the packets at the front of `mbufs` conforming to the rate limit, up to
+/// `quota` of them, returning the number of packets admitted.
+fn admit(shaper: &mut TokenBucket, mbufs: &VecDeque<*mut rte_mbuf>, quota: usize) -> usize {
+    let now = Instant::now();
+    mbufs
+        .iter()
+        .take(quota)
+        .take_while(|&&m| shaper.try_take(now, frame_len(m)).is_ok())
+        .count()
+}
+
+/// Length of the frame held by a buffered `mbuf`.
+#[allow(unsafe_code)]
+fn frame_len(m: *mut rte_mbuf) -> usize {
+    // SAFETY: `mbuf`s held by `TxBuffer` are valid until sent
+    let pkt_len = unsafe { (*m).pkt_len };
+    usize::try_from(pkt_len).unwrap_or(usize::MAX)
+}
+
+impl Drop for TxBuffer {
+    fn drop(&mut self) {
+        let dropped = self.clear();
+        if dropped > 0 {
+            warn!(
+                "{dropped} packets dropped on {}:{}",
+                self.port_id, self.queue_id
+            );
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{RxAgent, TxAgent};
-    use crate::{test_utils, Error};
+    use super::{
+        admit, frag_stats, strip_ipv6_ext_hdrs, AgentConf, IpFragDeathRow, IpFragmentTable,
+        PortConf, RxAgent, RxExec, TxAgent, TxBuffer, TxExec, TxRequest, TxRing, RX_AGENTS,
+        TX_CLASSES, TX_STALL_TIMEOUT,
+    };
+    use crate::{
+        mbuf::Mbuf,
+        mempool::{Mempool, PktMempool},
+        packet::PacketBuilder,
+        proto::{
+            IPV6_NEXT_PROTO_DSTOPTS, IPV6_NEXT_PROTO_HOPOPTS, IPV6_NEXT_PROTO_ROUTING,
+            IP_NEXT_PROTO_UDP,
+        },
+        shaper::{RateLimit, TokenBucket},
+        test_utils, Error,
+    };
+    use std::{
+        sync::{atomic::Ordering, Arc},
+        time::{Duration, Instant},
+    };
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn test_agent_conf() {
+        AgentConf::default().validate().unwrap();
+        let conf = AgentConf {
+            rx_burst: 64,
+            frag_bucket_num: 256,
+            frag_max_entries: 4096,
+            ..AgentConf::default()
+        };
+        conf.validate().unwrap();
+        for conf in [
+            AgentConf {
+                rx_burst: 0,
+                ..conf
+            },
+            AgentConf {
+                tx_ring_size: Some(0),
+                ..conf
+            },
+            AgentConf {
+                tx_buf_size: 0,
+                ..conf
+            },
+            AgentConf {
+                tx_flush_interval: Duration::ZERO,
+                ..conf
+            },
+            AgentConf {
+                frag_bucket_size: 12,
+                ..conf
+            },
+            AgentConf {
+                frag_max_entries: 8192,
+                ..conf
+            },
+            AgentConf {
+                frag_timeout: Duration::ZERO,
+                ..conf
+            },
+        ] {
+            assert!(matches!(conf.validate().unwrap_err(), Error::InvalidArg));
+        }
+    }
+
+    #[test]
+    fn test_strip_ipv6_ext_hdrs() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("test_ipv6_ext", 10).unwrap();
+        let mut m = Mbuf::new(&mp).unwrap();
+        let data = m.append(40 + 8 + 16 + 8).unwrap();
+        data.fill(0);
+        // Payload length and next header of the IPv6 header.
+        data[4..6].copy_from_slice(&32_u16.to_be_bytes());
+        data[6] = IPV6_NEXT_PROTO_HOPOPTS;
+        data[7] = 64;
+        // A hop-by-hop options header of 8 bytes followed by a destination options header of 16.
+        data[40] = IPV6_NEXT_PROTO_DSTOPTS;
+        data[48] = IP_NEXT_PROTO_UDP;
+        data[49] = 1;
+        assert_eq!(
+            strip_ipv6_ext_hdrs(&mut m, IPV6_NEXT_PROTO_HOPOPTS),
+            Some(IP_NEXT_PROTO_UDP)
+        );
+        let data = m.data_slice();
+        assert_eq!(data.len(), 48);
+        assert_eq!(&data[4..8], &[0, 8, IP_NEXT_PROTO_UDP, 64]);
+
+        let mut m = Mbuf::new(&mp).unwrap();
+        let data = m.append(40 + 8).unwrap();
+        data.fill(0);
+        data[40] = IP_NEXT_PROTO_UDP;
+        data[43] = 1;
+        // A routing header with segments left.
+        assert_eq!(strip_ipv6_ext_hdrs(&mut m, IPV6_NEXT_PROTO_ROUTING), None);
+    }
+
+    #[test]
+    fn test_frag_expire() {
+        test_utils::dpdk_setup();
+        let mut frag_tbl = IpFragmentTable::new(0).unwrap();
+        let mut death_row = IpFragDeathRow::new(0).unwrap();
+        let stats = frag_stats();
+        frag_tbl.expire(&mut death_row);
+        assert_eq!(death_row.len(), 0);
+        death_row.free();
+        assert_eq!(frag_stats().timed_out, stats.timed_out);
+    }
 
     #[tokio::test]
     async fn test_tx_agent() {
         test_utils::dpdk_setup();
-        let tx_agent = TxAgent::start();
-        let _ = tx_agent.register(0, 0).unwrap();
+        let tx_agent = TxAgent::start(TxExec::default(), 1).unwrap();
+        let conf = Arc::new(PortConf::new(1500, 0, None));
+        let tx = tx_agent.register(0, 0, Arc::clone(&conf)).unwrap();
         assert!(matches!(
-            tx_agent.register(0, 0).unwrap_err(),
+            tx_agent.register(0, 0, conf).unwrap_err(),
             Error::Already
         ));
-        tx_agent.unregister(0, 0).unwrap();
+        let (done, flushed) = oneshot::channel();
+        tx.requests.send(TxRequest::Flush(done)).await.unwrap();
+        assert_eq!(flushed.await.unwrap(), 0);
+        assert_eq!(tx_agent.unregister(0, 0).unwrap(), 0);
         assert!(matches!(
             tx_agent.unregister(0, 0).unwrap_err(),
             Error::NotExist
         ));
     }
 
+    #[tokio::test]
+    async fn test_tx_ring() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("tx_ring_test", 64).unwrap();
+        let ring = TxRing::create(0, 0, 4).unwrap();
+        assert!(ring.park());
+        for _ in 0..4 {
+            ring.try_push(Mbuf::new(&mp).unwrap()).unwrap();
+        }
+        // The parked Task is woken up once.
+        assert!(!ring.parked.load(Ordering::Relaxed));
+        assert!(!ring.park());
+        assert!(ring.is_full());
+        assert_eq!(
+            ring.try_push(Mbuf::new(&mp).unwrap()).unwrap_err(),
+            Error::TempUnavail
+        );
+        assert_eq!(mp.in_use(), 4);
+
+        assert_eq!(ring.close(), 4);
+        assert_eq!(mp.in_use(), 0);
+        assert_eq!(
+            ring.push(Mbuf::new(&mp).unwrap()).await.unwrap_err(),
+            Error::BrokenPipe
+        );
+        assert_eq!(ring.ready().await.unwrap_err(), Error::BrokenPipe);
+    }
+
+    #[test]
+    fn test_tx_buffer_stall() {
+        test_utils::dpdk_setup();
+        let conf = Arc::new(PortConf::new(1500, 0, None));
+        let mut txbuf = TxBuffer::new(0, 0, conf);
+        assert_eq!(txbuf.flush(), 0);
+        assert!(txbuf.stalled_since.is_none());
+
+        // A stalled buffer recovers once the device takes its packets.
+        txbuf.stalled_since = Instant::now().checked_sub(TX_STALL_TIMEOUT);
+        txbuf.check_stalled().unwrap();
+        assert!(txbuf.stalled_since.is_none());
+        let interval = Duration::from_micros(100);
+        txbuf.retry(interval);
+        assert_eq!(txbuf.backoff, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_tx_classes() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("test_tx_classes", 10).unwrap();
+        let builder = PacketBuilder::new()
+            .ethernet([2; 6], [3; 6])
+            .ipv4([10, 0, 0, 1].into(), [10, 0, 0, 2].into(), 64)
+            .udp(1234, 5678);
+        let v6 = builder.ipv6([1; 16].into(), [2; 16].into(), 64);
+        let frame =
+            |builder: PacketBuilder| builder.build(b"hello").unwrap().into_mbuf(&mp, 0).unwrap();
+
+        let txbuf = TxBuffer::new(0, 0, Arc::new(PortConf::new(1500, 0, None)));
+        assert_eq!(txbuf.classes.len(), 1);
+        assert_eq!(txbuf.class_of(&frame(builder.tos(0xc0))), 0);
+
+        let mut conf = PortConf::new(1500, 0, None);
+        conf.tx_weights = Some([8, 4, 2, 1]);
+        let mut txbuf = TxBuffer::new(0, 0, Arc::new(conf));
+        assert_eq!(txbuf.classes.len(), TX_CLASSES);
+        assert_eq!(txbuf.class_of(&frame(builder)), 3);
+        assert_eq!(txbuf.class_of(&frame(builder.tos(0xc0))), 0);
+        assert_eq!(txbuf.class_of(&frame(builder.tos(0xb8))), 1);
+        assert_eq!(txbuf.class_of(&frame(v6.tos(0x48))), 2);
+        assert_eq!(txbuf.flush(), 0);
+    }
+
+    #[test]
+    fn test_tx_admit() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("test_tx_admit", 10).unwrap();
+        let held = (0..4)
+            .map(|_| {
+                let mut m = Mbuf::new(&mp).unwrap();
+                let _data = m.append(125).unwrap();
+                m
+            })
+            .collect::<Vec<_>>();
+        let mbufs = held.iter().map(Mbuf::as_ptr).collect();
+
+        // 3 frames of 1000 bits fit in a burst of 3000 bits.
+        let mut shaper = TokenBucket::new(RateLimit::bps(1_000_000).burst(3000));
+        assert_eq!(admit(&mut shaper, &mbufs, 2), 2);
+        assert_eq!(admit(&mut shaper, &mbufs, usize::MAX), 1);
+        shaper.give_back(125);
+        assert_eq!(admit(&mut shaper, &mbufs, usize::MAX), 1);
+        assert_eq!(admit(&mut shaper, &mbufs, usize::MAX), 0);
+    }
+
+    #[tokio::test]
+    async fn test_tx_agent_workers() {
+        test_utils::dpdk_setup();
+        assert!(matches!(
+            TxAgent::start(TxExec::Threads(0), 1)
+                .map(|_| ())
+                .unwrap_err(),
+            Error::InvalidArg
+        ));
+        // Rejected before any thread is spawned.
+        for exec in [
+            TxExec::Threads(usize::MAX),
+            TxExec::Threads(3),
+            TxExec::Pinned { first: 0, n: 0 },
+            TxExec::Pinned { first: 0, n: 3 },
+            TxExec::Pinned {
+                first: usize::MAX,
+                n: 2,
+            },
+            TxExec::Pinned {
+                first: usize::MAX - 1,
+                n: 1,
+            },
+        ] {
+            assert!(matches!(
+                TxAgent::start(exec, 2).map(|_| ()).unwrap_err(),
+                Error::InvalidArg
+            ));
+        }
+        // The main lcore is not a service lcore.
+        assert!(matches!(
+            TxAgent::start(TxExec::Service(0), 1)
+                .map(|_| ())
+                .unwrap_err(),
+            Error::InvalidArg
+        ));
+        let tx_agent = TxAgent::start(TxExec::PerQueue, 2).unwrap();
+        assert_eq!(tx_agent.workers.len(), 2);
+        let conf = Arc::new(PortConf::new(1500, 0, None));
+        for queue_id in 0..2 {
+            let tx = tx_agent.register(0, queue_id, Arc::clone(&conf)).unwrap();
+            let (done, flushed) = oneshot::channel();
+            tx.requests.send(TxRequest::Flush(done)).await.unwrap();
+            assert_eq!(flushed.await.unwrap(), 0);
+        }
+        for queue_id in 0..2 {
+            assert_eq!(tx_agent.unregister(0, queue_id).unwrap(), 0);
+        }
+    }
+
     #[tokio::test]
     async fn test_rx_agent() {
         test_utils::dpdk_setup();
-        let rx_agent = RxAgent::start(0);
-        rx_agent.register(0, 0).unwrap();
+        let rx_agent = RxAgent::start(0, RxExec::Blocking).unwrap();
+        let conf = Arc::new(PortConf::new(1500, 0, None));
+        rx_agent.register(0, 0, Arc::clone(&conf)).unwrap();
         assert!(matches!(
-            rx_agent.register(0, 0).unwrap_err(),
+            rx_agent.register(0, 0, conf).unwrap_err(),
             Error::Already
         ));
         rx_agent.unregister(0, 0).unwrap();
@@ -727,4 +3121,59 @@ mod tests {
         ));
         rx_agent.stop();
     }
+
+    #[tokio::test]
+    async fn test_rx_agent_pinned() {
+        test_utils::dpdk_setup();
+        assert!(matches!(
+            RxAgent::start(0, RxExec::Pinned(usize::MAX)).err(),
+            Some(Error::InvalidArg)
+        ));
+        let rx_agent = RxAgent::start(0, RxExec::Pinned(0)).unwrap();
+        let conf = Arc::new(PortConf::new(1500, 0, None));
+        rx_agent.register(0, 0, conf).unwrap();
+        rx_agent.unregister(0, 0).unwrap();
+        rx_agent.stop();
+        // The main lcore is never launched.
+        assert!(matches!(
+            RxAgent::start(0, RxExec::Lcore(0)).err(),
+            Some(Error::InvalidArg)
+        ));
+        assert!(matches!(
+            RxAgent::start(0, RxExec::Service(0))
+                .map(|_| ())
+                .unwrap_err(),
+            Error::InvalidArg
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rx_agent_attach() {
+        test_utils::dpdk_setup();
+        let exec = RxExec::Pinned(0);
+        let conf = Arc::new(PortConf::new(1500, 0, None));
+        let rx_agent = RxAgent::attach(0, exec, 0, 0, Arc::clone(&conf)).unwrap();
+        assert!(matches!(
+            RxAgent::attach(0, exec, 0, 0, conf).err(),
+            Some(Error::Already)
+        ));
+        assert!(Arc::ptr_eq(
+            RX_AGENTS.lock().unwrap().get(&(exec, None)).unwrap(),
+            &rx_agent
+        ));
+        rx_agent.detach(0, 0).unwrap();
+        assert!(!RX_AGENTS.lock().unwrap().contains_key(&(exec, None)));
+    }
+
+    #[tokio::test]
+    async fn test_rx_agent_intr() {
+        test_utils::dpdk_setup();
+        let rx_agent = RxAgent::start(0, RxExec::Pinned(0)).unwrap();
+        // The null device has no RX interrupts, so the agent keeps busy polling.
+        let conf = Arc::new(PortConf::new(1500, 0, Some(Duration::ZERO)));
+        rx_agent.register(0, 0, conf).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        rx_agent.unregister(0, 0).unwrap();
+        rx_agent.stop();
+    }
 }
@@ -25,11 +25,25 @@
 //! unsafe {
 //!     alloc::free(p);
 //! }
+//!
+//! // Slices are freed on drop.
+//! let mut scratch = alloc::alloc_slice::<u64>(32).unwrap();
+//! scratch[31] = 1;
+//! scratch.realloc(64).unwrap();
+//! assert_eq!(scratch[31], 1);
 //! ```
 
 use crate::{Error, Result};
-use dpdk_sys::{rte_free, rte_malloc, rte_malloc_socket, rte_zmalloc, rte_zmalloc_socket};
-use std::{mem, ptr};
+use dpdk_sys::{
+    rte_free, rte_malloc, rte_malloc_socket, rte_realloc, rte_zmalloc, rte_zmalloc_socket,
+};
+use std::{
+    fmt::Debug,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    slice,
+};
 
 /// Check the size of `T` is non-zero, which is required in rte malloc functions.
 macro_rules! check_size {
@@ -66,6 +80,33 @@ pub fn malloc<T: Default>() -> Result<Box<T>> {
     }
 }
 
+/// Allocate memory from the huge-page area of memory like `malloc()`, which is aligned to `align`
+/// bytes, a power of 2 no less than the alignment of `T`.
+///
+/// # Errors
+///
+/// - An `Error::NoMem` could be returned if there's no enough memory.
+/// - An `Error::InvalidArg` could be returned if the size of `T` is 0, or `align` is invalid.
+#[inline]
+pub fn malloc_aligned<T: Default>(align: u32) -> Result<Box<T>> {
+    check_size!(T);
+    if !align.is_power_of_two() || (align as usize) < mem::align_of::<T>() {
+        return Err(Error::InvalidArg);
+    }
+    // SAFETY: alignment and size checked
+    #[allow(unsafe_code)]
+    let ptr = unsafe { rte_malloc(ptr::null(), mem::size_of::<T>(), align) };
+    if ptr.is_null() {
+        return Err(Error::NoMem);
+    }
+    // SAFETY: pointer checked then initialized using `T::default`.
+    #[allow(unsafe_code)]
+    unsafe {
+        ptr.cast::<T>().write(T::default());
+        Ok(Box::from_raw(ptr.cast()))
+    }
+}
+
 /// Allocate zeroed memory from the heap. In NUMA systems, the memory allocated resides on the same NUMA socket
 /// as the core that calls this function.
 ///
@@ -135,7 +176,8 @@ pub fn zmalloc_socket<T>(socket: i32) -> Result<Box<T>> {
 }
 
 /// Frees the memory space pointed to by the provided pointer. This pointer must have been returned
-/// by a previous call to `malloc()`, `zmalloc()`, `malloc_socket()` or `zmalloc_socket()`.
+/// by a previous call to `malloc()`, `malloc_aligned()`, `zmalloc()`, `malloc_socket()` or
+/// `zmalloc_socket()`.
 ///
 /// If the pointer is NULL, the function does nothing.
 ///
@@ -153,6 +195,139 @@ pub unsafe fn free<T>(obj: Box<T>) {
     }
 }
 
+/// Allocate a slice of `len` elements from the huge-page area of memory, each initialized with
+/// `T::default`. The slice is freed on drop.
+///
+/// # Errors
+///
+/// - An `Error::NoMem` could be returned if there's no enough memory.
+/// - An `Error::InvalidArg` could be returned if the size of `T` or `len` is 0.
+#[inline]
+pub fn alloc_slice<T: Default>(len: usize) -> Result<MallocSlice<T>> {
+    check_size!(T);
+    if len == 0 {
+        return Err(Error::InvalidArg);
+    }
+    let size = mem::size_of::<T>().checked_mul(len).ok_or(Error::NoMem)?;
+    // SAFETY: size checked, and the alignment of `T` is a power of 2
+    #[allow(unsafe_code, clippy::cast_possible_truncation)]
+    let ptr = unsafe { rte_malloc(ptr::null(), size, mem::align_of::<T>() as u32) };
+    let ptr = NonNull::new(ptr.cast::<T>()).ok_or(Error::NoMem)?;
+    let mut slice = MallocSlice { ptr, len: 0 };
+    slice.fill_to(len);
+    Ok(slice)
+}
+
+/// An owned slice allocated by `alloc_slice()` from the huge-page area of memory, which is freed
+/// on drop.
+pub struct MallocSlice<T> {
+    /// The first element.
+    ptr: NonNull<T>,
+    /// The number of initialized elements.
+    len: usize,
+}
+
+// SAFETY: the slice is owned
+#[allow(unsafe_code)]
+unsafe impl<T: Send> Send for MallocSlice<T> {}
+
+// SAFETY: the slice is only written through `&mut MallocSlice`
+#[allow(unsafe_code)]
+unsafe impl<T: Sync> Sync for MallocSlice<T> {}
+
+impl<T: Default> MallocSlice<T> {
+    /// Resize the slice to `len` elements, where new elements are initialized with `T::default`
+    /// and the exceeding ones are dropped. The slice may be moved.
+    ///
+    /// # Errors
+    ///
+    /// - An `Error::NoMem` could be returned if there's no enough memory, where the slice is
+    ///   kept unchanged.
+    /// - An `Error::InvalidArg` could be returned if `len` is 0.
+    #[inline]
+    pub fn realloc(&mut self, len: usize) -> Result<()> {
+        if len == 0 {
+            return Err(Error::InvalidArg);
+        }
+        let size = mem::size_of::<T>().checked_mul(len).ok_or(Error::NoMem)?;
+        if len < self.len {
+            // SAFETY: elements in `len..self.len` are initialized and never used again
+            #[allow(unsafe_code)]
+            unsafe {
+                ptr::drop_in_place(self.get_unchecked_mut(len..));
+            }
+            self.len = len;
+        }
+        // SAFETY: the pointer is allocated by `rte_malloc`, and `T` is aligned to a power of 2
+        #[allow(unsafe_code, clippy::cast_possible_truncation)]
+        let ptr =
+            unsafe { rte_realloc(self.ptr.as_ptr().cast(), size, mem::align_of::<T>() as u32) };
+        // The original memory is untouched if reallocation fails. A failed shrinking keeps it.
+        match NonNull::new(ptr.cast::<T>()) {
+            Some(ptr) => self.ptr = ptr,
+            None if len > self.len => return Err(Error::NoMem),
+            None => {}
+        }
+        self.fill_to(len);
+        Ok(())
+    }
+
+    /// Initialize elements up to `len`, within the allocated memory.
+    fn fill_to(&mut self, len: usize) {
+        while self.len < len {
+            // SAFETY: memory of `len` elements is allocated
+            #[allow(unsafe_code)]
+            unsafe {
+                self.ptr.as_ptr().add(self.len).write(T::default());
+            }
+            self.len = self.len.wrapping_add(1);
+        }
+    }
+}
+
+impl<T> Deref for MallocSlice<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        // SAFETY: `len` elements are initialized
+        #[allow(unsafe_code)]
+        unsafe {
+            slice::from_raw_parts(self.ptr.as_ptr(), self.len)
+        }
+    }
+}
+
+impl<T> DerefMut for MallocSlice<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: `len` elements are initialized
+        #[allow(unsafe_code)]
+        unsafe {
+            slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len)
+        }
+    }
+}
+
+impl<T: Debug> Debug for MallocSlice<T> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> Drop for MallocSlice<T> {
+    #[inline]
+    fn drop(&mut self) {
+        // SAFETY: elements are initialized, and the memory is allocated by `rte_malloc`
+        #[allow(unsafe_code)]
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr.as_ptr(), self.len));
+            rte_free(self.ptr.as_ptr().cast());
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{alloc, test_utils};
@@ -196,4 +371,36 @@ mod tests {
             alloc::free(t4);
         }
     }
+
+    #[test]
+    fn test_aligned() {
+        test_utils::dpdk_setup();
+
+        assert!(matches!(
+            alloc::malloc_aligned::<Test>(3),
+            Err(crate::Error::InvalidArg)
+        ));
+        let t = alloc::malloc_aligned::<Test>(4096).unwrap();
+        assert_eq!(std::ptr::addr_of!(*t) as usize % 4096, 0);
+        assert_eq!(t.x, 1);
+        #[allow(unsafe_code)]
+        unsafe {
+            alloc::free(t);
+        }
+
+        assert!(matches!(
+            alloc::alloc_slice::<Test>(0),
+            Err(crate::Error::InvalidArg)
+        ));
+        let mut s = alloc::alloc_slice::<Test>(4).unwrap();
+        assert_eq!(s.len(), 4);
+        assert!(s.iter().all(|t| t.x == 1 && t.y == 2));
+        s[3].x = 5;
+        s.realloc(1024).unwrap();
+        assert_eq!(s.len(), 1024);
+        assert_eq!(s[3].x, 5);
+        assert_eq!(s[1023].y, 2);
+        s.realloc(2).unwrap();
+        assert_eq!(s.len(), 2);
+    }
 }
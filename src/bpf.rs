@@ -0,0 +1,499 @@
+//! Classic BPF programs filtering received packets, e.g. the output of `tcpdump -dd`, which are
+//! run by the `RxAgent` so that unwanted traffic is dropped as early as possible.
+//!
+//! A program attached to a device by `net_dev::set_rx_filter` sees whole Ethernet frames before
+//! they are captured or dispatched, and one attached to a socket sees what the socket receives,
+//! e.g. the payload of UDP datagrams. A packet is dropped if the program returns 0.
+//!
+//! # Examples
+//!
+//! ```
+//! use async_dpdk::bpf::{BpfInsn, BpfProgram};
+//!
+//! // tcpdump -dd arp
+//! let prog = BpfProgram::new(vec![
+//!     BpfInsn::new(0x28, 0, 0, 0x0000_000c),
+//!     BpfInsn::new(0x15, 0, 1, 0x0000_0806),
+//!     BpfInsn::new(0x06, 0, 0, 0x0004_0000),
+//!     BpfInsn::new(0x06, 0, 0, 0x0000_0000),
+//! ])
+//! .unwrap();
+//! let mut frame = [0_u8; 42];
+//! frame[12..14].copy_from_slice(&[0x08, 0x06]);
+//! assert!(prog.matches(&frame));
+//! assert!(!prog.matches(&[0_u8; 42]));
+//! ```
+
+use crate::{mbuf::Mbuf, Error, Result};
+use lazy_static::lazy_static;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+};
+
+/// Max number of instructions of a program, the same as Linux.
+const BPF_MAXINSNS: usize = 4096;
+
+/// Number of words in the scratch memory.
+const BPF_MEMWORDS: usize = 16;
+
+/// Instruction classes.
+const BPF_LD: u16 = 0x00;
+/// Load into the index register.
+const BPF_LDX: u16 = 0x01;
+/// Store the accumulator into the scratch memory.
+const BPF_ST: u16 = 0x02;
+/// Store the index register into the scratch memory.
+const BPF_STX: u16 = 0x03;
+/// Arithmetic and logic operations.
+const BPF_ALU: u16 = 0x04;
+/// Jumps.
+const BPF_JMP: u16 = 0x05;
+/// Return.
+const BPF_RET: u16 = 0x06;
+/// Register transfers.
+const BPF_MISC: u16 = 0x07;
+
+/// Load a word.
+const BPF_W: u16 = 0x00;
+/// Load a half word.
+const BPF_H: u16 = 0x08;
+/// Load a byte.
+const BPF_B: u16 = 0x10;
+
+/// Load a constant.
+const BPF_IMM: u16 = 0x00;
+/// Load packet data at a constant offset.
+const BPF_ABS: u16 = 0x20;
+/// Load packet data at an offset relative to the index register.
+const BPF_IND: u16 = 0x40;
+/// Load from the scratch memory.
+const BPF_MEM: u16 = 0x60;
+/// Load the packet length.
+const BPF_LEN: u16 = 0x80;
+/// Load the IPv4 header length from the byte at a constant offset.
+const BPF_MSH: u16 = 0xa0;
+
+/// ALU operations and jump conditions.
+const BPF_ADD: u16 = 0x00;
+/// Subtraction.
+const BPF_SUB: u16 = 0x10;
+/// Multiplication.
+const BPF_MUL: u16 = 0x20;
+/// Division.
+const BPF_DIV: u16 = 0x30;
+/// Bitwise or.
+const BPF_OR: u16 = 0x40;
+/// Bitwise and.
+const BPF_AND: u16 = 0x50;
+/// Left shift.
+const BPF_LSH: u16 = 0x60;
+/// Right shift.
+const BPF_RSH: u16 = 0x70;
+/// Negation.
+const BPF_NEG: u16 = 0x80;
+/// Remainder.
+const BPF_MOD: u16 = 0x90;
+/// Bitwise exclusive or.
+const BPF_XOR: u16 = 0xa0;
+
+/// Unconditional jump.
+const BPF_JA: u16 = 0x00;
+/// Jump if equal.
+const BPF_JEQ: u16 = 0x10;
+/// Jump if greater than.
+const BPF_JGT: u16 = 0x20;
+/// Jump if greater than or equal.
+const BPF_JGE: u16 = 0x30;
+/// Jump if any bit is set.
+const BPF_JSET: u16 = 0x40;
+
+/// The operand is the constant `k`.
+const BPF_K: u16 = 0x00;
+/// The operand is the index register.
+const BPF_X: u16 = 0x08;
+/// Return the accumulator.
+const BPF_A: u16 = 0x10;
+
+/// Copy the accumulator into the index register.
+const BPF_TAX: u16 = 0x00;
+/// Copy the index register into the accumulator.
+const BPF_TXA: u16 = 0x80;
+
+lazy_static! {
+    /// Programs filtering the frames received by each port, indexed by port id.
+    static ref PORT_FILTERS: RwLock<BTreeMap<u16, Arc<BpfProgram>>> = RwLock::new(BTreeMap::new());
+}
+
+/// Number of ports with a filter, so that the `RxAgent` skips the lookup if there's none.
+static FILTER_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// A classic BPF instruction, laid out as `struct sock_filter` of Linux.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BpfInsn {
+    /// Operation code.
+    pub code: u16,
+    /// Offset of the next instruction if the condition is true.
+    pub jt: u8,
+    /// Offset of the next instruction if the condition is false.
+    pub jf: u8,
+    /// Generic field, e.g. a constant or an offset.
+    pub k: u32,
+}
+
+impl BpfInsn {
+    /// Creates an instruction, in the order of the lines printed by `tcpdump -dd`.
+    #[inline]
+    #[must_use]
+    pub const fn new(code: u16, jt: u8, jf: u8, k: u32) -> Self {
+        Self { code, jt, jf, k }
+    }
+}
+
+/// A validated classic BPF program.
+#[derive(Debug, Clone)]
+pub struct BpfProgram {
+    /// Instructions of the program.
+    insns: Vec<BpfInsn>,
+}
+
+impl BpfProgram {
+    /// Creates a program from its instructions, checking them as Linux does for
+    /// `SO_ATTACH_FILTER`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: the program is empty or too long, doesn't end with a return, or has
+    ///   an unknown instruction, a jump out of the program, an access out of the scratch memory,
+    ///   or a division by constant 0.
+    #[inline]
+    pub fn new(insns: Vec<BpfInsn>) -> Result<Self> {
+        if insns.is_empty() || insns.len() > BPF_MAXINSNS {
+            return Err(Error::InvalidArg);
+        }
+        for (pc, insn) in insns.iter().enumerate() {
+            // Instructions left after this one.
+            let left = insns.len().wrapping_sub(pc).wrapping_sub(1);
+            if !valid(*insn, left) {
+                return Err(Error::InvalidArg);
+            }
+        }
+        match insns.last() {
+            Some(insn) if insn.code & 0x07 == BPF_RET => Ok(Self { insns }),
+            Some(_) | None => Err(Error::InvalidArg),
+        }
+    }
+
+    /// Instructions of the program.
+    #[inline]
+    #[must_use]
+    pub fn insns(&self) -> &[BpfInsn] {
+        &self.insns
+    }
+
+    /// Runs the program on a packet. On success, returns the value returned by the program, i.e.
+    /// the number of bytes to keep, which is 0 if the packet is to be dropped.
+    ///
+    /// Loads out of the packet and divisions by 0 make the program return 0.
+    #[inline]
+    #[must_use]
+    pub fn run(&self, pkt: &[u8]) -> u32 {
+        self.run_with_len(pkt, pkt.len())
+    }
+
+    /// Whether a packet is accepted by the program, i.e. it returns a non-zero value.
+    #[inline]
+    #[must_use]
+    pub fn matches(&self, pkt: &[u8]) -> bool {
+        self.run(pkt) != 0
+    }
+
+    /// Whether the packet held by an `Mbuf` is accepted by the program. Multi-segment packets
+    /// are copied into a contiguous buffer first.
+    pub(crate) fn matches_mbuf(&self, m: &Mbuf) -> bool {
+        if m.num_segs() == 1 {
+            return self.run_with_len(m.data_slice(), m.pkt_len()) != 0;
+        }
+        let mut data = Vec::with_capacity(m.pkt_len());
+        for seg in m.iter() {
+            data.extend_from_slice(seg.data_slice());
+        }
+        self.run(&data) != 0
+    }
+
+    /// Run the program on `pkt`, which is `len` bytes on the wire.
+    #[allow(clippy::too_many_lines)]
+    fn run_with_len(&self, pkt: &[u8], len: usize) -> u32 {
+        let mut a: u32 = 0;
+        let mut x: u32 = 0;
+        let mut mem = [0_u32; BPF_MEMWORDS];
+        let mut pc: usize = 0;
+        while let Some(insn) = self.insns.get(pc) {
+            pc = pc.wrapping_add(1);
+            let k = insn.k;
+            match insn.code & 0x07 {
+                BPF_LD | BPF_LDX => {
+                    let value = match insn.code & 0xe0 {
+                        BPF_IMM => Some(k),
+                        BPF_ABS => load(pkt, Some(k), insn.code & 0x18),
+                        BPF_IND => load(pkt, x.checked_add(k), insn.code & 0x18),
+                        BPF_MEM => mem.get(k as usize).copied(),
+                        BPF_LEN => Some(u32::try_from(len).unwrap_or(u32::MAX)),
+                        BPF_MSH => load(pkt, Some(k), BPF_B).map(|b| (b & 0xf).wrapping_shl(2)),
+                        _ => None,
+                    };
+                    let Some(value) = value else {
+                        return 0;
+                    };
+                    if insn.code & 0x07 == BPF_LD {
+                        a = value;
+                    } else {
+                        x = value;
+                    }
+                }
+                BPF_ST | BPF_STX => {
+                    let value = if insn.code & 0x07 == BPF_ST { a } else { x };
+                    if let Some(word) = mem.get_mut(k as usize) {
+                        *word = value;
+                    }
+                }
+                BPF_ALU => {
+                    let operand = if insn.code & BPF_X == 0 { k } else { x };
+                    let res = match insn.code & 0xf0 {
+                        BPF_ADD => Some(a.wrapping_add(operand)),
+                        BPF_SUB => Some(a.wrapping_sub(operand)),
+                        BPF_MUL => Some(a.wrapping_mul(operand)),
+                        BPF_DIV => a.checked_div(operand),
+                        BPF_MOD => a.checked_rem(operand),
+                        BPF_OR => Some(a | operand),
+                        BPF_AND => Some(a & operand),
+                        BPF_XOR => Some(a ^ operand),
+                        BPF_LSH => Some(a.checked_shl(operand).unwrap_or(0)),
+                        BPF_RSH => Some(a.checked_shr(operand).unwrap_or(0)),
+                        BPF_NEG => Some(a.wrapping_neg()),
+                        _ => None,
+                    };
+                    let Some(res) = res else {
+                        return 0;
+                    };
+                    a = res;
+                }
+                BPF_JMP => {
+                    let operand = if insn.code & BPF_X == 0 { k } else { x };
+                    let cond = match insn.code & 0xf0 {
+                        BPF_JA => {
+                            pc = pc.wrapping_add(k as usize);
+                            continue;
+                        }
+                        BPF_JEQ => a == operand,
+                        BPF_JGT => a > operand,
+                        BPF_JGE => a >= operand,
+                        BPF_JSET => a & operand != 0,
+                        _ => return 0,
+                    };
+                    let offset = if cond { insn.jt } else { insn.jf };
+                    pc = pc.wrapping_add(usize::from(offset));
+                }
+                BPF_RET => {
+                    return match insn.code & 0x18 {
+                        BPF_K => k,
+                        BPF_X => x,
+                        BPF_A => a,
+                        _ => 0,
+                    };
+                }
+                BPF_MISC => {
+                    if insn.code & 0xf8 == BPF_TXA {
+                        a = x;
+                    } else {
+                        x = a;
+                    }
+                }
+                _ => return 0,
+            }
+        }
+        0
+    }
+}
+
+/// Whether `insn` is valid, with `left` instructions after it.
+fn valid(insn: BpfInsn, left: usize) -> bool {
+    let code = insn.code;
+    let k = insn.k as usize;
+    if code > 0xff {
+        return false;
+    }
+    match code & 0x07 {
+        BPF_LD => match code & 0xe0 {
+            BPF_ABS | BPF_IND => code & 0x18 != 0x18,
+            BPF_IMM | BPF_LEN => code & 0x18 == BPF_W,
+            BPF_MEM => code & 0x18 == BPF_W && k < BPF_MEMWORDS,
+            _ => false,
+        },
+        BPF_LDX => match code & 0xe0 {
+            BPF_IMM | BPF_LEN => code & 0x18 == BPF_W,
+            BPF_MEM => code & 0x18 == BPF_W && k < BPF_MEMWORDS,
+            BPF_MSH => code & 0x18 == BPF_B,
+            _ => false,
+        },
+        BPF_ST | BPF_STX => code & 0xf8 == 0 && k < BPF_MEMWORDS,
+        BPF_ALU => match code & 0xf0 {
+            BPF_DIV | BPF_MOD => code & BPF_X != 0 || k != 0,
+            BPF_NEG => code & BPF_X == 0,
+            op => op <= BPF_XOR,
+        },
+        BPF_JMP => match code & 0xf0 {
+            BPF_JA => code & BPF_X == 0 && k < left,
+            BPF_JEQ | BPF_JGT | BPF_JGE | BPF_JSET => {
+                usize::from(insn.jt) < left && usize::from(insn.jf) < left
+            }
+            _ => false,
+        },
+        BPF_RET => code & 0xe0 == 0 && code & 0x18 != 0x18,
+        BPF_MISC => matches!(code & 0xf8, BPF_TAX | BPF_TXA),
+        _ => false,
+    }
+}
+
+/// Load a word, half word or byte in network byte order from `pkt` at `offset`.
+fn load(pkt: &[u8], offset: Option<u32>, size: u16) -> Option<u32> {
+    let offset = usize::try_from(offset?).ok()?;
+    match size {
+        BPF_W => {
+            let bytes = pkt.get(offset..offset.checked_add(4)?)?;
+            Some(u32::from_be_bytes(bytes.try_into().ok()?))
+        }
+        BPF_H => {
+            let bytes = pkt.get(offset..offset.checked_add(2)?)?;
+            Some(u32::from(u16::from_be_bytes(bytes.try_into().ok()?)))
+        }
+        BPF_B => pkt.get(offset).copied().map(u32::from),
+        _ => None,
+    }
+}
+
+/// Attach a program filtering the frames received by a port, or detach it with `None`.
+pub(crate) fn set_port_filter(port_id: u16, prog: Option<BpfProgram>) -> Result<()> {
+    let mut filters = PORT_FILTERS.write().map_err(Error::from)?;
+    let prev = match prog {
+        Some(prog) => filters.insert(port_id, Arc::new(prog)),
+        None => filters.remove(&port_id),
+    };
+    if prev.is_none() && filters.contains_key(&port_id) {
+        let _prev = FILTER_NUM.fetch_add(1, Ordering::AcqRel);
+    } else if prev.is_some() && !filters.contains_key(&port_id) {
+        let _prev = FILTER_NUM.fetch_sub(1, Ordering::AcqRel);
+    } else {
+        // The number of filters is unchanged.
+    }
+    Ok(())
+}
+
+/// Called by the agent thread once a burst, get the program filtering the frames received by a
+/// port if any.
+pub(crate) fn port_filter(port_id: u16) -> Option<Arc<BpfProgram>> {
+    if FILTER_NUM.load(Ordering::Acquire) == 0 {
+        return None;
+    }
+    PORT_FILTERS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&port_id)
+        .map(Arc::clone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BpfInsn, BpfProgram};
+    use crate::Error;
+
+    /// `tcpdump -dd udp dst port 53` on Ethernet frames.
+    fn udp_dst_53() -> BpfProgram {
+        BpfProgram::new(vec![
+            BpfInsn::new(0x28, 0, 0, 0x0000_000c),
+            BpfInsn::new(0x15, 0, 4, 0x0000_86dd),
+            BpfInsn::new(0x30, 0, 0, 0x0000_0014),
+            BpfInsn::new(0x15, 0, 11, 0x0000_0011),
+            BpfInsn::new(0x28, 0, 0, 0x0000_0038),
+            BpfInsn::new(0x15, 8, 9, 0x0000_0035),
+            BpfInsn::new(0x15, 0, 8, 0x0000_0800),
+            BpfInsn::new(0x30, 0, 0, 0x0000_0017),
+            BpfInsn::new(0x15, 0, 6, 0x0000_0011),
+            BpfInsn::new(0x28, 0, 0, 0x0000_0014),
+            BpfInsn::new(0x45, 4, 0, 0x0000_1fff),
+            BpfInsn::new(0xb1, 0, 0, 0x0000_000e),
+            BpfInsn::new(0x48, 0, 0, 0x0000_0010),
+            BpfInsn::new(0x15, 0, 1, 0x0000_0035),
+            BpfInsn::new(0x06, 0, 0, 0x0004_0000),
+            BpfInsn::new(0x06, 0, 0, 0x0000_0000),
+        ])
+        .unwrap()
+    }
+
+    /// An Ethernet frame carrying a UDP datagram to `dst_port`.
+    fn udp_frame(dst_port: u16) -> Vec<u8> {
+        let mut frame = vec![0_u8; 12];
+        frame.extend_from_slice(&[0x08, 0x00, 0x45]);
+        frame.resize(23, 0);
+        frame.push(17);
+        frame.resize(36, 0);
+        frame.extend_from_slice(&dst_port.to_be_bytes());
+        frame.resize(42, 0);
+        frame
+    }
+
+    #[test]
+    fn test_run() {
+        let prog = udp_dst_53();
+        assert_eq!(prog.run(&udp_frame(53)), 0x0004_0000);
+        assert!(!prog.matches(&udp_frame(54)));
+        // Loads out of the packet drop it.
+        assert!(!prog.matches(udp_frame(53).get(..30).unwrap()));
+
+        // ldx #0; ld #1; div x; ret a
+        let div = BpfProgram::new(vec![
+            BpfInsn::new(0x01, 0, 0, 0),
+            BpfInsn::new(0x00, 0, 0, 1),
+            BpfInsn::new(0x3c, 0, 0, 0),
+            BpfInsn::new(0x16, 0, 0, 0),
+        ])
+        .unwrap();
+        assert_eq!(div.run(&[]), 0);
+        // ld len; st M[3]; ldx M[3]; txa; add #1; ret a
+        let len = BpfProgram::new(vec![
+            BpfInsn::new(0x80, 0, 0, 0),
+            BpfInsn::new(0x02, 0, 0, 3),
+            BpfInsn::new(0x61, 0, 0, 3),
+            BpfInsn::new(0x87, 0, 0, 0),
+            BpfInsn::new(0x04, 0, 0, 1),
+            BpfInsn::new(0x16, 0, 0, 0),
+        ])
+        .unwrap();
+        assert_eq!(len.run(&[0; 10]), 11);
+    }
+
+    #[test]
+    fn test_validate() {
+        let ret = BpfInsn::new(0x06, 0, 0, 0);
+        assert_eq!(BpfProgram::new(vec![]).unwrap_err(), Error::InvalidArg);
+        // Not ending with a return.
+        let ld = BpfInsn::new(0x00, 0, 0, 1);
+        assert!(BpfProgram::new(vec![ret, ld]).is_err());
+        // Jumping out of the program.
+        assert!(BpfProgram::new(vec![BpfInsn::new(0x15, 1, 0, 0), ret]).is_err());
+        assert!(BpfProgram::new(vec![BpfInsn::new(0x05, 0, 0, 1), ret]).is_err());
+        // Division by constant 0.
+        assert!(BpfProgram::new(vec![BpfInsn::new(0x34, 0, 0, 0), ret]).is_err());
+        // Scratch memory out of range.
+        assert!(BpfProgram::new(vec![BpfInsn::new(0x02, 0, 0, 16), ret]).is_err());
+        // Unknown class of instruction.
+        assert!(BpfProgram::new(vec![BpfInsn::new(0xff, 0, 0, 0), ret]).is_err());
+        assert_eq!(udp_dst_53().insns().len(), 16);
+    }
+}
@@ -0,0 +1,255 @@
+//! Capture of packets traversing the agents to pcapng files, for debugging without external taps.
+//!
+//! Captures are written by the agent threads as packets are received and buffered for sending,
+//! so they slow down the data path and are not meant for production traffic.
+
+use crate::dump::{Direction, PacketInfo};
+use crate::mbuf::Mbuf;
+use crate::{trace::warn, Error, Result};
+use lazy_static::lazy_static;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Block type of the pcapng Section Header Block.
+const SHB_TYPE: u32 = 0x0A0D_0D0A;
+
+/// Block type of the pcapng Interface Description Block.
+const IDB_TYPE: u32 = 1;
+
+/// Block type of the pcapng Enhanced Packet Block.
+const EPB_TYPE: u32 = 6;
+
+/// Magic number telling the byte order of a section.
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+/// Link type of Ethernet frames.
+const LINKTYPE_ETHERNET: u16 = 1;
+
+/// Option code of the flags of an Enhanced Packet Block, which tell the direction.
+const EPB_FLAGS: u16 = 2;
+
+/// Length of the fixed fields of an Enhanced Packet Block, including the leading block type and
+/// the trailing block length.
+const EPB_FIXED_LEN: usize = 32;
+
+/// Length of the options of an Enhanced Packet Block, i.e. the flags and the end of options.
+const EPB_OPTIONS_LEN: usize = 12;
+
+/// What a capture mirrors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// All frames received from and sent to a port.
+    Port(u16),
+    /// Frames carrying UDP datagrams to and from the socket with the given fd, which is bound to
+    /// the given address.
+    Socket(i32, SocketAddr),
+}
+
+impl Target {
+    /// Whether a frame with `info` is mirrored.
+    fn matches(self, info: &PacketInfo) -> bool {
+        match self {
+            Target::Port(port_id) => port_id == info.port_id,
+            Target::Socket(_, local) => {
+                let (ip, port) = match info.direction {
+                    Direction::Inbound => (info.dst_ip, info.dst_port),
+                    Direction::Outbound => (info.src_ip, info.src_port),
+                };
+                info.protocol == Some(17)
+                    && port == Some(local.port())
+                    && (local.ip().is_unspecified() || ip == Some(local.ip()))
+            }
+        }
+    }
+}
+
+/// A pcapng file being written.
+#[derive(Debug)]
+struct Writer {
+    /// The file.
+    file: BufWriter<File>,
+}
+
+impl Writer {
+    /// Create a pcapng file at `path` with a section of one Ethernet interface.
+    fn create(path: &Path) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let mut block = Vec::with_capacity(28);
+        block.extend_from_slice(&SHB_TYPE.to_ne_bytes());
+        block.extend_from_slice(&28_u32.to_ne_bytes());
+        block.extend_from_slice(&BYTE_ORDER_MAGIC.to_ne_bytes());
+        block.extend_from_slice(&1_u16.to_ne_bytes()); // major version
+        block.extend_from_slice(&0_u16.to_ne_bytes()); // minor version
+        block.extend_from_slice(&(-1_i64).to_ne_bytes()); // section length unspecified
+        block.extend_from_slice(&28_u32.to_ne_bytes());
+        block.extend_from_slice(&IDB_TYPE.to_ne_bytes());
+        block.extend_from_slice(&20_u32.to_ne_bytes());
+        block.extend_from_slice(&LINKTYPE_ETHERNET.to_ne_bytes());
+        block.extend_from_slice(&0_u16.to_ne_bytes()); // reserved
+        block.extend_from_slice(&0_u32.to_ne_bytes()); // no snap length limit
+        block.extend_from_slice(&20_u32.to_ne_bytes());
+        file.write_all(&block)?;
+        Ok(Self { file })
+    }
+
+    /// Write a frame held by `m` as an Enhanced Packet Block, timestamped in microseconds.
+    fn write(&mut self, m: &Mbuf, dir: Direction) -> Result<()> {
+        let len = m.pkt_len();
+        let padded = len.wrapping_add(3) & !3;
+        let block_len: u32 = EPB_FIXED_LEN
+            .wrapping_add(padded)
+            .wrapping_add(EPB_OPTIONS_LEN)
+            .try_into()?;
+        let pkt_len: u32 = len.try_into()?;
+        #[allow(clippy::cast_possible_truncation)] // until year 586912
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut block = Vec::with_capacity(block_len as usize);
+        block.extend_from_slice(&EPB_TYPE.to_ne_bytes());
+        block.extend_from_slice(&block_len.to_ne_bytes());
+        block.extend_from_slice(&0_u32.to_ne_bytes()); // interface id
+        #[allow(clippy::cast_possible_truncation)] // split into halves
+        {
+            block.extend_from_slice(&((ts >> 32_u32) as u32).to_ne_bytes());
+            block.extend_from_slice(&(ts as u32).to_ne_bytes());
+        }
+        block.extend_from_slice(&pkt_len.to_ne_bytes()); // captured length
+        block.extend_from_slice(&pkt_len.to_ne_bytes()); // original length
+        for seg in m.iter() {
+            block.extend_from_slice(seg.data_slice());
+        }
+        block.resize(block.len().wrapping_add(padded.wrapping_sub(len)), 0);
+        block.extend_from_slice(&EPB_FLAGS.to_ne_bytes());
+        block.extend_from_slice(&4_u16.to_ne_bytes());
+        let flags: u32 = match dir {
+            Direction::Inbound => 1,
+            Direction::Outbound => 2,
+        };
+        block.extend_from_slice(&flags.to_ne_bytes());
+        block.extend_from_slice(&0_u32.to_ne_bytes()); // end of options
+        block.extend_from_slice(&block_len.to_ne_bytes());
+        self.file.write_all(&block)?;
+        Ok(())
+    }
+}
+
+/// A running capture.
+#[derive(Debug)]
+struct Capture {
+    /// What it mirrors.
+    target: Target,
+    /// Where it's written to.
+    writer: Mutex<Writer>,
+}
+
+lazy_static! {
+    /// Running captures.
+    static ref CAPTURES: RwLock<Vec<Capture>> = RwLock::new(Vec::new());
+}
+
+/// Number of running captures, checked before `CAPTURES` is locked on the data path.
+static CAPTURE_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// Start mirroring the frames `target` specifies to a pcapng file at `path`, which is truncated
+/// if it exists.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::Already`: `target` is being captured.
+/// - Failed to create the file.
+pub(crate) fn start(target: Target, path: &Path) -> Result<()> {
+    let mut captures = CAPTURES.write().map_err(Error::from)?;
+    if captures.iter().any(|capture| capture.target == target) {
+        return Err(Error::Already);
+    }
+    let writer = Mutex::new(Writer::create(path)?);
+    captures.push(Capture { target, writer });
+    let _num = CAPTURE_NUM.fetch_add(1, Ordering::Release);
+    Ok(())
+}
+
+/// Stop mirroring the frames `target` specifies, flushing its file.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NotExist`: `target` is not being captured.
+/// - Failed to flush the file.
+pub(crate) fn stop(target: Target) -> Result<()> {
+    let mut captures = CAPTURES.write().map_err(Error::from)?;
+    let pos = captures
+        .iter()
+        .position(|capture| capture.target == target)
+        .ok_or(Error::NotExist)?;
+    let capture = captures.swap_remove(pos);
+    let _num = CAPTURE_NUM.fetch_sub(1, Ordering::Release);
+    capture
+        .writer
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+        .file
+        .flush()?;
+    Ok(())
+}
+
+/// Called by the agent threads, mirror a frame of `port_id` to the captures it matches.
+pub(crate) fn mirror(port_id: u16, dir: Direction, m: &Mbuf) {
+    if CAPTURE_NUM.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let info = PacketInfo::parse(
+        port_id,
+        dir,
+        m.data_slice(),
+        (m.pkt_len(), m.num_segs(), m.packet_type()),
+    );
+    let captures = CAPTURES.read().unwrap_or_else(PoisonError::into_inner);
+    for capture in captures.iter() {
+        if !capture.target.matches(&info) {
+            continue;
+        }
+        let mut writer = capture
+            .writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Err(e) = writer.write(m, dir) {
+            warn!("Failed to capture a frame of port {port_id}: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Target;
+    use crate::{
+        dump::{Direction, PacketInfo},
+        test_utils::udp_frame as frame,
+    };
+
+    #[test]
+    fn test_target() {
+        let frame = frame();
+        let info = |dir| PacketInfo::parse(1, dir, &frame, (frame.len(), 1, 0));
+        let sock = Target::Socket(0, "0.0.0.0:2000".parse().unwrap());
+        assert!(sock.matches(&info(Direction::Inbound)));
+        assert!(!sock.matches(&info(Direction::Outbound)));
+        let sock = Target::Socket(0, "10.0.0.3:2000".parse().unwrap());
+        assert!(!sock.matches(&info(Direction::Inbound)));
+        let sock = Target::Socket(0, "10.0.0.1:1000".parse().unwrap());
+        assert!(sock.matches(&info(Direction::Outbound)));
+        assert!(Target::Port(1).matches(&info(Direction::Outbound)));
+        assert!(!Target::Port(0).matches(&info(Direction::Outbound)));
+    }
+}
@@ -0,0 +1,872 @@
+//! IPsec ESP ([`RFC 4303`]) transform with AEAD algorithms, i.e. AES-GCM ([`RFC 4106`]) and
+//! ChaCha20-Poly1305 ([`RFC 7634`]), on top of a crypto device.
+//!
+//! An `Esp` encapsulates or decapsulates the IPv4 packets of a security association, in tunnel or
+//! transport mode, in batches on a `QueuePair`. Packets start with the IPv4 header, as are passed
+//! to `dispatch` handlers, and are sent or forwarded by the application. An `EspInbound`
+//! decapsulates the ESP packets received by the stack.
+//!
+//! Extended sequence numbers are not supported, so a security association should be rekeyed
+//! before 2^32 packets are sent with it.
+//!
+//! ```no_run
+//! use async_dpdk::crypto::esp::{Esp, EspInbound, SecurityAssociation};
+//! use async_dpdk::crypto::{AeadAlgo, CryptoDev};
+//! use std::net::Ipv4Addr;
+//!
+//! let dev = CryptoDev::open("crypto_openssl0").unwrap();
+//! dev.configure(2).unwrap();
+//! let mut qp = dev.queue_pair_setup(0).unwrap();
+//! let inbound_qp = dev.queue_pair_setup(1).unwrap();
+//! dev.start().unwrap();
+//!
+//! // 16-byte keys followed by 4-byte salts.
+//! let local = Ipv4Addr::new(10, 0, 0, 1);
+//! let remote = Ipv4Addr::new(10, 0, 0, 2);
+//! let sa_out = SecurityAssociation::new(0x1000, AeadAlgo::AesGcm, &[0x11; 20])
+//!     .unwrap()
+//!     .tunnel(local, remote);
+//! let sa_in = SecurityAssociation::new(0x2000, AeadAlgo::AesGcm, &[0x22; 20])
+//!     .unwrap()
+//!     .tunnel(remote, local);
+//!
+//! let mut outbound = Esp::outbound(&dev, sa_out).unwrap();
+//! let inbound = Esp::inbound(&dev, sa_in).unwrap();
+//! let _inbound = EspInbound::install(inbound_qp, vec![inbound], |inner| {
+//!     // Forward the inner IPv4 packet.
+//!     drop(inner);
+//! })
+//! .unwrap();
+//!
+//! # let pkts = Vec::new();
+//! // IPv4 packets encapsulated to be sent to `remote`.
+//! let encapsulated = outbound.process(&mut qp, pkts);
+//! ```
+//!
+//! [`RFC 4303`]: https://www.rfc-editor.org/rfc/rfc4303
+//! [`RFC 4106`]: https://www.rfc-editor.org/rfc/rfc4106
+//! [`RFC 7634`]: https://www.rfc-editor.org/rfc/rfc7634
+
+use super::{AeadAlgo, AeadOp, AeadXform, CryptoDev, OpStatus, QueuePair, Session};
+use crate::{
+    dispatch,
+    mbuf::Mbuf,
+    proto::{cksum_add, cksum_fold},
+    trace::error,
+    Error, Result,
+};
+use dpdk_sys::RTE_ETHER_TYPE_IPV4;
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fmt::{self, Debug},
+    net::Ipv4Addr,
+    result::Result as StdResult,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// ESP `proto_id`, to be populated in IP header.
+const IP_NEXT_PROTO_ESP: u8 = 50;
+
+/// IP-in-IP `proto_id`, i.e. the next header of packets encapsulated in tunnel mode.
+const IP_NEXT_PROTO_IPIP: u8 = 4;
+
+/// Length of the IPv4 header without options.
+const IPV4_HDR_LEN: usize = 20;
+
+/// Length of the ESP header, i.e. the SPI and the sequence number, which is also the AAD.
+const ESP_HDR_LEN: usize = 8;
+
+/// Length of the AAD.
+const AAD_LEN: u16 = 8;
+
+/// Length of the explicit IV following the ESP header.
+const ESP_IV_LEN: usize = 8;
+
+/// Length of the salt at the end of the keying material, which is the front of the IV.
+const SALT_LEN: usize = 4;
+
+/// Length of the ICV, i.e. the digest.
+const ICV_LEN: usize = 16;
+
+/// Length of the pad length and the next header before the ICV.
+const TRAILER_LEN: usize = 2;
+
+/// Alignment of the ESP trailer.
+const ESP_ALIGN: usize = 4;
+
+/// TTL of the outer IPv4 header in tunnel mode.
+const TUNNEL_TTL: u8 = 64;
+
+/// Number of sequence numbers in the anti-replay window.
+const REPLAY_WINDOW: u32 = 64;
+
+/// SPIs below are reserved.
+const MIN_SPI: u32 = 256;
+
+/// How packets are encapsulated.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EspMode {
+    /// The payload of a packet is encrypted, between its IPv4 header and the ESP trailer.
+    Transport,
+    /// The whole packet is encrypted, in an outer IPv4 header from `src` to `dst`.
+    Tunnel {
+        /// Source of the outer header.
+        src: Ipv4Addr,
+        /// Destination of the outer header.
+        dst: Ipv4Addr,
+    },
+}
+
+/// A security association, i.e. the SPI, the algorithm and the key of a one-way ESP flow.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecurityAssociation {
+    /// Security parameter index.
+    spi: u32,
+    /// The algorithm.
+    algo: AeadAlgo,
+    /// The key.
+    key: Vec<u8>,
+    /// The salt, i.e. the front of the IV.
+    salt: [u8; SALT_LEN],
+    /// How packets are encapsulated.
+    mode: EspMode,
+}
+
+impl Debug for SecurityAssociation {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The key and the salt are kept secret.
+        f.debug_struct("SecurityAssociation")
+            .field("spi", &self.spi)
+            .field("algo", &self.algo)
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SecurityAssociation {
+    /// Create a security association in transport mode with `spi` and `algo`, where `keymat` is
+    /// the key followed by a 4-byte salt, as is negotiated by IKE.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `spi` is reserved, i.e. less than 256, or `keymat` is not 20, 28 or
+    ///   36 bytes for AES-GCM, or 36 bytes for ChaCha20-Poly1305.
+    #[inline]
+    pub fn new(spi: u32, algo: AeadAlgo, keymat: &[u8]) -> Result<Self> {
+        if spi < MIN_SPI {
+            return Err(Error::InvalidArg);
+        }
+        let key_len = keymat
+            .len()
+            .checked_sub(SALT_LEN)
+            .ok_or(Error::InvalidArg)?;
+        let valid = match algo {
+            AeadAlgo::AesGcm => matches!(key_len, 16 | 24 | 32),
+            AeadAlgo::ChaCha20Poly1305 => key_len == 32,
+        };
+        if !valid {
+            return Err(Error::InvalidArg);
+        }
+        let (key, salt) = keymat.split_at(key_len);
+        Ok(Self {
+            spi,
+            algo,
+            key: key.to_vec(),
+            salt: salt.try_into().ok().ok_or(Error::InvalidArg)?,
+            mode: EspMode::Transport,
+        })
+    }
+
+    /// Encapsulate packets in tunnel mode, with an outer IPv4 header from `src` to `dst`. Inbound
+    /// packets are matched by the SPI only.
+    #[inline]
+    #[must_use]
+    pub fn tunnel(mut self, src: Ipv4Addr, dst: Ipv4Addr) -> Self {
+        self.mode = EspMode::Tunnel { src, dst };
+        self
+    }
+
+    /// Security parameter index.
+    #[inline]
+    #[must_use]
+    pub fn spi(&self) -> u32 {
+        self.spi
+    }
+
+    /// How packets are encapsulated.
+    #[inline]
+    #[must_use]
+    pub fn mode(&self) -> EspMode {
+        self.mode
+    }
+
+    /// Settings of the session doing `op`.
+    fn xform(&self, op: AeadOp) -> AeadXform {
+        AeadXform::new(op, self.algo, &self.key).aad_len(AAD_LEN)
+    }
+
+    /// The IV of a packet with `explicit_iv`, which follows the salt.
+    fn iv(&self, explicit_iv: &[u8]) -> Vec<u8> {
+        let mut iv = self.salt.to_vec();
+        iv.extend_from_slice(explicit_iv);
+        iv
+    }
+}
+
+/// Statistics of an `Esp`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EspStats {
+    /// Number of packets encapsulated or decapsulated.
+    pub packets: u64,
+    /// Number of packets dropped since they are not IPv4 packets to encapsulate, or not valid ESP
+    /// packets of the security association.
+    pub malformed: u64,
+    /// Number of packets dropped since their ICV doesn't match, i.e. they are forged or corrupted.
+    pub auth_failed: u64,
+    /// Number of packets dropped since they are replayed or too old.
+    pub replayed: u64,
+    /// Number of packets dropped for lack of room or crypto operations, or since the sequence
+    /// numbers run out.
+    pub dropped: u64,
+}
+
+/// Why a packet is dropped by an `Esp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Discard {
+    /// Counted in `EspStats::malformed`.
+    Malformed,
+    /// Counted in `EspStats::auth_failed`.
+    AuthFailed,
+    /// Counted in `EspStats::replayed`.
+    Replayed,
+    /// Counted in `EspStats::dropped`.
+    Dropped,
+}
+
+/// The anti-replay window of inbound packets.
+#[derive(Debug, Clone, Copy, Default)]
+struct ReplayWindow {
+    /// The highest sequence number received.
+    top: u32,
+    /// Bit `i` is set if `top - i` is received.
+    bitmap: u64,
+}
+
+impl ReplayWindow {
+    /// Whether `seq` is neither received nor too old.
+    fn check(&self, seq: u32) -> bool {
+        if seq == 0 {
+            return false;
+        }
+        if seq > self.top {
+            return true;
+        }
+        let diff = self.top.wrapping_sub(seq);
+        diff < REPLAY_WINDOW && self.bitmap & 1_u64.wrapping_shl(diff) == 0
+    }
+
+    /// Mark `seq` as received, moving the window forward if it's the highest.
+    fn update(&mut self, seq: u32) {
+        if seq > self.top {
+            let shift = seq.wrapping_sub(self.top);
+            self.bitmap = if shift >= REPLAY_WINDOW {
+                1
+            } else {
+                self.bitmap.wrapping_shl(shift) | 1
+            };
+            self.top = seq;
+        } else {
+            self.bitmap |= 1_u64.wrapping_shl(self.top.wrapping_sub(seq));
+        }
+    }
+}
+
+/// Where the AEAD operation of a packet works.
+#[derive(Debug)]
+struct AeadParams {
+    /// Offset of the data to encrypt or decrypt, which is followed by the ICV.
+    offset: usize,
+    /// Length of the data to encrypt or decrypt.
+    len: usize,
+    /// The AAD, i.e. the ESP header.
+    aad: [u8; ESP_HDR_LEN],
+    /// The IV.
+    iv: Vec<u8>,
+}
+
+/// Number of padding bytes of a payload of `len` bytes, which aligns the ESP trailer.
+fn esp_padding(len: usize) -> usize {
+    let rem = len.wrapping_add(TRAILER_LEN).wrapping_rem(ESP_ALIGN);
+    ESP_ALIGN.wrapping_sub(rem).wrapping_rem(ESP_ALIGN)
+}
+
+/// The IPv4 header at the front of a packet.
+#[derive(Debug, Clone, Copy)]
+struct Ipv4Info {
+    /// Length of the header.
+    ihl: usize,
+    /// Total length of the packet.
+    total: usize,
+    /// Protocol of the payload.
+    proto: u8,
+    /// Whether the packet is a fragment.
+    fragment: bool,
+}
+
+/// Parse the IPv4 header at the front of `m`, which is linearized, and trim the padding beyond
+/// the total length.
+fn ipv4_header(m: &mut Mbuf) -> Option<Ipv4Info> {
+    m.linearize().ok()?;
+    let data = m.data_slice();
+    let ver_ihl = *data.first()?;
+    if ver_ihl.wrapping_shr(4) != 4 {
+        return None;
+    }
+    let ihl = usize::from(ver_ihl & 0x0f).wrapping_mul(4);
+    let total = usize::from(u16::from_be_bytes([*data.get(2)?, *data.get(3)?]));
+    let fragment = u16::from_be_bytes([*data.get(6)?, *data.get(7)?]) & 0x3fff != 0;
+    let proto = *data.get(9)?;
+    let len = data.len();
+    if ihl < IPV4_HDR_LEN || total < ihl || total > len {
+        return None;
+    }
+    if total < len {
+        m.trim(len.wrapping_sub(total)).ok()?;
+    }
+    Some(Ipv4Info {
+        ihl,
+        total,
+        proto,
+        fragment,
+    })
+}
+
+/// Set the total length and the protocol of the IPv4 header of `ihl` bytes at the front of
+/// `data`, and update its checksum.
+fn update_ipv4(data: &mut [u8], ihl: usize, total: u16, proto: u8) -> Option<()> {
+    let hdr = data.get_mut(..ihl)?;
+    hdr.get_mut(2..4)?.copy_from_slice(&total.to_be_bytes());
+    *hdr.get_mut(9)? = proto;
+    hdr.get_mut(10..12)?.fill(0);
+    let cksum = cksum_fold(cksum_add(0, hdr));
+    hdr.get_mut(10..12)?.copy_from_slice(&cksum.to_be_bytes());
+    Some(())
+}
+
+/// The ESP transform of a security association, which either encapsulates outbound packets or
+/// decapsulates inbound ones.
+#[derive(Debug)]
+pub struct Esp {
+    /// The security association.
+    sa: SecurityAssociation,
+    /// The session encrypting or decrypting packets.
+    session: Session,
+    /// Sequence number of the last outbound packet.
+    seq: u32,
+    /// The anti-replay window of inbound packets.
+    replay: ReplayWindow,
+    /// Identification of the next outer IPv4 header in tunnel mode.
+    ip_id: u16,
+    /// Statistics.
+    stats: EspStats,
+}
+
+impl Esp {
+    /// Create the transform encapsulating outbound packets of `sa`, with a session on `dev`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::NotSupported`: the device doesn't support the algorithm or the key size.
+    /// - `Error::NoMem`: too many sessions are created.
+    #[inline]
+    pub fn outbound(dev: &CryptoDev, sa: SecurityAssociation) -> Result<Self> {
+        Self::new(dev, sa, AeadOp::Encrypt)
+    }
+
+    /// Create the transform decapsulating inbound packets of `sa`, with a session on `dev`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::NotSupported`: the device doesn't support the algorithm or the key size.
+    /// - `Error::NoMem`: too many sessions are created.
+    #[inline]
+    pub fn inbound(dev: &CryptoDev, sa: SecurityAssociation) -> Result<Self> {
+        Self::new(dev, sa, AeadOp::Decrypt)
+    }
+
+    /// Create the transform of `sa` doing `op`.
+    fn new(dev: &CryptoDev, sa: SecurityAssociation, op: AeadOp) -> Result<Self> {
+        let session = dev.aead_session(&sa.xform(op))?;
+        Ok(Self {
+            sa,
+            session,
+            seq: 0,
+            replay: ReplayWindow::default(),
+            ip_id: 0,
+            stats: EspStats::default(),
+        })
+    }
+
+    /// The security association.
+    #[inline]
+    #[must_use]
+    pub fn sa(&self) -> &SecurityAssociation {
+        &self.sa
+    }
+
+    /// Whether the transform encapsulates outbound packets.
+    #[inline]
+    #[must_use]
+    pub fn is_outbound(&self) -> bool {
+        self.session.op() == AeadOp::Encrypt
+    }
+
+    /// Statistics.
+    #[inline]
+    #[must_use]
+    pub fn stats(&self) -> EspStats {
+        self.stats
+    }
+
+    /// Count a dropped packet.
+    fn discard(&mut self, reason: Discard) {
+        let counter = match reason {
+            Discard::Malformed => &mut self.stats.malformed,
+            Discard::AuthFailed => &mut self.stats.auth_failed,
+            Discard::Replayed => &mut self.stats.replayed,
+            Discard::Dropped => &mut self.stats.dropped,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+
+    /// Encapsulate or decapsulate `pkts`, which start with the IPv4 header, on `qp`, and wait
+    /// until they are processed. Returns the packets processed in order, while the others are
+    /// dropped and counted in `stats`.
+    ///
+    /// Outbound packets are encapsulated in ESP packets, whose headers are in the headroom and
+    /// whose trailers are in the tailroom. Inbound packets are decapsulated into the inner IPv4
+    /// packets in tunnel mode, or the decrypted packets in transport mode.
+    ///
+    /// The queue pair should have no other operations in flight.
+    #[inline]
+    pub fn process(&mut self, qp: &mut QueuePair, pkts: Vec<Mbuf>) -> Vec<Mbuf> {
+        let outbound = self.is_outbound();
+        let mut ops = Vec::with_capacity(pkts.len());
+        for m in pkts {
+            let prepared = if outbound {
+                self.encapsulate(m)
+            } else {
+                self.prepare_decap(m)
+            };
+            let op = prepared.and_then(|(m, params)| {
+                let mut op = qp.op(&self.session, m).map_err(|_e| Discard::Dropped)?;
+                let digest = params.offset.wrapping_add(params.len);
+                let (offset, len, digest) = (
+                    u32::try_from(params.offset)
+                        .ok()
+                        .ok_or(Discard::Malformed)?,
+                    u32::try_from(params.len).ok().ok_or(Discard::Malformed)?,
+                    u32::try_from(digest).ok().ok_or(Discard::Malformed)?,
+                );
+                op.aead(offset, len, digest, &params.aad, &params.iv)
+                    .map_err(|_e| Discard::Malformed)?;
+                Ok(op)
+            });
+            match op {
+                Ok(op) => ops.push(op),
+                Err(reason) => self.discard(reason),
+            }
+        }
+        let mut processed = Vec::with_capacity(ops.len());
+        for op in qp.process(ops) {
+            let status = op.status();
+            let Some(m) = op.into_mbuf() else {
+                continue;
+            };
+            let done = match status {
+                OpStatus::Success if outbound => Ok(m),
+                OpStatus::Success => self.finish_decap(m),
+                OpStatus::AuthFailed => Err(Discard::AuthFailed),
+                _ => Err(Discard::Dropped),
+            };
+            match done {
+                Ok(m) => {
+                    self.stats.packets = self.stats.packets.wrapping_add(1);
+                    processed.push(m);
+                }
+                Err(reason) => self.discard(reason),
+            }
+        }
+        processed
+    }
+
+    /// Encapsulate `m` in an ESP packet to be encrypted.
+    fn encapsulate(&mut self, mut m: Mbuf) -> StdResult<(Mbuf, AeadParams), Discard> {
+        let ip = ipv4_header(&mut m).ok_or(Discard::Malformed)?;
+        // Length of the header in front of the ESP header, the payload and its next header.
+        let (front, payload_len, next_hdr) = match self.sa.mode {
+            EspMode::Tunnel { .. } => (IPV4_HDR_LEN, ip.total, IP_NEXT_PROTO_IPIP),
+            EspMode::Transport if ip.fragment => return Err(Discard::Malformed),
+            EspMode::Transport => (ip.ihl, ip.total.wrapping_sub(ip.ihl), ip.proto),
+        };
+        let pad_len = esp_padding(payload_len);
+        let trailer_len = pad_len.wrapping_add(TRAILER_LEN).wrapping_add(ICV_LEN);
+        let esp_len = ESP_HDR_LEN.wrapping_add(ESP_IV_LEN);
+        let total = front
+            .wrapping_add(esp_len)
+            .wrapping_add(payload_len)
+            .wrapping_add(trailer_len);
+        let total = u16::try_from(total).ok().ok_or(Discard::Dropped)?;
+        let grow = match self.sa.mode {
+            EspMode::Tunnel { .. } => esp_len.wrapping_add(IPV4_HDR_LEN),
+            EspMode::Transport => esp_len,
+        };
+        if m.headroom() < grow || m.tailroom() < trailer_len {
+            return Err(Discard::Dropped);
+        }
+        let seq = self.seq.checked_add(1).ok_or(Discard::Dropped)?;
+
+        let trailer = m.append(trailer_len).map_err(|_e| Discard::Dropped)?;
+        for (byte, value) in trailer.iter_mut().take(pad_len).zip(1..=u8::MAX) {
+            *byte = value;
+        }
+        let pad = trailer.get_mut(pad_len).ok_or(Discard::Dropped)?;
+        *pad = u8::try_from(pad_len).ok().ok_or(Discard::Dropped)?;
+        *trailer
+            .get_mut(pad_len.wrapping_add(1))
+            .ok_or(Discard::Dropped)? = next_hdr;
+        let data = m.prepend(grow).map_err(|_e| Discard::Dropped)?;
+        match self.sa.mode {
+            EspMode::Tunnel { src, dst } => {
+                let tos = *data.get(grow.wrapping_add(1)).ok_or(Discard::Dropped)?;
+                let hdr = data.get_mut(..IPV4_HDR_LEN).ok_or(Discard::Dropped)?;
+                hdr.fill(0);
+                hdr.get_mut(..2)
+                    .ok_or(Discard::Dropped)?
+                    .copy_from_slice(&[0x45, tos]);
+                hdr.get_mut(4..6)
+                    .ok_or(Discard::Dropped)?
+                    .copy_from_slice(&self.ip_id.to_be_bytes());
+                *hdr.get_mut(8).ok_or(Discard::Dropped)? = TUNNEL_TTL;
+                hdr.get_mut(12..16)
+                    .ok_or(Discard::Dropped)?
+                    .copy_from_slice(&src.octets());
+                hdr.get_mut(16..20)
+                    .ok_or(Discard::Dropped)?
+                    .copy_from_slice(&dst.octets());
+                self.ip_id = self.ip_id.wrapping_add(1);
+            }
+            EspMode::Transport => data.copy_within(grow..grow.wrapping_add(ip.ihl), 0),
+        }
+        update_ipv4(data, front, total, IP_NEXT_PROTO_ESP).ok_or(Discard::Dropped)?;
+        let mut aad = [0; ESP_HDR_LEN];
+        aad.get_mut(..4)
+            .ok_or(Discard::Dropped)?
+            .copy_from_slice(&self.sa.spi.to_be_bytes());
+        aad.get_mut(4..)
+            .ok_or(Discard::Dropped)?
+            .copy_from_slice(&seq.to_be_bytes());
+        // The sequence number is unique for the key, and is taken as the explicit IV.
+        let explicit_iv = u64::from(seq).to_be_bytes();
+        let esp = data
+            .get_mut(front..front.wrapping_add(esp_len))
+            .ok_or(Discard::Dropped)?;
+        esp.get_mut(..ESP_HDR_LEN)
+            .ok_or(Discard::Dropped)?
+            .copy_from_slice(&aad);
+        esp.get_mut(ESP_HDR_LEN..)
+            .ok_or(Discard::Dropped)?
+            .copy_from_slice(&explicit_iv);
+        self.seq = seq;
+        let params = AeadParams {
+            offset: front.wrapping_add(esp_len),
+            len: payload_len.wrapping_add(pad_len).wrapping_add(TRAILER_LEN),
+            aad,
+            iv: self.sa.iv(&explicit_iv),
+        };
+        Ok((m, params))
+    }
+
+    /// Check the ESP packet `m` before it's decrypted.
+    fn prepare_decap(&self, mut m: Mbuf) -> StdResult<(Mbuf, AeadParams), Discard> {
+        let ip = ipv4_header(&mut m).ok_or(Discard::Malformed)?;
+        if ip.proto != IP_NEXT_PROTO_ESP || ip.fragment {
+            return Err(Discard::Malformed);
+        }
+        let offset = ip.ihl.wrapping_add(ESP_HDR_LEN).wrapping_add(ESP_IV_LEN);
+        let len = ip
+            .total
+            .checked_sub(offset.wrapping_add(ICV_LEN))
+            .ok_or(Discard::Malformed)?;
+        if len < TRAILER_LEN || len.wrapping_rem(ESP_ALIGN) != 0 {
+            return Err(Discard::Malformed);
+        }
+        let esp = m
+            .data_slice()
+            .get(ip.ihl..offset)
+            .ok_or(Discard::Malformed)?;
+        let (aad, explicit_iv) = esp.split_at(ESP_HDR_LEN);
+        let aad: [u8; ESP_HDR_LEN] = aad.try_into().ok().ok_or(Discard::Malformed)?;
+        let [spi0, spi1, spi2, spi3, seq0, seq1, seq2, seq3] = aad;
+        if u32::from_be_bytes([spi0, spi1, spi2, spi3]) != self.sa.spi {
+            return Err(Discard::Malformed);
+        }
+        if !self
+            .replay
+            .check(u32::from_be_bytes([seq0, seq1, seq2, seq3]))
+        {
+            return Err(Discard::Replayed);
+        }
+        let iv = self.sa.iv(explicit_iv);
+        let params = AeadParams {
+            offset,
+            len,
+            aad,
+            iv,
+        };
+        Ok((m, params))
+    }
+
+    /// Strip the headers and the trailer of the decrypted ESP packet `m`.
+    fn finish_decap(&mut self, mut m: Mbuf) -> StdResult<Mbuf, Discard> {
+        let ip = ipv4_header(&mut m).ok_or(Discard::Malformed)?;
+        let esp_len = ESP_HDR_LEN.wrapping_add(ESP_IV_LEN);
+        let start = ip.ihl.wrapping_add(esp_len);
+        let data = m.data_slice();
+        let seq = data
+            .get(ip.ihl.wrapping_add(4)..ip.ihl.wrapping_add(ESP_HDR_LEN))
+            .and_then(|seq| <[u8; 4]>::try_from(seq).ok())
+            .map(u32::from_be_bytes)
+            .ok_or(Discard::Malformed)?;
+        // Packets of the same sequence number may be in the same batch.
+        if !self.replay.check(seq) {
+            return Err(Discard::Replayed);
+        }
+        self.replay.update(seq);
+        let trailer = ip.total.wrapping_sub(ICV_LEN).wrapping_sub(TRAILER_LEN);
+        let pad_len = usize::from(*data.get(trailer).ok_or(Discard::Malformed)?);
+        let next_hdr = *data
+            .get(trailer.wrapping_add(1))
+            .ok_or(Discard::Malformed)?;
+        let padding = trailer
+            .checked_sub(pad_len)
+            .filter(|&padding| padding >= start)
+            .ok_or(Discard::Malformed)?;
+        let monotonic = data
+            .get(padding..trailer)
+            .ok_or(Discard::Malformed)?
+            .iter()
+            .zip(1..=u8::MAX)
+            .all(|(&byte, value)| byte == value);
+        if !monotonic {
+            return Err(Discard::Malformed);
+        }
+        m.trim(ip.total.wrapping_sub(padding))
+            .map_err(|_e| Discard::Malformed)?;
+        match self.sa.mode {
+            EspMode::Tunnel { .. } => {
+                if next_hdr != IP_NEXT_PROTO_IPIP {
+                    return Err(Discard::Malformed);
+                }
+                m.adj(start).map_err(|_e| Discard::Malformed)?;
+            }
+            EspMode::Transport => {
+                let total = u16::try_from(padding.wrapping_sub(esp_len))
+                    .ok()
+                    .ok_or(Discard::Malformed)?;
+                m.data_slice_mut().copy_within(..ip.ihl, esp_len);
+                m.adj(esp_len).map_err(|_e| Discard::Malformed)?;
+                update_ipv4(m.data_slice_mut(), ip.ihl, total, next_hdr)
+                    .ok_or(Discard::Malformed)?;
+            }
+        }
+        Ok(m)
+    }
+}
+
+/// The SPI of the ESP packet `m`, which starts with the IPv4 header.
+fn spi_of(m: &Mbuf) -> Option<u32> {
+    let data = m.data_slice();
+    let ihl = usize::from(*data.first()? & 0x0f).wrapping_mul(4);
+    let spi = data.get(ihl..ihl.wrapping_add(4))?;
+    Some(u32::from_be_bytes(spi.try_into().ok()?))
+}
+
+/// State of an `EspInbound`, shared with its dispatch handler.
+#[derive(Debug)]
+struct InboundState {
+    /// The queue pair decrypting packets.
+    qp: QueuePair,
+    /// Inbound transforms, keyed by SPI.
+    sas: BTreeMap<u32, Esp>,
+}
+
+/// Decapsulation of the ESP packets received by the stack, which is installed as a `dispatch`
+/// handler, and uninstalled once dropped.
+///
+/// Packets are decapsulated one at a time by the agent threads receiving them, which wait for the
+/// crypto device, so a software device or a dedicated queue pair is preferred.
+#[derive(Debug)]
+pub struct EspInbound {
+    /// State shared with the handler.
+    state: Arc<Mutex<InboundState>>,
+}
+
+impl EspInbound {
+    /// Decapsulate the ESP packets received by the stack with the inbound transforms `sas` on
+    /// `qp`, and pass the inner packets to `deliver`, i.e. the inner IPv4 packets in tunnel mode,
+    /// or the decrypted packets in transport mode. ESP packets of other SPIs go on through the
+    /// stack.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: some of `sas` are outbound, or share the same SPI.
+    /// - `Error::Exists`: another handler is installed on ESP packets.
+    #[inline]
+    pub fn install<F>(qp: QueuePair, sas: Vec<Esp>, deliver: F) -> Result<Self>
+    where
+        F: Fn(Mbuf) + Send + Sync + 'static,
+    {
+        let mut map = BTreeMap::new();
+        for esp in sas {
+            if esp.is_outbound() {
+                return Err(Error::InvalidArg);
+            }
+            match map.entry(esp.sa.spi) {
+                Entry::Occupied(_) => return Err(Error::InvalidArg),
+                Entry::Vacant(entry) => {
+                    let _esp = entry.insert(esp);
+                }
+            }
+        }
+        let state = Arc::new(Mutex::new(InboundState { qp, sas: map }));
+        let shared = Arc::clone(&state);
+        #[allow(clippy::cast_possible_truncation)] // Ether type is 16 bits
+        dispatch::register(
+            RTE_ETHER_TYPE_IPV4 as u16,
+            Some(IP_NEXT_PROTO_ESP),
+            move |m| {
+                let Some(spi) = spi_of(&m) else {
+                    return Some(m);
+                };
+                let mut guard = shared.lock().unwrap_or_else(PoisonError::into_inner);
+                let InboundState {
+                    ref mut qp,
+                    ref mut sas,
+                } = *guard;
+                let Some(esp) = sas.get_mut(&spi) else {
+                    return Some(m);
+                };
+                for inner in esp.process(qp, vec![m]) {
+                    deliver(inner);
+                }
+                None
+            },
+        )?;
+        Ok(Self { state })
+    }
+
+    /// Statistics of the transform of `spi`.
+    #[inline]
+    #[must_use]
+    pub fn stats(&self, spi: u32) -> Option<EspStats> {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .sas
+            .get(&spi)
+            .map(Esp::stats)
+    }
+}
+
+impl Drop for EspInbound {
+    #[inline]
+    fn drop(&mut self) {
+        #[allow(clippy::cast_possible_truncation)] // Ether type is 16 bits
+        if let Err(e) = dispatch::unregister(RTE_ETHER_TYPE_IPV4 as u16, Some(IP_NEXT_PROTO_ESP)) {
+            error!("Failed to uninstall the ESP handler: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{esp_padding, ReplayWindow, SecurityAssociation, REPLAY_WINDOW};
+    use crate::{crypto::AeadAlgo, Error};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_sa() {
+        assert_eq!(
+            SecurityAssociation::new(255, AeadAlgo::AesGcm, &[0; 20]).unwrap_err(),
+            Error::InvalidArg
+        );
+        assert_eq!(
+            SecurityAssociation::new(256, AeadAlgo::AesGcm, &[0; 16]).unwrap_err(),
+            Error::InvalidArg
+        );
+        assert_eq!(
+            SecurityAssociation::new(256, AeadAlgo::ChaCha20Poly1305, &[0; 20]).unwrap_err(),
+            Error::InvalidArg
+        );
+        let keymat: Vec<u8> = (0..36).collect();
+        let sa = SecurityAssociation::new(0x1234, AeadAlgo::AesGcm, &keymat).unwrap();
+        assert_eq!(sa.key, keymat[..32]);
+        assert_eq!(sa.salt, [32, 33, 34, 35]);
+        assert_eq!(sa.iv(&[1; 8]), [32, 33, 34, 35, 1, 1, 1, 1, 1, 1, 1, 1]);
+        let src = Ipv4Addr::new(10, 0, 0, 1);
+        let dst = Ipv4Addr::new(10, 0, 0, 2);
+        let sa = sa.tunnel(src, dst);
+        assert_eq!(sa.spi(), 0x1234);
+        assert_eq!(sa.mode(), super::EspMode::Tunnel { src, dst });
+        // The key is not printed.
+        assert!(!format!("{sa:?}").contains("key"));
+    }
+
+    #[test]
+    fn test_padding() {
+        // The payload and the 2-byte trailer are aligned to 4 bytes.
+        assert_eq!(esp_padding(2), 0);
+        assert_eq!(esp_padding(3), 3);
+        assert_eq!(esp_padding(4), 2);
+        assert_eq!(esp_padding(5), 1);
+        assert_eq!(esp_padding(1400), 2);
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let mut window = ReplayWindow::default();
+        assert!(!window.check(0));
+        for seq in [1, 3, 2] {
+            assert!(window.check(seq));
+            window.update(seq);
+            assert!(!window.check(seq));
+        }
+        window.update(100);
+        assert!(window.check(99));
+        assert!(window.check(100 - REPLAY_WINDOW + 1));
+        // Too old.
+        assert!(!window.check(100 - REPLAY_WINDOW));
+        assert!(!window.check(3));
+        window.update(90);
+        assert!(!window.check(90));
+        // Far ahead, which resets the window.
+        window.update(1000);
+        assert!(!window.check(1000));
+        assert!(window.check(999));
+    }
+}
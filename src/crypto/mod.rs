@@ -0,0 +1,1375 @@
+//! Crypto devices based on `rte_cryptodev`, which encrypt and authenticate packets in batches, in
+//! hardware or in software drivers such as `Vdev::CryptoOpenssl`. For more information, please
+//! refer to [`Cryptography Device Library document`].
+//!
+//! A `CryptoDev` is configured with queue pairs, to which crypto operations are enqueued and from
+//! which they are dequeued once processed. Each operation works on a packet with a `Session`,
+//! which holds the algorithm and the key. AEAD algorithms are supported, which cover IPsec ESP
+//! as is done by the transform in [`esp`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use async_dpdk::crypto::{AeadAlgo, AeadOp, AeadXform, CryptoDev, OpStatus};
+//! # use async_dpdk::mbuf::Mbuf;
+//! # use async_dpdk::mempool::{Mempool, PktMempool};
+//!
+//! # let mp = PktMempool::create("crypto_example", 64).unwrap();
+//! let dev = CryptoDev::open("crypto_openssl0").unwrap();
+//! dev.configure(1).unwrap();
+//! let mut qp = dev.queue_pair_setup(0).unwrap();
+//! dev.start().unwrap();
+//! let xform = AeadXform::new(AeadOp::Encrypt, AeadAlgo::AesGcm, &[0; 16]).aad_len(8);
+//! let session = dev.aead_session(&xform).unwrap();
+//!
+//! let mut m = Mbuf::new(&mp).unwrap();
+//! m.append(64 + 16).unwrap();
+//! let mut op = qp.op(&session, m).unwrap();
+//! // Encrypt the first 64 bytes, with the digest in the last 16 bytes.
+//! op.aead(0, 64, 64, &[0; 8], &[0; 12]).unwrap();
+//! let ops = qp.process(vec![op]);
+//! assert_eq!(ops[0].status(), OpStatus::Success);
+//! ```
+//!
+//! [`Cryptography Device Library document`]: https://doc.dpdk.org/guides/prog_guide/cryptodev_lib.html
+
+#![allow(non_camel_case_types)]
+
+pub mod esp;
+
+use crate::{
+    lcore,
+    mbuf::Mbuf,
+    trace::{error, warn},
+    Error, Result,
+};
+use dpdk_sys::{
+    rte_mbuf, rte_mempool, rte_mempool_create, rte_mempool_free, rte_mempool_get, rte_mempool_put,
+};
+use lazy_static::lazy_static;
+use std::{
+    collections::BTreeSet,
+    ffi::{CStr, CString},
+    hint,
+    mem::{self, size_of, ManuallyDrop},
+    os::raw::{c_char, c_int, c_uint, c_void},
+    ptr::{self, NonNull},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Maximum number of crypto devices, i.e. `RTE_CRYPTO_MAX_DEVS`.
+const RTE_CRYPTO_MAX_DEVS: usize = 64;
+
+/// Type of symmetric crypto operations.
+const RTE_CRYPTO_OP_TYPE_SYMMETRIC: c_int = 1;
+
+/// An operation processed successfully.
+const RTE_CRYPTO_OP_STATUS_SUCCESS: u8 = 0;
+
+/// An operation not processed yet.
+const RTE_CRYPTO_OP_STATUS_NOT_PROCESSED: u8 = 1;
+
+/// An operation whose digest doesn't match.
+const RTE_CRYPTO_OP_STATUS_AUTH_FAILED: u8 = 2;
+
+/// An operation with an invalid session.
+const RTE_CRYPTO_OP_STATUS_INVALID_SESSION: u8 = 3;
+
+/// An operation with invalid arguments.
+const RTE_CRYPTO_OP_STATUS_INVALID_ARGS: u8 = 4;
+
+/// An operation working with a session.
+const RTE_CRYPTO_OP_WITH_SESSION: u8 = 0;
+
+/// Transform of AEAD algorithms.
+const RTE_CRYPTO_SYM_XFORM_AEAD: c_int = 3;
+
+/// Offset of the IV in a crypto operation, which is right after the symmetric operation.
+const IV_OFFSET: usize = size_of::<rte_crypto_op>().wrapping_add(size_of::<rte_crypto_sym_op>());
+
+/// Maximum length of the IV.
+const MAX_IV_LEN: usize = 16;
+
+/// Offset of the AAD in a crypto operation, which is right after the IV.
+const AAD_OFFSET: usize = IV_OFFSET.wrapping_add(MAX_IV_LEN);
+
+/// Maximum length of the AAD.
+const MAX_AAD_LEN: usize = 16;
+
+/// Offset of the session an operation works with, which is right after the AAD.
+const SESSION_OFFSET: usize = AAD_OFFSET.wrapping_add(MAX_AAD_LEN);
+
+/// Size of the private area of a crypto operation, which holds the IV, the AAD and the session.
+#[allow(clippy::cast_possible_truncation)] // 40 bytes
+const OP_PRIV_SIZE: u16 = SESSION_OFFSET
+    .wrapping_add(size_of::<*const SessionHandle>())
+    .wrapping_sub(IV_OFFSET) as u16;
+
+/// Number of descriptors of a queue pair.
+const QP_DESCRIPTORS: u32 = 2048;
+
+/// Number of crypto operations of a queue pair.
+const OP_POOL_SIZE: u32 = 4095;
+
+/// Size of the per-lcore cache of crypto operations.
+const OP_CACHE_SIZE: u32 = 64;
+
+/// Number of sessions of a device.
+const SESSION_POOL_SIZE: u32 = 1023;
+
+/// Max number of operations enqueued or dequeued by `QueuePair::process` in a burst.
+const PROCESS_BURST: usize = 32;
+
+/// How long `QueuePair::process` waits at most for the operations to be processed.
+const PROCESS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Capabilities and limits of a crypto device.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_cryptodev_info {
+    /// Name of the driver.
+    driver_name: *const c_char,
+    /// Id of the driver.
+    driver_id: u8,
+    /// Generic device information.
+    device: *mut c_void,
+    /// Flags of `RTE_CRYPTODEV_FF_*`.
+    feature_flags: u64,
+    /// Array of capabilities, ended by an undefined one.
+    capabilities: *const c_void,
+    /// Maximum number of queue pairs.
+    max_nb_queue_pairs: c_uint,
+    /// Headroom of packets required by the device.
+    min_mbuf_headroom_req: u16,
+    /// Tailroom of packets required by the device.
+    min_mbuf_tailroom_req: u16,
+    /// Maximum number of symmetric sessions, where 0 means unlimited, i.e. `sym.max_nb_sessions`.
+    max_nb_sessions: c_uint,
+}
+
+/// Configuration of a crypto device.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_cryptodev_config {
+    /// NUMA socket to allocate memory on.
+    socket_id: c_int,
+    /// Number of queue pairs.
+    nb_queue_pairs: u16,
+    /// Flags of `RTE_CRYPTODEV_FF_*` to disable.
+    ff_disable: u64,
+}
+
+/// Configuration of a queue pair.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_cryptodev_qp_conf {
+    /// Number of descriptors.
+    nb_descriptors: u32,
+    /// Mempool of the sessions of sessionless operations.
+    mp_session: *mut rte_mempool,
+    /// Mempool of the private data of the sessions of sessionless operations.
+    mp_session_private: *mut rte_mempool,
+}
+
+/// Header of a crypto operation, followed by the symmetric operation.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_crypto_op {
+    /// One of `RTE_CRYPTO_OP_TYPE_*`.
+    op_type: u8,
+    /// One of `RTE_CRYPTO_OP_STATUS_*`.
+    status: u8,
+    /// Whether the operation works with a session.
+    sess_type: u8,
+    /// Reserved.
+    reserved: [u8; 3],
+    /// Offset of the private data from the start of the operation.
+    private_data_offset: u16,
+    /// The mempool the operation is allocated from.
+    mempool: *mut rte_mempool,
+    /// IOVA of the operation.
+    phys_addr: u64,
+}
+
+/// A symmetric crypto operation, of which the union of the data to work on is mirrored as that of
+/// AEAD algorithms.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_crypto_sym_op {
+    /// The packet to work on.
+    m_src: *mut rte_mbuf,
+    /// The packet to write to, where null means in place.
+    m_dst: *mut rte_mbuf,
+    /// The session.
+    session: *mut c_void,
+    /// Offset of the data to encrypt or decrypt.
+    data_offset: u32,
+    /// Length of the data to encrypt or decrypt.
+    data_length: u32,
+    /// Where the digest is written to or read from.
+    digest_data: *mut u8,
+    /// IOVA of the digest.
+    digest_phys_addr: u64,
+    /// Additional authenticated data.
+    aad_data: *mut u8,
+    /// IOVA of the AAD.
+    aad_phys_addr: u64,
+}
+
+/// A key.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_crypto_key {
+    /// The key.
+    data: *const u8,
+    /// Length of the key.
+    length: u16,
+}
+
+/// Where the IV is in crypto operations.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_crypto_iv {
+    /// Offset of the IV from the start of the operation.
+    offset: u16,
+    /// Length of the IV.
+    length: u16,
+}
+
+/// Transform of an AEAD algorithm.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_crypto_aead_xform {
+    /// One of `RTE_CRYPTO_AEAD_OP_*`.
+    op: c_int,
+    /// One of `RTE_CRYPTO_AEAD_*`.
+    algo: c_int,
+    /// The key.
+    key: rte_crypto_key,
+    /// Where the IV is.
+    iv: rte_crypto_iv,
+    /// Length of the digest.
+    digest_length: u16,
+    /// Length of the AAD.
+    aad_length: u16,
+}
+
+/// A transform of a session, of which the union of transforms is mirrored as that of AEAD
+/// algorithms.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_crypto_sym_xform {
+    /// The next transform in the chain.
+    next: *mut rte_crypto_sym_xform,
+    /// One of `RTE_CRYPTO_SYM_XFORM_*`.
+    xform_type: c_int,
+    /// The AEAD transform.
+    aead: rte_crypto_aead_xform,
+}
+
+/// Index of the capability of an algorithm.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_cryptodev_sym_capability_idx {
+    /// One of `RTE_CRYPTO_SYM_XFORM_*`.
+    xform_type: c_int,
+    /// The algorithm.
+    algo: c_int,
+}
+
+/// Enqueue or dequeue a burst of operations on a queue pair, returning the number of operations
+/// accepted or dequeued.
+type crypto_burst_t =
+    Option<unsafe extern "C" fn(qp: *mut c_void, ops: *mut *mut rte_crypto_op, nb_ops: u16) -> u16>;
+
+/// Private data of the queue pairs of a device.
+#[repr(C)]
+struct rte_cryptodev_qp_data {
+    /// Private data of each queue pair.
+    data: *mut *mut c_void,
+    /// Callbacks called on enqueue.
+    enq_cb: *mut c_void,
+    /// Callbacks called on dequeue.
+    deq_cb: *mut c_void,
+}
+
+/// Fast-path functions of a crypto device, called by the inline enqueue and dequeue functions.
+#[repr(C, align(64))]
+struct rte_crypto_fp_ops {
+    /// Enqueue a burst of operations.
+    enqueue_burst: crypto_burst_t,
+    /// Dequeue a burst of operations.
+    dequeue_burst: crypto_burst_t,
+    /// Private data of the queue pairs.
+    qp: rte_cryptodev_qp_data,
+    /// Reserved.
+    reserved: [usize; 3],
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Fast-path functions of all crypto devices.
+    #[allow(non_upper_case_globals)]
+    static rte_crypto_fp_ops: [rte_crypto_fp_ops; RTE_CRYPTO_MAX_DEVS];
+
+    /// Get the id of a crypto device by its name.
+    fn rte_cryptodev_get_dev_id(name: *const c_char) -> c_int;
+
+    /// Get the capabilities and limits of a crypto device.
+    fn rte_cryptodev_info_get(dev_id: u8, dev_info: *mut rte_cryptodev_info);
+
+    /// Configure a stopped crypto device.
+    fn rte_cryptodev_configure(dev_id: u8, config: *mut rte_cryptodev_config) -> c_int;
+
+    /// Set up a queue pair.
+    fn rte_cryptodev_queue_pair_setup(
+        dev_id: u8,
+        queue_pair_id: u16,
+        qp_conf: *const rte_cryptodev_qp_conf,
+        socket_id: c_int,
+    ) -> c_int;
+
+    /// Start a crypto device.
+    fn rte_cryptodev_start(dev_id: u8) -> c_int;
+
+    /// Stop a crypto device.
+    fn rte_cryptodev_stop(dev_id: u8);
+
+    /// Close a stopped crypto device.
+    fn rte_cryptodev_close(dev_id: u8) -> c_int;
+
+    /// Get the capability of an algorithm, or null if it's not supported.
+    fn rte_cryptodev_sym_capability_get(
+        dev_id: u8,
+        idx: *const rte_cryptodev_sym_capability_idx,
+    ) -> *const c_void;
+
+    /// Check the sizes of an AEAD algorithm against its capability, returning 0 if supported.
+    fn rte_cryptodev_sym_capability_check_aead(
+        capability: *const c_void,
+        key_size: u16,
+        digest_size: u16,
+        aad_size: u16,
+        iv_size: u16,
+    ) -> c_int;
+
+    /// Get the size of the private data of the sessions of a device.
+    fn rte_cryptodev_sym_get_private_session_size(dev_id: u8) -> c_uint;
+
+    /// Create a mempool of symmetric session headers, whose elements are at least as large as
+    /// the header.
+    fn rte_cryptodev_sym_session_pool_create(
+        name: *const c_char,
+        nb_elts: u32,
+        elt_size: u32,
+        cache_size: u32,
+        user_data_size: u16,
+        socket_id: c_int,
+    ) -> *mut rte_mempool;
+
+    /// Create a symmetric session header, which is initialized on devices later.
+    fn rte_cryptodev_sym_session_create(mempool: *mut rte_mempool) -> *mut c_void;
+
+    /// Initialize a session on a device with a chain of transforms, whose private data is
+    /// allocated from `mempool`.
+    fn rte_cryptodev_sym_session_init(
+        dev_id: u8,
+        sess: *mut c_void,
+        xforms: *mut rte_crypto_sym_xform,
+        mempool: *mut rte_mempool,
+    ) -> c_int;
+
+    /// Release the private data of a session on a device.
+    fn rte_cryptodev_sym_session_clear(dev_id: u8, sess: *mut c_void) -> c_int;
+
+    /// Free a symmetric session header, which is cleared on all devices.
+    fn rte_cryptodev_sym_session_free(sess: *mut c_void) -> c_int;
+
+    /// Create a mempool of crypto operations.
+    fn rte_crypto_op_pool_create(
+        name: *const c_char,
+        op_type: c_int,
+        nb_elts: c_uint,
+        cache_size: c_uint,
+        priv_size: u16,
+        socket_id: c_int,
+    ) -> *mut rte_mempool;
+}
+
+/// An AEAD algorithm.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AeadAlgo {
+    /// AES in Galois/Counter mode, with 16, 24 or 32-byte keys.
+    AesGcm,
+    /// ChaCha20 with Poly1305, with 32-byte keys.
+    ChaCha20Poly1305,
+}
+
+impl AeadAlgo {
+    /// The `RTE_CRYPTO_AEAD_*` value.
+    fn raw(self) -> c_int {
+        match self {
+            AeadAlgo::AesGcm => 2,
+            AeadAlgo::ChaCha20Poly1305 => 3,
+        }
+    }
+}
+
+/// Whether an AEAD session encrypts or decrypts.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AeadOp {
+    /// Encrypt the data and generate the digest.
+    Encrypt,
+    /// Verify the digest and decrypt the data.
+    Decrypt,
+}
+
+impl AeadOp {
+    /// The `RTE_CRYPTO_AEAD_OP_*` value.
+    fn raw(self) -> c_int {
+        match self {
+            AeadOp::Encrypt => 0,
+            AeadOp::Decrypt => 1,
+        }
+    }
+}
+
+/// Settings of an AEAD session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AeadXform {
+    /// Whether the session encrypts or decrypts.
+    op: AeadOp,
+    /// The algorithm.
+    algo: AeadAlgo,
+    /// The key.
+    key: Vec<u8>,
+    /// Length of the IV.
+    iv_len: u16,
+    /// Length of the digest.
+    digest_len: u16,
+    /// Length of the AAD.
+    aad_len: u16,
+}
+
+impl AeadXform {
+    /// Create the settings of a session doing `op` with `algo` and `key`, with a 12-byte IV, a
+    /// 16-byte digest and no AAD.
+    #[inline]
+    #[must_use]
+    pub fn new(op: AeadOp, algo: AeadAlgo, key: &[u8]) -> Self {
+        Self {
+            op,
+            algo,
+            key: key.to_vec(),
+            iv_len: 12,
+            digest_len: 16,
+            aad_len: 0,
+        }
+    }
+
+    /// Set the length of the IV, which is 16 bytes at most.
+    #[inline]
+    #[must_use]
+    pub fn iv_len(mut self, len: u16) -> Self {
+        self.iv_len = len;
+        self
+    }
+
+    /// Set the length of the digest.
+    #[inline]
+    #[must_use]
+    pub fn digest_len(mut self, len: u16) -> Self {
+        self.digest_len = len;
+        self
+    }
+
+    /// Set the length of the AAD, which is 16 bytes at most.
+    #[inline]
+    #[must_use]
+    pub fn aad_len(mut self, len: u16) -> Self {
+        self.aad_len = len;
+        self
+    }
+}
+
+/// Capabilities and limits of a crypto device.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CryptoDevInfo {
+    /// Name of the driver, e.g. `crypto_openssl`.
+    pub driver: String,
+    /// Maximum number of queue pairs.
+    pub max_queue_pairs: u32,
+    /// Maximum number of sessions, where 0 means unlimited.
+    pub max_sessions: u32,
+    /// Headroom of packets required by the device.
+    pub min_headroom: u16,
+    /// Tailroom of packets required by the device.
+    pub min_tailroom: u16,
+}
+
+lazy_static! {
+    /// Ids of the crypto devices opened by `CryptoDev::open`.
+    static ref OPENED: Mutex<BTreeSet<u8>> = Mutex::new(BTreeSet::new());
+}
+
+/// Mempools of the sessions of a device.
+#[derive(Debug, Clone, Copy)]
+struct SessionPools {
+    /// Mempool of the session headers.
+    headers: NonNull<rte_mempool>,
+    /// Mempool of the private data of the sessions, whose size depends on the driver.
+    private: NonNull<rte_mempool>,
+}
+
+/// An opened crypto device, which is stopped and closed once the `CryptoDev`, its queue pairs and
+/// its sessions are dropped.
+#[derive(Debug)]
+struct DevHandle {
+    /// `dev_id` assigned by DPDK.
+    dev_id: u8,
+    /// The mempools of sessions, created along with the first session.
+    sessions: Mutex<Option<SessionPools>>,
+    /// Ids of the queue pairs set up.
+    qps: Mutex<BTreeSet<u16>>,
+}
+
+// SAFETY: the device and the session mempools can be accessed from any thread
+#[allow(unsafe_code)]
+unsafe impl Send for DevHandle {}
+
+// SAFETY: the session mempools are guarded by a lock
+#[allow(unsafe_code)]
+unsafe impl Sync for DevHandle {}
+
+#[allow(unsafe_code)]
+impl Drop for DevHandle {
+    fn drop(&mut self) {
+        // SAFETY: ffi, all sessions and queue pairs are dropped
+        unsafe {
+            rte_cryptodev_stop(self.dev_id);
+            if let Err(e) = Error::from_ret(rte_cryptodev_close(self.dev_id), "rte_cryptodev_close")
+            {
+                error!("Failed to close crypto device {}: {e}", self.dev_id);
+            }
+            let sessions = self
+                .sessions
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take();
+            if let Some(pools) = sessions {
+                rte_mempool_free(pools.private.as_ptr());
+                rte_mempool_free(pools.headers.as_ptr());
+            }
+        }
+        let _removed = OPENED
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.dev_id);
+    }
+}
+
+/// A crypto device, which is a safe wrapper of `rte_cryptodev`.
+///
+/// The device is configured with `configure`, and its queue pairs are set up before it starts.
+/// Sessions may be created at any time.
+#[derive(Debug)]
+pub struct CryptoDev {
+    /// The opened device.
+    dev: Arc<DevHandle>,
+}
+
+#[allow(unsafe_code)]
+impl CryptoDev {
+    /// Open the crypto device named `name`, e.g. `crypto_openssl0`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `name` contains a nul byte.
+    /// - Lock poisoned.
+    /// - `Error::NoDev`: there's no such device.
+    /// - `Error::Busy`: the device is already opened.
+    #[inline]
+    pub fn open(name: &str) -> Result<Self> {
+        let name = CString::new(name).map_err(Error::from)?;
+        // SAFETY: ffi
+        let ret = unsafe { rte_cryptodev_get_dev_id(name.as_ptr()) };
+        let dev_id = u8::try_from(ret).ok().ok_or(Error::NoDev)?;
+        if !OPENED.lock().map_err(Error::from)?.insert(dev_id) {
+            return Err(Error::Busy);
+        }
+        Ok(Self {
+            dev: Arc::new(DevHandle {
+                dev_id,
+                sessions: Mutex::new(None),
+                qps: Mutex::new(BTreeSet::new()),
+            }),
+        })
+    }
+
+    /// The `dev_id` of the device.
+    #[inline]
+    #[must_use]
+    pub fn id(&self) -> u8 {
+        self.dev.dev_id
+    }
+
+    /// Get the raw capabilities and limits of the device.
+    fn raw_info(&self) -> rte_cryptodev_info {
+        // SAFETY: all zeros is a valid `rte_cryptodev_info`
+        let mut info = unsafe { mem::zeroed::<rte_cryptodev_info>() };
+        // SAFETY: ffi
+        unsafe { rte_cryptodev_info_get(self.id(), ptr::addr_of_mut!(info)) };
+        info
+    }
+
+    /// Get the capabilities and limits of the device.
+    #[inline]
+    #[must_use]
+    pub fn info(&self) -> CryptoDevInfo {
+        let info = self.raw_info();
+        let driver = if info.driver_name.is_null() {
+            String::new()
+        } else {
+            // SAFETY: the name is a nul-terminated string owned by the driver
+            unsafe { CStr::from_ptr(info.driver_name) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        CryptoDevInfo {
+            driver,
+            max_queue_pairs: info.max_nb_queue_pairs,
+            max_sessions: info.max_nb_sessions,
+            min_headroom: info.min_mbuf_headroom_req,
+            min_tailroom: info.min_mbuf_tailroom_req,
+        }
+    }
+
+    /// Configure the stopped device with `n_qps` queue pairs.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `n_qps` is 0 or exceeds the limit of the device.
+    /// - `Error::Busy`: the device is started.
+    #[inline]
+    pub fn configure(&self, n_qps: u16) -> Result<()> {
+        if n_qps == 0 || u32::from(n_qps) > self.raw_info().max_nb_queue_pairs {
+            return Err(Error::InvalidArg);
+        }
+        let mut conf = rte_cryptodev_config {
+            socket_id: lcore::socket_id(),
+            nb_queue_pairs: n_qps,
+            ff_disable: 0,
+        };
+        // SAFETY: `conf` is copied by DPDK
+        let errno = unsafe { rte_cryptodev_configure(self.id(), ptr::addr_of_mut!(conf)) };
+        Error::from_ret(errno, "rte_cryptodev_configure")
+    }
+
+    /// Set up queue pair `qp_id`, along with a mempool of the crypto operations enqueued to it.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: `qp_id` is out of the configured range.
+    /// - `Error::Busy`: the device is started.
+    /// - `Error::Exists`: the queue pair is already set up and not dropped.
+    /// - `Error::NoMem`: no memory left for the operations.
+    #[inline]
+    pub fn queue_pair_setup(&self, qp_id: u16) -> Result<QueuePair> {
+        let mut qps = self.dev.qps.lock().map_err(Error::from)?;
+        if qps.contains(&qp_id) {
+            return Err(Error::Exists);
+        }
+        let conf = rte_cryptodev_qp_conf {
+            nb_descriptors: QP_DESCRIPTORS,
+            mp_session: ptr::null_mut(),
+            mp_session_private: ptr::null_mut(),
+        };
+        let socket_id = lcore::socket_id();
+        // SAFETY: `conf` is copied by DPDK
+        let errno = unsafe {
+            rte_cryptodev_queue_pair_setup(self.id(), qp_id, ptr::addr_of!(conf), socket_id)
+        };
+        Error::from_ret(errno, "rte_cryptodev_queue_pair_setup")?;
+        let name = CString::new(format!("crypto_op_{}_{qp_id}", self.id())).map_err(Error::from)?;
+        // SAFETY: pointer checked later
+        let ops = unsafe {
+            rte_crypto_op_pool_create(
+                name.as_ptr(),
+                RTE_CRYPTO_OP_TYPE_SYMMETRIC,
+                OP_POOL_SIZE,
+                OP_CACHE_SIZE,
+                OP_PRIV_SIZE,
+                socket_id,
+            )
+        };
+        let ops =
+            NonNull::new(ops).ok_or_else(|| Error::from_errno("rte_crypto_op_pool_create"))?;
+        let _inserted = qps.insert(qp_id);
+        Ok(QueuePair {
+            dev: Arc::clone(&self.dev),
+            qp_id,
+            ops,
+            in_flight: 0,
+        })
+    }
+
+    /// Start the device, after which operations are processed.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::Busy`: the device is already started.
+    /// - Some of the queue pairs are not set up.
+    #[inline]
+    pub fn start(&self) -> Result<()> {
+        // SAFETY: ffi
+        let errno = unsafe { rte_cryptodev_start(self.id()) };
+        Error::from_ret(errno, "rte_cryptodev_start")
+    }
+
+    /// Whether the device supports `xform`, i.e. its algorithm along with the sizes of its key,
+    /// IV, digest and AAD.
+    #[inline]
+    #[must_use]
+    pub fn supports(&self, xform: &AeadXform) -> bool {
+        let Ok(key_len) = u16::try_from(xform.key.len()) else {
+            return false;
+        };
+        let idx = rte_cryptodev_sym_capability_idx {
+            xform_type: RTE_CRYPTO_SYM_XFORM_AEAD,
+            algo: xform.algo.raw(),
+        };
+        // SAFETY: ffi
+        let capability = unsafe { rte_cryptodev_sym_capability_get(self.id(), ptr::addr_of!(idx)) };
+        if capability.is_null() {
+            return false;
+        }
+        // SAFETY: the capability is checked
+        let ret = unsafe {
+            rte_cryptodev_sym_capability_check_aead(
+                capability,
+                key_len,
+                xform.digest_len,
+                xform.aad_len,
+                xform.iv_len,
+            )
+        };
+        ret == 0
+    }
+
+    /// Get the mempools of sessions, which are created on the first call.
+    fn session_pools(&self) -> Result<SessionPools> {
+        let mut sessions = self.dev.sessions.lock().map_err(Error::from)?;
+        if let Some(pools) = *sessions {
+            return Ok(pools);
+        }
+        let socket_id = lcore::socket_id();
+        let name = CString::new(format!("crypto_sess_{}", self.id())).map_err(Error::from)?;
+        // SAFETY: pointer checked later
+        let headers = unsafe {
+            rte_cryptodev_sym_session_pool_create(
+                name.as_ptr(),
+                SESSION_POOL_SIZE,
+                0,
+                0,
+                0,
+                socket_id,
+            )
+        };
+        let headers = NonNull::new(headers)
+            .ok_or_else(|| Error::from_errno("rte_cryptodev_sym_session_pool_create"))?;
+        let private = CString::new(format!("crypto_sess_priv_{}", self.id()))
+            .map_err(Error::from)
+            .and_then(|name| {
+                // SAFETY: ffi
+                let elt_size = unsafe { rte_cryptodev_sym_get_private_session_size(self.id()) };
+                // SAFETY: pointer checked later
+                let private = unsafe {
+                    rte_mempool_create(
+                        name.as_ptr(),
+                        SESSION_POOL_SIZE,
+                        elt_size,
+                        0,
+                        0,
+                        None,
+                        ptr::null_mut(),
+                        None,
+                        ptr::null_mut(),
+                        socket_id,
+                        0,
+                    )
+                };
+                NonNull::new(private).ok_or_else(|| Error::from_errno("rte_mempool_create"))
+            });
+        let private = match private {
+            Ok(private) => private,
+            Err(e) => {
+                // SAFETY: no session is created from the mempool
+                unsafe { rte_mempool_free(headers.as_ptr()) };
+                return Err(e);
+            }
+        };
+        let pools = SessionPools { headers, private };
+        *sessions = Some(pools);
+        Ok(pools)
+    }
+
+    /// Create a session with `xform`, which is shared by the crypto operations working with it.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: the IV or the AAD is longer than 16 bytes.
+    /// - `Error::NotSupported`: the device doesn't support `xform`.
+    /// - `Error::NoMem`: too many sessions are created.
+    #[inline]
+    pub fn aead_session(&self, xform: &AeadXform) -> Result<Session> {
+        if usize::from(xform.iv_len) > MAX_IV_LEN || usize::from(xform.aad_len) > MAX_AAD_LEN {
+            return Err(Error::InvalidArg);
+        }
+        if !self.supports(xform) {
+            return Err(Error::NotSupported);
+        }
+        let pools = self.session_pools()?;
+        #[allow(clippy::cast_possible_truncation)] // checked by `supports`, and less than 256
+        let mut raw = rte_crypto_sym_xform {
+            next: ptr::null_mut(),
+            xform_type: RTE_CRYPTO_SYM_XFORM_AEAD,
+            aead: rte_crypto_aead_xform {
+                op: xform.op.raw(),
+                algo: xform.algo.raw(),
+                key: rte_crypto_key {
+                    data: xform.key.as_ptr(),
+                    length: xform.key.len() as u16,
+                },
+                iv: rte_crypto_iv {
+                    offset: IV_OFFSET as u16,
+                    length: xform.iv_len,
+                },
+                digest_length: xform.digest_len,
+                aad_length: xform.aad_len,
+            },
+        };
+        // SAFETY: pointer checked later
+        let sess = unsafe { rte_cryptodev_sym_session_create(pools.headers.as_ptr()) };
+        let sess = NonNull::new(sess)
+            .ok_or_else(|| Error::from_errno("rte_cryptodev_sym_session_create"))?;
+        // SAFETY: the session is created, and the key is copied by the driver
+        let errno = unsafe {
+            rte_cryptodev_sym_session_init(
+                self.id(),
+                sess.as_ptr(),
+                ptr::addr_of_mut!(raw),
+                pools.private.as_ptr(),
+            )
+        };
+        if let Err(e) = Error::from_ret(errno, "rte_cryptodev_sym_session_init") {
+            // SAFETY: the session isn't initialized on any device
+            let _errno = unsafe { rte_cryptodev_sym_session_free(sess.as_ptr()) };
+            return Err(e);
+        }
+        Ok(Session {
+            inner: Arc::new(SessionHandle {
+                dev: Arc::clone(&self.dev),
+                sess,
+                op: xform.op,
+                iv_len: xform.iv_len,
+                digest_len: xform.digest_len,
+                aad_len: xform.aad_len,
+            }),
+        })
+    }
+}
+
+/// A created session, which is freed once no `Session` or crypto operation refers to it.
+#[derive(Debug)]
+struct SessionHandle {
+    /// The device the session is created on.
+    dev: Arc<DevHandle>,
+    /// A pointer to the session.
+    sess: NonNull<c_void>,
+    /// Whether the session encrypts or decrypts.
+    op: AeadOp,
+    /// Length of the IV.
+    iv_len: u16,
+    /// Length of the digest.
+    digest_len: u16,
+    /// Length of the AAD.
+    aad_len: u16,
+}
+
+// SAFETY: the session is only read by the driver after it's created
+#[allow(unsafe_code)]
+unsafe impl Send for SessionHandle {}
+
+// SAFETY: the session is only read by the driver after it's created
+#[allow(unsafe_code)]
+unsafe impl Sync for SessionHandle {}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        // SAFETY: no operation works with the session
+        #[allow(unsafe_code)]
+        let errno = unsafe { rte_cryptodev_sym_session_clear(self.dev.dev_id, self.sess.as_ptr()) };
+        if let Err(e) = Error::from_ret(errno, "rte_cryptodev_sym_session_clear") {
+            // The header can't be freed while the session is initialized on the device.
+            error!("Failed to clear crypto session: {e}");
+            return;
+        }
+        // SAFETY: the session is only initialized on `dev`, where it's cleared
+        #[allow(unsafe_code)]
+        let errno = unsafe { rte_cryptodev_sym_session_free(self.sess.as_ptr()) };
+        if let Err(e) = Error::from_ret(errno, "rte_cryptodev_sym_session_free") {
+            error!("Failed to free crypto session: {e}");
+        }
+    }
+}
+
+/// A session holding the algorithm and the key of crypto operations.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// The created session.
+    inner: Arc<SessionHandle>,
+}
+
+impl Session {
+    /// Whether the session encrypts or decrypts.
+    #[inline]
+    #[must_use]
+    pub fn op(&self) -> AeadOp {
+        self.inner.op
+    }
+
+    /// Length of the IV.
+    #[inline]
+    #[must_use]
+    pub fn iv_len(&self) -> u16 {
+        self.inner.iv_len
+    }
+
+    /// Length of the digest.
+    #[inline]
+    #[must_use]
+    pub fn digest_len(&self) -> u16 {
+        self.inner.digest_len
+    }
+
+    /// Length of the AAD.
+    #[inline]
+    #[must_use]
+    pub fn aad_len(&self) -> u16 {
+        self.inner.aad_len
+    }
+}
+
+/// The result of a crypto operation.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpStatus {
+    /// Processed successfully.
+    Success,
+    /// Not processed yet.
+    NotProcessed,
+    /// The digest doesn't match, i.e. the packet is forged or corrupted.
+    AuthFailed,
+    /// The session is invalid.
+    InvalidSession,
+    /// The arguments are invalid.
+    InvalidArgs,
+    /// Failed for other reasons.
+    Error,
+}
+
+/// A crypto operation working on a packet with a session.
+///
+/// The packet is encrypted or decrypted in place. Both the data and the digest should be in the
+/// first segment of the packet.
+#[derive(Debug)]
+pub struct CryptoOp {
+    /// A pointer to `rte_crypto_op`, followed by the symmetric operation and the private area.
+    op: NonNull<rte_crypto_op>,
+}
+
+// SAFETY: the operation, its packet and its session can be accessed from any thread
+#[allow(unsafe_code)]
+unsafe impl Send for CryptoOp {}
+
+#[allow(unsafe_code)]
+impl CryptoOp {
+    /// Get the symmetric operation.
+    fn sym(&self) -> *mut rte_crypto_sym_op {
+        // SAFETY: the symmetric operation follows the header
+        unsafe { self.op.as_ptr().add(1).cast() }
+    }
+
+    /// Get the private area at `offset` from the start of the operation.
+    fn area(&self, offset: usize) -> *mut u8 {
+        // SAFETY: the private area is allocated along with the operation
+        unsafe { self.op.as_ptr().cast::<u8>().add(offset) }
+    }
+
+    /// The session the operation works with, which is held by the private area.
+    fn session(&self) -> *const SessionHandle {
+        // SAFETY: the private area holds the session
+        unsafe { ptr::read_unaligned(self.area(SESSION_OFFSET).cast::<*const SessionHandle>()) }
+    }
+
+    /// The result of the operation.
+    #[inline]
+    #[must_use]
+    pub fn status(&self) -> OpStatus {
+        // SAFETY: the operation is initialized on allocation
+        match unsafe { (*self.op.as_ptr()).status } {
+            RTE_CRYPTO_OP_STATUS_SUCCESS => OpStatus::Success,
+            RTE_CRYPTO_OP_STATUS_NOT_PROCESSED => OpStatus::NotProcessed,
+            RTE_CRYPTO_OP_STATUS_AUTH_FAILED => OpStatus::AuthFailed,
+            RTE_CRYPTO_OP_STATUS_INVALID_SESSION => OpStatus::InvalidSession,
+            RTE_CRYPTO_OP_STATUS_INVALID_ARGS => OpStatus::InvalidArgs,
+            _ => OpStatus::Error,
+        }
+    }
+
+    /// Encrypt or decrypt `len` bytes at `offset` of the packet, with the digest at
+    /// `digest_offset`, `aad` and `iv`, where the offsets are from the start of the packet data.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: the lengths of `aad` or `iv` don't match the session.
+    /// - `Error::OutOfRange`: the data or the digest is beyond the first segment of the packet.
+    #[inline]
+    pub fn aead(
+        &mut self,
+        offset: u32,
+        len: u32,
+        digest_offset: u32,
+        aad: &[u8],
+        iv: &[u8],
+    ) -> Result<()> {
+        // SAFETY: the session is held until the operation is freed
+        let session = unsafe { &*self.session() };
+        if aad.len() != usize::from(session.aad_len) || iv.len() != usize::from(session.iv_len) {
+            return Err(Error::InvalidArg);
+        }
+        let sym = self.sym();
+        // SAFETY: the packet is attached on allocation
+        let m = unsafe { (*sym).m_src };
+        // SAFETY: the packet is valid
+        let (data_len, data_off, buf_addr, buf_iova) =
+            unsafe { ((*m).data_len, (*m).data_off, (*m).buf_addr, (*m).buf_iova) };
+        let data_end = offset.checked_add(len).ok_or(Error::OutOfRange)?;
+        let digest_end = digest_offset
+            .checked_add(u32::from(session.digest_len))
+            .ok_or(Error::OutOfRange)?;
+        if data_end > u32::from(data_len) || digest_end > u32::from(data_len) {
+            return Err(Error::OutOfRange);
+        }
+        let digest_offset = u64::from(data_off).wrapping_add(u64::from(digest_offset));
+        // SAFETY: the areas are checked above, and the operation is initialized on allocation
+        unsafe {
+            ptr::copy_nonoverlapping(iv.as_ptr(), self.area(IV_OFFSET), iv.len());
+            ptr::copy_nonoverlapping(aad.as_ptr(), self.area(AAD_OFFSET), aad.len());
+            let phys_addr = (*self.op.as_ptr()).phys_addr;
+            (*sym).data_offset = offset;
+            (*sym).data_length = len;
+            #[allow(clippy::cast_possible_truncation)] // within the first segment
+            let digest_data = buf_addr.cast::<u8>().add(digest_offset as usize);
+            (*sym).digest_data = digest_data;
+            (*sym).digest_phys_addr = buf_iova.wrapping_add(digest_offset);
+            (*sym).aad_data = self.area(AAD_OFFSET);
+            (*sym).aad_phys_addr = phys_addr.wrapping_add(AAD_OFFSET as u64);
+        }
+        Ok(())
+    }
+
+    /// Take the packet the operation works on.
+    #[inline]
+    #[must_use]
+    pub fn into_mbuf(self) -> Option<Mbuf> {
+        let sym = self.sym();
+        // SAFETY: the packet is owned by the operation
+        let m = unsafe { mem::replace(&mut (*sym).m_src, ptr::null_mut()) };
+        Mbuf::new_with_ptr(m).ok()
+    }
+
+    /// Take the ownership of an operation dequeued from a device.
+    fn from_raw(op: *mut rte_crypto_op) -> Option<Self> {
+        NonNull::new(op).map(|op| Self { op })
+    }
+
+    /// Hand the ownership of the operation to a device.
+    fn into_raw(self) -> *mut rte_crypto_op {
+        ManuallyDrop::new(self).op.as_ptr()
+    }
+}
+
+#[allow(unsafe_code)]
+impl Drop for CryptoOp {
+    #[inline]
+    fn drop(&mut self) {
+        let sym = self.sym();
+        // SAFETY: the packet and the session are owned by the operation, which goes back to its
+        // mempool at last
+        unsafe {
+            drop(Mbuf::new_with_ptr((*sym).m_src));
+            let session = self.session();
+            if !session.is_null() {
+                drop(Arc::from_raw(session));
+            }
+            rte_mempool_put((*self.op.as_ptr()).mempool, self.op.as_ptr().cast());
+        }
+    }
+}
+
+/// A queue pair of a crypto device, to which crypto operations are enqueued and from which they
+/// are dequeued once processed.
+#[derive(Debug)]
+pub struct QueuePair {
+    /// The device the queue pair belongs to.
+    dev: Arc<DevHandle>,
+    /// `qp_id` of the queue pair.
+    qp_id: u16,
+    /// The mempool of crypto operations.
+    ops: NonNull<rte_mempool>,
+    /// Number of operations enqueued and not dequeued yet.
+    in_flight: usize,
+}
+
+// SAFETY: the queue pair is only accessed through `&mut QueuePair`, and its mempool from any
+// thread
+#[allow(unsafe_code)]
+unsafe impl Send for QueuePair {}
+
+#[allow(unsafe_code)]
+impl QueuePair {
+    /// The `qp_id` of the queue pair.
+    #[inline]
+    #[must_use]
+    pub fn id(&self) -> u16 {
+        self.qp_id
+    }
+
+    /// Number of operations enqueued and not dequeued yet.
+    #[inline]
+    #[must_use]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Allocate a crypto operation working on `m` with `session`, which should be created on the
+    /// same device.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `session` is created on another device.
+    /// - `Error::NoBuf`: no operation left in the mempool.
+    #[inline]
+    pub fn op(&self, session: &Session, m: Mbuf) -> Result<CryptoOp> {
+        if !Arc::ptr_eq(&session.inner.dev, &self.dev) {
+            return Err(Error::InvalidArg);
+        }
+        let mut raw = ptr::null_mut::<c_void>();
+        // SAFETY: the mempool is created along with the queue pair
+        let errno = unsafe { rte_mempool_get(self.ops.as_ptr(), ptr::addr_of_mut!(raw)) };
+        if errno != 0 {
+            return Err(Error::NoBuf);
+        }
+        let op = raw.cast::<rte_crypto_op>();
+        let m = ManuallyDrop::new(m);
+        // SAFETY: the operation is allocated from the mempool, whose objects are big enough for
+        // the symmetric operation and the private area
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // type of 1
+        unsafe {
+            (*op).op_type = RTE_CRYPTO_OP_TYPE_SYMMETRIC as u8;
+            (*op).status = RTE_CRYPTO_OP_STATUS_NOT_PROCESSED;
+            (*op).sess_type = RTE_CRYPTO_OP_WITH_SESSION;
+            let sym_op = op.add(1).cast::<rte_crypto_sym_op>();
+            ptr::write_bytes(sym_op, 0, 1);
+            (*sym_op).m_src = m.as_ptr();
+            (*sym_op).session = session.inner.sess.as_ptr();
+            let area = raw.cast::<u8>().add(SESSION_OFFSET);
+            ptr::write_unaligned(
+                area.cast::<*const SessionHandle>(),
+                Arc::into_raw(Arc::clone(&session.inner)),
+            );
+        }
+        Ok(CryptoOp {
+            // SAFETY: checked by `rte_mempool_get`
+            op: unsafe { NonNull::new_unchecked(op) },
+        })
+    }
+
+    /// Fast-path functions of the device.
+    fn fp_ops(&self) -> Option<&'static rte_crypto_fp_ops> {
+        // SAFETY: the table is initialized by EAL and lives as long as the process
+        unsafe { rte_crypto_fp_ops.get(usize::from(self.dev.dev_id)) }
+    }
+
+    /// Enqueue as many operations from the front of `ops` as the device accepts, and remove them
+    /// from `ops`. Returns the number of operations enqueued.
+    ///
+    /// Callbacks registered on the queue pair are not called, since none is registered by this
+    /// crate.
+    #[inline]
+    pub fn enqueue(&mut self, ops: &mut Vec<CryptoOp>) -> usize {
+        let Some(fp_ops) = self.fp_ops() else {
+            return 0;
+        };
+        let Some(enqueue_burst) = fp_ops.enqueue_burst else {
+            return 0;
+        };
+        let n = u16::try_from(ops.len()).unwrap_or(u16::MAX);
+        let mut raw: Vec<_> = ops
+            .drain(..usize::from(n))
+            .map(CryptoOp::into_raw)
+            .collect();
+        // SAFETY: the queue pair is set up, and `raw` holds `n` operations
+        let enqueued = unsafe {
+            let qp = *fp_ops.qp.data.add(usize::from(self.qp_id));
+            enqueue_burst(qp, raw.as_mut_ptr(), n)
+        };
+        // Operations refused are given back in order.
+        let mut refused: Vec<_> = raw
+            .drain(usize::from(enqueued)..)
+            .filter_map(CryptoOp::from_raw)
+            .collect();
+        refused.append(ops);
+        *ops = refused;
+        self.in_flight = self.in_flight.saturating_add(usize::from(enqueued));
+        usize::from(enqueued)
+    }
+
+    /// Dequeue up to `max` processed operations to the back of `ops`, without waiting. Returns
+    /// the number of operations dequeued.
+    #[inline]
+    pub fn dequeue(&mut self, ops: &mut Vec<CryptoOp>, max: usize) -> usize {
+        let Some(fp_ops) = self.fp_ops() else {
+            return 0;
+        };
+        let Some(dequeue_burst) = fp_ops.dequeue_burst else {
+            return 0;
+        };
+        let n = u16::try_from(max).unwrap_or(u16::MAX);
+        let mut raw = vec![ptr::null_mut(); usize::from(n)];
+        // SAFETY: the queue pair is set up, and `raw` holds `n` pointers
+        let dequeued = unsafe {
+            let qp = *fp_ops.qp.data.add(usize::from(self.qp_id));
+            dequeue_burst(qp, raw.as_mut_ptr(), n)
+        };
+        raw.truncate(usize::from(dequeued));
+        ops.extend(raw.into_iter().filter_map(CryptoOp::from_raw));
+        self.in_flight = self.in_flight.saturating_sub(usize::from(dequeued));
+        usize::from(dequeued)
+    }
+
+    /// Enqueue all of `ops`, and wait until all operations in flight are processed, for
+    /// `PROCESS_TIMEOUT` at most. Returns the processed operations in the order they are dequeued,
+    /// which is the order they are enqueued for most devices.
+    ///
+    /// On timeout, the operations not enqueued are returned at the back with
+    /// `OpStatus::NotProcessed`, and those in flight are left to be dequeued later.
+    #[inline]
+    pub fn process(&mut self, mut ops: Vec<CryptoOp>) -> Vec<CryptoOp> {
+        let mut done = Vec::with_capacity(ops.len().saturating_add(self.in_flight));
+        let start = Instant::now();
+        while !ops.is_empty() || self.in_flight > 0 {
+            let enqueued = self.enqueue(&mut ops);
+            let dequeued = self.dequeue(&mut done, PROCESS_BURST);
+            if enqueued == 0 && dequeued == 0 {
+                if start.elapsed() >= PROCESS_TIMEOUT {
+                    warn!(
+                        "Crypto queue pair {} timed out with {} operations in flight",
+                        self.qp_id, self.in_flight
+                    );
+                    done.append(&mut ops);
+                    break;
+                }
+                hint::spin_loop();
+            }
+        }
+        done
+    }
+}
+
+impl Drop for QueuePair {
+    #[inline]
+    fn drop(&mut self) {
+        // Operations in flight are taken back before their mempool is freed.
+        drop(self.process(Vec::new()));
+        if self.in_flight > 0 {
+            // The mempool is leaked, since the device may still write to the operations.
+            error!(
+                "Crypto queue pair {} dropped with {} operations in flight",
+                self.qp_id, self.in_flight
+            );
+        } else {
+            // SAFETY: all operations are back in the mempool
+            #[allow(unsafe_code)]
+            unsafe {
+                rte_mempool_free(self.ops.as_ptr());
+            }
+        }
+        let _removed = self
+            .dev
+            .qps
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.qp_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        rte_crypto_aead_xform, rte_crypto_op, rte_crypto_sym_op, rte_crypto_sym_xform,
+        rte_cryptodev_info, rte_cryptodev_qp_conf, AeadAlgo, AeadOp, AeadXform, CryptoDev,
+        IV_OFFSET, OP_PRIV_SIZE,
+    };
+    use crate::{test_utils, Error};
+    use std::mem::size_of;
+
+    #[test]
+    fn test_layout() {
+        // Sizes of the mirrored structures.
+        assert_eq!(size_of::<rte_cryptodev_info>(), 56);
+        assert_eq!(size_of::<rte_cryptodev_qp_conf>(), 24);
+        assert_eq!(size_of::<rte_crypto_op>(), 24);
+        assert_eq!(size_of::<rte_crypto_sym_op>(), 64);
+        assert_eq!(size_of::<rte_crypto_aead_xform>(), 32);
+        assert_eq!(size_of::<rte_crypto_sym_xform>(), 48);
+        assert_eq!(IV_OFFSET, 88);
+        assert_eq!(OP_PRIV_SIZE, 40);
+    }
+
+    #[test]
+    fn test_xform() {
+        let xform = AeadXform::new(AeadOp::Decrypt, AeadAlgo::AesGcm, &[1; 16]);
+        assert_eq!((xform.iv_len, xform.digest_len, xform.aad_len), (12, 16, 0));
+        let xform = xform.aad_len(8).iv_len(16).digest_len(8);
+        assert_eq!((xform.iv_len, xform.digest_len, xform.aad_len), (16, 8, 8));
+        assert_eq!(xform.key, [1; 16]);
+    }
+
+    #[test]
+    fn test_open() {
+        test_utils::dpdk_setup();
+        assert_eq!(CryptoDev::open("crypto_none").unwrap_err(), Error::NoDev);
+    }
+}
@@ -0,0 +1,159 @@
+//! Packet dispatch hooks.
+//!
+//! Handlers are installed for an Ether type, optionally along with an IP protocol number, and
+//! are called by the agent threads on matching frames before the built-in protocols. A handler
+//! takes the `Mbuf` and gives it back if it's not consumed, in which case the frame goes on
+//! through the stack.
+//!
+//! IP handlers receive packets starting with the IP header, after IP reassembly and with the
+//! IPv6 extension headers before the upper-layer header stripped. Other handlers receive frames
+//! with the Ethernet header stripped.
+//!
+//! ```no_run
+//! use async_dpdk::dispatch;
+//!
+//! const IP_NEXT_PROTO_SCTP: u8 = 132;
+//!
+//! dispatch::register(0x0800, Some(IP_NEXT_PROTO_SCTP), |m| {
+//!     // Handle the SCTP packet, whose IPv4 header is at the front of `m`.
+//!     drop(m);
+//!     None
+//! })
+//! .unwrap();
+//! // ...
+//! dispatch::unregister(0x0800, Some(IP_NEXT_PROTO_SCTP)).unwrap();
+//! ```
+
+use crate::{mbuf::Mbuf, Error, Result};
+use lazy_static::lazy_static;
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fmt::{self, Debug},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// A handler called on matching packets, which gives the `Mbuf` back if it's not consumed.
+type HandlerFn = dyn Fn(Mbuf) -> Option<Mbuf> + Send + Sync;
+
+/// An installed handler.
+#[derive(Clone)]
+struct Handler(Arc<HandlerFn>);
+
+impl Debug for Handler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Handler").finish()
+    }
+}
+
+lazy_static! {
+    /// Installed handlers, keyed by (Ether type, IP protocol number).
+    static ref HANDLERS: RwLock<BTreeMap<(u16, Option<u8>), Handler>> =
+        RwLock::new(BTreeMap::new());
+}
+
+/// The number of installed handlers, checked by the agent threads before looking up `HANDLERS`.
+static HANDLER_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// Install `handler` on frames with `ether_type`, or IP packets with `ip_proto` in them if it's
+/// set. `ether_type` is in host byte order.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::Exists`: a handler is already installed on them.
+#[inline]
+pub fn register<F>(ether_type: u16, ip_proto: Option<u8>, handler: F) -> Result<()>
+where
+    F: Fn(Mbuf) -> Option<Mbuf> + Send + Sync + 'static,
+{
+    match HANDLERS
+        .write()
+        .map_err(Error::from)?
+        .entry((ether_type, ip_proto))
+    {
+        Entry::Occupied(_) => Err(Error::Exists),
+        Entry::Vacant(entry) => {
+            let _handler = entry.insert(Handler(Arc::new(handler)));
+            let _prev = HANDLER_NUM.fetch_add(1, Ordering::AcqRel);
+            Ok(())
+        }
+    }
+}
+
+/// Remove the handler installed by `register`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NotExist`: no handler is installed on them.
+#[inline]
+pub fn unregister(ether_type: u16, ip_proto: Option<u8>) -> Result<()> {
+    if HANDLERS
+        .write()
+        .map_err(Error::from)?
+        .remove(&(ether_type, ip_proto))
+        .is_none()
+    {
+        return Err(Error::NotExist);
+    }
+    let _prev = HANDLER_NUM.fetch_sub(1, Ordering::AcqRel);
+    Ok(())
+}
+
+/// Called by the agent thread, pass a packet to the handler installed on it if any.
+///
+/// The packet is given back if no handler is installed, or the handler doesn't consume it.
+pub(crate) fn dispatch(ether_type: u16, ip_proto: Option<u8>, m: Mbuf) -> Option<Mbuf> {
+    if HANDLER_NUM.load(Ordering::Acquire) == 0 {
+        return Some(m);
+    }
+    // The handler is called without holding the lock, so that it can install handlers.
+    let handler = match HANDLERS.read() {
+        Ok(handlers) => handlers.get(&(ether_type, ip_proto)).cloned(),
+        Err(_) => None,
+    };
+    match handler {
+        Some(Handler(handler)) => handler(m),
+        None => Some(m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dispatch, register, unregister};
+    use crate::mbuf::Mbuf;
+    use crate::mempool::{Mempool, PktMempool};
+    use crate::{test_utils, Error};
+
+    #[test]
+    fn test() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("test_dispatch", 10).unwrap();
+
+        register(0x88b5, None, |m| (m.data_len() != 4).then_some(m)).unwrap();
+        assert!(matches!(
+            register(0x88b5, None, |m| Some(m)).unwrap_err(),
+            Error::Exists
+        ));
+        let mut m = Mbuf::new(&mp).unwrap();
+        m.append(4).unwrap().copy_from_slice(&[1, 2, 3, 4]);
+        assert!(dispatch(0x88b5, None, m).is_none());
+        let m = Mbuf::new(&mp).unwrap();
+        assert!(dispatch(0x88b5, None, m).is_some());
+        let m = Mbuf::new(&mp).unwrap();
+        assert!(dispatch(0x0800, Some(132), m).is_some());
+
+        unregister(0x88b5, None).unwrap();
+        assert!(matches!(
+            unregister(0x88b5, None).unwrap_err(),
+            Error::NotExist
+        ));
+    }
+}
@@ -0,0 +1,391 @@
+//! Packet dump hooks.
+//!
+//! Hooks observe the metadata of frames received from and sent to the devices, e.g. their
+//! lengths, addresses and protocols, without touching the frames. They are lighter than pcapng
+//! captures, and are called by the agent threads on frames matching their filters, so they
+//! should return quickly.
+//!
+//! ```no_run
+//! use async_dpdk::dump::{self, Filter};
+//!
+//! const IP_NEXT_PROTO_UDP: u8 = 17;
+//!
+//! let id = dump::register(Filter::new().protocol(IP_NEXT_PROTO_UDP), |info| {
+//!     println!("{info:?}");
+//! })
+//! .unwrap();
+//! // ...
+//! dump::unregister(id).unwrap();
+//! ```
+
+use crate::{mbuf::Mbuf, Error, Result};
+use lazy_static::lazy_static;
+use log::Level;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+};
+
+/// Target of the log records written by hooks installed with `register_log`.
+const LOG_TARGET: &str = "async_dpdk::dump";
+
+/// Direction of a frame.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received from a device.
+    Inbound,
+    /// Sent to a device.
+    Outbound,
+}
+
+/// Metadata of a frame observed by a hook.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketInfo {
+    /// The port that the frame is received from or sent to.
+    pub port_id: u16,
+    /// Whether the frame is received or sent.
+    pub direction: Direction,
+    /// Length of the frame.
+    pub len: usize,
+    /// Number of segments holding the frame.
+    pub segs: u32,
+    /// Packet type, i.e. the `RTE_PTYPE_*` flags set by the device or the stack.
+    pub packet_type: u32,
+    /// Ether type in host byte order.
+    pub ether_type: u16,
+    /// Source IP address, if it's an IP packet.
+    pub src_ip: Option<IpAddr>,
+    /// Destination IP address, if it's an IP packet.
+    pub dst_ip: Option<IpAddr>,
+    /// IP protocol number, if it's an IP packet.
+    pub protocol: Option<u8>,
+    /// Source port, if it's the first fragment of a UDP or TCP packet.
+    pub src_port: Option<u16>,
+    /// Destination port, if it's the first fragment of a UDP or TCP packet.
+    pub dst_port: Option<u16>,
+}
+
+impl PacketInfo {
+    /// Parse the metadata of a frame, whose first segment is `frame`.
+    ///
+    /// IPv6 extension headers are not skipped, so `protocol` is the first next header.
+    pub(crate) fn parse(
+        port_id: u16,
+        direction: Direction,
+        frame: &[u8],
+        (len, segs, packet_type): (usize, u32, u32),
+    ) -> Self {
+        let mut info = Self {
+            port_id,
+            direction,
+            len,
+            segs,
+            packet_type,
+            ether_type: 0,
+            src_ip: None,
+            dst_ip: None,
+            protocol: None,
+            src_port: None,
+            dst_port: None,
+        };
+        let _parsed = info.parse_headers(frame);
+        info
+    }
+
+    /// Fill in the fields parsed from the headers of `frame`, as many as possible.
+    fn parse_headers(&mut self, frame: &[u8]) -> Option<()> {
+        self.ether_type = u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?);
+        let ip = frame.get(14..)?;
+        let l4 = match self.ether_type {
+            0x0800 => {
+                let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+                let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+                self.src_ip = Some(src.into());
+                self.dst_ip = Some(dst.into());
+                self.protocol = Some(*ip.get(9)?);
+                let frag_off = u16::from_be_bytes(ip.get(6..8)?.try_into().ok()?) & 0x1fff;
+                if frag_off != 0 {
+                    return None;
+                }
+                ip.get(usize::from(ip.first()? & 0xf).wrapping_mul(4)..)?
+            }
+            0x86dd => {
+                let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+                let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+                self.src_ip = Some(src.into());
+                self.dst_ip = Some(dst.into());
+                self.protocol = Some(*ip.get(6)?);
+                ip.get(40..)?
+            }
+            _ => return None,
+        };
+        // UDP and TCP
+        if !matches!(self.protocol, Some(17 | 6)) {
+            return None;
+        }
+        self.src_port = Some(u16::from_be_bytes(l4.get(0..2)?.try_into().ok()?));
+        self.dst_port = Some(u16::from_be_bytes(l4.get(2..4)?.try_into().ok()?));
+        Some(())
+    }
+}
+
+/// Which frames a hook observes. Unset conditions match any frame.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// The port that frames are received from or sent to.
+    pub port_id: Option<u16>,
+    /// Direction of frames.
+    pub direction: Option<Direction>,
+    /// Source or destination IP address of packets.
+    pub ip: Option<IpAddr>,
+    /// IP protocol number of packets.
+    pub protocol: Option<u8>,
+    /// Source or destination UDP or TCP port of packets.
+    pub l4_port: Option<u16>,
+}
+
+impl Filter {
+    /// Create a filter matching any frame.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match frames received from or sent to `port_id`.
+    #[inline]
+    #[must_use]
+    pub fn port_id(mut self, port_id: u16) -> Self {
+        self.port_id = Some(port_id);
+        self
+    }
+
+    /// Match frames in `direction`.
+    #[inline]
+    #[must_use]
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    /// Match packets from or to `ip`.
+    #[inline]
+    #[must_use]
+    pub fn ip(mut self, ip: IpAddr) -> Self {
+        self.ip = Some(ip);
+        self
+    }
+
+    /// Match packets of IP protocol `protocol`.
+    #[inline]
+    #[must_use]
+    pub fn protocol(mut self, protocol: u8) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Match UDP or TCP packets from or to `l4_port`.
+    #[inline]
+    #[must_use]
+    pub fn l4_port(mut self, l4_port: u16) -> Self {
+        self.l4_port = Some(l4_port);
+        self
+    }
+
+    /// Whether a frame with `info` matches.
+    #[inline]
+    #[must_use]
+    pub fn matches(&self, info: &PacketInfo) -> bool {
+        self.port_id.is_none_or(|port_id| port_id == info.port_id)
+            && self.direction.is_none_or(|dir| dir == info.direction)
+            && self
+                .ip
+                .is_none_or(|ip| info.src_ip == Some(ip) || info.dst_ip == Some(ip))
+            && self
+                .protocol
+                .is_none_or(|protocol| info.protocol == Some(protocol))
+            && self
+                .l4_port
+                .is_none_or(|port| info.src_port == Some(port) || info.dst_port == Some(port))
+    }
+}
+
+/// Identifier of an installed hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HookId(u64);
+
+/// A hook called on the metadata of matching frames.
+type HookFn = dyn Fn(&PacketInfo) + Send + Sync;
+
+/// Where a hook sends the metadata.
+enum Sink {
+    /// A user callback.
+    Callback(Box<HookFn>),
+    /// The log, at the given level.
+    Log(Level),
+}
+
+/// An installed hook.
+struct Hook {
+    /// Which frames it observes.
+    filter: Filter,
+    /// Where the metadata goes.
+    sink: Sink,
+}
+
+impl Debug for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hook")
+            .field("filter", &self.filter)
+            .finish_non_exhaustive()
+    }
+}
+
+lazy_static! {
+    /// Installed hooks.
+    static ref HOOKS: RwLock<BTreeMap<HookId, Arc<Hook>>> = RwLock::new(BTreeMap::new());
+}
+
+/// The number of installed hooks, checked by the agent threads before looking up `HOOKS`.
+static HOOK_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// The identifier of the next hook.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Install a hook.
+fn install(filter: Filter, sink: Sink) -> Result<HookId> {
+    let id = HookId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let _hook = HOOKS
+        .write()
+        .map_err(Error::from)?
+        .insert(id, Arc::new(Hook { filter, sink }));
+    let _prev = HOOK_NUM.fetch_add(1, Ordering::AcqRel);
+    Ok(id)
+}
+
+/// Install `hook` on frames matching `filter`, returning its identifier.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+#[inline]
+pub fn register<F>(filter: Filter, hook: F) -> Result<HookId>
+where
+    F: Fn(&PacketInfo) + Send + Sync + 'static,
+{
+    install(filter, Sink::Callback(Box::new(hook)))
+}
+
+/// Install a hook logging the metadata of frames matching `filter` at `level`, with the target
+/// `async_dpdk::dump`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+#[inline]
+pub fn register_log(filter: Filter, level: Level) -> Result<HookId> {
+    install(filter, Sink::Log(level))
+}
+
+/// Remove the hook installed by `register` or `register_log`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NotExist`: no hook is installed with `id`.
+#[inline]
+pub fn unregister(id: HookId) -> Result<()> {
+    if HOOKS.write().map_err(Error::from)?.remove(&id).is_none() {
+        return Err(Error::NotExist);
+    }
+    let _prev = HOOK_NUM.fetch_sub(1, Ordering::AcqRel);
+    Ok(())
+}
+
+/// Called by the agent threads, pass the metadata of a frame of `port_id` to the hooks it
+/// matches.
+pub(crate) fn observe(port_id: u16, direction: Direction, m: &Mbuf) {
+    if HOOK_NUM.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let info = PacketInfo::parse(
+        port_id,
+        direction,
+        m.data_slice(),
+        (m.pkt_len(), m.num_segs(), m.packet_type()),
+    );
+    // The hooks are called out of the lock, so that they may install or remove hooks.
+    let hooks: Vec<_> = HOOKS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .values()
+        .filter(|hook| hook.filter.matches(&info))
+        .map(Arc::clone)
+        .collect();
+    for hook in hooks {
+        match hook.sink {
+            Sink::Callback(ref f) => f(&info),
+            Sink::Log(level) => log::log!(target: LOG_TARGET, level, "{info:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Direction, Filter, PacketInfo};
+    use crate::test_utils::udp_frame as frame;
+
+    #[test]
+    fn test_parse() {
+        let frame = frame();
+        let info = PacketInfo::parse(0, Direction::Inbound, &frame, (frame.len(), 1, 0));
+        assert_eq!(info.ether_type, 0x0800);
+        assert_eq!(info.src_ip, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(info.dst_ip, Some("10.0.0.2".parse().unwrap()));
+        assert_eq!(info.protocol, Some(17));
+        assert_eq!((info.src_port, info.dst_port), (Some(1000), Some(2000)));
+
+        // Non-first fragments carry no UDP header.
+        let mut frag = frame.clone();
+        frag[21] = 1;
+        let info = PacketInfo::parse(0, Direction::Inbound, &frag, (frag.len(), 1, 0));
+        assert_eq!(info.protocol, Some(17));
+        assert_eq!(info.dst_port, None);
+
+        let info = PacketInfo::parse(0, Direction::Inbound, &frame[..20], (20, 1, 0));
+        assert_eq!(info.ether_type, 0x0800);
+        assert_eq!(info.src_ip, None);
+    }
+
+    #[test]
+    fn test_filter() {
+        let frame = frame();
+        let info = PacketInfo::parse(1, Direction::Outbound, &frame, (frame.len(), 1, 0));
+        assert!(Filter::new().matches(&info));
+        assert!(Filter::new()
+            .port_id(1)
+            .direction(Direction::Outbound)
+            .ip("10.0.0.2".parse().unwrap())
+            .protocol(17)
+            .l4_port(1000)
+            .matches(&info));
+        assert!(!Filter::new().port_id(0).matches(&info));
+        assert!(!Filter::new().direction(Direction::Inbound).matches(&info));
+        assert!(!Filter::new().protocol(6).matches(&info));
+        assert!(!Filter::new().l4_port(3000).matches(&info));
+    }
+}
@@ -7,6 +7,8 @@
 //!
 //! This module deals with the configuring and launching of EAL. Users should first enter
 //! EAL environment before dealing with any DPDK provided features.
+//! The environment can be left with `shutdown` and entered again, while `cleanup` releases EAL
+//! for good.
 //!
 //! # Examples
 //!
@@ -24,20 +26,50 @@
 //!     .unwrap();
 //! ```
 
-use crate::{net_dev, Error, Result};
+use crate::{
+    agent::{self, AgentConf},
+    net_dev::{self, DevConfig, IfAddr, RssConfig, RxExec},
+    proto::udp,
+    trace::{error, warn},
+    Error, Result,
+};
 use dpdk_sys::{
     rte_eal_cleanup, rte_eal_get_runtime_dir, rte_eal_has_hugepages, rte_eal_has_pci, rte_eal_init,
+    rte_eal_process_type, rte_proc_type_t_RTE_PROC_SECONDARY,
 };
 use lazy_static::lazy_static;
-use log::error;
+use std::collections::BTreeMap;
 use std::ffi::CString;
-use std::net::IpAddr;
+use std::fmt;
 use std::str::FromStr;
-use std::sync::{Arc, RwLock};
-use std::{os::raw::c_char, path::PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use std::{
+    os::raw::c_char,
+    path::{Path, PathBuf},
+};
 
 lazy_static! {
     static ref CONTEXT: RwLock<Option<Arc<Eal>>> = RwLock::new(None);
+    /// Arguments of the devices removed by `shutdown`, which are probed again on the next
+    /// `enter`.
+    static ref REMOVED_DEVICES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+/// Whether `rte_eal_init` has succeeded. DPDK allows it to be called only once in a process.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Whether `rte_eal_cleanup` has been called, after which EAL can't be used any more.
+static CLEANED_UP: AtomicBool = AtomicBool::new(false);
+
+/// Join `sizes` with commas, e.g. the megabytes on each NUMA socket.
+fn join(sizes: &[u32]) -> String {
+    sizes
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
 }
 
 /// Create a new `CString`.
@@ -55,23 +87,61 @@ macro_rules! cstring {
 #[derive(Debug)]
 pub struct Eal {}
 
-#[allow(unsafe_code)]
-unsafe impl Sync for Eal {}
+/// Leave the environment entered by `Config::enter`, after which it can be entered again.
+///
+/// All agents are stopped, and all probed devices are stopped and removed along with the routes
+/// through them. EAL itself stays initialized since DPDK can't initialize it twice in a process,
+/// so the next `Config::enter` ignores its EAL arguments, probes the removed devices again and
+/// applies the other configurations.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NotStart`: the environment is not entered.
+/// - Unable to stop or remove a device.
+#[inline]
+pub fn shutdown() -> Result<()> {
+    let mut context = CONTEXT.write().map_err(Error::from)?;
+    if context.is_none() {
+        return Err(Error::NotStart);
+    }
+    let devargs = net_dev::device_shutdown()?;
+    REMOVED_DEVICES.lock().map_err(Error::from)?.extend(devargs);
+    *context = None;
+    Ok(())
+}
 
-impl Drop for Eal {
-    #[inline]
-    fn drop(&mut self) {
-        // Close all devices
-        #[allow(clippy::unwrap_used)] // used in drop
-        net_dev::device_close().unwrap();
-        // SAFETY: ffi
-        #[allow(unsafe_code)]
-        let errno = unsafe { rte_eal_cleanup() };
-        if let Err(e) = Error::from_ret(errno) {
-            log::error!("Fatal error occurred in EAL cleanup: {e:?}");
-        }
-        Error::parse_err(errno);
+/// Leave the environment entered with `shutdown` if it's not done, and release all resources
+/// held by EAL with `rte_eal_cleanup`.
+///
+/// This is final: DPDK can't be used in the process afterwards, and `Config::enter` fails.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NotStart`: EAL is not initialized.
+/// - `Error::Already`: EAL has been cleaned up.
+/// - Unable to stop or remove a device.
+#[allow(unsafe_code)]
+#[inline]
+pub fn cleanup() -> Result<()> {
+    if !INITIALIZED.load(Ordering::Acquire) {
+        return Err(Error::NotStart);
+    }
+    match shutdown() {
+        Ok(()) | Err(Error::NotStart) => {}
+        Err(e) => return Err(e),
     }
+    if CLEANED_UP.swap(true, Ordering::AcqRel) {
+        return Err(Error::Already);
+    }
+    // SAFETY: ffi
+    let errno = unsafe { rte_eal_cleanup() };
+    Error::from_ret(errno, "rte_eal_cleanup")
 }
 
 /// Whether EAL is using hugepages.
@@ -92,6 +162,20 @@ pub fn has_pci() -> bool {
     unsafe { rte_eal_has_pci() != 0 }
 }
 
+/// Get the type of the current process, which is `ProcessType::Primary` or
+/// `ProcessType::Secondary` once EAL is initialized.
+#[allow(unsafe_code)]
+#[inline]
+#[must_use]
+pub fn process_type() -> ProcessType {
+    // SAFETY: ffi
+    if unsafe { rte_eal_process_type() } == rte_proc_type_t_RTE_PROC_SECONDARY {
+        ProcessType::Secondary
+    } else {
+        ProcessType::Primary
+    }
+}
+
 /// Get the runtime directory of DPDK.
 ///
 /// # Errors
@@ -116,9 +200,29 @@ pub struct Config {
     /// Args passed to `rte_eal_init`.
     args: Vec<CString>,
     /// IP addresses for each `EthDev`s.
-    addrs: Vec<IpAddr>,
+    addrs: Vec<IfAddr>,
     /// Max RX/TX queues number for each devices.
     max_queues: Option<u16>,
+    /// RSS configuration for each devices.
+    rss: RssConfig,
+    /// Queue and memory configuration of each devices, keyed by `port_id`.
+    dev_conf: BTreeMap<u16, DevConfig>,
+    /// Validate checksums of received UDP datagrams or not.
+    udp_rx_cksum: bool,
+    /// Deliver UDP datagrams to sockets on this host directly or not.
+    udp_loopback: bool,
+    /// MTU for each devices.
+    mtu: Option<u16>,
+    /// Where each rx queue is polled, keyed by (`port_id`, `queue_id`).
+    rx_exec: BTreeMap<(u16, u16), RxExec>,
+    /// Where rx queues not given in `rx_exec` are spread over.
+    rx_spread: Vec<RxExec>,
+    /// Sizes of the buffers and tables used by the agents.
+    agent: AgentConf,
+    /// How long rx queues stay idle before their agents sleep waiting for RX interrupts.
+    rx_intr: Option<Duration>,
+    /// Type of the process, if set.
+    process_type: Option<ProcessType>,
 }
 
 /// IOVA mode. The addresses used by hardwares, it should either be physical addresses or
@@ -132,8 +236,105 @@ pub enum IovaMode {
     VA,
 }
 
+/// Type of a process in the DPDK multi-process model.
+///
+/// The primary process initializes the shared memory, and creates the mempools and devices,
+/// while secondary processes attach to them, e.g. with `Mempool::lookup` and `EthDev::attach`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessType {
+    /// The process initializing the shared memory.
+    Primary,
+    /// A process attaching to the shared memory of the primary process.
+    Secondary,
+    /// A primary process if no primary process is running, or a secondary process otherwise.
+    Auto,
+}
+
+/// Address of a PCI device, which is written as `domain:bus:devid.function`, e.g. `0000:08:00.0`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciAddr {
+    /// Domain of the device.
+    pub domain: u16,
+    /// Bus of the device.
+    pub bus: u8,
+    /// Device id on the bus, up to 31.
+    pub devid: u8,
+    /// Function of the device, up to 7.
+    pub function: u8,
+}
+
+impl PciAddr {
+    /// Create a PCI address.
+    #[inline]
+    #[must_use]
+    pub const fn new(domain: u16, bus: u8, devid: u8, function: u8) -> Self {
+        Self {
+            domain,
+            bus,
+            devid,
+            function,
+        }
+    }
+}
+
+impl fmt::Display for PciAddr {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{:x}",
+            self.domain, self.bus, self.devid, self.function
+        )
+    }
+}
+
+/// Where a `Vdev::Pcap` device reads packets from or writes packets to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PcapIo {
+    /// A .pcap file.
+    File(PathBuf),
+    /// A kernel network interface, e.g. `eth0`.
+    Iface(String),
+}
+
+/// A ring backing a port created by `Vdev::RingNodes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RingNode {
+    /// Name of the port, which is also that of its rings.
+    pub name: String,
+    /// NUMA node the rings are allocated on.
+    pub numa_node: u32,
+    /// Attach to the rings created by another process rather than create them.
+    pub attach: bool,
+}
+
+impl RingNode {
+    /// Create the rings of port `name` on `numa_node`.
+    #[inline]
+    #[must_use]
+    pub fn new(name: &str, numa_node: u32) -> Self {
+        Self {
+            name: name.to_owned(),
+            numa_node,
+            attach: false,
+        }
+    }
+
+    /// Attach to the rings created by another process rather than create them.
+    #[inline]
+    #[must_use]
+    pub fn attach(mut self, attach: bool) -> Self {
+        self.attach = attach;
+        self
+    }
+}
+
 /// DPDK-supported virtual device.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum Vdev {
     /// `Null` device is a simple virtual driver mainly for testing. It always
@@ -145,22 +346,134 @@ pub enum Vdev {
     /// just frees all sent packets.
     Null(i32),
 
-    /// 'Pcap' device allows you to read/write from/to a .pcap file.
+    /// 'Pcap' device allows you to read/write from/to a .pcap file or a kernel interface.
     ///
     /// Each `Pcap` device needs an unique integer as its id.
     ///
     /// For more information, please refer to [`pcap_ring docs`].
-    Pcap(i32),
+    Pcap {
+        /// Unique id of the device.
+        id: i32,
+        /// Where received packets are read from.
+        rx: PcapIo,
+        /// Where sent packets are written to.
+        tx: PcapIo,
+    },
 
     /// `Ring` device uses an `rte_ring` to emulate an Ethernet port. On Rx it gets
     /// a packet from the ring. On Tx it puts the packet to the ring.
     ///
     /// Each `Ring` device needs an unique integer as its id.
     ///
+    /// As the same ring is used for Rx and Tx, the device receives what it sends itself. Ports
+    /// connected back to back are created by `harness::Link` instead.
+    ///
     /// For more information, please refer to [`pcap_ring docs`].
     ///
     /// [`pcap_ring docs`]: https://doc.dpdk.org/guides/nics/pcap_ring.html
     Ring(i32),
+
+    /// `Ring` device creating a port for each of `nodes`, which are backed by rings shared with
+    /// other processes by their names.
+    RingNodes {
+        /// Unique id of the device.
+        id: i32,
+        /// Ports to create.
+        nodes: Vec<RingNode>,
+    },
+
+    /// `AfPacket` device sends and receives packets through a kernel interface with `AF_PACKET`
+    /// sockets, which needs neither hugepages nor binding the NIC to DPDK.
+    AfPacket {
+        /// Unique id of the device.
+        id: i32,
+        /// Name of the kernel interface, e.g. `eth0`.
+        iface: String,
+        /// Number of rx/tx queue pairs, each of which is backed by a socket. Defaults to 1.
+        qpairs: Option<u16>,
+    },
+
+    /// `AfXdp` device sends and receives packets through a queue of a kernel interface with
+    /// `AF_XDP` sockets, which is faster than `AfPacket` and needs neither hugepages nor binding
+    /// the NIC to DPDK. The device has a single rx/tx queue pair.
+    AfXdp {
+        /// Unique id of the device.
+        id: i32,
+        /// Name of the kernel interface, e.g. `eth0`.
+        iface: String,
+        /// The queue of the interface to bind to.
+        queue: u16,
+    },
+
+    /// `Tap` device creates a TAP interface in the kernel, so that the kernel and DPDK exchange
+    /// packets through it, which is handy for tests and containers.
+    Tap {
+        /// Unique id of the device.
+        id: i32,
+        /// Name of the TAP interface, e.g. `dtap0`.
+        name: String,
+    },
+
+    /// `EventSw` is a software event device named `event_sw{id}`, which schedules events on a
+    /// service lcore, e.g. for an `eventdev::Pipeline`.
+    ///
+    /// Each `EventSw` device needs an unique integer as its id.
+    EventSw(i32),
+
+    /// `CryptoOpenssl` is a software crypto device named `crypto_openssl{id}` backed by OpenSSL,
+    /// e.g. for the ESP transform in `crypto::esp`.
+    ///
+    /// Each `CryptoOpenssl` device needs an unique integer as its id.
+    CryptoOpenssl(i32),
+}
+
+impl Vdev {
+    /// The device arguments, e.g. `net_pcap0,rx_pcap=in.pcap,tx_pcap=out.pcap`, which are also
+    /// accepted by `net_dev::device_attach` except those of `RingNodes`, whose ports are named
+    /// after the nodes, and `EventSw` and `CryptoOpenssl`, which are not Ethernet devices.
+    #[inline]
+    #[must_use]
+    pub fn devargs(&self) -> String {
+        match *self {
+            Vdev::Null(id) => format!("net_null{id}"),
+            Vdev::Pcap { id, ref rx, ref tx } => {
+                let rx = match *rx {
+                    PcapIo::File(ref path) => format!("rx_pcap={}", path.display()),
+                    PcapIo::Iface(ref iface) => format!("rx_iface={iface}"),
+                };
+                let tx = match *tx {
+                    PcapIo::File(ref path) => format!("tx_pcap={}", path.display()),
+                    PcapIo::Iface(ref iface) => format!("tx_iface={iface}"),
+                };
+                format!("net_pcap{id},{rx},{tx}")
+            }
+            Vdev::Ring(id) => format!("net_ring{id}"),
+            Vdev::RingNodes { id, ref nodes } => {
+                let mut args = vec![format!("net_ring{id}")];
+                args.extend(nodes.iter().map(|node| {
+                    let action = if node.attach { "ATTACH" } else { "CREATE" };
+                    format!("nodeaction={}:{}:{action}", node.name, node.numa_node)
+                }));
+                args.join(",")
+            }
+            Vdev::AfPacket {
+                id,
+                ref iface,
+                qpairs,
+            } => match qpairs {
+                Some(qpairs) => format!("net_af_packet{id},iface={iface},qpairs={qpairs}"),
+                None => format!("net_af_packet{id},iface={iface}"),
+            },
+            Vdev::AfXdp {
+                id,
+                ref iface,
+                queue,
+            } => format!("net_af_xdp{id},iface={iface},start_queue={queue},queue_count=1"),
+            Vdev::Tap { id, ref name } => format!("net_tap{id},iface={name}"),
+            Vdev::EventSw(id) => format!("event_sw{id}"),
+            Vdev::CryptoOpenssl(id) => format!("crypto_openssl{id}"),
+        }
+    }
 }
 
 /// DPDK log level.
@@ -205,14 +518,17 @@ impl Config {
     /// of addresses should be less than the number of UIO/VFIO devices. The devices will be started
     /// after entering EAL.
     ///
+    /// An address may be followed by the prefix length of its subnet, e.g. `10.2.3.1/24`, which
+    /// is used to choose source addresses and to receive broadcasts to the subnet.
+    ///
     /// # Errors
     ///
-    /// The function returns an error if the address strings does not match IP format.
+    /// The function returns an error if the address strings does not match IP format, or the
+    /// prefix is longer than the address.
     #[inline]
     pub fn device_probe(mut self, addr_str: &[&str]) -> Result<Self> {
         for addr in addr_str.iter() {
-            self.addrs
-                .push(IpAddr::from_str(addr).map_err(Error::from)?);
+            self.addrs.push(IfAddr::from_str(addr)?);
         }
         Ok(self)
     }
@@ -251,8 +567,41 @@ impl Config {
         Ok(self)
     }
 
+    /// Reserve the lcores in `list` as service lcores, which run DPDK services such as the
+    /// agents with `RxExec::Service` or `TxExec::Service` instead of `main()` or workers.
+    ///
+    /// ```no_run
+    /// use async_dpdk::{eal, net_dev::RxExec};
+    ///
+    /// eal::Config::new()
+    ///     .corelist("0-2")
+    ///     .unwrap()
+    ///     .service_corelist("2")
+    ///     .unwrap()
+    ///     .device_probe(&["192.168.0.1"])
+    ///     .unwrap()
+    ///     .rx_spread(&[RxExec::Service(2)])
+    ///     .enter()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the `list` argument is empty.
+    #[inline]
+    pub fn service_corelist(mut self, list: &str) -> Result<Self> {
+        if list.is_empty() {
+            return Err(Error::InvalidArg);
+        }
+        self.args.push(cstring!("-S"));
+        self.args.push(CString::new(list).map_err(Error::from)?);
+        Ok(self)
+    }
+
     /// Set pci blacklist.
     ///
+    /// `-b` is the option of `block`, which is preferred for the typed address.
+    ///
     /// # Errors
     ///
     /// The function returns an error if the `name` argument is empty.
@@ -265,16 +614,46 @@ impl Config {
 
     /// Set pci whitelist.
     ///
+    /// `-w` is renamed to `-a` since DPDK 20.11, which is the option of `allow`.
+    ///
     /// # Errors
     ///
     /// The function returns an error if the `name` argument is empty.
     #[inline]
     pub fn pci_whitelist(mut self, name: &str) -> Result<Self> {
-        self.args.push(CString::new("-w").map_err(Error::from)?);
+        self.args.push(CString::new("-a").map_err(Error::from)?);
         self.args.push(CString::new(name).map_err(Error::from)?);
         Ok(self)
     }
 
+    /// Block the PCI device at `addr` from being probed. May be called multiple times.
+    #[inline]
+    #[must_use]
+    pub fn block(mut self, addr: PciAddr) -> Self {
+        self.args.push(cstring!("--block"));
+        self.args.push(cstring!(addr.to_string()));
+        self
+    }
+
+    /// Probe the PCI device at `addr` with the driver arguments `devargs`, e.g. `rxq_cqe_comp_en=0`
+    /// or an empty string if there are none. Only allowed devices are probed once this is called,
+    /// which may be called multiple times.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if `devargs` contains a nul byte.
+    #[inline]
+    pub fn allow(mut self, addr: PciAddr, devargs: &str) -> Result<Self> {
+        let arg = if devargs.is_empty() {
+            addr.to_string()
+        } else {
+            format!("{addr},{devargs}")
+        };
+        self.args.push(cstring!("--allow"));
+        self.args.push(CString::new(arg).map_err(Error::from)?);
+        Ok(self)
+    }
+
     /// Disable PCI.
     #[inline]
     #[must_use]
@@ -305,6 +684,65 @@ impl Config {
         self
     }
 
+    /// Reserve `sizes[i]` megabytes of hugepage memory on start on NUMA socket `i`, instead of
+    /// `memory_mb` regardless of sockets.
+    #[inline]
+    #[must_use]
+    pub fn socket_mem(mut self, sizes: &[u32]) -> Self {
+        self.args.push(cstring!("--socket-mem"));
+        self.args.push(cstring!(join(sizes)));
+        self
+    }
+
+    /// Limit the hugepage memory allocated on NUMA socket `i` to `sizes[i]` megabytes, where 0
+    /// means no limit.
+    #[inline]
+    #[must_use]
+    pub fn socket_limit(mut self, sizes: &[u32]) -> Self {
+        self.args.push(cstring!("--socket-limit"));
+        self.args.push(cstring!(join(sizes)));
+        self
+    }
+
+    /// Use hugepages mounted at `dir` rather than those detected.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if `dir` is not valid UTF-8 or contains a nul byte.
+    #[inline]
+    pub fn huge_dir<P: AsRef<Path>>(mut self, dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_str().ok_or(Error::InvalidArg)?;
+        self.args.push(cstring!("--huge-dir"));
+        self.args.push(CString::new(dir).map_err(Error::from)?);
+        Ok(self)
+    }
+
+    /// Set the prefix of the hugepage files and the runtime directory, so that multiple primary
+    /// processes are able to run, and secondary processes attach to the primary process with the
+    /// same prefix. Defaults to `rte`.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if `prefix` is empty or contains a nul byte.
+    #[inline]
+    pub fn file_prefix(mut self, prefix: &str) -> Result<Self> {
+        if prefix.is_empty() {
+            return Err(Error::InvalidArg);
+        }
+        self.args.push(cstring!("--file-prefix"));
+        self.args.push(CString::new(prefix).map_err(Error::from)?);
+        Ok(self)
+    }
+
+    /// Set the main lcore, which runs `main()` and is the lowest of the lcores by default.
+    #[inline]
+    #[must_use]
+    pub fn main_lcore(mut self, lcore_id: u32) -> Self {
+        self.args.push(cstring!("--main-lcore"));
+        self.args.push(cstring!(lcore_id.to_string()));
+        self
+    }
+
     /// Set iova mode.
     #[inline]
     #[must_use]
@@ -317,16 +755,36 @@ impl Config {
         self
     }
 
+    /// Set the type of the process. Processes running with the same `--file-prefix` share their
+    /// mempools and devices, which is `ProcessType::Primary` by default.
+    ///
+    /// Secondary processes should not probe devices with `device_probe`, which are configured by
+    /// the primary process.
+    #[inline]
+    #[must_use]
+    pub fn process_type(mut self, process_type: ProcessType) -> Self {
+        self.process_type = Some(process_type);
+        let process_type = match process_type {
+            ProcessType::Primary => "primary",
+            ProcessType::Secondary => "secondary",
+            ProcessType::Auto => "auto",
+        };
+        self.args
+            .push(cstring!(format!("--proc-type={process_type}")));
+        self
+    }
+
     /// Add a virtual device.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a name or path in `vdev` contains a nul byte.
     #[inline]
     #[must_use]
+    #[allow(clippy::needless_pass_by_value)] // taken like the other settings
     pub fn vdev(mut self, vdev: Vdev) -> Self {
         self.args.push(cstring!("--vdev"));
-        match vdev {
-            Vdev::Null(id) => self.args.push(cstring!(format!("net_null{id}"))),
-            Vdev::Pcap(id) => self.args.push(cstring!(format!("net_pcap{id}"))),
-            Vdev::Ring(id) => self.args.push(cstring!(format!("net_ring{id}"))),
-        }
+        self.args.push(cstring!(vdev.devargs()));
         self
     }
 
@@ -349,30 +807,205 @@ impl Config {
         self
     }
 
-    /// Initialize the Environment Abstraction Layer (EAL). This function is to be executed on the MAIN
-    /// lcore only, as soon as possible in the application's `main()` function.
+    /// Set RSS configuration, which takes effect on devices with multiple RX queues.
+    #[inline]
+    #[must_use]
+    pub fn rss(mut self, rss: RssConfig) -> Self {
+        self.rss = rss;
+        self
+    }
+
+    /// Set the number of descriptors, mempool sizes and offloads of the queues of the device
+    /// `port_id`. Devices are numbered in the order of `device_probe`, and those not given use
+    /// `DevConfig::default()`.
     ///
-    /// # Errors
+    /// ```no_run
+    /// use async_dpdk::{eal, net_dev::DevConfig};
     ///
-    /// Possible reasons for failure:
+    /// eal::Config::new()
+    ///     .device_probe(&["192.168.0.1"])
+    ///     .unwrap()
+    ///     .dev_config(0, DevConfig::new().rx_desc(4096).pool_cache_size(256))
+    ///     .enter()
+    ///     .unwrap();
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn dev_config(mut self, port_id: u16, conf: DevConfig) -> Self {
+        let _prev = self.dev_conf.insert(port_id, conf);
+        self
+    }
+
+    /// Set MTU for each devices, up to the device capability. Jumbo frames are received into and
+    /// sent from chained `Mbuf`s. Defaults to 1500.
+    #[inline]
+    #[must_use]
+    pub fn mtu(mut self, mtu: u16) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Set where rx queue `queue_id` of the device `port_id` is polled, which is a thread of the
+    /// tokio blocking pool by default. Devices are numbered in the order of `device_probe`, and
+    /// queues polled in the same place share a thread.
     ///
-    /// - `Error::NoAccess` indicates a permissions issue.
-    /// - `Error::TempUnavail` indicates either a bus or system resource was not available, setup may be
-    ///   attempted again.
-    /// - `Error::Already` indicates that the EAL has already been initialized, and cannot be initialized
-    ///   again.
-    /// - `Error::InvalidArg` indicates invalid parameters were passed.
-    /// - `Error::NoMem` indicates failure likely caused by an out-of-memory condition.
-    /// - `Error::NoDev` indicates memory setup issues.
-    /// - `Error::NotSupported` indicates that the EAL cannot initialize on this system.
-    /// - `Error::Proto` indicates that the PCI bus is either not present, or is not readable by the eal.
-    /// - `Error::NoExec` indicates that a service core failed to launch successfully.
-    /// - `Error::ToBig` indicates that there are too many configuration items.
+    /// ```no_run
+    /// use async_dpdk::{eal, net_dev::RxExec};
+    ///
+    /// eal::Config::new()
+    ///     .corelist("0-2")
+    ///     .unwrap()
+    ///     .device_probe(&["192.168.0.1"])
+    ///     .unwrap()
+    ///     .max_queues(2)
+    ///     // Poll each rx queue on a dedicated worker lcore.
+    ///     .rx_exec(0, 0, RxExec::Lcore(1))
+    ///     .rx_exec(0, 1, RxExec::Lcore(2))
+    ///     .enter()
+    ///     .unwrap();
+    /// ```
     #[inline]
-    pub fn enter(self) -> Result<()> {
-        if CONTEXT.read().map_err(Error::from)?.is_some() {
-            return Err(Error::Already);
-        }
+    #[must_use]
+    pub fn rx_exec(mut self, port_id: u16, queue_id: u16, exec: RxExec) -> Self {
+        let _prev = self.rx_exec.insert((port_id, queue_id), exec);
+        self
+    }
+
+    /// Spread rx queues not given by `rx_exec` over `execs` in a round-robin manner, so that RX
+    /// scales beyond a single thread. Rx queue `queue_id` of the device `port_id` is polled by
+    /// `execs[(port_id + queue_id) % execs.len()]`, and queues of all devices assigned to the same
+    /// place share a thread.
+    ///
+    /// ```no_run
+    /// use async_dpdk::{eal, net_dev::RxExec};
+    ///
+    /// eal::Config::new()
+    ///     .corelist("0-4")
+    ///     .unwrap()
+    ///     .device_probe(&["192.168.0.1", "192.168.1.1"])
+    ///     .unwrap()
+    ///     .max_queues(4)
+    ///     // Poll the 8 rx queues on 4 worker lcores.
+    ///     .rx_spread(&[1, 2, 3, 4].map(RxExec::Lcore))
+    ///     .enter()
+    ///     .unwrap();
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn rx_spread(mut self, execs: &[RxExec]) -> Self {
+        self.rx_spread = execs.to_vec();
+        self
+    }
+
+    /// Let the agent threads polling rx queues sleep once the queues stay idle for `idle`, and wake
+    /// them up on RX interrupts, so that idle devices don't occupy CPU cores. Busy polling resumes
+    /// once a packet arrives. Devices not supporting RX interrupts are always busy polled, which
+    /// is the default.
+    #[inline]
+    #[must_use]
+    pub fn rx_interrupt(mut self, idle: Duration) -> Self {
+        self.rx_intr = Some(idle);
+        self
+    }
+
+    /// Set the default max number of packets received from an rx queue in a burst, which is 32
+    /// by default. It can be changed per device with `net_dev::set_rx_burst`. Larger bursts,
+    /// e.g. 64, save per-burst overheads under heavy traffic at the cost of latency. It should be
+    /// no larger than 512.
+    #[inline]
+    #[must_use]
+    pub fn rx_burst(mut self, burst: u16) -> Self {
+        self.agent.rx_burst = burst;
+        self
+    }
+
+    /// Set the number of send requests buffered for a tx queue before senders wait, which is 256
+    /// by default.
+    #[inline]
+    #[must_use]
+    pub fn tx_channel_size(mut self, size: usize) -> Self {
+        self.agent.tx_chan_size = size;
+        self
+    }
+
+    /// Carry the packets sent to each tx queue through a lockless ring holding up to `size`
+    /// packets, instead of a channel of send requests. It saves an allocation and a wakeup per
+    /// packet, as the agent takes packets in bursts and is only woken up once the ring was empty.
+    ///
+    /// Sends then return once the packet is enqueued to the ring, and wait for room while it's
+    /// full. Failures to buffer the packet afterwards are only logged.
+    #[inline]
+    #[must_use]
+    pub fn tx_ring(mut self, size: u32) -> Self {
+        self.agent.tx_ring_size = Some(size);
+        self
+    }
+
+    /// Set the number of packets, including fragments, buffered for a tx queue before they are
+    /// put onto the wire, which is 1024 by default. Sends fail with `Error::NoBuf` once it's full.
+    #[inline]
+    #[must_use]
+    pub fn tx_buffer_size(mut self, size: usize) -> Self {
+        self.agent.tx_buf_size = size;
+        self
+    }
+
+    /// Set how often the packets left in the buffer of a tx queue are flushed, which is 100us by
+    /// default. Buffered packets are otherwise sent on the next send, so the interval bounds the
+    /// latency of the last packets of a burst under low-rate traffic.
+    #[inline]
+    #[must_use]
+    pub fn tx_flush_interval(mut self, interval: Duration) -> Self {
+        self.agent.tx_flush_interval = interval;
+        self
+    }
+
+    /// Set the sizes of the IP reassembly table of each agent polling rx queues, which holds
+    /// `max_entries` datagrams being reassembled at most, in `bucket_num` buckets of
+    /// `bucket_size` entries. `bucket_size` should be a power of two, and `max_entries` no larger
+    /// than `bucket_num * bucket_size`. They are 128, 16 and 2048 by default, and larger tables
+    /// help on high-loss links where fragments stay longer.
+    #[inline]
+    #[must_use]
+    pub fn frag_table(mut self, bucket_num: u32, bucket_size: u32, max_entries: u32) -> Self {
+        self.agent.frag_bucket_num = bucket_num;
+        self.agent.frag_bucket_size = bucket_size;
+        self.agent.frag_max_entries = max_entries;
+        self
+    }
+
+    /// Set how long IP fragments wait for the rest of their datagram in the reassembly table
+    /// before they are dropped, which is 1 second by default.
+    #[inline]
+    #[must_use]
+    pub fn frag_timeout(mut self, timeout: Duration) -> Self {
+        self.agent.frag_timeout = timeout;
+        self
+    }
+
+    /// Validate checksums of received UDP datagrams and drop corrupt ones. Checksums verified by
+    /// the hardware are trusted, others are verified in software. Disabled by default.
+    #[inline]
+    #[must_use]
+    pub fn udp_rx_checksum(mut self, validate: bool) -> Self {
+        self.udp_rx_cksum = validate;
+        self
+    }
+
+    /// Deliver UDP datagrams sent to sockets on this host, i.e. those bound to the address of a
+    /// device or the unspecified address, directly to their receive buffers without going
+    /// through the devices. Multicast datagrams always go through the devices. Disabled by
+    /// default.
+    #[inline]
+    #[must_use]
+    pub fn udp_loopback(mut self, enable: bool) -> Self {
+        self.udp_loopback = enable;
+        self
+    }
+
+    /// Initialize EAL with the arguments collected.
+    #[allow(unsafe_code)]
+    fn init(&self) -> Result<()> {
         let mut pargs = self
             .args
             .iter()
@@ -383,7 +1016,6 @@ impl Config {
             return Err(Error::TooBig);
         }
         // SAFETY: ffi
-        #[allow(unsafe_code)]
         let ret = unsafe {
             // arg length checked
             rte_eal_init(
@@ -393,16 +1025,172 @@ impl Config {
         };
         if ret < 0 {
             error!("Error initializing DPDK environment");
-            return Err(Error::from_errno());
+            return Err(Error::from_errno("rte_eal_init"));
+        }
+        INITIALIZED.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Initialize the Environment Abstraction Layer (EAL). This function is to be executed on the MAIN
+    /// lcore only, as soon as possible in the application's `main()` function.
+    ///
+    /// Once the environment is left with `shutdown`, it can be entered again, where EAL is not
+    /// initialized again and the EAL arguments are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons for failure:
+    ///
+    /// - `Error::NoAccess` indicates a permissions issue.
+    /// - `Error::TempUnavail` indicates either a bus or system resource was not available, setup may be
+    ///   attempted again.
+    /// - `Error::Already` indicates that the environment has already been entered and not left with
+    ///   `shutdown`, or EAL has been cleaned up with `cleanup`.
+    /// - `Error::InvalidArg` indicates invalid parameters were passed, including invalid buffer or
+    ///   table sizes, or a zero TX flush interval.
+    /// - `Error::NoMem` indicates failure likely caused by an out-of-memory condition.
+    /// - `Error::NoDev` indicates memory setup issues.
+    /// - `Error::NotSupported` indicates that the EAL cannot initialize on this system.
+    /// - `Error::Proto` indicates that the PCI bus is either not present, or is not readable by the eal.
+    /// - `Error::NoExec` indicates that a service core failed to launch successfully.
+    /// - `Error::ToBig` indicates that there are too many configuration items.
+    /// - `Error::Secondary` indicates that devices are probed in a secondary process.
+    #[inline]
+    pub fn enter(self) -> Result<()> {
+        if CONTEXT.read().map_err(Error::from)?.is_some() || CLEANED_UP.load(Ordering::Acquire) {
+            return Err(Error::Already);
+        }
+        // Settings are checked before EAL is initialized, which can't be undone.
+        if self.max_queues == Some(0) {
+            return Err(Error::InvalidArg);
+        }
+        self.agent.validate()?;
+        let secondary = if INITIALIZED.load(Ordering::Acquire) {
+            process_type() == ProcessType::Secondary
+        } else {
+            self.process_type == Some(ProcessType::Secondary)
+        };
+        if secondary && !self.addrs.is_empty() {
+            return Err(Error::Secondary);
+        }
+        if INITIALIZED.load(Ordering::Acquire) {
+            warn!("EAL already initialized, its arguments are ignored");
+            let mut removed = REMOVED_DEVICES.lock().map_err(Error::from)?;
+            // Probe in order, so that the ports get the ids they had. Devices failing to be probed
+            // are kept for the next `enter`.
+            let mut res = Ok(());
+            removed.retain(|devargs| match net_dev::probe_port(devargs) {
+                Ok(_port_id) => false,
+                Err(e) => {
+                    error!("Failed to probe device {devargs} again: {e}");
+                    res = res.and(Err(e));
+                    true
+                }
+            });
+            res?;
+        } else {
+            self.init()?;
+        }
+        // A process of `ProcessType::Auto` is known to be secondary once initialized.
+        if process_type() == ProcessType::Secondary && !self.addrs.is_empty() {
+            return Err(Error::Secondary);
         }
         let context = Arc::new(Eal {});
         *CONTEXT.write().map_err(Error::from)? = Some(context);
-        if let Some(max_queues) = self.max_queues {
-            if max_queues == 0 {
-                return Err(Error::InvalidArg);
-            }
-        }
-        net_dev::device_probe(self.addrs, self.max_queues.unwrap_or(u16::MAX))?;
+        udp::set_rx_cksum_validate(self.udp_rx_cksum);
+        udp::set_loopback(self.udp_loopback);
+        agent::set_agent_conf(self.agent)?;
+        net_dev::device_probe(
+            self.addrs,
+            self.max_queues.unwrap_or(u16::MAX),
+            &self.rss,
+            self.dev_conf,
+            self.mtu,
+            self.rx_exec,
+            self.rx_spread,
+            self.rx_intr,
+        )?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, PcapIo, PciAddr, RingNode, Vdev};
+
+    #[test]
+    fn test_args() {
+        let addr = PciAddr::new(0, 8, 0x1f, 1);
+        assert_eq!(addr.to_string(), "0000:08:1f.1");
+        let conf = Config::default()
+            .socket_mem(&[1024, 512])
+            .main_lcore(2)
+            .block(addr)
+            .allow(PciAddr::new(1, 0, 0, 0), "txq_inline=0")
+            .unwrap()
+            .file_prefix("app")
+            .unwrap()
+            .huge_dir("/mnt/huge")
+            .unwrap();
+        let args: Vec<_> = conf.args.iter().map(|s| s.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "--socket-mem",
+                "1024,512",
+                "--main-lcore",
+                "2",
+                "--block",
+                "0000:08:1f.1",
+                "--allow",
+                "0001:00:00.0,txq_inline=0",
+                "--file-prefix",
+                "app",
+                "--huge-dir",
+                "/mnt/huge",
+            ]
+        );
+        assert!(Config::default().file_prefix("").is_err());
+    }
+
+    #[test]
+    fn test_vdev() {
+        assert_eq!(Vdev::Null(1).devargs(), "net_null1");
+        let pcap = Vdev::Pcap {
+            id: 0,
+            rx: PcapIo::File("in.pcap".into()),
+            tx: PcapIo::Iface("eth0".to_owned()),
+        };
+        assert_eq!(pcap.devargs(), "net_pcap0,rx_pcap=in.pcap,tx_iface=eth0");
+        let ring = Vdev::RingNodes {
+            id: 2,
+            nodes: vec![RingNode::new("r0", 0), RingNode::new("r1", 1).attach(true)],
+        };
+        assert_eq!(
+            ring.devargs(),
+            "net_ring2,nodeaction=r0:0:CREATE,nodeaction=r1:1:ATTACH"
+        );
+        let af_packet = Vdev::AfPacket {
+            id: 0,
+            iface: "eth1".to_owned(),
+            qpairs: Some(2),
+        };
+        assert_eq!(af_packet.devargs(), "net_af_packet0,iface=eth1,qpairs=2");
+        let af_xdp = Vdev::AfXdp {
+            id: 0,
+            iface: "eth1".to_owned(),
+            queue: 3,
+        };
+        assert_eq!(
+            af_xdp.devargs(),
+            "net_af_xdp0,iface=eth1,start_queue=3,queue_count=1"
+        );
+        let tap = Vdev::Tap {
+            id: 1,
+            name: "dtap1".to_owned(),
+        };
+        assert_eq!(tap.devargs(), "net_tap1,iface=dtap1");
+        assert_eq!(Vdev::EventSw(0).devargs(), "event_sw0");
+        assert_eq!(Vdev::CryptoOpenssl(0).devargs(), "crypto_openssl0");
+    }
+}
@@ -1,169 +1,448 @@
 //! DPDK defined error codes.
 
-use dpdk_sys::{rte_errno_stub, rte_exit, rte_strerror};
+use crate::trace::error;
+use dpdk_sys::rte_errno_stub;
+use lazy_static::lazy_static;
 use std::{
     ffi::{IntoStringError, NulError},
+    fmt,
+    io::{Error as IoError, ErrorKind as IoErrorKind},
     net::AddrParseError,
     num::TryFromIntError,
-    os::raw::c_int,
-    sync::{mpsc::RecvError as StdRecvError, mpsc::SendError as StdSendError, PoisonError},
+    sync::{
+        mpsc::RecvError as StdRecvError, mpsc::SendError as StdSendError, Arc, PoisonError, RwLock,
+    },
 };
-use tokio::sync::{
-    mpsc::error::SendError as TokioMpscSendError,
-    mpsc::error::TrySendError as TokioMpscTrySendError,
-    oneshot::error::RecvError as TokioOneshotRecvError,
+use tokio::{
+    sync::{
+        mpsc::error::SendError as TokioMpscSendError,
+        mpsc::error::TrySendError as TokioMpscTrySendError,
+        oneshot::error::RecvError as TokioOneshotRecvError,
+    },
+    time::error::Elapsed,
 };
 
 /// async-dpdk defined Result.
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// Errors from DPDK and rust.
-#[doc(hidden)]
+/// Hook called with errors which can't be returned to the caller.
+type ErrorHook = Arc<dyn Fn(&Error) + Send + Sync>;
+
+lazy_static! {
+    /// Hook set by `set_error_hook`.
+    static ref ERROR_HOOK: RwLock<Option<ErrorHook>> = RwLock::new(None);
+}
+
+/// Set `hook` to be called with errors which can't be returned to the caller, e.g. failures to
+/// close a device when its `EthDev` is dropped, replacing the hook set before.
+///
+/// Such errors are logged whether a hook is set or not.
+#[inline]
+pub fn set_error_hook<F: Fn(&Error) + Send + Sync + 'static>(hook: F) {
+    *ERROR_HOOK.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(hook));
+}
+
+/// Remove the hook set by `set_error_hook`.
+#[inline]
+pub fn clear_error_hook() {
+    *ERROR_HOOK.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Report `err` which can't be returned to the caller, logging it and calling the hook set by
+/// `set_error_hook`.
+pub(crate) fn report(err: Error) {
+    error!("{err}");
+    // The hook is called without the lock, so that it may set or clear the hook.
+    let hook = ERROR_HOOK
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .map(Arc::clone);
+    if let Some(hook) = hook {
+        hook(&err);
+    }
+}
+
+/// Kinds of errors from DPDK and rust, which are matched to tell what an `Error` is, e.g.
+/// `err.kind() == ErrorKind::Busy`.
 #[non_exhaustive]
 #[repr(i32)]
-#[derive(Copy, Clone, Debug, thiserror::Error)]
-pub enum Error {
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum ErrorKind {
+    /// Operation not permitted.
     #[error("Operation not permitted")]
     NoPerm = libc::EPERM,
+    /// No such file or directory.
     #[error("No such file or directory")]
     NoEntry = libc::ENOENT,
+    /// No such process.
     #[error("No such process")]
     NoProc = libc::ESRCH,
+    /// Interrupted system call.
     #[error("Interrupted system call")]
     Interrupted = libc::EINTR,
+    /// Input/output error.
     #[error("Input/output error")]
     IoErr = libc::EIO,
+    /// Device not configured.
     #[error("Device not configured")]
     NotConfigured = libc::ENXIO,
+    /// Argument list too long.
     #[error("Argument list too long")]
     TooBig = libc::E2BIG,
+    /// Exec format error.
     #[error("Exec format error")]
     NoExec = libc::ENOEXEC,
+    /// Bad fd.
     #[error("Bad fd")]
     BadFd = libc::EBADF,
+    /// Resource temporarily unavailable.
     #[error("Resource temporarily unavailable")]
     TempUnavail = libc::EAGAIN,
+    /// Cannot allocate memory.
     #[error("Cannot allocate memory")]
     NoMem = libc::ENOMEM,
+    /// Permission denied.
     #[error("Permission denied")]
     NoAccess = libc::EACCES,
+    /// Bad address.
     #[error("Bad address")]
     BadAddress = libc::EFAULT,
+    /// Device or resource busy.
     #[error("Device or resource busy")]
     Busy = libc::EBUSY,
+    /// File exists.
     #[error("File exists")]
     Exists = libc::EEXIST,
+    /// Invalid cross device link.
     #[error("Invalid cross device link")]
     CrossDev = libc::EXDEV,
+    /// No such device.
     #[error("No such device")]
     NoDev = libc::ENODEV,
+    /// Invalid argument.
     #[error("Invalid argument")]
     InvalidArg = libc::EINVAL,
+    /// No space left on device.
     #[error("No space left on device")]
     NoSpace = libc::ENOSPC,
+    /// Broken pipe.
     #[error("Broken pipe")]
     BrokenPipe = libc::EPIPE,
+    /// Numerical result out of range.
     #[error("Numerical result out of range")]
     OutOfRange = libc::ERANGE,
+    /// Value too large for defined data type.
     #[error("Value too large for defined data type")]
     Overflow = libc::EOVERFLOW,
+    /// Not supported.
     #[error("Not supported")]
     NotSupported = libc::ENOTSUP,
+    /// Operation already in progress.
     #[error("Operation already in progress")]
     Already = libc::EALREADY,
+    /// No buffer space available.
     #[error("No buffer space available")]
     NoBuf = libc::ENOBUFS,
+    /// Protocol error.
     #[error("Protocol error")]
     Proto = libc::EPROTO,
+    /// Connection reset by peer.
+    #[error("Connection reset by peer")]
+    ConnReset = libc::ECONNRESET,
+    /// Connection refused.
+    #[error("Connection refused")]
+    ConnRefused = libc::ECONNREFUSED,
+    /// Transport endpoint is not connected.
+    #[error("Transport endpoint is not connected")]
+    NotConnected = libc::ENOTCONN,
+    /// Connection timed out.
+    #[error("Connection timed out")]
+    TimedOut = libc::ETIMEDOUT,
+    /// Operation not allowed in secondary processes.
     #[error("Operation not allowed in secondary processes")]
     Secondary = 1001, // RTE defined
+    /// Missing rte_config.
     #[error("Missing rte_config")]
     NoConfig = 1002, // RTE defined
+    /// Lock poisoned.
     #[error("Lock poisoned")]
     Poisoned = 1003,
+    /// Needed resource not started.
     #[error("Needed resource not started")]
     NotStart = 1004,
+    /// Not exist.
     #[error("Not exist")]
     NotExist = 1005,
+    /// Mempool exhausted.
+    #[error("Mempool exhausted")]
+    MempoolExhausted = 1006,
+    /// Rate limit exceeded.
+    #[error("Rate limit exceeded")]
+    RateLimited = 1007,
+    /// Unknown error.
     #[error("Unknown error")]
     Unknown,
 }
 
+/// An error from DPDK or rust, made of its kind along with the context where it happens: the
+/// failed operation, e.g. the DPDK function called, and the port and queue operated on.
+///
+/// Errors are equal if both their kinds and their contexts are, so constants such as
+/// `Error::Busy` only match errors without context. Match `Error::kind` against `ErrorKind` to
+/// tell the kind of any error:
+///
+/// ```
+/// use async_dpdk::{Error, ErrorKind};
+///
+/// let err = Error::from_ret(-libc::EBUSY, "rte_eth_dev_start").unwrap_err();
+/// assert_ne!(err, Error::Busy);
+/// assert!(matches!(err.kind(), ErrorKind::Busy));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Error {
+    /// Kind of the error.
+    kind: ErrorKind,
+    /// The failed operation.
+    op: Option<&'static str>,
+    /// The port operated on.
+    port_id: Option<u16>,
+    /// The queue operated on.
+    queue_id: Option<u16>,
+}
+
+// Constants of error kinds, keeping `Error::NoDev` and alike usable as before.
 #[doc(hidden)]
+#[allow(non_upper_case_globals, clippy::multiple_inherent_impl)]
+impl Error {
+    pub const NoPerm: Error = Error::new(ErrorKind::NoPerm);
+    pub const NoEntry: Error = Error::new(ErrorKind::NoEntry);
+    pub const NoProc: Error = Error::new(ErrorKind::NoProc);
+    pub const Interrupted: Error = Error::new(ErrorKind::Interrupted);
+    pub const IoErr: Error = Error::new(ErrorKind::IoErr);
+    pub const NotConfigured: Error = Error::new(ErrorKind::NotConfigured);
+    pub const TooBig: Error = Error::new(ErrorKind::TooBig);
+    pub const NoExec: Error = Error::new(ErrorKind::NoExec);
+    pub const BadFd: Error = Error::new(ErrorKind::BadFd);
+    pub const TempUnavail: Error = Error::new(ErrorKind::TempUnavail);
+    pub const NoMem: Error = Error::new(ErrorKind::NoMem);
+    pub const NoAccess: Error = Error::new(ErrorKind::NoAccess);
+    pub const BadAddress: Error = Error::new(ErrorKind::BadAddress);
+    pub const Busy: Error = Error::new(ErrorKind::Busy);
+    pub const Exists: Error = Error::new(ErrorKind::Exists);
+    pub const CrossDev: Error = Error::new(ErrorKind::CrossDev);
+    pub const NoDev: Error = Error::new(ErrorKind::NoDev);
+    pub const InvalidArg: Error = Error::new(ErrorKind::InvalidArg);
+    pub const NoSpace: Error = Error::new(ErrorKind::NoSpace);
+    pub const BrokenPipe: Error = Error::new(ErrorKind::BrokenPipe);
+    pub const OutOfRange: Error = Error::new(ErrorKind::OutOfRange);
+    pub const Overflow: Error = Error::new(ErrorKind::Overflow);
+    pub const NotSupported: Error = Error::new(ErrorKind::NotSupported);
+    pub const Already: Error = Error::new(ErrorKind::Already);
+    pub const NoBuf: Error = Error::new(ErrorKind::NoBuf);
+    pub const Proto: Error = Error::new(ErrorKind::Proto);
+    pub const ConnReset: Error = Error::new(ErrorKind::ConnReset);
+    pub const ConnRefused: Error = Error::new(ErrorKind::ConnRefused);
+    pub const NotConnected: Error = Error::new(ErrorKind::NotConnected);
+    pub const TimedOut: Error = Error::new(ErrorKind::TimedOut);
+    pub const Secondary: Error = Error::new(ErrorKind::Secondary);
+    pub const NoConfig: Error = Error::new(ErrorKind::NoConfig);
+    pub const Poisoned: Error = Error::new(ErrorKind::Poisoned);
+    pub const NotStart: Error = Error::new(ErrorKind::NotStart);
+    pub const NotExist: Error = Error::new(ErrorKind::NotExist);
+    pub const MempoolExhausted: Error = Error::new(ErrorKind::MempoolExhausted);
+    pub const RateLimited: Error = Error::new(ErrorKind::RateLimited);
+    pub const Unknown: Error = Error::new(ErrorKind::Unknown);
+}
+
 impl Error {
-    /// Read error code on stacks.
+    /// Create an error of `kind` without context.
+    #[inline]
+    #[must_use]
+    pub const fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            op: None,
+            port_id: None,
+            queue_id: None,
+        }
+    }
+
+    /// Kind of the error.
+    #[inline]
+    #[must_use]
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The errno value of the error, which is positive.
+    #[inline]
+    #[must_use]
+    pub fn errno(&self) -> i32 {
+        self.kind as i32
+    }
+
+    /// The failed operation, e.g. the DPDK function called, if it's known.
+    #[inline]
+    #[must_use]
+    pub fn op(&self) -> Option<&'static str> {
+        self.op
+    }
+
+    /// The port operated on, if it's known.
+    #[inline]
+    #[must_use]
+    pub fn port_id(&self) -> Option<u16> {
+        self.port_id
+    }
+
+    /// The queue operated on, if it's known.
+    #[inline]
+    #[must_use]
+    pub fn queue_id(&self) -> Option<u16> {
+        self.queue_id
+    }
+
+    /// Set the failed operation, keeping the one set before if any, which is more specific.
+    #[inline]
+    #[must_use]
+    pub fn with_op(mut self, op: &'static str) -> Self {
+        self.op = self.op.or(Some(op));
+        self
+    }
+
+    /// Set the port operated on.
+    #[inline]
+    #[must_use]
+    pub fn with_port(mut self, port_id: u16) -> Self {
+        self.port_id = Some(port_id);
+        self
+    }
+
+    /// Set the queue operated on.
+    #[inline]
+    #[must_use]
+    pub fn with_queue(mut self, queue_id: u16) -> Self {
+        self.queue_id = Some(queue_id);
+        self
+    }
+
+    /// Whether the operation may succeed if it's tried again later, e.g. when buffers are full or
+    /// it times out.
+    #[inline]
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::Interrupted
+                | ErrorKind::TempUnavail
+                | ErrorKind::Busy
+                | ErrorKind::NoBuf
+                | ErrorKind::TimedOut
+                | ErrorKind::MempoolExhausted
+                | ErrorKind::RateLimited
+        )
+    }
+
+    /// Read the error code of the last DPDK function `op` called on this thread.
+    #[doc(hidden)]
     #[inline]
     #[must_use]
-    pub fn from_errno() -> Error {
+    pub fn from_errno(op: &'static str) -> Error {
         // SAFETY: read mutable static variable
         #[allow(unsafe_code)]
         let errno = unsafe { rte_errno_stub() };
-        errno.into()
+        Error::from(errno).with_op(op)
     }
 
-    /// Convert DPDK returned error code to `async_dpdk` defined error code.
+    /// Convert the value returned by DPDK function `op`, which is negative on failures, to
+    /// `async_dpdk` defined error code.
+    #[doc(hidden)]
     #[inline]
-    pub fn from_ret(errno: i32) -> Result<()> {
+    pub fn from_ret(errno: i32, op: &'static str) -> Result<()> {
         let errno = errno.saturating_neg();
         match errno {
             e if e <= 0 => Ok(()),
-            e => Err(e.into()),
+            e => Err(Error::from(e).with_op(op)),
         }
     }
+}
 
-    /// Function to terminate the application immediately, printing an error message and returning
-    /// the exit code back to the shell.
-    ///
-    /// Call it only if the error code is checked.
+impl fmt::Display for Error {
     #[inline]
-    #[allow(unsafe_code)]
-    pub(crate) fn parse_err(errno: c_int) {
-        if errno < 0 {
-            // SAFETY: ffi
-            unsafe {
-                let msg = rte_strerror(errno);
-                rte_exit(errno, msg);
-            }
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(op) = self.op {
+            write!(f, " in {op}")?;
         }
+        if let Some(port_id) = self.port_id {
+            write!(f, " on port {port_id}")?;
+        }
+        if let Some(queue_id) = self.queue_id {
+            write!(f, " queue {queue_id}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ErrorKind> for Error {
+    #[inline]
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
     }
 }
 
 impl From<i32> for Error {
+    #[inline]
+    fn from(errno: i32) -> Self {
+        Self::new(ErrorKind::from(errno))
+    }
+}
+
+impl From<i32> for ErrorKind {
     #[inline]
     fn from(errno: i32) -> Self {
         match errno {
-            libc::EPERM => Error::NoPerm,
-            libc::ENOENT => Error::NoEntry,
-            libc::ESRCH => Error::NoProc,
-            libc::EINTR => Error::Interrupted,
-            libc::EIO => Error::IoErr,
-            libc::ENXIO => Error::NotConfigured,
-            libc::E2BIG => Error::TooBig,
-            libc::ENOEXEC => Error::NoExec,
-            libc::EBADF => Error::BadFd,
-            libc::EAGAIN => Error::TempUnavail,
-            libc::ENOMEM => Error::NoMem,
-            libc::EACCES => Error::NoAccess,
-            libc::EFAULT => Error::BadAddress,
-            libc::EBUSY => Error::Busy,
-            libc::EEXIST => Error::Exists,
-            libc::EXDEV => Error::CrossDev,
-            libc::ENODEV => Error::NoDev,
-            libc::EINVAL => Error::InvalidArg,
-            libc::ENOSPC => Error::NoSpace,
-            libc::EPIPE => Error::BrokenPipe,
-            libc::ERANGE => Error::OutOfRange,
-            libc::EOVERFLOW => Error::Overflow,
-            libc::ENOTSUP => Error::NotSupported,
-            libc::EALREADY => Error::Already,
-            libc::ENOBUFS => Error::NoBuf,
-            libc::EPROTO => Error::Proto,
-            1001 => Error::Secondary,
-            1002 => Error::NoConfig,
-            1003 => Error::Poisoned,
-            1004 => Error::NotStart,
-            1005 => Error::NotExist,
-            e if e > 0 => Error::Unknown,
+            libc::EPERM => ErrorKind::NoPerm,
+            libc::ENOENT => ErrorKind::NoEntry,
+            libc::ESRCH => ErrorKind::NoProc,
+            libc::EINTR => ErrorKind::Interrupted,
+            libc::EIO => ErrorKind::IoErr,
+            libc::ENXIO => ErrorKind::NotConfigured,
+            libc::E2BIG => ErrorKind::TooBig,
+            libc::ENOEXEC => ErrorKind::NoExec,
+            libc::EBADF => ErrorKind::BadFd,
+            libc::EAGAIN => ErrorKind::TempUnavail,
+            libc::ENOMEM => ErrorKind::NoMem,
+            libc::EACCES => ErrorKind::NoAccess,
+            libc::EFAULT => ErrorKind::BadAddress,
+            libc::EBUSY => ErrorKind::Busy,
+            libc::EEXIST => ErrorKind::Exists,
+            libc::EXDEV => ErrorKind::CrossDev,
+            libc::ENODEV => ErrorKind::NoDev,
+            libc::EINVAL => ErrorKind::InvalidArg,
+            libc::ENOSPC => ErrorKind::NoSpace,
+            libc::EPIPE => ErrorKind::BrokenPipe,
+            libc::ERANGE => ErrorKind::OutOfRange,
+            libc::EOVERFLOW => ErrorKind::Overflow,
+            libc::ENOTSUP => ErrorKind::NotSupported,
+            libc::EALREADY => ErrorKind::Already,
+            libc::ENOBUFS => ErrorKind::NoBuf,
+            libc::EPROTO => ErrorKind::Proto,
+            libc::ECONNRESET => ErrorKind::ConnReset,
+            libc::ECONNREFUSED => ErrorKind::ConnRefused,
+            libc::ENOTCONN => ErrorKind::NotConnected,
+            libc::ETIMEDOUT => ErrorKind::TimedOut,
+            1001 => ErrorKind::Secondary,
+            1002 => ErrorKind::NoConfig,
+            1003 => ErrorKind::Poisoned,
+            1004 => ErrorKind::NotStart,
+            1005 => ErrorKind::NotExist,
+            1006 => ErrorKind::MempoolExhausted,
+            1007 => ErrorKind::RateLimited,
+            e if e > 0 => ErrorKind::Unknown,
             _ => unreachable!("errno = {}", errno), // negative number
         }
     }
@@ -197,6 +476,13 @@ impl From<TokioOneshotRecvError> for Error {
     }
 }
 
+impl From<Elapsed> for Error {
+    #[inline]
+    fn from(_error: Elapsed) -> Self {
+        Error::TimedOut
+    }
+}
+
 impl<T> From<StdSendError<T>> for Error {
     #[inline]
     fn from(_error: StdSendError<T>) -> Self {
@@ -232,9 +518,88 @@ impl From<IntoStringError> for Error {
     }
 }
 
+impl From<IoError> for Error {
+    #[inline]
+    fn from(error: IoError) -> Self {
+        // Errors converted from `Error` are given back as they are.
+        if let Some(&err) = error.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            return err;
+        }
+        match error.raw_os_error() {
+            Some(errno) if errno > 0 => errno.into(),
+            Some(_) | None => Error::IoErr,
+        }
+    }
+}
+
 impl From<TryFromIntError> for Error {
     #[inline]
     fn from(_error: TryFromIntError) -> Self {
         Error::InvalidArg
     }
 }
+
+impl From<Error> for IoError {
+    /// Errors of errno kinds keep the `std::io::ErrorKind` of the errno, the others are
+    /// `std::io::ErrorKind::Other`.
+    #[inline]
+    fn from(error: Error) -> Self {
+        let kind = if error.errno() < 1000 {
+            IoError::from_raw_os_error(error.errno()).kind()
+        } else {
+            IoErrorKind::Other
+        };
+        IoError::new(kind, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clear_error_hook, report, set_error_hook, Error, ErrorKind};
+    use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+    use std::sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn test_context() {
+        let err = Error::from_ret(-libc::EBUSY, "rte_eth_dev_start")
+            .map_err(|e| e.with_port(1).with_queue(2).with_op("start"))
+            .unwrap_err();
+        assert_ne!(err, Error::Busy);
+        assert_eq!(err.kind(), ErrorKind::Busy);
+        assert_eq!(err.errno(), libc::EBUSY);
+        assert_eq!(err.op(), Some("rte_eth_dev_start"));
+        assert_eq!((err.port_id(), err.queue_id()), (Some(1), Some(2)));
+        assert!(err.is_retryable());
+        assert_eq!(
+            err.to_string(),
+            "Device or resource busy in rte_eth_dev_start on port 1 queue 2"
+        );
+        assert!(!Error::InvalidArg.is_retryable());
+        assert!(Error::from_ret(0, "rte_eth_dev_start").is_ok());
+    }
+
+    #[test]
+    fn test_error_hook() {
+        let errno = Arc::new(AtomicI32::new(0));
+        let hooked = Arc::clone(&errno);
+        set_error_hook(move |e| hooked.store(e.errno(), Ordering::Relaxed));
+        report(Error::Busy);
+        assert_eq!(errno.load(Ordering::Relaxed), libc::EBUSY);
+        clear_error_hook();
+        report(Error::NoDev);
+        assert_eq!(errno.load(Ordering::Relaxed), libc::EBUSY);
+    }
+
+    #[test]
+    fn test_io_error() {
+        let err = IoError::from(Error::TempUnavail);
+        assert_eq!(err.kind(), IoErrorKind::WouldBlock);
+        assert_eq!(Error::from(err), Error::TempUnavail);
+        let err = IoError::from(Error::RateLimited);
+        assert_eq!(err.kind(), IoErrorKind::Other);
+        assert_eq!(err.to_string(), "Rate limit exceeded");
+    }
+}
@@ -2,58 +2,162 @@
 //! and poll those devices in user space. This module provide safe wrappings for devices and
 //! queues. For more information, please refer to [`PMD document`].
 //!
+//! Devices probed by `eal::Config::device_probe` are polled by agent threads and used through
+//! sockets. Other ports can be driven directly with an `EthDev` created by `EthDev::new`, whose
+//! queues are polled by the application through `RxQueue` and `TxQueue`. Secondary processes
+//! drive the ports set up by the primary process in the same way, attaching to them with
+//! `EthDev::attach`. A queue polled by agent threads can also be taken over for a while with
+//! `EthDev::rx_queue` or `EthDev::tx_queue`, and the agents leave it alone until the handle is
+//! dropped.
+//!
 //! [`PMD document`]: https://doc.dpdk.org/guides/prog_guide/poll_mode_drv.html#poll-mode-driver
 
 use crate::{
-    agent::{RxAgent, TxAgent},
-    mbuf::Mbuf,
+    agent::{
+        PortConf, RxAgent, RxExec, TxAgent, TxChannel, TxExec, TxRequest, TxRing,
+        RX_BURST_CAPACITY, TX_CLASSES,
+    },
+    eal::{self, ProcessType},
+    errno,
+    ether::ETHER_ADDR_LEN,
+    flow::{Flow, FlowId, FlowRule},
+    gso::RTE_ETH_TX_OFFLOAD_UDP_TSO,
+    mbuf::{Mbuf, MbufSlot},
     mempool::{Mempool, PktMempool},
+    meter::{Meter, Policer},
     packet::Packet,
-    Error, Result,
+    shaper::RateLimit,
+    timestamp,
+    trace::{debug, error, trace, warn},
+    Error, ErrorKind, Result,
 };
 use dpdk_sys::{
-    rte_eth_conf, rte_eth_dev_adjust_nb_rx_tx_desc, rte_eth_dev_close, rte_eth_dev_configure,
-    rte_eth_dev_count_avail, rte_eth_dev_info, rte_eth_dev_info_get, rte_eth_dev_set_ptypes,
-    rte_eth_dev_socket_id, rte_eth_dev_start, rte_eth_dev_stop, rte_eth_macaddr_get,
-    rte_eth_rx_queue_setup, rte_eth_tx_queue_setup, rte_ether_addr,
-    RTE_ETH_TX_OFFLOAD_MBUF_FAST_FREE,
+    rte_device, rte_eth_allmulticast_disable, rte_eth_allmulticast_enable,
+    rte_eth_allmulticast_get, rte_eth_conf, rte_eth_dev_adjust_nb_rx_tx_desc, rte_eth_dev_close,
+    rte_eth_dev_configure, rte_eth_dev_count_avail, rte_eth_dev_default_mac_addr_set,
+    rte_eth_dev_get_mtu, rte_eth_dev_get_name_by_port, rte_eth_dev_get_port_by_name,
+    rte_eth_dev_info, rte_eth_dev_info_get, rte_eth_dev_is_valid_port, rte_eth_dev_mac_addr_add,
+    rte_eth_dev_mac_addr_remove, rte_eth_dev_rss_hash_update, rte_eth_dev_rss_reta_query,
+    rte_eth_dev_rss_reta_update, rte_eth_dev_set_mc_addr_list, rte_eth_dev_set_mtu,
+    rte_eth_dev_set_ptypes, rte_eth_dev_socket_id, rte_eth_dev_start, rte_eth_dev_stop,
+    rte_eth_link, rte_eth_link_get_nowait, rte_eth_macaddr_get, rte_eth_macaddrs_get,
+    rte_eth_promiscuous_disable, rte_eth_promiscuous_enable, rte_eth_promiscuous_get,
+    rte_eth_read_clock, rte_eth_rss_conf, rte_eth_rss_reta_entry64, rte_eth_rx_burst,
+    rte_eth_rx_mq_mode_RTE_ETH_MQ_RX_RSS, rte_eth_rx_queue_setup, rte_eth_stats, rte_eth_stats_get,
+    rte_eth_stats_reset, rte_eth_tx_burst, rte_eth_tx_queue_setup, rte_eth_xstat,
+    rte_eth_xstat_name, rte_eth_xstats_get, rte_eth_xstats_get_names, rte_eth_xstats_reset,
+    rte_ether_addr, rte_mbuf, RTE_ETHER_CRC_LEN, RTE_ETHER_HDR_LEN, RTE_ETHER_MTU,
+    RTE_ETH_DEV_INTR_LSC, RTE_ETH_NAME_MAX_LEN, RTE_ETH_RETA_GROUP_SIZE,
+    RTE_ETH_TX_OFFLOAD_MBUF_FAST_FREE, RTE_MBUF_DEFAULT_BUF_SIZE, RTE_PKTMBUF_HEADROOM,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::{CStr, CString},
+    fmt::Debug,
+    future::Future,
+    mem::{self, MaybeUninit},
+    os::raw::c_char,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
-use std::{fmt::Debug, mem::MaybeUninit, ptr, sync::Arc};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+
+/// Offload of IPv4 header checksum on TX.
+pub(crate) const RTE_ETH_TX_OFFLOAD_IPV4_CKSUM: u64 = 1 << 1;
+/// Offload of UDP checksum on TX.
+pub(crate) const RTE_ETH_TX_OFFLOAD_UDP_CKSUM: u64 = 1 << 2;
+/// Offload of TCP checksum on TX.
+pub(crate) const RTE_ETH_TX_OFFLOAD_TCP_CKSUM: u64 = 1 << 3;
+/// Offload of TCP segmentation on TX.
+pub(crate) const RTE_ETH_TX_OFFLOAD_TCP_TSO: u64 = 1 << 5;
+/// Offload of sending packets made up of multiple segments.
+pub(crate) const RTE_ETH_TX_OFFLOAD_MULTI_SEGS: u64 = 1 << 15;
+/// Offload of IPv4 header checksum validation on RX.
+const RTE_ETH_RX_OFFLOAD_IPV4_CKSUM: u64 = 1 << 1;
+/// Offload of UDP checksum validation on RX.
+const RTE_ETH_RX_OFFLOAD_UDP_CKSUM: u64 = 1 << 2;
+/// Offload of receiving packets into multiple segments.
+const RTE_ETH_RX_OFFLOAD_SCATTER: u64 = 1 << 13;
+/// Offload of timestamping received packets.
+const RTE_ETH_RX_OFFLOAD_TIMESTAMP: u64 = 1 << 14;
+/// Max number of packets received or sent through `RxQueue` and `TxQueue` in a burst.
+const QUEUE_BURST_CAPACITY: usize = 512;
 
 /// An Ethernet device.
 ///
 /// It is identified with a `port_id`. Each `EthDev` has several tx queues and rx queues,
-/// which are polled by agent threads.
+/// which are polled by agent threads, or by the application if it's created by `EthDev::new`.
 ///
 /// ```text
 ///     new         Configure the device then initialize tx/rx queues.
 ///      |
 ///      v
-///    start <--    Start agent threads and register queues on them.
+///    start <--    Start agent threads and register queues on them, if any.
 ///      |     |
 ///      v     |
-///    stop-----    Stop sgent threads and unregister queues.
+///    stop-----    Stop agent threads and unregister queues, if any.
 ///      |          Stopped devices can be started again.
 ///      v
 ///    drop         Close the device.
 /// ```
-#[allow(missing_copy_implementations)]
-pub(crate) struct EthDev {
+///
+/// A port not probed by `eal::Config::device_probe` can be driven directly:
+///
+/// ```no_run
+/// use async_dpdk::eth_dev::{DevConfig, EthDev};
+///
+/// let mut dev = EthDev::new(2, 1, 1, &DevConfig::new()).unwrap();
+/// dev.start().unwrap();
+/// let mut rxq = dev.rx_queue(0).unwrap();
+/// let mut txq = dev.tx_queue(0).unwrap();
+/// let mut pkts = Vec::new();
+/// loop {
+///     // Send the received frames back.
+///     let _n = rxq.recv(&mut pkts, 32);
+///     let _sent = txq.send(&mut pkts);
+/// }
+/// ```
+#[allow(missing_copy_implementations, clippy::struct_excessive_bools)]
+pub struct EthDev {
     /// `port_id` identifying this `EthDev`.
     port_id: u16,
     /// `socket_id` that this `EthDev` is on.
     socket_id: i32,
-    /// An agent tx thread if the device is started.
+    /// Agent tx threads if the device is started.
     tx_agent: Option<Arc<TxAgent>>,
-    /// An agent rx thread if the device is started.
-    rx_agent: Option<Arc<RxAgent>>,
+    /// Threads of the `TxAgent`.
+    tx_exec: TxExec,
+    /// Agent rx threads polling each rx queue if the device is started.
+    rx_agents: Vec<Arc<RxAgent>>,
+    /// Where each rx queue is polled.
+    rx_exec: Vec<RxExec>,
     /// An agent tx thread if the device is started.
     tx_queue: Vec<Arc<EthTxQueue>>,
     /// `EthRxQueue` for each queue.
     rx_queue: Vec<Arc<EthRxQueue>>,
     /// `TxSender` to send `Mbuf`s to `tx_queue`.
-    tx_chan: Vec<Option<mpsc::Sender<Mbuf>>>,
+    tx_chan: Vec<Option<TxChannel>>,
+    /// RSS configuration, whose RETA is applied once the device is started.
+    rss: RssConfig,
+    /// Flow rules created on the device, which are destroyed before the device is closed.
+    flows: Mutex<HashMap<FlowId, Flow>>,
+    /// Identifier for the next created flow rule.
+    next_flow_id: AtomicU64,
+    /// Settings of the device shared with the agents, e.g. the MTU to fragment packets.
+    conf: Arc<PortConf>,
+    /// Whether the queues are polled through `RxQueue` and `TxQueue` instead of agents.
+    direct: bool,
+    /// Whether the device is started.
+    started: bool,
+    /// Whether the device is configured by the primary process, and attached to by this
+    /// secondary process.
+    attached: bool,
+    /// Whether the port is closed by `EthDev::close`.
+    closed: bool,
 }
 
 #[allow(unsafe_code)]
@@ -66,140 +170,472 @@ impl EthDev {
         unsafe { u32::from(rte_eth_dev_count_avail()) }
     }
 
-    /// Create an instance of `EthDev`.
+    /// Create an `EthDev` on `port_id` with `n_rxq` rx queues and `n_txq` tx queues, which are
+    /// polled by the application through `RxQueue` and `TxQueue` once it's started. Flows are
+    /// spread over multiple rx queues with the default `RssConfig`.
+    ///
+    /// The port should not be probed by `eal::Config::device_probe`, whose devices are polled by
+    /// agent threads.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///  - `Error::NoDev`: invalid `port_id`.
+    ///  - `Error::InvalidArg`: invalid `n_rxq` or `n_txq`.
+    ///  - `Error::NotSupported`: the offloads in `conf` are not supported by the device.
+    ///  - Failed to configure the device or to setup its queues.
+    #[inline]
+    #[allow(clippy::similar_names)] // tx and rx are DPDK terms
+    pub fn new(port_id: u16, n_rxq: u16, n_txq: u16, conf: &DevConfig) -> Result<Self> {
+        let mut dev = Self::with_agents(
+            port_id,
+            n_rxq,
+            n_txq,
+            RssConfig::default(),
+            conf,
+            None,
+            &BTreeMap::new(),
+            None,
+        )?;
+        dev.direct = true;
+        Ok(dev)
+    }
+
+    /// Attach to the port named `name` in a secondary process, polling its queues directly
+    /// through `RxQueue` and `TxQueue`.
+    ///
+    /// The port should be configured and started by a primary process of this crate, whose
+    /// mempools of the queues are looked up by name. It can't be started or stopped by the
+    /// secondary process, and is left to the primary process when the `EthDev` is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///  - `Error::Secondary`: called from the primary process.
+    ///  - `Error::NoDev`: no port is named `name`.
+    ///  - Failed to find the mempools of the queues.
+    #[inline]
+    pub fn attach(name: &str) -> Result<Self> {
+        if eal::process_type() != ProcessType::Secondary {
+            return Err(Error::Secondary);
+        }
+        let port_id = port_by_name(name)?;
+        let mut dev_info = MaybeUninit::<rte_eth_dev_info>::uninit();
+        // SAFETY: the returned `dev_info` is to be verified with the check on errno
+        let errno = unsafe { rte_eth_dev_info_get(port_id, dev_info.as_mut_ptr()) };
+        Error::from_ret(errno, "rte_eth_dev_info_get").map_err(|e| e.with_port(port_id))?;
+        // SAFETY: `dev_info` init in `rte_eth_dev_info_get`
+        let dev_info = unsafe { dev_info.assume_init() };
+        // SAFETY: ffi
+        let socket_id = unsafe { rte_eth_dev_socket_id(port_id) };
+        let tx_queue = (0..dev_info.nb_tx_queues)
+            .map(|queue_id| {
+                Ok(Arc::new(EthTxQueue {
+                    queue_id,
+                    mp: PktMempool::lookup(&format!("tx_{port_id}_{queue_id}"))?,
+                    offloads: 0,
+                    taken: AtomicBool::new(false),
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let rx_queue = (0..dev_info.nb_rx_queues)
+            .map(|queue_id| {
+                Ok(Arc::new(EthRxQueue {
+                    queue_id,
+                    _mp: PktMempool::lookup(&format!("rx_{port_id}_{queue_id}"))?,
+                    taken: AtomicBool::new(false),
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        #[allow(clippy::cast_possible_truncation)] // 1500 < u16::MAX
+        let conf = PortConf::new(RTE_ETHER_MTU as u16, 0, None);
+        Ok(Self {
+            port_id,
+            socket_id,
+            tx_agent: None,
+            tx_exec: TxExec::default(),
+            rx_agents: Vec::new(),
+            rx_exec: Vec::new(),
+            tx_chan: tx_queue.iter().map(|_| None).collect(),
+            tx_queue,
+            rx_queue,
+            rss: RssConfig::default(),
+            flows: Mutex::new(HashMap::new()),
+            next_flow_id: AtomicU64::new(0),
+            conf: Arc::new(conf),
+            direct: true,
+            started: true,
+            attached: true,
+            closed: false,
+        })
+    }
+
+    /// Create an instance of `EthDev`, whose queues are polled by agent threads once it's started.
     ///
     /// During this process, it does some initialization to the device:
-    ///  1. Confugure the number of tx / rx queues.
-    ///  2. Adjust the number of tx / rx desc.
+    ///  1. Confugure the number of tx / rx queues, enabling RSS if there are multiple rx queues,
+    ///     and the MTU, enabling multi-segment packets if it doesn't fit in an `Mbuf`.
+    ///  2. Adjust the number of tx / rx desc, and create the mempools of the queues, as set in
+    ///     `dev_conf`.
     ///
     /// # Errors
     ///
     /// Possible reasons:
     ///  - `Error::NotSupported`: this device does not support getting info.
     ///  - `Error::NoDev`: invalid `port_id`.
-    ///  - `Error::InvalidArg`: invalid `n_rxq` or `n_txq`.
+    ///  - `Error::InvalidArg`: invalid `n_rxq` or `n_txq`, invalid RSS hash key length, `mtu` out
+    ///    of the range supported by the device, or a tx rate limit of 0.
+    ///  - `Error::NotSupported`: jumbo frames or the offloads in `dev_conf` are not supported by
+    ///    the device.
     ///  - Failed to configure devices.
     ///  - Failed to setup `RxQueue` and `TxQueue`.
     #[inline]
     #[allow(clippy::similar_names)] // tx and rx are DPDK terms
-    pub(crate) fn new(port_id: u16, n_rxq: u16, n_txq: u16) -> Result<Self> {
+    #[allow(clippy::too_many_arguments)] // device settings collected by `eal::Config`
+    pub(crate) fn with_agents(
+        port_id: u16,
+        n_rxq: u16,
+        n_txq: u16,
+        rss: RssConfig,
+        dev_conf: &DevConfig,
+        mtu: Option<u16>,
+        rx_exec: &BTreeMap<u16, RxExec>,
+        rx_intr: Option<Duration>,
+    ) -> Result<Self> {
         let mut dev_info = MaybeUninit::<rte_eth_dev_info>::uninit();
         // SAFETY: the returned `dev_info` is to be verified with the check on errno
         let errno = unsafe { rte_eth_dev_info_get(port_id, dev_info.as_mut_ptr()) };
-        Error::from_ret(errno)?;
+        Error::from_ret(errno, "rte_eth_dev_info_get").map_err(|e| e.with_port(port_id))?;
         // SAFETY: `dev_info` init in `rte_eth_dev_info_get`
         let dev_info = unsafe { dev_info.assume_init() };
 
-        let eth_conf = MaybeUninit::<rte_eth_conf>::zeroed();
-        // SAFETY: `eth_conf` set to zero, which is valid
-        let mut eth_conf = unsafe { eth_conf.assume_init() };
-        if dev_info.tx_offload_capa & RTE_ETH_TX_OFFLOAD_MBUF_FAST_FREE != 0 {
-            // Enable fast release of mbufs if supported by the hardware.
-            eth_conf.txmode.offloads |= RTE_ETH_TX_OFFLOAD_MBUF_FAST_FREE;
+        let mut eth_conf = Self::default_conf(&dev_info, dev_conf)?;
+        // Get notified of link status changes by interrupts if supported.
+        // SAFETY: `dev_flags` points to the flags of a valid port
+        if !dev_info.dev_flags.is_null()
+            && unsafe { *dev_info.dev_flags } & RTE_ETH_DEV_INTR_LSC != 0
+        {
+            eth_conf.intr_conf.set_lsc(1);
         }
-        // SAFETY: `eth_conf` is ok to be zerod, representing default configuration
-        #[allow(clippy::shadow_unrelated)] // is related
-        let errno = unsafe { rte_eth_dev_configure(port_id, n_rxq, n_txq, &eth_conf) };
-        Error::from_ret(errno)?;
-        log::trace!("Device {port_id} successfully configured");
-        let mut n_rxd = 1024;
-        let mut n_txd = 1024;
+        #[allow(clippy::cast_possible_truncation)] // 1500 < u16::MAX
+        let mtu = match mtu {
+            Some(mtu) => {
+                if mtu < dev_info.min_mtu || mtu > dev_info.max_mtu {
+                    return Err(Error::InvalidArg);
+                }
+                eth_conf.rxmode.mtu = u32::from(mtu);
+                let frame_len = u32::from(mtu)
+                    .wrapping_add(RTE_ETHER_HDR_LEN)
+                    .wrapping_add(RTE_ETHER_CRC_LEN);
+                if frame_len > RTE_MBUF_DEFAULT_BUF_SIZE.wrapping_sub(RTE_PKTMBUF_HEADROOM) {
+                    // Jumbo frames are received into chained `Mbuf`s, and sent from them if the
+                    // device supports multi-segment packets, see `default_conf`.
+                    if dev_info.rx_offload_capa & RTE_ETH_RX_OFFLOAD_SCATTER == 0 {
+                        return Err(Error::NotSupported);
+                    }
+                    eth_conf.rxmode.offloads |= RTE_ETH_RX_OFFLOAD_SCATTER;
+                }
+                mtu
+            }
+            None => RTE_ETHER_MTU as u16,
+        };
+        // `rss_key` is read during `rte_eth_dev_configure`, keep it alive until then.
+        let mut rss_key = rss.key.clone();
+        if n_rxq > 1 {
+            // Spread flows over all rx queues.
+            eth_conf.rxmode.mq_mode = rte_eth_rx_mq_mode_RTE_ETH_MQ_RX_RSS;
+            eth_conf.rx_adv_conf.rss_conf = rss.rss_conf(&mut rss_key, &dev_info)?;
+        }
+        if rx_intr.is_some() {
+            eth_conf.intr_conf.set_rxq(1);
+        }
+        let rx_intr = Self::configure(port_id, n_rxq, n_txq, &mut eth_conf, rx_intr)?;
+        trace!("Device {port_id} successfully configured");
+        let mut n_rxd = dev_conf.n_rxd;
+        let mut n_txd = dev_conf.n_txd;
         // SAFETY: ffi
         #[allow(clippy::shadow_unrelated)] // is related
         let errno = unsafe { rte_eth_dev_adjust_nb_rx_tx_desc(port_id, &mut n_rxd, &mut n_txd) };
-        Error::from_ret(errno)?;
+        Error::from_ret(errno, "rte_eth_dev_adjust_nb_rx_tx_desc")
+            .map_err(|e| e.with_port(port_id))?;
         // SAFETY: returned `socket_id` to be checked later
         let socket_id = unsafe { rte_eth_dev_socket_id(port_id) };
-        if socket_id < 0 {
+        // Devices on kernel interfaces, e.g. TAP and AF_XDP, are on `SOCKET_ID_ANY`, i.e. -1.
+        // SAFETY: ffi
+        if socket_id < 0 && unsafe { rte_eth_dev_is_valid_port(port_id) } == 0 {
             return Err(Error::InvalidArg); // port_id is invalid
         }
 
-        let nb_ports = EthDev::available_ports();
-        let n_elem = nb_ports
-            .saturating_mul(
-                u32::from(n_rxd)
-                    .saturating_add(u32::from(n_txd))
-                    .saturating_add(32),
-            )
-            .max(8192);
+        let n_elem = dev_conf.rx_pool_len(n_rxd, n_txd);
 
         let mut tx_queue = vec![];
         let mut rx_queue = vec![];
 
         for queue_id in 0..n_txq {
+            let mp = dev_conf.queue_pool(
+                &format!("tx_{port_id}_{queue_id}"),
+                dev_conf.tx_pool_size,
+                socket_id,
+            )?;
             tx_queue.push(EthTxQueue::init(
-                port_id, queue_id, socket_id, n_txd, &dev_info, &eth_conf,
+                port_id, queue_id, socket_id, n_txd, mp, &dev_info, &eth_conf,
             )?);
-            log::trace!("Device {port_id} successfully initialized tx_queue {queue_id}");
+            trace!("Device {port_id} successfully initialized tx_queue {queue_id}");
         }
         for queue_id in 0..n_rxq {
+            let mp = dev_conf.queue_pool(&format!("rx_{port_id}_{queue_id}"), n_elem, socket_id)?;
             rx_queue.push(EthRxQueue::init(
-                port_id, queue_id, socket_id, n_rxd, n_elem, &dev_info, &eth_conf,
+                port_id, queue_id, socket_id, n_rxd, mp, &dev_info, &eth_conf,
             )?);
-            log::trace!("Device {port_id} successfully initialized rx_queue {queue_id}");
+            trace!("Device {port_id} successfully initialized rx_queue {queue_id}");
         }
 
         let tx_chan = (0..n_txq).map(|_| None).collect();
+        let rx_exec = (0..n_rxq)
+            .map(|queue_id| rx_exec.get(&queue_id).copied().unwrap_or_default())
+            .collect();
+        let mut conf = PortConf::new(mtu, eth_conf.txmode.offloads, rx_intr);
+        conf.tx_weights = dev_conf.tx_weights;
+        if let Some(limit) = dev_conf.tx_rate_limit {
+            limit.validate()?;
+        }
+        conf.tx_rate_limit = dev_conf.tx_rate_limit;
+        if let Some(policer) = dev_conf.tx_policer {
+            let _meter = Meter::new(policer.profile())?;
+        }
+        conf.tx_policer = dev_conf.tx_policer;
+        if dev_conf.rx_timestamp {
+            conf.rx_timestamp = true;
+            timestamp::enable_rx();
+        }
 
         Ok(Self {
             port_id,
             socket_id,
             tx_agent: None,
-            rx_agent: None,
+            tx_exec: dev_conf.tx_exec,
+            rx_agents: Vec::new(),
+            rx_exec,
             tx_queue,
             rx_queue,
             tx_chan,
+            rss,
+            flows: Mutex::new(HashMap::new()),
+            next_flow_id: AtomicU64::new(0),
+            conf: Arc::new(conf),
+            direct: false,
+            started: false,
+            attached: false,
+            closed: false,
         })
     }
 
+    /// The configuration enabling offloads supported by the device, along with those in
+    /// `dev_conf`, which fails with `Error::NotSupported` if they are not supported.
+    fn default_conf(dev_info: &rte_eth_dev_info, dev_conf: &DevConfig) -> Result<rte_eth_conf> {
+        let eth_conf = MaybeUninit::<rte_eth_conf>::zeroed();
+        // SAFETY: `eth_conf` set to zero, which is valid
+        let mut eth_conf = unsafe { eth_conf.assume_init() };
+        if dev_info.tx_offload_capa & RTE_ETH_TX_OFFLOAD_MBUF_FAST_FREE != 0 {
+            // Enable fast release of mbufs if supported by the hardware.
+            eth_conf.txmode.offloads |= RTE_ETH_TX_OFFLOAD_MBUF_FAST_FREE;
+        }
+        // Offload checksums to the hardware if supported.
+        eth_conf.txmode.offloads |= dev_info.tx_offload_capa
+            & (RTE_ETH_TX_OFFLOAD_IPV4_CKSUM
+                | RTE_ETH_TX_OFFLOAD_UDP_CKSUM
+                | RTE_ETH_TX_OFFLOAD_TCP_CKSUM);
+        eth_conf.rxmode.offloads |= dev_info.rx_offload_capa
+            & (RTE_ETH_RX_OFFLOAD_IPV4_CKSUM | RTE_ETH_RX_OFFLOAD_UDP_CKSUM);
+        // Let the hardware timestamp received packets if requested and supported.
+        if dev_conf.rx_timestamp {
+            eth_conf.rxmode.offloads |= dev_info.rx_offload_capa & RTE_ETH_RX_OFFLOAD_TIMESTAMP;
+        }
+        // Send packets held by chained `Mbuf`s, e.g. jumbo frames or shared fragments attached
+        // without copying, if supported.
+        eth_conf.txmode.offloads |= dev_info.tx_offload_capa & RTE_ETH_TX_OFFLOAD_MULTI_SEGS;
+        // Let the hardware fragment large UDP datagrams held by chained `Mbuf`s with GSO enabled.
+        let udp_tso = RTE_ETH_TX_OFFLOAD_UDP_TSO | RTE_ETH_TX_OFFLOAD_MULTI_SEGS;
+        if dev_info.tx_offload_capa & udp_tso == udp_tso {
+            eth_conf.txmode.offloads |= udp_tso;
+        }
+        // Let the hardware split large TCP writes into segments, computing their checksums.
+        let tcp_tso = RTE_ETH_TX_OFFLOAD_TCP_TSO
+            | RTE_ETH_TX_OFFLOAD_TCP_CKSUM
+            | RTE_ETH_TX_OFFLOAD_IPV4_CKSUM
+            | RTE_ETH_TX_OFFLOAD_MULTI_SEGS;
+        if dev_info.tx_offload_capa & tcp_tso == tcp_tso {
+            eth_conf.txmode.offloads |= tcp_tso;
+        }
+        // Offloads requested by the user.
+        if dev_conf.rx_offloads & !dev_info.rx_offload_capa != 0
+            || dev_conf.tx_offloads & !dev_info.tx_offload_capa != 0
+        {
+            return Err(Error::NotSupported);
+        }
+        eth_conf.rxmode.offloads |= dev_conf.rx_offloads;
+        eth_conf.txmode.offloads |= dev_conf.tx_offloads;
+        Ok(eth_conf)
+    }
+
+    /// Configure the device, retrying without RX interrupts if they are not supported. Returns
+    /// `rx_intr` if RX interrupts are enabled, or `None` otherwise.
+    #[allow(clippy::similar_names)] // tx and rx are DPDK terms
+    fn configure(
+        port_id: u16,
+        n_rxq: u16,
+        n_txq: u16,
+        eth_conf: &mut rte_eth_conf,
+        rx_intr: Option<Duration>,
+    ) -> Result<Option<Duration>> {
+        // SAFETY: `eth_conf` is ok to be zerod, representing default configuration
+        let errno = unsafe { rte_eth_dev_configure(port_id, n_rxq, n_txq, eth_conf) };
+        match Error::from_ret(errno, "rte_eth_dev_configure").map_err(|e| e.with_port(port_id)) {
+            Ok(()) => Ok(rx_intr),
+            Err(e) if e.kind() == ErrorKind::NotSupported && rx_intr.is_some() => {
+                warn!("Rx interrupts not supported by device {port_id}, keep busy polling");
+                eth_conf.intr_conf.set_rxq(0);
+                // SAFETY: `eth_conf` is ok to be zerod, representing default configuration
+                let errno = unsafe { rte_eth_dev_configure(port_id, n_rxq, n_txq, eth_conf) };
+                Error::from_ret(errno, "rte_eth_dev_configure")
+                    .map_err(|e| e.with_port(port_id))?;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// TX offloads negotiated with the device, in `RTE_ETH_TX_OFFLOAD_*`, which are the checksum
+    /// and segmentation offloads supported by the device, along with those requested by
+    /// `DevConfig::tx_offloads`.
+    #[inline]
+    #[must_use]
+    pub fn tx_offloads(&self) -> u64 {
+        self.conf.tx_offloads
+    }
+
     /// Get port id.
     #[inline]
     #[must_use]
-    pub(crate) fn port_id(&self) -> u16 {
+    pub fn port_id(&self) -> u16 {
         self.port_id
     }
 
+    /// Get the contextual information of the device.
+    fn dev_info(&self) -> Result<rte_eth_dev_info> {
+        let mut dev_info = MaybeUninit::<rte_eth_dev_info>::uninit();
+        // SAFETY: the returned `dev_info` is to be verified with the check on errno
+        let errno = unsafe { rte_eth_dev_info_get(self.port_id, dev_info.as_mut_ptr()) };
+        Error::from_ret(errno, "rte_eth_dev_info_get").map_err(|e| e.with_port(self.port_id))?;
+        // SAFETY: `dev_info` init in `rte_eth_dev_info_get`
+        Ok(unsafe { dev_info.assume_init() })
+    }
+
+    /// Get the generic device backing the port, which is used to detach it.
+    pub(crate) fn device(&self) -> Result<*mut rte_device> {
+        Ok(self.dev_info()?.device)
+    }
+
+    /// Get the arguments the underlying device is probed with, e.g. `net_ring0` or
+    /// `0000:08:00.0,rxq_cqe_comp_en=0`, which probe it again once it's removed.
+    pub(crate) fn devargs(&self) -> Result<String> {
+        let device = self.device()?;
+        // SAFETY: the device and its arguments are valid while the port is
+        unsafe {
+            let name = CStr::from_ptr((*device).name)
+                .to_str()
+                .map_err(|_| Error::InvalidArg)?;
+            let devargs = (*device).devargs;
+            let args = if devargs.is_null() {
+                ptr::null()
+            } else {
+                (*devargs).__bindgen_anon_1.args
+            };
+            if args.is_null() || *args == 0 {
+                return Ok(name.to_owned());
+            }
+            let args = CStr::from_ptr(args)
+                .to_str()
+                .map_err(|_| Error::InvalidArg)?;
+            Ok(format!("{name},{args}"))
+        }
+    }
+
+    /// Get the name of the port, which is that of the underlying device.
+    pub(crate) fn name(&self) -> Result<String> {
+        let mut name: [c_char; RTE_ETH_NAME_MAX_LEN as usize] = [0; RTE_ETH_NAME_MAX_LEN as usize];
+        // SAFETY: `name` holds `RTE_ETH_NAME_MAX_LEN` bytes
+        let errno = unsafe { rte_eth_dev_get_name_by_port(self.port_id, name.as_mut_ptr()) };
+        Error::from_ret(errno, "rte_eth_dev_get_name_by_port")
+            .map_err(|e| e.with_port(self.port_id))?;
+        #[allow(clippy::cast_sign_loss)] // C string bytes
+        let name: Vec<u8> = name
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        String::from_utf8(name).map_err(|_| Error::InvalidArg)
+    }
+
     /// Start an Ethernet device.
     ///
-    /// Register all `TxQueue`s and `RxQueue`s on agent threads and start polling. On success, all
-    /// basic functions exported by the Ethernet API (link status, receive/transmit, and so on)
-    /// can be invoked.
+    /// Unless the device is created by `EthDev::new`, register all tx queues and rx queues on
+    /// agent threads and start polling. On success, all basic functions exported by the Ethernet
+    /// API (link status, receive/transmit, and so on) can be invoked. Rx queues with the same
+    /// `RxExec` are polled by the same agent thread, which is shared with other devices if it runs
+    /// on an lcore, a service lcore or a CPU core.
     ///
     /// # Errors
     ///
     /// Possible reasons:
     /// - `Error::TempUnavail`: temporary error, retry later.
-    /// - Failed to create a `TxAgent`.
+    /// - Failed to create a `TxAgent`, e.g. its threads are not available on the CPU cores in
+    ///   `DevConfig::tx_exec`.
+    /// - Failed to start `RxAgent`s on the lcores or CPU cores given.
     /// - Failed to register queues on `TxAgent` and `RxAgent`.
+    /// - `Error::Secondary`: the device is attached to by `EthDev::attach`.
     #[inline]
-    pub(crate) fn start(&mut self) -> Result<()> {
-        // XXX now we use one TxAgent and one RxAgent for each EthDev.
-        // Make the mapping more flexible.
-        let rx_agent = RxAgent::start(self.socket_id);
-        let tx_agent = TxAgent::start();
-
+    pub fn start(&mut self) -> Result<()> {
+        if self.attached {
+            return Err(Error::Secondary);
+        }
         // SAFETY: `port_id` validity verified
         let errno = unsafe { rte_eth_dev_start(self.port_id) };
-        Error::from_ret(errno)?;
-        log::debug!("Device {} successfully started", self.port_id);
+        Error::from_ret(errno, "rte_eth_dev_start").map_err(|e| e.with_port(self.port_id))?;
+        debug!("Device {} successfully started", self.port_id);
         // SAFETY: `ptypes` is ok to be NULL
         #[allow(clippy::shadow_unrelated)] // is related
         let errno = unsafe { rte_eth_dev_set_ptypes(self.port_id, 0, ptr::null_mut(), 0) };
-        Error::from_ret(errno)?;
+        Error::from_ret(errno, "rte_eth_dev_set_ptypes").map_err(|e| e.with_port(self.port_id))?;
+        if let Some(ref reta) = self.rss.reta {
+            self.rss_reta_update(reta)?;
+        }
+        self.started = true;
+        if self.direct {
+            return Ok(());
+        }
 
+        // Each EthDev has its own TxAgent, with threads as `DevConfig::tx_exec` specifies.
+        #[allow(clippy::cast_possible_truncation)] // self.tx_queue.len() checked
+        let tx_agent = TxAgent::start(self.tx_exec, self.tx_queue.len() as u16)?;
         // Start tx agent
         #[allow(clippy::cast_possible_truncation)] // self.tx_queue.len() checked
         for (queue_id, chan) in self.tx_chan.iter_mut().enumerate() {
-            *chan = Some(tx_agent.register(self.port_id, queue_id as _)?);
+            *chan = Some(tx_agent.register(self.port_id, queue_id as _, Arc::clone(&self.conf))?);
         }
 
-        // Start rx agent
+        // Start rx agents
         #[allow(clippy::cast_possible_truncation)] // self.rx_queue.len() checked
-        for (queue_id, _) in self.rx_queue.iter().enumerate() {
-            rx_agent.register(self.port_id, queue_id as _)?;
+        for (queue_id, &exec) in self.rx_exec.iter().enumerate() {
+            self.rx_agents.push(RxAgent::attach(
+                self.socket_id,
+                exec,
+                self.port_id,
+                queue_id as _,
+                Arc::clone(&self.conf),
+            )?);
         }
 
-        self.rx_agent = Some(rx_agent);
         self.tx_agent = Some(tx_agent);
 
         Ok(())
@@ -210,146 +646,1306 @@ impl EthDev {
     /// # Errors
     ///
     /// Possible reasons:
+    ///  - `Error::NotStart`: the device is not started.
     ///  - Agent threads are terminated.
     ///  - `Error::Busy`: unable to stop the device.
+    ///  - `Error::Secondary`: the device is attached to by `EthDev::attach`.
+    #[inline]
+    pub fn stop(&mut self) -> Result<()> {
+        if self.attached {
+            return Err(Error::Secondary);
+        }
+        if self.direct {
+            if !self.started {
+                return Err(Error::NotStart);
+            }
+        } else {
+            self.stop_agents()?;
+        }
+        self.started = false;
+        // SAFETY: `port_id` validity verified
+        let errno = unsafe { rte_eth_dev_stop(self.port_id) };
+        Error::from_ret(errno, "rte_eth_dev_stop").map_err(|e| e.with_port(self.port_id))?;
+        debug!("Device {} successfully stopped", self.port_id);
+        Ok(())
+    }
+
+    /// Close the device, destroying its flow rules and releasing its queues. The device is stopped
+    /// first if it's started.
+    ///
+    /// The device is also closed when the `EthDev` is dropped, where failures are only reported
+    /// through `set_error_hook`. A device attached to by `EthDev::attach` is not closed, which is
+    /// done by the primary process.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Unable to stop or close the device.
     #[inline]
-    pub(crate) fn stop(&mut self) -> Result<()> {
-        let rx_agent = self.rx_agent.take().ok_or(Error::BrokenPipe)?;
+    pub fn close(mut self) -> Result<()> {
+        if self.started {
+            self.stop()?;
+        }
+        self.close_port()
+    }
+
+    /// Destroy the flow rules and close the port once, unless it's attached to.
+    fn close_port(&mut self) -> Result<()> {
+        if let Ok(flows) = self.flows.get_mut() {
+            flows.clear();
+        }
+        if self.attached || self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        // SAFETY: `port_id` validity verified
+        let errno = unsafe { rte_eth_dev_close(self.port_id) };
+        Error::from_ret(errno, "rte_eth_dev_close").map_err(|e| e.with_port(self.port_id))
+    }
+
+    /// Unregister all queues from the agents.
+    fn stop_agents(&mut self) -> Result<()> {
+        if self.rx_agents.is_empty() {
+            return Err(Error::BrokenPipe);
+        }
+        let rx_agents = mem::take(&mut self.rx_agents);
         let tx_agent = self.tx_agent.take().ok_or(Error::BrokenPipe)?;
 
         #[allow(clippy::cast_possible_truncation)] // self.tx_queue.len() checked
         for (queue_id, _) in self.tx_queue.iter().enumerate() {
-            tx_agent.unregister(self.port_id, queue_id as _)?;
+            // Buffered packets are sent before the queue is unregistered.
+            let _dropped = tx_agent.unregister(self.port_id, queue_id as _)?;
         }
 
+        // Agents left without queues are stopped.
         #[allow(clippy::cast_possible_truncation)] // self.rx_queue.len() checked
-        for (queue_id, _) in self.rx_queue.iter().enumerate() {
-            rx_agent.unregister(self.port_id, queue_id as _)?;
+        for (queue_id, rx_agent) in rx_agents.iter().enumerate() {
+            rx_agent.detach(self.port_id, queue_id as _)?;
         }
-
-        rx_agent.stop();
-        // SAFETY: `port_id` validity verified
-        let errno = unsafe { rte_eth_dev_stop(self.port_id) };
-        Error::from_ret(errno)?;
-        log::debug!("Device {} successfully stopped", self.port_id);
         Ok(())
     }
 
+    /// Take the handle of rx queue `queue_id`, polling it directly. Each queue has one handle at
+    /// a time, which is given back when dropped.
+    ///
+    /// If the queues are polled by agent threads, the agent stops polling the queue before the
+    /// handle is returned, and resumes once it's dropped. Meanwhile, frames received on the queue
+    /// are only seen by the handle, so sockets don't receive them even if they are addressed to
+    /// them.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///  - `Error::NotStart`: the device is not started.
+    ///  - `Error::InvalidArg`: invalid `queue_id`.
+    ///  - `Error::Busy`: the handle is taken.
+    ///  - Agent threads are terminated.
+    #[inline]
+    pub fn rx_queue(&self, queue_id: u16) -> Result<RxQueue<'_>> {
+        if !self.started {
+            return Err(Error::NotStart);
+        }
+        let queue = self
+            .rx_queue
+            .get(usize::from(queue_id))
+            .ok_or(Error::InvalidArg)?;
+        if queue.taken.swap(true, Ordering::AcqRel) {
+            return Err(Error::Busy);
+        }
+        let agent = if self.direct {
+            None
+        } else {
+            let agent = self.rx_agents.get(usize::from(queue_id));
+            let res = agent
+                .ok_or(Error::NotStart)
+                .and_then(|agent| agent.unregister(self.port_id, queue_id));
+            if let Err(e) = res {
+                queue.taken.store(false, Ordering::Release);
+                return Err(e);
+            }
+            agent.map(|agent| (agent, &self.conf))
+        };
+        Ok(RxQueue {
+            port_id: self.port_id,
+            queue,
+            agent,
+        })
+    }
+
+    /// Take the handle of tx queue `queue_id`, sending to it directly. Each queue has one handle
+    /// at a time, which is given back when dropped.
+    ///
+    /// If the queues are served by agent threads, the agent stops sending to the queue before the
+    /// handle is returned, and resumes once it's dropped. Meanwhile, packets sent by sockets are
+    /// held in the tx buffer of the queue, and fail with `Error::NoBuf` once it's full, so the
+    /// handle should be held briefly.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///  - `Error::NotStart`: the device is not started.
+    ///  - `Error::InvalidArg`: invalid `queue_id`.
+    ///  - `Error::Busy`: the handle is taken.
+    ///  - `Error::TimedOut`: the agent serving the queue doesn't respond.
+    ///  - Agent threads are terminated.
+    #[inline]
+    pub fn tx_queue(&self, queue_id: u16) -> Result<TxQueue<'_>> {
+        if !self.started {
+            return Err(Error::NotStart);
+        }
+        let queue = self
+            .tx_queue
+            .get(usize::from(queue_id))
+            .ok_or(Error::InvalidArg)?;
+        if queue.taken.swap(true, Ordering::AcqRel) {
+            return Err(Error::Busy);
+        }
+        let agent = if self.direct {
+            None
+        } else {
+            let res = self
+                .tx_agent
+                .as_ref()
+                .ok_or(Error::NotStart)
+                .and_then(|agent| agent.pause(self.port_id, queue_id, true));
+            if let Err(e) = res {
+                queue.taken.store(false, Ordering::Release);
+                return Err(e);
+            }
+            self.tx_agent.as_ref()
+        };
+        Ok(TxQueue {
+            port_id: self.port_id,
+            queue,
+            agent,
+        })
+    }
+
     /// Get a `TxSender`.
     ///
     /// This function returns None if the `queue_id` is invalid or the queue is
     /// not registered yet.
     pub(crate) fn sender(&self, queue_id: u16) -> Option<TxSender> {
-        let chan: mpsc::Sender<Mbuf> = self.tx_chan.get(queue_id as usize)?.clone()?;
+        let TxChannel { requests, ring } = self.tx_chan.get(queue_id as usize)?.clone()?;
         let tx_queue: Arc<EthTxQueue> = Arc::clone(self.tx_queue.get(queue_id as usize)?);
-        Some(TxSender { chan, tx_queue })
+        Some(TxSender {
+            chan: requests,
+            ring,
+            tx_queue,
+        })
     }
 
     /// Get MAC address.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///  - `Error::NoDev`: the device is detached.
     #[inline]
-    pub(crate) fn mac_addr(&self) -> Result<rte_ether_addr> {
+    pub fn mac_addr(&self) -> Result<[u8; ETHER_ADDR_LEN]> {
+        Ok(self.ether_addr()?.addr_bytes)
+    }
+
+    /// Get MAC address as an `rte_ether_addr`.
+    pub(crate) fn ether_addr(&self) -> Result<rte_ether_addr> {
         let mut ether_addr = MaybeUninit::<rte_ether_addr>::uninit();
         // SAFETY: errno checked later
         let errno = unsafe { rte_eth_macaddr_get(self.port_id, ether_addr.as_mut_ptr()) };
-        Error::from_ret(errno)?;
+        Error::from_ret(errno, "rte_eth_macaddr_get").map_err(|e| e.with_port(self.port_id))?;
         // SAFETY: `rte_ether_addr` is successfully initialized due to no error code.
         Ok(unsafe { ether_addr.assume_init() })
     }
-}
 
-impl Drop for EthDev {
+    /// Replace the default MAC address, which is used as the source address of sent frames.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///  - `Error::NoDev`: the device is detached.
+    ///  - `Error::NotSupported`: the device doesn't support it.
+    ///  - `Error::InvalidArg`: `addr` is not a unicast address.
     #[inline]
-    fn drop(&mut self) {
-        // SAFETY: `port_id` validity verified
-        #[allow(unsafe_code)]
-        let errno = unsafe { rte_eth_dev_close(self.port_id) };
-        if errno < 0 {
-            Error::parse_err(errno);
-        }
+    pub fn set_mac_addr(&self, addr: [u8; ETHER_ADDR_LEN]) -> Result<()> {
+        let mut addr = rte_ether_addr { addr_bytes: addr };
+        // SAFETY: `addr` is a valid address
+        let errno =
+            unsafe { rte_eth_dev_default_mac_addr_set(self.port_id, ptr::addr_of_mut!(addr)) };
+        Error::from_ret(errno, "rte_eth_dev_default_mac_addr_set")
+            .map_err(|e| e.with_port(self.port_id))
     }
-}
 
-// SAFETY: no thread-local data involved.
-#[allow(unsafe_code)]
-unsafe impl Send for EthDev {}
+    /// Add a secondary MAC address, so that frames sent to it are received as well.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///  - `Error::NoDev`: the device is detached.
+    ///  - `Error::NotSupported`: the device doesn't support it.
+    ///  - `Error::InvalidArg`: `addr` is all zeros.
+    ///  - `Error::NoSpace`: no more addresses can be added.
+    #[inline]
+    pub fn add_mac_addr(&self, addr: [u8; ETHER_ADDR_LEN]) -> Result<()> {
+        let mut addr = rte_ether_addr { addr_bytes: addr };
+        // SAFETY: `addr` is a valid address
+        let errno = unsafe { rte_eth_dev_mac_addr_add(self.port_id, ptr::addr_of_mut!(addr), 0) };
+        Error::from_ret(errno, "rte_eth_dev_mac_addr_add").map_err(|e| e.with_port(self.port_id))
+    }
 
-// SAFETY: EthDev can be globally accessed.
-#[allow(unsafe_code)]
-unsafe impl Sync for EthDev {}
+    /// Remove a secondary MAC address added by `add_mac_addr`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///  - `Error::NoDev`: the device is detached.
+    ///  - `Error::NotSupported`: the device doesn't support it.
+    ///  - `Error::InvalidArg`: `addr` is the default MAC address.
+    #[inline]
+    pub fn remove_mac_addr(&self, addr: [u8; ETHER_ADDR_LEN]) -> Result<()> {
+        let mut addr = rte_ether_addr { addr_bytes: addr };
+        // SAFETY: `addr` is a valid address
+        let errno = unsafe { rte_eth_dev_mac_addr_remove(self.port_id, ptr::addr_of_mut!(addr)) };
+        Error::from_ret(errno, "rte_eth_dev_mac_addr_remove").map_err(|e| e.with_port(self.port_id))
+    }
 
-impl Debug for EthDev {
+    /// Get all MAC addresses of the device, starting with the default one.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///  - `Error::NoDev`: the device is detached.
     #[inline]
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("EthDev")
-            .field("port_id", &self.port_id)
-            .field("socket_id", &self.socket_id)
-            .finish()
+    pub fn mac_addrs(&self) -> Result<Vec<[u8; ETHER_ADDR_LEN]>> {
+        let max = self.dev_info()?.max_mac_addrs;
+        let mut addrs = vec![
+            rte_ether_addr {
+                addr_bytes: [0; ETHER_ADDR_LEN]
+            };
+            max as usize
+        ];
+        // SAFETY: `addrs` holds `max` addresses
+        let n = unsafe { rte_eth_macaddrs_get(self.port_id, addrs.as_mut_ptr(), max) };
+        Error::from_ret(n, "rte_eth_macaddrs_get").map_err(|e| e.with_port(self.port_id))?;
+        addrs.truncate(n.try_into().map_err(Error::from)?);
+        // Unused slots are all zeros.
+        Ok(addrs
+            .into_iter()
+            .map(|addr| addr.addr_bytes)
+            .filter(|addr| addr != &[0; ETHER_ADDR_LEN])
+            .collect())
     }
-}
 
-/// A wrapper for channel to send Mbuf from socket to `EthTxQueue`.
-#[derive(Debug)]
-pub(crate) struct TxSender {
-    /// The sender held by socket.
-    chan: mpsc::Sender<Mbuf>, // TODO add a oneshot sender
-    /// The `EthTxQueue` that this request is sent to.
-    tx_queue: Arc<EthTxQueue>,
-}
+    /// Get basic statistics of the device.
+    #[inline]
+    pub(crate) fn stats(&self) -> Result<EthStats> {
+        let mut stats = MaybeUninit::<rte_eth_stats>::uninit();
+        // SAFETY: errno checked later
+        let errno = unsafe { rte_eth_stats_get(self.port_id, stats.as_mut_ptr()) };
+        Error::from_ret(errno, "rte_eth_stats_get").map_err(|e| e.with_port(self.port_id))?;
+        // SAFETY: `rte_eth_stats` is successfully initialized due to no error code.
+        Ok(unsafe { stats.assume_init() }.into())
+    }
 
-impl TxSender {
-    /// Send a request to `TxAgent`
-    pub(crate) async fn send(&self, pkt: Packet) -> Result<()> {
-        let m = pkt.into_mbuf(&self.tx_queue.mp)?;
-        self.chan.send(m).await.map_err(Error::from)
+    /// Reset basic statistics of the device.
+    #[inline]
+    pub(crate) fn reset_stats(&self) -> Result<()> {
+        // SAFETY: `port_id` validity verified
+        let errno = unsafe { rte_eth_stats_reset(self.port_id) };
+        Error::from_ret(errno, "rte_eth_stats_reset").map_err(|e| e.with_port(self.port_id))
     }
-}
 
-/// An Ethernet device rx queue.
-#[allow(missing_copy_implementations)]
-#[derive(Debug)]
-struct EthRxQueue {
-    /// The `queue_id` refered to this `EthTxQueue`.
-    #[allow(dead_code)]
-    queue_id: u16,
-    /// `Mempool` to allocate `Mbuf`s to hold the received frames.
-    _mp: PktMempool,
-}
+    /// Get extended statistics of the device, keyed by their names.
+    ///
+    /// The set of extended statistics is driver-specific.
+    #[inline]
+    pub(crate) fn xstats(&self) -> Result<HashMap<String, u64>> {
+        // SAFETY: `xstats_names` is ok to be NULL if `size` is 0
+        let errno = unsafe { rte_eth_xstats_get_names(self.port_id, ptr::null_mut(), 0) };
+        Error::from_ret(errno, "rte_eth_xstats_get_names")
+            .map_err(|e| e.with_port(self.port_id))?;
+        let len: u32 = errno.try_into().map_err(Error::from)?;
 
-/// An Ethernet device tx queue.
-#[allow(missing_copy_implementations)]
-#[derive(Debug)]
-struct EthTxQueue {
-    /// The `queue_id` refered to this `EthTxQueue`.
-    #[allow(dead_code)]
-    queue_id: u16,
-    /// `Mempool` to allocate `Mbuf`s to send.
-    mp: PktMempool,
-}
+        // SAFETY: `rte_eth_xstat_name` set to zero, which is valid
+        let name = unsafe { MaybeUninit::<rte_eth_xstat_name>::zeroed().assume_init() };
+        let mut names = vec![name; len as usize];
+        // SAFETY: `names` holds `len` elements
+        #[allow(clippy::shadow_unrelated)] // is related
+        let errno = unsafe { rte_eth_xstats_get_names(self.port_id, names.as_mut_ptr(), len) };
+        Error::from_ret(errno, "rte_eth_xstats_get_names")
+            .map_err(|e| e.with_port(self.port_id))?;
 
-#[allow(unsafe_code)]
-impl EthRxQueue {
-    /// Init rx queue.
-    ///
-    /// Create a `Mempool` to hold received packets, then setup the queue.
-    fn init(
-        port_id: u16,
-        queue_id: u16,
-        socket_id: i32,
-        n_rxd: u16,
-        n_elem: u32,
-        dev_info: &rte_eth_dev_info,
-        eth_conf: &rte_eth_conf,
-    ) -> Result<Arc<Self>> {
-        let socket_id: u32 = socket_id.try_into().map_err(Error::from)?;
-        let mp = PktMempool::create(format!("rx_{port_id}_{queue_id}").as_str(), n_elem)?;
-        let mut rx_conf = dev_info.default_rxconf;
-        rx_conf.offloads = eth_conf.rxmode.offloads;
+        // SAFETY: `rte_eth_xstat` set to zero, which is valid
+        let xstat = unsafe { MaybeUninit::<rte_eth_xstat>::zeroed().assume_init() };
+        let mut xstats = vec![xstat; len as usize];
+        // SAFETY: `xstats` holds `len` elements
+        #[allow(clippy::shadow_unrelated)] // is related
+        let errno = unsafe { rte_eth_xstats_get(self.port_id, xstats.as_mut_ptr(), len) };
+        Error::from_ret(errno, "rte_eth_xstats_get").map_err(|e| e.with_port(self.port_id))?;
+        let n: usize = errno.try_into().map_err(Error::from)?;
+        if n > xstats.len() {
+            // The number of xstats changed in between.
+            return Err(Error::TempUnavail);
+        }
+
+        let xstats = xstats
+            .into_iter()
+            .take(n)
+            .filter_map(|xstat| {
+                let name = names.get(usize::try_from(xstat.id).ok()?)?;
+                #[allow(clippy::cast_sign_loss)] // C string bytes
+                let name: Vec<u8> = name
+                    .name
+                    .iter()
+                    .take_while(|&&c| c != 0)
+                    .map(|&c| c as u8)
+                    .collect();
+                Some((String::from_utf8_lossy(&name).into_owned(), xstat.value))
+            })
+            .collect();
+        Ok(xstats)
+    }
+
+    /// Reset extended statistics of the device.
+    #[inline]
+    pub(crate) fn reset_xstats(&self) -> Result<()> {
+        // SAFETY: `port_id` validity verified
+        let errno = unsafe { rte_eth_xstats_reset(self.port_id) };
+        Error::from_ret(errno, "rte_eth_xstats_reset").map_err(|e| e.with_port(self.port_id))
+    }
+
+    /// Update the RSS hash key and hash functions of the device, as well as its RETA if set.
+    #[inline]
+    pub(crate) fn rss_update(&self, rss: &RssConfig) -> Result<()> {
+        let mut dev_info = MaybeUninit::<rte_eth_dev_info>::uninit();
+        // SAFETY: the returned `dev_info` is to be verified with the check on errno
+        let errno = unsafe { rte_eth_dev_info_get(self.port_id, dev_info.as_mut_ptr()) };
+        Error::from_ret(errno, "rte_eth_dev_info_get").map_err(|e| e.with_port(self.port_id))?;
+        // SAFETY: `dev_info` init in `rte_eth_dev_info_get`
+        let dev_info = unsafe { dev_info.assume_init() };
+        let mut rss_key = rss.key.clone();
+        let mut rss_conf = rss.rss_conf(&mut rss_key, &dev_info)?;
+        // SAFETY: `rss_key` outlives the call
+        #[allow(clippy::shadow_unrelated)] // is related
+        let errno =
+            unsafe { rte_eth_dev_rss_hash_update(self.port_id, ptr::addr_of_mut!(rss_conf)) };
+        Error::from_ret(errno, "rte_eth_dev_rss_hash_update")
+            .map_err(|e| e.with_port(self.port_id))?;
+        match rss.reta {
+            Some(ref reta) => self.rss_reta_update(reta),
+            None => Ok(()),
+        }
+    }
+
+    /// Update the RSS redirection table (RETA). The i-th entry of `reta` is the rx queue that
+    /// packets whose hash value mapped to i are delivered to. The length of `reta` should be
+    /// the RETA size of the device.
+    #[inline]
+    pub(crate) fn rss_reta_update(&self, reta: &[u16]) -> Result<()> {
+        if reta.iter().any(|&q| q as usize >= self.rx_queue.len()) {
+            return Err(Error::InvalidArg);
+        }
+        let reta_size: u16 = reta.len().try_into().map_err(Error::from)?;
+        let mut reta_conf = reta
+            .chunks(RTE_ETH_RETA_GROUP_SIZE as usize)
+            .map(|chunk| {
+                let mut entry = rte_eth_rss_reta_entry64 {
+                    mask: 0,
+                    reta: [0; RTE_ETH_RETA_GROUP_SIZE as usize],
+                };
+                for ((mask, slot), &queue) in (0..).zip(entry.reta.iter_mut()).zip(chunk) {
+                    entry.mask |= 1_u64 << mask;
+                    *slot = queue;
+                }
+                entry
+            })
+            .collect::<Vec<_>>();
+        // SAFETY: `reta_conf` holds `reta_size` entries
+        let errno =
+            unsafe { rte_eth_dev_rss_reta_update(self.port_id, reta_conf.as_mut_ptr(), reta_size) };
+        Error::from_ret(errno, "rte_eth_dev_rss_reta_update").map_err(|e| e.with_port(self.port_id))
+    }
+
+    /// Query the RSS redirection table (RETA) of the device.
+    #[inline]
+    pub(crate) fn rss_reta_query(&self) -> Result<Vec<u16>> {
+        let mut dev_info = MaybeUninit::<rte_eth_dev_info>::uninit();
+        // SAFETY: the returned `dev_info` is to be verified with the check on errno
+        let errno = unsafe { rte_eth_dev_info_get(self.port_id, dev_info.as_mut_ptr()) };
+        Error::from_ret(errno, "rte_eth_dev_info_get").map_err(|e| e.with_port(self.port_id))?;
+        // SAFETY: `dev_info` init in `rte_eth_dev_info_get`
+        let reta_size = unsafe { dev_info.assume_init() }.reta_size;
+        let n_group = (reta_size as usize).div_ceil(RTE_ETH_RETA_GROUP_SIZE as usize);
+        let mut reta_conf = vec![
+            rte_eth_rss_reta_entry64 {
+                mask: u64::MAX,
+                reta: [0; RTE_ETH_RETA_GROUP_SIZE as usize],
+            };
+            n_group
+        ];
+        // SAFETY: `reta_conf` holds `reta_size` entries
+        #[allow(clippy::shadow_unrelated)] // is related
+        let errno =
+            unsafe { rte_eth_dev_rss_reta_query(self.port_id, reta_conf.as_mut_ptr(), reta_size) };
+        Error::from_ret(errno, "rte_eth_dev_rss_reta_query")
+            .map_err(|e| e.with_port(self.port_id))?;
+        Ok(reta_conf
+            .iter()
+            .flat_map(|entry| entry.reta)
+            .take(reta_size as usize)
+            .collect())
+    }
+
+    /// Get the MTU of the device.
+    #[inline]
+    pub(crate) fn mtu(&self) -> Result<u16> {
+        let mut mtu = 0;
+        // SAFETY: `port_id` validity verified
+        let errno = unsafe { rte_eth_dev_get_mtu(self.port_id, ptr::addr_of_mut!(mtu)) };
+        Error::from_ret(errno, "rte_eth_dev_get_mtu").map_err(|e| e.with_port(self.port_id))?;
+        Ok(mtu)
+    }
+
+    /// Set the MTU of the device. Packets larger than it are fragmented before sent.
+    ///
+    /// An MTU whose frames don't fit in an `Mbuf` is only accepted if the device is created with
+    /// a jumbo MTU, which enables multi-segment packets.
+    #[inline]
+    pub(crate) fn set_mtu(&self, mtu: u16) -> Result<()> {
+        // SAFETY: `port_id` validity verified
+        let errno = unsafe { rte_eth_dev_set_mtu(self.port_id, mtu) };
+        Error::from_ret(errno, "rte_eth_dev_set_mtu").map_err(|e| e.with_port(self.port_id))?;
+        self.conf.mtu.store(mtu, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Enable or disable GSO, with which large IPv4 UDP datagrams are fragmented by the hardware
+    /// if supported, or by the GSO library without copying their payloads.
+    #[inline]
+    pub(crate) fn set_gso(&self, enable: bool) {
+        self.conf.gso.store(enable, Ordering::Relaxed);
+    }
+
+    /// Whether GSO is enabled.
+    #[inline]
+    pub(crate) fn gso(&self) -> bool {
+        self.conf.gso.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable GRO, with which IPv4 fragments of UDP datagrams received in the same
+    /// burst are merged before reassembly.
+    #[inline]
+    pub(crate) fn set_gro(&self, enable: bool) {
+        self.conf.gro.store(enable, Ordering::Relaxed);
+    }
+
+    /// Whether GRO is enabled.
+    #[inline]
+    pub(crate) fn gro(&self) -> bool {
+        self.conf.gro.load(Ordering::Relaxed)
+    }
+
+    /// Read the clock of the device, in the unit of the timestamps taken by it.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::NotSupported`: the device has no clock to read.
+    /// - `Error::NoDev`: the port is invalid.
+    #[inline]
+    pub fn read_clock(&self) -> Result<u64> {
+        let mut clock = 0;
+        // SAFETY: `clock` is valid during the call
+        #[allow(unsafe_code)]
+        let errno = unsafe { rte_eth_read_clock(self.port_id, &mut clock) };
+        Error::from_ret(errno, "rte_eth_read_clock").map_err(|e| e.with_port(self.port_id))?;
+        Ok(clock)
+    }
+
+    /// Set the max number of packets received from an rx queue in a burst, which is set by
+    /// `eal::Config::rx_burst` by default. Larger bursts save per-burst overheads under heavy
+    /// traffic at the cost of latency.
+    ///
+    /// # Errors
+    ///
+    /// - `Error::InvalidArg`: `burst` is 0 or larger than 512.
+    #[inline]
+    pub(crate) fn set_rx_burst(&self, burst: u16) -> Result<()> {
+        if burst == 0 || burst > RX_BURST_CAPACITY {
+            return Err(Error::InvalidArg);
+        }
+        self.conf.rx_burst.store(burst, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The max number of packets received from an rx queue in a burst.
+    #[inline]
+    pub(crate) fn rx_burst(&self) -> u16 {
+        self.conf.rx_burst.load(Ordering::Relaxed)
+    }
+
+    /// Check whether a flow rule can be created on the device.
+    #[inline]
+    pub(crate) fn flow_validate(&self, rule: &FlowRule) -> Result<()> {
+        Flow::validate(self.port_id, rule)
+    }
+
+    /// Create a flow rule on the device.
+    #[inline]
+    pub(crate) fn flow_create(&self, rule: &FlowRule) -> Result<FlowId> {
+        let flow = Flow::create(self.port_id, rule)?;
+        let id = FlowId(self.next_flow_id.fetch_add(1, Ordering::Relaxed));
+        let _prev = self.flows.lock().map_err(Error::from)?.insert(id, flow);
+        Ok(id)
+    }
+
+    /// Destroy a flow rule created on the device.
+    #[inline]
+    pub(crate) fn flow_destroy(&self, id: FlowId) -> Result<()> {
+        let mut flows = self.flows.lock().map_err(Error::from)?;
+        let _flow = flows.remove(&id).ok_or(Error::NoEntry)?;
+        Ok(())
+    }
+
+    /// Enable or disable the promiscuous mode, in which all packets are received regardless of
+    /// their destination Ether addresses.
+    #[inline]
+    pub(crate) fn set_promiscuous(&self, enable: bool) -> Result<()> {
+        // SAFETY: `port_id` validity verified
+        let errno = unsafe {
+            if enable {
+                rte_eth_promiscuous_enable(self.port_id)
+            } else {
+                rte_eth_promiscuous_disable(self.port_id)
+            }
+        };
+        Error::from_ret(
+            errno,
+            if enable {
+                "rte_eth_promiscuous_enable"
+            } else {
+                "rte_eth_promiscuous_disable"
+            },
+        )
+        .map_err(|e| e.with_port(self.port_id))
+    }
+
+    /// Whether the promiscuous mode is enabled.
+    #[inline]
+    pub(crate) fn is_promiscuous(&self) -> Result<bool> {
+        // SAFETY: `port_id` validity verified
+        match unsafe { rte_eth_promiscuous_get(self.port_id) } {
+            1 => Ok(true),
+            0 => Ok(false),
+            _ => Err(Error::NoDev),
+        }
+    }
+
+    /// Enable or disable the allmulticast mode, in which all multicast packets are received.
+    #[inline]
+    pub(crate) fn set_allmulticast(&self, enable: bool) -> Result<()> {
+        // SAFETY: `port_id` validity verified
+        let errno = unsafe {
+            if enable {
+                rte_eth_allmulticast_enable(self.port_id)
+            } else {
+                rte_eth_allmulticast_disable(self.port_id)
+            }
+        };
+        Error::from_ret(
+            errno,
+            if enable {
+                "rte_eth_allmulticast_enable"
+            } else {
+                "rte_eth_allmulticast_disable"
+            },
+        )
+        .map_err(|e| e.with_port(self.port_id))
+    }
+
+    /// Set the multicast addresses that the device receives, replacing the previous ones. An empty
+    /// list flushes them.
+    #[inline]
+    pub(crate) fn set_mc_addr_list(&self, mc_addrs: &[rte_ether_addr]) -> Result<()> {
+        let mut mc_addrs = mc_addrs.to_vec();
+        let nb_mc_addr = mc_addrs.len().try_into().map_err(Error::from)?;
+        let mc_addr_set = if mc_addrs.is_empty() {
+            ptr::null_mut()
+        } else {
+            mc_addrs.as_mut_ptr()
+        };
+        // SAFETY: `port_id` validity verified, `mc_addr_set` holds `nb_mc_addr` addresses
+        let errno = unsafe { rte_eth_dev_set_mc_addr_list(self.port_id, mc_addr_set, nb_mc_addr) };
+        Error::from_ret(errno, "rte_eth_dev_set_mc_addr_list")
+            .map_err(|e| e.with_port(self.port_id))
+    }
+
+    /// Whether the allmulticast mode is enabled.
+    #[inline]
+    pub(crate) fn is_allmulticast(&self) -> Result<bool> {
+        // SAFETY: `port_id` validity verified
+        match unsafe { rte_eth_allmulticast_get(self.port_id) } {
+            1 => Ok(true),
+            0 => Ok(false),
+            _ => Err(Error::NoDev),
+        }
+    }
+
+    /// Get the link status of the device without waiting.
+    #[inline]
+    pub(crate) fn link(&self) -> Result<LinkStatus> {
+        let mut link = MaybeUninit::<rte_eth_link>::uninit();
+        // SAFETY: errno checked later
+        let errno = unsafe { rte_eth_link_get_nowait(self.port_id, link.as_mut_ptr()) };
+        Error::from_ret(errno, "rte_eth_link_get_nowait").map_err(|e| e.with_port(self.port_id))?;
+        // SAFETY: `rte_eth_link` is successfully initialized due to no error code.
+        Ok(unsafe { link.assume_init() }.into())
+    }
+}
+
+/// Queue and memory configuration of an Ethernet device.
+///
+/// ```no_run
+/// use async_dpdk::net_dev::DevConfig;
+///
+/// let conf = DevConfig::new()
+///     .rx_desc(4096)
+///     .tx_desc(4096)
+///     .rx_pool_size(16384)
+///     .pool_cache_size(256);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DevConfig {
+    /// Number of descriptors of each rx queue.
+    n_rxd: u16,
+    /// Number of descriptors of each tx queue.
+    n_txd: u16,
+    /// Number of `Mbuf`s in the mempool of each rx queue, or `None` to derive it from the number
+    /// of descriptors.
+    rx_pool_size: Option<u32>,
+    /// Number of `Mbuf`s in the mempool of each tx queue.
+    tx_pool_size: u32,
+    /// Number of `Mbuf`s cached per lcore in the mempools of the queues, or `None` to derive it
+    /// from the mempool sizes.
+    pool_cache_size: Option<u32>,
+    /// RX offloads enabled in addition to the defaults, in `RTE_ETH_RX_OFFLOAD_*`.
+    rx_offloads: u64,
+    /// TX offloads enabled in addition to the defaults, in `RTE_ETH_TX_OFFLOAD_*`.
+    tx_offloads: u64,
+    /// Threads serving the tx queues.
+    tx_exec: TxExec,
+    /// Weights of the tx classes, or `None` to queue all packets in one class.
+    tx_weights: Option<[u32; TX_CLASSES]>,
+    /// Rate limit of each tx queue, if any.
+    tx_rate_limit: Option<RateLimit>,
+    /// Policer of each tx queue, if any.
+    tx_policer: Option<Policer>,
+    /// Whether received packets are timestamped.
+    rx_timestamp: bool,
+}
+
+impl Default for DevConfig {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DevConfig {
+    /// Create a `DevConfig` with 1024 descriptors per queue, mempools with per-lcore caches and the
+    /// default offloads, which are the checksum offloads, multi-segment packets, TCP segmentation
+    /// and fast release of `Mbuf`s if supported.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            n_rxd: 1024,
+            n_txd: 1024,
+            rx_pool_size: None,
+            tx_pool_size: 1024,
+            pool_cache_size: None,
+            rx_offloads: 0,
+            tx_offloads: 0,
+            tx_exec: TxExec::Threads(1),
+            tx_weights: None,
+            tx_rate_limit: None,
+            tx_policer: None,
+            rx_timestamp: false,
+        }
+    }
+
+    /// Set the number of descriptors of each rx queue, which is adjusted to the limits of the
+    /// device.
+    #[inline]
+    #[must_use]
+    pub fn rx_desc(mut self, n_rxd: u16) -> Self {
+        self.n_rxd = n_rxd;
+        self
+    }
+
+    /// Set the number of descriptors of each tx queue, which is adjusted to the limits of the
+    /// device.
+    #[inline]
+    #[must_use]
+    pub fn tx_desc(mut self, n_txd: u16) -> Self {
+        self.n_txd = n_txd;
+        self
+    }
+
+    /// Set the number of `Mbuf`s in the mempool of each rx queue. By default it's enough to fill
+    /// the descriptors of all queues of all devices, and no less than 8192.
+    #[inline]
+    #[must_use]
+    pub fn rx_pool_size(mut self, size: u32) -> Self {
+        self.rx_pool_size = Some(size);
+        self
+    }
+
+    /// Set the number of `Mbuf`s in the mempool of each tx queue, which is 1024 by default.
+    #[inline]
+    #[must_use]
+    pub fn tx_pool_size(mut self, size: u32) -> Self {
+        self.tx_pool_size = size;
+        self
+    }
+
+    /// Set the number of `Mbuf`s cached per lcore in the mempools of the queues, which is a
+    /// quarter of the mempool sizes up to 512 by default. It should be no larger than 512 and two
+    /// thirds of the mempool sizes, and 0 disables the caches.
+    #[inline]
+    #[must_use]
+    pub fn pool_cache_size(mut self, size: u32) -> Self {
+        self.pool_cache_size = Some(size);
+        self
+    }
+
+    /// Create the mempool of a queue holding `size` `Mbuf`s on `socket_id`.
+    fn queue_pool(&self, name: &str, size: u32, socket_id: i32) -> Result<PktMempool> {
+        let cache_size = self
+            .pool_cache_size
+            .unwrap_or_else(|| PktMempool::default_cache_size(size));
+        #[allow(clippy::cast_possible_truncation)] // 2176 < u16::MAX
+        PktMempool::create_with(
+            name,
+            size,
+            cache_size,
+            0,
+            RTE_MBUF_DEFAULT_BUF_SIZE as u16,
+            socket_id,
+        )
+    }
+
+    /// The number of `Mbuf`s in the mempool of each rx queue, with the adjusted number of
+    /// descriptors.
+    #[allow(clippy::similar_names)] // tx and rx are DPDK terms
+    fn rx_pool_len(&self, n_rxd: u16, n_txd: u16) -> u32 {
+        self.rx_pool_size.unwrap_or_else(|| {
+            EthDev::available_ports()
+                .saturating_mul(
+                    u32::from(n_rxd)
+                        .saturating_add(u32::from(n_txd))
+                        .saturating_add(32),
+                )
+                .max(8192)
+        })
+    }
+
+    /// Enable RX offloads, in `RTE_ETH_RX_OFFLOAD_*`, in addition to the defaults.
+    #[inline]
+    #[must_use]
+    pub fn rx_offloads(mut self, offloads: u64) -> Self {
+        self.rx_offloads = offloads;
+        self
+    }
+
+    /// Enable TX offloads, in `RTE_ETH_TX_OFFLOAD_*`, in addition to the defaults.
+    #[inline]
+    #[must_use]
+    pub fn tx_offloads(mut self, offloads: u64) -> Self {
+        self.tx_offloads = offloads;
+        self
+    }
+
+    /// Timestamp received packets, which is offloaded to the NIC if supported, or done by the rx
+    /// agents with the TSC once they poll the packets otherwise. See `timestamp` for details.
+    #[inline]
+    #[must_use]
+    pub fn rx_timestamp(mut self, enable: bool) -> Self {
+        self.rx_timestamp = enable;
+        self
+    }
+
+    /// Set the threads serving the tx queues, which is a single thread for all queues by
+    /// default. Queues served by different threads are processed in parallel.
+    ///
+    /// ```no_run
+    /// use async_dpdk::net_dev::{DevConfig, TxExec};
+    ///
+    /// // Serve the tx queues on CPU cores 4 to 7.
+    /// let conf = DevConfig::new().tx_exec(TxExec::Pinned { first: 4, n: 4 });
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn tx_exec(mut self, exec: TxExec) -> Self {
+        self.tx_exec = exec;
+        self
+    }
+
+    /// Queue the packets to send in 4 tx classes by the precedence bits of their IPv4 type of
+    /// service or IPv6 traffic class, and drain the classes with the given weights. Class 0 holds
+    /// precedence 6 and 7, down to class 3 holding precedence 0 and 1, which is where packets land
+    /// by default.
+    ///
+    /// When the device can't take all buffered packets at once, the classes are served round by
+    /// round from class 0, each sending up to its weight of packets per round, where a weight of 0
+    /// counts as 1. Without weights, which is the default, packets are sent in the order they are
+    /// queued.
+    ///
+    /// ```no_run
+    /// use async_dpdk::net_dev::DevConfig;
+    ///
+    /// // Send up to 8 high-priority packets for each best-effort one under congestion.
+    /// let conf = DevConfig::new().tx_weights([8, 4, 2, 1]);
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn tx_weights(mut self, weights: [u32; TX_CLASSES]) -> Self {
+        self.tx_weights = Some(weights);
+        self
+    }
+
+    /// Shape each tx queue to the given rate limit, whose rate must not be 0. Queues are not
+    /// limited by default.
+    ///
+    /// Packets over the limit are held in the tx buffer of the queue until they conform to it,
+    /// and sending fails with `Error::NoBuf` once the buffer is full. Use
+    /// `UdpSocket::set_rate_limit` to wait until a datagram conforms instead.
+    ///
+    /// ```no_run
+    /// use async_dpdk::net_dev::{DevConfig, RateLimit};
+    ///
+    /// // Keep each tx queue under 1 Gbit/s.
+    /// let conf = DevConfig::new().tx_rate_limit(RateLimit::bps(1_000_000_000));
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn tx_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.tx_rate_limit = Some(limit);
+        self
+    }
+
+    /// Police the packets sent on each tx queue by a meter over whole Ethernet frames without
+    /// the FCS. Queues are not policed by default.
+    ///
+    /// Unlike `tx_rate_limit`, out-of-profile packets are never held: they're dropped and counted
+    /// in `QueueStats::tx_policed`, or demoted to the last traffic class, which only makes a
+    /// difference with `tx_weights`.
+    ///
+    /// ```no_run
+    /// use async_dpdk::meter::{MeterProfile, Policer};
+    /// use async_dpdk::net_dev::DevConfig;
+    ///
+    /// // Drop the packets beyond 100 MB/s with bursts of 1 MB.
+    /// let conf = DevConfig::new().tx_policer(Policer::new(MeterProfile::SrTcm {
+    ///     cir: 100_000_000,
+    ///     cbs: 1_000_000,
+    ///     ebs: 0,
+    /// }));
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn tx_policer(mut self, policer: Policer) -> Self {
+        self.tx_policer = Some(policer);
+        self
+    }
+}
+
+/// Receive Side Scaling (RSS) configuration of an Ethernet device.
+///
+/// When a device has multiple rx queues, the NIC computes a hash over the selected header
+/// fields of each received packet, then looks up the redirection table (RETA) with it to pick
+/// the rx queue, so that packets of the same flow always land on the same queue.
+///
+/// ```no_run
+/// use async_dpdk::net_dev::RssConfig;
+///
+/// let rss = RssConfig::new()
+///     .hash_functions(RssConfig::IP | RssConfig::UDP)
+///     .reta(vec![0, 1, 2, 3]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RssConfig {
+    /// RSS hash key. `None` to use the default key of the driver.
+    key: Option<Vec<u8>>,
+    /// RSS hash functions, a combination of `RssConfig::IP`, `RssConfig::UDP` and so on.
+    hash_functions: u64,
+    /// Rx queues for each RETA entry. `None` to use the default RETA of the driver.
+    reta: Option<Vec<u16>>,
+}
+
+impl RssConfig {
+    /// Hash over IPv4 and IPv6 addresses.
+    pub const IP: u64 =
+        (1 << 2) | (1 << 3) | (1 << 7) | (1 << 8) | (1 << 9) | (1 << 13) | (1 << 15);
+    /// Hash over IPv4 and IPv6 addresses and UDP ports.
+    pub const UDP: u64 = (1 << 5) | (1 << 11) | (1 << 17);
+    /// Hash over IPv4 and IPv6 addresses and TCP ports.
+    pub const TCP: u64 = (1 << 4) | (1 << 10) | (1 << 16);
+
+    /// Create an `RssConfig` hashing over IP addresses and UDP / TCP ports with the default key
+    /// and RETA of the driver.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            key: None,
+            hash_functions: Self::IP | Self::UDP | Self::TCP,
+            reta: None,
+        }
+    }
+
+    /// Set the RSS hash key. Its length should be the hash key size of the device, typically 40
+    /// bytes.
+    #[inline]
+    #[must_use]
+    pub fn key(mut self, key: Vec<u8>) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Set the RSS hash functions. Functions not supported by the device are ignored.
+    #[inline]
+    #[must_use]
+    pub fn hash_functions(mut self, hash_functions: u64) -> Self {
+        self.hash_functions = hash_functions;
+        self
+    }
+
+    /// Set the rx queue for each RETA entry. Its length should be the RETA size of the device.
+    #[inline]
+    #[must_use]
+    pub fn reta(mut self, reta: Vec<u16>) -> Self {
+        self.reta = Some(reta);
+        self
+    }
+
+    /// Generate `rte_eth_rss_conf` with `key`, which should be a copy of `self.key`.
+    fn rss_conf(
+        &self,
+        key: &mut Option<Vec<u8>>,
+        dev_info: &rte_eth_dev_info,
+    ) -> Result<rte_eth_rss_conf> {
+        let (rss_key, rss_key_len) = match *key {
+            Some(ref mut key) => {
+                if dev_info.hash_key_size != 0 && key.len() != dev_info.hash_key_size as usize {
+                    return Err(Error::InvalidArg);
+                }
+                let len: u8 = key.len().try_into().map_err(Error::from)?;
+                (key.as_mut_ptr(), len)
+            }
+            None => (ptr::null_mut(), 0),
+        };
+        Ok(rte_eth_rss_conf {
+            rss_key,
+            rss_key_len,
+            rss_hf: self.hash_functions & dev_info.flow_type_rss_offloads,
+        })
+    }
+}
+
+impl Default for RssConfig {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Basic statistics of an Ethernet device.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EthStats {
+    /// Total number of successfully received packets.
+    pub ipackets: u64,
+    /// Total number of successfully transmitted packets.
+    pub opackets: u64,
+    /// Total number of successfully received bytes.
+    pub ibytes: u64,
+    /// Total number of successfully transmitted bytes.
+    pub obytes: u64,
+    /// Total number of RX packets dropped by the HW, because there are no available buffers.
+    pub imissed: u64,
+    /// Total number of erroneous received packets.
+    pub ierrors: u64,
+    /// Total number of failed transmitted packets.
+    pub oerrors: u64,
+    /// Total number of RX mbuf allocation failures.
+    pub rx_nombuf: u64,
+}
+
+impl From<rte_eth_stats> for EthStats {
+    #[inline]
+    fn from(stats: rte_eth_stats) -> Self {
+        Self {
+            ipackets: stats.ipackets,
+            opackets: stats.opackets,
+            ibytes: stats.ibytes,
+            obytes: stats.obytes,
+            imissed: stats.imissed,
+            ierrors: stats.ierrors,
+            oerrors: stats.oerrors,
+            rx_nombuf: stats.rx_nombuf,
+        }
+    }
+}
+
+/// Link status of an Ethernet device.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStatus {
+    /// Link speed in Mbps, `u32::MAX` if unknown.
+    pub speed: u32,
+    /// Whether the link is in full duplex mode.
+    pub full_duplex: bool,
+    /// Whether the link speed is auto-negotiated.
+    pub autoneg: bool,
+    /// Whether the link is up.
+    pub up: bool,
+}
+
+impl From<rte_eth_link> for LinkStatus {
+    #[inline]
+    fn from(link: rte_eth_link) -> Self {
+        Self {
+            speed: link.link_speed,
+            full_duplex: link.link_duplex() != 0,
+            autoneg: link.link_autoneg() != 0,
+            up: link.link_status() != 0,
+        }
+    }
+}
+
+/// Get the id of the port named `name`.
+#[allow(unsafe_code)]
+pub(crate) fn port_by_name(name: &str) -> Result<u16> {
+    let c_name = CString::new(name).map_err(Error::from)?;
+    let mut port_id = 0_u16;
+    // SAFETY: `c_name` outlives the call
+    let errno =
+        unsafe { rte_eth_dev_get_port_by_name(c_name.as_ptr(), ptr::addr_of_mut!(port_id)) };
+    Error::from_ret(errno, "rte_eth_dev_get_port_by_name")?;
+    Ok(port_id)
+}
+
+impl Drop for EthDev {
+    #[inline]
+    fn drop(&mut self) {
+        if let Err(e) = self.close_port() {
+            errno::report(e);
+        }
+    }
+}
+
+// SAFETY: no thread-local data involved.
+#[allow(unsafe_code)]
+unsafe impl Send for EthDev {}
+
+// SAFETY: EthDev can be globally accessed.
+#[allow(unsafe_code)]
+unsafe impl Sync for EthDev {}
+
+impl Debug for EthDev {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EthDev")
+            .field("port_id", &self.port_id)
+            .field("socket_id", &self.socket_id)
+            .finish()
+    }
+}
+
+/// A wrapper for channel to send Mbuf from socket to `EthTxQueue`.
+///
+/// If the `TxAgent` has a `TxRing` for the queue, packets are enqueued to it, and no longer wait
+/// for being buffered by `TxAgent`, whose failures are only logged then.
+#[derive(Debug, Clone)]
+pub(crate) struct TxSender {
+    /// The sender held by socket.
+    chan: mpsc::Sender<TxRequest>,
+    /// The ring carrying packets instead of `chan`, if any.
+    ring: Option<Arc<TxRing>>,
+    /// The `EthTxQueue` that this request is sent to.
+    tx_queue: Arc<EthTxQueue>,
+}
+
+impl TxSender {
+    /// Send a request to `TxAgent`, and wait until the packet is buffered by it.
+    ///
+    /// It fails with `Error::NoBuf` if the tx buffer is full, or `Error::Busy` if the device has
+    /// taken no packet for a while.
+    pub(crate) async fn send(&self, pkt: Packet) -> Result<()> {
+        let m = pkt.into_mbuf(&self.tx_queue.mp, self.tx_queue.offloads)?;
+        self.send_mbuf(m).await
+    }
+
+    /// Send an `Mbuf` holding a whole Ethernet frame to `TxAgent` without copying, and wait until
+    /// the packet is buffered by it.
+    pub(crate) async fn send_mbuf(&self, m: Mbuf) -> Result<()> {
+        if let Some(ref ring) = self.ring {
+            return ring.push(m).await;
+        }
+        let (tx, rx) = oneshot::channel();
+        self.chan
+            .send(TxRequest::Send(m, Some(tx)))
+            .await
+            .map_err(Error::from)?;
+        rx.await.map_err(Error::from)?
+    }
+
+    /// Send a burst of packets to `TxAgent` in one request, and wait until they are buffered by
+    /// it. Returns the number of packets buffered.
+    ///
+    /// It fails only if no packet is buffered.
+    pub(crate) async fn send_batch(&self, pkts: Vec<Packet>) -> Result<usize> {
+        let batch = pkts
+            .into_iter()
+            .map(|pkt| pkt.into_mbuf(&self.tx_queue.mp, self.tx_queue.offloads))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(ref ring) = self.ring {
+            return ring.push_batch(batch).await;
+        }
+        let (tx, rx) = oneshot::channel();
+        self.chan
+            .send(TxRequest::SendBatch(batch, tx))
+            .await
+            .map_err(Error::from)?;
+        rx.await.map_err(Error::from)?
+    }
+
+    /// Try to send an `Mbuf` holding a whole Ethernet frame to `TxAgent` without waiting.
+    ///
+    /// Failures of `TxAgent` are only logged.
+    pub(crate) fn try_send_mbuf(&self, m: Mbuf) -> Result<()> {
+        if let Some(ref ring) = self.ring {
+            return ring.try_push(m);
+        }
+        self.chan
+            .try_send(TxRequest::Send(m, None))
+            .map_err(Error::from)
+    }
+
+    /// Wait until all requests sent before are handled and the buffered packets are sent out.
+    ///
+    /// Returns the number of packets still unsent after `TxAgent` gives up retrying.
+    pub(crate) async fn flush(&self) -> Result<usize> {
+        let (tx, rx) = oneshot::channel();
+        self.chan
+            .send(TxRequest::Flush(tx))
+            .await
+            .map_err(Error::from)?;
+        rx.await.map_err(Error::from)
+    }
+
+    /// Allocate an `Mbuf` from the mempool of the `EthTxQueue`.
+    pub(crate) fn alloc_mbuf(&self) -> Result<Mbuf> {
+        Mbuf::new(&self.tx_queue.mp)
+    }
+
+    /// Copy a `Packet` into `Mbuf`s from the mempool of the `EthTxQueue` without sending it.
+    pub(crate) fn packet_mbuf(&self, pkt: Packet) -> Result<Mbuf> {
+        pkt.into_mbuf(&self.tx_queue.mp, self.tx_queue.offloads)
+    }
+
+    /// TX offloads enabled on the `EthTxQueue`.
+    pub(crate) fn offloads(&self) -> u64 {
+        self.tx_queue.offloads
+    }
+
+    /// Whether the channel to `TxAgent` is full, in which case `try_send` fails.
+    pub(crate) fn is_full(&self) -> bool {
+        self.ring
+            .as_ref()
+            .map_or(self.chan.capacity() == 0, |ring| ring.is_full())
+    }
+
+    /// Wait until the channel to `TxAgent` has room for a request. The returned future doesn't
+    /// borrow the sender, so that it can be kept between polls.
+    pub(crate) fn ready(&self) -> impl Future<Output = Result<()>> + Send + 'static {
+        let chan = self.chan.clone();
+        let ring = self.ring.clone();
+        async move {
+            if let Some(ring) = ring {
+                return ring.ready().await;
+            }
+            let _permit = chan.reserve_owned().await.map_err(Error::from)?;
+            Ok(())
+        }
+    }
+
+    /// Try to send a request to `TxAgent` without waiting.
+    ///
+    /// It's used where `await` is not allowed, e.g. in `Drop` or in the `RxAgent`. Failures of
+    /// `TxAgent` are only logged.
+    pub(crate) fn try_send(&self, pkt: Packet) -> Result<()> {
+        let m = pkt.into_mbuf(&self.tx_queue.mp, self.tx_queue.offloads)?;
+        self.try_send_mbuf(m)
+    }
+}
+
+/// An Ethernet device rx queue.
+#[allow(missing_copy_implementations)]
+#[derive(Debug)]
+struct EthRxQueue {
+    /// The `queue_id` refered to this `EthTxQueue`.
+    queue_id: u16,
+    /// `Mempool` to allocate `Mbuf`s to hold the received frames.
+    _mp: PktMempool,
+    /// Whether an `RxQueue` handle is taken.
+    taken: AtomicBool,
+}
+
+/// An Ethernet device tx queue.
+#[allow(missing_copy_implementations)]
+#[derive(Debug)]
+struct EthTxQueue {
+    /// The `queue_id` refered to this `EthTxQueue`.
+    queue_id: u16,
+    /// `Mempool` to allocate `Mbuf`s to send.
+    mp: PktMempool,
+    /// TX offloads enabled on this queue.
+    offloads: u64,
+    /// Whether a `TxQueue` handle is taken.
+    taken: AtomicBool,
+}
+
+#[allow(unsafe_code)]
+impl EthRxQueue {
+    /// Init rx queue.
+    ///
+    /// Setup the queue with `mp` to hold received packets.
+    fn init(
+        port_id: u16,
+        queue_id: u16,
+        socket_id: i32,
+        n_rxd: u16,
+        mp: PktMempool,
+        dev_info: &rte_eth_dev_info,
+        eth_conf: &rte_eth_conf,
+    ) -> Result<Arc<Self>> {
+        #[allow(clippy::cast_sign_loss)] // `SOCKET_ID_ANY` is passed as is
+        let socket_id = socket_id as u32;
+        let mut rx_conf = dev_info.default_rxconf;
+        rx_conf.offloads = eth_conf.rxmode.offloads;
         // SAFETY: `mp` checked in initialization
         let errno = unsafe {
             rte_eth_rx_queue_setup(port_id, queue_id, n_rxd, socket_id, &rx_conf, mp.as_ptr())
         };
-        Error::from_ret(errno)?;
-        Ok(Arc::new(Self { queue_id, _mp: mp }))
+        Error::from_ret(errno, "rte_eth_rx_queue_setup")
+            .map_err(|e| e.with_port(port_id).with_queue(queue_id))?;
+        Ok(Arc::new(Self {
+            queue_id,
+            _mp: mp,
+            taken: AtomicBool::new(false),
+        }))
     }
 }
 
@@ -357,40 +1953,327 @@ impl EthRxQueue {
 impl EthTxQueue {
     /// Init tx queue.
     ///
-    /// Create a `Mempool` to hold packets to be sent, then setup the queue.
+    /// Setup the queue with `mp` to hold packets to be sent.
     fn init(
         port_id: u16,
         queue_id: u16,
         socket_id: i32,
         n_txd: u16,
+        mp: PktMempool,
         dev_info: &rte_eth_dev_info,
         eth_conf: &rte_eth_conf,
     ) -> Result<Arc<Self>> {
-        let socket_id: u32 = socket_id.try_into().map_err(Error::from)?;
-        let mp = PktMempool::create(format!("tx_{port_id}_{queue_id}").as_str(), 1024)?;
+        #[allow(clippy::cast_sign_loss)] // `SOCKET_ID_ANY` is passed as is
+        let socket_id = socket_id as u32;
         let mut tx_conf = dev_info.default_txconf;
         tx_conf.offloads = eth_conf.txmode.offloads;
         // SAFETY: ffi
         let errno =
             unsafe { rte_eth_tx_queue_setup(port_id, queue_id, n_txd, socket_id, &tx_conf) };
-        Error::from_ret(errno)?;
-        Ok(Arc::new(Self { queue_id, mp }))
+        Error::from_ret(errno, "rte_eth_tx_queue_setup")
+            .map_err(|e| e.with_port(port_id).with_queue(queue_id))?;
+        Ok(Arc::new(Self {
+            queue_id,
+            mp,
+            offloads: tx_conf.offloads,
+            taken: AtomicBool::new(false),
+        }))
+    }
+}
+
+/// The handle of an rx queue of an `EthDev`, taken by `EthDev::rx_queue`.
+///
+/// DPDK queues are not thread-safe, so a queue is only polled by its handle while it's taken,
+/// even if the device is driven by agent threads.
+pub struct RxQueue<'a> {
+    /// `port_id` of the device.
+    port_id: u16,
+    /// The queue polled.
+    queue: &'a EthRxQueue,
+    /// The agent which polled the queue before, along with the settings of the port.
+    agent: Option<(&'a Arc<RxAgent>, &'a Arc<PortConf>)>,
+}
+
+impl RxQueue<'_> {
+    /// `port_id` of the device.
+    #[inline]
+    #[must_use]
+    pub fn port_id(&self) -> u16 {
+        self.port_id
+    }
+
+    /// Poll the queue once, appending at most `max` received frames to `pkts`. Returns the number
+    /// of frames received, which is no more than 512.
+    #[inline]
+    pub fn recv(&mut self, pkts: &mut Vec<Mbuf>, max: usize) -> usize {
+        let mut ptrs = [ptr::null_mut(); QUEUE_BURST_CAPACITY];
+        let n = self.burst(&mut ptrs, max);
+        pkts.extend(
+            ptrs.iter()
+                .take(n)
+                .filter_map(|&ptr| Mbuf::new_with_ptr(ptr).ok()),
+        );
+        n
+    }
+
+    /// Poll the queue once without allocating, filling the slots at the front of `slots` with
+    /// the received frames. Returns the number of frames received, which is no more than 512.
+    ///
+    /// `Mbuf`s left in the slots filled are dropped.
+    ///
+    /// ```no_run
+    /// use async_dpdk::{eth_dev::EthDev, mbuf::MbufSlot, net_dev::DevConfig};
+    ///
+    /// let mut dev = EthDev::new(0, 1, 1, &DevConfig::new()).unwrap();
+    /// dev.start().unwrap();
+    /// let mut rxq = dev.rx_queue(0).unwrap();
+    /// let mut slots: [MbufSlot; 32] = Default::default();
+    /// let n = rxq.rx_burst(&mut slots);
+    /// for m in slots.iter_mut().take(n).filter_map(Option::take) {
+    ///     println!("received {} bytes", m.pkt_len());
+    /// }
+    /// ```
+    #[inline]
+    pub fn rx_burst(&mut self, slots: &mut [MbufSlot]) -> usize {
+        let mut ptrs = [ptr::null_mut(); QUEUE_BURST_CAPACITY];
+        let n = self.burst(&mut ptrs, slots.len());
+        for (slot, &ptr) in slots.iter_mut().zip(ptrs.iter().take(n)) {
+            *slot = Mbuf::new_with_ptr(ptr).ok();
+        }
+        n
+    }
+
+    /// Receive at most `max` frames into `ptrs`, returning the number of frames received.
+    #[allow(unsafe_code)]
+    fn burst(&mut self, ptrs: &mut [*mut rte_mbuf; QUEUE_BURST_CAPACITY], max: usize) -> usize {
+        #[allow(clippy::cast_possible_truncation)] // no more than `QUEUE_BURST_CAPACITY`
+        let burst = max.min(QUEUE_BURST_CAPACITY) as u16;
+        // SAFETY: `burst` fits in `ptrs`, and `n` packets at the front are valid
+        let n = unsafe {
+            rte_eth_rx_burst(self.port_id, self.queue.queue_id, ptrs.as_mut_ptr(), burst)
+        };
+        usize::from(n)
+    }
+}
+
+impl Debug for RxQueue<'_> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RxQueue")
+            .field("port_id", &self.port_id)
+            .field("queue", &self.queue)
+            .field("agent", &self.agent.is_some())
+            .finish()
+    }
+}
+
+impl Drop for RxQueue<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some((agent, conf)) = self.agent {
+            let queue_id = self.queue.queue_id;
+            if let Err(e) = agent.register(self.port_id, queue_id, Arc::clone(conf)) {
+                error!("Failed to resume polling {}:{queue_id}: {e}", self.port_id);
+            }
+        }
+        self.queue.taken.store(false, Ordering::Release);
+    }
+}
+
+/// The handle of a tx queue of an `EthDev`, taken by `EthDev::tx_queue`.
+///
+/// DPDK queues are not thread-safe, so a queue is only sent to by its handle while it's taken,
+/// even if the device is driven by agent threads.
+pub struct TxQueue<'a> {
+    /// `port_id` of the device.
+    port_id: u16,
+    /// The queue sent to.
+    queue: &'a EthTxQueue,
+    /// The agent which sent to the queue before.
+    agent: Option<&'a Arc<TxAgent>>,
+}
+
+impl TxQueue<'_> {
+    /// `port_id` of the device.
+    #[inline]
+    #[must_use]
+    pub fn port_id(&self) -> u16 {
+        self.port_id
+    }
+
+    /// Allocate an `Mbuf` from the mempool of the queue.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///  - Mempool exhausted.
+    #[inline]
+    pub fn alloc_mbuf(&self) -> Result<Mbuf> {
+        Mbuf::new(&self.queue.mp)
+    }
+
+    /// Send frames at the front of `pkts` to the queue, at most 512 at a time. Sent frames are
+    /// removed from `pkts`, and the rest are left for the next try. Returns the number of frames
+    /// sent.
+    #[inline]
+    #[allow(unsafe_code)]
+    pub fn send(&mut self, pkts: &mut Vec<Mbuf>) -> usize {
+        let mut ptrs = [ptr::null_mut(); QUEUE_BURST_CAPACITY];
+        let mut n = 0_u16;
+        for (slot, m) in ptrs.iter_mut().zip(pkts.iter()) {
+            *slot = m.as_ptr();
+            n = n.wrapping_add(1);
+        }
+        // SAFETY: `n` packets at the front of `ptrs` are valid
+        let sent =
+            unsafe { rte_eth_tx_burst(self.port_id, self.queue.queue_id, ptrs.as_mut_ptr(), n) };
+        for m in pkts.drain(..usize::from(sent)) {
+            #[allow(clippy::mem_forget)] // freed by the driver once sent
+            mem::forget(m);
+        }
+        usize::from(sent)
+    }
+}
+
+impl Debug for TxQueue<'_> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TxQueue")
+            .field("port_id", &self.port_id)
+            .field("queue", &self.queue)
+            .field("agent", &self.agent.is_some())
+            .finish()
+    }
+}
+
+impl Drop for TxQueue<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(agent) = self.agent {
+            let queue_id = self.queue.queue_id;
+            if let Err(e) = agent.pause(self.port_id, queue_id, false) {
+                error!(
+                    "Failed to resume sending to {}:{queue_id}: {e}",
+                    self.port_id
+                );
+            }
+        }
+        self.queue.taken.store(false, Ordering::Release);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::EthDev;
-    use crate::test_utils;
+    use super::{DevConfig, EthDev, RssConfig};
+    use crate::{mbuf::MbufSlot, test_utils, Error};
+    use std::collections::BTreeMap;
 
     #[tokio::test]
     async fn test() {
         test_utils::dpdk_setup();
-        let mut dev = EthDev::new(0, 1, 1).unwrap();
+        let mut dev = EthDev::with_agents(
+            0,
+            1,
+            1,
+            RssConfig::new(),
+            &DevConfig::new(),
+            None,
+            &BTreeMap::new(),
+            None,
+        )
+        .unwrap();
         dev.start().unwrap();
+        let _stats = dev.stats().unwrap();
+        dev.reset_stats().unwrap();
+        assert_eq!(dev.stats().unwrap().opackets, 0);
+        let _xstats = dev.xstats().unwrap();
+        dev.reset_xstats().unwrap();
+        dev.set_promiscuous(true).unwrap();
+        assert!(dev.is_promiscuous().unwrap());
+        dev.set_promiscuous(false).unwrap();
+        assert!(!dev.is_promiscuous().unwrap());
+        dev.set_allmulticast(true).unwrap();
+        assert!(dev.is_allmulticast().unwrap());
+        dev.set_allmulticast(false).unwrap();
+        assert_eq!(dev.mtu().unwrap(), 1500);
+        dev.set_gso(true);
+        assert!(dev.gso());
+        dev.set_gro(true);
+        assert!(dev.gro());
+        assert_eq!(dev.rx_burst(), 32);
+        dev.set_rx_burst(64).unwrap();
+        assert_eq!(dev.rx_burst(), 64);
+        assert!(matches!(
+            dev.set_rx_burst(0).unwrap_err(),
+            Error::InvalidArg
+        ));
+        assert!(dev.link().unwrap().up);
+        assert!(matches!(
+            EthDev::with_agents(
+                0,
+                1,
+                1,
+                RssConfig::new(),
+                &DevConfig::new().rx_offloads(u64::MAX),
+                None,
+                &BTreeMap::new(),
+                None,
+            )
+            .unwrap_err(),
+            Error::NotSupported
+        ));
         dev.stop().unwrap();
         dev.start().unwrap();
+        // Queues are taken over from the agents while their handles are held.
+        let mut rxq = dev.rx_queue(0).unwrap();
+        assert!(matches!(dev.rx_queue(0).unwrap_err(), Error::Busy));
+        let mut slots: [MbufSlot; 4] = Default::default();
+        assert_eq!(rxq.rx_burst(&mut slots), 0);
+        let mut txq = dev.tx_queue(0).unwrap();
+        let mut pkts = vec![txq.alloc_mbuf().unwrap()];
+        assert_eq!(txq.send(&mut pkts), 1);
+        drop((rxq, txq));
+        drop((dev.rx_queue(0).unwrap(), dev.tx_queue(0).unwrap()));
         dev.stop().unwrap();
+        drop(dev);
+
+        // Driven directly.
+        let mut dev = EthDev::new(0, 1, 1, &DevConfig::new()).unwrap();
+        let mac = [0x02, 0, 0, 0, 0, 1];
+        dev.set_mac_addr(mac).unwrap();
+        assert_eq!(dev.mac_addr().unwrap(), mac);
+        assert_eq!(dev.mac_addrs().unwrap().first(), Some(&mac));
+        assert!(matches!(dev.tx_queue(0).unwrap_err(), Error::NotStart));
+        dev.start().unwrap();
+        let mut rxq = dev.rx_queue(0).unwrap();
+        assert!(matches!(dev.rx_queue(0).unwrap_err(), Error::Busy));
+        assert!(matches!(dev.rx_queue(1).unwrap_err(), Error::InvalidArg));
+        let mut txq = dev.tx_queue(0).unwrap();
+        let mut pkts = vec![txq.alloc_mbuf().unwrap(), txq.alloc_mbuf().unwrap()];
+        assert_eq!(txq.send(&mut pkts), 2);
+        assert!(pkts.is_empty());
+        // The null device receives empty frames.
+        assert_eq!(rxq.recv(&mut pkts, 4), pkts.len());
+        drop(rxq);
+        drop(txq);
+        drop(dev.rx_queue(0).unwrap());
+        dev.stop().unwrap();
+        assert!(matches!(dev.stop().unwrap_err(), Error::NotStart));
+        // Only secondary processes attach to ports.
+        assert!(matches!(
+            EthDev::attach("net_null0").unwrap_err(),
+            Error::Secondary
+        ));
         // `dev` drop here
     }
+
+    #[test]
+    fn test_close() {
+        test_utils::dpdk_setup();
+        let mut dev = EthDev::new(2, 1, 1, &DevConfig::new()).unwrap();
+        dev.start().unwrap();
+        // The device is stopped before it's closed, and can't be set up again.
+        dev.close().unwrap();
+        assert!(EthDev::new(2, 1, 1, &DevConfig::new()).is_err());
+    }
 }
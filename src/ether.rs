@@ -0,0 +1,251 @@
+//! Ethernet frames for L2-only applications.
+//!
+//! `Ethernet` is bound to a device like other sockets, and sends and receives `EthPacket`s
+//! without going through the IP stack.
+
+use crate::{
+    mbuf::Mbuf,
+    net_dev,
+    proto::{raw::RawSocket, ETHER_HDR_LEN},
+    Error, Result,
+};
+use std::net::IpAddr;
+
+/// Length of an Ether address.
+pub const ETHER_ADDR_LEN: usize = 6;
+
+/// The broadcast Ether address.
+pub const ETHER_BROADCAST: [u8; ETHER_ADDR_LEN] = [0xff; ETHER_ADDR_LEN];
+
+/// An Ethernet frame, with its header parsed and its payload held by an `Mbuf`.
+#[derive(Debug)]
+pub struct EthPacket {
+    /// Destination Ether address.
+    dst: [u8; ETHER_ADDR_LEN],
+    /// Source Ether address.
+    src: [u8; ETHER_ADDR_LEN],
+    /// Ether type, in host byte order.
+    ether_type: u16,
+    /// The payload following the Ethernet header.
+    payload: Mbuf,
+}
+
+impl EthPacket {
+    /// Create an Ethernet frame carrying `payload`.
+    #[inline]
+    #[must_use]
+    pub fn new(
+        dst: [u8; ETHER_ADDR_LEN],
+        src: [u8; ETHER_ADDR_LEN],
+        ether_type: u16,
+        payload: Mbuf,
+    ) -> Self {
+        Self {
+            dst,
+            src,
+            ether_type,
+            payload,
+        }
+    }
+
+    /// Parse an `Mbuf` starting with an Ethernet header, which is stripped from the `Mbuf`.
+    ///
+    /// # Errors
+    ///
+    /// - `Error::InvalidArg`: the first segment is shorter than an Ethernet header.
+    #[inline]
+    pub fn parse(mut m: Mbuf) -> Result<Self> {
+        let (dst, src, ether_type) = match *m.data_slice() {
+            [d0, d1, d2, d3, d4, d5, s0, s1, s2, s3, s4, s5, t0, t1, ..] => (
+                [d0, d1, d2, d3, d4, d5],
+                [s0, s1, s2, s3, s4, s5],
+                u16::from_be_bytes([t0, t1]),
+            ),
+            _ => return Err(Error::InvalidArg),
+        };
+        m.adj(ETHER_HDR_LEN as usize)?;
+        Ok(Self::new(dst, src, ether_type, m))
+    }
+
+    /// Prepend the Ethernet header to the payload, returning an `Mbuf` holding the whole frame.
+    ///
+    /// # Errors
+    ///
+    /// - `Error::NoMem`: not enough headroom for the Ethernet header.
+    #[inline]
+    pub fn into_mbuf(self) -> Result<Mbuf> {
+        let mut m = self.payload;
+        let hdr = m.prepend(ETHER_HDR_LEN as usize)?;
+        let (dst, rest) = hdr.split_at_mut(ETHER_ADDR_LEN);
+        let (src, ether_type) = rest.split_at_mut(ETHER_ADDR_LEN);
+        dst.copy_from_slice(&self.dst);
+        src.copy_from_slice(&self.src);
+        ether_type.copy_from_slice(&self.ether_type.to_be_bytes());
+        Ok(m)
+    }
+
+    /// Destination Ether address.
+    #[inline]
+    #[must_use]
+    pub fn dst(&self) -> [u8; ETHER_ADDR_LEN] {
+        self.dst
+    }
+
+    /// Source Ether address.
+    #[inline]
+    #[must_use]
+    pub fn src(&self) -> [u8; ETHER_ADDR_LEN] {
+        self.src
+    }
+
+    /// Ether type, in host byte order.
+    #[inline]
+    #[must_use]
+    pub fn ether_type(&self) -> u16 {
+        self.ether_type
+    }
+
+    /// The payload following the Ethernet header.
+    #[inline]
+    #[must_use]
+    pub fn payload(&self) -> &Mbuf {
+        &self.payload
+    }
+
+    /// Take the payload following the Ethernet header.
+    #[inline]
+    #[must_use]
+    pub fn into_payload(self) -> Mbuf {
+        self.payload
+    }
+}
+
+/// An Ethernet endpoint bound to a device, sending and receiving `EthPacket`s.
+#[derive(Debug)]
+pub struct Ethernet {
+    /// The raw socket doing the job.
+    sock: RawSocket,
+    /// Ether address of the device.
+    mac_addr: [u8; ETHER_ADDR_LEN],
+}
+
+impl Ethernet {
+    /// Bind to the device with the given IP address.
+    ///
+    /// Only frames with `ether_type` are received if it's set, otherwise all frames arriving at
+    /// the device are received.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::NoDev`: no running device is bound to `addr`.
+    /// - Too much bound sockets.
+    #[inline]
+    pub fn bind(addr: IpAddr, ether_type: Option<u16>) -> Result<Self> {
+        let sock = RawSocket::bind(addr, ether_type)?;
+        let (_, mac_addr) = net_dev::find_dev_by_ip(addr)?;
+        Ok(Self {
+            sock,
+            mac_addr: mac_addr.addr_bytes,
+        })
+    }
+
+    /// Ether address of the device.
+    #[inline]
+    #[must_use]
+    pub fn mac_addr(&self) -> [u8; ETHER_ADDR_LEN] {
+        self.mac_addr
+    }
+
+    /// Allocates an `Mbuf` from the mempool of the device, to hold the payload of an
+    /// `EthPacket`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Mempool exhausted.
+    #[inline]
+    pub fn alloc_mbuf(&self) -> Result<Mbuf> {
+        self.sock.alloc_mbuf()
+    }
+
+    /// Receives a single frame.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    #[inline]
+    pub async fn recv(&self) -> Result<EthPacket> {
+        loop {
+            // Runt frames are skipped.
+            if let Ok(pkt) = EthPacket::parse(self.sock.recv_mbuf().await?) {
+                return Ok(pkt);
+            }
+        }
+    }
+
+    /// Sends a single frame. On success, returns the number of bytes written, including the
+    /// Ethernet header.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::NoMem`: not enough headroom for the Ethernet header.
+    /// - Send agent not started.
+    /// - `Error::NoBuf`: the tx buffer is full, try again later.
+    /// - `Error::Busy`: the device has taken no packet for a while, e.g. its link is down.
+    #[inline]
+    pub async fn send(&self, pkt: EthPacket) -> Result<usize> {
+        self.sock.send_mbuf(pkt.into_mbuf()?).await
+    }
+
+    /// Waits until all frames sent before are put onto the wire.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Send agent not started.
+    /// - `Error::TimedOut`: some frames are still unsent after retrying.
+    #[inline]
+    pub async fn flush(&self) -> Result<()> {
+        self.sock.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EthPacket, ETHER_BROADCAST};
+    use crate::mbuf::Mbuf;
+    use crate::mempool::{Mempool, PktMempool};
+    use crate::test_utils;
+
+    #[test]
+    fn test() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("test_ether", 10).unwrap();
+
+        let mut payload = Mbuf::new(&mp).unwrap();
+        payload.append(4).unwrap().copy_from_slice(&[1, 2, 3, 4]);
+        let src = [2, 0, 0, 0, 0, 1];
+        let pkt = EthPacket::new(ETHER_BROADCAST, src, 0x88b5, payload);
+        let m = pkt.into_mbuf().unwrap();
+        assert_eq!(m.data_len(), 18);
+        assert_eq!(&m.data_slice()[12..], &[0x88, 0xb5, 1, 2, 3, 4]);
+
+        let pkt = EthPacket::parse(m).unwrap();
+        assert_eq!(pkt.dst(), ETHER_BROADCAST);
+        assert_eq!(pkt.src(), src);
+        assert_eq!(pkt.ether_type(), 0x88b5);
+        assert_eq!(pkt.payload().data_slice(), &[1, 2, 3, 4]);
+
+        let mut runt = Mbuf::new(&mp).unwrap();
+        let _ = runt.append(10).unwrap();
+        assert!(EthPacket::parse(runt).is_err());
+    }
+}
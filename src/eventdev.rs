@@ -0,0 +1,1475 @@
+//! Event devices based on `rte_eventdev`, which schedule events, e.g. packets, to the ports of
+//! worker threads by their flows, so that the load is balanced across cores dynamically instead of
+//! by a static mapping of queues to cores. For more information, please refer to
+//! [`Event Device Library document`].
+//!
+//! An `EventDev` is configured with event queues, where events wait to be scheduled, and event
+//! ports linked to them, through which threads enqueue and dequeue events. Events of the same flow
+//! in an atomic queue are processed by one port at a time, so that their order is kept.
+//!
+//! A `Pipeline` runs the stack on top of an event device: the `RxAgent`s inject the packets they
+//! receive into it instead of processing them, and its worker threads pull them out and process
+//! them, e.g. deliver datagrams to sockets.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use async_dpdk::eal::{self, Vdev};
+//! use async_dpdk::eventdev::{Pipeline, PipelineConfig};
+//!
+//! eal::Config::new()
+//!     .corelist("0-2")
+//!     .unwrap()
+//!     .service_corelist("2")
+//!     .unwrap()
+//!     .vdev(Vdev::EventSw(0))
+//!     .device_probe(&["192.168.0.1"])
+//!     .unwrap()
+//!     .enter()
+//!     .unwrap();
+//! let config = PipelineConfig::new("event_sw0").workers(4).scheduler(2);
+//! let pipeline = Pipeline::start(config).unwrap();
+//! // Packets received by the devices are processed by the 4 workers.
+//! // ...
+//! pipeline.stop().unwrap();
+//! ```
+//!
+//! [`Event Device Library document`]: https://doc.dpdk.org/guides/prog_guide/eventdev.html
+
+#![allow(non_camel_case_types)]
+
+use crate::{
+    agent::{self, RxWorker},
+    lcore,
+    mbuf::Mbuf,
+    service,
+    trace::{error, info, warn},
+    Error, Result,
+};
+use dpdk_sys::{rte_mbuf, rte_pktmbuf_free};
+use lazy_static::lazy_static;
+use std::{
+    collections::BTreeSet,
+    ffi::CString,
+    hint,
+    mem::ManuallyDrop,
+    os::raw::{c_char, c_int, c_void},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc as std_mpsc, Arc, Condvar, Mutex, PoisonError, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Maximum number of event devices, i.e. `RTE_EVENT_MAX_DEVS`.
+const RTE_EVENT_MAX_DEVS: usize = 16;
+
+/// Events of packets received from Ethernet devices.
+const RTE_EVENT_TYPE_ETHDEV: u64 = 0x0;
+
+/// Events created by the application.
+const RTE_EVENT_TYPE_CPU: u64 = 0x3;
+
+/// A new event, which is not dequeued before.
+const RTE_EVENT_OP_NEW: u64 = 0;
+
+/// An event dequeued before and sent on to the next stage.
+const RTE_EVENT_OP_FORWARD: u64 = 1;
+
+/// Normal priority of events and links.
+const RTE_EVENT_DEV_PRIORITY_NORMAL: u8 = 128;
+
+/// Mask of the flow id of an event, which is 20 bits.
+const FLOW_ID_MASK: u32 = 0xf_ffff;
+
+/// Offset basis of FNV-1a, hashing the IP addresses of packets without an RSS hash.
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+
+/// Prime of FNV-1a.
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// Max number of events dequeued by a worker of a `Pipeline` in a burst.
+const WORKER_BURST: usize = 32;
+
+/// Max number of events dequeued from a port in a burst, which is no less than the dequeue depth
+/// of any device, a `u8`.
+const MAX_DEQUEUE_DEPTH: usize = 255;
+
+/// Default number of event ports of a `Pipeline` reserved for the `RxAgent`s.
+const PIPELINE_RX_PORTS: u8 = 4;
+
+/// How long a stopping `Pipeline` waits at most for the `RxAgent`s to give their ports back.
+const PORT_RETURN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An event, which carries a packet.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_event {
+    /// Attributes of the event, in bitfields of `flow_id:20`, `sub_event_type:8`,
+    /// `event_type:4`, `op:2`, `rsvd:4`, `sched_type:2`, `queue_id:8`, `priority:8` and
+    /// `impl_opaque:8` from the least significant bit.
+    event: u64,
+    /// The packet carried by the event.
+    mbuf: *mut rte_mbuf,
+}
+
+impl rte_event {
+    /// Offset of `event_type`.
+    const EVENT_TYPE_SHIFT: u32 = 28;
+    /// Offset of `op`.
+    const OP_SHIFT: u32 = 32;
+    /// Offset of `sched_type`.
+    const SCHED_TYPE_SHIFT: u32 = 38;
+    /// Offset of `queue_id`.
+    const QUEUE_ID_SHIFT: u32 = 40;
+    /// Offset of `priority`.
+    const PRIORITY_SHIFT: u32 = 48;
+
+    /// Get the bitfield of `width` bits at `shift`.
+    fn field(&self, shift: u32, width: u32) -> u64 {
+        self.event.wrapping_shr(shift) & 1_u64.wrapping_shl(width).wrapping_sub(1)
+    }
+}
+
+/// Capabilities and limits of an event device.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_event_dev_info {
+    /// Name of the driver.
+    driver_name: *const c_char,
+    /// Generic device information.
+    dev: *mut c_void,
+    /// Minimum dequeue timeout.
+    min_dequeue_timeout_ns: u32,
+    /// Maximum dequeue timeout.
+    max_dequeue_timeout_ns: u32,
+    /// Configured global dequeue timeout.
+    dequeue_timeout_ns: u32,
+    /// Maximum number of event queues.
+    max_event_queues: u8,
+    /// Maximum number of flows in an event queue.
+    max_event_queue_flows: u32,
+    /// Maximum number of priority levels of event queues.
+    max_event_queue_priority_levels: u8,
+    /// Maximum number of priority levels of events.
+    max_event_priority_levels: u8,
+    /// Maximum number of event ports.
+    max_event_ports: u8,
+    /// Maximum number of events dequeued from a port in a burst.
+    max_event_port_dequeue_depth: u8,
+    /// Maximum number of events enqueued to a port in a burst.
+    max_event_port_enqueue_depth: u32,
+    /// Maximum number of queues a port links to.
+    max_event_port_links: u8,
+    /// Maximum number of events in flight.
+    max_num_events: i32,
+    /// Flags of `RTE_EVENT_DEV_CAP_*`.
+    event_dev_cap: u32,
+    /// Maximum number of single-link queue and port pairs.
+    max_single_link_event_port_queue_pairs: u8,
+}
+
+/// Configuration of an event device.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct rte_event_dev_config {
+    /// Global dequeue timeout.
+    dequeue_timeout_ns: u32,
+    /// Maximum number of events in flight.
+    nb_events_limit: i32,
+    /// Number of event queues.
+    nb_event_queues: u8,
+    /// Number of event ports.
+    nb_event_ports: u8,
+    /// Number of flows in an event queue.
+    nb_event_queue_flows: u32,
+    /// Maximum number of events dequeued from a port in a burst.
+    nb_event_port_dequeue_depth: u32,
+    /// Maximum number of events enqueued to a port in a burst.
+    nb_event_port_enqueue_depth: u32,
+    /// Flags of `RTE_EVENT_DEV_CFG_*`.
+    event_dev_cfg: u32,
+    /// Number of single-link queue and port pairs.
+    nb_single_link_event_port_queues: u8,
+}
+
+/// Configuration of an event queue.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct rte_event_queue_conf {
+    /// Number of atomic flows.
+    nb_atomic_flows: u32,
+    /// Number of ordered events in flight.
+    nb_atomic_order_sequences: u32,
+    /// Flags of `RTE_EVENT_QUEUE_CFG_*`.
+    event_queue_cfg: u32,
+    /// One of `RTE_SCHED_TYPE_*`.
+    schedule_type: u8,
+    /// Priority of the queue.
+    priority: u8,
+}
+
+/// Configuration of an event port.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct rte_event_port_conf {
+    /// Number of events in flight beyond which new events are refused.
+    new_event_threshold: i32,
+    /// Maximum number of events dequeued in a burst.
+    dequeue_depth: u16,
+    /// Maximum number of events enqueued in a burst.
+    enqueue_depth: u16,
+    /// Flags of `RTE_EVENT_PORT_CFG_*`.
+    event_port_cfg: u32,
+}
+
+/// Enqueue a burst of events to a port, returning the number of events accepted.
+type event_enqueue_burst_t =
+    Option<unsafe extern "C" fn(port: *mut c_void, ev: *const rte_event, nb_events: u16) -> u16>;
+
+/// Dequeue a burst of events from a port, returning the number of events dequeued.
+type event_dequeue_burst_t = Option<
+    unsafe extern "C" fn(
+        port: *mut c_void,
+        ev: *mut rte_event,
+        nb_events: u16,
+        timeout_ticks: u64,
+    ) -> u16,
+>;
+
+/// Called on each event left in an event device when it stops.
+type rte_eventdev_stop_flush_t =
+    Option<unsafe extern "C" fn(dev_id: u8, event: rte_event, arg: *mut c_void)>;
+
+/// Fast-path functions of an event device, called by the inline enqueue and dequeue functions.
+#[repr(C, align(64))]
+struct rte_event_fp_ops {
+    /// Private data of each port.
+    data: *mut *mut c_void,
+    /// Enqueue an event.
+    enqueue: *const c_void,
+    /// Enqueue a burst of events.
+    enqueue_burst: event_enqueue_burst_t,
+    /// Enqueue a burst of new events.
+    enqueue_new_burst: event_enqueue_burst_t,
+    /// Enqueue a burst of forwarded events.
+    enqueue_forward_burst: event_enqueue_burst_t,
+    /// Dequeue an event.
+    dequeue: *const c_void,
+    /// Dequeue a burst of events.
+    dequeue_burst: event_dequeue_burst_t,
+    /// Enqueue to the tx adapter.
+    txa_enqueue: *const c_void,
+    /// Enqueue to the tx adapter, with the same destination queue.
+    txa_enqueue_same_dest: *const c_void,
+    /// Enqueue to the crypto adapter.
+    ca_enqueue: *const c_void,
+    /// Reserved.
+    reserved: [usize; 6],
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Fast-path functions of all event devices.
+    #[allow(non_upper_case_globals)]
+    static rte_event_fp_ops: [rte_event_fp_ops; RTE_EVENT_MAX_DEVS];
+
+    /// Get the id of an event device by its name.
+    fn rte_event_dev_get_dev_id(name: *const c_char) -> c_int;
+
+    /// Get the capabilities and limits of an event device.
+    fn rte_event_dev_info_get(dev_id: u8, dev_info: *mut rte_event_dev_info) -> c_int;
+
+    /// Configure a stopped event device.
+    fn rte_event_dev_configure(dev_id: u8, dev_conf: *const rte_event_dev_config) -> c_int;
+
+    /// Get the default configuration of an event queue.
+    fn rte_event_queue_default_conf_get(
+        dev_id: u8,
+        queue_id: u8,
+        queue_conf: *mut rte_event_queue_conf,
+    ) -> c_int;
+
+    /// Set up an event queue.
+    fn rte_event_queue_setup(
+        dev_id: u8,
+        queue_id: u8,
+        queue_conf: *const rte_event_queue_conf,
+    ) -> c_int;
+
+    /// Get the default configuration of an event port.
+    fn rte_event_port_default_conf_get(
+        dev_id: u8,
+        port_id: u8,
+        port_conf: *mut rte_event_port_conf,
+    ) -> c_int;
+
+    /// Set up an event port.
+    fn rte_event_port_setup(
+        dev_id: u8,
+        port_id: u8,
+        port_conf: *const rte_event_port_conf,
+    ) -> c_int;
+
+    /// Link event queues to a port, returning the number of links established.
+    fn rte_event_port_link(
+        dev_id: u8,
+        port_id: u8,
+        queues: *const u8,
+        priorities: *const u8,
+        nb_links: u16,
+    ) -> c_int;
+
+    /// Unlink event queues from a port, returning the number of links removed.
+    fn rte_event_port_unlink(dev_id: u8, port_id: u8, queues: *mut u8, nb_unlinks: u16) -> c_int;
+
+    /// Get the id of the service scheduling the events of a device in software.
+    fn rte_event_dev_service_id_get(dev_id: u8, service_id: *mut u32) -> c_int;
+
+    /// Register the callback called on events left in a device when it stops.
+    fn rte_event_dev_stop_flush_callback_register(
+        dev_id: u8,
+        callback: rte_eventdev_stop_flush_t,
+        userdata: *mut c_void,
+    ) -> c_int;
+
+    /// Start an event device.
+    fn rte_event_dev_start(dev_id: u8) -> c_int;
+
+    /// Stop an event device.
+    fn rte_event_dev_stop(dev_id: u8);
+
+    /// Close a stopped event device.
+    fn rte_event_dev_close(dev_id: u8) -> c_int;
+}
+
+/// Free the packet of an event left in a device when it stops.
+#[allow(unsafe_code)]
+unsafe extern "C" fn free_event(_dev_id: u8, event: rte_event, _arg: *mut c_void) {
+    if !event.mbuf.is_null() {
+        // SAFETY: the packet is owned by the event
+        unsafe { rte_pktmbuf_free(event.mbuf) };
+    }
+}
+
+/// How the events of an event queue are scheduled.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SchedType {
+    /// Events of a flow are spread across ports, and put back in order when they are forwarded
+    /// to the next queue.
+    Ordered,
+    /// Events of a flow are processed by one port at a time, which keeps their order.
+    #[default]
+    Atomic,
+    /// Events are spread across ports without any order.
+    Parallel,
+}
+
+impl SchedType {
+    /// The `RTE_SCHED_TYPE_*` value.
+    fn raw(self) -> u8 {
+        match self {
+            SchedType::Ordered => 0,
+            SchedType::Atomic => 1,
+            SchedType::Parallel => 2,
+        }
+    }
+
+    /// Convert an `RTE_SCHED_TYPE_*` value, where unknown values are taken as `Parallel`.
+    fn from_raw(raw: u64) -> Self {
+        match raw {
+            0 => SchedType::Ordered,
+            1 => SchedType::Atomic,
+            _ => SchedType::Parallel,
+        }
+    }
+}
+
+/// An event carrying a packet.
+#[derive(Debug)]
+pub struct Event {
+    /// The packet.
+    mbuf: Mbuf,
+    /// The event queue to enqueue to.
+    queue_id: u8,
+    /// The flow, of which only the lower 20 bits are kept.
+    flow_id: u32,
+    /// How the event is scheduled.
+    sched: SchedType,
+    /// Priority of the event, where 0 is the highest.
+    priority: u8,
+    /// Whether the event is dequeued before.
+    forwarded: bool,
+}
+
+impl Event {
+    /// Create a new event carrying `mbuf` to `queue_id`, in flow 0 with normal priority and
+    /// atomic scheduling.
+    #[inline]
+    #[must_use]
+    pub fn new(mbuf: Mbuf, queue_id: u8) -> Self {
+        Self {
+            mbuf,
+            queue_id,
+            flow_id: 0,
+            sched: SchedType::Atomic,
+            priority: RTE_EVENT_DEV_PRIORITY_NORMAL,
+            forwarded: false,
+        }
+    }
+
+    /// Set the flow, of which only the lower 20 bits are kept.
+    #[inline]
+    #[must_use]
+    pub fn flow_id(mut self, flow_id: u32) -> Self {
+        self.flow_id = flow_id & FLOW_ID_MASK;
+        self
+    }
+
+    /// Set how the event is scheduled, which should match the event queue.
+    #[inline]
+    #[must_use]
+    pub fn sched(mut self, sched: SchedType) -> Self {
+        self.sched = sched;
+        self
+    }
+
+    /// Set the priority, where 0 is the highest.
+    #[inline]
+    #[must_use]
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Send a dequeued event on to `queue_id`, i.e. the next stage.
+    #[inline]
+    #[must_use]
+    pub fn forward(mut self, queue_id: u8) -> Self {
+        self.queue_id = queue_id;
+        self.forwarded = true;
+        self
+    }
+
+    /// The event queue the event is enqueued to.
+    #[inline]
+    #[must_use]
+    pub fn queue(&self) -> u8 {
+        self.queue_id
+    }
+
+    /// The flow of the event.
+    #[inline]
+    #[must_use]
+    pub fn flow(&self) -> u32 {
+        self.flow_id
+    }
+
+    /// The packet carried by the event.
+    #[inline]
+    #[must_use]
+    pub fn mbuf(&self) -> &Mbuf {
+        &self.mbuf
+    }
+
+    /// Take the packet carried by the event.
+    #[inline]
+    #[must_use]
+    pub fn into_mbuf(self) -> Mbuf {
+        self.mbuf
+    }
+
+    /// Convert to an `rte_event`, which takes the ownership of the packet.
+    fn into_raw(self, event_type: u64) -> rte_event {
+        let op = if self.forwarded {
+            RTE_EVENT_OP_FORWARD
+        } else {
+            RTE_EVENT_OP_NEW
+        };
+        let event = u64::from(self.flow_id)
+            | event_type.wrapping_shl(rte_event::EVENT_TYPE_SHIFT)
+            | op.wrapping_shl(rte_event::OP_SHIFT)
+            | u64::from(self.sched.raw()).wrapping_shl(rte_event::SCHED_TYPE_SHIFT)
+            | u64::from(self.queue_id).wrapping_shl(rte_event::QUEUE_ID_SHIFT)
+            | u64::from(self.priority).wrapping_shl(rte_event::PRIORITY_SHIFT);
+        let mbuf = ManuallyDrop::new(self.mbuf);
+        rte_event {
+            event,
+            mbuf: mbuf.as_ptr(),
+        }
+    }
+
+    /// Convert an `rte_event`, taking the ownership of its packet.
+    #[allow(clippy::cast_possible_truncation)] // bitfields of no more than 20 bits
+    fn from_raw(ev: rte_event) -> Option<Self> {
+        let mbuf = Mbuf::new_with_ptr(ev.mbuf).ok()?;
+        Some(Self {
+            mbuf,
+            queue_id: ev.field(rte_event::QUEUE_ID_SHIFT, 8) as u8,
+            flow_id: ev.field(0, 20) as u32,
+            sched: SchedType::from_raw(ev.field(rte_event::SCHED_TYPE_SHIFT, 2)),
+            priority: ev.field(rte_event::PRIORITY_SHIFT, 8) as u8,
+            forwarded: ev.field(rte_event::OP_SHIFT, 2) == RTE_EVENT_OP_FORWARD,
+        })
+    }
+}
+
+/// Capabilities and limits of an event device.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventDevInfo {
+    /// Maximum number of event queues.
+    pub max_queues: u8,
+    /// Maximum number of event ports.
+    pub max_ports: u8,
+    /// Maximum number of flows in an event queue.
+    pub max_flows: u32,
+    /// Maximum number of events in flight.
+    pub max_events: i32,
+    /// Maximum number of events dequeued from a port in a burst.
+    pub max_dequeue_depth: u8,
+    /// Maximum number of events enqueued to a port in a burst.
+    pub max_enqueue_depth: u32,
+}
+
+lazy_static! {
+    /// Ids of the event devices opened by `EventDev::open`.
+    static ref OPENED: Mutex<BTreeSet<u8>> = Mutex::new(BTreeSet::new());
+}
+
+/// An opened event device, which is stopped and closed once the `EventDev` and all its ports
+/// are dropped.
+#[derive(Debug)]
+struct DevHandle {
+    /// `dev_id` assigned by DPDK.
+    dev_id: u8,
+    /// The service scheduling events in software and the service lcore running it, if any.
+    scheduler: Mutex<Option<(u32, u32)>>,
+}
+
+#[allow(unsafe_code)]
+impl Drop for DevHandle {
+    fn drop(&mut self) {
+        let scheduler = self
+            .scheduler
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some((id, lcore)) = scheduler {
+            service::stop_driver_service(id, lcore);
+        }
+        // SAFETY: ffi, packets left in the device are freed by `free_event`
+        unsafe {
+            rte_event_dev_stop(self.dev_id);
+            if let Err(e) = Error::from_ret(rte_event_dev_close(self.dev_id), "rte_event_dev_close")
+            {
+                error!("Failed to close event device {}: {e}", self.dev_id);
+            }
+        }
+        let _removed = OPENED
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.dev_id);
+    }
+}
+
+/// An event device, which is a safe wrapper of `rte_eventdev`.
+///
+/// The device is configured with `configure`, and its queues and ports are set up before it
+/// starts. Devices scheduling events in software, e.g. `Vdev::EventSw`, also need a service lcore
+/// to run their scheduler with `run_scheduler`.
+///
+/// ```no_run
+/// use async_dpdk::eventdev::{Event, EventDev, SchedType};
+/// # use async_dpdk::mbuf::Mbuf;
+/// # use async_dpdk::mempool::{Mempool, PktMempool};
+///
+/// # let mp = PktMempool::create("eventdev_example", 64).unwrap();
+/// let dev = EventDev::open("event_sw0").unwrap();
+/// dev.configure(1, 2).unwrap();
+/// dev.queue_setup(0, SchedType::Atomic).unwrap();
+/// let mut producer = dev.port_setup(0).unwrap();
+/// let mut consumer = dev.port_setup(1).unwrap();
+/// consumer.link(&[0]).unwrap();
+/// dev.run_scheduler(2).unwrap();
+/// dev.start().unwrap();
+///
+/// let mut events = vec![Event::new(Mbuf::new(&mp).unwrap(), 0).flow_id(1)];
+/// assert_eq!(producer.enqueue(&mut events), 1);
+/// while consumer.dequeue(&mut events, 32) == 0 {}
+/// ```
+#[derive(Debug)]
+pub struct EventDev {
+    /// The opened device.
+    dev: Arc<DevHandle>,
+}
+
+#[allow(unsafe_code)]
+impl EventDev {
+    /// Open the event device named `name`, e.g. `event_sw0`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `name` contains a nul byte.
+    /// - Lock poisoned.
+    /// - `Error::NoDev`: there's no such device.
+    /// - `Error::Busy`: the device is already opened.
+    #[inline]
+    pub fn open(name: &str) -> Result<Self> {
+        let name = CString::new(name).map_err(Error::from)?;
+        // SAFETY: ffi
+        let ret = unsafe { rte_event_dev_get_dev_id(name.as_ptr()) };
+        Error::from_ret(ret, "rte_event_dev_get_dev_id")?;
+        let dev_id = u8::try_from(ret).ok().ok_or(Error::NoDev)?;
+        if !OPENED.lock().map_err(Error::from)?.insert(dev_id) {
+            return Err(Error::Busy);
+        }
+        // Closed on failures by the drop of `dev`.
+        let dev = Arc::new(DevHandle {
+            dev_id,
+            scheduler: Mutex::new(None),
+        });
+        // SAFETY: `free_event` takes no user data
+        let errno = unsafe {
+            rte_event_dev_stop_flush_callback_register(dev_id, Some(free_event), ptr::null_mut())
+        };
+        Error::from_ret(errno, "rte_event_dev_stop_flush_callback_register")?;
+        Ok(Self { dev })
+    }
+
+    /// The `dev_id` of the device.
+    #[inline]
+    #[must_use]
+    pub fn id(&self) -> u8 {
+        self.dev.dev_id
+    }
+
+    /// Get the raw capabilities and limits of the device.
+    fn raw_info(&self) -> Result<rte_event_dev_info> {
+        // SAFETY: all zeros is a valid `rte_event_dev_info`
+        let mut info = unsafe { std::mem::zeroed::<rte_event_dev_info>() };
+        // SAFETY: ffi
+        let errno = unsafe { rte_event_dev_info_get(self.id(), ptr::addr_of_mut!(info)) };
+        Error::from_ret(errno, "rte_event_dev_info_get")?;
+        Ok(info)
+    }
+
+    /// Get the capabilities and limits of the device.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Failed to get the information from the driver.
+    #[inline]
+    pub fn info(&self) -> Result<EventDevInfo> {
+        let info = self.raw_info()?;
+        Ok(EventDevInfo {
+            max_queues: info.max_event_queues,
+            max_ports: info.max_event_ports,
+            max_flows: info.max_event_queue_flows,
+            max_events: info.max_num_events,
+            max_dequeue_depth: info.max_event_port_dequeue_depth,
+            max_enqueue_depth: info.max_event_port_enqueue_depth,
+        })
+    }
+
+    /// Configure the stopped device with `n_queues` event queues and `n_ports` event ports, with
+    /// the limits of the device.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `n_queues` or `n_ports` is 0 or exceeds the limits of the device.
+    /// - `Error::Busy`: the device is started.
+    #[inline]
+    pub fn configure(&self, n_queues: u8, n_ports: u8) -> Result<()> {
+        let info = self.raw_info()?;
+        if n_queues == 0
+            || n_ports == 0
+            || n_queues > info.max_event_queues
+            || n_ports > info.max_event_ports
+        {
+            return Err(Error::InvalidArg);
+        }
+        let conf = rte_event_dev_config {
+            dequeue_timeout_ns: info.min_dequeue_timeout_ns,
+            nb_events_limit: info.max_num_events,
+            nb_event_queues: n_queues,
+            nb_event_ports: n_ports,
+            nb_event_queue_flows: info.max_event_queue_flows,
+            nb_event_port_dequeue_depth: u32::from(info.max_event_port_dequeue_depth),
+            nb_event_port_enqueue_depth: info.max_event_port_enqueue_depth,
+            event_dev_cfg: 0,
+            nb_single_link_event_port_queues: 0,
+        };
+        // SAFETY: `conf` is copied by DPDK
+        let errno = unsafe { rte_event_dev_configure(self.id(), ptr::addr_of!(conf)) };
+        Error::from_ret(errno, "rte_event_dev_configure")
+    }
+
+    /// Set up event queue `queue_id`, whose events are scheduled as `sched`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `queue_id` is out of the configured range.
+    /// - `Error::Busy`: the device is started.
+    #[inline]
+    pub fn queue_setup(&self, queue_id: u8, sched: SchedType) -> Result<()> {
+        let mut conf = rte_event_queue_conf::default();
+        // SAFETY: ffi
+        let errno = unsafe {
+            rte_event_queue_default_conf_get(self.id(), queue_id, ptr::addr_of_mut!(conf))
+        };
+        Error::from_ret(errno, "rte_event_queue_default_conf_get")?;
+        conf.schedule_type = sched.raw();
+        // SAFETY: `conf` is copied by DPDK
+        let errno = unsafe { rte_event_queue_setup(self.id(), queue_id, ptr::addr_of!(conf)) };
+        Error::from_ret(errno, "rte_event_queue_setup")
+    }
+
+    /// Set up event port `port_id` with the default configuration.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `port_id` is out of the configured range.
+    /// - `Error::Busy`: the device is started.
+    #[inline]
+    pub fn port_setup(&self, port_id: u8) -> Result<EventPort> {
+        let mut conf = rte_event_port_conf::default();
+        // SAFETY: ffi
+        let errno =
+            unsafe { rte_event_port_default_conf_get(self.id(), port_id, ptr::addr_of_mut!(conf)) };
+        Error::from_ret(errno, "rte_event_port_default_conf_get")?;
+        // SAFETY: `conf` is copied by DPDK
+        let errno = unsafe { rte_event_port_setup(self.id(), port_id, ptr::addr_of!(conf)) };
+        Error::from_ret(errno, "rte_event_port_setup")?;
+        Ok(EventPort {
+            dev: Arc::clone(&self.dev),
+            port_id,
+        })
+    }
+
+    /// The service scheduling the events of the device in software, if any.
+    fn service_id(&self) -> Option<u32> {
+        let mut id = 0;
+        // SAFETY: ffi
+        let errno = unsafe { rte_event_dev_service_id_get(self.id(), ptr::addr_of_mut!(id)) };
+        Error::from_ret(errno, "rte_event_dev_service_id_get")
+            .ok()
+            .map(|()| id)
+    }
+
+    /// Run the scheduler of a device scheduling events in software on service lcore `lcore`,
+    /// which should be reserved with `eal::Config::service_corelist`. The lcore may run other
+    /// services in turn. The scheduler stops once the device is closed.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::NotSupported`: the device schedules events in hardware.
+    /// - `Error::Already`: the scheduler is already running.
+    /// - `Error::InvalidArg`: `lcore` is not a service lcore.
+    #[inline]
+    pub fn run_scheduler(&self, lcore: u32) -> Result<()> {
+        let id = self.service_id().ok_or(Error::NotSupported)?;
+        let mut scheduler = self.dev.scheduler.lock().map_err(Error::from)?;
+        if scheduler.is_some() {
+            return Err(Error::Already);
+        }
+        service::run_driver_service(id, lcore)?;
+        *scheduler = Some((id, lcore));
+        Ok(())
+    }
+
+    /// Start the device, after which events are scheduled.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::Busy`: the device is already started.
+    /// - Some of the queues or ports are not set up, or no port links to a queue.
+    #[inline]
+    pub fn start(&self) -> Result<()> {
+        // SAFETY: ffi
+        let errno = unsafe { rte_event_dev_start(self.id()) };
+        Error::from_ret(errno, "rte_event_dev_start")
+    }
+}
+
+/// An event port, through which a thread enqueues and dequeues events.
+#[derive(Debug)]
+pub struct EventPort {
+    /// The device the port belongs to.
+    dev: Arc<DevHandle>,
+    /// `port_id` of the port.
+    port_id: u8,
+}
+
+#[allow(unsafe_code)]
+impl EventPort {
+    /// The `port_id` of the port.
+    #[inline]
+    #[must_use]
+    pub fn id(&self) -> u8 {
+        self.port_id
+    }
+
+    /// Link `queues` to the port with normal priority, so that their events are dequeued from it.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: some of `queues` are not set up.
+    /// - `Error::NoSpace`: the port links to too many queues.
+    #[inline]
+    pub fn link(&self, queues: &[u8]) -> Result<()> {
+        let n = u16::try_from(queues.len()).ok().ok_or(Error::InvalidArg)?;
+        // SAFETY: `queues` holds `n` ids, and a null `priorities` means normal ones
+        let linked = unsafe {
+            rte_event_port_link(
+                self.dev.dev_id,
+                self.port_id,
+                queues.as_ptr(),
+                ptr::null(),
+                n,
+            )
+        };
+        if linked < c_int::from(n) {
+            return Err(Error::from_errno("rte_event_port_link"));
+        }
+        Ok(())
+    }
+
+    /// Unlink `queues` from the port.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: some of `queues` are not linked to the port.
+    #[inline]
+    pub fn unlink(&self, queues: &[u8]) -> Result<()> {
+        let mut queues = queues.to_vec();
+        let n = u16::try_from(queues.len()).ok().ok_or(Error::InvalidArg)?;
+        // SAFETY: `queues` holds `n` ids
+        let unlinked =
+            unsafe { rte_event_port_unlink(self.dev.dev_id, self.port_id, queues.as_mut_ptr(), n) };
+        if unlinked < c_int::from(n) {
+            return Err(Error::from_errno("rte_event_port_unlink"));
+        }
+        Ok(())
+    }
+
+    /// Fast-path functions of the device.
+    fn fp_ops(&self) -> Option<&'static rte_event_fp_ops> {
+        // SAFETY: the table is initialized by EAL and lives as long as the process
+        unsafe { rte_event_fp_ops.get(usize::from(self.dev.dev_id)) }
+    }
+
+    /// Enqueue as many events from the front of `events` as the device accepts, and remove them
+    /// from `events`. Returns the number of events enqueued.
+    #[inline]
+    pub fn enqueue(&mut self, events: &mut Vec<Event>) -> usize {
+        let mut raw: Vec<rte_event> = events
+            .drain(..)
+            .map(|event| event.into_raw(RTE_EVENT_TYPE_CPU))
+            .collect();
+        let enqueued = self.enqueue_raw(&mut raw);
+        // Events refused are given back.
+        events.extend(raw.into_iter().filter_map(Event::from_raw));
+        enqueued
+    }
+
+    /// Enqueue as many events from the front of `raw` as the device accepts, and remove them from
+    /// `raw`. Returns the number of events enqueued.
+    fn enqueue_raw(&mut self, raw: &mut Vec<rte_event>) -> usize {
+        let Some(ops) = self.fp_ops() else {
+            return 0;
+        };
+        let Some(enqueue_burst) = ops.enqueue_burst else {
+            return 0;
+        };
+        let n = u16::try_from(raw.len()).unwrap_or(u16::MAX);
+        // SAFETY: the port is set up, and `raw` holds at least `n` events
+        let enqueued = unsafe {
+            let port = *ops.data.add(usize::from(self.port_id));
+            enqueue_burst(port, raw.as_ptr(), n)
+        };
+        let _enqueued = raw.drain(..usize::from(enqueued));
+        usize::from(enqueued)
+    }
+
+    /// Dequeue up to `max` events scheduled to the port to the back of `events`, without waiting,
+    /// and no more than 255, i.e. the dequeue depth of any device, at a time. Returns the number
+    /// of events dequeued.
+    ///
+    /// Dequeuing again releases the events dequeued before, i.e. lets the next events of their
+    /// flows be scheduled.
+    #[inline]
+    pub fn dequeue(&mut self, events: &mut Vec<Event>, max: usize) -> usize {
+        let Some(ops) = self.fp_ops() else {
+            return 0;
+        };
+        let Some(dequeue_burst) = ops.dequeue_burst else {
+            return 0;
+        };
+        let mut raw = [rte_event {
+            event: 0,
+            mbuf: ptr::null_mut(),
+        }; MAX_DEQUEUE_DEPTH];
+        #[allow(clippy::cast_possible_truncation)] // no more than `MAX_DEQUEUE_DEPTH`
+        let n = max.min(MAX_DEQUEUE_DEPTH) as u16;
+        // SAFETY: the port is set up, and `raw` holds at least `n` events
+        let dequeued = unsafe {
+            let port = *ops.data.add(usize::from(self.port_id));
+            dequeue_burst(port, raw.as_mut_ptr(), n, 0)
+        };
+        // Dequeued events are sent on as forwarded ones, which releases their flows.
+        events.extend(
+            raw.into_iter()
+                .take(usize::from(dequeued))
+                .filter_map(Event::from_raw)
+                .map(|mut event| {
+                    event.forwarded = true;
+                    event
+                }),
+        );
+        usize::from(dequeued)
+    }
+}
+
+/// Settings of a `Pipeline`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineConfig {
+    /// Name of the event device.
+    dev: String,
+    /// Number of worker threads.
+    workers: usize,
+    /// The CPU core the first worker is pinned to, if any.
+    first_core: Option<usize>,
+    /// Number of event ports reserved for the `RxAgent`s.
+    rx_ports: u8,
+    /// How packets are scheduled.
+    sched: SchedType,
+    /// The service lcore running the scheduler of the device, if any.
+    scheduler: Option<u32>,
+}
+
+impl PipelineConfig {
+    /// Settings of a pipeline on the event device named `dev`, with a worker thread without core
+    /// affinity, 4 ports for the `RxAgent`s and atomic scheduling.
+    #[inline]
+    #[must_use]
+    pub fn new(dev: &str) -> Self {
+        Self {
+            dev: dev.to_owned(),
+            workers: 1,
+            first_core: None,
+            rx_ports: PIPELINE_RX_PORTS,
+            sched: SchedType::Atomic,
+            scheduler: None,
+        }
+    }
+
+    /// Set the number of worker threads.
+    #[inline]
+    #[must_use]
+    pub fn workers(mut self, n: usize) -> Self {
+        self.workers = n;
+        self
+    }
+
+    /// Pin the workers to CPU cores `first..first + n`.
+    #[inline]
+    #[must_use]
+    pub fn pinned(mut self, first: usize) -> Self {
+        self.first_core = Some(first);
+        self
+    }
+
+    /// Set the number of event ports reserved for the `RxAgent`s. Agents beyond it go on
+    /// processing the packets they receive by themselves.
+    #[inline]
+    #[must_use]
+    pub fn rx_ports(mut self, n: u8) -> Self {
+        self.rx_ports = n;
+        self
+    }
+
+    /// Set how packets are scheduled to the workers. Packets of a flow are processed in order
+    /// with `SchedType::Atomic`, where a flow is given by the RSS hash of the device, or the IP
+    /// addresses if there's none.
+    #[inline]
+    #[must_use]
+    pub fn sched(mut self, sched: SchedType) -> Self {
+        self.sched = sched;
+        self
+    }
+
+    /// Run the scheduler of a device scheduling events in software on service lcore `lcore`.
+    #[inline]
+    #[must_use]
+    pub fn scheduler(mut self, lcore: u32) -> Self {
+        self.scheduler = Some(lcore);
+        self
+    }
+}
+
+/// Statistics of a `Pipeline`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    /// Number of packets injected by the `RxAgent`s.
+    pub injected: u64,
+    /// Number of packets dropped since the event device refuses them, e.g. when too many events
+    /// are in flight.
+    pub rejected: u64,
+    /// Number of packets processed by the workers.
+    pub processed: u64,
+    /// Number of packets dropped by the classifier in the workers, e.g. malformed or matching no
+    /// socket.
+    pub dropped: u64,
+}
+
+/// State of a `Pipeline` shared with its workers and the `RxAgent`s.
+#[derive(Debug)]
+struct Shared {
+    /// Whether the workers are running.
+    running: AtomicBool,
+    /// How packets are scheduled.
+    sched: SchedType,
+    /// Event ports reserved for the `RxAgent`s and not taken yet.
+    rx_ports: Mutex<Vec<EventPort>>,
+    /// Notified when an `RxAgent` gives its port back.
+    returned: Condvar,
+    /// Number of packets injected.
+    injected: AtomicU64,
+    /// Number of packets refused by the device.
+    rejected: AtomicU64,
+    /// Number of packets processed by the workers.
+    processed: AtomicU64,
+    /// Number of packets dropped by the classifier in the workers.
+    dropped: AtomicU64,
+}
+
+lazy_static! {
+    /// The running pipeline, if any.
+    static ref PIPELINE: RwLock<Option<Arc<Shared>>> = RwLock::new(None);
+}
+
+/// Generation of `PIPELINE`, increased each time a pipeline starts or stops, so that the
+/// `RxAgent`s only look it up on changes.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// A pipeline running the stack on worker threads, which pull the packets received by the
+/// `RxAgent`s out of an event device. There's at most one pipeline at a time.
+///
+/// The workers stop once the pipeline is stopped or dropped, after which the `RxAgent`s go back to
+/// processing the packets they receive by themselves.
+#[derive(Debug)]
+pub struct Pipeline {
+    /// The event device.
+    dev: Option<EventDev>,
+    /// State shared with the workers and the `RxAgent`s.
+    shared: Arc<Shared>,
+    /// Number of event ports reserved for the `RxAgent`s.
+    rx_ports: usize,
+    /// The worker threads.
+    workers: Vec<JoinHandle<Result<()>>>,
+}
+
+impl Pipeline {
+    /// Configure the event device with an event queue, an event port for each worker and those for
+    /// the `RxAgent`s, and start the workers.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::Already`: a pipeline is running.
+    /// - `Error::InvalidArg`: no workers or ports for the `RxAgent`s, more ports than the device
+    ///   supports, or no scheduler for a device scheduling events in software.
+    /// - Failed to open, configure or start the event device, or to spawn or pin the workers.
+    #[inline]
+    pub fn start(conf: PipelineConfig) -> Result<Self> {
+        if PIPELINE.read().map_err(Error::from)?.is_some() {
+            return Err(Error::Already);
+        }
+        let n_workers = u8::try_from(conf.workers).ok().ok_or(Error::InvalidArg)?;
+        let n_ports = n_workers
+            .checked_add(conf.rx_ports)
+            .ok_or(Error::InvalidArg)?;
+        if n_workers == 0 || conf.rx_ports == 0 {
+            return Err(Error::InvalidArg);
+        }
+        let dev = EventDev::open(&conf.dev)?;
+        dev.configure(1, n_ports)?;
+        dev.queue_setup(0, conf.sched)?;
+        let mut worker_ports = Vec::with_capacity(conf.workers);
+        for port_id in 0..n_workers {
+            let port = dev.port_setup(port_id)?;
+            port.link(&[0])?;
+            worker_ports.push(port);
+        }
+        let rx_ports = (n_workers..n_ports)
+            .map(|port_id| dev.port_setup(port_id))
+            .collect::<Result<Vec<_>>>()?;
+        match conf.scheduler {
+            Some(lcore) => dev.run_scheduler(lcore)?,
+            None if dev.service_id().is_some() => return Err(Error::InvalidArg),
+            None => {}
+        }
+        dev.start()?;
+
+        let shared = Arc::new(Shared {
+            running: AtomicBool::new(true),
+            sched: conf.sched,
+            rx_ports: Mutex::new(rx_ports),
+            returned: Condvar::new(),
+            injected: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            processed: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+        let mut pipeline = Self {
+            dev: Some(dev),
+            shared,
+            rx_ports: usize::from(conf.rx_ports),
+            workers: Vec::with_capacity(conf.workers),
+        };
+        for (index, port) in worker_ports.into_iter().enumerate() {
+            let core = conf.first_core.map(|first| first.wrapping_add(index));
+            // The workers spawned are stopped by the drop of `pipeline` on failures.
+            let worker = spawn_worker(index, core, port, Arc::clone(&pipeline.shared))?;
+            pipeline.workers.push(worker);
+        }
+        let mut current = PIPELINE.write().map_err(Error::from)?;
+        if current.is_some() {
+            return Err(Error::Already);
+        }
+        *current = Some(Arc::clone(&pipeline.shared));
+        let _prev = GENERATION.fetch_add(1, Ordering::AcqRel);
+        info!("Pipeline started with {n_workers} workers");
+        Ok(pipeline)
+    }
+
+    /// Get the statistics of the pipeline.
+    #[inline]
+    #[must_use]
+    pub fn stats(&self) -> PipelineStats {
+        let shared = &self.shared;
+        PipelineStats {
+            injected: shared.injected.load(Ordering::Relaxed),
+            rejected: shared.rejected.load(Ordering::Relaxed),
+            processed: shared.processed.load(Ordering::Relaxed),
+            dropped: shared.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stop the pipeline, waiting for the workers to terminate and the `RxAgent`s to give their
+    /// ports back, for `PORT_RETURN_TIMEOUT` at most. The event device is closed once all the
+    /// ports are given back, and the packets left in it are dropped.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - A worker terminated with an error.
+    #[inline]
+    pub fn stop(mut self) -> Result<()> {
+        self.shutdown()
+    }
+
+    /// Stop the workers, and detach the pipeline from the `RxAgent`s.
+    fn shutdown(&mut self) -> Result<()> {
+        self.shared.running.store(false, Ordering::Release);
+        {
+            let mut current = PIPELINE.write().unwrap_or_else(PoisonError::into_inner);
+            if current
+                .as_ref()
+                .map_or(false, |shared| Arc::ptr_eq(shared, &self.shared))
+            {
+                *current = None;
+                let _prev = GENERATION.fetch_add(1, Ordering::AcqRel);
+            }
+        }
+        let mut res = Ok(());
+        for worker in self.workers.drain(..) {
+            match worker.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => res = Err(e),
+                Err(_) => error!("Pipeline worker panicked"),
+            }
+        }
+        if let Some(dev) = self.dev.take() {
+            let rx_ports = self
+                .shared
+                .rx_ports
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let (rx_ports, timeout) = self
+                .shared
+                .returned
+                .wait_timeout_while(rx_ports, PORT_RETURN_TIMEOUT, |ports| {
+                    ports.len() < self.rx_ports
+                })
+                .unwrap_or_else(PoisonError::into_inner);
+            if timeout.timed_out() {
+                warn!(
+                    "{} event ports not given back by the RxAgents, the device is closed later",
+                    self.rx_ports.saturating_sub(rx_ports.len())
+                );
+            }
+            drop(rx_ports);
+            drop(dev);
+        }
+        res
+    }
+}
+
+impl Drop for Pipeline {
+    #[inline]
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            error!("Pipeline worker terminated with an error {e}");
+        }
+    }
+}
+
+/// Spawn a worker thread, pinned to `core` if given, processing the packets dequeued from `port`.
+fn spawn_worker(
+    index: usize,
+    core: Option<usize>,
+    port: EventPort,
+    shared: Arc<Shared>,
+) -> Result<JoinHandle<Result<()>>> {
+    let (pinned_tx, pinned_rx) = std_mpsc::sync_channel(1);
+    let handle = thread::Builder::new()
+        .name(format!("event-worker-{index}"))
+        .spawn(move || {
+            if let Some(core) = core {
+                let res = agent::pin_to_core(core);
+                let ok = res.is_ok();
+                _ = pinned_tx.send(res);
+                if !ok {
+                    return Ok(());
+                }
+            }
+            let res = work(port, &shared);
+            if let Err(ref e) = res {
+                error!("Pipeline worker {index} terminated with an error {e}");
+            }
+            res
+        })
+        .map_err(|e| Error::from(e.raw_os_error().unwrap_or(libc::EAGAIN)))?;
+    if core.is_some() {
+        pinned_rx.recv().map_err(Error::from)??;
+    }
+    Ok(handle)
+}
+
+/// The loop of a worker, processing the packets dequeued from `port` until the pipeline stops.
+fn work(mut port: EventPort, shared: &Shared) -> Result<()> {
+    let mut worker = RxWorker::new(lcore::socket_id())?;
+    let mut events = Vec::with_capacity(WORKER_BURST);
+    while shared.running.load(Ordering::Acquire) {
+        let n = port.dequeue(&mut events, WORKER_BURST);
+        if n == 0 {
+            // Expired fragments are freed while idle.
+            let _dropped = worker.process(Vec::new());
+            hint::spin_loop();
+            continue;
+        }
+        let _processed = shared.processed.fetch_add(n as u64, Ordering::Relaxed);
+        let dropped = worker.process(events.drain(..).map(Event::into_mbuf));
+        if dropped > 0 {
+            let _dropped = shared.dropped.fetch_add(dropped, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+/// The flow of a received packet, which is the RSS hash computed by the device if any, or a hash
+/// of its IP addresses otherwise, so that the fragments of a datagram are in the same flow.
+fn flow_id(m: &Mbuf) -> u32 {
+    if let Some(hash) = m.rss_hash() {
+        return hash & FLOW_ID_MASK;
+    }
+    let data = m.data_slice();
+    let addrs = match data.get(12..14) {
+        // The source and destination addresses of IPv4.
+        Some(&[0x08, 0x00]) => data.get(26..34),
+        // The source and destination addresses of IPv6.
+        Some(&[0x86, 0xdd]) => data.get(22..54),
+        _ => None,
+    };
+    let hash = addrs
+        .unwrap_or_default()
+        .iter()
+        .fold(FNV_OFFSET_BASIS, |hash, &b| {
+            (hash ^ u32::from(b)).wrapping_mul(FNV_PRIME)
+        });
+    hash & FLOW_ID_MASK
+}
+
+/// The generation of the running pipeline, which changes each time a pipeline starts or stops.
+pub(crate) fn generation() -> u64 {
+    GENERATION.load(Ordering::Acquire)
+}
+
+/// Take an event port of the running pipeline for an `RxAgent` to inject the packets it
+/// receives, or `None` if there's no pipeline or all ports are taken.
+pub(crate) fn ingress() -> Option<Ingress> {
+    let shared = PIPELINE.read().ok()?.as_ref().map(Arc::clone)?;
+    let port = shared.rx_ports.lock().ok()?.pop();
+    let Some(port) = port else {
+        warn!("No event port left for an RxAgent, which processes packets by itself");
+        return None;
+    };
+    Some(Ingress {
+        shared,
+        port: Some(port),
+        events: Vec::new(),
+    })
+}
+
+/// An event port taken by an `RxAgent` to inject the packets it receives into the pipeline,
+/// which is given back when dropped.
+#[derive(Debug)]
+pub(crate) struct Ingress {
+    /// The pipeline.
+    shared: Arc<Shared>,
+    /// The port, which is only taken on drop.
+    port: Option<EventPort>,
+    /// Packets to be injected.
+    events: Vec<Event>,
+}
+
+impl Ingress {
+    /// Buffer a packet to be injected by `flush`.
+    pub(crate) fn push(&mut self, m: Mbuf) {
+        let flow_id = flow_id(&m);
+        self.events
+            .push(Event::new(m, 0).flow_id(flow_id).sched(self.shared.sched));
+    }
+
+    /// Inject the buffered packets, dropping those refused by the device.
+    pub(crate) fn flush(&mut self) {
+        if self.events.is_empty() {
+            return;
+        }
+        let Some(ref mut port) = self.port else {
+            return;
+        };
+        let n = self.events.len();
+        let mut raw: Vec<rte_event> = self
+            .events
+            .drain(..)
+            .map(|event| event.into_raw(RTE_EVENT_TYPE_ETHDEV))
+            .collect();
+        let injected = port.enqueue_raw(&mut raw);
+        // Packets of refused events are freed.
+        for ev in raw {
+            drop(Event::from_raw(ev));
+        }
+        let _injected = self
+            .shared
+            .injected
+            .fetch_add(injected as u64, Ordering::Relaxed);
+        let rejected = n.saturating_sub(injected);
+        if rejected > 0 {
+            let _rejected = self
+                .shared
+                .rejected
+                .fetch_add(rejected as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Ingress {
+    fn drop(&mut self) {
+        self.flush();
+        if let Some(port) = self.port.take() {
+            self.shared
+                .rx_ports
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(port);
+            self.shared.returned.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{flow_id, rte_event, Event, SchedType, RTE_EVENT_TYPE_CPU};
+    use crate::{
+        mbuf::Mbuf,
+        mempool::{Mempool, PktMempool},
+        test_utils,
+    };
+
+    #[test]
+    fn test_event() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("eventdev_test_event", 16).unwrap();
+        let event = Event::new(Mbuf::new(&mp).unwrap(), 3)
+            .flow_id(0x12_3456)
+            .sched(SchedType::Parallel)
+            .priority(7);
+        assert_eq!(event.flow(), 0x2_3456);
+        let raw = event.into_raw(RTE_EVENT_TYPE_CPU);
+        assert_eq!(
+            raw.field(rte_event::EVENT_TYPE_SHIFT, 4),
+            RTE_EVENT_TYPE_CPU
+        );
+        let event = Event::from_raw(raw).unwrap();
+        assert_eq!(event.queue(), 3);
+        assert_eq!(event.flow(), 0x2_3456);
+        assert_eq!(event.sched, SchedType::Parallel);
+        assert_eq!(event.priority, 7);
+        assert!(!event.forwarded);
+        let event = Event::from_raw(event.forward(1).into_raw(RTE_EVENT_TYPE_CPU)).unwrap();
+        assert_eq!(event.queue(), 1);
+        assert!(event.forwarded);
+        drop(event);
+        assert_eq!(mp.in_use(), 0);
+    }
+
+    #[test]
+    fn test_flow_id() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("eventdev_test_flow", 16).unwrap();
+        let ipv4 = |src: u8, dst: u8, port: u8| {
+            let mut m = Mbuf::new(&mp).unwrap();
+            let data = m.append(38).unwrap();
+            data.fill(0);
+            data.get_mut(12..14).unwrap().copy_from_slice(&[0x08, 0x00]);
+            *data.get_mut(29).unwrap() = src;
+            *data.get_mut(33).unwrap() = dst;
+            *data.get_mut(35).unwrap() = port;
+            m
+        };
+        // Packets between the same addresses are in the same flow, including fragments without
+        // the ports.
+        assert_eq!(flow_id(&ipv4(1, 2, 80)), flow_id(&ipv4(1, 2, 0)));
+        assert_ne!(flow_id(&ipv4(1, 2, 80)), flow_id(&ipv4(1, 3, 80)));
+        assert!(flow_id(&ipv4(1, 2, 80)) <= 0xf_ffff);
+    }
+}
@@ -0,0 +1,180 @@
+//! Exception path to the kernel stack.
+//!
+//! A virtio-user port backed by vhost-net shows up as a TAP interface in the kernel. Once it's
+//! attached to a device, frames that the stack doesn't handle on that device, e.g. ICMP or
+//! unknown protocols, are forwarded to the kernel through it, and frames sent by the kernel are
+//! put onto the wire of the device. ARP frames are handled by the stack and also forwarded, so
+//! that the kernel keeps its own neighbor table.
+//!
+//! ```no_run
+//! use async_dpdk::exception;
+//! use std::net::IpAddr;
+//!
+//! let addr = IpAddr::from([192, 168, 0, 1]);
+//! exception::attach("virtio_user0,path=/dev/vhost-net,iface=tap0", &addr).unwrap();
+//! // ...
+//! exception::detach(&addr).unwrap();
+//! ```
+
+use crate::{
+    eth_dev::{DevConfig, EthDev, RssConfig, TxSender},
+    mbuf::Mbuf,
+    net_dev,
+    trace::{debug, error, trace},
+    Error, Result,
+};
+use lazy_static::lazy_static;
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock,
+    },
+};
+
+lazy_static! {
+    /// Attached exception paths.
+    static ref EXCEPTION_PATHS: RwLock<Vec<ExceptionPath>> = RwLock::new(Vec::new());
+}
+
+/// The number of attached exception paths, checked by the agent thread before looking up
+/// `EXCEPTION_PATHS`.
+static EXCEPTION_PATH_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// An exception path between a device and the kernel.
+#[derive(Debug)]
+struct ExceptionPath {
+    /// Port id of the device whose unhandled frames are forwarded.
+    wire_port: u16,
+    /// The virtio-user port connected to the kernel.
+    kernel: EthDev,
+    /// Sending frames to the kernel.
+    to_kernel: TxSender,
+    /// Sending frames from the kernel onto the wire.
+    to_wire: TxSender,
+}
+
+/// Attach an exception path to the running device bound to `addr`.
+///
+/// `devargs` identifies the virtio-user port along with its arguments, e.g.
+/// `virtio_user0,path=/dev/vhost-net,iface=tap0`. The TAP interface should be configured in the
+/// kernel with the same Ether and IP addresses as the device.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no running device is bound to `addr`.
+/// - `Error::Exists`: an exception path is already attached to the device.
+/// - Unable to probe or start the virtio-user port.
+#[inline]
+pub fn attach(devargs: &str, addr: &IpAddr) -> Result<()> {
+    let (wire_port, to_wire) = net_dev::find_port_by_ip(*addr)?;
+    if EXCEPTION_PATHS
+        .read()
+        .map_err(Error::from)?
+        .iter()
+        .any(|path| path.wire_port == wire_port)
+    {
+        return Err(Error::Exists);
+    }
+    let kernel_port = net_dev::probe_port(devargs)?;
+    let mut kernel = EthDev::with_agents(
+        kernel_port,
+        1,
+        1,
+        RssConfig::default(),
+        &DevConfig::default(),
+        None,
+        &BTreeMap::new(),
+        None,
+    )?;
+    // The agents of the kernel port look up `EXCEPTION_PATHS`, so it's started without holding
+    // the lock.
+    kernel.start()?;
+    let to_kernel = kernel.sender(0).ok_or(Error::NotStart)?;
+    EXCEPTION_PATHS
+        .write()
+        .map_err(Error::from)?
+        .push(ExceptionPath {
+            wire_port,
+            kernel,
+            to_kernel,
+            to_wire,
+        });
+    let _prev = EXCEPTION_PATH_NUM.fetch_add(1, Ordering::AcqRel);
+    debug!("Exception path {kernel_port} attached to port {wire_port}");
+    Ok(())
+}
+
+/// Detach the exception path from the device bound to `addr`, and remove the virtio-user port.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotExist`: no exception path is attached to the device.
+/// - Unable to stop or remove the virtio-user port.
+#[inline]
+pub fn detach(addr: &IpAddr) -> Result<()> {
+    let wire_port = net_dev::port_id(addr)?;
+    let mut path = {
+        let mut paths = EXCEPTION_PATHS.write().map_err(Error::from)?;
+        let idx = paths
+            .iter()
+            .position(|path| path.wire_port == wire_port)
+            .ok_or(Error::NotExist)?;
+        let _prev = EXCEPTION_PATH_NUM.fetch_sub(1, Ordering::AcqRel);
+        paths.remove(idx)
+    };
+    // The agents of the kernel port look up `EXCEPTION_PATHS`, so it's stopped after the lock
+    // is released.
+    path.kernel.stop()?;
+    let kernel_port = path.kernel.port_id();
+    net_dev::remove_port(path.kernel)?;
+    debug!("Exception path {kernel_port} detached from port {wire_port}");
+    Ok(())
+}
+
+/// Called by the agent thread, forward a frame not handled by the stack to the kernel.
+///
+/// The frame is dropped if no exception path is attached to the device it's received from.
+pub(crate) fn to_kernel(m: Mbuf) {
+    if EXCEPTION_PATH_NUM.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let port_id = m.port();
+    if let Ok(paths) = EXCEPTION_PATHS.read() {
+        if let Some(path) = paths.iter().find(|path| path.wire_port == port_id) {
+            trace!("Forward a frame from port {port_id} to the kernel");
+            if let Err(e) = path.to_kernel.try_send_mbuf(m) {
+                error!("Failed to forward a frame to the kernel: {e:?}");
+            }
+        }
+    }
+}
+
+/// Called by the agent thread, put a frame sent by the kernel onto the wire.
+///
+/// The frame is given back if it's not received from a kernel port.
+pub(crate) fn from_kernel(m: Mbuf) -> Option<Mbuf> {
+    if EXCEPTION_PATH_NUM.load(Ordering::Acquire) == 0 {
+        return Some(m);
+    }
+    let port_id = m.port();
+    let Ok(paths) = EXCEPTION_PATHS.read() else {
+        return Some(m);
+    };
+    let Some(path) = paths.iter().find(|path| path.kernel.port_id() == port_id) else {
+        return Some(m);
+    };
+    trace!("Forward a frame from the kernel to port {}", path.wire_port);
+    if let Err(e) = path.to_wire.try_send_mbuf(m) {
+        error!("Failed to forward a frame from the kernel: {e:?}");
+    }
+    None
+}
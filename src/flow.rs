@@ -0,0 +1,695 @@
+//! Flow rules steer packets matching specific patterns to specific queues, or drop / mark them
+//! in hardware. This module provides safe wrappings for the generic flow API of DPDK. For more
+//! information, please refer to [`rte_flow document`].
+//!
+//! ```no_run
+//! use async_dpdk::flow::{Action, FlowRule, Pattern};
+//! use async_dpdk::net_dev;
+//! use std::net::{IpAddr, Ipv4Addr};
+//!
+//! let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1));
+//! // Deliver UDP datagrams sent to port 1234 to rx queue 1.
+//! let rule = FlowRule::new()
+//!     .pattern(Pattern::eth())
+//!     .pattern(Pattern::ipv4())
+//!     .pattern(Pattern::udp(None, Some(1234)))
+//!     .action(Action::Queue(1));
+//! let id = net_dev::flow_create(&addr, &rule).unwrap();
+//! net_dev::flow_destroy(&addr, id).unwrap();
+//! ```
+//!
+//! [`rte_flow document`]: https://doc.dpdk.org/guides/prog_guide/rte_flow.html
+
+#![allow(non_camel_case_types)]
+
+use crate::{eth_dev::RssConfig, trace::error, Error, Result};
+use dpdk_sys::{rte_ether_addr, rte_ipv4_hdr, rte_tcp_hdr, rte_udp_hdr};
+use std::{
+    ffi::CStr,
+    mem::MaybeUninit,
+    net::Ipv4Addr,
+    os::raw::{c_char, c_int, c_void},
+    ptr::{self, NonNull},
+};
+
+/// Item type matching nothing but indicating the end of a pattern.
+const RTE_FLOW_ITEM_TYPE_END: c_int = 0;
+/// Item type matching an Ethernet header.
+const RTE_FLOW_ITEM_TYPE_ETH: c_int = 9;
+/// Item type matching an IPv4 header.
+const RTE_FLOW_ITEM_TYPE_IPV4: c_int = 11;
+/// Item type matching a UDP header.
+const RTE_FLOW_ITEM_TYPE_UDP: c_int = 14;
+/// Item type matching a TCP header.
+const RTE_FLOW_ITEM_TYPE_TCP: c_int = 15;
+
+/// Action type indicating the end of an action list.
+const RTE_FLOW_ACTION_TYPE_END: c_int = 0;
+/// Action type attaching an integer value to packets.
+const RTE_FLOW_ACTION_TYPE_MARK: c_int = 4;
+/// Action type assigning packets to a given queue.
+const RTE_FLOW_ACTION_TYPE_QUEUE: c_int = 6;
+/// Action type dropping packets.
+const RTE_FLOW_ACTION_TYPE_DROP: c_int = 7;
+/// Action type spreading packets over several queues with RSS.
+const RTE_FLOW_ACTION_TYPE_RSS: c_int = 9;
+
+/// Flow rule attributes.
+#[repr(C)]
+struct rte_flow_attr {
+    /// Priority group.
+    group: u32,
+    /// Rule priority level within group.
+    priority: u32,
+    /// Bit field of `ingress:1`, `egress:1`, `transfer:1` and `reserved:29`.
+    bits: u32,
+}
+
+/// Matching pattern item.
+#[repr(C)]
+struct rte_flow_item {
+    /// Item type.
+    type_: c_int,
+    /// Pointer to item specification structure.
+    spec: *const c_void,
+    /// Defines an inclusive range (spec to last).
+    last: *const c_void,
+    /// Bit-mask applied to spec and last.
+    mask: *const c_void,
+}
+
+/// Definition of a single action.
+#[repr(C)]
+struct rte_flow_action {
+    /// Action type.
+    type_: c_int,
+    /// Pointer to action configuration object.
+    conf: *const c_void,
+}
+
+/// Verbose error structure definition.
+#[repr(C)]
+struct rte_flow_error {
+    /// Cause field and error types.
+    type_: c_int,
+    /// Object responsible for the error.
+    cause: *const c_void,
+    /// Human-readable error message.
+    message: *const c_char,
+}
+
+/// Specification of an Ethernet item.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct rte_flow_item_eth {
+    /// Destination MAC.
+    dst: rte_ether_addr,
+    /// Source MAC.
+    src: rte_ether_addr,
+    /// Ether type or TPID.
+    type_: u16,
+    /// Bit field of `has_vlan:1` and `reserved:31`.
+    bits: u32,
+}
+
+/// Specification of an IPv4 item.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct rte_flow_item_ipv4 {
+    /// IPv4 header definition.
+    hdr: rte_ipv4_hdr,
+}
+
+/// Specification of a UDP item.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct rte_flow_item_udp {
+    /// UDP header definition.
+    hdr: rte_udp_hdr,
+}
+
+/// Specification of a TCP item.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct rte_flow_item_tcp {
+    /// TCP header definition.
+    hdr: rte_tcp_hdr,
+}
+
+/// Configuration of the QUEUE action.
+#[repr(C)]
+struct rte_flow_action_queue {
+    /// Queue index to use.
+    index: u16,
+}
+
+/// Configuration of the MARK action.
+#[repr(C)]
+struct rte_flow_action_mark {
+    /// Integer value to return with packets.
+    id: u32,
+}
+
+/// Configuration of the RSS action.
+#[repr(C)]
+struct rte_flow_action_rss {
+    /// RSS hash function to apply, 0 for the default one.
+    func: c_int,
+    /// Packet encapsulation level RSS hash types apply to.
+    level: u32,
+    /// Specific RSS hash types.
+    types: u64,
+    /// Hash key length in bytes.
+    key_len: u32,
+    /// Number of entries in `queue`.
+    queue_num: u32,
+    /// Hash key.
+    key: *const u8,
+    /// Queue indices to use.
+    queue: *const u16,
+}
+
+/// Opaque type returned after successfully creating a flow.
+#[repr(C)]
+struct rte_flow {
+    /// Zero-sized private field.
+    _private: [u8; 0],
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Check whether a flow rule can be created on a given port.
+    fn rte_flow_validate(
+        port_id: u16,
+        attr: *const rte_flow_attr,
+        pattern: *const rte_flow_item,
+        actions: *const rte_flow_action,
+        error: *mut rte_flow_error,
+    ) -> c_int;
+
+    /// Create a flow rule on a given port.
+    fn rte_flow_create(
+        port_id: u16,
+        attr: *const rte_flow_attr,
+        pattern: *const rte_flow_item,
+        actions: *const rte_flow_action,
+        error: *mut rte_flow_error,
+    ) -> *mut rte_flow;
+
+    /// Destroy a flow rule on a given port.
+    fn rte_flow_destroy(port_id: u16, flow: *mut rte_flow, error: *mut rte_flow_error) -> c_int;
+}
+
+/// A pattern item matching a protocol header. Fields set to `None` match any value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Pattern {
+    /// Match an Ethernet header.
+    Eth {
+        /// Source MAC address.
+        src: Option<[u8; 6]>,
+        /// Destination MAC address.
+        dst: Option<[u8; 6]>,
+        /// Ether type.
+        ether_type: Option<u16>,
+    },
+    /// Match an IPv4 header.
+    Ipv4 {
+        /// Source address.
+        src: Option<Ipv4Addr>,
+        /// Destination address.
+        dst: Option<Ipv4Addr>,
+        /// Next protocol ID.
+        proto: Option<u8>,
+    },
+    /// Match a UDP header.
+    Udp {
+        /// Source port.
+        src_port: Option<u16>,
+        /// Destination port.
+        dst_port: Option<u16>,
+    },
+    /// Match a TCP header.
+    Tcp {
+        /// Source port.
+        src_port: Option<u16>,
+        /// Destination port.
+        dst_port: Option<u16>,
+    },
+}
+
+impl Pattern {
+    /// Match any Ethernet header.
+    #[inline]
+    #[must_use]
+    pub fn eth() -> Self {
+        Pattern::Eth {
+            src: None,
+            dst: None,
+            ether_type: None,
+        }
+    }
+
+    /// Match any IPv4 header.
+    #[inline]
+    #[must_use]
+    pub fn ipv4() -> Self {
+        Pattern::Ipv4 {
+            src: None,
+            dst: None,
+            proto: None,
+        }
+    }
+
+    /// Match a UDP header with given ports.
+    #[inline]
+    #[must_use]
+    pub fn udp(src_port: Option<u16>, dst_port: Option<u16>) -> Self {
+        Pattern::Udp { src_port, dst_port }
+    }
+
+    /// Match a TCP header with given ports.
+    #[inline]
+    #[must_use]
+    pub fn tcp(src_port: Option<u16>, dst_port: Option<u16>) -> Self {
+        Pattern::Tcp { src_port, dst_port }
+    }
+}
+
+/// An action applied to packets matching the pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Action {
+    /// Deliver packets to the given rx queue.
+    Queue(u16),
+    /// Drop packets.
+    Drop,
+    /// Spread packets over the given rx queues with RSS.
+    Rss(Vec<u16>),
+    /// Attach the value to packets, which can be read with `Mbuf::flow_mark`.
+    Mark(u32),
+}
+
+/// A flow rule, made up of a matching pattern and a list of actions, applied on ingress traffic.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowRule {
+    /// Priority group.
+    group: u32,
+    /// Priority level within group, 0 for the highest.
+    priority: u32,
+    /// Pattern items, from the outermost header to the innermost.
+    patterns: Vec<Pattern>,
+    /// Actions.
+    actions: Vec<Action>,
+}
+
+impl FlowRule {
+    /// Create an empty `FlowRule`.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the priority group.
+    #[inline]
+    #[must_use]
+    pub fn group(mut self, group: u32) -> Self {
+        self.group = group;
+        self
+    }
+
+    /// Set the priority level within group. Lower values denote higher priority.
+    #[inline]
+    #[must_use]
+    pub fn priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Append a pattern item.
+    #[inline]
+    #[must_use]
+    pub fn pattern(mut self, pattern: Pattern) -> Self {
+        self.patterns.push(pattern);
+        self
+    }
+
+    /// Append an action.
+    #[inline]
+    #[must_use]
+    pub fn action(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    /// Convert the rule into raw structures and call `f` on them.
+    fn with_raw<T>(
+        &self,
+        f: impl FnOnce(&rte_flow_attr, &[rte_flow_item], &[rte_flow_action]) -> T,
+    ) -> T {
+        let attr = rte_flow_attr {
+            group: self.group,
+            priority: self.priority,
+            bits: 1, // ingress
+        };
+        // Specs and masks are referred to by `items`, so they're built beforehand.
+        let specs = self.patterns.iter().map(ItemSpec::from).collect::<Vec<_>>();
+        let mut items = specs.iter().map(ItemSpec::item).collect::<Vec<_>>();
+        items.push(rte_flow_item {
+            type_: RTE_FLOW_ITEM_TYPE_END,
+            spec: ptr::null(),
+            last: ptr::null(),
+            mask: ptr::null(),
+        });
+
+        let confs = self
+            .actions
+            .iter()
+            .map(ActionConf::from)
+            .collect::<Vec<_>>();
+        let mut actions = confs.iter().map(ActionConf::action).collect::<Vec<_>>();
+        actions.push(rte_flow_action {
+            type_: RTE_FLOW_ACTION_TYPE_END,
+            conf: ptr::null(),
+        });
+        f(&attr, &items, &actions)
+    }
+}
+
+/// Specification and mask of a pattern item.
+enum ItemSpec {
+    /// Ethernet item.
+    Eth(rte_flow_item_eth, rte_flow_item_eth),
+    /// IPv4 item.
+    Ipv4(rte_flow_item_ipv4, rte_flow_item_ipv4),
+    /// UDP item.
+    Udp(rte_flow_item_udp, rte_flow_item_udp),
+    /// TCP item.
+    Tcp(rte_flow_item_tcp, rte_flow_item_tcp),
+}
+
+/// Zero-initialize a spec structure.
+#[allow(unsafe_code)]
+fn zeroed<T: Copy>() -> T {
+    // SAFETY: spec structures are plain old data, which are valid if set to zero
+    unsafe { MaybeUninit::<T>::zeroed().assume_init() }
+}
+
+impl From<&Pattern> for ItemSpec {
+    #[inline]
+    fn from(pattern: &Pattern) -> Self {
+        match *pattern {
+            Pattern::Eth {
+                src,
+                dst,
+                ether_type,
+            } => {
+                let mut spec = zeroed::<rte_flow_item_eth>();
+                let mut mask = spec;
+                if let Some(src) = src {
+                    spec.src.addr_bytes = src;
+                    mask.src.addr_bytes = [0xff; 6];
+                }
+                if let Some(dst) = dst {
+                    spec.dst.addr_bytes = dst;
+                    mask.dst.addr_bytes = [0xff; 6];
+                }
+                if let Some(ether_type) = ether_type {
+                    spec.type_ = ether_type.to_be();
+                    mask.type_ = u16::MAX;
+                }
+                ItemSpec::Eth(spec, mask)
+            }
+            Pattern::Ipv4 { src, dst, proto } => {
+                let mut spec = zeroed::<rte_flow_item_ipv4>();
+                let mut mask = spec;
+                if let Some(src) = src {
+                    spec.hdr.src_addr = u32::from(src).to_be();
+                    mask.hdr.src_addr = u32::MAX;
+                }
+                if let Some(dst) = dst {
+                    spec.hdr.dst_addr = u32::from(dst).to_be();
+                    mask.hdr.dst_addr = u32::MAX;
+                }
+                if let Some(proto) = proto {
+                    spec.hdr.next_proto_id = proto;
+                    mask.hdr.next_proto_id = u8::MAX;
+                }
+                ItemSpec::Ipv4(spec, mask)
+            }
+            Pattern::Udp { src_port, dst_port } => {
+                let mut spec = zeroed::<rte_flow_item_udp>();
+                let mut mask = spec;
+                if let Some(port) = src_port {
+                    spec.hdr.src_port = port.to_be();
+                    mask.hdr.src_port = u16::MAX;
+                }
+                if let Some(port) = dst_port {
+                    spec.hdr.dst_port = port.to_be();
+                    mask.hdr.dst_port = u16::MAX;
+                }
+                ItemSpec::Udp(spec, mask)
+            }
+            Pattern::Tcp { src_port, dst_port } => {
+                let mut spec = zeroed::<rte_flow_item_tcp>();
+                let mut mask = spec;
+                if let Some(port) = src_port {
+                    spec.hdr.src_port = port.to_be();
+                    mask.hdr.src_port = u16::MAX;
+                }
+                if let Some(port) = dst_port {
+                    spec.hdr.dst_port = port.to_be();
+                    mask.hdr.dst_port = u16::MAX;
+                }
+                ItemSpec::Tcp(spec, mask)
+            }
+        }
+    }
+}
+
+impl ItemSpec {
+    /// Generate an `rte_flow_item` referring to `self`.
+    fn item(&self) -> rte_flow_item {
+        /// Cast a reference to a `*const c_void`.
+        fn void<T>(r: &T) -> *const c_void {
+            ptr::addr_of!(*r).cast()
+        }
+        let (type_, spec, mask) = match *self {
+            ItemSpec::Eth(ref spec, ref mask) => (RTE_FLOW_ITEM_TYPE_ETH, void(spec), void(mask)),
+            ItemSpec::Ipv4(ref spec, ref mask) => (RTE_FLOW_ITEM_TYPE_IPV4, void(spec), void(mask)),
+            ItemSpec::Udp(ref spec, ref mask) => (RTE_FLOW_ITEM_TYPE_UDP, void(spec), void(mask)),
+            ItemSpec::Tcp(ref spec, ref mask) => (RTE_FLOW_ITEM_TYPE_TCP, void(spec), void(mask)),
+        };
+        rte_flow_item {
+            type_,
+            spec,
+            last: ptr::null(),
+            mask,
+        }
+    }
+}
+
+/// Configuration of an action.
+enum ActionConf {
+    /// QUEUE action.
+    Queue(rte_flow_action_queue),
+    /// DROP action.
+    Drop,
+    /// RSS action.
+    Rss(Box<rte_flow_action_rss>),
+    /// MARK action.
+    Mark(rte_flow_action_mark),
+}
+
+impl From<&Action> for ActionConf {
+    #[inline]
+    fn from(action: &Action) -> Self {
+        match *action {
+            Action::Queue(index) => ActionConf::Queue(rte_flow_action_queue { index }),
+            Action::Drop => ActionConf::Drop,
+            Action::Rss(ref queues) => ActionConf::Rss(Box::new(rte_flow_action_rss {
+                func: 0,
+                level: 0,
+                types: RssConfig::IP | RssConfig::UDP | RssConfig::TCP,
+                key_len: 0,
+                #[allow(clippy::cast_possible_truncation)] // queues are indexed by u16
+                queue_num: queues.len() as u32,
+                key: ptr::null(),
+                queue: queues.as_ptr(),
+            })),
+            Action::Mark(id) => ActionConf::Mark(rte_flow_action_mark { id }),
+        }
+    }
+}
+
+impl ActionConf {
+    /// Generate an `rte_flow_action` referring to `self`.
+    fn action(&self) -> rte_flow_action {
+        let (type_, conf) = match *self {
+            ActionConf::Queue(ref conf) => {
+                (RTE_FLOW_ACTION_TYPE_QUEUE, ptr::addr_of!(*conf).cast())
+            }
+            ActionConf::Drop => (RTE_FLOW_ACTION_TYPE_DROP, ptr::null()),
+            ActionConf::Rss(ref conf) => (RTE_FLOW_ACTION_TYPE_RSS, ptr::addr_of!(*conf).cast()),
+            ActionConf::Mark(ref conf) => (RTE_FLOW_ACTION_TYPE_MARK, ptr::addr_of!(*conf).cast()),
+        };
+        rte_flow_action { type_, conf }
+    }
+}
+
+/// Log the message carried by `rte_flow_error`, if any.
+#[allow(unsafe_code)]
+fn log_error(err: &rte_flow_error) {
+    if !err.message.is_null() {
+        // SAFETY: `message` is a C string set by the driver
+        let msg = unsafe { CStr::from_ptr(err.message) };
+        error!("Flow error: {}", msg.to_string_lossy());
+    }
+}
+
+/// Identifier of a flow rule created on a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowId(pub(crate) u64);
+
+/// A flow rule created on a device, which is destroyed on drop.
+pub(crate) struct Flow {
+    /// The device that the flow is created on.
+    port_id: u16,
+    /// Pointer to the opaque `rte_flow`.
+    ptr: NonNull<rte_flow>,
+}
+
+#[allow(unsafe_code)]
+impl Flow {
+    /// Check whether `rule` is valid and can be created on the device.
+    pub(crate) fn validate(port_id: u16, rule: &FlowRule) -> Result<()> {
+        let mut err = zeroed_error();
+        let errno = rule.with_raw(|attr, items, actions| {
+            // SAFETY: all pointers are valid during the call
+            unsafe {
+                rte_flow_validate(
+                    port_id,
+                    attr,
+                    items.as_ptr(),
+                    actions.as_ptr(),
+                    ptr::addr_of_mut!(err),
+                )
+            }
+        });
+        if errno < 0 {
+            log_error(&err);
+        }
+        Error::from_ret(errno, "rte_flow_validate")
+    }
+
+    /// Create a flow rule on the device.
+    pub(crate) fn create(port_id: u16, rule: &FlowRule) -> Result<Self> {
+        let mut err = zeroed_error();
+        let ptr = rule.with_raw(|attr, items, actions| {
+            // SAFETY: all pointers are valid during the call
+            unsafe {
+                rte_flow_create(
+                    port_id,
+                    attr,
+                    items.as_ptr(),
+                    actions.as_ptr(),
+                    ptr::addr_of_mut!(err),
+                )
+            }
+        });
+        NonNull::new(ptr)
+            .map(|ptr| Self { port_id, ptr })
+            .ok_or_else(|| {
+                log_error(&err);
+                Error::from_errno("rte_flow_create")
+            })
+    }
+}
+
+/// Create an empty `rte_flow_error`.
+fn zeroed_error() -> rte_flow_error {
+    rte_flow_error {
+        type_: 0,
+        cause: ptr::null(),
+        message: ptr::null(),
+    }
+}
+
+impl Drop for Flow {
+    #[inline]
+    fn drop(&mut self) {
+        let mut err = zeroed_error();
+        // SAFETY: `ptr` is created on `port_id` and destroyed only once
+        #[allow(unsafe_code)]
+        let errno =
+            unsafe { rte_flow_destroy(self.port_id, self.ptr.as_ptr(), ptr::addr_of_mut!(err)) };
+        if errno < 0 {
+            log_error(&err);
+        }
+    }
+}
+
+// SAFETY: `rte_flow` handles can be destroyed in any thread.
+#[allow(unsafe_code)]
+unsafe impl Send for Flow {}
+
+// SAFETY: `Flow` exposes no method on `&self`.
+#[allow(unsafe_code)]
+unsafe impl Sync for Flow {}
+
+impl std::fmt::Debug for Flow {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Flow")
+            .field("port_id", &self.port_id)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw() {
+        let rule = FlowRule::new()
+            .pattern(Pattern::eth())
+            .pattern(Pattern::ipv4())
+            .pattern(Pattern::udp(None, Some(1234)))
+            .action(Action::Mark(1))
+            .action(Action::Queue(1));
+        rule.with_raw(|attr, items, actions| {
+            assert_eq!(attr.bits, 1);
+            let types = items.iter().map(|item| item.type_).collect::<Vec<_>>();
+            assert_eq!(
+                types,
+                [
+                    RTE_FLOW_ITEM_TYPE_ETH,
+                    RTE_FLOW_ITEM_TYPE_IPV4,
+                    RTE_FLOW_ITEM_TYPE_UDP,
+                    RTE_FLOW_ITEM_TYPE_END
+                ]
+            );
+            let types = actions
+                .iter()
+                .map(|action| action.type_)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                types,
+                [
+                    RTE_FLOW_ACTION_TYPE_MARK,
+                    RTE_FLOW_ACTION_TYPE_QUEUE,
+                    RTE_FLOW_ACTION_TYPE_END
+                ]
+            );
+        });
+
+        let ItemSpec::Udp(spec, mask) = ItemSpec::from(&Pattern::udp(None, Some(1234))) else {
+            panic!("not a UDP item");
+        };
+        assert_eq!({ spec.hdr.dst_port }, 1234_u16.to_be());
+        assert_eq!({ mask.hdr.dst_port }, u16::MAX);
+        assert_eq!({ mask.hdr.src_port }, 0);
+    }
+}
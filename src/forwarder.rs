@@ -0,0 +1,260 @@
+//! Port-to-port packet forwarding.
+//!
+//! A `Forwarder` polls the rx queues taken from `EthDev`s, passes each frame to a hook, and
+//! sends the frame on the tx queue of the port chosen by the hook, which covers the classic
+//! l2fwd and l3fwd applications. The hook may rewrite the frame in place, e.g. its MAC addresses.
+//!
+//! Frames are received and sent in bursts. Frames not accepted by a tx queue are held and sent
+//! first in the next poll, and dropped once too many are held, so a slow port doesn't exhaust the
+//! mempool.
+//!
+//! ```no_run
+//! use async_dpdk::{
+//!     eth_dev::EthDev,
+//!     forwarder::{ForwardDecision, Forwarder},
+//!     net_dev::DevConfig,
+//! };
+//! use std::sync::atomic::AtomicBool;
+//!
+//! let mut dev0 = EthDev::new(0, 1, 1, &DevConfig::new()).unwrap();
+//! let mut dev1 = EthDev::new(1, 1, 1, &DevConfig::new()).unwrap();
+//! dev0.start().unwrap();
+//! dev1.start().unwrap();
+//! let stop = AtomicBool::new(false);
+//! Forwarder::new(|m| match m.port() {
+//!     0 => ForwardDecision::Forward(1),
+//!     1 => ForwardDecision::Forward(0),
+//!     _ => ForwardDecision::Drop,
+//! })
+//! .rx(dev0.rx_queue(0).unwrap())
+//! .rx(dev1.rx_queue(0).unwrap())
+//! .tx(dev0.tx_queue(0).unwrap())
+//! .tx(dev1.tx_queue(0).unwrap())
+//! .run(&stop);
+//! ```
+
+use crate::{
+    eth_dev::{RxQueue, TxQueue},
+    mbuf::Mbuf,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug},
+    hint,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Number of frames received from an rx queue at a time by default.
+const DEFAULT_BURST: usize = 32;
+
+/// Number of frames held for a tx queue at most, beyond which frames are dropped.
+const PENDING_CAPACITY: usize = 1024;
+
+/// What to do with a frame, decided by the hook of a `Forwarder`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardDecision {
+    /// Send the frame on the tx queue added for the port. It's dropped if there's no such queue.
+    Forward(u16),
+    /// Drop the frame.
+    Drop,
+    /// The frame is handled by the hook, e.g. answered or copied elsewhere, and is freed without
+    /// counted as dropped.
+    Consume,
+}
+
+/// Statistics of a `Forwarder`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ForwardStats {
+    /// Number of frames received from the rx queues.
+    pub rx_packets: u64,
+    /// Number of frames sent on the tx queues.
+    pub tx_packets: u64,
+    /// Number of frames dropped, by the hook, for lack of the tx queue or since too many frames
+    /// are held for it.
+    pub dropped: u64,
+    /// Number of frames consumed by the hook.
+    pub consumed: u64,
+}
+
+/// A tx queue along with the frames held for it.
+#[derive(Debug)]
+struct TxPort<'a> {
+    /// The queue sent to.
+    queue: TxQueue<'a>,
+    /// Frames to be sent, in order.
+    pending: Vec<Mbuf>,
+}
+
+/// Forwards frames between the queues of `EthDev`s, whose handles are held until it's dropped.
+pub struct Forwarder<'a, F> {
+    /// The rx queues polled in turn.
+    rx: Vec<RxQueue<'a>>,
+    /// The tx queues, keyed by `port_id`.
+    tx: BTreeMap<u16, TxPort<'a>>,
+    /// The hook deciding what to do with each frame.
+    hook: F,
+    /// Number of frames received from an rx queue at a time.
+    burst: usize,
+    /// Frames received in a poll.
+    received: Vec<Mbuf>,
+    /// Statistics.
+    stats: ForwardStats,
+}
+
+impl<'a, F> Forwarder<'a, F>
+where
+    F: FnMut(&mut Mbuf) -> ForwardDecision,
+{
+    /// Create a `Forwarder` deciding what to do with each frame by `hook`.
+    #[inline]
+    #[must_use]
+    pub fn new(hook: F) -> Self {
+        Self {
+            rx: Vec::new(),
+            tx: BTreeMap::new(),
+            hook,
+            burst: DEFAULT_BURST,
+            received: Vec::with_capacity(DEFAULT_BURST),
+            stats: ForwardStats::default(),
+        }
+    }
+
+    /// Poll rx queue `queue`.
+    #[inline]
+    #[must_use]
+    pub fn rx(mut self, queue: RxQueue<'a>) -> Self {
+        self.rx.push(queue);
+        self
+    }
+
+    /// Send frames forwarded to the port of `queue` on it, replacing the queue added for the
+    /// port before.
+    #[inline]
+    #[must_use]
+    pub fn tx(mut self, queue: TxQueue<'a>) -> Self {
+        let port = TxPort {
+            queue,
+            pending: Vec::new(),
+        };
+        let _prev = self.tx.insert(port.queue.port_id(), port);
+        self
+    }
+
+    /// Set the number of frames received from an rx queue at a time, 32 by default. A burst of 0
+    /// counts as 1.
+    #[inline]
+    #[must_use]
+    pub fn burst(mut self, burst: usize) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Statistics of the forwarding so far.
+    #[inline]
+    #[must_use]
+    pub fn stats(&self) -> ForwardStats {
+        self.stats
+    }
+
+    /// Poll each rx queue once, and send the frames to be forwarded. Returns the number of frames
+    /// received.
+    #[inline]
+    pub fn poll(&mut self) -> usize {
+        let mut received = 0_usize;
+        for rxq in &mut self.rx {
+            let n = rxq.recv(&mut self.received, self.burst);
+            received = received.wrapping_add(n);
+            for mut m in self.received.drain(..) {
+                self.stats.rx_packets = self.stats.rx_packets.wrapping_add(1);
+                match (self.hook)(&mut m) {
+                    ForwardDecision::Forward(port_id) => match self.tx.get_mut(&port_id) {
+                        Some(port) if port.pending.len() < PENDING_CAPACITY => port.pending.push(m),
+                        Some(_) | None => self.stats.dropped = self.stats.dropped.wrapping_add(1),
+                    },
+                    ForwardDecision::Drop => {
+                        self.stats.dropped = self.stats.dropped.wrapping_add(1);
+                    }
+                    ForwardDecision::Consume => {
+                        self.stats.consumed = self.stats.consumed.wrapping_add(1);
+                    }
+                }
+            }
+        }
+        self.flush();
+        received
+    }
+
+    /// Poll until `stop` is set, then try once more to send the frames held.
+    #[inline]
+    pub fn run(&mut self, stop: &AtomicBool) {
+        while !stop.load(Ordering::Acquire) {
+            if self.poll() == 0 {
+                hint::spin_loop();
+            }
+        }
+        self.flush();
+    }
+
+    /// Send the frames held for each tx queue, as many as it accepts.
+    fn flush(&mut self) {
+        for port in self.tx.values_mut() {
+            if !port.pending.is_empty() {
+                let sent = port.queue.send(&mut port.pending);
+                self.stats.tx_packets = self
+                    .stats
+                    .tx_packets
+                    .wrapping_add(u64::try_from(sent).unwrap_or(u64::MAX));
+            }
+        }
+    }
+}
+
+impl<F> Debug for Forwarder<'_, F> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Forwarder")
+            .field("rx", &self.rx)
+            .field("tx", &self.tx)
+            .field("burst", &self.burst)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ForwardDecision, Forwarder};
+    use crate::{eth_dev::EthDev, net_dev::DevConfig, test_utils};
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_forwarder() {
+        test_utils::dpdk_setup();
+        let mut dev = EthDev::new(1, 1, 1, &DevConfig::new()).unwrap();
+        dev.start().unwrap();
+        let mut n = 0_u32;
+        let mut fwd = Forwarder::new(|_m| {
+            n += 1;
+            match n % 3 {
+                0 => ForwardDecision::Forward(1),
+                1 => ForwardDecision::Forward(7),
+                _ => ForwardDecision::Consume,
+            }
+        })
+        .rx(dev.rx_queue(0).unwrap())
+        .tx(dev.tx_queue(0).unwrap())
+        .burst(30);
+        // The null device receives a full burst of empty frames, and sends any frames.
+        assert_eq!(fwd.poll(), 30);
+        let stats = fwd.stats();
+        assert_eq!(stats.rx_packets, 30);
+        assert_eq!(stats.tx_packets, 10);
+        assert_eq!(stats.dropped, 10);
+        assert_eq!(stats.consumed, 10);
+        fwd.run(&AtomicBool::new(true));
+        drop(fwd);
+        dev.stop().unwrap();
+    }
+}
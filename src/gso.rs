@@ -0,0 +1,226 @@
+//! Generic segmentation offload (GSO) on TX and generic receive offload (GRO) on RX for large UDP
+//! datagrams over IPv4, enabled per device.
+//!
+//! With GSO enabled, a datagram larger than the MTU is fragmented by the NIC if it supports UDP
+//! fragmentation offload, or by the GSO library otherwise, whose fragments refer to the payload
+//! with indirect `Mbuf`s instead of copying it. With GRO enabled, fragments received in the same
+//! burst are merged by the GRO library before they reach the reassembly table, which then only
+//! handles those left over. For more information, please refer to [`GSO document`] and
+//! [`GRO document`].
+//!
+//! [`GSO document`]: https://doc.dpdk.org/guides/prog_guide/generic_segmentation_offload_lib.html
+//! [`GRO document`]: https://doc.dpdk.org/guides/prog_guide/generic_receive_offload_lib.html
+
+#![allow(non_camel_case_types)]
+
+use crate::{
+    mbuf::Mbuf,
+    packet::{set_packet_type, set_tso_segsz},
+    proto::{L3Protocol, L4Protocol, ETHER_HDR_LEN, IP_NEXT_PROTO_UDP},
+    Error, Result,
+};
+use dpdk_sys::{
+    rte_ipv4_cksum, rte_ipv4_hdr, rte_mbuf, rte_mempool, RTE_ETHER_TYPE_IPV4, RTE_MBUF_F_TX_IPV4,
+    RTE_MBUF_F_TX_IP_CKSUM, RTE_MBUF_F_TX_UDP_SEG, RTE_PTYPE_L3_IPV4, RTE_PTYPE_L3_MASK,
+    RTE_PTYPE_L4_MASK, RTE_PTYPE_L4_UDP,
+};
+use std::{mem::ManuallyDrop, os::raw::c_int, ptr};
+
+/// Offload of UDP fragmentation on TX.
+pub(crate) const RTE_ETH_TX_OFFLOAD_UDP_TSO: u64 = 1 << 6;
+
+/// GRO type merging IPv4 fragments of UDP datagrams.
+const RTE_GRO_UDP_IPV4: u64 = 1 << 2;
+
+/// Maximum number of flows merged in a burst.
+const GRO_MAX_FLOW_NUM: u16 = 4;
+
+/// Length of the IPv4 header without options, which GSO and GRO are applied to.
+const IPV4_HDR_LEN: usize = 20;
+
+/// GSO context of a packet to be segmented.
+#[repr(C)]
+struct rte_gso_ctx {
+    /// Mempool to allocate direct `Mbuf`s holding the headers of the output segments.
+    direct_pool: *mut rte_mempool,
+    /// Mempool to allocate indirect `Mbuf`s referring to the payload of the input packet.
+    indirect_pool: *mut rte_mempool,
+    /// Flags of `RTE_GSO_FLAG_*`.
+    flag: u64,
+    /// Types of packets to be segmented, in `RTE_ETH_TX_OFFLOAD_*_TSO`.
+    gso_types: u32,
+    /// Maximum length of the output segments, including the packet header.
+    gso_size: u16,
+}
+
+/// Parameters of GRO.
+#[repr(C)]
+struct rte_gro_param {
+    /// Types of packets to be merged, in `RTE_GRO_*`.
+    gro_types: u64,
+    /// Maximum number of flows merged.
+    max_flow_num: u16,
+    /// Maximum number of packets per flow.
+    max_item_per_flow: u16,
+    /// Socket of the reassembly tables.
+    socket_id: u16,
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Segment a packet. Returns the number of output segments, or a negative errno.
+    fn rte_gso_segment(
+        pkt: *mut rte_mbuf,
+        ctx: *const rte_gso_ctx,
+        pkts_out: *mut *mut rte_mbuf,
+        nb_pkts_out: u16,
+    ) -> c_int;
+
+    /// Merge a burst of packets in place. Returns the number of packets left.
+    fn rte_gro_reassemble_burst(
+        pkts: *mut *mut rte_mbuf,
+        nb_pkts: u16,
+        param: *const rte_gro_param,
+    ) -> u16;
+}
+
+/// Whether an Ethernet frame to be sent holds an IPv4 UDP datagram without IP options.
+#[allow(unsafe_code)]
+pub(crate) fn is_ipv4_udp(m: &Mbuf) -> bool {
+    // SAFETY: mbuf pointer checked upon its allocation
+    let pm = unsafe { &*m.as_ptr() };
+    // SAFETY: access union type
+    let ptype = unsafe { pm.packet_type_union.packet_type };
+    ptype & RTE_PTYPE_L3_MASK == RTE_PTYPE_L3_IPV4
+        && ptype & RTE_PTYPE_L4_MASK == RTE_PTYPE_L4_UDP
+        && m.data_slice().get(usize::from(ETHER_HDR_LEN)) == Some(&0x45)
+}
+
+/// Let the NIC fragment an IPv4 UDP datagram into IP packets no larger than `mtu`.
+#[allow(unsafe_code)]
+pub(crate) fn set_udp_seg(m: &Mbuf, mtu: u16) {
+    let frag_size = usize::from(mtu).saturating_sub(IPV4_HDR_LEN) & !7;
+    // SAFETY: mbuf pointer checked upon its allocation
+    let pm = unsafe { &mut *m.as_ptr() };
+    pm.ol_flags |= RTE_MBUF_F_TX_UDP_SEG | RTE_MBUF_F_TX_IPV4;
+    set_tso_segsz(m, u16::try_from(frag_size).unwrap_or(u16::MAX));
+}
+
+/// Fragment an IPv4 UDP datagram into Ethernet frames holding IP packets no larger than `mtu`,
+/// returning at most `max_segs` of them.
+///
+/// The IP header checksums of the fragments are offloaded if `ip_cksum_offload`, otherwise they
+/// are computed in software.
+#[allow(unsafe_code)]
+pub(crate) fn segment(
+    m: Mbuf,
+    mtu: u16,
+    max_segs: usize,
+    ip_cksum_offload: bool,
+) -> Result<Vec<*mut rte_mbuf>> {
+    let frag_size = usize::from(mtu).saturating_sub(IPV4_HDR_LEN) & !7;
+    if frag_size == 0 {
+        return Err(Error::InvalidArg);
+    }
+    let exp_nb_segs = m.pkt_len().div_ceil(frag_size);
+    if max_segs < exp_nb_segs {
+        return Err(Error::NoBuf);
+    }
+    let pm = m.as_ptr();
+    // SAFETY: mbuf pointer checked upon its allocation
+    unsafe {
+        (*pm).ol_flags |= RTE_MBUF_F_TX_UDP_SEG | RTE_MBUF_F_TX_IPV4;
+    }
+    let ctx = rte_gso_ctx {
+        // SAFETY: mbuf pointer checked upon its allocation
+        direct_pool: unsafe { (*pm).pool },
+        // SAFETY: mbuf pointer checked upon its allocation
+        indirect_pool: unsafe { (*pm).pool },
+        flag: 0,
+        #[allow(clippy::cast_possible_truncation)] // within `gso_types`
+        gso_types: RTE_ETH_TX_OFFLOAD_UDP_TSO as u32,
+        gso_size: mtu.saturating_add(ETHER_HDR_LEN),
+    };
+    let mut segs: Vec<*mut rte_mbuf> = vec![ptr::null_mut(); exp_nb_segs];
+    // SAFETY: `segs` holds `exp_nb_segs` pointers
+    let ret = unsafe {
+        rte_gso_segment(
+            pm,
+            ptr::addr_of!(ctx),
+            segs.as_mut_ptr(),
+            exp_nb_segs.try_into().map_err(Error::from)?,
+        )
+    };
+    Error::from_ret(ret, "rte_gso_segment")?;
+    if ret == 0 {
+        // Not segmented, the input packet is output as is.
+        let _m = ManuallyDrop::new(m);
+        segs.clear();
+        segs.push(pm);
+    } else {
+        // The segments hold their own references to the input packet.
+        drop(m);
+        #[allow(clippy::cast_sign_loss)] // errno checked
+        segs.truncate(ret as usize);
+    }
+    for &seg in &segs {
+        // SAFETY: output segments are valid
+        let seg = unsafe { &mut *seg };
+        seg.ol_flags &= !RTE_MBUF_F_TX_UDP_SEG;
+        if ip_cksum_offload {
+            seg.ol_flags |= RTE_MBUF_F_TX_IP_CKSUM;
+        } else {
+            // SAFETY: the first `Mbuf` of a segment holds the Ethernet and IPv4 headers
+            unsafe {
+                #[allow(clippy::cast_ptr_alignment)] // allowed in DPDK
+                let ip_hdr = seg
+                    .buf_addr
+                    .cast::<u8>()
+                    .add(usize::from(seg.data_off).wrapping_add(usize::from(ETHER_HDR_LEN)))
+                    .cast::<rte_ipv4_hdr>();
+                (*ip_hdr).hdr_checksum = 0;
+                (*ip_hdr).hdr_checksum = rte_ipv4_cksum(ip_hdr).to_be();
+            }
+        }
+    }
+    Ok(segs)
+}
+
+/// Merge IPv4 fragments of UDP datagrams in a burst of received Ethernet frames in place,
+/// returning the number of frames left at the front of `pkts`.
+#[allow(unsafe_code)]
+pub(crate) fn reassemble(pkts: &mut [*mut rte_mbuf], socket_id: i32) -> usize {
+    for &pm in pkts.iter() {
+        let Ok(m) = Mbuf::new_with_ptr(pm) else {
+            continue;
+        };
+        // Owned by `pkts`.
+        let m = ManuallyDrop::new(m);
+        #[allow(clippy::cast_possible_truncation)] // Ether type is 16 bits
+        let ether_type = (RTE_ETHER_TYPE_IPV4 as u16).to_be_bytes();
+        let l2_len = usize::from(ETHER_HDR_LEN);
+        let data = m.data_slice();
+        if data.get(l2_len.wrapping_sub(2)..l2_len) == Some(&ether_type[..])
+            && data.get(l2_len) == Some(&0x45)
+            && data.get(l2_len.wrapping_add(9)) == Some(&IP_NEXT_PROTO_UDP)
+        {
+            // The GRO library relies on the packet type and header lengths.
+            set_packet_type(&m, L3Protocol::Ipv4, L4Protocol::Udp);
+        }
+    }
+    let param = rte_gro_param {
+        gro_types: RTE_GRO_UDP_IPV4,
+        max_flow_num: GRO_MAX_FLOW_NUM,
+        max_item_per_flow: pkts.len().try_into().unwrap_or(u16::MAX),
+        socket_id: socket_id.try_into().unwrap_or_default(),
+    };
+    // SAFETY: `pkts` holds valid packets
+    let n = unsafe {
+        rte_gro_reassemble_burst(
+            pkts.as_mut_ptr(),
+            pkts.len().try_into().unwrap_or(u16::MAX),
+            ptr::addr_of!(param),
+        )
+    };
+    usize::from(n)
+}
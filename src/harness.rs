@@ -0,0 +1,202 @@
+//! Harness connecting ports back to back through rings in a single process, like two NICs
+//! connected by a cable, so that the agents and the protocols can be tested end to end without
+//! NICs.
+//!
+//! Unlike a port of `Vdev::Ring`, which happens to receive what it sends itself, each port of a
+//! `Link` receives what the other one sends. Packets between the ports go through the tx and rx
+//! agents, ARP and the devices just as they do on a wire.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use async_dpdk::harness::Link;
+//! # use async_dpdk::net_dev::{self, IfAddr};
+//!
+//! # let _ = async_dpdk::eal::Config::new().enter();
+//! let a: IfAddr = "10.9.0.1/24".parse().unwrap();
+//! let b: IfAddr = "10.9.0.2/24".parse().unwrap();
+//! let link = Link::create("link0", 1, a, b).unwrap();
+//! net_dev::device_start_all().unwrap();
+//! // Datagrams from sockets bound to 10.9.0.1 to 10.9.0.2 go through the link.
+//! drop(link);
+//! ```
+
+use crate::{
+    lcore,
+    mbuf::Mbuf,
+    net_dev::{self, IfAddr},
+    ring::{Ring, SyncMode},
+    trace::{debug, error},
+    Error, Result,
+};
+use dpdk_sys::rte_ring;
+use std::{
+    ffi::CString,
+    net::IpAddr,
+    os::raw::{c_char, c_int, c_uint},
+};
+
+/// Number of packets each ring of a `Link` holds.
+const LINK_RING_SIZE: u32 = 1024;
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Create a port named `net_ring_{name}` receiving from `rx_queues` and sending to
+    /// `tx_queues`. Returns the port id, or -1 with `rte_errno` set.
+    fn rte_eth_from_rings(
+        name: *const c_char,
+        rx_queues: *const *mut rte_ring,
+        nb_rx_queues: c_uint,
+        tx_queues: *const *mut rte_ring,
+        nb_tx_queues: c_uint,
+        numa_node: c_uint,
+    ) -> c_int;
+
+    /// Uninitialize a virtual device by its name. Returns 0 on success, or a negative errno.
+    fn rte_vdev_uninit(name: *const c_char) -> c_int;
+}
+
+/// A port at one end of a `Link`.
+#[derive(Debug)]
+struct End {
+    /// Name of the underlying virtual device.
+    vdev: CString,
+    /// Address assigned to the port.
+    addr: IpAddr,
+    /// Whether the port is attached to the stack.
+    attached: bool,
+}
+
+/// Two ports connected back to back, attached to the stack with an address each.
+///
+/// The ports are set up with the configurations given on probing, and should be started with
+/// `net_dev::device_start`. Both of them are detached when the `Link` is dropped.
+#[derive(Debug)]
+pub struct Link {
+    /// Ports created so far, which are torn down in reverse order.
+    ends: Vec<End>,
+    /// Rings carrying packets from the first port to the second one, one for each queue.
+    forward: Vec<Ring<Mbuf>>,
+    /// Rings carrying packets from the second port to the first one, one for each queue.
+    backward: Vec<Ring<Mbuf>>,
+}
+
+impl Link {
+    /// Create two ports with `queues` rx and tx queues each, connected by rings named after
+    /// `name`, and attach them to the stack with addresses `a` and `b` respectively, which are
+    /// either `IpAddr`s or `IfAddr`s along with the prefix length of their subnets.
+    ///
+    /// Queue `i` of one port sends to queue `i` of the other one, so packets are received on the
+    /// queue that the peer sends them on.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: `queues` is 0, or `name` holds a nul byte.
+    /// - `Error::Exists`: `a` or `b` is already assigned to a device, or `name` is used by
+    ///   another `Link`.
+    /// - Unable to create the rings or the ports, or to set up the ports.
+    #[inline]
+    pub fn create<A, B>(name: &str, queues: u16, a: A, b: B) -> Result<Self>
+    where
+        A: Into<IfAddr>,
+        B: Into<IfAddr>,
+    {
+        if queues == 0 {
+            return Err(Error::InvalidArg);
+        }
+        let rings = |dir: &str| {
+            (0..queues)
+                .map(|queue| {
+                    let ring_name = format!("{name}_{dir}{queue}");
+                    Ring::create(
+                        &ring_name,
+                        LINK_RING_SIZE,
+                        SyncMode::Single,
+                        SyncMode::Single,
+                    )
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        let mut link = Self {
+            ends: Vec::with_capacity(2),
+            forward: rings("fw")?,
+            backward: rings("bw")?,
+        };
+        let forward: Vec<_> = link.forward.iter().map(Ring::as_ptr).collect();
+        let backward: Vec<_> = link.backward.iter().map(Ring::as_ptr).collect();
+        link.attach(&format!("{name}_a"), a.into(), &backward, &forward)?;
+        link.attach(&format!("{name}_b"), b.into(), &forward, &backward)?;
+        debug!("Link {name} created with {queues} queues");
+        Ok(link)
+    }
+
+    /// Create a port receiving from `rx` and sending to `tx`, and attach it with `addr`.
+    #[allow(unsafe_code)]
+    fn attach(
+        &mut self,
+        name: &str,
+        addr: IfAddr,
+        rx: &[*mut rte_ring],
+        tx: &[*mut rte_ring],
+    ) -> Result<()> {
+        let c_name = CString::new(name).map_err(Error::from)?;
+        let vdev = CString::new(format!("net_ring_{name}")).map_err(Error::from)?;
+        let nb_queues = c_uint::try_from(rx.len()).map_err(Error::from)?;
+        // `SOCKET_ID_ANY` is passed as is.
+        #[allow(clippy::cast_sign_loss)]
+        let socket_id = lcore::socket_id() as c_uint;
+        let ends = &mut self.ends;
+        net_dev::attach_port(addr, || {
+            // SAFETY: the rings outlive the port, which is removed on drop
+            let port_id = unsafe {
+                rte_eth_from_rings(
+                    c_name.as_ptr(),
+                    rx.as_ptr(),
+                    nb_queues,
+                    tx.as_ptr(),
+                    nb_queues,
+                    socket_id,
+                )
+            };
+            let port_id =
+                u16::try_from(port_id).map_err(|_e| Error::from_errno("rte_eth_from_rings"))?;
+            ends.push(End {
+                vdev,
+                addr: addr.ip,
+                attached: false,
+            });
+            Ok(port_id)
+        })?;
+        if let Some(end) = self.ends.last_mut() {
+            end.attached = true;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Link {
+    #[inline]
+    fn drop(&mut self) {
+        while let Some(end) = self.ends.pop() {
+            if end.attached {
+                if let Err(e) = net_dev::device_detach(&end.addr) {
+                    error!(
+                        "Failed to detach the link port bound to {}: {e:?}",
+                        end.addr
+                    );
+                }
+                continue;
+            }
+            // SAFETY: the device is created by `rte_eth_from_rings` and not attached
+            #[allow(unsafe_code)]
+            let errno = unsafe { rte_vdev_uninit(end.vdev.as_ptr()) };
+            if let Err(e) = Error::from_ret(errno, "rte_vdev_uninit") {
+                error!("Failed to remove link device {:?}: {e:?}", end.vdev);
+            }
+        }
+        // The rings are freed after the ports, along with the packets left in them.
+    }
+}
@@ -0,0 +1,485 @@
+//! Hash table of flows based on `rte_hash`, a cuckoo hash table of fixed-length keys, which suits
+//! per-flow state such as that of connections or user flow tracking. For more information, please
+//! refer to [`Hash library document`].
+//!
+//! # Examples
+//!
+//! ```
+//! # use async_dpdk::hash::{FlowHash, HashParams};
+//!
+//! # let _ = async_dpdk::eal::Config::new().enter();
+//! // Keyed by (source IPv4 address, destination IPv4 address, source port, destination port).
+//! let flows: FlowHash<[u8; 12], u64> =
+//!     FlowHash::create("flows", HashParams::new(1024, 12)).unwrap();
+//! let key = [10, 0, 0, 1, 10, 0, 0, 2, 0x04, 0xd2, 0x16, 0x2e];
+//! assert_eq!(flows.add(key, 1).unwrap(), None);
+//! assert_eq!(flows.lookup(&key).unwrap(), Some(1));
+//! assert_eq!(flows.delete(&key).unwrap(), Some(1));
+//! ```
+//!
+//! [`Hash library document`]: https://doc.dpdk.org/guides/prog_guide/hash_lib.html
+
+#![allow(non_camel_case_types)]
+
+use crate::{lcore, Error, ErrorKind, Result};
+use std::{
+    ffi::CString,
+    fmt::Debug,
+    os::raw::{c_char, c_int, c_void},
+    ptr::{self, NonNull},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+/// Let several writers add keys concurrently.
+const RTE_HASH_EXTRA_FLAGS_MULTI_WRITER_ADD: u8 = 0x02;
+
+/// Let readers look up keys concurrently with writers.
+const RTE_HASH_EXTRA_FLAGS_RW_CONCURRENCY: u8 = 0x04;
+
+/// Chain keys that don't fit in their cuckoo buckets in extendable buckets.
+const RTE_HASH_EXTRA_FLAGS_EXT_TABLE: u8 = 0x08;
+
+/// Maximum number of keys looked up in a bulk.
+const RTE_HASH_LOOKUP_BULK_MAX: usize = 64;
+
+/// Signature of the hash functions.
+type rte_hash_function = Option<unsafe extern "C" fn(*const c_void, u32, u32) -> u32>;
+
+/// Parameters to create a hash table.
+#[repr(C)]
+struct rte_hash_parameters {
+    /// Name of the hash table.
+    name: *const c_char,
+    /// Total number of entries.
+    entries: u32,
+    /// Unused.
+    reserved: u32,
+    /// Length of the keys.
+    key_len: u32,
+    /// Hash function, or the default one if it's `None`.
+    hash_func: rte_hash_function,
+    /// Initial value of the hash function.
+    hash_func_init_val: u32,
+    /// NUMA socket to allocate memory on.
+    socket_id: c_int,
+    /// Flags of `RTE_HASH_EXTRA_FLAGS_*`.
+    extra_flag: u8,
+}
+
+/// Opaque type of hash tables.
+#[repr(C)]
+struct rte_hash {
+    /// Zero-sized private field.
+    _private: [u8; 0],
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Create a hash table.
+    fn rte_hash_create(params: *const rte_hash_parameters) -> *mut rte_hash;
+
+    /// Free a hash table.
+    fn rte_hash_free(h: *mut rte_hash);
+
+    /// Add a key, or find it if it exists. Returns the position of the key, or a negative errno.
+    fn rte_hash_add_key(h: *const rte_hash, key: *const c_void) -> i32;
+
+    /// Delete a key. Returns the position of the key, or a negative errno.
+    fn rte_hash_del_key(h: *const rte_hash, key: *const c_void) -> i32;
+
+    /// Look up a key. Returns the position of the key, or a negative errno.
+    fn rte_hash_lookup(h: *const rte_hash, key: *const c_void) -> i32;
+
+    /// Look up several keys, storing their positions or `-ENOENT` in `positions`.
+    fn rte_hash_lookup_bulk(
+        h: *const rte_hash,
+        keys: *mut *const c_void,
+        num_keys: u32,
+        positions: *mut i32,
+    ) -> c_int;
+
+    /// The number of keys in a hash table, or a negative errno.
+    fn rte_hash_count(h: *const rte_hash) -> i32;
+
+    /// The maximum position of keys in a hash table, or a negative errno.
+    fn rte_hash_max_key_id(h: *const rte_hash) -> i32;
+}
+
+/// Parameters of a `FlowHash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct HashParams {
+    /// Maximum number of entries.
+    entries: u32,
+    /// Length of the keys in bytes.
+    key_len: u32,
+    /// Initial value of the hash function.
+    init_val: u32,
+    /// Flags of `RTE_HASH_EXTRA_FLAGS_*`.
+    flags: u8,
+}
+
+impl HashParams {
+    /// Parameters of a table holding up to `entries` keys of `key_len` bytes.
+    #[inline]
+    #[must_use]
+    pub fn new(entries: u32, key_len: u32) -> Self {
+        Self {
+            entries,
+            key_len,
+            init_val: 0,
+            flags: 0,
+        }
+    }
+
+    /// Set the initial value of the hash function. Default is 0.
+    #[inline]
+    #[must_use]
+    pub fn hash_init_val(mut self, init_val: u32) -> Self {
+        self.init_val = init_val;
+        self
+    }
+
+    /// Chain keys that don't fit in their cuckoo buckets in extendable buckets, so that the table
+    /// always holds `entries` keys. Disabled by default.
+    #[inline]
+    #[must_use]
+    pub fn ext_table(mut self, enable: bool) -> Self {
+        self.set_flag(RTE_HASH_EXTRA_FLAGS_EXT_TABLE, enable);
+        self
+    }
+
+    /// Let lookups run concurrently with writes, synchronized by `rte_hash` instead of a lock of
+    /// the whole table. Disabled by default.
+    #[inline]
+    #[must_use]
+    pub fn rw_concurrency(mut self, enable: bool) -> Self {
+        self.set_flag(RTE_HASH_EXTRA_FLAGS_RW_CONCURRENCY, enable);
+        self
+    }
+
+    /// Let several writers add and delete keys concurrently. It only takes effect together with
+    /// `rw_concurrency`. Disabled by default.
+    #[inline]
+    #[must_use]
+    pub fn multi_writer(mut self, enable: bool) -> Self {
+        self.set_flag(RTE_HASH_EXTRA_FLAGS_MULTI_WRITER_ADD, enable);
+        self
+    }
+
+    /// Set or clear `flag`.
+    fn set_flag(&mut self, flag: u8, enable: bool) {
+        if enable {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+}
+
+/// A hash table from keys of fixed length to values, which is a safe wrapper of `rte_hash`.
+///
+/// `rte_hash` maps each key to a position, and the values are stored in a slot per position along
+/// with their keys. Unless `rw_concurrency` is set, the table is locked as a whole: lookups share
+/// the lock, while writes hold it exclusively.
+#[allow(clippy::module_name_repetitions)]
+pub struct FlowHash<K, V> {
+    /// A pointer to `rte_hash`.
+    h: NonNull<rte_hash>,
+    /// Length of the keys in bytes.
+    key_len: usize,
+    /// Flags of `RTE_HASH_EXTRA_FLAGS_*`.
+    flags: u8,
+    /// Lock of the whole table, which is not held when `rte_hash` synchronizes the accesses.
+    lock: RwLock<()>,
+    /// Keys and values indexed by the positions of keys.
+    slots: Vec<RwLock<Option<(K, V)>>>,
+}
+
+// SAFETY: `rte_hash` can be accessed from any thread
+#[allow(unsafe_code)]
+unsafe impl<K: Send, V: Send> Send for FlowHash<K, V> {}
+
+// SAFETY: concurrent accesses are synchronized by `lock` or `rte_hash`, and the slots by their
+// locks.
+#[allow(unsafe_code)]
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for FlowHash<K, V> {}
+
+impl<K, V> Debug for FlowHash<K, V> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FlowHash")
+            .field("key_len", &self.key_len)
+            .field("flags", &self.flags)
+            .finish()
+    }
+}
+
+impl<K: AsRef<[u8]>, V: Clone> FlowHash<K, V> {
+    /// Create a hash table named `name`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `name` contains a nul byte.
+    /// - `entries` or `key_len` of `params` is 0, or `entries` is too large.
+    /// - No appropriate memory area left.
+    /// - A hash table with the same name already exists.
+    #[inline]
+    pub fn create(name: &str, params: HashParams) -> Result<Self> {
+        if params.entries == 0 || params.key_len == 0 {
+            return Err(Error::InvalidArg);
+        }
+        let name = CString::new(name).map_err(Error::from)?;
+        let raw = rte_hash_parameters {
+            name: name.as_ptr(),
+            entries: params.entries,
+            reserved: 0,
+            key_len: params.key_len,
+            hash_func: None,
+            hash_func_init_val: params.init_val,
+            socket_id: lcore::socket_id(),
+            extra_flag: params.flags,
+        };
+        // SAFETY: pointer checked later
+        #[allow(unsafe_code)]
+        let ptr = unsafe { rte_hash_create(ptr::addr_of!(raw)) };
+        let h = NonNull::new(ptr).ok_or_else(|| Error::from_errno("rte_hash_create"))?;
+        let mut table = Self {
+            h,
+            key_len: params.key_len as usize,
+            flags: params.flags,
+            lock: RwLock::new(()),
+            slots: Vec::new(),
+        };
+        // SAFETY: *rte_hash checked
+        #[allow(unsafe_code)]
+        let max_id = unsafe { rte_hash_max_key_id(table.h.as_ptr()) };
+        let n_slots = usize::try_from(max_id).map_err(Error::from)?;
+        table.slots = (0..=n_slots).map(|_| RwLock::new(None)).collect();
+        Ok(table)
+    }
+
+    /// Add `key` with `value`, returning the previous value of `key` if it exists.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: the length of `key` doesn't match.
+    /// - `Error::NoSpace`: the table is full.
+    #[inline]
+    pub fn add(&self, key: K, value: V) -> Result<Option<V>> {
+        let _guard = self.write_lock()?;
+        let pos = self.check(key.as_ref(), rte_hash_add_key, "rte_hash_add_key")?;
+        let mut slot = self.slot(pos)?.write().map_err(Error::from)?;
+        let prev = slot.replace((key, value));
+        Ok(prev.map(|(_, value)| value))
+    }
+
+    /// Delete `key`, returning its value if it exists.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: the length of `key` doesn't match.
+    #[inline]
+    pub fn delete(&self, key: &K) -> Result<Option<V>> {
+        let _guard = self.write_lock()?;
+        let pos = match self.check(key.as_ref(), rte_hash_del_key, "rte_hash_del_key") {
+            Ok(pos) => pos,
+            Err(e) if e.kind() == ErrorKind::NoEntry => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut slot = self.slot(pos)?.write().map_err(Error::from)?;
+        // The position may be reused by a concurrent writer once the key is deleted.
+        if slot.as_ref().map(|entry| entry.0.as_ref()) != Some(key.as_ref()) {
+            return Ok(None);
+        }
+        Ok(slot.take().map(|(_, value)| value))
+    }
+
+    /// Look up the value of `key`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: the length of `key` doesn't match.
+    #[inline]
+    pub fn lookup(&self, key: &K) -> Result<Option<V>> {
+        let _guard = self.read_lock()?;
+        match self.check(key.as_ref(), rte_hash_lookup, "rte_hash_lookup") {
+            Ok(pos) => self.value(pos, key.as_ref()),
+            Err(e) if e.kind() == ErrorKind::NoEntry => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Look up the values of several keys, in the order of `keys`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: the length of some key doesn't match.
+    #[inline]
+    pub fn lookup_bulk(&self, keys: &[K]) -> Result<Vec<Option<V>>> {
+        if keys.iter().any(|key| key.as_ref().len() != self.key_len) {
+            return Err(Error::InvalidArg);
+        }
+        let _guard = self.read_lock()?;
+        let mut values = Vec::with_capacity(keys.len());
+        let mut positions = [0_i32; RTE_HASH_LOOKUP_BULK_MAX];
+        for chunk in keys.chunks(RTE_HASH_LOOKUP_BULK_MAX) {
+            let mut ptrs: Vec<_> = chunk
+                .iter()
+                .map(|key| key.as_ref().as_ptr().cast::<c_void>())
+                .collect();
+            #[allow(clippy::cast_possible_truncation)] // at most RTE_HASH_LOOKUP_BULK_MAX keys
+            let n = chunk.len() as u32;
+            // SAFETY: *rte_hash checked, and the keys are of `key_len` bytes
+            #[allow(unsafe_code)]
+            let errno = unsafe {
+                rte_hash_lookup_bulk(
+                    self.h.as_ptr(),
+                    ptrs.as_mut_ptr(),
+                    n,
+                    positions.as_mut_ptr(),
+                )
+            };
+            Error::from_ret(errno, "rte_hash_lookup_bulk")?;
+            for (key, &pos) in chunk.iter().zip(positions.iter()) {
+                values.push(match usize::try_from(pos) {
+                    Ok(pos) => self.value(pos, key.as_ref())?,
+                    Err(_) => None,
+                });
+            }
+        }
+        Ok(values)
+    }
+
+    /// The number of keys in the table.
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        // SAFETY: *rte_hash checked
+        #[allow(unsafe_code)]
+        let count = unsafe { rte_hash_count(self.h.as_ptr()) };
+        usize::try_from(count).unwrap_or(0)
+    }
+
+    /// Whether the table is empty.
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Call `f`, i.e. DPDK function `op`, with `key` if its length matches, which returns a
+    /// position or a negative errno.
+    fn check(
+        &self,
+        key: &[u8],
+        f: unsafe extern "C" fn(*const rte_hash, *const c_void) -> i32,
+        op: &'static str,
+    ) -> Result<usize> {
+        if key.len() != self.key_len {
+            return Err(Error::InvalidArg);
+        }
+        // SAFETY: *rte_hash checked, and the key is of `key_len` bytes
+        #[allow(unsafe_code)]
+        let ret = unsafe { f(self.h.as_ptr(), key.as_ptr().cast()) };
+        Error::from_ret(ret, op)?;
+        usize::try_from(ret).map_err(Error::from)
+    }
+
+    /// The slot of position `pos`.
+    fn slot(&self, pos: usize) -> Result<&RwLock<Option<(K, V)>>> {
+        self.slots.get(pos).ok_or(Error::OutOfRange)
+    }
+
+    /// The value in the slot of position `pos` if it's of `key`.
+    fn value(&self, pos: usize, key: &[u8]) -> Result<Option<V>> {
+        let slot = self.slot(pos)?.read().map_err(Error::from)?;
+        Ok(slot
+            .as_ref()
+            .filter(|entry| entry.0.as_ref() == key)
+            .map(|entry| entry.1.clone()))
+    }
+
+    /// Lock the table for a lookup, unless `rte_hash` synchronizes readers with writers.
+    fn read_lock(&self) -> Result<Option<RwLockReadGuard<'_, ()>>> {
+        if self.flags & RTE_HASH_EXTRA_FLAGS_RW_CONCURRENCY != 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.lock.read().map_err(Error::from)?))
+    }
+
+    /// Lock the table for a write, unless `rte_hash` synchronizes writers with each other and with
+    /// readers.
+    fn write_lock(&self) -> Result<Option<RwLockWriteGuard<'_, ()>>> {
+        let concurrent =
+            RTE_HASH_EXTRA_FLAGS_RW_CONCURRENCY | RTE_HASH_EXTRA_FLAGS_MULTI_WRITER_ADD;
+        if self.flags & concurrent == concurrent {
+            return Ok(None);
+        }
+        Ok(Some(self.lock.write().map_err(Error::from)?))
+    }
+}
+
+impl<K, V> Drop for FlowHash<K, V> {
+    #[inline]
+    fn drop(&mut self) {
+        // SAFETY: *rte_hash checked upon `create`
+        #[allow(unsafe_code)]
+        unsafe {
+            rte_hash_free(self.h.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlowHash, HashParams};
+    use crate::{test_utils, Error};
+
+    #[test]
+    fn test() {
+        test_utils::dpdk_setup();
+        let table: FlowHash<[u8; 4], u32> =
+            FlowHash::create("flow_hash", HashParams::new(64, 4).ext_table(true)).unwrap();
+        assert!(table.is_empty());
+        for i in 0..10_u32 {
+            assert_eq!(table.add(i.to_be_bytes(), i).unwrap(), None);
+        }
+        assert_eq!(table.add(3_u32.to_be_bytes(), 30).unwrap(), Some(3));
+        assert_eq!(table.len(), 10);
+        assert_eq!(table.lookup(&3_u32.to_be_bytes()).unwrap(), Some(30));
+        assert_eq!(table.lookup(&42_u32.to_be_bytes()).unwrap(), None);
+
+        let keys: Vec<_> = (8..12_u32).map(u32::to_be_bytes).collect();
+        assert_eq!(
+            table.lookup_bulk(&keys).unwrap(),
+            [Some(8), Some(9), None, None]
+        );
+
+        assert_eq!(table.delete(&8_u32.to_be_bytes()).unwrap(), Some(8));
+        assert_eq!(table.delete(&8_u32.to_be_bytes()).unwrap(), None);
+        assert_eq!(table.lookup(&8_u32.to_be_bytes()).unwrap(), None);
+        assert_eq!(table.len(), 9);
+
+        let bytes: FlowHash<Vec<u8>, u32> =
+            FlowHash::create("flow_hash_vec", HashParams::new(64, 4)).unwrap();
+        assert!(matches!(
+            bytes.add(vec![0; 3], 0).unwrap_err(),
+            Error::InvalidArg
+        ));
+    }
+}
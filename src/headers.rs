@@ -0,0 +1,351 @@
+//! Typed views of protocol headers in packets, which read and write fields in the network byte
+//! order, so that users get and set values in the host byte order.
+//!
+//! Each header has an immutable view, e.g. `UdpHdr`, and a mutable one, e.g. `UdpHdrMut`, over
+//! a byte slice or the first segment of an `Mbuf` at some offset. The length is checked when a
+//! view is created.
+//!
+//! # Examples
+//!
+//! ```
+//! # use async_dpdk::headers::{UdpHdr, UdpHdrMut};
+//! let mut buf = [0; 8];
+//! let mut udp = UdpHdrMut::new(&mut buf).unwrap();
+//! udp.set_dst_port(53);
+//! udp.set_dgram_len(8);
+//! assert_eq!(buf, [0, 0, 0, 53, 0, 8, 0, 0]);
+//! assert_eq!(UdpHdr::new(&buf).unwrap().dst_port(), 53);
+//! assert!(UdpHdr::new(&buf[..4]).is_err());
+//! ```
+
+use crate::{
+    mbuf::Mbuf,
+    proto::{cksum_add, cksum_fold},
+    Error, Result,
+};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// A field of a header, which is encoded in the network byte order.
+trait Field: Sized {
+    /// Read the field at `off` of `buf`.
+    fn read(buf: &[u8], off: usize) -> Self;
+    /// Write the field at `off` of `buf`.
+    fn write(self, buf: &mut [u8], off: usize);
+}
+
+/// Implement `Field` for types converted from and to big endian byte arrays.
+macro_rules! impl_field {
+    ($($t: ty, $n: expr, $from: expr, $to: expr;)*) => {
+        $(
+            impl Field for $t {
+                #[allow(clippy::redundant_closure_call)]
+                fn read(buf: &[u8], off: usize) -> Self {
+                    let bytes = buf
+                        .get(off..off.wrapping_add($n))
+                        .and_then(|b| <[u8; $n]>::try_from(b).ok())
+                        .unwrap_or([0; $n]);
+                    $from(bytes)
+                }
+
+                #[allow(clippy::redundant_closure_call)]
+                fn write(self, buf: &mut [u8], off: usize) {
+                    if let Some(b) = buf.get_mut(off..off.wrapping_add($n)) {
+                        b.copy_from_slice(&$to(self));
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_field! {
+    u8, 1, |b: [u8; 1]| u8::from_be_bytes(b), u8::to_be_bytes;
+    u16, 2, u16::from_be_bytes, u16::to_be_bytes;
+    u32, 4, u32::from_be_bytes, u32::to_be_bytes;
+    [u8; 6], 6, |b| b, |b| b;
+    Ipv4Addr, 4, Ipv4Addr::from, |a: Ipv4Addr| a.octets();
+    Ipv6Addr, 16, Ipv6Addr::from, |a: Ipv6Addr| a.octets();
+}
+
+/// Define the immutable and mutable views of a header, with accessors of its fields.
+macro_rules! header {
+    (
+        $(#[$attr: meta])*
+        $name: ident, $name_mut: ident, $len: expr;
+        $($(#[$fattr: meta])* $field: ident, $set: ident: $t: ty = $off: expr;)*
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy)]
+        pub struct $name<'a> {
+            /// Bytes of the header.
+            buf: &'a [u8],
+        }
+
+        $(#[$attr])*
+        #[derive(Debug)]
+        pub struct $name_mut<'a> {
+            /// Bytes of the header.
+            buf: &'a mut [u8],
+        }
+
+        impl<'a> $name<'a> {
+            /// Length of the header in bytes.
+            pub const LEN: usize = $len;
+
+            /// View the header at the start of `buf`.
+            ///
+            /// # Errors
+            ///
+            /// This function returns `Error::OutOfRange` if `buf` is shorter than the header.
+            #[inline]
+            pub fn new(buf: &'a [u8]) -> Result<Self> {
+                let buf = buf.get(..Self::LEN).ok_or(Error::OutOfRange)?;
+                Ok(Self { buf })
+            }
+
+            /// View the header at `offset` of the first segment of `m`.
+            ///
+            /// # Errors
+            ///
+            /// This function returns `Error::OutOfRange` if the first segment is too short.
+            #[inline]
+            pub fn from_mbuf(m: &'a Mbuf, offset: usize) -> Result<Self> {
+                Self::new(m.data_slice().get(offset..).ok_or(Error::OutOfRange)?)
+            }
+
+            /// Get the bytes of the header.
+            #[inline]
+            #[must_use]
+            pub fn as_bytes(&self) -> &'a [u8] {
+                self.buf
+            }
+
+            $(
+                $(#[$fattr])*
+                #[inline]
+                #[must_use]
+                pub fn $field(&self) -> $t {
+                    Field::read(self.buf, $off)
+                }
+            )*
+        }
+
+        impl<'a> $name_mut<'a> {
+            /// Length of the header in bytes.
+            pub const LEN: usize = $len;
+
+            /// View the header at the start of `buf`.
+            ///
+            /// # Errors
+            ///
+            /// This function returns `Error::OutOfRange` if `buf` is shorter than the header.
+            #[inline]
+            pub fn new(buf: &'a mut [u8]) -> Result<Self> {
+                let buf = buf.get_mut(..Self::LEN).ok_or(Error::OutOfRange)?;
+                Ok(Self { buf })
+            }
+
+            /// View the header at `offset` of the first segment of `m`.
+            ///
+            /// # Errors
+            ///
+            /// This function returns `Error::OutOfRange` if the first segment is too short.
+            #[inline]
+            pub fn from_mbuf(m: &'a mut Mbuf, offset: usize) -> Result<Self> {
+                Self::new(m.data_slice_mut().get_mut(offset..).ok_or(Error::OutOfRange)?)
+            }
+
+            /// Get the bytes of the header.
+            #[inline]
+            #[must_use]
+            pub fn as_bytes(&self) -> &[u8] {
+                self.buf
+            }
+
+            /// Get an immutable view of the header.
+            #[inline]
+            #[must_use]
+            pub fn as_ref(&self) -> $name<'_> {
+                $name { buf: self.buf }
+            }
+
+            $(
+                $(#[$fattr])*
+                #[inline]
+                #[must_use]
+                pub fn $field(&self) -> $t {
+                    Field::read(self.buf, $off)
+                }
+
+                #[doc = concat!("Set the value returned by `", stringify!($field), "`.")]
+                #[inline]
+                pub fn $set(&mut self, value: $t) {
+                    value.write(self.buf, $off);
+                }
+            )*
+        }
+    };
+}
+
+header! {
+    /// A view of an Ethernet header.
+    EtherHdr, EtherHdrMut, 14;
+    /// Get the destination MAC address.
+    dst_addr, set_dst_addr: [u8; 6] = 0;
+    /// Get the source MAC address.
+    src_addr, set_src_addr: [u8; 6] = 6;
+    /// Get the protocol of the payload, e.g. `RTE_ETHER_TYPE_IPV4`.
+    ether_type, set_ether_type: u16 = 12;
+}
+
+header! {
+    /// A view of an IPv4 header without options.
+    Ipv4Hdr, Ipv4HdrMut, 20;
+    /// Get the version and the header length in 32-bit words.
+    version_ihl, set_version_ihl: u8 = 0;
+    /// Get the type of service.
+    type_of_service, set_type_of_service: u8 = 1;
+    /// Get the length of the packet, including the header.
+    total_length, set_total_length: u16 = 2;
+    /// Get the identification.
+    packet_id, set_packet_id: u16 = 4;
+    /// Get the flags and the fragment offset.
+    fragment_offset, set_fragment_offset: u16 = 6;
+    /// Get the time to live.
+    time_to_live, set_time_to_live: u8 = 8;
+    /// Get the protocol of the payload, e.g. `IPPROTO_UDP`.
+    next_proto_id, set_next_proto_id: u8 = 9;
+    /// Get the header checksum.
+    hdr_checksum, set_hdr_checksum: u16 = 10;
+    /// Get the source address.
+    src_addr, set_src_addr: Ipv4Addr = 12;
+    /// Get the destination address.
+    dst_addr, set_dst_addr: Ipv4Addr = 16;
+}
+
+impl Ipv4Hdr<'_> {
+    /// Get the length of the header including options in bytes.
+    #[inline]
+    #[must_use]
+    pub fn header_len(&self) -> usize {
+        usize::from(self.version_ihl() & 0xf).wrapping_mul(4)
+    }
+}
+
+impl Ipv4HdrMut<'_> {
+    /// Compute the header checksum and fill it in.
+    #[inline]
+    pub fn update_checksum(&mut self) {
+        self.set_hdr_checksum(0);
+        self.set_hdr_checksum(cksum_fold(cksum_add(0, self.buf)));
+    }
+}
+
+header! {
+    /// A view of an IPv6 header without extension headers.
+    Ipv6Hdr, Ipv6HdrMut, 40;
+    /// Get the version, traffic class and flow label.
+    vtc_flow, set_vtc_flow: u32 = 0;
+    /// Get the length of the payload, including extension headers.
+    payload_len, set_payload_len: u16 = 4;
+    /// Get the protocol of the next header, e.g. `IPPROTO_UDP`.
+    proto, set_proto: u8 = 6;
+    /// Get the hop limit.
+    hop_limits, set_hop_limits: u8 = 7;
+    /// Get the source address.
+    src_addr, set_src_addr: Ipv6Addr = 8;
+    /// Get the destination address.
+    dst_addr, set_dst_addr: Ipv6Addr = 24;
+}
+
+header! {
+    /// A view of a UDP header.
+    UdpHdr, UdpHdrMut, 8;
+    /// Get the source port.
+    src_port, set_src_port: u16 = 0;
+    /// Get the destination port.
+    dst_port, set_dst_port: u16 = 2;
+    /// Get the length of the datagram, including the header.
+    dgram_len, set_dgram_len: u16 = 4;
+    /// Get the checksum of the datagram.
+    dgram_cksum, set_dgram_cksum: u16 = 6;
+}
+
+header! {
+    /// A view of a TCP header without options.
+    TcpHdr, TcpHdrMut, 20;
+    /// Get the source port.
+    src_port, set_src_port: u16 = 0;
+    /// Get the destination port.
+    dst_port, set_dst_port: u16 = 2;
+    /// Get the sequence number.
+    sent_seq, set_sent_seq: u32 = 4;
+    /// Get the acknowledgement number.
+    recv_ack, set_recv_ack: u32 = 8;
+    /// Get the data offset in the upper 4 bits, in 32-bit words.
+    data_off, set_data_off: u8 = 12;
+    /// Get the flags, e.g. SYN and ACK.
+    tcp_flags, set_tcp_flags: u8 = 13;
+    /// Get the receive window.
+    rx_win, set_rx_win: u16 = 14;
+    /// Get the checksum.
+    cksum, set_cksum: u16 = 16;
+    /// Get the urgent pointer.
+    tcp_urp, set_tcp_urp: u16 = 18;
+}
+
+impl TcpHdr<'_> {
+    /// Get the length of the header including options in bytes.
+    #[inline]
+    #[must_use]
+    pub fn header_len(&self) -> usize {
+        usize::from(self.data_off().wrapping_shr(4)).wrapping_mul(4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EtherHdr, EtherHdrMut, Ipv4Hdr, Ipv4HdrMut, TcpHdr, TcpHdrMut};
+    use crate::Error;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test() {
+        let mut buf = [0_u8; 54];
+        let (l2, rest) = buf.split_at_mut(EtherHdr::LEN);
+        let (l3, l4) = rest.split_at_mut(Ipv4Hdr::LEN);
+
+        let mut ether = EtherHdrMut::new(l2).unwrap();
+        ether.set_dst_addr([1, 2, 3, 4, 5, 6]);
+        ether.set_ether_type(0x0800);
+        assert_eq!(ether.as_ref().dst_addr(), [1, 2, 3, 4, 5, 6]);
+
+        let mut ip = Ipv4HdrMut::new(l3).unwrap();
+        ip.set_version_ihl(0x45);
+        ip.set_total_length(40);
+        ip.set_time_to_live(64);
+        ip.set_next_proto_id(6);
+        ip.set_src_addr(Ipv4Addr::new(192, 168, 0, 1));
+        ip.set_dst_addr(Ipv4Addr::new(192, 168, 0, 2));
+        ip.update_checksum();
+        assert_eq!(ip.hdr_checksum(), 0xf97c);
+        assert_eq!(ip.as_ref().header_len(), 20);
+
+        let mut tcp = TcpHdrMut::new(l4).unwrap();
+        tcp.set_src_port(8080);
+        tcp.set_sent_seq(0x0102_0304);
+        tcp.set_data_off(5 << 4);
+
+        assert_eq!(buf.get(12..14), Some(&[8, 0][..]));
+        assert_eq!(buf.get(26..30), Some(&[192, 168, 0, 1][..]));
+        assert_eq!(buf.get(34..36), Some(&[0x1f, 0x90][..]));
+        assert_eq!(buf.get(38..42), Some(&[1, 2, 3, 4][..]));
+        let tcp = TcpHdr::new(buf.get(34..).unwrap()).unwrap();
+        assert_eq!(tcp.header_len(), 20);
+        assert_eq!(tcp.src_port(), 8080);
+        assert_eq!(
+            TcpHdr::new(buf.get(40..).unwrap()).unwrap_err(),
+            Error::OutOfRange
+        );
+    }
+}
@@ -1,4 +1,8 @@
 //! async-dpdk is a wrapper of DPDK in safe Rust.
+//!
+//! It targets DPDK 21.11. Definitions not exported by `dpdk_sys`, i.e. those of the libraries out
+//! of its bindings and of some inline functions, are mirrored from the headers of that release in
+//! the modules using them.
 
 #![deny(
     // The following are allowed by default lints according to
@@ -110,19 +114,46 @@
 pub use dpdk_sys::{eth_foreach_dev, lcore_foreach, lcore_foreach_worker};
 
 pub mod alloc;
+pub mod bpf;
+pub mod crypto;
+pub mod dispatch;
+pub mod dump;
 pub mod eal;
+pub mod eth_dev;
+pub mod ether;
+pub mod eventdev;
+pub mod exception;
+pub mod flow;
+pub mod forwarder;
+#[cfg(feature = "harness")]
+pub mod harness;
+pub mod hash;
+pub mod headers;
 pub mod lcore;
+pub mod lpm;
 pub mod mbuf;
 pub mod mempool;
+pub mod memzone;
+pub mod meter;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod net_dev;
 pub mod packet;
+pub mod reorder;
+pub mod ring;
+pub mod timer;
+pub mod timestamp;
 
 mod agent;
+mod capture;
 mod errno;
-mod eth_dev;
+mod gso;
 mod proto;
+mod service;
+mod shaper;
 #[cfg(test)]
 mod test_utils;
+mod trace;
 
 pub use errno::*;
 pub use proto::*;
@@ -0,0 +1,374 @@
+//! Longest prefix match (LPM) tables of IPv4 and IPv6 addresses based on `rte_lpm` and `rte_lpm6`,
+//! which map prefixes to next hops in constant time. For more information, please refer to
+//! [`LPM library document`].
+//!
+//! # Examples
+//!
+//! ```
+//! # use async_dpdk::lpm::Lpm;
+//! # use std::net::IpAddr;
+//!
+//! # let _ = async_dpdk::eal::Config::new().enter();
+//! let mut lpm = Lpm::create("routes", 1024, 256).unwrap();
+//! lpm.add("10.0.0.0".parse().unwrap(), 8, 1).unwrap();
+//! lpm.add("10.1.0.0".parse().unwrap(), 16, 2).unwrap();
+//! assert_eq!(lpm.lookup("10.1.2.3".parse().unwrap()), Some(2));
+//! assert_eq!(lpm.lookup("10.2.3.4".parse().unwrap()), Some(1));
+//! assert_eq!(lpm.lookup("192.168.0.1".parse().unwrap()), None);
+//! ```
+//!
+//! [`LPM library document`]: https://doc.dpdk.org/guides/prog_guide/lpm_lib.html
+
+#![allow(non_camel_case_types)]
+
+use crate::{lcore, Error, Result};
+use std::{
+    ffi::CString,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::raw::{c_char, c_int, c_uint},
+    ptr::{self, NonNull},
+};
+
+/// Next hops of IPv4 rules are 24 bits.
+const RTE_LPM_NEXT_HOP_MASK: u32 = 0x00ff_ffff;
+
+/// Next hops of IPv6 rules are 21 bits.
+const RTE_LPM6_NEXT_HOP_MASK: u32 = 0x001f_ffff;
+
+/// Flag of valid entries.
+const RTE_LPM_LOOKUP_SUCCESS: u32 = 0x0100_0000;
+
+/// Flags of valid entries which refer to a tbl8 group.
+const RTE_LPM_VALID_EXT_ENTRY_BITMASK: u32 = 0x0300_0000;
+
+/// Number of entries in tbl24.
+const RTE_LPM_TBL24_NUM_ENTRIES: usize = 1 << 24;
+
+/// Number of entries in a tbl8 group.
+const RTE_LPM_TBL8_GROUP_NUM_ENTRIES: usize = 256;
+
+/// Configuration of IPv4 and IPv6 LPM tables, which share the layout.
+#[repr(C)]
+struct rte_lpm_config {
+    /// Maximum number of rules.
+    max_rules: u32,
+    /// Number of tbl8 groups, which hold the rules longer than 24 bits.
+    number_tbl8s: u32,
+    /// Unused.
+    flags: c_int,
+}
+
+/// An IPv4 LPM table, which starts with tbl24 followed by a pointer to tbl8.
+#[repr(C)]
+struct rte_lpm {
+    /// Zero-sized private field.
+    _private: [u8; 0],
+}
+
+/// Opaque type of IPv6 LPM tables.
+#[repr(C)]
+struct rte_lpm6 {
+    /// Zero-sized private field.
+    _private: [u8; 0],
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Create an IPv4 LPM table.
+    fn rte_lpm_create(
+        name: *const c_char,
+        socket_id: c_int,
+        config: *const rte_lpm_config,
+    ) -> *mut rte_lpm;
+
+    /// Free an IPv4 LPM table.
+    fn rte_lpm_free(lpm: *mut rte_lpm);
+
+    /// Add an IPv4 rule, or update its next hop if it exists.
+    fn rte_lpm_add(lpm: *mut rte_lpm, ip: u32, depth: u8, next_hop: u32) -> c_int;
+
+    /// Delete an IPv4 rule.
+    fn rte_lpm_delete(lpm: *mut rte_lpm, ip: u32, depth: u8) -> c_int;
+
+    /// Create an IPv6 LPM table.
+    fn rte_lpm6_create(
+        name: *const c_char,
+        socket_id: c_int,
+        config: *const rte_lpm_config,
+    ) -> *mut rte_lpm6;
+
+    /// Free an IPv6 LPM table.
+    fn rte_lpm6_free(lpm: *mut rte_lpm6);
+
+    /// Add an IPv6 rule, or update its next hop if it exists.
+    fn rte_lpm6_add(lpm: *mut rte_lpm6, ip: *const u8, depth: u8, next_hop: u32) -> c_int;
+
+    /// Delete an IPv6 rule.
+    fn rte_lpm6_delete(lpm: *mut rte_lpm6, ip: *const u8, depth: u8) -> c_int;
+
+    /// Look up an IPv6 address.
+    fn rte_lpm6_lookup(lpm: *const rte_lpm6, ip: *const u8, next_hop: *mut u32) -> c_int;
+
+    /// Look up several IPv6 addresses, storing their next hops or -1 in `next_hops`.
+    fn rte_lpm6_lookup_bulk_func(
+        lpm: *const rte_lpm6,
+        ips: *mut [u8; 16],
+        next_hops: *mut i32,
+        n: c_uint,
+    ) -> c_int;
+}
+
+/// A longest prefix match table from IPv4 and IPv6 prefixes to next hops, which is a safe wrapper
+/// of `rte_lpm` and `rte_lpm6`.
+///
+/// Next hops are user-defined IDs, e.g. indices of routes, of up to 24 bits for IPv4 rules and 21
+/// bits for IPv6 ones.
+#[derive(Debug)]
+pub struct Lpm {
+    /// A pointer to `rte_lpm`.
+    v4: NonNull<rte_lpm>,
+    /// A pointer to `rte_lpm6`.
+    v6: NonNull<rte_lpm6>,
+}
+
+// SAFETY: LPM tables can be accessed from any thread
+#[allow(unsafe_code)]
+unsafe impl Send for Lpm {}
+
+// SAFETY: rules are only modified through `&mut Lpm`
+#[allow(unsafe_code)]
+unsafe impl Sync for Lpm {}
+
+impl Lpm {
+    /// Create an LPM table named `name`, holding up to `max_rules` rules of each IP version, and
+    /// `number_tbl8s` groups of rules longer than 24 bits.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `name` contains a nul byte.
+    /// - `max_rules` or `number_tbl8s` is 0.
+    /// - No appropriate memory area left.
+    /// - An LPM table with the same name already exists.
+    #[inline]
+    pub fn create(name: &str, max_rules: u32, number_tbl8s: u32) -> Result<Self> {
+        let name = CString::new(name).map_err(Error::from)?;
+        let config = rte_lpm_config {
+            max_rules,
+            number_tbl8s,
+            flags: 0,
+        };
+        let socket_id = lcore::socket_id();
+        // SAFETY: pointer checked later
+        #[allow(unsafe_code)]
+        let v4 = unsafe { rte_lpm_create(name.as_ptr(), socket_id, ptr::addr_of!(config)) };
+        let v4 = NonNull::new(v4).ok_or_else(|| Error::from_errno("rte_lpm_create"))?;
+        // SAFETY: pointer checked later
+        #[allow(unsafe_code)]
+        let v6 = unsafe { rte_lpm6_create(name.as_ptr(), socket_id, ptr::addr_of!(config)) };
+        let Some(v6) = NonNull::new(v6) else {
+            let err = Error::from_errno("rte_lpm6_create");
+            // SAFETY: *rte_lpm checked
+            #[allow(unsafe_code)]
+            unsafe {
+                rte_lpm_free(v4.as_ptr());
+            }
+            return Err(err);
+        };
+        Ok(Self { v4, v6 })
+    }
+
+    /// Add a rule from `ip`/`depth` to `next_hop`, or update its next hop if it exists.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `depth` is 0 or longer than the address, or `next_hop` is too large.
+    /// - `Error::NoSpace`: the rules or the tbl8 groups are exhausted.
+    #[inline]
+    pub fn add(&mut self, ip: IpAddr, depth: u8, next_hop: u32) -> Result<()> {
+        // SAFETY: the tables are checked upon `create`, and the arguments are checked by DPDK
+        #[allow(unsafe_code)]
+        let errno = match ip {
+            IpAddr::V4(ip) => {
+                if next_hop & !RTE_LPM_NEXT_HOP_MASK != 0 {
+                    return Err(Error::InvalidArg);
+                }
+                unsafe { rte_lpm_add(self.v4.as_ptr(), ip.into(), depth, next_hop) }
+            }
+            IpAddr::V6(ip) => {
+                if next_hop & !RTE_LPM6_NEXT_HOP_MASK != 0 {
+                    return Err(Error::InvalidArg);
+                }
+                let ip = ip.octets();
+                unsafe { rte_lpm6_add(self.v6.as_ptr(), ip.as_ptr(), depth, next_hop) }
+            }
+        };
+        Error::from_ret(errno, "rte_lpm6_add")
+    }
+
+    /// Delete the rule of `ip`/`depth`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `depth` is 0 or longer than the address.
+    /// - `Error::NoEntry`: the rule doesn't exist.
+    #[inline]
+    pub fn delete(&mut self, ip: IpAddr, depth: u8) -> Result<()> {
+        // SAFETY: the tables are checked upon `create`, and the arguments are checked by DPDK
+        #[allow(unsafe_code)]
+        let errno = match ip {
+            IpAddr::V4(ip) => unsafe { rte_lpm_delete(self.v4.as_ptr(), ip.into(), depth) },
+            IpAddr::V6(ip) => {
+                let ip = ip.octets();
+                unsafe { rte_lpm6_delete(self.v6.as_ptr(), ip.as_ptr(), depth) }
+            }
+        };
+        Error::from_ret(errno, "rte_lpm6_delete")
+    }
+
+    /// Look up the next hop of the longest rule matching `ip`.
+    #[inline]
+    #[must_use]
+    pub fn lookup(&self, ip: IpAddr) -> Option<u32> {
+        match ip {
+            IpAddr::V4(ip) => self.lookup_v4(ip),
+            IpAddr::V6(ip) => self.lookup_v6(ip),
+        }
+    }
+
+    /// Look up the next hops of several addresses, in the order of `ips`.
+    #[inline]
+    #[must_use]
+    pub fn lookup_bulk(&self, ips: &[IpAddr]) -> Vec<Option<u32>> {
+        let mut next_hops: Vec<_> = ips
+            .iter()
+            .map(|&ip| match ip {
+                IpAddr::V4(ip) => self.lookup_v4(ip),
+                IpAddr::V6(_) => None,
+            })
+            .collect();
+        let (idx, mut v6): (Vec<_>, Vec<_>) = ips
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &ip)| match ip {
+                IpAddr::V4(_) => None,
+                IpAddr::V6(ip) => Some((i, ip.octets())),
+            })
+            .unzip();
+        if v6.is_empty() {
+            return next_hops;
+        }
+        let mut hops = vec![-1_i32; v6.len()];
+        #[allow(clippy::cast_possible_truncation)] // no more addresses than memory
+        let n = v6.len() as c_uint;
+        // SAFETY: *rte_lpm6 checked upon `create`, and `hops` is as long as `v6`
+        #[allow(unsafe_code)]
+        let _errno = unsafe {
+            rte_lpm6_lookup_bulk_func(self.v6.as_ptr(), v6.as_mut_ptr(), hops.as_mut_ptr(), n)
+        };
+        for (i, hop) in idx.into_iter().zip(hops) {
+            if let Some(next_hop) = next_hops.get_mut(i) {
+                *next_hop = u32::try_from(hop).ok();
+            }
+        }
+        next_hops
+    }
+
+    /// Look up an IPv4 address. This is `rte_lpm_lookup`, which is inline.
+    fn lookup_v4(&self, ip: Ipv4Addr) -> Option<u32> {
+        let ip = u32::from(ip);
+        let tbl24 = self.v4.as_ptr().cast::<u32>();
+        // SAFETY: tbl24 covers the first 24 bits of all addresses, and a valid entry with the
+        // extended flag refers to a tbl8 group covering the last 8 bits.
+        #[allow(unsafe_code, clippy::cast_ptr_alignment)] // tbl24 ends on a cache line
+        let entry = unsafe {
+            let mut entry = tbl24.add((ip >> 8) as usize).read_volatile();
+            if entry & RTE_LPM_VALID_EXT_ENTRY_BITMASK == RTE_LPM_VALID_EXT_ENTRY_BITMASK {
+                let tbl8 = tbl24
+                    .add(RTE_LPM_TBL24_NUM_ENTRIES)
+                    .cast::<*const u32>()
+                    .read();
+                let group = (entry & RTE_LPM_NEXT_HOP_MASK) as usize;
+                let idx = group
+                    .wrapping_mul(RTE_LPM_TBL8_GROUP_NUM_ENTRIES)
+                    .wrapping_add((ip & 0xff) as usize);
+                entry = tbl8.add(idx).read_volatile();
+            }
+            entry
+        };
+        (entry & RTE_LPM_LOOKUP_SUCCESS != 0).then_some(entry & RTE_LPM_NEXT_HOP_MASK)
+    }
+
+    /// Look up an IPv6 address.
+    fn lookup_v6(&self, ip: Ipv6Addr) -> Option<u32> {
+        let ip = ip.octets();
+        let mut next_hop = 0;
+        // SAFETY: *rte_lpm6 checked upon `create`
+        #[allow(unsafe_code)]
+        let errno =
+            unsafe { rte_lpm6_lookup(self.v6.as_ptr(), ip.as_ptr(), ptr::addr_of_mut!(next_hop)) };
+        (errno == 0).then_some(next_hop)
+    }
+}
+
+impl Drop for Lpm {
+    #[inline]
+    fn drop(&mut self) {
+        // SAFETY: the tables are checked upon `create`
+        #[allow(unsafe_code)]
+        unsafe {
+            rte_lpm_free(self.v4.as_ptr());
+            rte_lpm6_free(self.v6.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lpm;
+    use crate::{test_utils, Error};
+    use std::net::IpAddr;
+
+    #[test]
+    fn test() {
+        test_utils::dpdk_setup();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let mut lpm = Lpm::create("lpm", 64, 16).unwrap();
+        lpm.add(ip("10.0.0.0"), 8, 1).unwrap();
+        lpm.add(ip("10.1.0.0"), 16, 2).unwrap();
+        lpm.add(ip("10.1.2.16"), 28, 3).unwrap();
+        lpm.add(ip("fd00::"), 8, 4).unwrap();
+        lpm.add(ip("fd00:1::"), 32, 5).unwrap();
+        assert!(matches!(
+            lpm.add(ip("10.0.0.0"), 8, 1 << 24).unwrap_err(),
+            Error::InvalidArg
+        ));
+
+        assert_eq!(lpm.lookup(ip("10.2.0.1")), Some(1));
+        assert_eq!(lpm.lookup(ip("10.1.2.3")), Some(2));
+        assert_eq!(lpm.lookup(ip("10.1.2.17")), Some(3));
+        assert_eq!(lpm.lookup(ip("11.0.0.1")), None);
+        assert_eq!(lpm.lookup(ip("fd00:1::1")), Some(5));
+        assert_eq!(lpm.lookup(ip("fd00:2::1")), Some(4));
+        assert_eq!(lpm.lookup(ip("fe80::1")), None);
+
+        let ips = [
+            ip("10.1.2.17"),
+            ip("fd00:2::1"),
+            ip("11.0.0.1"),
+            ip("fe80::1"),
+        ];
+        assert_eq!(lpm.lookup_bulk(&ips), [Some(3), Some(4), None, None]);
+
+        lpm.delete(ip("10.1.2.16"), 28).unwrap();
+        assert_eq!(lpm.lookup(ip("10.1.2.17")), Some(2));
+        lpm.delete(ip("fd00::"), 8).unwrap();
+        assert_eq!(lpm.lookup(ip("fd00:2::1")), None);
+        assert!(lpm.delete(ip("fd00::"), 8).is_err());
+    }
+}
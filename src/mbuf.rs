@@ -2,21 +2,31 @@
 //! by the DPDK application to store message buffers. The message buffers are stored in a mempool,
 //! using the Mempool Library.
 
-use crate::mempool::{MempoolObj, PktMempool};
+use crate::mempool::{self, MempoolObj, PktMempool};
 use crate::{Error, Result};
+use bytes::{buf::UninitSlice, Buf, BufMut};
 use dpdk_sys::{
-    rte_mbuf, rte_mbuf_buf_addr, rte_pktmbuf_adj, rte_pktmbuf_alloc, rte_pktmbuf_alloc_bulk,
-    rte_pktmbuf_append, rte_pktmbuf_chain, rte_pktmbuf_clone, rte_pktmbuf_free,
-    rte_pktmbuf_headroom, rte_pktmbuf_prepend, rte_pktmbuf_tailroom, rte_pktmbuf_trim,
+    rte_eal_iova_mode, rte_iova_mode_RTE_IOVA_VA, rte_mbuf, rte_mbuf_buf_addr, rte_mbuf_dynfield,
+    rte_mbuf_dynfield_lookup, rte_mbuf_dynfield_register, rte_mbuf_dynflag,
+    rte_mbuf_dynflag_lookup, rte_mbuf_dynflag_register, rte_mbuf_ext_shared_info,
+    rte_mbuf_from_indirect, rte_mempool, rte_mempool_cache, rte_mempool_objhdr, rte_mempool_put,
+    rte_pktmbuf_adj, rte_pktmbuf_alloc, rte_pktmbuf_alloc_bulk, rte_pktmbuf_append,
+    rte_pktmbuf_chain, rte_pktmbuf_clone, rte_pktmbuf_free, rte_pktmbuf_headroom,
+    rte_pktmbuf_linearize, rte_pktmbuf_pool_private, rte_pktmbuf_prepend,
+    rte_pktmbuf_reset_headroom, rte_pktmbuf_tailroom, rte_pktmbuf_trim, RTE_MAX_LCORE,
+    RTE_MBUF_F_EXTERNAL, RTE_MBUF_F_INDIRECT, RTE_MBUF_F_RX_FDIR_ID, RTE_MBUF_F_RX_RSS_HASH,
+    RTE_PKTMBUF_POOL_F_PINNED_EXT_BUF,
 };
 use std::{
+    ffi::CString,
     marker::PhantomData,
-    mem::{self, size_of, ManuallyDrop},
+    mem::{self, align_of, size_of, ManuallyDrop},
     ops::{Deref, DerefMut},
-    os::raw::c_void,
+    os::raw::{c_char, c_void},
     ptr::{self, NonNull},
     result::Result as StdResult,
     slice,
+    sync::atomic::{AtomicU16, Ordering},
 };
 
 /// `Mbuf` is used to hold network packets.
@@ -46,10 +56,13 @@ pub struct Mbuf {
     mb: NonNull<rte_mbuf>,
 }
 
+/// A slot to be filled with a received `Mbuf` by `RxQueue::rx_burst`.
+pub type MbufSlot = Option<Mbuf>;
+
 impl MempoolObj for Mbuf {
     #[inline]
     fn into_raw(self) -> *mut c_void {
-        self.mb.as_ptr().cast()
+        ManuallyDrop::new(self).mb.as_ptr().cast()
     }
     #[inline]
     fn from_raw(ptr: *mut c_void) -> Result<Self> {
@@ -73,27 +86,29 @@ impl Mbuf {
     ///
     /// # Errors
     ///
-    /// This function returns an error if allocation fails.
+    /// This function returns `Error::MempoolExhausted` if no `Mbuf` is left in `mp`.
     #[inline]
     pub fn new(mp: &PktMempool) -> Result<Self> {
-        // SAFETY: check pointer in `Self::new_with_ptr`. Fields in `rte_mbuf` are set
-        // to default values. DPDK allocated objects are aligned to the cacheline size.
+        // SAFETY: pointer checked. Fields in `rte_mbuf` are set to default values. DPDK allocated
+        // objects are aligned to the cacheline size.
         let ptr = unsafe { rte_pktmbuf_alloc(mp.as_ptr()) };
-        Self::new_with_ptr(ptr)
+        NonNull::new(ptr).map_or_else(|| Err(mempool::exhausted()), |mb| Ok(Self { mb }))
     }
 
     /// Allocate a bulk of `Mbuf`s from the given `PktMempool`.
     ///
     /// # Errors
     ///
-    /// This function returns an error if allocation fails.
+    /// This function returns `Error::MempoolExhausted` if less than `n` `Mbuf`s are left in `mp`.
     #[inline]
     pub fn new_bulk(mp: &PktMempool, n: u32) -> Result<Vec<Self>> {
         let mut ptrs = (0..n).map(|_| ptr::null_mut()).collect::<Vec<_>>();
         // SAFETY: invalid allocation result in a negative errno, which is checked later.
         // In this function, fields are set to default values.
         let errno = unsafe { rte_pktmbuf_alloc_bulk(mp.as_ptr(), ptrs.as_mut_ptr(), n) };
-        Error::from_ret(errno)?;
+        if errno < 0 {
+            return Err(mempool::exhausted());
+        }
         let mut v = vec![];
         for ptr in ptrs {
             v.push(Self::new_with_ptr(ptr)?);
@@ -101,6 +116,74 @@ impl Mbuf {
         Ok(v)
     }
 
+    /// Create an `Mbuf` holding `data` without copying it. `data` is attached to the `Mbuf` as
+    /// an external buffer, and dropped once the `Mbuf` and all its clones are freed, e.g. after
+    /// being transmitted.
+    ///
+    /// Data longer than `u16::MAX` bytes is held by a chain of segments. Devices access `data` by
+    /// its virtual address, so EAL must run in IOVA as VA mode.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::MempoolExhausted`: not enough `Mbuf`s are left in `mp`.
+    /// - `Error::InvalidArg`: EAL is not in IOVA as VA mode.
+    /// - `Error::Overflow`: `data` needs more segments than an `Mbuf` chain holds.
+    #[inline]
+    pub fn from_shared<T>(mp: &PktMempool, data: T) -> Result<Self>
+    where
+        T: AsRef<[u8]> + Send + 'static,
+    {
+        if !extbuf_supported() {
+            return Err(Error::InvalidArg);
+        }
+        let len = data.as_ref().len();
+        let n_segs = len.div_ceil(EXTBUF_MAX_LEN).max(1);
+        #[allow(clippy::map_err_ignore)]
+        let refcnt = u16::try_from(n_segs).map_err(|_| Error::Overflow)?;
+        let mut segs = Self::new_bulk(mp, u32::from(refcnt))?;
+
+        // The box is released by `free_extbuf` when the last segment is freed.
+        let ext = Box::into_raw(Box::new(ExtBuf {
+            shinfo: rte_mbuf_ext_shared_info {
+                free_cb: Some(free_extbuf::<T>),
+                fcb_opaque: ptr::null_mut(),
+                refcnt,
+            },
+            data,
+        }));
+        // SAFETY: `ext` is valid, and the data it owns never moves
+        let (shinfo, addr) = unsafe {
+            (*ext).shinfo.fcb_opaque = ext.cast();
+            (
+                ptr::addr_of_mut!((*ext).shinfo),
+                (*ext).data.as_ref().as_ptr(),
+            )
+        };
+        for (i, seg) in segs.iter_mut().enumerate() {
+            let off = i.wrapping_mul(EXTBUF_MAX_LEN);
+            #[allow(clippy::cast_possible_truncation)] // at most `EXTBUF_MAX_LEN`
+            let seg_len = len.saturating_sub(off).min(EXTBUF_MAX_LEN) as u16;
+            // SAFETY: `seg_len` bytes from `off` are in `data`, which is kept until all segments
+            // release `shinfo`. In IOVA as VA mode, the IOVA of the data is its address.
+            unsafe {
+                let buf = addr.add(off).cast_mut();
+                attach_extbuf(seg.as_ptr(), buf.cast(), buf as u64, seg_len, shinfo);
+            }
+        }
+        let mut head: Option<Self> = None;
+        for mut seg in segs {
+            // The whole external buffer is data.
+            let _data = seg.append(seg.tailroom())?;
+            match head.as_mut() {
+                Some(head) => head.chain_mbuf(seg).map_err(|(err, _)| err)?,
+                None => head = Some(seg),
+            }
+        }
+        head.ok_or(Error::InvalidArg)
+    }
+
     /// Get the data length of an `Mbuf`.
     #[inline]
     #[must_use]
@@ -141,6 +224,101 @@ impl Mbuf {
         unsafe { rte_pktmbuf_tailroom(self.as_ptr()) as usize }
     }
 
+    /// Get the id of the port that an `Mbuf` is received from.
+    #[inline]
+    #[must_use]
+    pub fn port(&self) -> u16 {
+        // SAFETY: the *rte_mbuf pointer is checked at initialization and never changes
+        unsafe { (*self.as_ptr()).port }
+    }
+
+    /// Get the offload flags of an `Mbuf`.
+    #[inline]
+    pub(crate) fn ol_flags(&self) -> u64 {
+        // SAFETY: the *rte_mbuf pointer is checked at initialization and never changes
+        unsafe { (*self.as_ptr()).ol_flags }
+    }
+
+    /// Get the packet type of an `Mbuf`, i.e. the `RTE_PTYPE_*` flags.
+    #[inline]
+    pub(crate) fn packet_type(&self) -> u32 {
+        // SAFETY: the *rte_mbuf pointer is checked at initialization and never changes
+        unsafe { (*self.as_ptr()).packet_type_union.packet_type }
+    }
+
+    /// Get the value attached by a flow rule with `Action::Mark`, if any.
+    #[inline]
+    #[must_use]
+    pub fn flow_mark(&self) -> Option<u32> {
+        // SAFETY: the *rte_mbuf pointer is checked at initialization and never changes
+        unsafe {
+            let m = &*self.as_ptr();
+            (self.ol_flags() & u64::from(RTE_MBUF_F_RX_FDIR_ID) != 0)
+                .then_some(m.hash_union.hash.fdir.hi)
+        }
+    }
+
+    /// Get the RSS hash computed by the device on receipt, if any.
+    #[inline]
+    #[must_use]
+    pub fn rss_hash(&self) -> Option<u32> {
+        // SAFETY: the *rte_mbuf pointer is checked at initialization and never changes
+        unsafe {
+            let m = &*self.as_ptr();
+            (self.ol_flags() & u64::from(RTE_MBUF_F_RX_RSS_HASH) != 0)
+                .then_some(m.hash_union.hash.rss)
+        }
+    }
+
+    /// Get the value of a dynamic field.
+    #[inline]
+    #[must_use]
+    pub fn dynfield<T: DynFieldValue>(&self, field: DynField<T>) -> T {
+        // SAFETY: the field is registered with the size of `T` at `offset` of every `rte_mbuf`,
+        // and any bytes in it make a valid `T`
+        unsafe {
+            self.as_ptr()
+                .cast::<u8>()
+                .add(field.offset)
+                .cast::<T>()
+                .read_unaligned()
+        }
+    }
+
+    /// Set the value of a dynamic field.
+    #[inline]
+    pub fn set_dynfield<T: DynFieldValue>(&mut self, field: DynField<T>, value: T) {
+        // SAFETY: the field is registered with the size of `T` at `offset` of every `rte_mbuf`
+        unsafe {
+            self.as_ptr()
+                .cast::<u8>()
+                .add(field.offset)
+                .cast::<T>()
+                .write_unaligned(value);
+        }
+    }
+
+    /// Test if a dynamic flag is set.
+    #[inline]
+    #[must_use]
+    pub fn dynflag(&self, flag: DynFlag) -> bool {
+        self.ol_flags() & flag.mask() != 0
+    }
+
+    /// Set or clear a dynamic flag.
+    #[inline]
+    pub fn set_dynflag(&mut self, flag: DynFlag, on: bool) {
+        // SAFETY: the *rte_mbuf pointer is checked at initialization and never changes
+        unsafe {
+            let m = &mut *self.as_ptr();
+            if on {
+                m.ol_flags |= flag.mask();
+            } else {
+                m.ol_flags &= !flag.mask();
+            }
+        }
+    }
+
     /// Create a "clone" of the given packet `Mbuf`.
     ///
     /// New `Mbuf`s are allocated from the given `PktMempool`, and populated with the same content
@@ -156,6 +334,213 @@ impl Mbuf {
         Self::new_with_ptr(ptr)
     }
 
+    /// Get the reference count of the first segment, i.e. the number of `Mbuf`s sharing it.
+    #[inline]
+    #[must_use]
+    pub fn refcnt(&self) -> u16 {
+        refcnt(self.as_ptr()).load(Ordering::Relaxed)
+    }
+
+    /// Add `v` to the reference count of every segment.
+    ///
+    /// # Safety
+    ///
+    /// Each increment should be paired with a decrement, or a drop of an `Mbuf` which holds the
+    /// references, e.g. one built with `Mbuf::from_raw` on the same `rte_mbuf`. Otherwise the
+    /// segments are leaked, or freed while still in use.
+    #[inline]
+    pub unsafe fn refcnt_update(&mut self, v: i16) {
+        let mut seg = self.as_ptr();
+        while !seg.is_null() {
+            let _refcnt = refcnt_add(refcnt(seg), v);
+            // SAFETY: segments in the chain are valid
+            seg = unsafe { (*seg).next };
+        }
+    }
+
+    /// Test if the first segment is indirect, i.e. attached to the data of another `Mbuf`.
+    #[inline]
+    #[must_use]
+    pub fn is_indirect(&self) -> bool {
+        self.ol_flags() & RTE_MBUF_F_INDIRECT != 0
+    }
+
+    /// Attach this `Mbuf` to the data of the first segment of `m`, so that it becomes an
+    /// indirect `Mbuf` sharing the data without copying, until it's detached or dropped.
+    ///
+    /// # Errors
+    ///
+    /// This function returns `Error::InvalidArg` if this `Mbuf` is chained, shared, indirect or
+    /// holding an external buffer.
+    #[inline]
+    pub fn attach(&mut self, m: &Mbuf) -> Result<()> {
+        let mi = self.as_ptr();
+        // SAFETY: the *rte_mbuf pointers are checked at initialization
+        unsafe {
+            if !(*mi).next.is_null()
+                || refcnt(mi).load(Ordering::Relaxed) != 1
+                || self.ol_flags() & (RTE_MBUF_F_INDIRECT | RTE_MBUF_F_EXTERNAL) != 0
+            {
+                return Err(Error::InvalidArg);
+            }
+            attach_seg(mi, m.as_ptr());
+        }
+        Ok(())
+    }
+
+    /// Detach this `Mbuf` from the data it's attached to, restoring its own empty buffer. Nothing
+    /// happens if it's not indirect.
+    #[inline]
+    pub fn detach(&mut self) {
+        if self.is_indirect() {
+            // SAFETY: the *rte_mbuf pointer is checked, and the `Mbuf` is indirect
+            unsafe {
+                detach_seg(self.as_ptr());
+                (*self.as_ptr()).pkt_len = 0;
+            }
+        }
+    }
+
+    /// Split the packet into two at `offset` without copying the data. The first one holds bytes
+    /// in `[0, offset)`, and the second one holds the rest.
+    ///
+    /// If `offset` falls in the middle of a segment, an indirect `Mbuf` attached to it is
+    /// allocated from `mp` to hold the rest of its data.
+    ///
+    /// # Errors
+    ///
+    /// The `Mbuf` is returned along with the error, and possible reasons are:
+    ///
+    /// - `Error::InvalidArg`: `offset` is 0 or not less than the packet length.
+    /// - `Error::MempoolExhausted`: no `Mbuf` is left in `mp`.
+    #[inline]
+    pub fn split_at(
+        self,
+        offset: usize,
+        mp: &PktMempool,
+    ) -> StdResult<(Self, Self), (Error, Self)> {
+        let pkt_len = self.pkt_len();
+        if offset == 0 || offset >= pkt_len {
+            return Err((Error::InvalidArg, self));
+        }
+        let head = self.as_ptr();
+        // SAFETY: segments are valid in the chain, which holds more than `offset` bytes
+        unsafe {
+            let (mut prev, mut seg, mut pos, mut nb_segs) = (ptr::null_mut(), head, 0_usize, 0_u16);
+            loop {
+                let end = pos.wrapping_add((*seg).data_len.into());
+                if end > offset || (*seg).next.is_null() {
+                    break;
+                }
+                (prev, seg, pos) = (seg, (*seg).next, end);
+                nb_segs = nb_segs.wrapping_add(1);
+            }
+            let off = offset.wrapping_sub(pos);
+            let tail = if off == 0 && !prev.is_null() {
+                (*prev).next = ptr::null_mut();
+                seg
+            } else {
+                let Ok(mi) = Mbuf::new(mp) else {
+                    return Err((mempool::exhausted(), self));
+                };
+                let mi = ManuallyDrop::new(mi).as_ptr();
+                attach_seg(mi, seg);
+                #[allow(clippy::cast_possible_truncation)] // less than `data_len`
+                let off = off as u16;
+                (*mi).data_off = (*mi).data_off.wrapping_add(off);
+                (*mi).data_len = (*mi).data_len.wrapping_sub(off);
+                (*mi).next = (*seg).next;
+                (*seg).next = ptr::null_mut();
+                (*seg).data_len = off;
+                nb_segs = nb_segs.wrapping_add(1);
+                mi
+            };
+            (*tail).nb_segs = (*head)
+                .nb_segs
+                .wrapping_sub(nb_segs)
+                .wrapping_add(u16::from(off != 0));
+            #[allow(clippy::cast_possible_truncation)] // less than `pkt_len`
+            {
+                (*tail).pkt_len = pkt_len.wrapping_sub(offset) as u32;
+                (*head).pkt_len = offset as u32;
+            }
+            inherit_hdr(tail, head);
+            (*head).nb_segs = nb_segs;
+            Ok((
+                self,
+                Mbuf {
+                    mb: NonNull::new_unchecked(tail),
+                },
+            ))
+        }
+    }
+
+    /// Move the data of all segments into the first one, which frees the other segments, so that
+    /// the data is contiguous in `data_slice`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns `Error::NoBuf` if the tailroom of the first segment is not enough,
+    /// without modifying the `Mbuf`.
+    #[inline]
+    pub fn linearize(&mut self) -> Result<()> {
+        if self.pkt_len().saturating_sub(self.data_len()) > self.tailroom() {
+            return Err(Error::NoBuf);
+        }
+        // SAFETY: the *rte_mbuf pointer is checked at initialization and never changes
+        let errno = unsafe { rte_pktmbuf_linearize(self.as_ptr()) };
+        if errno == 0 {
+            Ok(())
+        } else {
+            Err(Error::NoBuf)
+        }
+    }
+
+    /// Copy the data starting at `offset` of the packet into `buf` across segments, returning
+    /// the number of bytes copied, which is less than the length of `buf` if the packet ends.
+    #[inline]
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let mut skip = offset;
+        let mut len: usize = 0;
+        for seg in self.iter() {
+            let Some(data) = seg.data_slice().get(skip..) else {
+                skip = skip.wrapping_sub(seg.data_len());
+                continue;
+            };
+            skip = 0;
+            let Some(dst) = buf.get_mut(len..) else {
+                break;
+            };
+            let sz = data.len().min(dst.len());
+            if let (Some(dst), Some(src)) = (dst.get_mut(..sz), data.get(..sz)) {
+                dst.copy_from_slice(src);
+            }
+            len = len.wrapping_add(sz);
+            if len == buf.len() {
+                break;
+            }
+        }
+        len
+    }
+
+    /// Copy `len` bytes starting at `offset` of the packet into a `Vec` across segments.
+    ///
+    /// # Errors
+    ///
+    /// This function returns `Error::OutOfRange` if the packet ends within the range.
+    #[inline]
+    pub fn copy_to_vec(&self, offset: usize, len: usize) -> Result<Vec<u8>> {
+        if offset
+            .checked_add(len)
+            .is_none_or(|end| end > self.pkt_len())
+        {
+            return Err(Error::OutOfRange);
+        }
+        let mut v = vec![0; len];
+        let _len = self.read_at(offset, &mut v);
+        Ok(v)
+    }
+
     /// Test if mbuf data is contiguous (i.e. with only one segment).
     #[inline]
     #[must_use]
@@ -267,32 +652,43 @@ impl Mbuf {
         }
     }
 
-    /// Pop the mbuf at the front.
+    /// Pop the mbuf at the front, which is freed, returning the rest of the chain if any.
     #[inline]
     #[must_use]
     pub fn pop_mbuf(self) -> Option<Mbuf> {
-        let prev = self.as_ptr();
-        // SAFETY: `prev` checked in `Mbuf::new`
-        let ptr = unsafe {
-            let ptr = (*prev).next;
-            (*prev).next = ptr::null_mut();
-            ptr
-        };
-        let m = Mbuf::new_with_ptr(ptr).ok()?;
-        // SAFETY: `ptr` and `prev` checked
+        self.take_first().1
+    }
+
+    /// Detach the first segment from the chain, returning it as a single-segment `Mbuf` along
+    /// with the rest of the chain if any.
+    ///
+    /// The rest inherits the packet metadata of the first segment, e.g. the packet type and the
+    /// offload flags, while the reference count of each segment is kept.
+    #[inline]
+    #[must_use]
+    pub fn take_first(self) -> (Mbuf, Option<Mbuf>) {
+        let head = self.as_ptr();
+        // SAFETY: `head` checked in `Mbuf::new`, and the next segment is valid if not null
         unsafe {
-            (*ptr).refcnt = (*prev).refcnt;
-            (*ptr).nb_segs = (*prev).nb_segs.saturating_sub(1);
-            (*ptr).ol_flags = (*prev).ol_flags;
-            (*ptr).pkt_len = (*prev).pkt_len.saturating_sub(u32::from((*prev).data_len));
-            (*ptr)
-                .packet_type_union
-                .clone_from(&(*prev).packet_type_union);
-            (*ptr)
-                .tx_offload_union
-                .clone_from(&(*prev).tx_offload_union);
+            let rest = NonNull::new((*head).next).map(|mb| {
+                let next = mb.as_ptr();
+                (*next).nb_segs = (*head).nb_segs.saturating_sub(1);
+                (*next).pkt_len = (*head).pkt_len.saturating_sub((*head).data_len.into());
+                inherit_hdr(next, head);
+                Mbuf { mb }
+            });
+            (*head).next = ptr::null_mut();
+            (*head).nb_segs = 1;
+            (*head).pkt_len = (*head).data_len.into();
+            (self, rest)
         }
-        Some(m)
+    }
+
+    /// Consume the `Mbuf`, iterating over its segments as single-segment `Mbuf`s.
+    #[inline]
+    #[must_use]
+    pub fn into_segments(self) -> IntoSegments {
+        IntoSegments { rest: Some(self) }
     }
 
     /// Chain an mbuf to another, thereby creating a segmented packet.
@@ -307,7 +703,7 @@ impl Mbuf {
     pub fn chain_mbuf(&mut self, tail: Mbuf) -> StdResult<(), (Error, Mbuf)> {
         // SAFETY: *rte_mbuf pointers checked
         let errno = unsafe { rte_pktmbuf_chain(self.as_ptr(), tail.as_ptr()) };
-        if let Err(err) = Error::from_ret(errno) {
+        if let Err(err) = Error::from_ret(errno, "rte_pktmbuf_chain") {
             return Err((err, tail));
         }
         #[allow(clippy::mem_forget)] // deallocated with `head`
@@ -335,6 +731,13 @@ impl Mbuf {
         }
     }
 
+    /// Get a cursor reading from the start of the `Mbuf` and writing to its end, across segments.
+    #[inline]
+    #[must_use]
+    pub fn cursor(&mut self) -> MbufCursor<'_> {
+        MbufCursor::new(self)
+    }
+
     /// Get pointer to `rte_mbuf`.
     pub(crate) fn as_ptr(&self) -> *mut rte_mbuf {
         self.mb.as_ptr()
@@ -345,6 +748,239 @@ impl Mbuf {
 #[allow(unsafe_code)]
 unsafe impl Send for Mbuf {}
 
+/// The reference count of `m` as an atomic.
+fn refcnt<'a>(m: *mut rte_mbuf) -> &'a AtomicU16 {
+    // SAFETY: `refcnt` is a properly aligned u16 in a live mbuf, also accessed atomically by DPDK
+    #[allow(unsafe_code)]
+    unsafe {
+        &*ptr::addr_of_mut!((*m).refcnt).cast::<AtomicU16>()
+    }
+}
+
+/// The reference count of the external buffer `shinfo` describes as an atomic.
+fn ext_refcnt<'a>(shinfo: *mut rte_mbuf_ext_shared_info) -> &'a AtomicU16 {
+    // SAFETY: `refcnt` is a properly aligned u16 in a live shared info, and accessed atomically by
+    // DPDK too
+    #[allow(unsafe_code)]
+    unsafe {
+        &*ptr::addr_of_mut!((*shinfo).refcnt).cast::<AtomicU16>()
+    }
+}
+
+/// Add `v` to the reference count `cnt` and return the new value. Nobody else holds a reference
+/// of 1, so it's set without an atomic read-modify-write. This is `rte_mbuf_refcnt_update` and
+/// `rte_mbuf_ext_refcnt_update`.
+fn refcnt_add(cnt: &AtomicU16, v: i16) -> u16 {
+    let v = v.cast_unsigned();
+    if cnt.load(Ordering::Relaxed) == 1 {
+        let v = v.wrapping_add(1);
+        cnt.store(v, Ordering::Relaxed);
+        return v;
+    }
+    cnt.fetch_add(v, Ordering::AcqRel).wrapping_add(v)
+}
+
+/// The private data of the pktmbuf pool `mp`, which follows the mempool and its per-lcore caches.
+/// This is `rte_mempool_get_priv`.
+///
+/// # Safety
+///
+/// `mp` should be a valid pktmbuf pool.
+#[allow(unsafe_code)]
+unsafe fn pool_private(mp: *mut rte_mempool) -> *const rte_pktmbuf_pool_private {
+    let mut off = size_of::<rte_mempool>();
+    // SAFETY: ensured by the caller
+    if unsafe { (*mp).cache_size } != 0 {
+        off = off.wrapping_add(size_of::<rte_mempool_cache>().wrapping_mul(RTE_MAX_LCORE as usize));
+    }
+    mp.cast::<u8>()
+        .wrapping_add(off)
+        .cast::<rte_pktmbuf_pool_private>()
+}
+
+/// Attach `mi` to the data of `m`, which is either direct, indirect or external, so that `mi`
+/// becomes indirect or external as well. This is `rte_pktmbuf_attach`.
+///
+/// # Safety
+///
+/// Both should be valid, and `mi` should be direct, unchained and not shared.
+#[allow(unsafe_code)]
+unsafe fn attach_seg(mi: *mut rte_mbuf, m: *mut rte_mbuf) {
+    // SAFETY: ensured by the caller
+    unsafe {
+        if (*m).ol_flags & RTE_MBUF_F_EXTERNAL != 0 {
+            let _refcnt = refcnt_add(ext_refcnt((*m).shinfo), 1);
+            (*mi).ol_flags = (*m).ol_flags;
+            (*mi).shinfo = (*m).shinfo;
+        } else {
+            // The data is embedded in the direct mbuf, which is `m` itself if it's direct.
+            let _refcnt = refcnt_add(refcnt(rte_mbuf_from_indirect(m)), 1);
+            (*mi).priv_size = (*m).priv_size;
+            (*mi).ol_flags = (*m).ol_flags | RTE_MBUF_F_INDIRECT;
+        }
+        (*mi).port = (*m).port;
+        (*mi).vlan_tci = (*m).vlan_tci;
+        (*mi).vlan_tci_outer = (*m).vlan_tci_outer;
+        (*mi).tx_offload_union.clone_from(&(*m).tx_offload_union);
+        (*mi).hash_union.clone_from(&(*m).hash_union);
+        (*mi).packet_type_union.clone_from(&(*m).packet_type_union);
+        (*mi).dynfield1 = (*m).dynfield1;
+        (*mi).data_off = (*m).data_off;
+        (*mi).data_len = (*m).data_len;
+        (*mi).buf_iova = (*m).buf_iova;
+        (*mi).buf_addr = (*m).buf_addr;
+        (*mi).buf_len = (*m).buf_len;
+        (*mi).next = ptr::null_mut();
+        (*mi).pkt_len = (*mi).data_len.into();
+        (*mi).nb_segs = 1;
+    }
+}
+
+/// Attach `buf_len` bytes at `buf_addr` to `m` as an external buffer shared through `shinfo`,
+/// leaving no data in it. This is `rte_pktmbuf_attach_extbuf`.
+///
+/// # Safety
+///
+/// `m` should be a valid direct mbuf, and the buffer should be valid until `shinfo` is released.
+#[allow(unsafe_code)]
+unsafe fn attach_extbuf(
+    m: *mut rte_mbuf,
+    buf_addr: *mut c_void,
+    buf_iova: u64,
+    buf_len: u16,
+    shinfo: *mut rte_mbuf_ext_shared_info,
+) {
+    // SAFETY: ensured by the caller
+    unsafe {
+        (*m).buf_addr = buf_addr;
+        (*m).buf_iova = buf_iova;
+        (*m).buf_len = buf_len;
+        (*m).data_len = 0;
+        (*m).data_off = 0;
+        (*m).ol_flags |= RTE_MBUF_F_EXTERNAL;
+        (*m).shinfo = shinfo;
+    }
+}
+
+/// Release the data `m` is attached to, and restore its own empty buffer. This is
+/// `rte_pktmbuf_detach`.
+///
+/// # Safety
+///
+/// `m` should be a valid indirect or external mbuf.
+#[allow(unsafe_code)]
+unsafe fn detach_seg(m: *mut rte_mbuf) {
+    // SAFETY: ensured by the caller
+    unsafe {
+        let pool = pool_private((*m).pool);
+        if (*m).ol_flags & RTE_MBUF_F_EXTERNAL != 0 {
+            // A pinned external buffer is never detached.
+            if (*pool).flags & RTE_PKTMBUF_POOL_F_PINNED_EXT_BUF != 0 {
+                return;
+            }
+            let shinfo = (*m).shinfo;
+            if refcnt_add(ext_refcnt(shinfo), -1) == 0 {
+                if let Some(free_cb) = (*shinfo).free_cb {
+                    free_cb((*m).buf_addr, (*shinfo).fcb_opaque);
+                }
+            }
+        } else {
+            let md = rte_mbuf_from_indirect(m);
+            if refcnt_add(refcnt(md), -1) == 0 {
+                (*md).next = ptr::null_mut();
+                (*md).nb_segs = 1;
+                refcnt(md).store(1, Ordering::Relaxed);
+                rte_mempool_put((*md).pool, md.cast());
+            }
+        }
+        let priv_size = (*pool).mbuf_priv_size;
+        let mbuf_size = size_of::<rte_mbuf>().wrapping_add(priv_size.into());
+        // The IOVA of the mbuf is in the mempool header right before it.
+        let hdr = m.cast::<rte_mempool_objhdr>().wrapping_sub(1);
+        (*m).priv_size = priv_size;
+        (*m).buf_addr = m.cast::<u8>().add(mbuf_size).cast();
+        (*m).buf_iova = (*hdr).iova.wrapping_add(mbuf_size as u64);
+        (*m).buf_len = (*pool).mbuf_data_room_size;
+        rte_pktmbuf_reset_headroom(m);
+        (*m).data_len = 0;
+        (*m).ol_flags = 0;
+    }
+}
+
+/// Copy the packet metadata of `head` to `seg`, which becomes the first segment of a chain.
+///
+/// # Safety
+///
+/// Both `seg` and `head` should be valid.
+#[allow(unsafe_code)]
+unsafe fn inherit_hdr(seg: *mut rte_mbuf, head: *const rte_mbuf) {
+    // Whether a segment is indirect or external is its own.
+    let own = RTE_MBUF_F_INDIRECT | RTE_MBUF_F_EXTERNAL;
+    // SAFETY: ensured by the caller
+    unsafe {
+        (*seg).port = (*head).port;
+        (*seg).ol_flags = ((*head).ol_flags & !own) | ((*seg).ol_flags & own);
+        (*seg)
+            .packet_type_union
+            .clone_from(&(*head).packet_type_union);
+        (*seg)
+            .tx_offload_union
+            .clone_from(&(*head).tx_offload_union);
+    }
+}
+
+/// Maximum length of the external buffer attached to a segment.
+const EXTBUF_MAX_LEN: usize = u16::MAX as usize;
+
+/// Whether data can be attached to `Mbuf`s by `Mbuf::from_shared`, i.e. EAL is in IOVA as VA
+/// mode.
+#[allow(unsafe_code)]
+pub(crate) fn extbuf_supported() -> bool {
+    // SAFETY: ffi
+    unsafe { rte_eal_iova_mode() == rte_iova_mode_RTE_IOVA_VA }
+}
+
+/// Data attached to `Mbuf`s as an external buffer, along with its shared info.
+struct ExtBuf<T> {
+    /// The shared info counting the `Mbuf`s attached.
+    shinfo: rte_mbuf_ext_shared_info,
+    /// The data, dropped when no `Mbuf` is attached.
+    data: T,
+}
+
+/// Free an `ExtBuf<T>` released by all `Mbuf`s, which is called by DPDK.
+#[allow(unsafe_code)]
+unsafe extern "C" fn free_extbuf<T>(_addr: *mut c_void, opaque: *mut c_void) {
+    // SAFETY: `opaque` is the leaked box of the `ExtBuf<T>`, which is no longer referenced
+    drop(unsafe { Box::from_raw(opaque.cast::<ExtBuf<T>>()) });
+}
+
+/// An iterator consuming an `Mbuf` segment by segment, returned by `Mbuf::into_segments`.
+#[derive(Debug)]
+pub struct IntoSegments {
+    /// Segments not iterated.
+    rest: Option<Mbuf>,
+}
+
+impl Iterator for IntoSegments {
+    type Item = Mbuf;
+
+    #[inline]
+    fn next(&mut self) -> Option<Mbuf> {
+        let (first, rest) = self.rest.take()?.take_first();
+        self.rest = rest;
+        Some(first)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.rest.as_ref().map_or(0, |m| m.num_segs() as usize);
+        (n, Some(n))
+    }
+}
+
+impl ExactSizeIterator for IntoSegments {}
+
 impl Drop for Mbuf {
     #[inline]
     fn drop(&mut self) {
@@ -469,11 +1105,417 @@ impl DerefMut for MbufRefMut<'_> {
     }
 }
 
+/// A cursor over a possibly chained `Mbuf`, which reads its data from the start with `Buf`, and
+/// appends data to its end with `BufMut`, crossing segment boundaries transparently.
+///
+/// Writes are limited to the tailroom of the last segment, unless more segments are chained with
+/// `reserve`. Reserved segments left empty are freed when the cursor is dropped.
+///
+/// # Examples
+///
+/// ```
+/// # use async_dpdk::{mbuf::Mbuf, mempool::{Mempool, PktMempool}};
+/// use bytes::{Buf, BufMut};
+///
+/// # let _ = async_dpdk::eal::Config::new().enter();
+/// let mp = PktMempool::create("cursor", 16).unwrap();
+/// let mut mbuf = Mbuf::new(&mp).unwrap();
+/// let mut cursor = mbuf.cursor();
+/// cursor.reserve(&mp, 4096).unwrap();
+/// cursor.put_bytes(1, 4096);
+/// assert_eq!(cursor.get_u32(), 0x0101_0101);
+/// drop(cursor);
+/// assert_eq!(mbuf.pkt_len(), 4096);
+/// ```
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug)]
+pub struct MbufCursor<'a> {
+    /// The `Mbuf` read and written.
+    mb: &'a mut Mbuf,
+    /// The segment being read.
+    rseg: *mut rte_mbuf,
+    /// Offset of the next byte to read in `rseg`.
+    roff: usize,
+    /// Number of bytes read.
+    consumed: usize,
+    /// The segment being written.
+    wseg: *mut rte_mbuf,
+}
+
+#[allow(unsafe_code)]
+impl<'a> MbufCursor<'a> {
+    /// Get a cursor at the start of `mb`, writing to its last segment.
+    fn new(mb: &'a mut Mbuf) -> Self {
+        let head = mb.as_ptr();
+        let mut wseg = head;
+        // SAFETY: segments are valid in the chain
+        unsafe {
+            while !(*wseg).next.is_null() {
+                wseg = (*wseg).next;
+            }
+        }
+        Self {
+            mb,
+            rseg: head,
+            roff: 0,
+            consumed: 0,
+            wseg,
+        }
+    }
+
+    /// Chain segments allocated from `mp` to the `Mbuf`, until at least `len` bytes can be
+    /// written.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::MempoolExhausted`: no `Mbuf` is left in `mp`.
+    /// - `Error::Overflow`: the segment limit of a chain is exceeded.
+    #[inline]
+    pub fn reserve(&mut self, mp: &PktMempool, len: usize) -> Result<()> {
+        let mut room = self.remaining_mut();
+        while room < len {
+            let seg = Mbuf::new(mp)?;
+            room = room.saturating_add(seg.tailroom());
+            self.mb.chain_mbuf(seg).map_err(|(e, _)| e)?;
+        }
+        Ok(())
+    }
+
+    /// Get the start of the data in segment `m`.
+    ///
+    /// # Safety
+    ///
+    /// `m` should be a valid segment.
+    unsafe fn seg_data(m: *mut rte_mbuf) -> *mut u8 {
+        // SAFETY: ensured by the caller
+        unsafe {
+            rte_mbuf_buf_addr(m, (*m).pool)
+                .cast::<u8>()
+                .add((*m).data_off.into())
+        }
+    }
+}
+
+#[allow(unsafe_code)]
+impl Buf for MbufCursor<'_> {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.mb.pkt_len().saturating_sub(self.consumed)
+    }
+
+    #[inline]
+    fn chunk(&self) -> &[u8] {
+        let (mut seg, mut off) = (self.rseg, self.roff);
+        // SAFETY: segments are valid in the chain, and `off` is within `data_len`
+        unsafe {
+            while off >= (*seg).data_len.into() && !(*seg).next.is_null() {
+                seg = (*seg).next;
+                off = 0;
+            }
+            let len = usize::from((*seg).data_len).saturating_sub(off);
+            slice::from_raw_parts(Self::seg_data(seg).add(off), len)
+        }
+    }
+
+    #[inline]
+    fn advance(&mut self, mut cnt: usize) {
+        while cnt > 0 {
+            // SAFETY: segments are valid in the chain
+            let (len, next) = unsafe { (usize::from((*self.rseg).data_len), (*self.rseg).next) };
+            if self.roff >= len {
+                if next.is_null() {
+                    break;
+                }
+                self.rseg = next;
+                self.roff = 0;
+                continue;
+            }
+            let n = cnt.min(len.wrapping_sub(self.roff));
+            self.roff = self.roff.wrapping_add(n);
+            self.consumed = self.consumed.wrapping_add(n);
+            cnt = cnt.wrapping_sub(n);
+        }
+    }
+}
+
+#[allow(unsafe_code)]
+// SAFETY: `chunk_mut` returns the tailroom of a segment, which is claimed in `advance_mut`
+unsafe impl BufMut for MbufCursor<'_> {
+    #[inline]
+    fn remaining_mut(&self) -> usize {
+        let mut seg = self.wseg;
+        let mut room = 0_usize;
+        // SAFETY: segments are valid in the chain
+        unsafe {
+            while !seg.is_null() {
+                room = room.saturating_add(rte_pktmbuf_tailroom(seg).into());
+                seg = (*seg).next;
+            }
+        }
+        room
+    }
+
+    #[inline]
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        let cnt = u16::try_from(cnt).unwrap_or(u16::MAX);
+        // SAFETY: `wseg` is the segment returned by `chunk_mut`, where `cnt` bytes are written
+        unsafe {
+            let head = self.mb.as_ptr();
+            (*self.wseg).data_len = (*self.wseg).data_len.wrapping_add(cnt);
+            (*head).pkt_len = (*head).pkt_len.wrapping_add(cnt.into());
+        }
+    }
+
+    #[inline]
+    fn chunk_mut(&mut self) -> &mut UninitSlice {
+        // SAFETY: segments are valid in the chain, and the tailroom is owned by the `Mbuf`
+        unsafe {
+            while rte_pktmbuf_tailroom(self.wseg) == 0 && !(*self.wseg).next.is_null() {
+                self.wseg = (*self.wseg).next;
+            }
+            let seg = self.wseg;
+            let data = Self::seg_data(seg).add((*seg).data_len.into());
+            UninitSlice::from_raw_parts_mut(data, rte_pktmbuf_tailroom(seg).into())
+        }
+    }
+}
+
+impl Drop for MbufCursor<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        let head = self.mb.as_ptr();
+        // SAFETY: segments are valid in the chain, and the empty ones after the last segment
+        // with data are unlinked before freed
+        #[allow(unsafe_code)]
+        unsafe {
+            let (mut last, mut seg, mut nb_segs) = (head, (*head).next, 1_u16);
+            let mut i = 1_u16;
+            while !seg.is_null() {
+                i = i.wrapping_add(1);
+                if (*seg).data_len > 0 {
+                    (last, nb_segs) = (seg, i);
+                }
+                seg = (*seg).next;
+            }
+            let rest = (*last).next;
+            if !rest.is_null() {
+                (*last).next = ptr::null_mut();
+                (*head).nb_segs = nb_segs;
+                (*rest).nb_segs = i.wrapping_sub(nb_segs);
+                rte_pktmbuf_free(rest);
+            }
+        }
+    }
+}
+
+/// Length of the names of dynamic fields and flags, including the terminating nul byte.
+const RTE_MBUF_DYN_NAMESIZE: usize = 64;
+
+/// Convert `name` to the name of a dynamic field or flag.
+fn dyn_name(name: &str) -> Result<[c_char; RTE_MBUF_DYN_NAMESIZE]> {
+    let name = CString::new(name).map_err(Error::from)?;
+    let bytes = name.as_bytes_with_nul();
+    if bytes.len() > RTE_MBUF_DYN_NAMESIZE {
+        return Err(Error::InvalidArg);
+    }
+    let mut buf = [0; RTE_MBUF_DYN_NAMESIZE];
+    for (c, &b) in buf.iter_mut().zip(bytes) {
+        #[allow(clippy::cast_possible_wrap)] // C string bytes
+        {
+            *c = b as c_char;
+        }
+    }
+    Ok(buf)
+}
+
+/// Types of values held by a `DynField`, i.e. integers and arrays of them.
+///
+/// Any bytes make a valid value of them, since a field is shared by all users of the same name,
+/// which may write anything in it. The trait is sealed.
+pub trait DynFieldValue: Copy + sealed::Sealed {}
+
+/// Seal of `DynFieldValue`.
+mod sealed {
+    /// Implemented by the types of `DynFieldValue` only.
+    pub trait Sealed {}
+}
+
+/// Implement `DynFieldValue` for the given types.
+macro_rules! impl_dynfield_value {
+    ($($t:ty),*) => {
+        $(
+            impl sealed::Sealed for $t {}
+            impl DynFieldValue for $t {}
+        )*
+    };
+}
+
+impl_dynfield_value!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+impl<T: DynFieldValue, const N: usize> sealed::Sealed for [T; N] {}
+
+impl<T: DynFieldValue, const N: usize> DynFieldValue for [T; N] {}
+
+/// A dynamic field in the spare space of every `Mbuf`, holding a value of `T`.
+///
+/// Dynamic fields are registered by name, so that all users of the same name share the same
+/// field, including secondary processes. Registered fields are never released.
+#[derive(Debug)]
+pub struct DynField<T> {
+    /// Offset of the field in `rte_mbuf`.
+    offset: usize,
+    /// The type of the field.
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for DynField<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for DynField<T> {}
+
+#[allow(unsafe_code)]
+impl<T: DynFieldValue> DynField<T> {
+    /// Register a dynamic field named `name` holding a `T`, or get the field if it's registered
+    /// with the same size and alignment.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::Exists`: a field named `name` is registered with a different size or alignment.
+    /// - `Error::InvalidArg`: `name` is too long, or `T` is zero-sized.
+    /// - `Error::NoMem`: no space left in `rte_mbuf`.
+    #[inline]
+    pub fn register(name: &str) -> Result<Self> {
+        if size_of::<T>() == 0 {
+            return Err(Error::InvalidArg);
+        }
+        let params = rte_mbuf_dynfield {
+            name: dyn_name(name)?,
+            size: size_of::<T>(),
+            align: align_of::<T>(),
+            flags: 0,
+        };
+        // SAFETY: `params` is valid during the call, and the return value is checked
+        let offset = unsafe { rte_mbuf_dynfield_register(ptr::addr_of!(params)) };
+        let offset =
+            usize::try_from(offset).map_err(|_| Error::from_errno("rte_mbuf_dynfield_register"))?;
+        Ok(Self {
+            offset,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Look up a dynamic field named `name` registered by this process or another one.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::NoEntry`: no field is named `name`.
+    /// - `Error::InvalidArg`: the field is registered with a different size or alignment.
+    #[inline]
+    pub fn lookup(name: &str) -> Result<Self> {
+        let name = CString::new(name).map_err(Error::from)?;
+        let mut params = rte_mbuf_dynfield {
+            name: [0; RTE_MBUF_DYN_NAMESIZE],
+            size: 0,
+            align: 0,
+            flags: 0,
+        };
+        // SAFETY: `params` is valid during the call, and the return value is checked
+        let offset = unsafe { rte_mbuf_dynfield_lookup(name.as_ptr(), ptr::addr_of_mut!(params)) };
+        let offset =
+            usize::try_from(offset).map_err(|_| Error::from_errno("rte_mbuf_dynfield_lookup"))?;
+        if params.size != size_of::<T>() || params.align != align_of::<T>() {
+            return Err(Error::InvalidArg);
+        }
+        Ok(Self {
+            offset,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Get the offset of the field in `rte_mbuf`.
+    #[inline]
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+/// A dynamic flag in the offload flags of every `Mbuf`.
+///
+/// Dynamic flags are registered by name, so that all users of the same name share the same flag,
+/// including secondary processes. Registered flags are never released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DynFlag {
+    /// The bit number in `ol_flags`.
+    bit: u32,
+}
+
+#[allow(unsafe_code)]
+impl DynFlag {
+    /// Register a dynamic flag named `name`, or get the flag if it's registered.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `name` is too long.
+    /// - `Error::NoMem`: no bit left in the offload flags.
+    #[inline]
+    pub fn register(name: &str) -> Result<Self> {
+        let params = rte_mbuf_dynflag {
+            name: dyn_name(name)?,
+            flags: 0,
+        };
+        // SAFETY: `params` is valid during the call, and the return value is checked
+        let bit = unsafe { rte_mbuf_dynflag_register(ptr::addr_of!(params)) };
+        let bit = u32::try_from(bit).map_err(|_| Error::from_errno("rte_mbuf_dynflag_register"))?;
+        Ok(Self { bit })
+    }
+
+    /// Look up a dynamic flag named `name` registered by this process or another one.
+    ///
+    /// # Errors
+    ///
+    /// This function returns `Error::NoEntry` if no flag is named `name`.
+    #[inline]
+    pub fn lookup(name: &str) -> Result<Self> {
+        let name = CString::new(name).map_err(Error::from)?;
+        // SAFETY: the return value is checked, and the parameters are not needed
+        let bit = unsafe { rte_mbuf_dynflag_lookup(name.as_ptr(), ptr::null_mut()) };
+        let bit = u32::try_from(bit).map_err(|_| Error::from_errno("rte_mbuf_dynflag_lookup"))?;
+        Ok(Self { bit })
+    }
+
+    /// Get the bit number of the flag in the offload flags.
+    #[inline]
+    #[must_use]
+    pub fn bit(&self) -> u32 {
+        self.bit
+    }
+
+    /// Get the mask of the flag in the offload flags.
+    fn mask(self) -> u64 {
+        1_u64.wrapping_shl(self.bit)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::mbuf::Mbuf;
+    use crate::mbuf::{self, DynField, DynFlag, Mbuf};
     use crate::mempool::{Mempool, PktMempool};
     use crate::test_utils;
+    use bytes::{Buf, BufMut};
+    use std::sync::Arc;
 
     #[test]
     fn test() {
@@ -542,4 +1584,226 @@ mod tests {
             assert_eq!(m.data_len(), 5);
         }
     }
+
+    #[test]
+    fn test_split() {
+        test_utils::dpdk_setup();
+
+        let mp = PktMempool::create("test_split", 16).unwrap();
+        let mut mbuf = Mbuf::new(&mp).unwrap();
+        mbuf.append(10)
+            .unwrap()
+            .copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let mut seg = Mbuf::new(&mp).unwrap();
+        seg.append(4).unwrap().copy_from_slice(&[10, 11, 12, 13]);
+        mbuf.chain_mbuf(seg).unwrap();
+
+        // Attach and detach.
+        let mut mi = Mbuf::new(&mp).unwrap();
+        mi.attach(&mbuf).unwrap();
+        assert!(mi.is_indirect());
+        assert_eq!(mbuf.refcnt(), 2);
+        assert_eq!(mi.data_slice(), mbuf.data_slice());
+        assert_eq!(mi.attach(&mbuf).unwrap_err(), crate::Error::InvalidArg);
+        mi.detach();
+        assert!(!mi.is_indirect());
+        assert_eq!(mi.data_len(), 0);
+        assert_eq!(mbuf.refcnt(), 1);
+
+        // Split at a segment boundary.
+        let mbuf = mbuf.split_at(0, &mp).unwrap_err().1;
+        let (head, tail) = mbuf.split_at(10, &mp).unwrap();
+        assert_eq!((head.num_segs(), head.pkt_len()), (1, 10));
+        assert_eq!((tail.num_segs(), tail.pkt_len()), (1, 4));
+        assert_eq!(tail.data_slice(), &[10, 11, 12, 13]);
+
+        // Split in the middle of a segment.
+        let (first, second) = head.split_at(3, &mp).unwrap();
+        assert_eq!(first.data_slice(), &[0, 1, 2]);
+        assert_eq!(second.data_slice(), &[3, 4, 5, 6, 7, 8, 9]);
+        assert!(second.is_indirect());
+        assert_eq!(first.refcnt(), 2);
+        drop(first);
+        assert_eq!(second.data_slice(), &[3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_segments() {
+        test_utils::dpdk_setup();
+
+        let mp = PktMempool::create("test_segments", 16).unwrap();
+        let mut mbuf = Mbuf::new(&mp).unwrap();
+        mbuf.append(1).unwrap().copy_from_slice(&[1]);
+        for part in [&[2, 3][..], &[4, 5, 6]] {
+            let mut seg = Mbuf::new(&mp).unwrap();
+            seg.append(part.len()).unwrap().copy_from_slice(part);
+            mbuf.chain_mbuf(seg).unwrap();
+        }
+
+        // Clones are indirect segments sharing the data, each holding a reference.
+        let clone = mbuf.clone(&mp).unwrap();
+        assert!(mbuf.iter().all(|seg| seg.refcnt() == 2));
+        let segs = clone.into_segments();
+        assert_eq!(segs.len(), 3);
+        let segs = segs.collect::<Vec<_>>();
+        for (seg, len) in segs.iter().zip(1..) {
+            assert!(seg.is_indirect());
+            assert_eq!(seg.refcnt(), 1);
+            assert_eq!(
+                (seg.num_segs(), seg.pkt_len(), seg.data_len()),
+                (1, len, len)
+            );
+        }
+        assert_eq!(segs.get(2).unwrap().data_slice(), &[4, 5, 6]);
+        drop(segs);
+        assert!(mbuf.iter().all(|seg| seg.refcnt() == 1));
+
+        // The reference count of the first segment is not inherited.
+        let mut mi = Mbuf::new(&mp).unwrap();
+        mi.attach(&mbuf).unwrap();
+        let (first, rest) = mbuf.take_first();
+        assert_eq!(
+            (first.refcnt(), first.num_segs(), first.pkt_len()),
+            (2, 1, 1)
+        );
+        let rest = rest.unwrap();
+        assert!(!rest.is_indirect());
+        assert_eq!((rest.refcnt(), rest.num_segs(), rest.pkt_len()), (1, 2, 5));
+        drop(first);
+        assert_eq!(mi.data_slice(), &[1]);
+        let rest = rest.pop_mbuf().unwrap();
+        assert_eq!(rest.data_slice(), &[4, 5, 6]);
+        assert!(rest.pop_mbuf().is_none());
+    }
+
+    #[test]
+    fn test_copy() {
+        test_utils::dpdk_setup();
+
+        let mp = PktMempool::create("test_copy", 16).unwrap();
+        let mut mbuf = Mbuf::new(&mp).unwrap();
+        for part in [&[0, 1, 2][..], &[][..], &[3, 4, 5, 6], &[7]] {
+            let mut seg = Mbuf::new(&mp).unwrap();
+            seg.append(part.len()).unwrap().copy_from_slice(part);
+            mbuf.chain_mbuf(seg).unwrap();
+        }
+        assert_eq!(mbuf.pkt_len(), 8);
+
+        let mut buf = [0; 4];
+        assert_eq!(mbuf.read_at(2, &mut buf), 4);
+        assert_eq!(buf, [2, 3, 4, 5]);
+        assert_eq!(mbuf.read_at(6, &mut buf), 2);
+        assert_eq!(buf[..2], [6, 7]);
+        assert_eq!(mbuf.read_at(8, &mut buf), 0);
+        assert_eq!(mbuf.copy_to_vec(1, 7).unwrap(), [1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(
+            mbuf.copy_to_vec(1, 8).unwrap_err(),
+            crate::Error::OutOfRange
+        );
+
+        mbuf.linearize().unwrap();
+        assert!(mbuf.is_contiguous());
+        assert_eq!(mbuf.data_slice(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_cursor() {
+        test_utils::dpdk_setup();
+
+        let mp = PktMempool::create("test_cursor", 16).unwrap();
+        let mut mbuf = Mbuf::new(&mp).unwrap();
+        let data = (0..5000_u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        let mut cursor = mbuf.cursor();
+        assert_eq!(cursor.remaining_mut(), 2048);
+        cursor.reserve(&mp, 8000).unwrap();
+        assert!(cursor.remaining_mut() >= 8000);
+        cursor.put_slice(&data);
+        assert_eq!(cursor.remaining(), 5000);
+        let mut read = vec![0; 5000];
+        cursor.copy_to_slice(&mut read);
+        assert_eq!(read, data);
+        assert_eq!(cursor.remaining(), 0);
+        cursor.put_u16(0x1234);
+        assert_eq!(cursor.get_u16(), 0x1234);
+        drop(cursor);
+
+        // Reserved segments left empty are freed.
+        assert_eq!(mbuf.num_segs(), 3);
+        assert_eq!(mbuf.pkt_len(), 5002);
+        let mut cursor = mbuf.cursor();
+        cursor.advance(4999);
+        assert_eq!(cursor.get_u8(), data[4999]);
+    }
+
+    #[test]
+    fn test_dynfield() {
+        test_utils::dpdk_setup();
+
+        let field = DynField::<u64>::register("test_dynfield").unwrap();
+        assert_eq!(
+            DynField::<u64>::register("test_dynfield").unwrap().offset(),
+            field.offset()
+        );
+        assert_eq!(
+            DynField::<u64>::lookup("test_dynfield").unwrap().offset(),
+            field.offset()
+        );
+        assert_eq!(
+            DynField::<u16>::register("test_dynfield")
+                .unwrap_err()
+                .kind(),
+            crate::ErrorKind::Exists
+        );
+        assert_eq!(
+            DynField::<u16>::lookup("test_dynfield").unwrap_err(),
+            crate::Error::InvalidArg
+        );
+        assert_eq!(
+            DynField::<u64>::lookup("test_nofield").unwrap_err().kind(),
+            crate::ErrorKind::NoEntry
+        );
+        let flag = DynFlag::register("test_dynflag").unwrap();
+        assert_eq!(DynFlag::lookup("test_dynflag").unwrap(), flag);
+
+        let mp = PktMempool::create("test_dynfield", 10).unwrap();
+        let mut mbuf = Mbuf::new(&mp).unwrap();
+        mbuf.set_dynfield(field, 0xdead_beef);
+        assert_eq!(mbuf.dynfield(field), 0xdead_beef);
+        assert!(!mbuf.dynflag(flag));
+        mbuf.set_dynflag(flag, true);
+        assert!(mbuf.dynflag(flag));
+        mbuf.set_dynflag(flag, false);
+        assert!(!mbuf.dynflag(flag));
+    }
+
+    #[test]
+    fn test_shared() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("test_shared", 8).unwrap();
+        let data: Arc<[u8]> = (0..70_000_u32).map(|i| i as u8).collect::<Vec<_>>().into();
+        if !mbuf::extbuf_supported() {
+            assert_eq!(
+                Mbuf::from_shared(&mp, Arc::clone(&data)).unwrap_err(),
+                crate::Error::InvalidArg
+            );
+            return;
+        }
+        let m = Mbuf::from_shared(&mp, Arc::clone(&data)).unwrap();
+        assert_eq!(Arc::strong_count(&data), 2);
+        assert_eq!((m.num_segs(), m.pkt_len()), (2, 70_000));
+        assert_eq!(m.data_len(), usize::from(u16::MAX));
+        assert_eq!(m.data_slice().as_ptr(), data.as_ptr());
+        assert_eq!(m.copy_to_vec(0, 70_000).unwrap(), &data[..]);
+
+        // The data is dropped after all segments and their clones are freed.
+        let (first, rest) = m.take_first();
+        drop(first);
+        assert_eq!(Arc::strong_count(&data), 2);
+        drop(rest);
+        assert_eq!(Arc::strong_count(&data), 1);
+
+        let m = Mbuf::from_shared(&mp, Vec::new()).unwrap();
+        assert_eq!((m.num_segs(), m.pkt_len()), (1, 0));
+    }
 }
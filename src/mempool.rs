@@ -57,33 +57,74 @@
 //! # let _ = async_dpdk::eal::Config::new().enter();
 //! let mp: GenericMempool<SomePtr> = GenericMempool::create("mempool", 64).unwrap();
 //! let obj = mp.get().unwrap();
-//! mp.put(obj);
+//! // Put back when dropped, or explicitly.
+//! mp.put(obj.into_inner());
+//! ```
+//!
+//! Plain Rust structs are allocated with `PooledBox`, which puts them back on drop:
+//!
+//! ```
+//! # use async_dpdk::mempool::{GenericMempool, Mempool, PooledBox};
+//!
+//! #[derive(Default)]
+//! struct SomeType {
+//!     x: u64,
+//!     y: u64,
+//! }
+//!
+//! # let _ = async_dpdk::eal::Config::new().enter();
+//! let mp: GenericMempool<PooledBox<SomeType>> = GenericMempool::create("boxes", 64).unwrap();
+//! let mut obj = mp.get().unwrap();
+//! obj.x = 1;
+//! drop(obj);
+//! assert!(mp.is_full());
 //! ```
 
-use crate::{lcore, mbuf::Mbuf, Error, Result};
+use crate::{
+    eal::{self, ProcessType},
+    lcore,
+    mbuf::Mbuf,
+    trace::{info, trace, warn},
+    Error, Result,
+};
 use dpdk_sys::{
     rte_mempool, rte_mempool_avail_count, rte_mempool_create, rte_mempool_free, rte_mempool_get,
-    rte_mempool_get_bulk, rte_mempool_in_use_count, rte_mempool_lookup, rte_mempool_put,
-    rte_mempool_put_bulk, rte_pktmbuf_alloc, rte_pktmbuf_free, rte_pktmbuf_pool_create,
-    RTE_MBUF_DEFAULT_BUF_SIZE,
+    rte_mempool_get_bulk, rte_mempool_in_use_count, rte_mempool_lookup, rte_mempool_objhdr,
+    rte_mempool_put, rte_mempool_put_bulk, rte_pktmbuf_free, rte_pktmbuf_pool_create,
+    RTE_MBUF_DEFAULT_BUF_SIZE, RTE_MEMPOOL_CACHE_MAX_SIZE,
 };
 use lazy_static::lazy_static;
-use log::trace;
 use std::{
     collections::HashMap,
     ffi::CString,
     fmt::Debug,
     marker::PhantomData,
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut},
     os::raw::c_void,
     ptr::{self, NonNull},
-    sync::Mutex,
-    sync::{Arc, Weak},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::{Arc, Mutex, PoisonError, Weak},
+    thread,
+    time::Duration,
 };
 
 lazy_static! {
     static ref MEMPOOLS: Mutex<HashMap<usize, Weak<MpRef>>> = Mutex::default();
 }
 
+/// Get the name, the number of available objects and the number of objects in use of each
+/// mempool alive in this process.
+#[cfg(feature = "metrics")]
+pub(crate) fn mempool_usage() -> Result<Vec<(String, u32, u32)>> {
+    let mempools = MEMPOOLS.lock().map_err(Error::from)?;
+    Ok(mempools
+        .values()
+        .filter_map(Weak::upgrade)
+        .map(|mp| (mp.name(), mp.avail_count(), mp.in_use_count()))
+        .collect())
+}
+
 /// Objects allocated from a mempool.
 ///
 /// In DPDK APIs, allocated objects are represented by pointers. So implementations should take care of
@@ -104,6 +145,120 @@ pub trait MempoolObj: Sized {
 
     /// Size of the object.
     fn obj_size() -> usize;
+
+    /// Initialize the memory of an object just allocated by `GenericMempool`, before it's
+    /// converted with `from_raw`. Nothing is done by default.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` points to an object of `obj_size()` bytes, which is aligned to the cache line.
+    #[inline]
+    #[allow(unsafe_code)]
+    unsafe fn init(_ptr: *mut c_void) {}
+}
+
+/// Get the mempool `obj` is allocated from, whose pointer is in the header right before it.
+///
+/// # Safety
+///
+/// `obj` is allocated from a mempool.
+#[allow(unsafe_code)]
+unsafe fn mempool_of(obj: *mut c_void) -> *mut rte_mempool {
+    let hdr = obj.cast::<rte_mempool_objhdr>().wrapping_sub(1);
+    // SAFETY: the header is valid while the object is allocated
+    unsafe { (*hdr).mp }
+}
+
+/// A `T` allocated from a `GenericMempool<PooledBox<T>>`, which makes plain Rust structs
+/// poolable without pointer wrappers.
+///
+/// The value is initialized with `T::default()` on allocation, and dropped when the box is dropped
+/// or put back with `Mempool::put`. A dropped box is put back to its mempool, which should outlive
+/// the box.
+pub struct PooledBox<T> {
+    /// The value in the mempool.
+    ptr: NonNull<T>,
+}
+
+// SAFETY: the box owns the value
+#[allow(unsafe_code)]
+unsafe impl<T: Send> Send for PooledBox<T> {}
+
+// SAFETY: the box owns the value
+#[allow(unsafe_code)]
+unsafe impl<T: Sync> Sync for PooledBox<T> {}
+
+impl<T: Default> MempoolObj for PooledBox<T> {
+    #[inline]
+    #[allow(unsafe_code)]
+    fn into_raw(self) -> *mut c_void {
+        let ptr = ManuallyDrop::new(self).ptr.as_ptr();
+        // SAFETY: the value is valid and never used again
+        unsafe { ptr::drop_in_place(ptr) };
+        ptr.cast()
+    }
+
+    #[inline]
+    fn from_raw(ptr: *mut c_void) -> Result<Self> {
+        let ptr = NonNull::new(ptr.cast()).ok_or(Error::InvalidArg)?;
+        Ok(Self { ptr })
+    }
+
+    #[inline]
+    fn obj_size() -> usize {
+        mem::size_of::<T>()
+    }
+
+    #[inline]
+    #[allow(unsafe_code)]
+    unsafe fn init(ptr: *mut c_void) {
+        // SAFETY: the memory is large enough and aligned for `T`
+        unsafe { ptr.cast::<T>().write(T::default()) };
+    }
+}
+
+impl<T> Deref for PooledBox<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        // SAFETY: the value is valid while the box lives
+        #[allow(unsafe_code)]
+        unsafe {
+            self.ptr.as_ref()
+        }
+    }
+}
+
+impl<T> DerefMut for PooledBox<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the value is valid while the box lives
+        #[allow(unsafe_code)]
+        unsafe {
+            self.ptr.as_mut()
+        }
+    }
+}
+
+impl<T: Debug> Debug for PooledBox<T> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PooledBox").field(&**self).finish()
+    }
+}
+
+impl<T> Drop for PooledBox<T> {
+    #[inline]
+    fn drop(&mut self) {
+        let ptr = self.ptr.as_ptr();
+        // SAFETY: the value is valid, and allocated from a mempool
+        #[allow(unsafe_code)]
+        unsafe {
+            ptr::drop_in_place(ptr);
+            rte_mempool_put(mempool_of(ptr.cast()), ptr.cast());
+        }
+    }
 }
 
 /// Mempool is an allocator for fixed-sized objects and it is widely used in DPDK. For more
@@ -132,6 +287,9 @@ pub trait Mempool<T: MempoolObj>: Sized {
 
     /// Get a mempool instance using name.
     ///
+    /// Mempools created by other processes, e.g. the primary process when called from a secondary
+    /// process, are attached to and never freed by this process.
+    ///
     /// # Errors
     ///
     /// This function could returns an error if the name does not match to any mempool.
@@ -141,7 +299,7 @@ pub trait Mempool<T: MempoolObj>: Sized {
     ///
     /// # Errors
     ///
-    /// This function could returns an error if the mempool is out of memory.
+    /// This function returns `Error::MempoolExhausted` if no object is left in the mempool.
     fn get(&self) -> Result<T>;
 
     /// Deallocate an object.
@@ -160,12 +318,79 @@ pub trait Mempool<T: MempoolObj>: Sized {
     fn is_full(&self) -> bool;
 }
 
+/// An object allocated by `GenericMempool::get`, which is put back to the mempool on drop. The
+/// mempool is kept alive until then.
+pub struct PoolGuard<T: MempoolObj> {
+    /// The object, put back on drop.
+    obj: ManuallyDrop<T>,
+    /// The mempool the object is allocated from.
+    mp: Arc<MpRef>,
+}
+
+impl<T: MempoolObj> PoolGuard<T> {
+    /// Guard `obj` allocated from `mp`.
+    fn new(obj: T, mp: Arc<MpRef>) -> Self {
+        Self {
+            obj: ManuallyDrop::new(obj),
+            mp,
+        }
+    }
+
+    /// Take the object out, which is to be put back with `Mempool::put`.
+    #[inline]
+    #[must_use]
+    pub fn into_inner(self) -> T {
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never used again, and `mp` is dropped here
+        #[allow(unsafe_code)]
+        unsafe {
+            ptr::drop_in_place(ptr::addr_of_mut!(this.mp));
+            ManuallyDrop::take(&mut this.obj)
+        }
+    }
+}
+
+impl<T: MempoolObj> Deref for PoolGuard<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.obj
+    }
+}
+
+impl<T: MempoolObj> DerefMut for PoolGuard<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.obj
+    }
+}
+
+impl<T: MempoolObj + Debug> Debug for PoolGuard<T> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PoolGuard").field(&*self.obj).finish()
+    }
+}
+
+impl<T: MempoolObj> Drop for PoolGuard<T> {
+    #[inline]
+    fn drop(&mut self) {
+        // SAFETY: `obj` is never used again, and put back to the mempool it's allocated from
+        #[allow(unsafe_code)]
+        unsafe {
+            let obj = ManuallyDrop::take(&mut self.obj);
+            rte_mempool_put(self.mp.as_ptr(), obj.into_raw());
+        }
+    }
+}
+
 /// Generic `MempoolObj` allocator.
 #[allow(clippy::module_name_repetitions)]
 #[derive(Debug)]
 pub struct GenericMempool<T>
 where
-    T: Default + MempoolObj,
+    T: MempoolObj,
 {
     /// An `Arc` pointer to `MempoolInner`.
     inner: Arc<MpRef>,
@@ -175,7 +400,7 @@ where
 
 impl<T> Mempool<T> for GenericMempool<T>
 where
-    T: Default + MempoolObj,
+    T: MempoolObj,
 {
     #[inline]
     fn create(name: &str, size: u32) -> Result<Self> {
@@ -204,11 +429,13 @@ where
                 ptr::addr_of_mut!(ptr).cast::<*mut c_void>(),
             )
         };
-        Error::from_ret(errno)?;
+        if errno < 0 {
+            return Err(exhausted());
+        }
         // SAFETY: valid memory, initialized here
         #[allow(unsafe_code)]
         unsafe {
-            *ptr = T::default();
+            T::init(ptr.cast());
             T::from_raw(ptr.cast())
         }
     }
@@ -248,7 +475,7 @@ where
 
 impl<T> GenericMempool<T>
 where
-    T: Default + MempoolObj,
+    T: MempoolObj,
 {
     /// Get a new instance.
     ///
@@ -289,31 +516,82 @@ where
         })
     }
 
-    /// Get several objects from the mempool.
+    /// Call `f` once the available objects fall below `low`, and once they're back to `low` or
+    /// above, which is also logged. The mempool is sampled every `set_watch_interval`, so that
+    /// starvation is detected before allocations fail with `Error::MempoolExhausted`.
+    ///
+    /// The watch is removed with `unwatch` or when the mempool is freed.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: `low` is larger than the size of the mempool.
+    #[inline]
+    pub fn watch<F>(&self, low: u32, f: F) -> Result<WatchId>
+    where
+        F: Fn(WatermarkEvent) + Send + Sync + 'static,
+    {
+        self.inner.watch(low, Box::new(f))
+    }
+
+    /// Allocate an object from the mempool, which is put back when the returned guard is
+    /// dropped. Use `Mempool::get` for an object to be put back with `Mempool::put` explicitly.
     ///
     /// # Errors
     ///
     /// This function could returns an error if the mempool is out of memory.
     #[inline]
-    pub fn get_bulk(&self, n: u32) -> Result<Vec<Box<T>>> {
-        let mut ptrs = (0..n).map(|_| ptr::null_mut::<T>()).collect::<Vec<_>>();
+    #[allow(clippy::same_name_method)] // shadows `Mempool::get`
+    pub fn get(&self) -> Result<PoolGuard<T>> {
+        let obj = <Self as Mempool<T>>::get(self)?;
+        Ok(PoolGuard::new(obj, Arc::clone(&self.inner)))
+    }
+
+    /// Get several objects from the mempool, each of which is put back when its guard is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - The mempool is out of memory.
+    /// - An object fails to be converted with `from_raw`, in which case all the objects got are
+    ///   put back.
+    #[inline]
+    pub fn get_bulk(&self, n: u32) -> Result<Vec<PoolGuard<T>>> {
+        let mut ptrs = (0..n)
+            .map(|_| ptr::null_mut::<c_void>())
+            .collect::<Vec<_>>();
         // SAFETY: invalid allocation result in a negative errno
         #[allow(unsafe_code)]
-        let errno =
-            unsafe { rte_mempool_get_bulk(self.inner.as_ptr(), ptrs.as_mut_ptr().cast(), n) };
-        Error::from_ret(errno)?;
-        let vec = ptrs
-            .into_iter()
-            .map(|ptr| {
-                // SAFETY: pointers' validity checked
-                #[allow(unsafe_code)]
-                unsafe {
-                    *ptr = T::default();
-                    Box::from_raw(ptr)
+        let errno = unsafe { rte_mempool_get_bulk(self.inner.as_ptr(), ptrs.as_mut_ptr(), n) };
+        if errno < 0 {
+            return Err(exhausted());
+        }
+        let mut guards = Vec::with_capacity(ptrs.len());
+        for (i, &ptr) in ptrs.iter().enumerate() {
+            // SAFETY: pointers' validity checked, initialized here
+            #[allow(unsafe_code)]
+            unsafe {
+                T::init(ptr);
+            }
+            match T::from_raw(ptr) {
+                Ok(obj) => guards.push(PoolGuard::new(obj, Arc::clone(&self.inner))),
+                Err(e) => {
+                    // The objects wrapped are put back by their guards, the others here.
+                    for &ptr in ptrs.get(i..).unwrap_or_default() {
+                        // SAFETY: got from this mempool, and not wrapped
+                        #[allow(unsafe_code)]
+                        unsafe {
+                            rte_mempool_put(self.inner.as_ptr(), ptr);
+                        }
+                    }
+                    return Err(e);
                 }
-            })
-            .collect();
-        Ok(vec)
+            }
+        }
+        Ok(guards)
     }
 
     /// Put several objects back in the mempool.
@@ -339,22 +617,15 @@ pub struct PktMempool {
 impl Mempool<Mbuf> for PktMempool {
     #[inline]
     fn create(name: &str, size: u32) -> Result<Self> {
-        let socket_id = lcore::socket_id();
-        let name = CString::new(name).map_err(Error::from)?;
-        // SAFETY: pointer checked in `MpRef::new`
-        #[allow(unsafe_code, clippy::cast_possible_truncation)]
-        let ptr = unsafe {
-            rte_pktmbuf_pool_create(
-                name.as_ptr(),
-                size,
-                0,
-                0,
-                RTE_MBUF_DEFAULT_BUF_SIZE as u16,
-                socket_id,
-            )
-        };
-        let inner = MpRef::new(ptr)?;
-        Ok(Self::new(inner))
+        #[allow(clippy::cast_possible_truncation)] // 2176 < u16::MAX
+        Self::create_with(
+            name,
+            size,
+            0,
+            0,
+            RTE_MBUF_DEFAULT_BUF_SIZE as u16,
+            lcore::socket_id(),
+        )
     }
 
     #[inline]
@@ -366,10 +637,7 @@ impl Mempool<Mbuf> for PktMempool {
 
     #[inline]
     fn get(&self) -> Result<Mbuf> {
-        // SAFETY: pointer checked in `Mbuf::new_with_ptr`
-        #[allow(unsafe_code)]
-        let ptr = unsafe { rte_pktmbuf_alloc(self.as_ptr()) };
-        Mbuf::new_with_ptr(ptr)
+        Mbuf::new(self)
     }
 
     #[inline]
@@ -409,11 +677,227 @@ impl PktMempool {
         self.inner.as_ptr()
     }
 
+    /// Call `f` once the available `Mbuf`s fall below `low`, and once they're back to `low` or
+    /// above. See `GenericMempool::watch`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: `low` is larger than the size of the mempool.
+    #[inline]
+    pub fn watch<F>(&self, low: u32, f: F) -> Result<WatchId>
+    where
+        F: Fn(WatermarkEvent) + Send + Sync + 'static,
+    {
+        self.inner.watch(low, Box::new(f))
+    }
+
     /// Get a new instance of `Mempool`.
     #[inline]
     pub(crate) fn new(inner: Arc<MpRef>) -> Self {
         Self { inner }
     }
+
+    /// Create a `PktMempool` of `size` `Mbuf`s on NUMA socket `socket_id`, each of which has a
+    /// private area of `priv_size` bytes and `data_room` bytes for the headroom and data.
+    ///
+    /// Each lcore caches up to `cache_size` `Mbuf`s, which saves the accesses to the shared ring
+    /// on allocation and deallocation. It should be no larger than `RTE_MEMPOOL_CACHE_MAX_SIZE`,
+    /// i.e. 512, and two thirds of `size`, and preferably a divisor of `size`. `PktMempool::create`
+    /// creates one without caches on the socket of the caller, with `RTE_MBUF_DEFAULT_BUF_SIZE`
+    /// bytes of data room.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `cache_size` is too large, or `priv_size` is not aligned to 8 bytes.
+    /// - `Error::NoMem`: no appropriate memory area left.
+    /// - `Error::Secondary`: called from a secondary process.
+    #[inline]
+    pub fn create_with(
+        name: &str,
+        size: u32,
+        cache_size: u32,
+        priv_size: u16,
+        data_room: u16,
+        socket_id: i32,
+    ) -> Result<Self> {
+        if cache_size > RTE_MEMPOOL_CACHE_MAX_SIZE
+            || u64::from(cache_size).saturating_mul(3) > u64::from(size).saturating_mul(2)
+            || !priv_size.is_multiple_of(8)
+        {
+            return Err(Error::InvalidArg);
+        }
+        let name = CString::new(name).map_err(Error::from)?;
+        // SAFETY: pointer checked in `MpRef::new`
+        #[allow(unsafe_code)]
+        let ptr = unsafe {
+            rte_pktmbuf_pool_create(
+                name.as_ptr(),
+                size,
+                cache_size,
+                priv_size,
+                data_room,
+                socket_id,
+            )
+        };
+        let inner = MpRef::new(ptr)?;
+        Ok(Self::new(inner))
+    }
+
+    /// The per-lcore cache size of the mempools of the device queues holding `size` `Mbuf`s, which
+    /// is a quarter of them up to `RTE_MEMPOOL_CACHE_MAX_SIZE`.
+    pub(crate) fn default_cache_size(size: u32) -> u32 {
+        (size >> 2_u32).min(RTE_MEMPOOL_CACHE_MAX_SIZE)
+    }
+}
+
+/// A mempool watched by `GenericMempool::watch` or `PktMempool::watch` crossing its watermark.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatermarkEvent {
+    /// The available objects fall below the watermark.
+    Low {
+        /// The number of available objects.
+        available: u32,
+        /// The number of objects the mempool holds.
+        size: u32,
+    },
+    /// The available objects are back to the watermark or above.
+    Recovered {
+        /// The number of available objects.
+        available: u32,
+        /// The number of objects the mempool holds.
+        size: u32,
+    },
+}
+
+/// Identifier of a watch on a mempool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WatchId(u64);
+
+/// A callback of a watch.
+type WatchFn = dyn Fn(WatermarkEvent) + Send + Sync;
+
+/// A watch on a mempool.
+struct Watch {
+    /// Identifier of the watch.
+    id: WatchId,
+    /// The mempool watched, whose watch is removed once it's freed.
+    mp: Weak<MpRef>,
+    /// The watermark.
+    low: u32,
+    /// Whether the available objects are below `low`.
+    below: bool,
+    /// Called on crossing `low`.
+    callback: Arc<WatchFn>,
+}
+
+lazy_static! {
+    /// Watches on mempools.
+    static ref WATCHES: Mutex<Vec<Watch>> = Mutex::new(Vec::new());
+}
+
+/// The identifier of the next watch.
+static NEXT_WATCH_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the thread sampling the watched mempools is running.
+static WATCHER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Interval of sampling the watched mempools in microseconds.
+static WATCH_INTERVAL_US: AtomicU64 = AtomicU64::new(100_000);
+
+/// Number of allocations failed on exhausted mempools.
+static EXHAUSTED: AtomicU64 = AtomicU64::new(0);
+
+/// Count an allocation failed on an exhausted mempool, returning `Error::MempoolExhausted`.
+pub(crate) fn exhausted() -> Error {
+    let _prev = EXHAUSTED.fetch_add(1, Ordering::Relaxed);
+    Error::MempoolExhausted
+}
+
+/// Get the number of allocations failed with `Error::MempoolExhausted` in all mempools.
+#[inline]
+#[must_use]
+pub fn exhausted_count() -> u64 {
+    EXHAUSTED.load(Ordering::Relaxed)
+}
+
+/// Set how often the watched mempools are sampled, which is 100ms by default.
+#[inline]
+pub fn set_watch_interval(interval: Duration) {
+    let us = u64::try_from(interval.as_micros()).unwrap_or(u64::MAX);
+    WATCH_INTERVAL_US.store(us.max(1), Ordering::Relaxed);
+}
+
+/// Remove a watch added by `GenericMempool::watch` or `PktMempool::watch`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NotExist`: no such watch, or its mempool is freed.
+#[inline]
+pub fn unwatch(id: WatchId) -> Result<()> {
+    let mut watches = WATCHES.lock().map_err(Error::from)?;
+    let pos = watches
+        .iter()
+        .position(|watch| watch.id == id)
+        .ok_or(Error::NotExist)?;
+    let _watch = watches.swap_remove(pos);
+    Ok(())
+}
+
+/// Sample the watched mempools until no watch is left.
+fn run_watcher() {
+    loop {
+        thread::sleep(Duration::from_micros(
+            WATCH_INTERVAL_US.load(Ordering::Relaxed),
+        ));
+        let mut events = vec![];
+        {
+            let mut watches = WATCHES.lock().unwrap_or_else(PoisonError::into_inner);
+            watches.retain(|watch| watch.mp.strong_count() > 0);
+            if watches.is_empty() {
+                WATCHER_RUNNING.store(false, Ordering::Release);
+                return;
+            }
+            for watch in watches.iter_mut() {
+                let Some(mp) = watch.mp.upgrade() else {
+                    continue;
+                };
+                let available = mp.avail_count();
+                let size = mp.size();
+                let below = available < watch.low;
+                if below == watch.below {
+                    continue;
+                }
+                watch.below = below;
+                let event = if below {
+                    warn!(
+                        "Mempool {} running low: {available} of {size} available",
+                        mp.name()
+                    );
+                    WatermarkEvent::Low { available, size }
+                } else {
+                    info!(
+                        "Mempool {} recovered: {available} of {size} available",
+                        mp.name()
+                    );
+                    WatermarkEvent::Recovered { available, size }
+                };
+                events.push((Arc::clone(&watch.callback), event));
+            }
+        }
+        // Called without the lock, so that callbacks are able to add or remove watches.
+        for (callback, event) in events {
+            callback(event);
+        }
+    }
 }
 
 /// `MempoolRef` is a wrapper of `*rte_mempool`. It is mapped to one instance of `rte_mempool`.
@@ -424,6 +908,8 @@ impl PktMempool {
 pub struct MpRef {
     /// A pointer to `rte_mempool`.
     mp: NonNull<rte_mempool>,
+    /// Whether the mempool is created by this process, which frees it on drop.
+    owned: bool,
 }
 
 // SAFETY: mempool can be globally accessed
@@ -442,10 +928,17 @@ impl Debug for MpRef {
 }
 
 impl MpRef {
-    /// Create a new `MempoolInner` instance with a pointer.
+    /// Create a new `MempoolInner` instance with a pointer to a mempool just created.
     fn new(ptr: *mut rte_mempool) -> Result<Arc<Self>> {
-        let mp = NonNull::new(ptr).ok_or(Error::NoMem)?;
-        let mp = Arc::new(Self { mp });
+        let mp = NonNull::new(ptr).ok_or_else(|| {
+            // Secondary processes can't create mempools.
+            if eal::process_type() == ProcessType::Secondary {
+                Error::Secondary
+            } else {
+                Error::NoMem
+            }
+        })?;
+        let mp = Arc::new(Self { mp, owned: true });
         let _prev = MEMPOOLS
             .lock()
             .map_err(Error::from)?
@@ -453,20 +946,21 @@ impl MpRef {
         Ok(mp)
     }
 
-    /// Lookup a `Mempool` with its name.
+    /// Lookup a `Mempool` with its name, attaching to it if it's not created by this process.
     #[inline]
     fn lookup(name: &CString) -> Result<Arc<Self>> {
         // SAFETY: pointer checked later
         #[allow(unsafe_code)]
         let ptr = unsafe { rte_mempool_lookup(name.as_ptr()) };
-        if ptr.is_null() {
-            return Err(Error::from_errno());
-        }
-        if let Some(weak) = MEMPOOLS.lock().map_err(Error::from)?.get(&(ptr as usize)) {
-            Ok(weak.upgrade().ok_or(Error::NotExist)?)
-        } else {
-            Err(Error::NotExist)
+        let mp = NonNull::new(ptr).ok_or_else(|| Error::from_errno("rte_mempool_lookup"))?;
+        let mut mempools = MEMPOOLS.lock().map_err(Error::from)?;
+        if let Some(weak) = mempools.get(&(ptr as usize)) {
+            // A mempool of this process being freed.
+            return weak.upgrade().ok_or(Error::NotExist);
         }
+        let mp = Arc::new(Self { mp, owned: false });
+        let _prev = mempools.insert(ptr as usize, Arc::downgrade(&mp));
+        Ok(mp)
     }
 
     /// The number of available objects.
@@ -509,11 +1003,57 @@ impl MpRef {
     fn as_ptr(&self) -> *mut rte_mempool {
         self.mp.as_ptr()
     }
+
+    /// The number of objects the mempool holds.
+    fn size(&self) -> u32 {
+        // SAFETY: the *rte_mempool pointer is valid
+        #[allow(unsafe_code)]
+        unsafe {
+            self.mp.as_ref().size
+        }
+    }
+
+    /// The name of the mempool.
+    fn name(&self) -> String {
+        // SAFETY: the *rte_mempool pointer is valid
+        #[allow(unsafe_code)]
+        let name = unsafe { &self.mp.as_ref().name };
+        #[allow(clippy::cast_sign_loss)] // C string bytes
+        let name: Vec<u8> = name
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        String::from_utf8_lossy(&name).into_owned()
+    }
+
+    /// Watch the available objects of the mempool against `low`.
+    fn watch(self: &Arc<Self>, low: u32, callback: Box<WatchFn>) -> Result<WatchId> {
+        if low > self.size() {
+            return Err(Error::InvalidArg);
+        }
+        let id = WatchId(NEXT_WATCH_ID.fetch_add(1, Ordering::Relaxed));
+        let mut watches = WATCHES.lock().map_err(Error::from)?;
+        watches.push(Watch {
+            id,
+            mp: Arc::downgrade(self),
+            low,
+            below: false,
+            callback: Arc::from(callback),
+        });
+        if !WATCHER_RUNNING.swap(true, Ordering::AcqRel) {
+            let _handle = thread::spawn(run_watcher);
+        }
+        Ok(id)
+    }
 }
 
 impl Drop for MpRef {
     #[inline]
     fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
         // SAFETY: *rte_mempool checked
         #[allow(unsafe_code)]
         unsafe {
@@ -528,8 +1068,16 @@ mod tests {
     use std::os::raw::c_void;
     use std::ptr;
 
-    use crate::mempool::{GenericMempool, Mempool};
+    use crate::eal::{self, ProcessType};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{mpsc, Mutex};
+    use std::time::Duration;
+
+    use crate::mempool::{
+        self, GenericMempool, Mempool, PktMempool, PoolGuard, PooledBox, WatermarkEvent,
+    };
     use crate::test_utils;
+    use crate::Error;
 
     use super::MempoolObj;
 
@@ -564,6 +1112,7 @@ mod tests {
     #[test]
     fn test() {
         test_utils::dpdk_setup();
+        assert_eq!(eal::process_type(), ProcessType::Primary);
         let mp: GenericMempool<SomePtr> = GenericMempool::create("mempool", 64).unwrap();
         assert!(mp.is_full());
         assert!(!mp.is_empty());
@@ -579,7 +1128,113 @@ mod tests {
         assert_eq!(mp1.in_use(), 1);
         assert_eq!(mp1.available(), 63);
 
-        mp.put(obj);
+        mp.put(obj.into_inner());
         assert!(mp1.is_full());
+
+        let objs = mp.get_bulk(2).unwrap();
+        assert_eq!(mp1.in_use(), 2);
+        drop(objs);
+        assert!(mp1.is_full());
+    }
+
+    #[test]
+    fn test_create_with() {
+        test_utils::dpdk_setup();
+        assert_eq!(PktMempool::default_cache_size(1024), 256);
+        assert_eq!(PktMempool::default_cache_size(8192), 512);
+        assert_eq!(
+            PktMempool::create_with("cache_too_large", 64, 64, 0, 2176, -1).unwrap_err(),
+            Error::InvalidArg
+        );
+        let mp = PktMempool::create_with("cached", 512, 128, 8, 2176, -1).unwrap();
+        assert!(mp.is_full());
+        assert_eq!(mp.available(), 512);
+    }
+
+    #[test]
+    fn test_watch() {
+        test_utils::dpdk_setup();
+        let mp: GenericMempool<PooledBox<u64>> = GenericMempool::create("watched", 8).unwrap();
+        assert_eq!(mp.watch(9, |_| {}).unwrap_err(), Error::InvalidArg);
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        mempool::set_watch_interval(Duration::from_millis(1));
+        let id = mp
+            .watch(4, move |event| tx.lock().unwrap().send(event).unwrap())
+            .unwrap();
+        let exhausted = mempool::exhausted_count();
+        assert_eq!(mp.get_bulk(9).unwrap_err(), Error::MempoolExhausted);
+        assert!(mempool::exhausted_count() > exhausted);
+
+        let objs = mp.get_bulk(6).unwrap();
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            rx.recv_timeout(timeout).unwrap(),
+            WatermarkEvent::Low {
+                available: 2,
+                size: 8
+            }
+        );
+        drop(objs);
+        assert_eq!(
+            rx.recv_timeout(timeout).unwrap(),
+            WatermarkEvent::Recovered {
+                available: 8,
+                size: 8
+            }
+        );
+        mempool::unwatch(id).unwrap();
+        assert_eq!(mempool::unwatch(id).unwrap_err(), Error::NotExist);
+    }
+
+    /// Converted from the first two objects only.
+    struct SomeRejected {
+        ptr: *mut c_void,
+    }
+    /// Objects converted to `SomeRejected`.
+    static CONVERTED: AtomicU32 = AtomicU32::new(0);
+    impl MempoolObj for SomeRejected {
+        fn into_raw(self) -> *mut c_void {
+            self.ptr
+        }
+        fn from_raw(ptr: *mut c_void) -> crate::Result<Self> {
+            if CONVERTED.fetch_add(1, Ordering::Relaxed) < 2 {
+                Ok(Self { ptr })
+            } else {
+                Err(Error::InvalidArg)
+            }
+        }
+        fn obj_size() -> usize {
+            mem::size_of::<SomeType>()
+        }
+    }
+
+    #[test]
+    fn test_get_bulk_rejected() {
+        test_utils::dpdk_setup();
+        let mp: GenericMempool<SomeRejected> = GenericMempool::create("rejected", 8).unwrap();
+        assert_eq!(mp.get_bulk(4).unwrap_err(), Error::InvalidArg);
+        // Neither the objects converted nor the others are leaked.
+        assert!(mp.is_full());
+    }
+
+    #[test]
+    fn test_pooled_box() {
+        test_utils::dpdk_setup();
+        let mp: GenericMempool<PooledBox<Vec<u64>>> =
+            GenericMempool::create("pooled_box", 8).unwrap();
+        let mut obj = mp.get().unwrap();
+        assert!(obj.is_empty());
+        obj.push(1);
+        assert_eq!(mp.in_use(), 1);
+        drop(obj);
+        assert!(mp.is_full());
+
+        let objs = mp.get_bulk(8).unwrap();
+        assert!(mp.is_empty());
+        assert!(objs.iter().all(|obj| obj.is_empty()));
+        let objs = objs.into_iter().map(PoolGuard::into_inner).collect();
+        mp.put_bulk(objs, 8);
+        assert!(mp.is_full());
     }
 }
@@ -0,0 +1,302 @@
+//! Memory zones are named, contiguous regions of hugepage memory reserved from EAL, which are
+//! shared by name with secondary processes and addressable by devices with their IO virtual
+//! addresses (IOVA). They suit descriptor rings built by the application and memory shared with
+//! accelerators. For more information, please refer to [`Memory zone document`].
+//!
+//! # Examples
+//!
+//! ```
+//! # use async_dpdk::memzone::Memzone;
+//!
+//! # let _ = async_dpdk::eal::Config::new().enter();
+//! let mut mz = Memzone::reserve("descs", 4096, -1).unwrap();
+//! mz.as_mut_slice()[0] = 1;
+//! // Handed to a device.
+//! let _iova = mz.iova();
+//! // SAFETY: `mz` is not accessed while `found` is in use
+//! let found = unsafe { Memzone::lookup("descs") }.unwrap();
+//! assert_eq!(found.as_slice()[0], 1);
+//! ```
+//!
+//! [`Memory zone document`]: https://doc.dpdk.org/guides/prog_guide/env_abstraction_layer.html#memory-zones
+
+// `rte_memzone` is mirrored, since `dpdk_sys` exports its address in an anonymous union.
+#![allow(non_camel_case_types)]
+
+use crate::{trace::error, Error, Result};
+use std::{
+    ffi::CString,
+    fmt::Debug,
+    os::raw::{c_char, c_int, c_uint, c_void},
+    ptr::{self, NonNull},
+    slice,
+};
+
+/// Length of the names of memory zones, including the terminating nul byte.
+const RTE_MEMZONE_NAMESIZE: usize = 32;
+
+/// Reserve a memory zone contiguous in IOVA space.
+const RTE_MEMZONE_IOVA_CONTIG: c_uint = 0x0010_0000;
+
+/// Alignment of memory zones by default, i.e. the cache line size.
+const RTE_CACHE_LINE_SIZE: c_uint = 64;
+
+/// A reserved memory zone.
+#[repr(C, packed)]
+struct rte_memzone {
+    /// Name of the memory zone.
+    name: [c_char; RTE_MEMZONE_NAMESIZE],
+    /// IO virtual address of the start.
+    iova: u64,
+    /// Virtual address of the start.
+    addr: *mut c_void,
+    /// Length in bytes.
+    len: usize,
+    /// Size of the underlying pages.
+    hugepage_sz: u64,
+    /// NUMA socket it's on.
+    socket_id: i32,
+    /// Flags it's reserved with.
+    flags: u32,
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Reserve a memory zone aligned to `align`, a power of 2.
+    fn rte_memzone_reserve_aligned(
+        name: *const c_char,
+        len: usize,
+        socket_id: c_int,
+        flags: c_uint,
+        align: c_uint,
+    ) -> *const rte_memzone;
+
+    /// Look up a memory zone by its name.
+    fn rte_memzone_lookup(name: *const c_char) -> *const rte_memzone;
+
+    /// Free a memory zone. Returns 0 on success, or a negative errno.
+    fn rte_memzone_free(mz: *const rte_memzone) -> c_int;
+}
+
+/// A named region of memory contiguous in both virtual and IOVA space.
+///
+/// The memory zone is freed on drop if it's reserved by `reserve` or `reserve_aligned`, while
+/// those found by `lookup` are not.
+pub struct Memzone {
+    /// The memory zone.
+    mz: NonNull<rte_memzone>,
+    /// Whether it's reserved here, which frees it on drop.
+    owned: bool,
+}
+
+// SAFETY: memory zones can be globally accessed
+#[allow(unsafe_code)]
+unsafe impl Send for Memzone {}
+
+// SAFETY: the memory is only read through `&Memzone`, and other handles of the same memory zone
+// are only got from `lookup`, whose callers ensure they are not used concurrently
+#[allow(unsafe_code)]
+unsafe impl Sync for Memzone {}
+
+impl Debug for Memzone {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Memzone")
+            .field("name", &self.name())
+            .field("len", &self.len())
+            .field("iova", &self.iova())
+            .field("socket_id", &self.socket_id())
+            .finish()
+    }
+}
+
+#[allow(unsafe_code)]
+impl Memzone {
+    /// Reserve a memory zone of `len` bytes named `name` on NUMA socket `socket_id`, or any
+    /// socket if it's -1, which is aligned to the cache line and contiguous in IOVA space.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::Exists`: a memory zone named `name` exists.
+    /// - `Error::InvalidArg`: `name` is too long, or `len` is 0.
+    /// - `Error::NoMem`: no contiguous memory of `len` bytes left.
+    /// - `Error::NoSpace`: the maximum number of memory zones are reserved.
+    /// - `Error::Secondary`: called from a secondary process.
+    #[inline]
+    pub fn reserve(name: &str, len: usize, socket_id: i32) -> Result<Self> {
+        Self::reserve_aligned(name, len, socket_id, RTE_CACHE_LINE_SIZE)
+    }
+
+    /// Reserve a memory zone like `reserve`, which is aligned to `align` bytes, a power of 2.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons are those of `reserve`, and `Error::InvalidArg` if `align` is not a power
+    /// of 2.
+    #[inline]
+    pub fn reserve_aligned(name: &str, len: usize, socket_id: i32, align: u32) -> Result<Self> {
+        if len == 0 || !align.is_power_of_two() || name.len() >= RTE_MEMZONE_NAMESIZE {
+            return Err(Error::InvalidArg);
+        }
+        let name = CString::new(name).map_err(Error::from)?;
+        // SAFETY: pointer checked later
+        let mz = unsafe {
+            rte_memzone_reserve_aligned(
+                name.as_ptr(),
+                len,
+                socket_id,
+                RTE_MEMZONE_IOVA_CONTIG,
+                align,
+            )
+        };
+        let mz = NonNull::new(mz.cast_mut())
+            .ok_or_else(|| Error::from_errno("rte_memzone_reserve_aligned"))?;
+        Ok(Self { mz, owned: true })
+    }
+
+    /// Look up a memory zone reserved by this process or another one by its name, which is not
+    /// freed on drop.
+    ///
+    /// # Safety
+    ///
+    /// The memory zone is shared with the handle it's reserved with, and with those looked up in
+    /// this process or others. While a slice got from one of them is in use, the memory should
+    /// not be written through the others, nor accessed at all if the slice is mutable.
+    ///
+    /// # Errors
+    ///
+    /// This function returns `Error::NoEntry` if no memory zone is named `name`.
+    #[inline]
+    pub unsafe fn lookup(name: &str) -> Result<Self> {
+        let name = CString::new(name).map_err(Error::from)?;
+        // SAFETY: pointer checked later
+        let mz = unsafe { rte_memzone_lookup(name.as_ptr()) };
+        let mz = NonNull::new(mz.cast_mut()).ok_or(Error::NoEntry)?;
+        Ok(Self { mz, owned: false })
+    }
+
+    /// Get the name of the memory zone.
+    #[inline]
+    #[must_use]
+    pub fn name(&self) -> String {
+        // SAFETY: the memory zone is valid, and its name is read unaligned
+        let name = unsafe { ptr::addr_of!((*self.mz.as_ptr()).name).read_unaligned() };
+        #[allow(clippy::cast_sign_loss)] // C string bytes
+        let name: Vec<u8> = name
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        String::from_utf8_lossy(&name).into_owned()
+    }
+
+    /// Get the length of the memory zone in bytes.
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        // SAFETY: the memory zone is valid
+        unsafe { ptr::addr_of!((*self.mz.as_ptr()).len).read_unaligned() }
+    }
+
+    /// Whether the memory zone is empty, which is never the case.
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the IO virtual address of the memory zone, with which devices access it.
+    #[inline]
+    #[must_use]
+    pub fn iova(&self) -> u64 {
+        // SAFETY: the memory zone is valid
+        unsafe { ptr::addr_of!((*self.mz.as_ptr()).iova).read_unaligned() }
+    }
+
+    /// Get the NUMA socket the memory zone is on.
+    #[inline]
+    #[must_use]
+    pub fn socket_id(&self) -> i32 {
+        // SAFETY: the memory zone is valid
+        unsafe { ptr::addr_of!((*self.mz.as_ptr()).socket_id).read_unaligned() }
+    }
+
+    /// Get the virtual address of the memory zone.
+    #[inline]
+    #[must_use]
+    pub fn as_ptr(&self) -> *mut u8 {
+        // SAFETY: the memory zone is valid
+        unsafe { ptr::addr_of!((*self.mz.as_ptr()).addr).read_unaligned() }.cast()
+    }
+
+    /// Get the memory of the memory zone.
+    #[inline]
+    #[must_use]
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: the memory zone holds `len` bytes
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len()) }
+    }
+
+    /// Get the memory of the memory zone mutably.
+    #[inline]
+    #[must_use]
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: the memory zone holds `len` bytes
+        unsafe { slice::from_raw_parts_mut(self.as_ptr(), self.len()) }
+    }
+}
+
+impl Drop for Memzone {
+    #[inline]
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+        // SAFETY: the memory zone is reserved by this process and never used again
+        #[allow(unsafe_code)]
+        let errno = unsafe { rte_memzone_free(self.mz.as_ptr()) };
+        if let Err(e) = Error::from_ret(errno, "rte_memzone_free") {
+            error!("Failed to free memory zone {}: {e:?}", self.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Memzone;
+    use crate::{test_utils, Error, ErrorKind};
+
+    #[test]
+    #[allow(unsafe_code)]
+    fn test() {
+        test_utils::dpdk_setup();
+        assert_eq!(
+            Memzone::reserve("mz_test", 0, -1).unwrap_err(),
+            Error::InvalidArg
+        );
+        assert_eq!(
+            Memzone::reserve_aligned("mz_test", 64, -1, 3).unwrap_err(),
+            Error::InvalidArg
+        );
+        let mut mz = Memzone::reserve_aligned("mz_test", 4096, -1, 4096).unwrap();
+        assert_eq!(mz.name(), "mz_test");
+        assert_eq!(mz.len(), 4096);
+        assert_eq!(mz.as_ptr() as usize % 4096, 0);
+        mz.as_mut_slice().fill(7);
+        assert_eq!(
+            Memzone::reserve("mz_test", 64, -1).unwrap_err().kind(),
+            ErrorKind::Exists
+        );
+        // SAFETY: `mz` is not accessed while `found` is in use
+        let found = unsafe { Memzone::lookup("mz_test") }.unwrap();
+        assert_eq!(found.iova(), mz.iova());
+        assert!(found.as_slice().iter().all(|&b| b == 7));
+        drop(found);
+        drop(mz);
+        // SAFETY: no memory zone is accessed
+        let res = unsafe { Memzone::lookup("mz_test") };
+        assert_eq!(res.unwrap_err(), Error::NoEntry);
+    }
+}
@@ -0,0 +1,563 @@
+//! Traffic metering and policing based on `rte_meter`, which marks packets green, yellow or red
+//! with the single rate three color marker (srTCM, RFC 2697) or the two rate three color marker
+//! (trTCM, RFC 2698). For more information, please refer to [`QoS Meter library document`].
+//!
+//! A `Policer` drops or demotes the out-of-profile packets sent by a socket or on a tx queue,
+//! see `UdpSocket::set_policer` and `DevConfig::tx_policer`.
+//!
+//! # Examples
+//!
+//! ```
+//! # use async_dpdk::meter::{Color, Meter, MeterProfile};
+//!
+//! # let _ = async_dpdk::eal::Config::new().enter();
+//! // 1 MB/s, with bursts of up to 1500 bytes in profile and another 1500 bytes exceeding it.
+//! let mut meter = Meter::new(MeterProfile::SrTcm {
+//!     cir: 1_000_000,
+//!     cbs: 1500,
+//!     ebs: 1500,
+//! })
+//! .unwrap();
+//! assert_eq!(meter.check(1000), Color::Green);
+//! ```
+//!
+//! [`QoS Meter library document`]: https://doc.dpdk.org/guides/prog_guide/traffic_metering_and_policing.html
+
+#![allow(non_camel_case_types)]
+
+use crate::{Error, Result};
+use dpdk_sys::rte_rdtsc;
+use std::{os::raw::c_int, ptr};
+
+/// Parameters of an srTCM profile.
+#[repr(C)]
+struct rte_meter_srtcm_params {
+    /// Committed Information Rate (CIR) in bytes per second.
+    cir: u64,
+    /// Committed Burst Size (CBS) in bytes.
+    cbs: u64,
+    /// Excess Burst Size (EBS) in bytes.
+    ebs: u64,
+}
+
+/// An srTCM profile, shared by the meters with the same parameters.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct rte_meter_srtcm_profile {
+    /// Upper limit of the committed bucket in bytes.
+    cbs: u64,
+    /// Upper limit of the excess bucket in bytes.
+    ebs: u64,
+    /// TSC cycles between refills of the committed bucket.
+    cir_period: u64,
+    /// Bytes refilled to the committed bucket each period.
+    cir_bytes_per_period: u64,
+}
+
+/// Run-time state of an srTCM meter.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct rte_meter_srtcm {
+    /// TSC cycles of the latest refill.
+    time: u64,
+    /// Bytes in the committed bucket.
+    tc: u64,
+    /// Bytes in the excess bucket.
+    te: u64,
+}
+
+/// Parameters of a trTCM profile.
+#[repr(C)]
+struct rte_meter_trtcm_params {
+    /// Committed Information Rate (CIR) in bytes per second.
+    cir: u64,
+    /// Peak Information Rate (PIR) in bytes per second.
+    pir: u64,
+    /// Committed Burst Size (CBS) in bytes.
+    cbs: u64,
+    /// Peak Burst Size (PBS) in bytes.
+    pbs: u64,
+}
+
+/// A trTCM profile, shared by the meters with the same parameters.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct rte_meter_trtcm_profile {
+    /// Upper limit of the committed bucket in bytes.
+    cbs: u64,
+    /// Upper limit of the peak bucket in bytes.
+    pbs: u64,
+    /// TSC cycles between refills of the committed bucket.
+    cir_period: u64,
+    /// Bytes refilled to the committed bucket each period.
+    cir_bytes_per_period: u64,
+    /// TSC cycles between refills of the peak bucket.
+    pir_period: u64,
+    /// Bytes refilled to the peak bucket each period.
+    pir_bytes_per_period: u64,
+}
+
+/// Run-time state of a trTCM meter.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct rte_meter_trtcm {
+    /// TSC cycles of the latest refill of the committed bucket.
+    time_tc: u64,
+    /// TSC cycles of the latest refill of the peak bucket.
+    time_tp: u64,
+    /// Bytes in the committed bucket.
+    tc: u64,
+    /// Bytes in the peak bucket.
+    tp: u64,
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Compute an srTCM profile from its parameters.
+    fn rte_meter_srtcm_profile_config(
+        p: *mut rte_meter_srtcm_profile,
+        params: *const rte_meter_srtcm_params,
+    ) -> c_int;
+
+    /// Start an srTCM meter with full buckets.
+    fn rte_meter_srtcm_config(m: *mut rte_meter_srtcm, p: *const rte_meter_srtcm_profile) -> c_int;
+
+    /// Compute a trTCM profile from its parameters.
+    fn rte_meter_trtcm_profile_config(
+        p: *mut rte_meter_trtcm_profile,
+        params: *const rte_meter_trtcm_params,
+    ) -> c_int;
+
+    /// Start a trTCM meter with full buckets.
+    fn rte_meter_trtcm_config(m: *mut rte_meter_trtcm, p: *const rte_meter_trtcm_profile) -> c_int;
+}
+
+/// The color a packet is marked with by a `Meter`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Color {
+    /// The packet conforms to the committed rate.
+    Green,
+    /// The packet exceeds the committed rate, but still conforms to the excess burst or the peak
+    /// rate.
+    Yellow,
+    /// The packet violates the profile.
+    Red,
+}
+
+/// Traffic profile of a `Meter`, with rates in bytes per second and burst sizes in bytes.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MeterProfile {
+    /// Single rate three color marker, where a packet is green if it conforms to the committed
+    /// burst, yellow if it conforms to the excess burst, and red otherwise.
+    SrTcm {
+        /// Committed Information Rate (CIR).
+        cir: u64,
+        /// Committed Burst Size (CBS).
+        cbs: u64,
+        /// Excess Burst Size (EBS).
+        ebs: u64,
+    },
+    /// Two rate three color marker, where a packet is red if it exceeds the peak rate, yellow if
+    /// it exceeds the committed rate, and green otherwise.
+    TrTcm {
+        /// Committed Information Rate (CIR).
+        cir: u64,
+        /// Peak Information Rate (PIR), which is no less than the CIR.
+        pir: u64,
+        /// Committed Burst Size (CBS).
+        cbs: u64,
+        /// Peak Burst Size (PBS).
+        pbs: u64,
+    },
+}
+
+/// Profile and run-time state of a meter.
+#[derive(Debug, Clone, Copy)]
+enum MeterState {
+    /// An srTCM meter.
+    SrTcm(rte_meter_srtcm_profile, rte_meter_srtcm),
+    /// A trTCM meter.
+    TrTcm(rte_meter_trtcm_profile, rte_meter_trtcm),
+}
+
+/// A meter marking packets with colors by a `MeterProfile`, which is a safe wrapper of
+/// `rte_meter`.
+///
+/// The buckets start full, and are refilled by the TSC cycles elapsed when a packet is checked.
+#[derive(Debug, Clone)]
+pub struct Meter {
+    /// The profile the meter is created with.
+    profile: MeterProfile,
+    /// Profile and run-time state in the layout of `rte_meter`.
+    state: MeterState,
+}
+
+impl Meter {
+    /// Create a meter with full buckets.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: a rate is 0, all burst sizes are 0, or the PIR is less than the
+    ///   CIR.
+    #[inline]
+    pub fn new(profile: MeterProfile) -> Result<Self> {
+        let state = match profile {
+            MeterProfile::SrTcm { cir, cbs, ebs } => {
+                let params = rte_meter_srtcm_params { cir, cbs, ebs };
+                let mut p = rte_meter_srtcm_profile::default();
+                let mut m = rte_meter_srtcm::default();
+                // SAFETY: all pointers are valid during the calls
+                #[allow(unsafe_code)]
+                unsafe {
+                    let errno = rte_meter_srtcm_profile_config(&mut p, ptr::addr_of!(params));
+                    Error::from_ret(errno, "rte_meter_srtcm_profile_config")?;
+                    let errno = rte_meter_srtcm_config(&mut m, ptr::addr_of!(p));
+                    Error::from_ret(errno, "rte_meter_srtcm_config")?;
+                }
+                MeterState::SrTcm(p, m)
+            }
+            MeterProfile::TrTcm { cir, pir, cbs, pbs } => {
+                let params = rte_meter_trtcm_params { cir, pir, cbs, pbs };
+                let mut p = rte_meter_trtcm_profile::default();
+                let mut m = rte_meter_trtcm::default();
+                // SAFETY: all pointers are valid during the calls
+                #[allow(unsafe_code)]
+                unsafe {
+                    let errno = rte_meter_trtcm_profile_config(&mut p, ptr::addr_of!(params));
+                    Error::from_ret(errno, "rte_meter_trtcm_profile_config")?;
+                    let errno = rte_meter_trtcm_config(&mut m, ptr::addr_of!(p));
+                    Error::from_ret(errno, "rte_meter_trtcm_config")?;
+                }
+                MeterState::TrTcm(p, m)
+            }
+        };
+        Ok(Self { profile, state })
+    }
+
+    /// The profile of the meter.
+    #[inline]
+    #[must_use]
+    pub fn profile(&self) -> MeterProfile {
+        self.profile
+    }
+
+    /// Mark a packet of `len` bytes in color-blind mode, taking its bytes from the buckets.
+    #[inline]
+    pub fn check(&mut self, len: usize) -> Color {
+        self.check_at(tsc(), len, None)
+    }
+
+    /// Mark a packet of `len` bytes in color-aware mode, where the packet is never marked better
+    /// than `color`, e.g. it's pre-colored by a meter upstream.
+    #[inline]
+    pub fn check_color(&mut self, len: usize, color: Color) -> Color {
+        self.check_at(tsc(), len, Some(color))
+    }
+
+    /// Mark a packet of `len` bytes at TSC cycle `time`, the same as
+    /// `rte_meter_*_color_blind_check` if `color` is `None`, and `rte_meter_*_color_aware_check`
+    /// otherwise.
+    fn check_at(&mut self, time: u64, len: usize, color: Option<Color>) -> Color {
+        let len = u64::try_from(len).unwrap_or(u64::MAX);
+        let color = color.unwrap_or(Color::Green);
+        match self.state {
+            MeterState::SrTcm(ref p, ref mut m) => {
+                let n_periods = time
+                    .wrapping_sub(m.time)
+                    .checked_div(p.cir_period)
+                    .unwrap_or(0);
+                m.time = m.time.wrapping_add(n_periods.wrapping_mul(p.cir_period));
+                // Tokens overflowing from the committed bucket go to the excess one.
+                let mut tc =
+                    m.tc.saturating_add(n_periods.saturating_mul(p.cir_bytes_per_period));
+                let mut te = m.te;
+                if tc > p.cbs {
+                    te = te.saturating_add(tc.wrapping_sub(p.cbs)).min(p.ebs);
+                    tc = p.cbs;
+                }
+                if color == Color::Green && tc >= len {
+                    m.tc = tc.wrapping_sub(len);
+                    m.te = te;
+                    return Color::Green;
+                }
+                if color != Color::Red && te >= len {
+                    m.tc = tc;
+                    m.te = te.wrapping_sub(len);
+                    return Color::Yellow;
+                }
+                m.tc = tc;
+                m.te = te;
+                Color::Red
+            }
+            MeterState::TrTcm(ref p, ref mut m) => {
+                let n_periods_tc = time
+                    .wrapping_sub(m.time_tc)
+                    .checked_div(p.cir_period)
+                    .unwrap_or(0);
+                let n_periods_tp = time
+                    .wrapping_sub(m.time_tp)
+                    .checked_div(p.pir_period)
+                    .unwrap_or(0);
+                m.time_tc = m
+                    .time_tc
+                    .wrapping_add(n_periods_tc.wrapping_mul(p.cir_period));
+                m.time_tp = m
+                    .time_tp
+                    .wrapping_add(n_periods_tp.wrapping_mul(p.pir_period));
+                let tc =
+                    m.tc.saturating_add(n_periods_tc.saturating_mul(p.cir_bytes_per_period))
+                        .min(p.cbs);
+                let tp =
+                    m.tp.saturating_add(n_periods_tp.saturating_mul(p.pir_bytes_per_period))
+                        .min(p.pbs);
+                if color == Color::Red || tp < len {
+                    m.tc = tc;
+                    m.tp = tp;
+                    return Color::Red;
+                }
+                if color == Color::Yellow || tc < len {
+                    m.tc = tc;
+                    m.tp = tp.wrapping_sub(len);
+                    return Color::Yellow;
+                }
+                m.tc = tc.wrapping_sub(len);
+                m.tp = tp.wrapping_sub(len);
+                Color::Green
+            }
+        }
+    }
+}
+
+/// What a `Policer` does with the packets of a color.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PoliceAction {
+    /// Send the packet as it is.
+    Pass,
+    /// Send the packet with the lowest priority, i.e. in the last traffic class of the tx queue
+    /// when `DevConfig::tx_weights` is set.
+    Demote,
+    /// Drop the packet.
+    Drop,
+}
+
+/// Settings of policing packets by a `Meter`, which passes green packets, and by default passes
+/// yellow ones and drops red ones.
+///
+/// ```no_run
+/// use async_dpdk::meter::{MeterProfile, PoliceAction, Policer};
+///
+/// // Demote the packets over 10 MB/s, and drop the ones over 20 MB/s.
+/// let policer = Policer::new(MeterProfile::TrTcm {
+///     cir: 10_000_000,
+///     pir: 20_000_000,
+///     cbs: 64 * 1024,
+///     pbs: 64 * 1024,
+/// })
+/// .yellow(PoliceAction::Demote);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Policer {
+    /// Profile of the meter.
+    profile: MeterProfile,
+    /// What to do with yellow packets.
+    yellow: PoliceAction,
+    /// What to do with red packets.
+    red: PoliceAction,
+}
+
+impl Policer {
+    /// Police packets by a meter with `profile`, passing yellow packets and dropping red ones.
+    #[inline]
+    #[must_use]
+    pub fn new(profile: MeterProfile) -> Self {
+        Self {
+            profile,
+            yellow: PoliceAction::Pass,
+            red: PoliceAction::Drop,
+        }
+    }
+
+    /// Set what to do with yellow packets.
+    #[inline]
+    #[must_use]
+    pub fn yellow(mut self, action: PoliceAction) -> Self {
+        self.yellow = action;
+        self
+    }
+
+    /// Set what to do with red packets.
+    #[inline]
+    #[must_use]
+    pub fn red(mut self, action: PoliceAction) -> Self {
+        self.red = action;
+        self
+    }
+
+    /// Profile of the meter.
+    #[inline]
+    #[must_use]
+    pub fn profile(&self) -> MeterProfile {
+        self.profile
+    }
+
+    /// What to do with the packets of `color`.
+    #[inline]
+    #[must_use]
+    pub fn action(&self, color: Color) -> PoliceAction {
+        match color {
+            Color::Green => PoliceAction::Pass,
+            Color::Yellow => self.yellow,
+            Color::Red => self.red,
+        }
+    }
+}
+
+/// A `Policer` along with its meter.
+#[derive(Debug)]
+pub(crate) struct PoliceState {
+    /// The settings.
+    policer: Policer,
+    /// The meter of the packets policed.
+    meter: Meter,
+}
+
+impl PoliceState {
+    /// Start policing with full buckets.
+    pub(crate) fn new(policer: Policer) -> Result<Self> {
+        Ok(Self {
+            policer,
+            meter: Meter::new(policer.profile)?,
+        })
+    }
+
+    /// The settings.
+    pub(crate) fn policer(&self) -> Policer {
+        self.policer
+    }
+
+    /// Meter a packet of `len` bytes, returning what to do with it.
+    pub(crate) fn police(&mut self, len: usize) -> PoliceAction {
+        self.policer.action(self.meter.check(len))
+    }
+}
+
+/// Current TSC cycles.
+fn tsc() -> u64 {
+    // SAFETY: ffi
+    #[allow(unsafe_code)]
+    unsafe {
+        rte_rdtsc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Color, Meter, MeterProfile, MeterState, PoliceAction, Policer};
+    use crate::{test_utils, ErrorKind};
+    use dpdk_sys::rte_get_tsc_hz;
+
+    /// TSC cycles of a second.
+    fn hz() -> u64 {
+        // SAFETY: ffi
+        #[allow(unsafe_code)]
+        unsafe {
+            rte_get_tsc_hz()
+        }
+    }
+
+    #[test]
+    fn test_srtcm() {
+        test_utils::dpdk_setup();
+        let profile = MeterProfile::SrTcm {
+            cir: 1000,
+            cbs: 100,
+            ebs: 100,
+        };
+        let mut meter = Meter::new(profile).unwrap();
+        assert_eq!(meter.profile(), profile);
+        let MeterState::SrTcm(_, m) = meter.state else {
+            panic!("not an srTCM meter");
+        };
+        let start = m.time;
+        assert_eq!(meter.check_at(start, 60, None), Color::Green);
+        assert_eq!(meter.check_at(start, 60, None), Color::Yellow);
+        assert_eq!(meter.check_at(start, 60, None), Color::Red);
+        // Both buckets are full again after a second.
+        let later = start.wrapping_add(hz());
+        assert_eq!(
+            meter.check_at(later, 60, Some(Color::Yellow)),
+            Color::Yellow
+        );
+        assert_eq!(meter.check_at(later, 40, None), Color::Green);
+        assert_eq!(meter.check_at(later, 40, Some(Color::Red)), Color::Red);
+
+        let invalid = MeterProfile::SrTcm {
+            cir: 0,
+            cbs: 100,
+            ebs: 100,
+        };
+        assert_eq!(
+            Meter::new(invalid).unwrap_err().kind(),
+            ErrorKind::InvalidArg
+        );
+    }
+
+    #[test]
+    fn test_trtcm() {
+        test_utils::dpdk_setup();
+        let mut meter = Meter::new(MeterProfile::TrTcm {
+            cir: 1000,
+            pir: 2000,
+            cbs: 100,
+            pbs: 200,
+        })
+        .unwrap();
+        let MeterState::TrTcm(_, m) = meter.state else {
+            panic!("not a trTCM meter");
+        };
+        let start = m.time_tc;
+        assert_eq!(meter.check_at(start, 60, None), Color::Green);
+        assert_eq!(meter.check_at(start, 60, None), Color::Yellow);
+        assert_eq!(meter.check_at(start, 60, None), Color::Yellow);
+        assert_eq!(meter.check_at(start, 60, None), Color::Red);
+        let later = start.wrapping_add(hz());
+        assert_eq!(
+            meter.check_at(later, 60, Some(Color::Yellow)),
+            Color::Yellow
+        );
+        assert_eq!(meter.check_at(later, 60, None), Color::Green);
+
+        let invalid = MeterProfile::TrTcm {
+            cir: 2000,
+            pir: 1000,
+            cbs: 100,
+            pbs: 200,
+        };
+        assert_eq!(
+            Meter::new(invalid).unwrap_err().kind(),
+            ErrorKind::InvalidArg
+        );
+    }
+
+    #[test]
+    fn test_policer() {
+        let policer = Policer::new(MeterProfile::SrTcm {
+            cir: 1000,
+            cbs: 100,
+            ebs: 100,
+        });
+        assert_eq!(policer.action(Color::Green), PoliceAction::Pass);
+        assert_eq!(policer.action(Color::Yellow), PoliceAction::Pass);
+        assert_eq!(policer.action(Color::Red), PoliceAction::Drop);
+        let policer = policer
+            .yellow(PoliceAction::Demote)
+            .red(PoliceAction::Demote);
+        assert_eq!(policer.action(Color::Yellow), PoliceAction::Demote);
+        assert_eq!(policer.action(Color::Red), PoliceAction::Demote);
+    }
+}
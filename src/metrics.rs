@@ -0,0 +1,456 @@
+//! Metrics of the stack and the devices in the Prometheus text format.
+//!
+//! `gather` renders the counters of the probed devices and their queues, the usage of mempools,
+//! the receive buffers of sockets and IP reassembly, which can be scraped from the HTTP endpoint
+//! started by `serve` or exposed by the application itself.
+//!
+//! ```no_run
+//! use async_dpdk::metrics;
+//!
+//! let server = metrics::serve("0.0.0.0:9100").unwrap();
+//! println!("Scrape http://{}/metrics", server.local_addr());
+//! ```
+
+use crate::{
+    agent::{self, FragStats, QueueStats},
+    eth_dev::EthStats,
+    mempool, net_dev,
+    proto::socket::{self, SocketStats},
+    trace::{debug, warn},
+    Error, Result,
+};
+use std::{
+    fmt::Write as _,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Prefix of the names of all metrics.
+const PREFIX: &str = "async_dpdk";
+
+/// Interval to check whether the server is stopped while no request arrives.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Timeout to read a request or write a response.
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Max length of a request read.
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// Type of a metric family.
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    /// A monotonically increasing value.
+    Counter,
+    /// A value which may go up and down.
+    Gauge,
+}
+
+/// A metric family rendered for each of the items of type `T`: its name, type and help, and
+/// how to get its value from an item.
+type Metric<T> = (&'static str, Kind, &'static str, fn(&T) -> u64);
+
+/// Metrics of each device.
+const DEVICE_METRICS: [Metric<EthStats>; 8] = [
+    (
+        "device_rx_packets_total",
+        Kind::Counter,
+        "Packets received by the device.",
+        |stats| stats.ipackets,
+    ),
+    (
+        "device_tx_packets_total",
+        Kind::Counter,
+        "Packets sent by the device.",
+        |stats| stats.opackets,
+    ),
+    (
+        "device_rx_bytes_total",
+        Kind::Counter,
+        "Bytes received by the device.",
+        |stats| stats.ibytes,
+    ),
+    (
+        "device_tx_bytes_total",
+        Kind::Counter,
+        "Bytes sent by the device.",
+        |stats| stats.obytes,
+    ),
+    (
+        "device_rx_missed_total",
+        Kind::Counter,
+        "Packets dropped by the device for no rx buffer available.",
+        |stats| stats.imissed,
+    ),
+    (
+        "device_rx_errors_total",
+        Kind::Counter,
+        "Erroneous packets received by the device.",
+        |stats| stats.ierrors,
+    ),
+    (
+        "device_tx_errors_total",
+        Kind::Counter,
+        "Packets failed to be sent by the device.",
+        |stats| stats.oerrors,
+    ),
+    (
+        "device_rx_nombuf_total",
+        Kind::Counter,
+        "Mbuf allocation failures of the device.",
+        |stats| stats.rx_nombuf,
+    ),
+];
+
+/// Metrics of each queue.
+const QUEUE_METRICS: [Metric<QueueStats>; 7] = [
+    (
+        "queue_rx_bursts_total",
+        Kind::Counter,
+        "Non-empty bursts received from the rx queue.",
+        |stats| stats.rx_bursts,
+    ),
+    (
+        "queue_rx_packets_total",
+        Kind::Counter,
+        "Packets received from the rx queue.",
+        |stats| stats.rx_packets,
+    ),
+    (
+        "queue_rx_dropped_total",
+        Kind::Counter,
+        "Received packets dropped by the classifier.",
+        |stats| stats.rx_dropped,
+    ),
+    (
+        "queue_tx_packets_total",
+        Kind::Counter,
+        "Packets sent on the tx queue.",
+        |stats| stats.tx_packets,
+    ),
+    (
+        "queue_tx_dropped_total",
+        Kind::Counter,
+        "Packets buffered for the tx queue but dropped before sent.",
+        |stats| stats.tx_dropped,
+    ),
+    (
+        "queue_tx_pending",
+        Kind::Gauge,
+        "Packets buffered for the tx queue and not sent yet.",
+        |stats| stats.tx_pending,
+    ),
+    (
+        "queue_tx_policed_total",
+        Kind::Counter,
+        "Packets dropped by the policer of the tx queue.",
+        |stats| stats.tx_policed,
+    ),
+];
+
+/// Metrics of each mempool, with its number of available objects and objects in use.
+const MEMPOOL_METRICS: [Metric<(u32, u32)>; 2] = [
+    (
+        "mempool_available",
+        Kind::Gauge,
+        "Objects available in the mempool.",
+        |&(avail, _)| u64::from(avail),
+    ),
+    (
+        "mempool_in_use",
+        Kind::Gauge,
+        "Objects of the mempool in use.",
+        |&(_, in_use)| u64::from(in_use),
+    ),
+];
+
+/// Metrics of each socket.
+const SOCKET_METRICS: [Metric<SocketStats>; 5] = [
+    (
+        "socket_received_total",
+        Kind::Counter,
+        "Packets arrived at the socket, including the dropped ones.",
+        |stats| stats.received,
+    ),
+    (
+        "socket_dropped_total",
+        Kind::Counter,
+        "Packets dropped for the receive buffer of the socket being full.",
+        |stats| stats.dropped,
+    ),
+    (
+        "socket_overlimit_total",
+        Kind::Counter,
+        "Packets kept beyond the receive buffer size of the socket.",
+        |stats| stats.overlimit,
+    ),
+    (
+        "socket_filtered_total",
+        Kind::Counter,
+        "Packets rejected by the BPF filter of the socket.",
+        |stats| stats.filtered,
+    ),
+    (
+        "socket_queued",
+        Kind::Gauge,
+        "Packets in the receive buffer of the socket.",
+        |stats| stats.queued as u64,
+    ),
+];
+
+/// Metrics of IP reassembly.
+const FRAG_METRICS: [Metric<FragStats>; 3] = [
+    (
+        "frag_reassembled_total",
+        Kind::Counter,
+        "Datagrams reassembled from IP fragments.",
+        |stats| stats.reassembled,
+    ),
+    (
+        "frag_failed_total",
+        Kind::Counter,
+        "IP fragments dropped as invalid or evicted from the reassembly table.",
+        |stats| stats.failed,
+    ),
+    (
+        "frag_timed_out_total",
+        Kind::Counter,
+        "IP fragments dropped for the rest of their datagram not arriving in time.",
+        |stats| stats.timed_out,
+    ),
+];
+
+/// Render `metrics` in the Prometheus text format, with a sample of each metric for each of
+/// `items` labeled with its label set.
+///
+/// Metrics without samples are left out.
+fn render<T>(text: &mut String, metrics: &[Metric<T>], items: &[(String, T)]) {
+    if items.is_empty() {
+        return;
+    }
+    for &(name, kind, help, value) in metrics {
+        let kind = match kind {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+        };
+        _ = write!(
+            text,
+            "# HELP {PREFIX}_{name} {help}\n# TYPE {PREFIX}_{name} {kind}\n"
+        );
+        for &(ref labels, ref item) in items {
+            if labels.is_empty() {
+                _ = writeln!(text, "{PREFIX}_{name} {}", value(item));
+            } else {
+                _ = writeln!(text, "{PREFIX}_{name}{{{labels}}} {}", value(item));
+            }
+        }
+    }
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Gather the metrics of the stack and the devices, rendered in the Prometheus text format.
+///
+/// Counters of devices are labeled with their port ids and primary addresses, counters of queues
+/// with their port and queue ids, mempools with their names and sockets with their sockfds.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NotSupported`: a device doesn't support statistics.
+#[inline]
+pub fn gather() -> Result<String> {
+    let mut text = String::new();
+    let devices = net_dev::device_stats()?;
+    let queues: Vec<_> = devices
+        .iter()
+        .flat_map(|&(_, port_id, _)| {
+            agent::queue_stats(port_id)
+                .into_iter()
+                .map(move |(queue_id, stats)| {
+                    (format!("port=\"{port_id}\",queue=\"{queue_id}\""), stats)
+                })
+        })
+        .collect();
+    let devices: Vec<_> = devices
+        .into_iter()
+        .map(|(addr, port_id, stats)| (format!("port=\"{port_id}\",addr=\"{addr}\""), stats))
+        .collect();
+    render(&mut text, &DEVICE_METRICS, &devices);
+    render(&mut text, &QUEUE_METRICS, &queues);
+
+    let mempools: Vec<_> = mempool::mempool_usage()?
+        .into_iter()
+        .map(|(name, avail, in_use)| (format!("mempool=\"{}\"", escape(&name)), (avail, in_use)))
+        .collect();
+    render(&mut text, &MEMPOOL_METRICS, &mempools);
+
+    let sockets: Vec<_> = socket::mailbox_stats()?
+        .into_iter()
+        .map(|(sockfd, stats)| (format!("sockfd=\"{sockfd}\""), stats))
+        .collect();
+    render(&mut text, &SOCKET_METRICS, &sockets);
+
+    render(
+        &mut text,
+        &FRAG_METRICS,
+        &[(String::new(), agent::frag_stats())],
+    );
+    Ok(text)
+}
+
+/// An HTTP server started by `serve`, which is stopped once dropped.
+#[derive(Debug)]
+pub struct MetricsServer {
+    /// The address that the server listens on.
+    local_addr: SocketAddr,
+    /// Whether the server should stop.
+    stopped: Arc<AtomicBool>,
+    /// The thread serving requests.
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// The address that the server listens on.
+    #[inline]
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for MetricsServer {
+    #[inline]
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                warn!("The metrics server on {} panicked", self.local_addr);
+            }
+        }
+    }
+}
+
+/// Serve the metrics rendered by `gather` over HTTP on `addr`, answering `GET` requests of any
+/// path in a background thread.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Invalid socket address.
+/// - Unable to listen on `addr`.
+#[inline]
+pub fn serve<A: ToSocketAddrs>(addr: A) -> Result<MetricsServer> {
+    let listener = TcpListener::bind(addr).map_err(Error::from)?;
+    listener.set_nonblocking(true).map_err(Error::from)?;
+    let local_addr = listener.local_addr().map_err(Error::from)?;
+    let stopped = Arc::new(AtomicBool::new(false));
+    let stop = Arc::clone(&stopped);
+    let handle = thread::Builder::new()
+        .name("metrics".to_owned())
+        .spawn(move || {
+            while !stop.load(Ordering::Acquire) {
+                match listener.accept() {
+                    Ok((stream, peer)) => {
+                        if let Err(e) = respond(stream) {
+                            debug!("Failed to serve metrics to {peer}: {e}");
+                        }
+                    }
+                    Err(_) => thread::sleep(ACCEPT_INTERVAL),
+                }
+            }
+        })
+        .map_err(Error::from)?;
+    debug!("Serving metrics on {local_addr}");
+    Ok(MetricsServer {
+        local_addr,
+        stopped,
+        handle: Some(handle),
+    })
+}
+
+/// Read a request from `stream`, and respond with the metrics.
+fn respond(mut stream: TcpStream) -> Result<()> {
+    stream.set_nonblocking(false).map_err(Error::from)?;
+    stream
+        .set_read_timeout(Some(IO_TIMEOUT))
+        .map_err(Error::from)?;
+    stream
+        .set_write_timeout(Some(IO_TIMEOUT))
+        .map_err(Error::from)?;
+    let mut request = Vec::new();
+    let mut buf = [0_u8; 512];
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).map_err(Error::from)?;
+        if n == 0 || request.len() >= MAX_REQUEST_LEN {
+            break;
+        }
+        request.extend_from_slice(buf.get(..n).ok_or(Error::OutOfRange)?);
+    }
+    let response = if request.starts_with(b"GET ") {
+        match gather() {
+            Ok(body) => format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            ),
+            Err(e) => {
+                let body = e.to_string();
+                format!(
+                    "HTTP/1.1 500 Internal Server Error\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{body}",
+                    body.len()
+                )
+            }
+        }
+    } else {
+        "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\nContent-Length: 0\r\n\
+         Connection: close\r\n\r\n"
+            .to_owned()
+    };
+    stream.write_all(response.as_bytes()).map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, gather, serve};
+    use crate::test_utils;
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+    };
+
+    #[test]
+    fn test_gather() {
+        test_utils::dpdk_setup();
+        let text = gather().unwrap();
+        assert!(text.contains("# TYPE async_dpdk_frag_reassembled_total counter\n"));
+        assert!(text.contains("async_dpdk_frag_failed_total "));
+        assert_eq!(escape("a\"b\\c"), "a\\\"b\\\\c");
+
+        let server = serve("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        let _len = stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("async_dpdk_frag_timed_out_total"));
+    }
+}
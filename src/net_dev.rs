@@ -1,28 +1,312 @@
 //! Net device.
 
 use crate::{
-    eth_dev::{EthDev, TxSender},
-    Error, Result,
+    agent,
+    bpf::{self, BpfProgram},
+    capture::{self, Target},
+    eth_dev::{port_by_name, EthDev, TxSender},
+    ether::ETHER_ADDR_LEN,
+    flow::{FlowId, FlowRule},
+    proto::{arp, route},
+    trace::{debug, error, warn},
+    Error, ErrorKind, Result,
+};
+use dpdk_sys::{
+    rte_dev_probe, rte_dev_remove, rte_eth_dev_callback_register, rte_eth_dev_info,
+    rte_eth_dev_info_get, rte_eth_dev_is_valid_port, rte_eth_event_type,
+    rte_eth_event_type_RTE_ETH_EVENT_DESTROY, rte_eth_event_type_RTE_ETH_EVENT_INTR_LSC,
+    rte_eth_event_type_RTE_ETH_EVENT_INTR_RESET, rte_eth_event_type_RTE_ETH_EVENT_INTR_RMV,
+    rte_eth_link, rte_eth_link_get_nowait, rte_ether_addr, rte_free, rte_malloc, RTE_MAX_ETHPORTS,
 };
-use dpdk_sys::{rte_eth_dev_info, rte_eth_dev_info_get, rte_ether_addr, rte_free, rte_malloc};
 use lazy_static::lazy_static;
-use log::{debug, error};
-use std::{ffi::CString, mem, net::IpAddr, sync::RwLock};
+use std::{
+    collections::{btree_map, BTreeMap, HashMap},
+    ffi::{c_int, c_void, CString},
+    fmt::{self, Display},
+    mem,
+    mem::MaybeUninit,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    ptr,
+    str::FromStr,
+    sync::{Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{sync::mpsc, time};
+
+pub use crate::agent::{FragStats, QueueStats, RxExec, TxExec};
+pub use crate::eth_dev::{DevConfig, EthStats, LinkStatus, RssConfig};
+pub use crate::proto::route::Route;
+pub use crate::shaper::RateLimit;
 
 lazy_static! {
     /// Holding all probed Inet Devices.
     static ref INET_DEVICE: RwLock<Vec<InetDevice>> = RwLock::new(Vec::default());
+    /// Configurations given on probing, which are also applied to devices attached later.
+    static ref PROBE_CONF: RwLock<ProbeConf> = RwLock::new(ProbeConf::default());
+    /// Subscribers of events on each port.
+    static ref EVENT_SUBSCRIBERS: Mutex<HashMap<u16, Vec<mpsc::Sender<DevEvent>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// The capacity of the channel to each event subscriber.
+const EVENT_CHAN_SIZE: usize = 16;
+
+/// Events of an Ethernet device.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DevEvent {
+    /// The link status changed, carrying the new status.
+    LinkChange(LinkStatus),
+    /// The device needs to be reset, e.g. a VF reset by its PF.
+    Reset,
+    /// The device is being removed, e.g. unplugged.
+    Removal,
+}
+
+/// Interval of polling the link status in `wait_for_link_up`.
+const LINK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Port id referring to all ports, used to register event callbacks.
+#[allow(clippy::cast_possible_truncation)] // RTE_MAX_ETHPORTS is small
+const RTE_ETH_ALL: u16 = RTE_MAX_ETHPORTS as u16;
+
+/// Configurations for setting up an `EthDev`.
+#[derive(Debug, Clone)]
+struct ProbeConf {
+    /// Max number of rx and tx queues.
+    max_queues: u16,
+    /// RSS configurations.
+    rss: RssConfig,
+    /// Queue and memory configurations, keyed by `port_id`. Devices not given use the defaults.
+    dev_conf: BTreeMap<u16, DevConfig>,
+    /// MTU, or the device default if `None`.
+    mtu: Option<u16>,
+    /// Where each rx queue is polled, keyed by (`port_id`, `queue_id`). Queues not given are
+    /// polled on the tokio blocking pool.
+    rx_exec: BTreeMap<(u16, u16), RxExec>,
+    /// Where queues not given in `rx_exec` are spread over in a round-robin manner, or the tokio
+    /// blocking pool if empty.
+    rx_spread: Vec<RxExec>,
+    /// How long rx queues stay idle before their agents sleep waiting for RX interrupts, or
+    /// `None` to keep busy polling.
+    rx_intr: Option<Duration>,
+}
+
+impl Default for ProbeConf {
+    fn default() -> Self {
+        Self {
+            max_queues: u16::MAX,
+            rss: RssConfig::default(),
+            dev_conf: BTreeMap::new(),
+            mtu: None,
+            rx_exec: BTreeMap::new(),
+            rx_spread: Vec::new(),
+            rx_intr: None,
+        }
+    }
+}
+
+/// An IP address assigned to a device, along with the prefix length of its subnet.
+///
+/// It's parsed from the CIDR notation, e.g. `10.2.3.1/24`. A bare address has the full prefix
+/// length, so its subnet holds nothing but itself.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IfAddr {
+    /// The address.
+    pub ip: IpAddr,
+    /// Prefix length of the subnet.
+    pub prefix_len: u8,
+}
+
+impl IfAddr {
+    /// Create an address in a subnet with prefix length `prefix_len`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `prefix_len` is longer than the address.
+    #[inline]
+    pub fn new(ip: IpAddr, prefix_len: u8) -> Result<Self> {
+        let _network = route::mask(ip, prefix_len).ok_or(Error::InvalidArg)?;
+        Ok(Self { ip, prefix_len })
+    }
+
+    /// Network address of the subnet.
+    #[inline]
+    #[must_use]
+    pub fn network(&self) -> IpAddr {
+        route::mask(self.ip, self.prefix_len).unwrap_or(self.ip)
+    }
+
+    /// Whether `ip` is in the subnet.
+    #[inline]
+    #[must_use]
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        ip.is_ipv4() == self.ip.is_ipv4()
+            && route::mask(ip, self.prefix_len) == Some(self.network())
+    }
+
+    /// Directed broadcast address of the subnet, or `None` if it's an IPv6 subnet or an IPv4 one
+    /// without room for broadcast, i.e. whose prefix is 31 or 32 bits long.
+    #[inline]
+    #[must_use]
+    pub fn broadcast(&self) -> Option<Ipv4Addr> {
+        match self.ip {
+            IpAddr::V4(ip) if self.prefix_len < 31 => {
+                let host_mask = u32::MAX
+                    .checked_shr(u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                Some(Ipv4Addr::from(u32::from(ip) | host_mask))
+            }
+            IpAddr::V4(_) | IpAddr::V6(_) => None,
+        }
+    }
+}
+
+impl From<IpAddr> for IfAddr {
+    #[inline]
+    fn from(ip: IpAddr) -> Self {
+        let prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+        Self { ip, prefix_len }
+    }
+}
+
+impl FromStr for IfAddr {
+    type Err = Error;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('/') {
+            Some((ip, prefix_len)) => {
+                #[allow(clippy::map_err_ignore)]
+                let prefix_len = prefix_len.parse().map_err(|_| Error::InvalidArg)?;
+                Self::new(ip.parse().map_err(Error::from)?, prefix_len)
+            }
+            None => Ok(IpAddr::from_str(s).map_err(Error::from)?.into()),
+        }
+    }
+}
+
+impl Display for IfAddr {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.ip, self.prefix_len)
+    }
 }
 
 /// Device that can be bound to using an IP address.
 #[derive(Debug)]
 struct InetDevice {
-    /// The IP address assigned for the device.
-    ip: IpAddr,
+    /// The primary IP address assigned for the device.
+    ip: IfAddr,
+    /// Other IP addresses assigned for the device.
+    aliases: Vec<IfAddr>,
     /// Occupied `EthDev`.
     ethdev: EthDev,
     /// The device is started or not.
     running: bool,
+    /// Joined multicast groups, along with the number of sockets joining each.
+    mc_groups: BTreeMap<Ipv4Addr, usize>,
+    /// DNS servers reachable through the device.
+    dns_servers: Vec<IpAddr>,
+}
+
+impl InetDevice {
+    /// Whether `ip` is one of the addresses of the device.
+    fn has_ip(&self, ip: &IpAddr) -> bool {
+        &self.ip.ip == ip || self.aliases.iter().any(|alias| &alias.ip == ip)
+    }
+
+    /// All addresses of the device, the primary one first.
+    fn addresses(&self) -> Vec<IfAddr> {
+        let mut addrs = Vec::with_capacity(self.aliases.len().wrapping_add(1));
+        addrs.push(self.ip);
+        addrs.extend_from_slice(&self.aliases);
+        addrs
+    }
+
+    /// Program the multicast filter of the device with the joined groups.
+    fn update_mc_filter(&self) -> Result<()> {
+        let mc_addrs: Vec<_> = self
+            .mc_groups
+            .keys()
+            .map(|&group| arp::multicast_mac(group))
+            .collect();
+        match self.ethdev.set_mc_addr_list(&mc_addrs) {
+            Err(e) if e.kind() == ErrorKind::NotSupported && !mc_addrs.is_empty() => {
+                debug!("Multicast filter not supported, enabling allmulticast mode");
+                match self.ethdev.set_allmulticast(true) {
+                    Err(e) if e.kind() == ErrorKind::NotSupported => Ok(()),
+                    res => res,
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotSupported => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Register the started device to resolve its IP and answer ARP requests.
+    fn register_arp(&self) -> Result<()> {
+        let sender = self.ethdev.sender(0).ok_or(Error::NotStart)?;
+        arp::register_iface(
+            self.ethdev.port_id(),
+            self.addresses(),
+            self.ethdev.ether_addr()?,
+            sender,
+        )
+    }
+}
+
+/// Set up an `EthDev` on `port_id`.
+#[allow(unsafe_code)]
+#[allow(clippy::similar_names)] // tx and rx are DPDK terms
+fn new_ethdev(port_id: u16, conf: &ProbeConf) -> Result<EthDev> {
+    // SAFETY: `dev_info` validity checked in `rte_eth_dev_info_get`
+    let dev_info = unsafe {
+        let name = CString::new("rte_eth_dev_info").map_err(Error::from)?;
+        let dev_info = rte_malloc(name.as_ptr(), mem::size_of::<rte_eth_dev_info>(), 0);
+        let errno = rte_eth_dev_info_get(port_id, dev_info.cast());
+        Error::from_ret(errno, "rte_eth_dev_info_get").map_err(|e| {
+            rte_free(dev_info.cast());
+            e
+        })?;
+        &mut *(dev_info.cast::<rte_eth_dev_info>())
+    };
+    let n_rxq = dev_info.max_rx_queues.min(conf.max_queues);
+    let n_txq = dev_info.max_tx_queues.min(conf.max_queues);
+    // SAFETY: dev_info`'s validity is checked upon its allocation
+    #[allow(trivial_casts)]
+    unsafe {
+        rte_free((dev_info as *mut rte_eth_dev_info).cast());
+    }
+    let mut rx_exec: BTreeMap<_, _> = conf
+        .rx_exec
+        .range((port_id, 0)..=(port_id, u16::MAX))
+        .map(|(&(_, queue_id), &exec)| (queue_id, exec))
+        .collect();
+    for queue_id in 0..n_rxq {
+        let spread = usize::from(port_id)
+            .wrapping_add(usize::from(queue_id))
+            .checked_rem(conf.rx_spread.len())
+            .and_then(|idx| conf.rx_spread.get(idx));
+        if let (btree_map::Entry::Vacant(entry), Some(&exec)) = (rx_exec.entry(queue_id), spread) {
+            let _exec = entry.insert(exec);
+        }
+    }
+    EthDev::with_agents(
+        port_id,
+        n_rxq,
+        n_txq,
+        conf.rss.clone(),
+        &conf.dev_conf.get(&port_id).copied().unwrap_or_default(),
+        conf.mtu,
+        &rx_exec,
+        conf.rx_intr,
+    )
 }
 
 /// Probe all devices.
@@ -30,51 +314,322 @@ struct InetDevice {
 /// IP addresses assigned to devices should be distinct. The input addresses
 /// are automatically deduplicated.
 #[allow(unsafe_code)]
-#[allow(clippy::similar_names)] // tx and rx are DPDK terms
-pub(crate) fn device_probe(mut addrs: Vec<IpAddr>, max_queues: u16) -> Result<()> {
+#[allow(clippy::too_many_arguments)] // device settings collected by `eal::Config`
+pub(crate) fn device_probe(
+    mut addrs: Vec<IfAddr>,
+    max_queues: u16,
+    rss: &RssConfig,
+    dev_conf: BTreeMap<u16, DevConfig>,
+    mtu: Option<u16>,
+    rx_exec: BTreeMap<(u16, u16), RxExec>,
+    rx_spread: Vec<RxExec>,
+    rx_intr: Option<Duration>,
+) -> Result<()> {
     let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
     if !inet_device.is_empty() {
         error!("Device already probed");
         return Err(Error::Already);
     }
-    addrs.dedup();
+    addrs.dedup_by_key(|addr| addr.ip);
     let ndev = EthDev::available_ports();
     if (ndev as usize) < addrs.len() || (u16::MAX as usize) < addrs.len() {
         error!("Address list too long");
         return Err(Error::InvalidArg);
     }
+    let conf = ProbeConf {
+        max_queues,
+        rss: rss.clone(),
+        dev_conf,
+        mtu,
+        rx_exec,
+        rx_spread,
+        rx_intr,
+    };
     for (i, addr) in addrs.into_iter().enumerate() {
         #[allow(clippy::cast_possible_truncation)] // checked
         let port_id = i as u16;
-        // SAFETY: `dev_info` validity checked in `rte_eth_dev_info_get`
-        let dev_info = unsafe {
-            let name = CString::new("rte_eth_dev_info").map_err(Error::from)?;
-            let dev_info = rte_malloc(name.as_ptr(), mem::size_of::<rte_eth_dev_info>(), 0);
-            let errno = rte_eth_dev_info_get(port_id, dev_info.cast());
-            Error::from_ret(errno).map_err(|e| {
-                rte_free(dev_info.cast());
-                e
-            })?;
-            &mut *(dev_info.cast::<rte_eth_dev_info>())
-        };
-        let n_rxq = dev_info.max_rx_queues.min(max_queues);
-        let n_txq = dev_info.max_tx_queues.min(max_queues);
-        let ethdev = EthDev::new(port_id, n_rxq, n_txq)?;
+        let ethdev = new_ethdev(port_id, &conf)?;
         inet_device.push(InetDevice {
             ip: addr,
+            aliases: Vec::new(),
             ethdev,
             running: false,
+            mc_groups: BTreeMap::new(),
+            dns_servers: Vec::new(),
         });
-        debug!("Ethdev {port_id} probed, bound to {addr:?}");
-        // SAFETY: dev_info`'s validity is checked upon its allocation
-        #[allow(trivial_casts)]
-        unsafe {
-            rte_free((dev_info as *mut rte_eth_dev_info).cast());
+        debug!("Ethdev {port_id} probed, bound to {addr}");
+    }
+    *PROBE_CONF.write().map_err(Error::from)? = conf;
+    // SAFETY: the callback is a plain function
+    let errno = unsafe {
+        rte_eth_dev_callback_register(
+            RTE_ETH_ALL,
+            rte_eth_event_type_RTE_ETH_EVENT_DESTROY,
+            Some(on_port_destroy),
+            ptr::null_mut(),
+        )
+    };
+    Error::from_ret(errno, "rte_eth_dev_callback_register")?;
+    for event in [
+        rte_eth_event_type_RTE_ETH_EVENT_INTR_LSC,
+        rte_eth_event_type_RTE_ETH_EVENT_INTR_RESET,
+        rte_eth_event_type_RTE_ETH_EVENT_INTR_RMV,
+    ] {
+        // SAFETY: the callback is a plain function
+        let errno = unsafe {
+            rte_eth_dev_callback_register(RTE_ETH_ALL, event, Some(on_dev_event), ptr::null_mut())
+        };
+        Error::from_ret(errno, "rte_eth_dev_callback_register")?;
+    }
+    Ok(())
+}
+
+/// Attach a device at runtime, and assign `addr` to it, which is either an `IpAddr` or an
+/// `IfAddr` along with the prefix length of its subnet.
+///
+/// `devargs` identifies the device along with its arguments, e.g. `0000:08:00.0` for a PCI device
+/// or `net_ring0` for a virtual device. The device is set up with the configurations given on
+/// probing, and should be started with `device_start`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::Exists`: `addr` is already assigned to another device.
+/// - `Error::InvalidArg`: invalid `devargs`.
+/// - Unable to probe or set up the device.
+#[inline]
+pub fn device_attach<A: Into<IfAddr>>(devargs: &str, addr: A) -> Result<()> {
+    attach_port(addr.into(), || probe_port(devargs))
+}
+
+/// Set up the port created by `create` with the configurations given on probing, and assign
+/// `addr` to it. `create` is not called if `addr` is already assigned.
+pub(crate) fn attach_port(addr: IfAddr, create: impl FnOnce() -> Result<u16>) -> Result<()> {
+    let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
+    if inet_device.iter().any(|dev| dev.has_ip(&addr.ip)) {
+        error!("Address {} already assigned", addr.ip);
+        return Err(Error::Exists);
+    }
+    let port_id = create()?;
+    let conf = PROBE_CONF.read().map_err(Error::from)?.clone();
+    let ethdev = new_ethdev(port_id, &conf)?;
+    inet_device.push(InetDevice {
+        ip: addr,
+        aliases: Vec::new(),
+        ethdev,
+        running: false,
+        mc_groups: BTreeMap::new(),
+        dns_servers: Vec::new(),
+    });
+    debug!("Ethdev {port_id} attached, bound to {addr}");
+    Ok(())
+}
+
+/// Detach the device bound to `addr` at runtime. The device is stopped first if it's running.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - Unable to stop or remove the device.
+#[inline]
+pub fn device_detach(addr: &IpAddr) -> Result<()> {
+    let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
+    let idx = inet_device
+        .iter()
+        .position(|dev| dev.has_ip(addr))
+        .ok_or(Error::NoDev)?;
+    let mut dev = inet_device.remove(idx);
+    let port_id = dev.ethdev.port_id();
+    if dev.running {
+        arp::unregister_iface(port_id)?;
+        dev.ethdev.stop()?;
+    }
+    unsubscribe_events(port_id)?;
+    bpf::set_port_filter(port_id, None)?;
+    remove_port(dev.ethdev)?;
+    debug!("Ethdev {port_id} detached");
+    Ok(())
+}
+
+/// Close the device bound to `addr`, which is stopped first if it's running, and remove it from
+/// the stack. Unlike `device_detach`, the underlying device is not removed.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - Unable to stop or close the device.
+#[inline]
+pub fn device_close(addr: &IpAddr) -> Result<()> {
+    let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
+    let idx = inet_device
+        .iter()
+        .position(|dev| dev.has_ip(addr))
+        .ok_or(Error::NoDev)?;
+    let dev = inet_device.remove(idx);
+    let port_id = dev.ethdev.port_id();
+    if dev.running {
+        arp::unregister_iface(port_id)?;
+    }
+    unsubscribe_events(port_id)?;
+    bpf::set_port_filter(port_id, None)?;
+    dev.ethdev.close()?;
+    debug!("Ethdev {port_id} closed");
+    Ok(())
+}
+
+/// Probe the device identified by `devargs` at runtime, returning its port id.
+#[allow(unsafe_code)]
+pub(crate) fn probe_port(devargs: &str) -> Result<u16> {
+    let c_devargs = CString::new(devargs).map_err(Error::from)?;
+    // SAFETY: `c_devargs` outlives the call
+    let errno = unsafe { rte_dev_probe(c_devargs.as_ptr()) };
+    Error::from_ret(errno, "rte_dev_probe")?;
+    // The device name is followed by its arguments.
+    port_by_name(devargs.split(',').next().ok_or(Error::InvalidArg)?)
+}
+
+/// Close the port of a stopped `EthDev`, and remove the underlying device.
+#[allow(unsafe_code)]
+pub(crate) fn remove_port(ethdev: EthDev) -> Result<()> {
+    let device = ethdev.device()?;
+    // Close the port before removing the underlying device.
+    ethdev.close()?;
+    // SAFETY: `device` is not freed until it's removed
+    let errno = unsafe { rte_dev_remove(device) };
+    Error::from_ret(errno, "rte_dev_remove")
+}
+
+/// Called by DPDK when a port is released, e.g. its device is unplugged.
+///
+/// The event is also emitted when a port is closed by `EthDev`, where `INET_DEVICE` may be
+/// locked, so the device is removed in another thread.
+extern "C" fn on_port_destroy(
+    port_id: u16,
+    _event: rte_eth_event_type,
+    _cb_arg: *mut c_void,
+    _ret_param: *mut c_void,
+) -> c_int {
+    let _handle = thread::spawn(move || {
+        if let Err(e) = forget_port(port_id) {
+            error!("Failed to remove destroyed port {port_id}: {e:?}");
+        }
+    });
+    0
+}
+
+/// Remove the device on a released port.
+#[allow(unsafe_code)]
+fn forget_port(port_id: u16) -> Result<()> {
+    let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
+    // SAFETY: ffi
+    let valid = unsafe { rte_eth_dev_is_valid_port(port_id) } != 0;
+    // The port may have been closed by us, or reused by a newly attached device.
+    if valid {
+        return Ok(());
+    }
+    if let Some(idx) = inet_device
+        .iter()
+        .position(|dev| dev.ethdev.port_id() == port_id)
+    {
+        let mut dev = inet_device.remove(idx);
+        warn!(
+            "Port {port_id} destroyed, device bound to {} removed",
+            dev.ip.ip
+        );
+        if dev.running {
+            arp::unregister_iface(port_id)?;
+            // The port is already gone, just stop the agents.
+            let _res = dev.ethdev.stop();
         }
+        unsubscribe_events(port_id)?;
+        bpf::set_port_filter(port_id, None)?;
     }
     Ok(())
 }
 
+/// Subscribe to events of the device bound to `addr`, including link status changes, reset
+/// requests and removal.
+///
+/// Events are dropped if the receiver lags behind. The receiver is closed once the device is
+/// detached.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn subscribe_events(addr: &IpAddr) -> Result<mpsc::Receiver<DevEvent>> {
+    let port_id = port_id(addr)?;
+    let (tx, rx) = mpsc::channel(EVENT_CHAN_SIZE);
+    EVENT_SUBSCRIBERS
+        .lock()
+        .map_err(Error::from)?
+        .entry(port_id)
+        .or_default()
+        .push(tx);
+    Ok(rx)
+}
+
+/// Drop all subscribers of events on `port_id`.
+fn unsubscribe_events(port_id: u16) -> Result<()> {
+    let _prev = EVENT_SUBSCRIBERS
+        .lock()
+        .map_err(Error::from)?
+        .remove(&port_id);
+    Ok(())
+}
+
+/// Called by DPDK on link status changes, reset requests and removal of a port.
+#[allow(unsafe_code)]
+#[allow(non_upper_case_globals)]
+extern "C" fn on_dev_event(
+    port_id: u16,
+    event: rte_eth_event_type,
+    _cb_arg: *mut c_void,
+    _ret_param: *mut c_void,
+) -> c_int {
+    let event = match event {
+        rte_eth_event_type_RTE_ETH_EVENT_INTR_LSC => {
+            let mut link = MaybeUninit::<rte_eth_link>::uninit();
+            // SAFETY: errno checked later
+            let errno = unsafe { rte_eth_link_get_nowait(port_id, link.as_mut_ptr()) };
+            if errno < 0 {
+                error!("Failed to get link status of port {port_id}");
+                return 0;
+            }
+            // SAFETY: `rte_eth_link` is successfully initialized due to no error code.
+            DevEvent::LinkChange(unsafe { link.assume_init() }.into())
+        }
+        rte_eth_event_type_RTE_ETH_EVENT_INTR_RESET => DevEvent::Reset,
+        rte_eth_event_type_RTE_ETH_EVENT_INTR_RMV => DevEvent::Removal,
+        _ => return 0,
+    };
+    debug!("Port {port_id} got event {event:?}");
+    if let Ok(mut subscribers) = EVENT_SUBSCRIBERS.lock() {
+        if let Some(senders) = subscribers.get_mut(&port_id) {
+            senders.retain(|tx| match tx.try_send(event) {
+                Ok(()) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    warn!("Event {event:?} of port {port_id} dropped");
+                    true
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            });
+        }
+    }
+    0
+}
+
 /// Start all probed devices.
 ///
 /// # Errors
@@ -89,6 +644,7 @@ pub fn device_start_all() -> Result<()> {
         dev.ethdev.start()?;
         debug!("Device {} started", dev.ethdev.port_id());
         dev.running = true;
+        dev.register_arp()?;
     }
     Ok(())
 }
@@ -106,6 +662,7 @@ pub fn device_stop_all() -> Result<()> {
     let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
     let inet_iter = inet_device.iter_mut();
     for dev in inet_iter {
+        arp::unregister_iface(dev.ethdev.port_id())?;
         dev.ethdev.stop()?;
         debug!("Device {} stopped", dev.ethdev.port_id());
         dev.running = false;
@@ -126,11 +683,11 @@ pub fn device_start(addr: &IpAddr) -> Result<()> {
     let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
     let inet_iter = inet_device.iter_mut();
     for dev in inet_iter {
-        if &dev.ip == addr {
+        if dev.has_ip(addr) {
             dev.ethdev.start()?;
             debug!("Device {} started", dev.ethdev.port_id());
             dev.running = true;
-            return Ok(());
+            return dev.register_arp();
         }
     }
     Err(Error::NoDev)
@@ -149,7 +706,8 @@ pub fn device_stop(addr: &IpAddr) -> Result<()> {
     let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
     let inet_iter = inet_device.iter_mut();
     for dev in inet_iter {
-        if &dev.ip == addr {
+        if dev.has_ip(addr) {
+            arp::unregister_iface(dev.ethdev.port_id())?;
             dev.ethdev.stop()?;
             debug!("Device {} stopped", dev.ethdev.port_id());
             dev.running = false;
@@ -159,40 +717,913 @@ pub fn device_stop(addr: &IpAddr) -> Result<()> {
     Err(Error::NoDev)
 }
 
-/// Close all probed device.
-pub(crate) fn device_close() -> Result<()> {
+/// Assign `ip` to the device bound to `addr` as well, e.g. an IPv6 address alongside an IPv4
+/// one. Sockets can be bound to any address of a device, and ARP requests for each of them are
+/// answered once the device is started.
+///
+/// `ip` is either an `IpAddr`, or an `IfAddr` along with the prefix length of its subnet.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::Exists`: `ip` is already assigned to a device.
+#[inline]
+pub fn add_address<A: Into<IfAddr>>(addr: &IpAddr, ip: A) -> Result<()> {
+    let ip = ip.into();
     let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
-    inet_device.clear();
+    if inet_device.iter().any(|dev| dev.has_ip(&ip.ip)) {
+        error!("Address {} already assigned", ip.ip);
+        return Err(Error::Exists);
+    }
+    let dev = inet_device
+        .iter_mut()
+        .find(|dev| dev.has_ip(addr))
+        .ok_or(Error::NoDev)?;
+    dev.aliases.push(ip);
+    if dev.running {
+        dev.register_arp()?;
+    }
+    debug!("Address {ip} added to ethdev {}", dev.ethdev.port_id());
     Ok(())
 }
 
-/// Get a device from an IP address.
+/// Remove `ip` from the addresses of the device it's assigned to. If it's the primary address,
+/// the earliest added one of the rest becomes the primary. Sockets bound to `ip` before are not
+/// closed, but no longer receive packets.
 ///
-/// The returned result will be a tuple of a `TxSender` sending messages to that device and its Ether
-/// address.
-pub(crate) fn find_dev_by_ip(ip: IpAddr) -> Result<(TxSender, rte_ether_addr)> {
-    let inet_device = INET_DEVICE.read().map_err(Error::from)?;
-    let inet_iter = inet_device.iter();
-    for dev in inet_iter {
-        if dev.ip == ip {
-            if !dev.running {
-                error!("Device is not running!");
-                return Err(Error::NoDev);
-            }
-            let sender = dev.ethdev.sender(0).ok_or(Error::NotStart)?;
-            let addr = dev.ethdev.mac_addr()?;
-            return Ok((sender, addr));
-        }
-        if ip.is_unspecified() || ip.is_loopback() {
-            if !dev.running {
-                debug!("Device is not running, try the next one");
-                continue;
-            }
-            let sender = dev.ethdev.sender(0).ok_or(Error::NotStart)?;
-            let addr = dev.ethdev.mac_addr()?;
-            return Ok((sender, addr));
-        }
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NotExist`: `ip` is not assigned to any device.
+/// - `Error::InvalidArg`: `ip` is the only address of the device, which should be detached
+///   instead.
+#[inline]
+pub fn remove_address(ip: &IpAddr) -> Result<()> {
+    let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
+    let dev = inet_device
+        .iter_mut()
+        .find(|dev| dev.has_ip(ip))
+        .ok_or(Error::NotExist)?;
+    if dev.aliases.is_empty() {
+        error!("Unable to remove the only address {ip}");
+        return Err(Error::InvalidArg);
     }
-    error!("Ip address {ip} not matched to any address");
-    Err(Error::InvalidArg)
+    if &dev.ip.ip == ip {
+        dev.ip = dev.aliases.remove(0);
+    } else {
+        dev.aliases.retain(|alias| &alias.ip != ip);
+    }
+    if dev.running {
+        dev.register_arp()?;
+    }
+    debug!("Address {ip} removed from ethdev {}", dev.ethdev.port_id());
+    Ok(())
+}
+
+/// Get all addresses of the device bound to `addr`, the primary one first.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn addresses(addr: &IpAddr) -> Result<Vec<IfAddr>> {
+    with_device(addr, |dev| Ok(dev.addresses()))
+}
+
+/// Get basic statistics of the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support statistics.
+#[inline]
+pub fn stats(addr: &IpAddr) -> Result<EthStats> {
+    with_device(addr, |dev| dev.ethdev.stats())
+}
+
+/// Get the statistics of each queue of the device bound to `addr`, indexed by queue id.
+///
+/// Queues are listed once they are polled or sent on, and their counters are kept across
+/// restarts of the device.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn queue_stats(addr: &IpAddr) -> Result<BTreeMap<u16, QueueStats>> {
+    with_device(addr, |dev| Ok(agent::queue_stats(dev.ethdev.port_id())))
+}
+
+/// Get the primary address, the port id and the basic statistics of each probed device.
+#[cfg(feature = "metrics")]
+pub(crate) fn device_stats() -> Result<Vec<(IpAddr, u16, EthStats)>> {
+    let inet_device = INET_DEVICE.read().map_err(Error::from)?;
+    inet_device
+        .iter()
+        .map(|dev| Ok((dev.ip.ip, dev.ethdev.port_id(), dev.ethdev.stats()?)))
+        .collect()
+}
+
+/// Get the statistics of IP reassembly, summed over all devices.
+#[inline]
+#[must_use]
+pub fn frag_stats() -> FragStats {
+    agent::frag_stats()
+}
+
+/// Reset basic statistics of the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support statistics.
+#[inline]
+pub fn reset_stats(addr: &IpAddr) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.reset_stats())
+}
+
+/// Start capturing the frames received from and sent to the device bound to `addr` into a pcapng
+/// file at `path`, which is truncated if it exists.
+///
+/// Frames are captured as the agents see them, i.e. before IP reassembly on receiving and before
+/// IP fragmentation on sending.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::Already`: the device is being captured.
+/// - Failed to create the file.
+#[inline]
+pub fn start_capture<P: AsRef<Path>>(addr: &IpAddr, path: P) -> Result<()> {
+    let port_id = with_device(addr, |dev| Ok(dev.ethdev.port_id()))?;
+    capture::start(Target::Port(port_id), path.as_ref())
+}
+
+/// Stop capturing the frames of the device bound to `addr`, flushing the pcapng file.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotExist`: the device is not being captured.
+/// - Failed to flush the file.
+#[inline]
+pub fn stop_capture(addr: &IpAddr) -> Result<()> {
+    let port_id = with_device(addr, |dev| Ok(dev.ethdev.port_id()))?;
+    capture::stop(Target::Port(port_id))
+}
+
+/// Attach a BPF program filtering the frames received by the device bound to `addr`, replacing
+/// the previous one, or detach it with `None`.
+///
+/// Frames rejected by the program are dropped before they're captured or dispatched, and counted
+/// in `QueueStats::rx_dropped`. The filter is removed once the device is detached.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn set_rx_filter(addr: &IpAddr, filter: Option<BpfProgram>) -> Result<()> {
+    with_device(addr, |dev| {
+        bpf::set_port_filter(dev.ethdev.port_id(), filter)
+    })
+}
+
+/// Get extended statistics of the device bound to `addr`, keyed by their names.
+///
+/// The set of extended statistics is driver-specific, e.g. per-queue drops or bus errors.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support extended statistics.
+#[inline]
+pub fn xstats(addr: &IpAddr) -> Result<HashMap<String, u64>> {
+    with_device(addr, |dev| dev.ethdev.xstats())
+}
+
+/// Reset extended statistics of the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support extended statistics.
+#[inline]
+pub fn reset_xstats(addr: &IpAddr) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.reset_xstats())
+}
+
+/// Update the RSS hash key and hash functions of the device bound to `addr`. The RETA in `rss`
+/// is applied as well if it is set.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::InvalidArg`: invalid hash key length or RETA entries.
+/// - `Error::NotSupported`: the device doesn't support it.
+#[inline]
+pub fn rss_update(addr: &IpAddr, rss: &RssConfig) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.rss_update(rss))
+}
+
+/// Get the RSS redirection table (RETA) of the device bound to `addr`, i.e. the rx queue for
+/// each RETA entry.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support it.
+#[inline]
+pub fn rss_reta(addr: &IpAddr) -> Result<Vec<u16>> {
+    with_device(addr, |dev| dev.ethdev.rss_reta_query())
+}
+
+/// Get the MTU of the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn mtu(addr: &IpAddr) -> Result<u16> {
+    with_device(addr, |dev| dev.ethdev.mtu())
+}
+
+/// Set the MTU of the device bound to `addr`. Outgoing IP packets larger than it are fragmented.
+///
+/// To use jumbo frames which don't fit in an `Mbuf`, a jumbo MTU should be set with
+/// `eal::Config::mtu` as well so that multi-segment packets are enabled.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::InvalidArg`: `mtu` is out of the range supported by the device.
+/// - `Error::NotSupported`: the device doesn't support changing its MTU.
+/// - `Error::Busy`: the device should be stopped before changing its MTU.
+#[inline]
+pub fn set_mtu(addr: &IpAddr, mtu: u16) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.set_mtu(mtu))
+}
+
+/// Enable or disable GSO on the device bound to `addr`.
+///
+/// With GSO enabled, outgoing IPv4 UDP datagrams larger than the MTU are fragmented by the
+/// hardware if it supports UDP fragmentation offload, or by the GSO library otherwise, which
+/// refers to the payload instead of copying it.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn set_gso(addr: &IpAddr, enable: bool) -> Result<()> {
+    with_device(addr, |dev| {
+        dev.ethdev.set_gso(enable);
+        Ok(())
+    })
+}
+
+/// Whether GSO is enabled on the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn gso(addr: &IpAddr) -> Result<bool> {
+    with_device(addr, |dev| Ok(dev.ethdev.gso()))
+}
+
+/// Enable or disable GRO on the device bound to `addr`.
+///
+/// With GRO enabled, IPv4 fragments of UDP datagrams received in the same burst are merged by
+/// the GRO library, and only those left over go through the reassembly table.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn set_gro(addr: &IpAddr, enable: bool) -> Result<()> {
+    with_device(addr, |dev| {
+        dev.ethdev.set_gro(enable);
+        Ok(())
+    })
+}
+
+/// Whether GRO is enabled on the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn gro(addr: &IpAddr) -> Result<bool> {
+    with_device(addr, |dev| Ok(dev.ethdev.gro()))
+}
+
+/// Read the clock of the device bound to `addr`, in ticks of `Timestamp::Hardware` taken by it.
+/// Reading it along with the TSC at times tells the rate of the clock, with which hardware
+/// timestamps are converted to durations.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device has no clock to read.
+#[inline]
+pub fn read_clock(addr: &IpAddr) -> Result<u64> {
+    with_device(addr, |dev| dev.ethdev.read_clock())
+}
+
+/// Set the max number of packets received from an rx queue of the device bound to `addr` in a
+/// burst, which is set by `eal::Config::rx_burst` by default.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::InvalidArg`: `burst` is 0 or larger than 512.
+#[inline]
+pub fn set_rx_burst(addr: &IpAddr, burst: u16) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.set_rx_burst(burst))
+}
+
+/// The max number of packets received from an rx queue of the device bound to `addr` in a
+/// burst.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn rx_burst(addr: &IpAddr) -> Result<u16> {
+    with_device(addr, |dev| Ok(dev.ethdev.rx_burst()))
+}
+
+/// Check whether `rule` can be created on the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the rule is not supported by the device.
+/// - `Error::InvalidArg`: the rule is invalid.
+#[inline]
+pub fn flow_validate(addr: &IpAddr, rule: &FlowRule) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.flow_validate(rule))
+}
+
+/// Create a flow rule on the device bound to `addr`. The rule lives until it is destroyed or
+/// the device is closed.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the rule is not supported by the device.
+/// - `Error::InvalidArg`: the rule is invalid.
+#[inline]
+pub fn flow_create(addr: &IpAddr, rule: &FlowRule) -> Result<FlowId> {
+    with_device(addr, |dev| dev.ethdev.flow_create(rule))
+}
+
+/// Destroy a flow rule created on the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NoEntry`: no flow rule is identified with `id`.
+#[inline]
+pub fn flow_destroy(addr: &IpAddr, id: FlowId) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.flow_destroy(id))
+}
+
+/// Add a route to the destinations in `dst`/`prefix_len` through the device bound to `dev`.
+/// Packets to them are sent to `gateway` if it's set, otherwise the destinations are on-link.
+/// Host bits of `dst` are ignored, and the route with the longest prefix is used.
+///
+/// ```no_run
+/// use async_dpdk::net_dev;
+/// use std::net::IpAddr;
+///
+/// let dev = IpAddr::from([192, 168, 0, 2]);
+/// // The default route.
+/// net_dev::add_route([0, 0, 0, 0].into(), 0, &dev, Some([192, 168, 0, 1].into())).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `dev`.
+/// - `Error::InvalidArg`: `prefix_len` is too long, or the addresses are of different families.
+/// - `Error::Exists`: a route to the prefix exists.
+#[inline]
+pub fn add_route(dst: IpAddr, prefix_len: u8, dev: &IpAddr, gateway: Option<IpAddr>) -> Result<()> {
+    with_device(dev, |_| route::add(dst, prefix_len, *dev, gateway))
+}
+
+/// Delete the route to `dst`/`prefix_len` added by `add_route`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::InvalidArg`: `prefix_len` is too long.
+/// - `Error::NotExist`: no route to the prefix exists.
+#[inline]
+pub fn del_route(dst: IpAddr, prefix_len: u8) -> Result<()> {
+    route::del(dst, prefix_len)
+}
+
+/// Get all routes, the longest prefixes first.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+#[inline]
+pub fn routes() -> Result<Vec<Route>> {
+    route::routes()
+}
+
+/// Set the DNS servers reachable through the device bound to `addr`, e.g. those offered by a
+/// DHCP server, replacing the ones set before.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn set_dns_servers(addr: &IpAddr, servers: Vec<IpAddr>) -> Result<()> {
+    let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
+    inet_device
+        .iter_mut()
+        .find(|dev| dev.has_ip(addr))
+        .ok_or(Error::NoDev)?
+        .dns_servers = servers;
+    Ok(())
+}
+
+/// Get the DNS servers reachable through the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn dns_servers(addr: &IpAddr) -> Result<Vec<IpAddr>> {
+    with_device(addr, |dev| Ok(dev.dns_servers.clone()))
+}
+
+/// Get the default MAC address of the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn mac_addr(addr: &IpAddr) -> Result<[u8; ETHER_ADDR_LEN]> {
+    with_device(addr, |dev| dev.ethdev.mac_addr())
+}
+
+/// Get all MAC addresses of the device bound to `addr`, starting with the default one.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn mac_addrs(addr: &IpAddr) -> Result<Vec<[u8; ETHER_ADDR_LEN]>> {
+    with_device(addr, |dev| dev.ethdev.mac_addrs())
+}
+
+/// Replace the default MAC address of the device bound to `addr`, which is announced in ARP
+/// replies from then on. Sockets bound before keep sending frames from the previous address.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support it.
+/// - `Error::InvalidArg`: `mac` is not a unicast address.
+#[inline]
+pub fn set_mac_addr(addr: &IpAddr, mac: [u8; ETHER_ADDR_LEN]) -> Result<()> {
+    with_device(addr, |dev| {
+        dev.ethdev.set_mac_addr(mac)?;
+        if dev.running {
+            dev.register_arp()?;
+        }
+        Ok(())
+    })
+}
+
+/// Add a secondary MAC address to the device bound to `addr`, so that frames sent to it are
+/// received as well.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support it.
+/// - `Error::NoSpace`: no more addresses can be added.
+#[inline]
+pub fn add_mac_addr(addr: &IpAddr, mac: [u8; ETHER_ADDR_LEN]) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.add_mac_addr(mac))
+}
+
+/// Remove a secondary MAC address added by `add_mac_addr` from the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support it.
+/// - `Error::InvalidArg`: `mac` is the default MAC address.
+#[inline]
+pub fn remove_mac_addr(addr: &IpAddr, mac: [u8; ETHER_ADDR_LEN]) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.remove_mac_addr(mac))
+}
+
+/// Enable the promiscuous mode of the device bound to `addr`, so that all packets are received
+/// regardless of their destination Ether addresses.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support it.
+#[inline]
+pub fn promiscuous_enable(addr: &IpAddr) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.set_promiscuous(true))
+}
+
+/// Disable the promiscuous mode of the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support it.
+#[inline]
+pub fn promiscuous_disable(addr: &IpAddr) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.set_promiscuous(false))
+}
+
+/// Whether the promiscuous mode of the device bound to `addr` is enabled.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn is_promiscuous(addr: &IpAddr) -> Result<bool> {
+    with_device(addr, |dev| dev.ethdev.is_promiscuous())
+}
+
+/// Enable the allmulticast mode of the device bound to `addr`, so that all multicast packets are
+/// received.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support it.
+#[inline]
+pub fn allmulticast_enable(addr: &IpAddr) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.set_allmulticast(true))
+}
+
+/// Disable the allmulticast mode of the device bound to `addr`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support it.
+#[inline]
+pub fn allmulticast_disable(addr: &IpAddr) -> Result<()> {
+    with_device(addr, |dev| dev.ethdev.set_allmulticast(false))
+}
+
+/// Whether the allmulticast mode of the device bound to `addr` is enabled.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+#[inline]
+pub fn is_allmulticast(addr: &IpAddr) -> Result<bool> {
+    with_device(addr, |dev| dev.ethdev.is_allmulticast())
+}
+
+/// Join the multicast `group` on the device bound to `addr`, counting the sockets joining it.
+///
+/// The multicast filter of the device is updated when a group is joined for the first time. The
+/// allmulticast mode is enabled instead if the device doesn't support the filter, and it's
+/// assumed that devices supporting neither receive all multicast packets.
+pub(crate) fn join_multicast(addr: &IpAddr, group: Ipv4Addr) -> Result<()> {
+    let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
+    let dev = inet_device
+        .iter_mut()
+        .find(|dev| dev.has_ip(addr))
+        .ok_or(Error::NoDev)?;
+    let count = dev.mc_groups.entry(group).or_insert(0);
+    *count = count.wrapping_add(1);
+    if *count == 1 {
+        if let Err(e) = dev.update_mc_filter() {
+            let _prev = dev.mc_groups.remove(&group);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Leave the multicast `group` on the device bound to `addr`.
+///
+/// The multicast filter of the device is updated when no socket joins the group any more.
+pub(crate) fn leave_multicast(addr: &IpAddr, group: Ipv4Addr) -> Result<()> {
+    let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
+    let dev = inet_device
+        .iter_mut()
+        .find(|dev| dev.has_ip(addr))
+        .ok_or(Error::NoDev)?;
+    let count = dev.mc_groups.get_mut(&group).ok_or(Error::NotExist)?;
+    *count = count.saturating_sub(1);
+    if *count == 0 {
+        let _prev = dev.mc_groups.remove(&group);
+        dev.update_mc_filter()?;
+    }
+    Ok(())
+}
+
+/// Get the link status of the device bound to `addr` without waiting.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support link status query.
+#[inline]
+pub fn link(addr: &IpAddr) -> Result<LinkStatus> {
+    with_device(addr, |dev| dev.ethdev.link())
+}
+
+/// Wait until the link of the device bound to `addr` is up, polling its status periodically.
+///
+/// Packets sent before the link is up are likely to be dropped silently.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::NoDev`: no device is bound to `addr`.
+/// - `Error::NotSupported`: the device doesn't support link status query.
+/// - `Error::TimedOut`: the link is still down after `timeout`.
+#[inline]
+pub async fn wait_for_link_up(addr: &IpAddr, timeout: Duration) -> Result<LinkStatus> {
+    let deadline = Instant::now()
+        .checked_add(timeout)
+        .ok_or(Error::InvalidArg)?;
+    loop {
+        let link = link(addr)?;
+        if link.up {
+            return Ok(link);
+        }
+        if Instant::now() >= deadline {
+            return Err(Error::TimedOut);
+        }
+        time::sleep(LINK_POLL_INTERVAL).await;
+    }
+}
+
+/// Apply `f` to the device bound to `addr`.
+fn with_device<T, F: FnOnce(&InetDevice) -> Result<T>>(addr: &IpAddr, f: F) -> Result<T> {
+    let inet_device = INET_DEVICE.read().map_err(Error::from)?;
+    let dev = inet_device
+        .iter()
+        .find(|dev| dev.has_ip(addr))
+        .ok_or(Error::NoDev)?;
+    f(dev)
+}
+
+/// Stop and remove all probed devices, and clear the routes through them.
+///
+/// Returns the arguments of the devices removed, with which they can be probed again.
+pub(crate) fn device_shutdown() -> Result<Vec<String>> {
+    let mut inet_device = INET_DEVICE.write().map_err(Error::from)?;
+    let mut devargs = Vec::with_capacity(inet_device.len());
+    for mut dev in inet_device.drain(..) {
+        let port_id = dev.ethdev.port_id();
+        if dev.running {
+            arp::unregister_iface(port_id)?;
+            dev.ethdev.stop()?;
+        }
+        unsubscribe_events(port_id)?;
+        bpf::set_port_filter(port_id, None)?;
+        devargs.push(dev.ethdev.devargs()?);
+        remove_port(dev.ethdev)?;
+        debug!("Ethdev {port_id} shut down");
+    }
+    route::clear()?;
+    Ok(devargs)
+}
+
+/// Choose the local IP address to reach `dst`.
+///
+/// `dst` itself is chosen if it's a loopback address or an address of a running device,
+/// otherwise the address whose subnet holds `dst` with the longest prefix, or the address of the
+/// route to `dst`, or the first address of the same family of a running device.
+pub(crate) fn local_ip_for(dst: IpAddr) -> Result<IpAddr> {
+    let route_dev = route::lookup(dst)?.map(|route| route.dev);
+    let inet_device = INET_DEVICE.read().map_err(Error::from)?;
+    let running = || inet_device.iter().filter(|dev| dev.running);
+    if dst.is_loopback() || running().any(|dev| dev.has_ip(&dst)) {
+        return Ok(dst);
+    }
+    let on_link = running()
+        .flat_map(InetDevice::addresses)
+        .filter(|addr| addr.contains(dst))
+        .max_by_key(|addr| addr.prefix_len);
+    if let Some(addr) = on_link {
+        return Ok(addr.ip);
+    }
+    if let Some(ip) = route_dev.filter(|ip| running().any(|dev| dev.has_ip(ip))) {
+        return Ok(ip);
+    }
+    running()
+        .flat_map(InetDevice::addresses)
+        .map(|addr| addr.ip)
+        .find(|ip| ip.is_ipv4() == dst.is_ipv4())
+        .ok_or(Error::NoDev)
+}
+
+/// The address of the device on `port_id` whose subnet has the directed broadcast address
+/// `dst`, if any.
+pub(crate) fn broadcast_owner(port_id: u16, dst: Ipv4Addr) -> Option<IpAddr> {
+    let inet_device = INET_DEVICE.read().ok()?;
+    inet_device
+        .iter()
+        .find(|dev| dev.ethdev.port_id() == port_id)?
+        .addresses()
+        .into_iter()
+        .find(|addr| addr.broadcast() == Some(dst))
+        .map(|addr| addr.ip)
+}
+
+/// Whether `ip` is the address of a running device, or a loopback address.
+pub(crate) fn is_local_ip(ip: IpAddr) -> Result<bool> {
+    if ip.is_loopback() {
+        return Ok(true);
+    }
+    let inet_device = INET_DEVICE.read().map_err(Error::from)?;
+    Ok(inet_device.iter().any(|dev| dev.running && dev.has_ip(&ip)))
+}
+
+/// Get a device from an IP address.
+///
+/// The returned result will be a tuple of a `TxSender` sending messages to that device and its Ether
+/// address.
+pub(crate) fn find_dev_by_ip(ip: IpAddr) -> Result<(TxSender, rte_ether_addr)> {
+    let inet_device = INET_DEVICE.read().map_err(Error::from)?;
+    let inet_iter = inet_device.iter();
+    for dev in inet_iter {
+        if dev.has_ip(&ip) {
+            if !dev.running {
+                error!("Device is not running!");
+                return Err(Error::NoDev);
+            }
+            let sender = dev.ethdev.sender(0).ok_or(Error::NotStart)?;
+            let addr = dev.ethdev.ether_addr()?;
+            return Ok((sender, addr));
+        }
+        if ip.is_unspecified() || ip.is_loopback() {
+            if !dev.running {
+                debug!("Device is not running, try the next one");
+                continue;
+            }
+            let sender = dev.ethdev.sender(0).ok_or(Error::NotStart)?;
+            let addr = dev.ethdev.ether_addr()?;
+            return Ok((sender, addr));
+        }
+    }
+    error!("Ip address {ip} not matched to any address");
+    Err(Error::InvalidArg)
+}
+
+/// Get the port id of the device bound to `addr`.
+pub(crate) fn port_id(addr: &IpAddr) -> Result<u16> {
+    with_device(addr, |dev| Ok(dev.ethdev.port_id()))
+}
+
+/// Get the port id of the running device bound to `ip`, along with a `TxSender` sending messages
+/// to it.
+pub(crate) fn find_port_by_ip(ip: IpAddr) -> Result<(u16, TxSender)> {
+    let inet_device = INET_DEVICE.read().map_err(Error::from)?;
+    let dev = inet_device
+        .iter()
+        .find(|dev| dev.has_ip(&ip))
+        .ok_or(Error::NoDev)?;
+    if !dev.running {
+        error!("Device is not running!");
+        return Err(Error::NoDev);
+    }
+    let sender = dev.ethdev.sender(0).ok_or(Error::NotStart)?;
+    Ok((dev.ethdev.port_id(), sender))
 }
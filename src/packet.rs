@@ -1,13 +1,28 @@
 //! Generic L3 packet.
 
 use crate::{
-    mbuf::Mbuf,
+    eth_dev::RTE_ETH_TX_OFFLOAD_MULTI_SEGS,
+    headers::{EtherHdrMut, Ipv4HdrMut, Ipv6HdrMut, UdpHdrMut},
+    mbuf::{self, Mbuf},
     mempool::PktMempool,
-    proto::{L3Protocol, L4Protocol, Protocol, ETHER_HDR_LEN, PTYPE_L2_ETHER},
-    Result,
+    proto::{
+        cksum_add, cksum_fold, ipv4_pseudo_sum, ipv6_pseudo_sum, socket::IPID, L3Protocol,
+        L4Protocol, Protocol, ETHER_HDR_LEN, IP_NEXT_PROTO_UDP, PTYPE_L2_ETHER,
+    },
+    Error, Result,
+};
+use bytes::BytesMut;
+use dpdk_sys::{
+    RTE_ETHER_TYPE_IPV4, RTE_ETHER_TYPE_IPV6, RTE_MBUF_F_TX_IPV4, RTE_MBUF_F_TX_IPV6,
+    RTE_MBUF_F_TX_IP_CKSUM, RTE_MBUF_F_TX_UDP_CKSUM, RTE_PTYPE_L3_MASK, RTE_PTYPE_L4_MASK,
+};
+use std::{
+    fmt::Debug,
+    mem,
+    net::{Ipv4Addr, Ipv6Addr},
+    ops::Deref,
+    sync::atomic::Ordering,
 };
-use bytes::{BufMut, BytesMut};
-use dpdk_sys::{RTE_PTYPE_L3_MASK, RTE_PTYPE_L4_MASK};
 
 /// Mask for L3 protocol id in `rte_mbuf`.
 const L3_MASK: u32 = RTE_PTYPE_L3_MASK;
@@ -15,6 +30,48 @@ const L3_MASK: u32 = RTE_PTYPE_L3_MASK;
 /// Mask for L4 protocol id in `rte_mbuf`.
 const L4_MASK: u32 = RTE_PTYPE_L4_MASK;
 
+/// Offset of `tso_segsz` in `tx_offload` of `rte_mbuf`, after `l2_len:7`, `l3_len:9` and
+/// `l4_len:8`.
+const TSO_SEGSZ_SHIFT: u64 = 24;
+
+/// A fragment of a `Packet`.
+pub(crate) enum Frag {
+    /// Data owned by the packet, which is copied into `Mbuf`s.
+    Owned(BytesMut),
+    /// Data shared with the application, which is attached to `Mbuf`s without copying.
+    Shared(Box<dyn AsRef<[u8]> + Send + Sync>),
+}
+
+impl Deref for Frag {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        match *self {
+            Frag::Owned(ref data) => data,
+            Frag::Shared(ref data) => (**data).as_ref(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for Frag {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Debug for Frag {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match *self {
+            Frag::Owned(_) => "Owned",
+            Frag::Shared(_) => "Shared",
+        };
+        f.debug_tuple(kind).field(&self.len()).finish()
+    }
+}
+
 /// Generic packet. By default, it's an network layer packet.
 ///
 /// It is equivalent to a `Mbuf` without L2 header. It consists of several memory slices for easy
@@ -25,8 +82,13 @@ pub struct Packet {
     pub l3protocol: L3Protocol,
     /// L4 (Transport layer) protocol.
     pub l4protocol: L4Protocol,
-    /// Fragments of slices. `BytesMut` indicates that `Packet` owns its fragments exclusively.
-    pub(crate) frags: Vec<BytesMut>,
+    /// Fragments of slices, either owned by the `Packet` or shared with the application.
+    pub(crate) frags: Vec<Frag>,
+    /// TX offload flags to be populated in `rte_mbuf`.
+    pub(crate) ol_flags: u64,
+    /// Max payload size of the segments split by the hardware with TCP segmentation offload, or
+    /// 0 if not segmented.
+    pub(crate) tso_segsz: u16,
 }
 
 #[allow(unsafe_code)]
@@ -39,19 +101,42 @@ impl Packet {
             frags: vec![],
             l3protocol,
             l4protocol,
+            ol_flags: 0,
+            tso_segsz: 0,
         }
     }
 
+    /// Total length of the fragments.
+    pub(crate) fn len(&self) -> usize {
+        self.frags.iter().map(|frag| frag.len()).sum()
+    }
+
     /// Append fragment
     #[inline]
     pub fn append(&mut self, frag: BytesMut) {
-        self.frags.push(frag);
+        self.frags.push(Frag::Owned(frag));
+    }
+
+    /// Append a fragment shared with the application, e.g. `Bytes` or `Arc<[u8]>`, which is
+    /// attached to the `Mbuf`s without copying when sent, and dropped after being transmitted.
+    ///
+    /// It's copied like other fragments if EAL is not in IOVA as VA mode, see
+    /// `Mbuf::from_shared`.
+    #[inline]
+    pub fn append_shared<T>(&mut self, frag: T)
+    where
+        T: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        self.frags.push(Frag::Shared(Box::new(frag)));
     }
 
     /// Takes the ownership of a `Mbuf` and convert it to a `Packet` instance.
+    ///
+    /// The data of `Mbuf` is copied, and the `Mbuf` is given back to its mempool.
     #[inline]
+    #[must_use]
     #[allow(clippy::needless_pass_by_value)]
-    pub(crate) fn from_mbuf(m: Mbuf) -> Self {
+    pub fn from_mbuf(m: Mbuf) -> Self {
         // XXX protocol information in rte_mbuf may be incorrect
         let (l3protocol, l4protocol): (L3Protocol, L4Protocol) = {
             // SAFETY: mbuf pointer checked upon its allocation
@@ -64,80 +149,364 @@ impl Packet {
         let mut frags = vec![];
         for cur in m.iter() {
             let data = cur.data_slice();
-            frags.push(data.into());
+            frags.push(Frag::Owned(data.into()));
         }
 
         Packet {
             l3protocol,
             l4protocol,
             frags,
+            ol_flags: 0,
+            tso_segsz: 0,
         }
     }
 
     /// Convert a `Packet` to a `Mbuf`.
+    ///
+    /// Owned fragments are copied into the `Mbuf`s, while shared ones are attached to their own
+    /// segments if possible, i.e. `tx_offloads` of the device has `RTE_ETH_TX_OFFLOAD_MULTI_SEGS`.
+    /// The offload flags of the `Packet` are set on the `Mbuf`.
     #[inline]
-    pub(crate) fn into_mbuf(mut self, mp: &PktMempool) -> Result<Mbuf> {
-        let mut tail = Mbuf::new(mp)?;
+    pub(crate) fn into_mbuf(self, mp: &PktMempool, tx_offloads: u64) -> Result<Mbuf> {
+        let zero_copy =
+            tx_offloads & RTE_ETH_TX_OFFLOAD_MULTI_SEGS != 0 && mbuf::extbuf_supported();
         let mut head: Option<Mbuf> = None;
-        for frag in &mut self.frags {
-            let mut len = frag.len();
-            while len > tail.tailroom() {
+        let mut tail = Mbuf::new(mp)?;
+        for frag in self.frags {
+            if zero_copy && matches!(frag, Frag::Shared(_)) {
+                let ext = Mbuf::from_shared(mp, frag)?;
+                let filled = mem::replace(&mut tail, Mbuf::new(mp)?);
+                if filled.data_len() > 0 {
+                    chain(&mut head, filled)?;
+                }
+                chain(&mut head, ext)?;
+                continue;
+            }
+            let mut data: &[u8] = &frag;
+            while data.len() > tail.tailroom() {
                 if tail.tailroom() == 0 {
-                    if let Some(m) = head.as_mut() {
-                        if let Err((err, _)) = m.chain_mbuf(tail) {
-                            return Err(err);
-                        }
-                    } else {
-                        head = Some(tail);
-                    }
                     // Out of space, should alloc a new mbuf.
-                    tail = Mbuf::new(mp)?;
-                }
-                let delta = tail.tailroom();
-                let data = tail.append(delta)?;
-                #[allow(clippy::indexing_slicing)]
-                // frag.len() > delta, implied by while condition
-                data.copy_from_slice(&frag[..delta]); // TODO: zero-copy
-                len = len.wrapping_sub(delta);
-                // SAFETY: delta > frag's remain size
-                unsafe {
-                    frag.advance_mut(delta);
+                    let filled = mem::replace(&mut tail, Mbuf::new(mp)?);
+                    chain(&mut head, filled)?;
                 }
+                let (chunk, rest) = data
+                    .split_at_checked(tail.tailroom())
+                    .ok_or(Error::OutOfRange)?;
+                tail.append(chunk.len())?.copy_from_slice(chunk);
+                data = rest;
             }
-            let data = tail.append(len)?;
-            data.copy_from_slice(frag); // TODO: zero-copy
+            tail.append(data.len())?.copy_from_slice(data);
         }
-        let mbuf = head.unwrap_or(tail);
-        // SAFETY: mbuf pointer checked upon its allocation
-        let m = unsafe { &mut *(mbuf.as_ptr()) };
-        m.packet_type_union.packet_type =
-            PTYPE_L2_ETHER | self.l3protocol as u32 | self.l4protocol as u32;
-        // SAFETY: access to union field
-        unsafe {
-            m.tx_offload_union
-                .tx_offload_struct
-                .set_l2_len(ETHER_HDR_LEN);
-            m.tx_offload_union
-                .tx_offload_struct
-                .set_l3_len(self.l3protocol.length());
-            m.tx_offload_union
-                .tx_offload_struct
-                .set_l4_len(self.l4protocol.length());
+        let mbuf = match head {
+            Some(mut head) if tail.data_len() > 0 => {
+                head.chain_mbuf(tail).map_err(|(err, _)| err)?;
+                head
+            }
+            Some(head) => head,
+            None => tail,
+        };
+        set_packet_type(&mbuf, self.l3protocol, self.l4protocol);
+        set_tx_offload(&mbuf, self.ol_flags);
+        if self.tso_segsz > 0 {
+            set_tso_segsz(&mbuf, self.tso_segsz);
         }
         Ok(mbuf)
     }
 }
 
+/// Chain `seg` to the end of `head`, or make it the head if there's none.
+fn chain(head: &mut Option<Mbuf>, seg: Mbuf) -> Result<()> {
+    if let Some(m) = head.as_mut() {
+        m.chain_mbuf(seg).map_err(|(err, _)| err)
+    } else {
+        *head = Some(seg);
+        Ok(())
+    }
+}
+
+/// Set the packet type and header lengths of an Ethernet frame to be sent.
+#[allow(unsafe_code)]
+pub(crate) fn set_packet_type(mbuf: &Mbuf, l3protocol: L3Protocol, l4protocol: L4Protocol) {
+    // SAFETY: mbuf pointer checked upon its allocation
+    let m = unsafe { &mut *(mbuf.as_ptr()) };
+    m.packet_type_union.packet_type = PTYPE_L2_ETHER | l3protocol as u32 | l4protocol as u32;
+    // SAFETY: access to union field
+    unsafe {
+        m.tx_offload_union
+            .tx_offload_struct
+            .set_l2_len(ETHER_HDR_LEN);
+        m.tx_offload_union
+            .tx_offload_struct
+            .set_l3_len(l3protocol.length());
+        m.tx_offload_union
+            .tx_offload_struct
+            .set_l4_len(l4protocol.length());
+    }
+}
+
+/// Request TX offloads, e.g. checksum computation, of an Ethernet frame to be sent.
+#[allow(unsafe_code)]
+pub(crate) fn set_tx_offload(mbuf: &Mbuf, ol_flags: u64) {
+    // SAFETY: mbuf pointer checked upon its allocation
+    let m = unsafe { &mut *(mbuf.as_ptr()) };
+    m.ol_flags |= ol_flags;
+}
+
+/// Set the max payload size of the segments or fragments split by the hardware from a packet to
+/// be sent.
+#[allow(unsafe_code)]
+pub(crate) fn set_tso_segsz(mbuf: &Mbuf, segsz: u16) {
+    // SAFETY: mbuf pointer checked upon its allocation
+    let m = unsafe { &mut *(mbuf.as_ptr()) };
+    // SAFETY: access union type
+    unsafe {
+        m.tx_offload_union.tx_offload &= !(0xffff << TSO_SEGSZ_SHIFT);
+        m.tx_offload_union.tx_offload |= u64::from(segsz) << TSO_SEGSZ_SHIFT;
+    }
+}
+
+/// The IP header to be built by `PacketBuilder`.
+#[derive(Debug, Clone, Copy)]
+#[allow(variant_size_differences)] // held by value in the builder only
+enum IpHdrSpec {
+    /// IPv4 source and destination addresses, and the TTL.
+    V4(Ipv4Addr, Ipv4Addr, u8),
+    /// IPv6 source and destination addresses, and the hop limit.
+    V6(Ipv6Addr, Ipv6Addr, u8),
+}
+
+/// Builder of UDP datagrams in Ethernet frames, which fills in the lengths and checksums of
+/// the headers.
+///
+/// Each layer is set by its method, and `build` fails if any of them is missing.
+///
+/// # Examples
+///
+/// ```
+/// # use async_dpdk::packet::PacketBuilder;
+/// # use std::net::Ipv4Addr;
+/// let pkt = PacketBuilder::new()
+///     .ethernet([0x02, 0, 0, 0, 0, 1], [0x02, 0, 0, 0, 0, 2])
+///     .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), 64)
+///     .udp(1234, 5678)
+///     .build(b"hello")
+///     .unwrap();
+/// assert!(PacketBuilder::new().udp(1234, 5678).build(b"hello").is_err());
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PacketBuilder {
+    /// Source and destination Ether addresses.
+    ether: Option<([u8; 6], [u8; 6])>,
+    /// The IP header.
+    ip: Option<IpHdrSpec>,
+    /// Source and destination ports.
+    udp: Option<(u16, u16)>,
+    /// Whether the IPv4 header checksum is computed by the hardware.
+    ip_cksum_offload: bool,
+    /// Whether the UDP checksum is computed by the hardware.
+    udp_cksum_offload: bool,
+    /// IPv4 type of service or IPv6 traffic class.
+    tos: u8,
+}
+
+impl PacketBuilder {
+    /// Create a builder with no layer set.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the source and destination Ether addresses.
+    #[inline]
+    #[must_use]
+    pub fn ethernet(mut self, src: [u8; 6], dst: [u8; 6]) -> Self {
+        self.ether = Some((src, dst));
+        self
+    }
+
+    /// Set the IPv4 source and destination addresses, and the time to live.
+    #[inline]
+    #[must_use]
+    pub fn ipv4(mut self, src: Ipv4Addr, dst: Ipv4Addr, ttl: u8) -> Self {
+        self.ip = Some(IpHdrSpec::V4(src, dst, ttl));
+        self
+    }
+
+    /// Set the IPv6 source and destination addresses, and the hop limit.
+    #[inline]
+    #[must_use]
+    pub fn ipv6(mut self, src: Ipv6Addr, dst: Ipv6Addr, hop_limit: u8) -> Self {
+        self.ip = Some(IpHdrSpec::V6(src, dst, hop_limit));
+        self
+    }
+
+    /// Set the UDP source and destination ports.
+    #[inline]
+    #[must_use]
+    pub fn udp(mut self, src_port: u16, dst_port: u16) -> Self {
+        self.udp = Some((src_port, dst_port));
+        self
+    }
+
+    /// Set the IPv4 type of service or IPv6 traffic class, which is 0 by default.
+    #[inline]
+    #[must_use]
+    pub fn tos(mut self, tos: u8) -> Self {
+        self.tos = tos;
+        self
+    }
+
+    /// Leave the IPv4 header checksum to the hardware, which must support
+    /// `RTE_ETH_TX_OFFLOAD_IPV4_CKSUM`.
+    #[inline]
+    #[must_use]
+    pub fn ip_cksum_offload(mut self, enable: bool) -> Self {
+        self.ip_cksum_offload = enable;
+        self
+    }
+
+    /// Leave the UDP checksum to the hardware, which must support
+    /// `RTE_ETH_TX_OFFLOAD_UDP_CKSUM`.
+    #[inline]
+    #[must_use]
+    pub fn udp_cksum_offload(mut self, enable: bool) -> Self {
+        self.udp_cksum_offload = enable;
+        self
+    }
+
+    /// Build a datagram carrying `payload`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: a layer is not set, or the payload is too long.
+    #[inline]
+    pub fn build(&self, payload: &[u8]) -> Result<Packet> {
+        let payload_sum = (!self.udp_cksum_offload).then(|| cksum_add(0, payload));
+        let (hdr, l3protocol, ol_flags) = self.headers(payload.len(), payload_sum)?;
+        let mut pkt = Packet::new(l3protocol, L4Protocol::Udp);
+        pkt.ol_flags = ol_flags;
+        pkt.append(hdr);
+        pkt.append(BytesMut::from(payload));
+        Ok(pkt)
+    }
+
+    /// Build the headers of a datagram, returning the headers, the L3 protocol and the TX
+    /// offload flags.
+    ///
+    /// `payload_sum` is the ones' complement sum of the payload, or `None` if the UDP checksum is
+    /// offloaded to the hardware.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn headers(
+        &self,
+        payload_len: usize,
+        payload_sum: Option<u32>,
+    ) -> Result<(BytesMut, L3Protocol, u64)> {
+        let (src_mac, dst_mac) = self.ether.ok_or(Error::InvalidArg)?;
+        let ip = self.ip.ok_or(Error::InvalidArg)?;
+        let (src_port, dst_port) = self.udp.ok_or(Error::InvalidArg)?;
+        let (l3protocol, ether_type) = match ip {
+            IpHdrSpec::V4(..) => (L3Protocol::Ipv4, RTE_ETHER_TYPE_IPV4 as u16),
+            IpHdrSpec::V6(..) => (L3Protocol::Ipv6, RTE_ETHER_TYPE_IPV6 as u16),
+        };
+
+        let l3_off = usize::from(ETHER_HDR_LEN);
+        let l4_off = l3_off.wrapping_add(l3protocol.length().into());
+        let l4_sz = L4Protocol::Udp.length();
+        let payload_len: u16 = payload_len.try_into().map_err(Error::from)?;
+        let dgram_len = payload_len.checked_add(l4_sz).ok_or(Error::InvalidArg)?;
+        let mut hdr = BytesMut::zeroed(l4_off.wrapping_add(l4_sz.into()));
+
+        // fill l2 header
+        let mut ether_hdr = EtherHdrMut::new(&mut hdr)?;
+        ether_hdr.set_src_addr(src_mac);
+        ether_hdr.set_dst_addr(dst_mac);
+        ether_hdr.set_ether_type(ether_type);
+
+        // fill l3 header
+        let l3_hdr = hdr.get_mut(l3_off..).ok_or(Error::OutOfRange)?;
+        let (pseudo_sum, mut ol_flags, l3_flag) = match ip {
+            IpHdrSpec::V4(src, dst, ttl) => {
+                let total_len = dgram_len
+                    .checked_add(L3Protocol::Ipv4.length())
+                    .ok_or(Error::InvalidArg)?;
+                let mut ip_hdr = Ipv4HdrMut::new(l3_hdr)?;
+                ip_hdr.set_version_ihl(0x45); // version = 4, ihl = 5
+                ip_hdr.set_type_of_service(self.tos);
+                ip_hdr.set_total_length(total_len);
+                ip_hdr.set_packet_id(IPID.fetch_add(1, Ordering::AcqRel));
+                ip_hdr.set_time_to_live(ttl);
+                ip_hdr.set_next_proto_id(IP_NEXT_PROTO_UDP);
+                ip_hdr.set_dst_addr(dst);
+                ip_hdr.set_src_addr(src);
+                let ol_flags = if self.ip_cksum_offload {
+                    RTE_MBUF_F_TX_IP_CKSUM
+                } else {
+                    ip_hdr.update_checksum();
+                    0
+                };
+                let sum = ipv4_pseudo_sum(src.octets(), dst.octets(), IP_NEXT_PROTO_UDP, dgram_len);
+                (sum, ol_flags, RTE_MBUF_F_TX_IPV4)
+            }
+            IpHdrSpec::V6(src, dst, hop_limit) => {
+                let mut ip_hdr = Ipv6HdrMut::new(l3_hdr)?;
+                ip_hdr.set_vtc_flow((6_u32 << 28) | (u32::from(self.tos) << 20)); // version = 6
+                ip_hdr.set_payload_len(dgram_len);
+                ip_hdr.set_proto(IP_NEXT_PROTO_UDP);
+                ip_hdr.set_hop_limits(hop_limit);
+                ip_hdr.set_src_addr(src);
+                ip_hdr.set_dst_addr(dst);
+                let sum = ipv6_pseudo_sum(
+                    src.octets(),
+                    dst.octets(),
+                    IP_NEXT_PROTO_UDP,
+                    u32::from(dgram_len),
+                );
+                (sum, 0, RTE_MBUF_F_TX_IPV6)
+            }
+        };
+
+        // fill l4 header
+        let dgram_cksum = if let Some(payload_sum) = payload_sum {
+            let sum = cksum_add(pseudo_sum, &src_port.to_be_bytes());
+            let sum = cksum_add(sum, &dst_port.to_be_bytes());
+            let sum = cksum_add(sum, &dgram_len.to_be_bytes());
+            // 0 means no checksum, which is transmitted as all ones.
+            match cksum_fold(sum.wrapping_add(payload_sum)) {
+                0 => 0xffff,
+                cksum => cksum,
+            }
+        } else {
+            // The hardware computes the checksum seeded with the pseudo header sum.
+            ol_flags |= RTE_MBUF_F_TX_UDP_CKSUM;
+            !cksum_fold(pseudo_sum)
+        };
+        if ol_flags != 0 {
+            ol_flags |= l3_flag;
+        }
+        let mut udp_hdr = UdpHdrMut::new(hdr.get_mut(l4_off..).ok_or(Error::OutOfRange)?)?;
+        udp_hdr.set_src_port(src_port);
+        udp_hdr.set_dst_port(dst_port);
+        udp_hdr.set_dgram_len(dgram_len);
+        udp_hdr.set_dgram_cksum(dgram_cksum);
+        Ok((hdr, l3protocol, ol_flags))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Packet;
+    use super::{Packet, PacketBuilder};
     use crate::{
-        mbuf::Mbuf,
+        eth_dev::RTE_ETH_TX_OFFLOAD_MULTI_SEGS,
+        mbuf::{self, Mbuf},
         mempool::{Mempool, PktMempool},
-        proto::{L3Protocol, L4Protocol},
+        proto::{cksum_add, cksum_fold, ipv4_pseudo_sum, L3Protocol, L4Protocol},
         test_utils,
     };
-    use bytes::BytesMut;
+    use bytes::{Bytes, BytesMut};
+    use std::net::Ipv4Addr;
 
     #[test]
     fn test() {
@@ -156,7 +525,7 @@ mod tests {
         assert_eq!(pkt.frags.len(), 1);
         assert_eq!(&pkt.frags[0][..], &[0, 1, 2, 3, 4]);
 
-        let mb2 = pkt.into_mbuf(&mp).unwrap();
+        let mb2 = pkt.into_mbuf(&mp, 0).unwrap();
         assert_eq!(mb2.num_segs(), 1);
         assert_eq!(mb2.data_slice(), &[0, 1, 2, 3, 4]);
 
@@ -179,11 +548,67 @@ mod tests {
         assert_eq!(&pkt.frags[1][..], &[1, 1, 1, 1, 1]);
         assert_eq!(&pkt.frags[2][..], &[2, 2, 2, 2, 2]);
 
-        let mb4 = pkt.into_mbuf(&mp).unwrap();
+        let mb4 = pkt.into_mbuf(&mp, 0).unwrap();
         assert_eq!(mb4.num_segs(), 1);
         assert_eq!(
             mb4.data_slice(),
             &[0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2]
         );
     }
+
+    #[test]
+    #[allow(unsafe_code)]
+    fn test_shared() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("pktmpool_shared", 10).unwrap();
+        let mut pkt = Packet::new(L3Protocol::Ipv4, L4Protocol::Udp);
+        pkt.append(BytesMut::from(&[1, 2][..]));
+        pkt.append_shared(Bytes::from_static(&[3, 4, 5]));
+        pkt.append(BytesMut::from(&[6][..]));
+        assert_eq!(&pkt.frags[1][..], &[3, 4, 5]);
+        let m = pkt.into_mbuf(&mp, RTE_ETH_TX_OFFLOAD_MULTI_SEGS).unwrap();
+        assert_eq!(m.copy_to_vec(0, 6).unwrap(), &[1, 2, 3, 4, 5, 6]);
+        let n_segs = if mbuf::extbuf_supported() { 3 } else { 1 };
+        assert_eq!(m.num_segs(), n_segs);
+
+        // Shared fragments are copied if the device can't send chained `Mbuf`s.
+        let mut pkt = Packet::new(L3Protocol::Ipv4, L4Protocol::Udp);
+        pkt.append_shared(Bytes::from_static(&[3, 4, 5]));
+        pkt.tso_segsz = 1460;
+        let m = pkt.into_mbuf(&mp, 0).unwrap();
+        assert_eq!(m.num_segs(), 1);
+        assert_eq!(m.data_slice(), &[3, 4, 5]);
+        // SAFETY: mbuf pointer checked upon its allocation
+        let tx_offload = unsafe { (*m.as_ptr()).tx_offload_union.tx_offload };
+        assert_eq!((tx_offload >> 24) & 0xffff, 1460);
+    }
+
+    #[test]
+    fn test_builder() {
+        let src = Ipv4Addr::new(192, 168, 0, 1);
+        let dst = Ipv4Addr::new(192, 168, 0, 199);
+        let builder = PacketBuilder::new().ethernet([1; 6], [2; 6]);
+        assert!(builder.udp(1, 2).build(&[]).is_err());
+        assert!(builder.ipv4(src, dst, 64).build(&[]).is_err());
+        let pkt = builder
+            .ipv4(src, dst, 32)
+            .udp(1234, 80)
+            .build(b"hi")
+            .unwrap();
+        assert!(matches!(pkt.l3protocol, L3Protocol::Ipv4));
+        assert_eq!(pkt.frags.len(), 2);
+        assert_eq!(&pkt.frags[1][..], b"hi");
+        let hdr = &pkt.frags[0];
+        assert_eq!(hdr.len(), 42);
+        assert_eq!(&hdr[..14], &[2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 0x08, 0]);
+        // total length, TTL and protocol
+        assert_eq!(&hdr[16..18], &[0, 30]);
+        assert_eq!(&hdr[22..24], &[32, 17]);
+        // ports, length, and a checksum summing up to 0xffff with the pseudo header
+        assert_eq!(&hdr[34..40], &[0x04, 0xd2, 0, 80, 0, 10]);
+        let sum = ipv4_pseudo_sum(src.octets(), dst.octets(), 17, 10);
+        let sum = cksum_add(cksum_add(sum, &hdr[34..]), b"hi");
+        assert_eq!(cksum_fold(sum), 0);
+        assert_eq!(pkt.ol_flags, 0);
+    }
 }
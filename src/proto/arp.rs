@@ -0,0 +1,435 @@
+//! ARP implementation.
+//!
+//! Requests and replies are handled in `RxAgent`. Resolved addresses are kept in a neighbor cache
+//! keyed by IP, and sockets resolve the L2 destination address asynchronously before sending.
+//! Destinations reached through a gateway in the routing table resolve to the gateway's address.
+
+use crate::{
+    eth_dev::TxSender,
+    mbuf::Mbuf,
+    net_dev::IfAddr,
+    packet::Packet,
+    proto::{route, L3Protocol, L4Protocol, ETHER_HDR_LEN},
+    trace::{debug, trace, warn},
+    Error, Result,
+};
+use bytes::{BufMut, BytesMut};
+use dpdk_sys::{rte_ether_addr, RTE_ETHER_TYPE_ARP, RTE_ETHER_TYPE_IPV4};
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::{sync::oneshot, time};
+
+/// ARP header length for Ethernet & IPv4.
+pub(crate) const ARP_HDR_LEN: usize = 28;
+
+/// Hardware type of Ethernet.
+const ARP_HRD_ETHER: u16 = 1;
+
+/// ARP request opcode.
+const ARP_OP_REQUEST: u16 = 1;
+
+/// ARP reply opcode.
+const ARP_OP_REPLY: u16 = 2;
+
+/// Length of an Ethernet address.
+const ETHER_ADDR_LEN: u8 = 6;
+
+/// Length of an IPv4 address.
+const IPV4_ADDR_LEN: u8 = 4;
+
+/// How long a resolved entry stays valid.
+const ARP_ENTRY_TTL: Duration = Duration::from_secs(300);
+
+/// How long to wait for a reply before an ARP request is resent.
+const ARP_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of ARP requests sent before the resolution fails.
+const ARP_MAX_RETRIES: usize = 3;
+
+lazy_static! {
+    /// The neighbor cache, mapping IP addresses to Ether addresses.
+    static ref NEIGH_TABLE: Mutex<HashMap<Ipv4Addr, Neighbor>> = Mutex::new(HashMap::new());
+    /// Interfaces answering ARP requests, keyed by port id.
+    static ref ARP_IFACES: RwLock<HashMap<u16, ArpIface>> = RwLock::new(HashMap::new());
+}
+
+/// An entry in the neighbor cache.
+#[derive(Debug)]
+enum Neighbor {
+    /// The Ether address is known.
+    Resolved {
+        /// Ether address of the neighbor.
+        mac: rte_ether_addr,
+        /// When the entry was last updated.
+        updated: Instant,
+    },
+    /// A request has been sent, and these waiters are waiting for the reply.
+    Pending {
+        /// Waiters to be notified once the reply arrives.
+        waiters: Vec<oneshot::Sender<rte_ether_addr>>,
+    },
+}
+
+/// A started device answering ARP requests for its IPs.
+#[derive(Debug)]
+struct ArpIface {
+    /// IP addresses of the device.
+    addrs: Vec<IfAddr>,
+    /// Ether address of the device.
+    mac: rte_ether_addr,
+    /// A channel to `TxAgent` of the device.
+    tx: TxSender,
+}
+
+impl ArpIface {
+    /// Whether `ip` is one of the addresses of the device.
+    fn has_ip(&self, ip: IpAddr) -> bool {
+        self.addrs.iter().any(|addr| addr.ip == ip)
+    }
+}
+
+/// An ARP packet for Ethernet & IPv4.
+#[derive(Debug, Clone, Copy)]
+struct ArpPacket {
+    /// Opcode.
+    oper: u16,
+    /// Sender hardware address.
+    sha: [u8; 6],
+    /// Sender protocol address.
+    spa: Ipv4Addr,
+    /// Target hardware address.
+    tha: [u8; 6],
+    /// Target protocol address.
+    tpa: Ipv4Addr,
+}
+
+impl ArpPacket {
+    /// Parse an ARP packet, starting from the ARP header.
+    ///
+    /// Only Ethernet & IPv4 ARP packets are recognized.
+    fn parse(data: &[u8]) -> Option<Self> {
+        let hdr = data.get(..ARP_HDR_LEN)?;
+        let word = |off: usize| {
+            Some(u16::from_be_bytes(
+                hdr.get(off..off.wrapping_add(2))?.try_into().ok()?,
+            ))
+        };
+        let field = |off: usize, len: usize| hdr.get(off..off.wrapping_add(len));
+        if word(0)? != ARP_HRD_ETHER
+            || u32::from(word(2)?) != RTE_ETHER_TYPE_IPV4
+            || *hdr.get(4)? != ETHER_ADDR_LEN
+            || *hdr.get(5)? != IPV4_ADDR_LEN
+        {
+            return None;
+        }
+        Some(Self {
+            oper: word(6)?,
+            sha: field(8, 6)?.try_into().ok()?,
+            spa: <[u8; 4]>::try_from(field(14, 4)?).ok()?.into(),
+            tha: field(18, 6)?.try_into().ok()?,
+            tpa: <[u8; 4]>::try_from(field(24, 4)?).ok()?.into(),
+        })
+    }
+
+    /// Build an Ethernet frame carrying this ARP packet, sent from `src` to `dst`.
+    #[allow(clippy::cast_possible_truncation)]
+    fn into_packet(self, src: rte_ether_addr, dst: [u8; 6]) -> Packet {
+        let mut buf = BytesMut::with_capacity(ARP_HDR_LEN.wrapping_add(ETHER_HDR_LEN as _));
+        buf.put_slice(&dst);
+        buf.put_slice(&src.addr_bytes);
+        buf.put_u16(RTE_ETHER_TYPE_ARP as u16);
+        buf.put_u16(ARP_HRD_ETHER);
+        buf.put_u16(RTE_ETHER_TYPE_IPV4 as u16);
+        buf.put_u8(ETHER_ADDR_LEN);
+        buf.put_u8(IPV4_ADDR_LEN);
+        buf.put_u16(self.oper);
+        buf.put_slice(&self.sha);
+        buf.put_slice(&self.spa.octets());
+        buf.put_slice(&self.tha);
+        buf.put_slice(&self.tpa.octets());
+        let mut pkt = Packet::new(L3Protocol::Unknown, L4Protocol::Unknown);
+        pkt.append(buf);
+        pkt
+    }
+}
+
+/// Register a started device, so that ARP requests for `addrs` received on `port_id` are answered.
+/// A device registered before is replaced.
+pub(crate) fn register_iface(
+    port_id: u16,
+    addrs: Vec<IfAddr>,
+    mac: rte_ether_addr,
+    tx: TxSender,
+) -> Result<()> {
+    let mut ifaces = ARP_IFACES.write().map_err(Error::from)?;
+    let _prev = ifaces.insert(port_id, ArpIface { addrs, mac, tx });
+    Ok(())
+}
+
+/// Unregister a device before it's stopped.
+pub(crate) fn unregister_iface(port_id: u16) -> Result<()> {
+    let mut ifaces = ARP_IFACES.write().map_err(Error::from)?;
+    let _prev = ifaces.remove(&port_id);
+    Ok(())
+}
+
+/// Look up the Ether address of the device on `port_id`, if `ip` is one of its addresses.
+pub(crate) fn iface_mac(port_id: u16, ip: IpAddr) -> Result<Option<rte_ether_addr>> {
+    let ifaces = ARP_IFACES.read().map_err(Error::from)?;
+    Ok(ifaces
+        .get(&port_id)
+        .filter(|iface| iface.has_ip(ip))
+        .map(|iface| iface.mac))
+}
+
+/// Send an Ethernet frame through the device on `port_id` without waiting.
+pub(crate) fn send_on(port_id: u16, m: Mbuf) -> Result<()> {
+    let ifaces = ARP_IFACES.read().map_err(Error::from)?;
+    ifaces
+        .get(&port_id)
+        .ok_or(Error::NoDev)?
+        .tx
+        .try_send_mbuf(m)
+}
+
+/// Whether `ip` is the directed broadcast address of a local subnet.
+fn is_directed_broadcast(ip: Ipv4Addr) -> Result<bool> {
+    let ifaces = ARP_IFACES.read().map_err(Error::from)?;
+    Ok(ifaces
+        .values()
+        .flat_map(|iface| &iface.addrs)
+        .any(|addr| addr.broadcast() == Some(ip)))
+}
+
+/// Look up the Ether address of a local IP address.
+fn local_mac(ip: IpAddr) -> Result<Option<rte_ether_addr>> {
+    let ifaces = ARP_IFACES.read().map_err(Error::from)?;
+    Ok(ifaces
+        .values()
+        .find(|iface| iface.has_ip(ip))
+        .map(|iface| iface.mac))
+}
+
+/// Update the neighbor cache and wake up the waiters.
+///
+/// A new entry is created only if `create` is set, otherwise only existing entries are updated.
+fn update_neighbor(ip: Ipv4Addr, mac: rte_ether_addr, create: bool) -> Result<()> {
+    let mut table = NEIGH_TABLE.lock().map_err(Error::from)?;
+    let resolved = Neighbor::Resolved {
+        mac,
+        updated: Instant::now(),
+    };
+    match table.get_mut(&ip) {
+        Some(entry) => {
+            if let Neighbor::Pending { ref mut waiters } = *entry {
+                for waiter in waiters.drain(..) {
+                    let _res = waiter.send(mac);
+                }
+            }
+            *entry = resolved;
+        }
+        None => {
+            if create {
+                let _prev = table.insert(ip, resolved);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The neighbor that packets to `ip` are sent to, which is the gateway of the route to it if any.
+fn next_hop(ip: Ipv4Addr) -> Result<Ipv4Addr> {
+    match route::next_hop(IpAddr::V4(ip))? {
+        IpAddr::V4(hop) => Ok(hop),
+        IpAddr::V6(_) => Ok(ip),
+    }
+}
+
+/// Map an IPv4 multicast address to its Ether address.
+pub(crate) fn multicast_mac(ip: Ipv4Addr) -> rte_ether_addr {
+    let [_, b1, b2, b3] = ip.octets();
+    rte_ether_addr {
+        addr_bytes: [0x01, 0x00, 0x5e, b1 & 0x7f, b2, b3],
+    }
+}
+
+/// Look up the Ether address of `ip` without sending ARP requests.
+///
+/// Broadcast, including directed broadcast to local subnets, multicast and local addresses are
+/// mapped directly. Otherwise the neighbor cache is
+/// looked up for the next hop, and `None` is returned if the entry is missing or expired.
+pub(crate) fn lookup(ip: Ipv4Addr) -> Result<Option<rte_ether_addr>> {
+    if ip.is_broadcast() || ip.is_unspecified() || is_directed_broadcast(ip)? {
+        return Ok(Some(rte_ether_addr {
+            addr_bytes: [0xff; 6],
+        }));
+    }
+    if ip.is_multicast() {
+        return Ok(Some(multicast_mac(ip)));
+    }
+    if let Some(mac) = local_mac(IpAddr::V4(ip))? {
+        return Ok(Some(mac));
+    }
+    let ip = next_hop(ip)?;
+    match NEIGH_TABLE.lock().map_err(Error::from)?.get(&ip) {
+        Some(&Neighbor::Resolved { mac, updated }) if updated.elapsed() < ARP_ENTRY_TTL => {
+            Ok(Some(mac))
+        }
+        Some(&(Neighbor::Resolved { .. } | Neighbor::Pending { .. })) | None => Ok(None),
+    }
+}
+
+/// Resolve the Ether address of `ip`, on the device with address `src_ip` and `src_mac`.
+///
+/// The address is looked up first, and ARP requests for the next hop are sent if it's not found.
+pub(crate) async fn resolve(
+    ip: Ipv4Addr,
+    src_ip: Ipv4Addr,
+    src_mac: rte_ether_addr,
+    tx: &TxSender,
+) -> Result<rte_ether_addr> {
+    if let Some(mac) = lookup(ip)? {
+        return Ok(mac);
+    }
+    let ip = next_hop(ip)?;
+    for _ in 0..ARP_MAX_RETRIES {
+        let rx = {
+            let mut table = NEIGH_TABLE.lock().map_err(Error::from)?;
+            let (waiter, rx) = oneshot::channel();
+            match table.get_mut(&ip) {
+                Some(&mut Neighbor::Resolved { mac, updated })
+                    if updated.elapsed() < ARP_ENTRY_TTL =>
+                {
+                    return Ok(mac);
+                }
+                Some(&mut Neighbor::Pending { ref mut waiters }) => waiters.push(waiter),
+                Some(&mut Neighbor::Resolved { .. }) | None => {
+                    let _prev = table.insert(
+                        ip,
+                        Neighbor::Pending {
+                            waiters: vec![waiter],
+                        },
+                    );
+                }
+            }
+            rx
+        };
+        let request = ArpPacket {
+            oper: ARP_OP_REQUEST,
+            sha: src_mac.addr_bytes,
+            spa: src_ip,
+            tha: [0; 6],
+            tpa: ip,
+        };
+        trace!("Sending ARP request for {ip}");
+        tx.send(request.into_packet(src_mac, [0xff; 6])).await?;
+        if let Ok(Ok(mac)) = time::timeout(ARP_RETRY_INTERVAL, rx).await {
+            return Ok(mac);
+        }
+    }
+    warn!("Failed to resolve the Ether address of {ip}");
+    Err(Error::TimedOut)
+}
+
+/// Resolve the Ether address of an IPv6 address.
+///
+/// Multicast and local addresses are mapped directly. Neighbor Discovery is not implemented yet,
+/// so other addresses are mapped to the broadcast address.
+pub(crate) fn resolve_v6(ip: Ipv6Addr) -> Result<rte_ether_addr> {
+    if ip.is_multicast() {
+        let [_, _, _, _, _, _, _, _, _, _, _, _, b12, b13, b14, b15] = ip.octets();
+        return Ok(rte_ether_addr {
+            addr_bytes: [0x33, 0x33, b12, b13, b14, b15],
+        });
+    }
+    Ok(local_mac(IpAddr::V6(ip))?.unwrap_or(rte_ether_addr {
+        addr_bytes: [0xff; 6],
+    }))
+}
+
+/// Handle an ARP packet, whose Ethernet header is stripped.
+///
+/// The sender is learned into the neighbor cache, and requests for a local address are replied.
+#[allow(unsafe_code)]
+pub(crate) fn handle_arp(m: &Mbuf) {
+    let Some(arp) = ArpPacket::parse(m.data_slice()) else {
+        debug!("Malformed ARP packet dropped");
+        return;
+    };
+    // SAFETY: *rte_mbuf checked
+    let port_id = unsafe { (*m.as_ptr()).port };
+    let ifaces = match ARP_IFACES.read() {
+        Ok(ifaces) => ifaces,
+        Err(e) => {
+            warn!("Failed to handle ARP packet: {e}");
+            return;
+        }
+    };
+    let iface = ifaces
+        .get(&port_id)
+        .filter(|iface| iface.has_ip(IpAddr::V4(arp.tpa)));
+    if !arp.spa.is_unspecified() {
+        let sender = rte_ether_addr {
+            addr_bytes: arp.sha,
+        };
+        if let Err(e) = update_neighbor(arp.spa, sender, iface.is_some()) {
+            warn!("Failed to update neighbor {}: {e}", arp.spa);
+        }
+    }
+    if let Some(iface) = iface {
+        if arp.oper == ARP_OP_REQUEST {
+            let reply = ArpPacket {
+                oper: ARP_OP_REPLY,
+                sha: iface.mac.addr_bytes,
+                spa: arp.tpa,
+                tha: arp.sha,
+                tpa: arp.spa,
+            };
+            trace!("Replying ARP request from {}", arp.spa);
+            if let Err(e) = iface.tx.try_send(reply.into_packet(iface.mac, arp.sha)) {
+                warn!("Failed to send ARP reply to {}: {e}", arp.spa);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArpPacket, ARP_OP_REQUEST};
+    use crate::proto::ETHER_HDR_LEN;
+    use dpdk_sys::rte_ether_addr;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_parse() {
+        let src = rte_ether_addr {
+            addr_bytes: [0, 1, 2, 3, 4, 5],
+        };
+        let request = ArpPacket {
+            oper: ARP_OP_REQUEST,
+            sha: src.addr_bytes,
+            spa: Ipv4Addr::new(10, 2, 3, 0),
+            tha: [0; 6],
+            tpa: Ipv4Addr::new(10, 2, 3, 1),
+        };
+        let pkt = request.into_packet(src, [0xff; 6]);
+        assert_eq!(pkt.frags.len(), 1);
+        let frame = &pkt.frags[0][..];
+        assert_eq!(&frame[..6], &[0xff; 6]);
+        assert_eq!(&frame[12..14], &[0x08, 0x06]);
+
+        let parsed = ArpPacket::parse(&frame[ETHER_HDR_LEN as usize..]).unwrap();
+        assert_eq!(parsed.oper, ARP_OP_REQUEST);
+        assert_eq!(parsed.sha, src.addr_bytes);
+        assert_eq!(parsed.spa, request.spa);
+        assert_eq!(parsed.tha, [0; 6]);
+        assert_eq!(parsed.tpa, request.tpa);
+
+        assert!(ArpPacket::parse(&frame[ETHER_HDR_LEN as usize..20]).is_none());
+    }
+}
@@ -0,0 +1,724 @@
+//! `DHCPv4` client.
+//!
+//! A `DhcpClient` leases an IPv4 address for a started device through the discover, offer,
+//! request and acknowledge exchange of RFC 2131. The device is usually probed with `0.0.0.0` as a
+//! placeholder, which is replaced by the leased address. The subnet route, the default route
+//! through the offered gateway and the offered DNS servers are configured in `net_dev` as well.
+//!
+//! `DhcpClient::run` keeps the lease, renewing it with the server that granted it once half of the
+//! lease time has passed, or with any server once seven eighths has passed. Renewals are scheduled
+//! with `timer::Timer`.
+//!
+//! As the client port is bound by the client, only one client can run at a time.
+//!
+//! ```no_run
+//! use async_dpdk::{dhcp::DhcpClient, net_dev};
+//! use std::net::Ipv4Addr;
+//!
+//! # async fn dhcp() -> async_dpdk::Result<()> {
+//! let placeholder = Ipv4Addr::UNSPECIFIED.into();
+//! net_dev::device_start(&placeholder)?;
+//! let mut client = DhcpClient::new(&placeholder)?;
+//! let lease = client.acquire().await?;
+//! println!("Leased {}/{}", lease.addr, lease.prefix_len);
+//! client.run().await
+//! # }
+//! ```
+
+use crate::{
+    eth_dev::TxSender,
+    net_dev::{self, IfAddr},
+    packet::PacketBuilder,
+    proto::{arp, route, udp::UdpSocket},
+    timer::Timer,
+    trace::{debug, info, warn},
+    Error, Result,
+};
+use dpdk_sys::rte_ether_addr;
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+/// UDP port of DHCP clients.
+const CLIENT_PORT: u16 = 68;
+
+/// UDP port of DHCP servers.
+const SERVER_PORT: u16 = 67;
+
+/// `op` of messages sent by clients.
+const BOOTREQUEST: u8 = 1;
+
+/// `op` of messages sent by servers.
+const BOOTREPLY: u8 = 2;
+
+/// `htype` of Ethernet.
+const HTYPE_ETHER: u8 = 1;
+
+/// Length of Ethernet addresses.
+const HLEN_ETHER: u8 = 6;
+
+/// Flag asking servers to broadcast replies, since the client can't receive unicast ones before
+/// it has an address.
+const FLAG_BROADCAST: u16 = 0x8000;
+
+/// Magic cookie preceding the options.
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// Offset of the options, right after the magic cookie.
+const OPTIONS_OFFSET: usize = 240;
+
+/// Messages are padded to the minimum length of BOOTP messages.
+const MIN_MESSAGE_LEN: usize = 300;
+
+/// Pad option.
+const OPT_PAD: u8 = 0;
+/// Subnet mask option.
+const OPT_SUBNET_MASK: u8 = 1;
+/// Router option.
+const OPT_ROUTER: u8 = 3;
+/// Domain name server option.
+const OPT_DNS: u8 = 6;
+/// Requested IP address option.
+const OPT_REQUESTED_IP: u8 = 50;
+/// IP address lease time option.
+const OPT_LEASE_TIME: u8 = 51;
+/// DHCP message type option.
+const OPT_MSG_TYPE: u8 = 53;
+/// Server identifier option.
+const OPT_SERVER_ID: u8 = 54;
+/// Parameter request list option.
+const OPT_PARAM_REQUEST: u8 = 55;
+/// Renewal (T1) time option.
+const OPT_RENEWAL_TIME: u8 = 58;
+/// Rebinding (T2) time option.
+const OPT_REBINDING_TIME: u8 = 59;
+/// End option.
+const OPT_END: u8 = 255;
+
+/// DHCPDISCOVER message type.
+const DHCPDISCOVER: u8 = 1;
+/// DHCPOFFER message type.
+const DHCPOFFER: u8 = 2;
+/// DHCPREQUEST message type.
+const DHCPREQUEST: u8 = 3;
+/// DHCPACK message type.
+const DHCPACK: u8 = 5;
+/// DHCPNAK message type.
+const DHCPNAK: u8 = 6;
+/// DHCPRELEASE message type.
+const DHCPRELEASE: u8 = 7;
+
+/// How long to wait for a reply to the first transmission of a message, which is doubled on
+/// each retransmission.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Number of transmissions of a message before giving up.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Time to live of the packets sent.
+const DEFAULT_TTL: u8 = 64;
+
+/// An address leased from a DHCP server.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    /// The leased address.
+    pub addr: Ipv4Addr,
+    /// Prefix length of the subnet, 32 if the server offers no subnet mask.
+    pub prefix_len: u8,
+    /// The default gateway, if any.
+    pub gateway: Option<Ipv4Addr>,
+    /// DNS servers.
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// Identifier of the server granting the lease.
+    pub server: Ipv4Addr,
+    /// How long the lease lasts.
+    pub lease_time: Duration,
+    /// When to renew the lease with `server`, after it's acquired.
+    pub renewal_time: Duration,
+    /// When to renew the lease with any server, after it's acquired.
+    pub rebinding_time: Duration,
+    /// When the lease is acquired.
+    acquired: Instant,
+}
+
+/// A DHCP message, of which only the fields and options used by clients are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Message {
+    /// `BOOTREQUEST` or `BOOTREPLY`.
+    op: u8,
+    /// Transaction ID.
+    xid: u32,
+    /// Flags.
+    flags: u16,
+    /// Client address, set when the client has one.
+    ciaddr: Ipv4Addr,
+    /// Address offered to the client.
+    yiaddr: Ipv4Addr,
+    /// Ethernet address of the client.
+    chaddr: [u8; 6],
+    /// DHCP message type.
+    msg_type: u8,
+    /// Requested IP address.
+    requested_ip: Option<Ipv4Addr>,
+    /// Server identifier.
+    server_id: Option<Ipv4Addr>,
+    /// Subnet mask.
+    subnet_mask: Option<Ipv4Addr>,
+    /// The first router.
+    router: Option<Ipv4Addr>,
+    /// Domain name servers.
+    dns_servers: Vec<Ipv4Addr>,
+    /// Lease time in seconds.
+    lease_time: Option<u32>,
+    /// Renewal time in seconds.
+    renewal_time: Option<u32>,
+    /// Rebinding time in seconds.
+    rebinding_time: Option<u32>,
+}
+
+impl Message {
+    /// Create a message sent by the client with Ethernet address `chaddr`.
+    fn request(msg_type: u8, xid: u32, chaddr: [u8; 6]) -> Self {
+        Self {
+            op: BOOTREQUEST,
+            xid,
+            flags: 0,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            msg_type,
+            requested_ip: None,
+            server_id: None,
+            subnet_mask: None,
+            router: None,
+            dns_servers: Vec::new(),
+            lease_time: None,
+            renewal_time: None,
+            rebinding_time: None,
+        }
+    }
+
+    /// Encode the message. Options asked from servers are requested along with discovers and
+    /// requests.
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(MIN_MESSAGE_LEN);
+        buf.extend_from_slice(&[self.op, HTYPE_ETHER, HLEN_ETHER, 0]);
+        buf.extend_from_slice(&self.xid.to_be_bytes());
+        buf.extend_from_slice(&0_u16.to_be_bytes()); // secs
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        buf.extend_from_slice(&self.ciaddr.octets());
+        buf.extend_from_slice(&self.yiaddr.octets());
+        buf.extend_from_slice(&[0; 8]); // siaddr and giaddr
+        buf.extend_from_slice(&self.chaddr);
+        buf.resize(OPTIONS_OFFSET.wrapping_sub(MAGIC_COOKIE.len()), 0); // chaddr, sname and file
+        buf.extend_from_slice(&MAGIC_COOKIE);
+        buf.extend_from_slice(&[OPT_MSG_TYPE, 1, self.msg_type]);
+        let addrs = [
+            (OPT_REQUESTED_IP, self.requested_ip),
+            (OPT_SERVER_ID, self.server_id),
+            (OPT_SUBNET_MASK, self.subnet_mask),
+            (OPT_ROUTER, self.router),
+        ];
+        for (code, addr) in addrs {
+            if let Some(addr) = addr {
+                buf.extend_from_slice(&[code, 4]);
+                buf.extend_from_slice(&addr.octets());
+            }
+        }
+        if !self.dns_servers.is_empty() {
+            #[allow(clippy::cast_possible_truncation)] // at most 63 servers fit in an option
+            let len = self.dns_servers.len().min(63).wrapping_mul(4) as u8;
+            buf.extend_from_slice(&[OPT_DNS, len]);
+            for dns in self.dns_servers.iter().take(63) {
+                buf.extend_from_slice(&dns.octets());
+            }
+        }
+        let times = [
+            (OPT_LEASE_TIME, self.lease_time),
+            (OPT_RENEWAL_TIME, self.renewal_time),
+            (OPT_REBINDING_TIME, self.rebinding_time),
+        ];
+        for (code, secs) in times {
+            if let Some(secs) = secs {
+                buf.extend_from_slice(&[code, 4]);
+                buf.extend_from_slice(&secs.to_be_bytes());
+            }
+        }
+        if matches!(self.msg_type, DHCPDISCOVER | DHCPREQUEST) {
+            buf.extend_from_slice(&[
+                OPT_PARAM_REQUEST,
+                6,
+                OPT_SUBNET_MASK,
+                OPT_ROUTER,
+                OPT_DNS,
+                OPT_LEASE_TIME,
+                OPT_RENEWAL_TIME,
+                OPT_REBINDING_TIME,
+            ]);
+        }
+        buf.push(OPT_END);
+        if buf.len() < MIN_MESSAGE_LEN {
+            buf.resize(MIN_MESSAGE_LEN, OPT_PAD);
+        }
+        buf
+    }
+
+    /// Decode a message, returning `None` if it's malformed or has no message type.
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let ipv4_at = |off: usize| -> Option<Ipv4Addr> {
+            let octets: [u8; 4] = buf.get(off..off.checked_add(4)?)?.try_into().ok()?;
+            Some(Ipv4Addr::from(octets))
+        };
+        if buf.get(OPTIONS_OFFSET.wrapping_sub(MAGIC_COOKIE.len())..OPTIONS_OFFSET)? != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let mut msg = Self {
+            op: *buf.first()?,
+            xid: u32::from_be_bytes(buf.get(4..8)?.try_into().ok()?),
+            flags: u16::from_be_bytes(buf.get(10..12)?.try_into().ok()?),
+            ciaddr: ipv4_at(12)?,
+            yiaddr: ipv4_at(16)?,
+            chaddr: buf.get(28..34)?.try_into().ok()?,
+            msg_type: 0,
+            requested_ip: None,
+            server_id: None,
+            subnet_mask: None,
+            router: None,
+            dns_servers: Vec::new(),
+            lease_time: None,
+            renewal_time: None,
+            rebinding_time: None,
+        };
+        let mut opts = buf.get(OPTIONS_OFFSET..)?;
+        loop {
+            match *opts {
+                [] | [OPT_END, ..] => break,
+                [OPT_PAD, ref rest @ ..] => opts = rest,
+                [code, len, ref rest @ ..] => {
+                    let len = usize::from(len);
+                    let data = rest.get(..len)?;
+                    opts = rest.get(len..)?;
+                    msg.set_option(code, data)?;
+                }
+                [_] => return None,
+            }
+        }
+        (msg.msg_type != 0).then_some(msg)
+    }
+
+    /// Set the option `code` with value `data`, ignoring unknown options.
+    fn set_option(&mut self, code: u8, data: &[u8]) -> Option<()> {
+        let addr = || -> Option<Ipv4Addr> {
+            let octets: [u8; 4] = data.get(..4)?.try_into().ok()?;
+            Some(Ipv4Addr::from(octets))
+        };
+        let secs = || -> Option<u32> { Some(u32::from_be_bytes(data.get(..4)?.try_into().ok()?)) };
+        match code {
+            OPT_MSG_TYPE => self.msg_type = *data.first()?,
+            OPT_REQUESTED_IP => self.requested_ip = Some(addr()?),
+            OPT_SERVER_ID => self.server_id = Some(addr()?),
+            OPT_SUBNET_MASK => self.subnet_mask = Some(addr()?),
+            OPT_ROUTER => self.router = Some(addr()?),
+            OPT_DNS => {
+                self.dns_servers = data
+                    .chunks_exact(4)
+                    .filter_map(|octets| <[u8; 4]>::try_from(octets).ok())
+                    .map(Ipv4Addr::from)
+                    .collect();
+            }
+            OPT_LEASE_TIME => self.lease_time = Some(secs()?),
+            OPT_RENEWAL_TIME => self.renewal_time = Some(secs()?),
+            OPT_REBINDING_TIME => self.rebinding_time = Some(secs()?),
+            _ => {}
+        }
+        Some(())
+    }
+
+    /// Make a lease out of an acknowledgement.
+    fn lease(&self, acquired: Instant) -> Option<Lease> {
+        let lease_time = Duration::from_secs(u64::from(self.lease_time?));
+        let secs_or = |secs: Option<u32>, default: Option<Duration>| {
+            secs.map(|secs| Duration::from_secs(u64::from(secs)))
+                .or(default)
+        };
+        let prefix_len = self
+            .subnet_mask
+            .map_or(32, |mask| u32::from(mask).leading_ones());
+        Some(Lease {
+            addr: self.yiaddr,
+            prefix_len: u8::try_from(prefix_len).ok()?,
+            gateway: self.router,
+            dns_servers: self.dns_servers.clone(),
+            server: self.server_id?,
+            lease_time,
+            renewal_time: secs_or(self.renewal_time, lease_time.checked_div(2))?,
+            rebinding_time: secs_or(
+                self.rebinding_time,
+                lease_time.checked_div(8).and_then(|d| d.checked_mul(7)),
+            )?,
+            acquired,
+        })
+    }
+}
+
+/// A DHCP client configuring a device with the address leased from a server.
+#[derive(Debug)]
+pub struct DhcpClient {
+    /// The address the device is bound to before the lease, restored once the lease expires.
+    home: IpAddr,
+    /// A channel to `TxAgent` of the device.
+    tx: TxSender,
+    /// Ether address of the device.
+    mac: [u8; 6],
+    /// Socket receiving replies.
+    socket: UdpSocket,
+    /// The current lease.
+    lease: Option<Lease>,
+}
+
+impl DhcpClient {
+    /// Create a client for the running device bound to `dev`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::NoDev`: no running device is bound to `dev`.
+    /// - `Error::InvalidArg`: the client port is bound by another socket.
+    #[inline]
+    pub fn new(dev: &IpAddr) -> Result<Self> {
+        let (_port_id, tx) = net_dev::find_port_by_ip(*dev)?;
+        let mac = net_dev::mac_addr(dev)?;
+        // Ports of socket addresses are in network byte order.
+        let socket = UdpSocket::bind(SocketAddr::new(
+            Ipv4Addr::UNSPECIFIED.into(),
+            CLIENT_PORT.to_be(),
+        ))?;
+        Ok(Self {
+            home: *dev,
+            tx,
+            mac,
+            socket,
+            lease: None,
+        })
+    }
+
+    /// The current lease, if any.
+    #[inline]
+    #[must_use]
+    pub fn lease(&self) -> Option<&Lease> {
+        self.lease.as_ref()
+    }
+
+    /// Lease an address from any server, and configure the device with it. The current lease is
+    /// dropped first, if any.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::TimedOut`: no server replies.
+    /// - `Error::ConnRefused`: the server refuses the address it offers.
+    /// - `Error::Exists`: the leased address is assigned to another device.
+    /// - Unable to send the messages, or to configure the device.
+    #[inline]
+    pub async fn acquire(&mut self) -> Result<Lease> {
+        if self.lease.is_some() {
+            self.expire()?;
+        }
+        let xid = self.new_xid();
+        let mut discover = Message::request(DHCPDISCOVER, xid, self.mac);
+        discover.flags = FLAG_BROADCAST;
+        let offer = self.exchange(&discover, None).await?;
+        debug!("Offered {} by {:?}", offer.yiaddr, offer.server_id);
+        let mut request = Message::request(DHCPREQUEST, xid, self.mac);
+        request.flags = FLAG_BROADCAST;
+        request.requested_ip = Some(offer.yiaddr);
+        request.server_id = offer.server_id;
+        let ack = self.exchange(&request, None).await?;
+        self.bind(&ack)
+    }
+
+    /// Renew the current lease with the server granting it, or with any server if `rebind` is
+    /// set.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::NotExist`: there's no lease.
+    /// - `Error::TimedOut`: no server replies.
+    /// - `Error::ConnRefused`: the lease is refused, which should be acquired again.
+    /// - Unable to send the messages, or to configure the device.
+    #[inline]
+    pub async fn renew(&mut self, rebind: bool) -> Result<Lease> {
+        let lease = self.lease.as_ref().ok_or(Error::NotExist)?;
+        let mut request = Message::request(DHCPREQUEST, self.new_xid(), self.mac);
+        request.ciaddr = lease.addr;
+        let server = (!rebind).then_some((lease.addr, lease.server));
+        let ack = self.exchange(&request, server).await?;
+        self.bind(&ack)
+    }
+
+    /// Give the current lease back to the server, and restore the address the device is bound
+    /// to before.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::NotExist`: there's no lease.
+    /// - Unable to send the message, or to configure the device.
+    #[inline]
+    pub async fn release(&mut self) -> Result<()> {
+        let lease = self.lease.as_ref().ok_or(Error::NotExist)?;
+        let mut release = Message::request(DHCPRELEASE, self.new_xid(), self.mac);
+        release.ciaddr = lease.addr;
+        release.server_id = Some(lease.server);
+        self.send(&release, Some((lease.addr, lease.server)))
+            .await?;
+        self.expire()
+    }
+
+    /// Keep a lease, acquiring one if there's none. Never returns unless it fails.
+    ///
+    /// The lease is renewed with its server after `renewal_time`, or with any server after
+    /// `rebinding_time` if it fails. A new lease is acquired once the lease expires or is
+    /// refused.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::TimedOut`: no server replies to the acquisition.
+    /// - Unable to send the messages, or to configure the device.
+    #[inline]
+    pub async fn run(&mut self) -> Result<()> {
+        loop {
+            let lease = match self.lease.clone() {
+                Some(lease) => lease,
+                None => self.acquire().await?,
+            };
+            sleep_until(lease.acquired, lease.renewal_time).await?;
+            match self.renew(false).await {
+                Ok(_) => continue,
+                Err(Error::ConnRefused) => {
+                    self.expire()?;
+                    continue;
+                }
+                Err(e) => warn!("Failed to renew the lease of {}: {e}", lease.addr),
+            }
+            sleep_until(lease.acquired, lease.rebinding_time).await?;
+            match self.renew(true).await {
+                Ok(_) => continue,
+                Err(Error::ConnRefused) => {
+                    self.expire()?;
+                    continue;
+                }
+                Err(e) => warn!("Failed to rebind the lease of {}: {e}", lease.addr),
+            }
+            sleep_until(lease.acquired, lease.lease_time).await?;
+            info!("Lease of {} expired", lease.addr);
+            self.expire()?;
+        }
+    }
+
+    /// The address the device is bound to now.
+    fn dev(&self) -> IpAddr {
+        self.lease
+            .as_ref()
+            .map_or(self.home, |lease| IpAddr::V4(lease.addr))
+    }
+
+    /// A random transaction ID.
+    fn new_xid(&self) -> u32 {
+        #[allow(clippy::cast_possible_truncation)] // used as a random number
+        let xid = RandomState::new().hash_one(self.mac) as u32;
+        xid
+    }
+
+    /// Send `msg`, to `server` from a leased address if it's set, or broadcast otherwise.
+    async fn send(&self, msg: &Message, server: Option<(Ipv4Addr, Ipv4Addr)>) -> Result<()> {
+        let (src, dst, dst_mac) = match server {
+            Some((src, dst)) => {
+                let src_mac = rte_ether_addr {
+                    addr_bytes: self.mac,
+                };
+                let dst_mac = arp::resolve(dst, src, src_mac, &self.tx).await?;
+                (src, dst, dst_mac.addr_bytes)
+            }
+            None => (msg.ciaddr, Ipv4Addr::BROADCAST, [0xff; 6]),
+        };
+        let pkt = PacketBuilder::new()
+            .ethernet(self.mac, dst_mac)
+            .ipv4(src, dst, DEFAULT_TTL)
+            .udp(CLIENT_PORT, SERVER_PORT)
+            .build(&msg.encode())?;
+        self.tx.send(pkt).await
+    }
+
+    /// Send `msg` and wait for the reply, retransmitting it if there's none in time. Replies of
+    /// other transactions or clients are ignored.
+    async fn exchange(
+        &self,
+        msg: &Message,
+        server: Option<(Ipv4Addr, Ipv4Addr)>,
+    ) -> Result<Message> {
+        let expected = if msg.msg_type == DHCPDISCOVER {
+            DHCPOFFER
+        } else {
+            DHCPACK
+        };
+        let mut buf = [0_u8; 1500];
+        let mut timeout = RETRANSMIT_TIMEOUT;
+        for _ in 0..MAX_ATTEMPTS {
+            self.send(msg, server).await?;
+            let deadline = Instant::now().checked_add(timeout).ok_or(Error::Overflow)?;
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let len = match self.socket.recv_from_timeout(&mut buf, remaining).await {
+                    Ok((len, _src)) => len,
+                    Err(Error::TimedOut) => break,
+                    Err(e) => return Err(e),
+                };
+                let Some(reply) = buf.get(..len).and_then(Message::decode) else {
+                    continue;
+                };
+                if reply.op != BOOTREPLY || reply.xid != msg.xid || reply.chaddr != self.mac {
+                    continue;
+                }
+                match reply.msg_type {
+                    DHCPNAK if msg.msg_type == DHCPREQUEST => return Err(Error::ConnRefused),
+                    ty if ty == expected && reply.server_id.is_some() => return Ok(reply),
+                    _ => {}
+                }
+            }
+            timeout = timeout.saturating_mul(2);
+        }
+        Err(Error::TimedOut)
+    }
+
+    /// Configure the device with the lease acknowledged by `ack`.
+    fn bind(&mut self, ack: &Message) -> Result<Lease> {
+        let lease = ack.lease(Instant::now()).ok_or(Error::Proto)?;
+        let addr = IpAddr::V4(lease.addr);
+        let dev = self.dev();
+        if dev != addr {
+            net_dev::add_address(&dev, IfAddr::new(addr, lease.prefix_len)?)?;
+        }
+        match self.lease.take() {
+            Some(old) => {
+                unroute(&old);
+                if dev != addr {
+                    net_dev::remove_address(&dev)?;
+                }
+            }
+            // The placeholder is replaced by the leased address.
+            None if self.home.is_unspecified() => net_dev::remove_address(&self.home)?,
+            None => {}
+        }
+        reroute(addr, lease.prefix_len, addr, None)?;
+        if let Some(gateway) = lease.gateway {
+            reroute(Ipv4Addr::UNSPECIFIED.into(), 0, addr, Some(gateway.into()))?;
+        }
+        let dns_servers = lease.dns_servers.iter().map(|&dns| dns.into()).collect();
+        net_dev::set_dns_servers(&addr, dns_servers)?;
+        info!(
+            "Leased {}/{} from {}",
+            lease.addr, lease.prefix_len, lease.server
+        );
+        self.lease = Some(lease.clone());
+        Ok(lease)
+    }
+
+    /// Drop the current lease, restoring the address the device is bound to before.
+    fn expire(&mut self) -> Result<()> {
+        let Some(lease) = self.lease.take() else {
+            return Ok(());
+        };
+        let addr = IpAddr::V4(lease.addr);
+        unroute(&lease);
+        net_dev::set_dns_servers(&addr, Vec::new())?;
+        if self.home != addr {
+            if self.home.is_unspecified() {
+                net_dev::add_address(&addr, self.home)?;
+            }
+            net_dev::remove_address(&addr)?;
+        }
+        Ok(())
+    }
+}
+
+/// Add a route to `dst`/`prefix_len` through the device with address `dev`, replacing the
+/// existing route to the prefix.
+fn reroute(dst: IpAddr, prefix_len: u8, dev: IpAddr, gateway: Option<IpAddr>) -> Result<()> {
+    match route::add(dst, prefix_len, dev, gateway) {
+        Err(Error::Exists) => {
+            route::del(dst, prefix_len)?;
+            route::add(dst, prefix_len, dev, gateway)
+        }
+        res => res,
+    }
+}
+
+/// Delete the routes added for `lease`.
+fn unroute(lease: &Lease) {
+    if let Err(e) = route::del(lease.addr.into(), lease.prefix_len) {
+        warn!("Failed to delete the subnet route of {}: {e}", lease.addr);
+    }
+    if lease.gateway.is_some() {
+        if let Err(e) = route::del(Ipv4Addr::UNSPECIFIED.into(), 0) {
+            warn!("Failed to delete the default route of {}: {e}", lease.addr);
+        }
+    }
+}
+
+/// Sleep until `after` has passed since `start`.
+async fn sleep_until(start: Instant, after: Duration) -> Result<()> {
+    let delay = after.saturating_sub(start.elapsed());
+    if delay.is_zero() {
+        return Ok(());
+    }
+    Timer::sleep(delay).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, DHCPACK, DHCPDISCOVER, MIN_MESSAGE_LEN};
+    use std::{
+        net::Ipv4Addr,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_message() {
+        let mac = [0x02, 0, 0, 0, 0, 1];
+        let discover = Message::request(DHCPDISCOVER, 0x1234_5678, mac);
+        let buf = discover.encode();
+        assert_eq!(buf.len(), MIN_MESSAGE_LEN);
+        assert_eq!(Message::decode(&buf).unwrap(), discover);
+        assert!(Message::decode(&buf[..200]).is_none());
+
+        let mut ack = Message::request(DHCPACK, 0x1234_5678, mac);
+        ack.yiaddr = Ipv4Addr::new(192, 168, 0, 9);
+        ack.server_id = Some(Ipv4Addr::new(192, 168, 0, 1));
+        ack.subnet_mask = Some(Ipv4Addr::new(255, 255, 255, 0));
+        ack.router = Some(Ipv4Addr::new(192, 168, 0, 1));
+        ack.dns_servers = vec![Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(1, 1, 1, 1)];
+        ack.lease_time = Some(3600);
+        let ack = Message::decode(&ack.encode()).unwrap();
+        let lease = ack.lease(Instant::now()).unwrap();
+        assert_eq!(lease.addr, Ipv4Addr::new(192, 168, 0, 9));
+        assert_eq!(lease.prefix_len, 24);
+        assert_eq!(lease.gateway, Some(Ipv4Addr::new(192, 168, 0, 1)));
+        assert_eq!(lease.dns_servers.len(), 2);
+        assert_eq!(lease.renewal_time, Duration::from_secs(1800));
+        assert_eq!(lease.rebinding_time, Duration::from_secs(3150));
+    }
+}
@@ -0,0 +1,247 @@
+//! ICMP echo implementation over IPv4.
+//!
+//! Echo requests to the addresses of started devices are answered in `RxAgent`, and `ping` sends
+//! echo requests and waits for the replies.
+
+use crate::{
+    mbuf::Mbuf,
+    net_dev,
+    packet::{set_packet_type, set_tx_offload, Packet},
+    proto::{
+        arp, cksum_add, cksum_fold, socket::IPID, L3Protocol, L4Protocol, ETHER_HDR_LEN,
+        IP_NEXT_PROTO_ICMP,
+    },
+    trace::{trace, warn},
+    Error, Result,
+};
+use bytes::{BufMut, BytesMut};
+use dpdk_sys::RTE_ETHER_TYPE_IPV4;
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{sync::oneshot, time};
+
+/// Echo reply type.
+const ICMP_ECHO_REPLY: u8 = 0;
+
+/// Echo request type.
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+/// Length of an ICMP echo header.
+const ICMP_ECHO_HDR_LEN: usize = 8;
+
+/// Length of the IPv4 header without options.
+const IPV4_HDR_LEN: usize = 20;
+
+/// Length of the payload of an echo request sent by `ping`.
+const PING_PAYLOAD_LEN: usize = 56;
+
+/// Time to live of the packets sent.
+const DEFAULT_TTL: u8 = 64;
+
+lazy_static! {
+    /// Pending echo requests sent by `ping`, keyed by (identifier, sequence number).
+    static ref PINGS: Mutex<HashMap<(u16, u16), oneshot::Sender<()>>> = Mutex::new(HashMap::new());
+}
+
+/// Sequence number of the next echo request.
+static PING_SEQ: AtomicU16 = AtomicU16::new(0);
+
+/// An echo request in `PINGS`, removed once `ping` returns or is cancelled.
+struct PendingPing((u16, u16));
+
+impl Drop for PendingPing {
+    fn drop(&mut self) {
+        if let Ok(mut pings) = PINGS.lock() {
+            let _prev = pings.remove(&self.0);
+        }
+    }
+}
+
+/// Sends an ICMP echo request to `dst`, and waits for the reply. On success, returns the round
+/// trip time.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - `Error::NotSupported`: `dst` is an IPv6 address.
+/// - `Error::NoDev`: no device is running.
+/// - Send agent not started.
+/// - `Error::TimedOut`: no reply is received in `timeout`.
+#[inline]
+pub async fn ping(dst: IpAddr, timeout: Duration) -> Result<Duration> {
+    let IpAddr::V4(dst) = dst else {
+        return Err(Error::NotSupported);
+    };
+    let IpAddr::V4(src) = net_dev::local_ip_for(IpAddr::V4(dst))? else {
+        return Err(Error::NotSupported);
+    };
+    #[allow(clippy::cast_possible_truncation)] // truncated as identifier
+    let id = std::process::id() as u16;
+    let seq = PING_SEQ.fetch_add(1, Ordering::Relaxed);
+    let (done, rx) = oneshot::channel();
+    let _prev = PINGS.lock().map_err(Error::from)?.insert((id, seq), done);
+    let _pending = PendingPing((id, seq));
+    let start = Instant::now();
+    let res = time::timeout(timeout, async {
+        let (tx, src_mac) = net_dev::find_dev_by_ip(IpAddr::V4(src))?;
+        let dst_mac = arp::resolve(dst, src, src_mac, &tx).await?;
+        let mut buf = BytesMut::with_capacity(
+            (ETHER_HDR_LEN as usize)
+                .wrapping_add(IPV4_HDR_LEN)
+                .wrapping_add(ICMP_ECHO_HDR_LEN)
+                .wrapping_add(PING_PAYLOAD_LEN),
+        );
+        buf.put_slice(&dst_mac.addr_bytes);
+        buf.put_slice(&src_mac.addr_bytes);
+        #[allow(clippy::cast_possible_truncation)] // Ether type is 16 bits
+        buf.put_u16(RTE_ETHER_TYPE_IPV4 as u16);
+        buf.put_slice(&ipv4_hdr(
+            src,
+            dst,
+            ICMP_ECHO_HDR_LEN.wrapping_add(PING_PAYLOAD_LEN),
+        ));
+        let mut icmp = BytesMut::with_capacity(ICMP_ECHO_HDR_LEN.wrapping_add(PING_PAYLOAD_LEN));
+        icmp.put_u8(ICMP_ECHO_REQUEST);
+        icmp.put_u8(0);
+        icmp.put_u16(0);
+        icmp.put_u16(id);
+        icmp.put_u16(seq);
+        #[allow(clippy::cast_possible_truncation)] // less than 256
+        for i in 0..PING_PAYLOAD_LEN {
+            icmp.put_u8(i as u8);
+        }
+        let cksum = cksum_fold(cksum_add(0, &icmp));
+        if let Some(field) = icmp.get_mut(2..4) {
+            field.copy_from_slice(&cksum.to_be_bytes());
+        }
+        buf.put_slice(&icmp);
+        let mut pkt = Packet::new(L3Protocol::Ipv4, L4Protocol::Unknown);
+        pkt.append(buf);
+        tx.send(pkt).await?;
+        rx.await.map_err(Error::from)
+    })
+    .await;
+    match res {
+        Ok(Ok(())) => Ok(start.elapsed()),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(Error::TimedOut),
+    }
+}
+
+/// Build an IPv4 header without options carrying `payload_len` bytes of ICMP.
+#[allow(clippy::indexing_slicing)] // within the header
+fn ipv4_hdr(src: Ipv4Addr, dst: Ipv4Addr, payload_len: usize) -> [u8; IPV4_HDR_LEN] {
+    #[allow(clippy::cast_possible_truncation)] // less than MTU
+    let total_len = IPV4_HDR_LEN.wrapping_add(payload_len) as u16;
+    let id = IPID.fetch_add(1, Ordering::AcqRel);
+    let [l0, l1] = total_len.to_be_bytes();
+    let [i0, i1] = id.to_be_bytes();
+    let [s0, s1, s2, s3] = src.octets();
+    let [d0, d1, d2, d3] = dst.octets();
+    let mut hdr = [
+        0x45,
+        0,
+        l0,
+        l1,
+        i0,
+        i1,
+        0,
+        0,
+        DEFAULT_TTL,
+        IP_NEXT_PROTO_ICMP,
+        0,
+        0,
+        s0,
+        s1,
+        s2,
+        s3,
+        d0,
+        d1,
+        d2,
+        d3,
+    ];
+    let [c0, c1] = cksum_fold(cksum_add(0, &hdr)).to_be_bytes();
+    hdr[10] = c0;
+    hdr[11] = c1;
+    hdr
+}
+
+/// Handle an ICMP packet over IPv4, whose Ethernet header is stripped.
+///
+/// Echo requests to a local address are answered, and echo replies to `ping` wake it up. Other
+/// packets are given back.
+pub(crate) fn handle_icmp(m: Mbuf) -> Option<Mbuf> {
+    let data = m.data_slice();
+    let ihl = usize::from(data.first()? & 0x0f).wrapping_mul(4);
+    if ihl < IPV4_HDR_LEN {
+        return Some(m);
+    }
+    let src: [u8; 4] = data.get(12..16)?.try_into().ok()?;
+    let dst: [u8; 4] = data.get(16..20)?.try_into().ok()?;
+    let icmp = data.get(ihl..ihl.checked_add(ICMP_ECHO_HDR_LEN)?)?;
+    match *icmp {
+        [ICMP_ECHO_REQUEST, 0, ..] => {
+            let port_id = m.port();
+            match arp::iface_mac(port_id, IpAddr::from(dst)) {
+                Ok(Some(mac)) => {
+                    trace!("Replying ICMP echo request from {}", Ipv4Addr::from(src));
+                    if let Err(e) = echo_reply(m, ihl, mac.addr_bytes) {
+                        warn!("Failed to reply ICMP echo request: {e:?}");
+                    }
+                    None
+                }
+                Ok(None) | Err(_) => Some(m),
+            }
+        }
+        [ICMP_ECHO_REPLY, 0, _, _, i0, i1, s0, s1] => {
+            let key = (u16::from_be_bytes([i0, i1]), u16::from_be_bytes([s0, s1]));
+            let waiter = PINGS.lock().ok()?.remove(&key);
+            match waiter {
+                Some(waiter) => {
+                    let _res = waiter.send(());
+                    None
+                }
+                None => Some(m),
+            }
+        }
+        _ => Some(m),
+    }
+}
+
+/// Turn an echo request, whose Ethernet header is stripped, into the reply in place and send it.
+#[allow(clippy::indexing_slicing)] // length checked in `handle_icmp`
+fn echo_reply(mut m: Mbuf, ihl: usize, mac: [u8; 6]) -> Result<()> {
+    let data = m.data_slice_mut();
+    // Swap the addresses and recompute the IP checksum.
+    let (ip_hdr, icmp) = data.split_at_mut(ihl);
+    let mut src = [0_u8; 4];
+    src.copy_from_slice(&ip_hdr[12..16]);
+    ip_hdr.copy_within(16..20, 12);
+    ip_hdr[16..20].copy_from_slice(&src);
+    ip_hdr[8] = DEFAULT_TTL;
+    ip_hdr[10..12].fill(0);
+    let cksum = cksum_fold(cksum_add(0, ip_hdr));
+    ip_hdr[10..12].copy_from_slice(&cksum.to_be_bytes());
+    // Change the type, and update the ICMP checksum incrementally as in RFC 1624, so that a
+    // payload across segments needs no touching.
+    icmp[0] = ICMP_ECHO_REPLY;
+    let old = u16::from_be_bytes([icmp[2], icmp[3]]);
+    let sum = u32::from(!old).wrapping_add(u32::from(!(u16::from(ICMP_ECHO_REQUEST) << 8)));
+    icmp[2..4].copy_from_slice(&cksum_fold(sum).to_be_bytes());
+    // Send back to the sender.
+    let eth_hdr = m.prepend(ETHER_HDR_LEN as usize)?;
+    eth_hdr.copy_within(6..12, 0);
+    eth_hdr[6..12].copy_from_slice(&mac);
+    set_packet_type(&m, L3Protocol::Ipv4, L4Protocol::Unknown);
+    set_tx_offload(&m, 0);
+    arp::send_on(m.port(), m)
+}
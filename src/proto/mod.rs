@@ -1,8 +1,15 @@
 //! Protocols supported in this lib.
 
+pub(crate) mod arp;
+pub mod dhcp;
+pub mod icmp;
+pub mod raw;
+pub(crate) mod route;
 pub mod socket;
+pub mod tcp;
 pub mod udp;
 
+use crate::mbuf::Mbuf;
 use dpdk_sys::{
     RTE_PTYPE_L2_ETHER, RTE_PTYPE_L3_IPV4, RTE_PTYPE_L3_IPV6, RTE_PTYPE_L4_TCP, RTE_PTYPE_L4_UDP,
     RTE_PTYPE_UNKNOWN,
@@ -17,12 +24,91 @@ pub(crate) trait Protocol {
 /// UDP `proto_id`, to be populated in IP header.
 pub(crate) const IP_NEXT_PROTO_UDP: u8 = 0x11;
 
+/// TCP `proto_id`, to be populated in IP header.
+pub(crate) const IP_NEXT_PROTO_TCP: u8 = 0x06;
+
+/// ICMP `proto_id`, to be populated in IP header.
+pub(crate) const IP_NEXT_PROTO_ICMP: u8 = 0x01;
+
+/// IPv6 fragment extension header `proto_id`.
+pub(crate) const IPV6_NEXT_PROTO_FRAGMENT: u8 = 44;
+
+/// IPv6 hop-by-hop options extension header `proto_id`.
+pub(crate) const IPV6_NEXT_PROTO_HOPOPTS: u8 = 0;
+
+/// IPv6 routing extension header `proto_id`.
+pub(crate) const IPV6_NEXT_PROTO_ROUTING: u8 = 43;
+
+/// IPv6 destination options extension header `proto_id`.
+pub(crate) const IPV6_NEXT_PROTO_DSTOPTS: u8 = 60;
+
+/// IPv6 fragment extension header length.
+pub(crate) const IPV6_FRAG_HDR_LEN: u16 = 8;
+
 /// Ethernet header length.
 pub(crate) const ETHER_HDR_LEN: u16 = 14;
 
 /// Ethernet proto number, to be populated in `rte_mbuf`.
 pub(crate) const PTYPE_L2_ETHER: u32 = RTE_PTYPE_L2_ETHER;
 
+/// Add `data` to a ones' complement sum, as is used by the Internet checksum.
+pub(crate) fn cksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        if let [hi, lo] = *word {
+            sum = sum.wrapping_add(u32::from(u16::from_be_bytes([hi, lo])));
+        }
+    }
+    if let [last] = *chunks.remainder() {
+        sum = sum.wrapping_add(u32::from(u16::from_be_bytes([last, 0])));
+    }
+    sum
+}
+
+/// Add the data of all segments of `m` to a ones' complement sum.
+///
+/// A segment starting at an odd offset is summed with its bytes swapped.
+pub(crate) fn cksum_add_mbuf(mut sum: u32, m: &Mbuf) -> u32 {
+    let mut odd = false;
+    for seg in m.iter() {
+        let data = seg.data_slice();
+        let seg_sum = cksum_add(0, data);
+        sum = if odd {
+            sum.wrapping_add(u32::from((!cksum_fold(seg_sum)).swap_bytes()))
+        } else {
+            sum.wrapping_add(seg_sum)
+        };
+        odd ^= data.len() % 2 == 1;
+    }
+    sum
+}
+
+/// Fold a ones' complement sum into 16 bits, and return its complement.
+pub(crate) fn cksum_fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff).wrapping_add(sum.wrapping_shr(16));
+    }
+    #[allow(clippy::cast_possible_truncation)] // folded into 16 bits
+    let sum = sum as u16;
+    !sum
+}
+
+/// Ones' complement sum of the IPv4 pseudo header used by UDP and TCP checksums.
+pub(crate) fn ipv4_pseudo_sum(src: [u8; 4], dst: [u8; 4], proto_id: u8, l4_len: u16) -> u32 {
+    let sum = cksum_add(0, &src);
+    let sum = cksum_add(sum, &dst);
+    sum.wrapping_add(u32::from(proto_id))
+        .wrapping_add(u32::from(l4_len))
+}
+
+/// Ones' complement sum of the IPv6 pseudo header used by UDP and TCP checksums.
+pub(crate) fn ipv6_pseudo_sum(src: [u8; 16], dst: [u8; 16], proto_id: u8, l4_len: u32) -> u32 {
+    let sum = cksum_add(0, &src);
+    let sum = cksum_add(sum, &dst);
+    let sum = cksum_add(sum, &l4_len.to_be_bytes());
+    sum.wrapping_add(u32::from(proto_id))
+}
+
 #[repr(u32)]
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy)]
@@ -0,0 +1,214 @@
+//! Raw socket sending and receiving whole Ethernet frames, bypassing the IP stack.
+
+use crate::{
+    bpf::BpfProgram,
+    eth_dev::TxSender,
+    mbuf::Mbuf,
+    net_dev,
+    packet::set_packet_type,
+    proto::socket::{self, Mailbox},
+    proto::{L3Protocol, L4Protocol, ETHER_HDR_LEN},
+    Error, Result,
+};
+use std::{fmt::Debug, net::IpAddr, sync::Arc};
+
+/// A raw socket bound to a device.
+///
+/// It receives a copy of every frame arriving at the device, or only frames with the given
+/// Ether type, while the frames are still handled by the stack as usual. Frames sent by it are
+/// put onto the wire as they are.
+#[allow(missing_copy_implementations, clippy::module_name_repetitions)]
+pub struct RawSocket {
+    /// Socket fd.
+    sockfd: i32,
+    /// The port id of the device that this socket is bound to.
+    port_id: u16,
+    /// Only frames with this Ether type are received if it's set.
+    ether_type: Option<u16>,
+    /// A channel to `TxAgent`.
+    tx: TxSender,
+    /// A pointer to its mailbox.
+    mailbox: Arc<Mailbox<Mbuf>>,
+}
+
+impl RawSocket {
+    /// Creates a raw socket bound to the device with the given IP address.
+    ///
+    /// Only frames with `ether_type` are received if it's set, otherwise all frames are received.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::NoDev`: no running device is bound to `addr`.
+    /// - Too much bound sockets.
+    /// - Unable to create the mempool to copy received frames.
+    #[inline]
+    pub fn bind(addr: IpAddr, ether_type: Option<u16>) -> Result<Self> {
+        let (port_id, tx) = net_dev::find_port_by_ip(addr)?;
+        let (sockfd, mailbox) = socket::bind_raw(port_id, ether_type)?;
+        Ok(RawSocket {
+            sockfd,
+            port_id,
+            ether_type,
+            tx,
+            mailbox,
+        })
+    }
+
+    /// Receives a single frame on the socket. On success, returns the number of bytes read.
+    ///
+    /// The frame is truncated if `buf` is not large enough.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    #[inline]
+    #[allow(clippy::indexing_slicing)]
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        let data = self.recv_mbuf().await?;
+        let mut len: usize = 0;
+        let mut buf = buf;
+        for seg in data.iter() {
+            let seg = seg.data_slice();
+            let sz = seg.len().min(buf.len());
+            buf[..sz].copy_from_slice(&seg[..sz]);
+            buf = &mut buf[sz..];
+            len = len.wrapping_add(sz);
+            if buf.is_empty() {
+                break;
+            }
+        }
+        Ok(len)
+    }
+
+    /// Receives a single frame on the socket without copying. The returned `Mbuf` starts with
+    /// the Ethernet header.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    #[inline]
+    pub async fn recv_mbuf(&self) -> Result<Mbuf> {
+        self.mailbox.recv().await
+    }
+
+    /// Sends a whole Ethernet frame on the socket. On success, returns the number of bytes
+    /// written.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: the frame is shorter than an Ethernet header.
+    /// - `Error::NoMem`: the frame is too long to fit in an `Mbuf`.
+    /// - Send agent not started.
+    /// - `Error::NoBuf`: the tx buffer is full, try again later.
+    /// - `Error::Busy`: the device has taken no packet for a while, e.g. its link is down.
+    /// - `Error::MempoolExhausted`: no `Mbuf` is left to hold the data.
+    #[inline]
+    pub async fn send(&self, frame: &[u8]) -> Result<usize> {
+        let mut m = self.alloc_mbuf()?;
+        m.append(frame.len())?.copy_from_slice(frame);
+        self.send_mbuf(m).await
+    }
+
+    /// Allocates an `Mbuf` from the mempool of the device, to be filled with a frame and sent by
+    /// `send_mbuf`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Mempool exhausted.
+    #[inline]
+    pub fn alloc_mbuf(&self) -> Result<Mbuf> {
+        self.tx.alloc_mbuf()
+    }
+
+    /// Sends a whole Ethernet frame held by an `Mbuf` without copying it. On success, returns the
+    /// number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: the frame is shorter than an Ethernet header.
+    /// - Send agent not started.
+    /// - `Error::NoBuf`: the tx buffer is full, try again later.
+    /// - `Error::Busy`: the device has taken no packet for a while, e.g. its link is down.
+    /// - `Error::MempoolExhausted`: no `Mbuf` is left to hold the data.
+    #[inline]
+    pub async fn send_mbuf(&self, m: Mbuf) -> Result<usize> {
+        let len = m.pkt_len();
+        if len < ETHER_HDR_LEN as usize {
+            return Err(Error::InvalidArg);
+        }
+        set_packet_type(&m, L3Protocol::Unknown, L4Protocol::Unknown);
+        self.tx.send_mbuf(m).await?;
+        Ok(len)
+    }
+
+    /// Waits until all frames sent before are put onto the wire.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Send agent not started.
+    /// - `Error::TimedOut`: some frames are still unsent after retrying.
+    #[inline]
+    pub async fn flush(&self) -> Result<()> {
+        if self.tx.flush().await? > 0 {
+            return Err(Error::TimedOut);
+        }
+        Ok(())
+    }
+
+    /// Attaches a BPF program filtering the frames received by the socket, replacing the
+    /// previous one, or detaches it with `None`.
+    ///
+    /// Frames rejected by the program are not copied to the socket, and are still handled by the
+    /// stack as usual.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    #[inline]
+    pub fn set_filter(&self, filter: Option<BpfProgram>) -> Result<()> {
+        self.mailbox.set_filter(filter)
+    }
+
+    /// The Ether type of frames that this socket receives, or `None` if it receives all frames.
+    #[inline]
+    #[must_use]
+    pub fn ether_type(&self) -> Option<u16> {
+        self.ether_type
+    }
+}
+
+impl Debug for RawSocket {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RawSocket")
+            .field("sockfd", &self.sockfd)
+            .field("port_id", &self.port_id)
+            .field("ether_type", &self.ether_type)
+            .field("tx", &self.tx)
+            .finish()
+    }
+}
+
+impl Drop for RawSocket {
+    #[inline]
+    fn drop(&mut self) {
+        #[allow(clippy::unwrap_used)] // used in drop
+        socket::unbind_raw(self.port_id, self.sockfd).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//! Routing table.
+//!
+//! Routes map destination prefixes to local devices, identified by their IP addresses, and
+//! optionally to gateways. Destinations are matched against the longest prefix, and those without
+//! a matching route or gateway are considered on-link.
+
+use crate::{Error, Result};
+use lazy_static::lazy_static;
+use std::{net::IpAddr, sync::RwLock};
+
+lazy_static! {
+    /// Routes sorted by prefix length in descending order, so that the first match is the longest.
+    static ref ROUTES: RwLock<Vec<Route>> = RwLock::new(Vec::new());
+}
+
+/// A route to the destinations in a prefix.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    /// Network address of the destinations.
+    pub dst: IpAddr,
+    /// Prefix length of the destinations.
+    pub prefix_len: u8,
+    /// IP address of the device the destinations are reached through.
+    pub dev: IpAddr,
+    /// The gateway to forward packets to, or `None` if the destinations are on-link.
+    pub gateway: Option<IpAddr>,
+}
+
+impl Route {
+    /// Whether `ip` is in the destinations of the route.
+    fn contains(&self, ip: IpAddr) -> bool {
+        mask(ip, self.prefix_len) == Some(self.dst)
+    }
+}
+
+/// Mask `ip` with a prefix of `prefix_len`, which fails if it's longer than the address.
+pub(crate) fn mask(ip: IpAddr, prefix_len: u8) -> Option<IpAddr> {
+    match ip {
+        IpAddr::V4(ip) if prefix_len <= 32 => {
+            let mask = u32::MAX
+                .checked_shl(32_u32.wrapping_sub(u32::from(prefix_len)))
+                .unwrap_or(0);
+            Some(IpAddr::V4((u32::from(ip) & mask).into()))
+        }
+        IpAddr::V6(ip) if prefix_len <= 128 => {
+            let mask = u128::MAX
+                .checked_shl(128_u32.wrapping_sub(u32::from(prefix_len)))
+                .unwrap_or(0);
+            Some(IpAddr::V6((u128::from(ip) & mask).into()))
+        }
+        IpAddr::V4(_) | IpAddr::V6(_) => None,
+    }
+}
+
+/// Add a route to `dst`/`prefix_len` through the device with address `dev`, and `gateway` if
+/// it's set. Host bits of `dst` are ignored.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::InvalidArg`: `prefix_len` is too long, or the addresses are of different families.
+/// - `Error::Exists`: a route to the prefix exists.
+pub(crate) fn add(dst: IpAddr, prefix_len: u8, dev: IpAddr, gateway: Option<IpAddr>) -> Result<()> {
+    let dst = mask(dst, prefix_len).ok_or(Error::InvalidArg)?;
+    if dst.is_ipv4() != dev.is_ipv4() || gateway.is_some_and(|gw| gw.is_ipv4() != dst.is_ipv4()) {
+        return Err(Error::InvalidArg);
+    }
+    let mut routes = ROUTES.write().map_err(Error::from)?;
+    if routes
+        .iter()
+        .any(|route| route.dst == dst && route.prefix_len == prefix_len)
+    {
+        return Err(Error::Exists);
+    }
+    let pos = routes.partition_point(|route| route.prefix_len >= prefix_len);
+    routes.insert(
+        pos,
+        Route {
+            dst,
+            prefix_len,
+            dev,
+            gateway,
+        },
+    );
+    Ok(())
+}
+
+/// Delete the route to `dst`/`prefix_len`.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - Lock poisoned.
+/// - `Error::InvalidArg`: `prefix_len` is too long.
+/// - `Error::NotExist`: no route to the prefix exists.
+pub(crate) fn del(dst: IpAddr, prefix_len: u8) -> Result<()> {
+    let dst = mask(dst, prefix_len).ok_or(Error::InvalidArg)?;
+    let mut routes = ROUTES.write().map_err(Error::from)?;
+    let pos = routes
+        .iter()
+        .position(|route| route.dst == dst && route.prefix_len == prefix_len)
+        .ok_or(Error::NotExist)?;
+    let _route = routes.remove(pos);
+    Ok(())
+}
+
+/// Remove all routes.
+pub(crate) fn clear() -> Result<()> {
+    ROUTES.write().map_err(Error::from)?.clear();
+    Ok(())
+}
+
+/// Get all routes, the longest prefixes first.
+pub(crate) fn routes() -> Result<Vec<Route>> {
+    Ok(ROUTES.read().map_err(Error::from)?.clone())
+}
+
+/// Look up the route to `dst` with the longest prefix.
+pub(crate) fn lookup(dst: IpAddr) -> Result<Option<Route>> {
+    Ok(ROUTES
+        .read()
+        .map_err(Error::from)?
+        .iter()
+        .find(|route| route.contains(dst))
+        .copied())
+}
+
+/// The neighbor that packets to `dst` are sent to, which is the gateway of the route to it if
+/// any, or `dst` itself otherwise.
+pub(crate) fn next_hop(dst: IpAddr) -> Result<IpAddr> {
+    Ok(lookup(dst)?.and_then(|route| route.gateway).unwrap_or(dst))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add, del, lookup, next_hop, routes};
+    use crate::Error;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let dev = ip("10.0.0.1");
+        add(ip("0.0.0.0"), 0, dev, Some(ip("10.0.0.254"))).unwrap();
+        add(ip("10.0.0.7"), 24, dev, None).unwrap();
+        add(ip("172.16.0.0"), 12, dev, Some(ip("10.0.0.253"))).unwrap();
+        assert!(matches!(
+            add(ip("10.0.0.0"), 24, dev, None).unwrap_err(),
+            Error::Exists
+        ));
+        assert!(matches!(
+            add(ip("10.0.0.0"), 33, dev, None).unwrap_err(),
+            Error::InvalidArg
+        ));
+        assert!(matches!(
+            add(ip("fd00::"), 64, dev, None).unwrap_err(),
+            Error::InvalidArg
+        ));
+        let prefixes: Vec<u8> = routes().unwrap().iter().map(|r| r.prefix_len).collect();
+        assert_eq!(prefixes, [24, 12, 0]);
+
+        assert_eq!(lookup(ip("10.0.0.9")).unwrap().unwrap().dst, ip("10.0.0.0"));
+        assert_eq!(next_hop(ip("10.0.0.9")).unwrap(), ip("10.0.0.9"));
+        assert_eq!(next_hop(ip("172.20.1.1")).unwrap(), ip("10.0.0.253"));
+        assert_eq!(next_hop(ip("8.8.8.8")).unwrap(), ip("10.0.0.254"));
+        assert_eq!(next_hop(ip("fd00::1")).unwrap(), ip("fd00::1"));
+
+        del(ip("0.0.0.0"), 0).unwrap();
+        assert_eq!(next_hop(ip("8.8.8.8")).unwrap(), ip("8.8.8.8"));
+        assert!(matches!(
+            del(ip("0.0.0.0"), 0).unwrap_err(),
+            Error::NotExist
+        ));
+        del(ip("10.0.0.0"), 24).unwrap();
+        del(ip("172.16.0.0"), 12).unwrap();
+    }
+}
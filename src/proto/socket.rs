@@ -1,25 +1,52 @@
 //! Socket implementation
 
-use crate::{packet::Packet, Error, Result};
+use crate::{
+    bpf::BpfProgram,
+    mbuf::{DynField, Mbuf},
+    mempool::{Mempool, PktMempool},
+    reorder::{self, ReorderBuffer},
+    trace::{error, trace, warn},
+    Error, Result,
+};
 use lazy_static::lazy_static;
-use log::{error, trace};
 use std::{
-    collections::{BTreeMap, HashMap, VecDeque},
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, VecDeque},
+    fmt::Debug,
+    future::Future,
+    hash::{Hash, Hasher},
+    mem,
     net::{IpAddr, SocketAddr},
-    sync::{atomic::AtomicU16, Arc, Mutex},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU16, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
 };
-use tokio::sync::oneshot;
+use tokio::{pin, sync::Notify, time};
 
 lazy_static! {
     static ref SOCK_TABLE: SockTable = SockTable::default();
     static ref PORT_TABLE: PortTable = PortTable::default();
     static ref MAILBOX_TABLE: MailboxTable = MailboxTable::default();
+    static ref CONN_TABLE: ConnTable = ConnTable::default();
+    static ref RAW_TABLE: RawTable = RawTable::default();
     pub(crate) static ref IPID: AtomicU16 = AtomicU16::new(1);
 }
 
 /// The max number of sockets a program can open.
 const MAX_SOCK_NUM: i32 = 8192;
 
+/// The default number of packets that the receive buffer of a socket holds.
+pub const DEFAULT_RECV_BUFFER_SIZE: usize = 4096;
+
+/// The number of `Mbuf`s in the mempool of a raw socket, used to clone received frames.
+const RAW_POOL_SIZE: u32 = 1024;
+
+/// The number of bound raw sockets, checked by the agent thread before looking up `RAW_TABLE`.
+static RAW_SOCK_NUM: AtomicUsize = AtomicUsize::new(0);
+
 /// Socket state.
 #[derive(Debug, Default, Copy, Clone)]
 pub(crate) enum SockState {
@@ -31,6 +58,10 @@ pub(crate) enum SockState {
         /// port number.
         port: u16,
     },
+    /// Connection accepted on a port owned by a listening socket.
+    Accepted,
+    /// Raw socket bound to a device.
+    Raw,
 }
 
 /// Socket table for this process, guarded by a mutex.
@@ -73,6 +104,52 @@ struct PortTable {
     inner: Mutex<PortTableInner>,
 }
 
+/// Options applied when binding a socket.
+///
+/// ```
+/// use async_dpdk::socket::SocketOptions;
+///
+/// let opts = SocketOptions::new().reuse_port(true);
+/// ```
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Whether sockets bound to different IP addresses can share the port.
+    pub reuse_addr: bool,
+    /// Whether sockets bound to the same IP address can share the port, with inbound packets
+    /// distributed across them by the hash of the source address.
+    pub reuse_port: bool,
+}
+
+impl SocketOptions {
+    /// Create default options, with nothing shared.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow sockets bound to different IP addresses to share the port, like `SO_REUSEADDR`.
+    /// All the sockets sharing the port should set it.
+    #[inline]
+    #[must_use]
+    pub fn reuse_addr(mut self, reuse_addr: bool) -> Self {
+        self.reuse_addr = reuse_addr;
+        self
+    }
+
+    /// Allow sockets bound to the same IP address to share the port, like `SO_REUSEPORT`. All
+    /// the sockets sharing the port should set it.
+    ///
+    /// Packets from the same source address always go to the same socket.
+    #[inline]
+    #[must_use]
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = reuse_port;
+        self
+    }
+}
+
 /// Info for bound ports.
 #[derive(Debug)]
 struct PortInfo {
@@ -80,22 +157,75 @@ struct PortInfo {
     fd: i32,
     /// `IpAddr` bound to this port.
     ip: IpAddr,
+    /// The connected peer, packets from other addresses are dropped if it's set.
+    peer: Option<SocketAddr>,
+    /// Options applied on binding.
+    opts: SocketOptions,
+    /// Joined multicast groups.
+    groups: Vec<IpAddr>,
+}
+
+impl PortInfo {
+    /// Whether a packet from `src_addr` to `dst_ip` is accepted by this socket.
+    ///
+    /// Multicast packets are accepted only if the group is joined.
+    fn accepts(&self, dst_ip: IpAddr, src_addr: SocketAddr) -> bool {
+        (self.ip.is_unspecified() || self.ip == dst_ip)
+            && (!dst_ip.is_multicast() || self.groups.contains(&dst_ip))
+            && !matches!(self.peer, Some(peer) if peer != src_addr)
+    }
+
+    /// Whether a socket bound to `ip` with `opts` can share the port with this socket.
+    fn shares_with(&self, ip: IpAddr, opts: SocketOptions) -> bool {
+        if self.ip == ip {
+            self.opts.reuse_port && opts.reuse_port
+        } else {
+            self.opts.reuse_addr && opts.reuse_addr
+        }
+    }
 }
 
 /// Global port info.
 #[derive(Debug, Default)]
 struct PortTableInner {
-    /// port -> port info
-    info: HashMap<u16, PortInfo>,
+    /// port -> sockets bound to the port
+    info: HashMap<u16, Vec<PortInfo>>,
     /// the next port available
     next_port: u16,
 }
 
+/// Established connections for this process, guarded by a mutex.
+#[derive(Debug, Default)]
+struct ConnTable {
+    /// (local port, remote address) -> sockfd
+    inner: Mutex<HashMap<(u16, SocketAddr), i32>>,
+}
+
+/// Raw sockets bound to each device, guarded by a mutex.
+#[derive(Debug, Default)]
+struct RawTable {
+    /// `port_id` -> raw sockets
+    inner: Mutex<HashMap<u16, Vec<RawSock>>>,
+}
+
+/// Info for a bound raw socket.
+#[derive(Debug)]
+struct RawSock {
+    /// Sockfd of the raw socket.
+    fd: i32,
+    /// Only frames with this Ether type are received if it's set.
+    ether_type: Option<u16>,
+    /// Mempool to clone received frames, which are also handled by the stack.
+    mp: PktMempool,
+    /// Mailbox of the raw socket.
+    mailbox: Arc<Mailbox<Mbuf>>,
+}
+
 /// Mailboxes for all bound sockets.
 #[derive(Debug)]
 struct MailboxTable {
     /// fd -> mailbox
-    inner: Mutex<BTreeMap<i32, Arc<Mutex<Mailbox>>>>,
+    inner: Mutex<BTreeMap<i32, Arc<Mailbox>>>,
 }
 
 impl Default for MailboxTable {
@@ -107,55 +237,479 @@ impl Default for MailboxTable {
 }
 
 /// The result for trying to receive a packet.
-pub(crate) type RecvResult = Result<(SocketAddr, Packet)>;
+///
+/// The `Mbuf` starts with the payload of the protocol that the socket handles.
+pub(crate) type RecvResult = Result<(SocketAddr, Mbuf)>;
+
+/// What a `poll_` method of a socket waits for before trying again, e.g. room in the channel to
+/// `TxAgent`, kept by the socket between polls.
+pub(crate) type SendWait = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// What to do when a packet arrives at a full receive buffer.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drop the arriving packet.
+    #[default]
+    DropNewest,
+    /// Drop the oldest packet in the buffer to make room for the arriving one.
+    DropOldest,
+    /// Keep the arriving packet anyway, only counting it in `SocketStats::overlimit`, so that the
+    /// application can slow down the peers itself.
+    Backpressure,
+}
+
+/// Statistics of a socket.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketStats {
+    /// Total number of packets arrived at the socket, including the dropped ones.
+    pub received: u64,
+    /// Total number of packets dropped because the receive buffer is full.
+    pub dropped: u64,
+    /// Total number of packets kept beyond the receive buffer size under
+    /// `DropPolicy::Backpressure`.
+    pub overlimit: u64,
+    /// Number of packets in the receive buffer now.
+    pub queued: usize,
+    /// Total number of datagrams handed over to the device or delivered to local sockets.
+    pub sent: u64,
+    /// Total number of payload bytes of the datagrams sent.
+    pub sent_bytes: u64,
+    /// Total number of datagrams dropped by the policer of the socket instead of sent.
+    pub policed: u64,
+    /// Total number of packets rejected by the BPF filter of the socket, which are not counted in
+    /// `received`.
+    pub filtered: u64,
+}
 
 /// Mailbox is used for packet passing by agents and sockets.
-#[derive(Debug, Default)]
-pub(crate) struct Mailbox {
+///
+/// Any number of tasks may wait on a mailbox at the same time. A packet is only taken out by a
+/// receiving future when it returns, so dropping the future never loses packets, nor the wakeup
+/// of other waiting tasks. Besides, tasks may poll it with `poll_recv`, which are all woken up
+/// once a packet is put.
+#[derive(Debug)]
+pub(crate) struct Mailbox<T = RecvResult> {
+    /// Received packets along with the settings of the receive buffer.
+    queue: Mutex<MailboxQueue<T>>,
+    /// Wakes up the tasks waiting for packets, a permit for each packet put.
+    notify: Notify,
+}
+
+/// The receive buffer of a `Mailbox`.
+#[derive(Debug)]
+struct MailboxQueue<T> {
     /// Received packets.
-    received: VecDeque<RecvResult>,
-    /// Registered by sockets.
-    watcher: Option<oneshot::Sender<RecvResult>>,
-}
-
-impl Mailbox {
-    /// Extract a packet from mailbox.
-    pub(crate) fn recv(&mut self) -> Result<oneshot::Receiver<RecvResult>> {
-        let (tx, rx) = oneshot::channel();
-        if let Some(res) = self.received.pop_front() {
-            trace!("Got a packet from recv buffer");
-            #[allow(clippy::map_err_ignore)]
-            tx.send(res).map_err(|_| Error::BrokenPipe)?;
-        } else {
-            trace!("Registered a channel");
-            self.watcher = Some(tx);
+    received: VecDeque<T>,
+    /// The max number of packets in `received`.
+    capacity: usize,
+    /// What to do when `received` is full.
+    policy: DropPolicy,
+    /// Receive statistics.
+    stats: SocketStats,
+    /// The wakers registered by `Mailbox::poll_recv` finding no packet since the last packet put,
+    /// one for each task.
+    wakers: Vec<Waker>,
+    /// Packets rejected by this program are dropped before put into the mailbox.
+    filter: Option<Arc<BpfProgram>>,
+    /// Datagrams are put back in order by this buffer before put into the mailbox.
+    reorder: Option<Reorder>,
+}
+
+/// Reorders the datagrams arriving at a socket by the sequence numbers in their `Mbuf`s.
+#[derive(Debug)]
+struct Reorder {
+    /// Datagrams waiting for the ones before them.
+    buf: ReorderBuffer,
+    /// The dynamic field holding the sequence numbers.
+    seqn: DynField<u32>,
+    /// Source addresses of the datagrams in `buf`, indexed by their sequence numbers.
+    srcs: HashMap<u32, SocketAddr>,
+}
+
+impl<T> Default for Mailbox<T> {
+    fn default() -> Self {
+        Self {
+            queue: Mutex::new(MailboxQueue {
+                received: VecDeque::new(),
+                capacity: DEFAULT_RECV_BUFFER_SIZE,
+                policy: DropPolicy::default(),
+                stats: SocketStats::default(),
+                wakers: Vec::new(),
+                filter: None,
+                reorder: None,
+            }),
+            notify: Notify::new(),
+        }
+    }
+}
+
+impl<T: Debug> Mailbox<T> {
+    /// Extract a packet from mailbox, waiting for one.
+    pub(crate) async fn recv(&self) -> Result<T> {
+        let mut guard = RecvGuard {
+            mailbox: self,
+            done: false,
+        };
+        loop {
+            let notified = self.notify.notified();
+            pin!(notified);
+            // Registered before checking, so that a packet put right after is not missed.
+            _ = notified.as_mut().enable();
+            if let Some(res) = self.take_front(1)?.pop() {
+                trace!("Got a packet from recv buffer");
+                guard.done = true;
+                return Ok(res);
+            }
+            notified.await;
+        }
+    }
+
+    /// Extract at most `max` packets from mailbox, waiting for at least one.
+    pub(crate) async fn recv_batch(&self, max: usize) -> Result<Vec<T>> {
+        if max == 0 {
+            return Err(Error::InvalidArg);
+        }
+        let mut guard = RecvGuard {
+            mailbox: self,
+            done: false,
+        };
+        loop {
+            let notified = self.notify.notified();
+            pin!(notified);
+            _ = notified.as_mut().enable();
+            let batch = self.take_front(max)?;
+            if !batch.is_empty() {
+                trace!("Got {} packets from recv buffer", batch.len());
+                guard.done = true;
+                return Ok(batch);
+            }
+            notified.await;
+        }
+    }
+
+    /// Wait until there's a packet in mailbox, without taking it out.
+    pub(crate) async fn ready(&self) -> Result<()> {
+        let mut guard = RecvGuard {
+            mailbox: self,
+            done: false,
+        };
+        loop {
+            let notified = self.notify.notified();
+            pin!(notified);
+            _ = notified.as_mut().enable();
+            if self.is_ready()? {
+                // The wakeup may be taken from a receiving task, so it's passed on.
+                self.notify.notify_one();
+                guard.done = true;
+                return Ok(());
+            }
+            notified.await;
         }
-        Ok(rx)
+    }
+
+    /// Whether there's a packet in mailbox.
+    pub(crate) fn is_ready(&self) -> Result<bool> {
+        Ok(!self.queue.lock().map_err(Error::from)?.received.is_empty())
+    }
+
+    /// Extract a packet from mailbox, or register the waker of `cx` to be woken up once a packet
+    /// is put, along with the wakers of other tasks polling it meanwhile.
+    pub(crate) fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Result<T>> {
+        let mut queue = self.queue.lock().map_err(Error::from)?;
+        // Registered under the same lock as `put`, so that a packet put right after is not missed.
+        let Some(res) = queue.received.pop_front() else {
+            if !queue.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                queue.wakers.push(cx.waker().clone());
+            }
+            return Poll::Pending;
+        };
+        let left = !queue.received.is_empty();
+        drop(queue);
+        if left {
+            self.notify.notify_one();
+        }
+        trace!("Got a packet from recv buffer");
+        Poll::Ready(Ok(res))
+    }
+
+    /// Extract a packet from mailbox if there's one, without waiting.
+    pub(crate) fn try_recv(&self) -> Result<Option<T>> {
+        Ok(self.take_front(1)?.pop())
+    }
+
+    /// Extract a packet from mailbox, waiting for at most `timeout`.
+    pub(crate) async fn recv_timeout(&self, timeout: Duration) -> Result<T> {
+        #[allow(clippy::map_err_ignore)]
+        time::timeout(timeout, self.recv())
+            .await
+            .map_err(|_| Error::TimedOut)?
     }
 
     /// Put a packet into mailbox.
-    pub(crate) fn put(&mut self, res: RecvResult) -> Result<()> {
+    pub(crate) fn put(&self, res: T) -> Result<()> {
         trace!("{:?} received a packet", self);
-        if let Some(tx) = self.watcher.take() {
-            #[allow(clippy::map_err_ignore)]
-            tx.send(res).map_err(|_| Error::BrokenPipe)?;
-        } else {
-            self.received.push_back(res);
+        let wakers = {
+            let mut queue = self.queue.lock().map_err(Error::from)?;
+            queue.enqueue(res);
+            mem::take(&mut queue.wakers)
+        };
+        self.notify.notify_one();
+        wakers.into_iter().for_each(Waker::wake);
+        Ok(())
+    }
+
+    /// Put a burst of packets into mailbox in one shot.
+    pub(crate) fn put_batch(&self, batch: Vec<T>) -> Result<()> {
+        trace!("{:?} received {} packets", self, batch.len());
+        let mut queue = self.queue.lock().map_err(Error::from)?;
+        for res in batch {
+            queue.enqueue(res);
         }
+        let wakers = mem::take(&mut queue.wakers);
+        drop(queue);
+        self.notify.notify_one();
+        wakers.into_iter().for_each(Waker::wake);
         Ok(())
     }
+
+    /// Put packets taken out but not consumed back at the front of mailbox, in order. They are
+    /// neither counted nor dropped again.
+    pub(crate) fn put_back(&self, batch: Vec<T>) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut queue = self.queue.lock().map_err(Error::from)?;
+        for res in batch.into_iter().rev() {
+            queue.received.push_front(res);
+        }
+        let wakers = mem::take(&mut queue.wakers);
+        drop(queue);
+        self.notify.notify_one();
+        wakers.into_iter().for_each(Waker::wake);
+        Ok(())
+    }
+
+    /// Set the max number of packets in the receive buffer, and the policy on overflow.
+    ///
+    /// Packets beyond the new size are dropped from the front unless under
+    /// `DropPolicy::Backpressure`.
+    pub(crate) fn set_capacity(&self, capacity: usize, policy: DropPolicy) -> Result<()> {
+        if capacity == 0 {
+            return Err(Error::InvalidArg);
+        }
+        let mut queue = self.queue.lock().map_err(Error::from)?;
+        queue.capacity = capacity;
+        queue.policy = policy;
+        if policy != DropPolicy::Backpressure {
+            while queue.received.len() > capacity {
+                let _dropped = queue.received.pop_front();
+                queue.stats.dropped = queue.stats.dropped.wrapping_add(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// The max number of packets in the receive buffer, and the policy on overflow.
+    pub(crate) fn capacity(&self) -> Result<(usize, DropPolicy)> {
+        let queue = self.queue.lock().map_err(Error::from)?;
+        Ok((queue.capacity, queue.policy))
+    }
+
+    /// Receive statistics.
+    pub(crate) fn stats(&self) -> Result<SocketStats> {
+        let queue = self.queue.lock().map_err(Error::from)?;
+        Ok(SocketStats {
+            queued: queue.received.len(),
+            ..queue.stats
+        })
+    }
+
+    /// Attach a BPF program filtering the packets arriving at the mailbox, or detach it with
+    /// `None`.
+    pub(crate) fn set_filter(&self, filter: Option<BpfProgram>) -> Result<()> {
+        self.queue.lock().map_err(Error::from)?.filter = filter.map(Arc::new);
+        Ok(())
+    }
+
+    /// The BPF program filtering the packets arriving at the mailbox, if any.
+    pub(crate) fn filter(&self) -> Result<Option<Arc<BpfProgram>>> {
+        let queue = self.queue.lock().map_err(Error::from)?;
+        Ok(queue.filter.as_ref().map(Arc::clone))
+    }
+
+    /// Count `n` packets rejected by the filter.
+    pub(crate) fn count_filtered(&self, n: usize) -> Result<()> {
+        let mut queue = self.queue.lock().map_err(Error::from)?;
+        queue.stats.filtered = queue.stats.filtered.wrapping_add(n as u64);
+        Ok(())
+    }
+
+    /// Take at most `max` packets from the front, and pass the wakeup on to another waiting
+    /// task if packets are left.
+    fn take_front(&self, max: usize) -> Result<Vec<T>> {
+        let mut queue = self.queue.lock().map_err(Error::from)?;
+        let len = queue.received.len().min(max);
+        let batch: Vec<T> = queue.received.drain(..len).collect();
+        let left = !queue.received.is_empty();
+        drop(queue);
+        if !batch.is_empty() && left {
+            self.notify.notify_one();
+        }
+        Ok(batch)
+    }
+}
+
+/// Passes the wakeup on to another waiting task if a receiving future is dropped before it
+/// returns while packets are left, as it may have been woken up for one of them, e.g. when it's
+/// cancelled by `select!` or a timeout, or its task is aborted.
+struct RecvGuard<'a, T> {
+    /// The mailbox received from.
+    mailbox: &'a Mailbox<T>,
+    /// Whether the receiving future has returned.
+    done: bool,
+}
+
+impl<T> Drop for RecvGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let left = self
+            .mailbox
+            .queue
+            .lock()
+            .map_or(false, |queue| !queue.received.is_empty());
+        if left {
+            self.mailbox.notify.notify_one();
+        }
+    }
+}
+
+impl Mailbox<RecvResult> {
+    /// Put the datagrams arriving at the mailbox back in order by `buf`, or stop reordering
+    /// with `None`. Datagrams ready in the previous buffer are put into the mailbox.
+    pub(crate) fn set_reorder(&self, buf: Option<ReorderBuffer>) -> Result<()> {
+        let reorder = match buf {
+            Some(buf) => Some(Reorder {
+                buf,
+                seqn: reorder::seqn_field()?,
+                srcs: HashMap::new(),
+            }),
+            None => None,
+        };
+        let mut queue = self.queue.lock().map_err(Error::from)?;
+        let Some(mut prev) = mem::replace(&mut queue.reorder, reorder) else {
+            return Ok(());
+        };
+        let ready = prev.drain();
+        if ready.is_empty() {
+            return Ok(());
+        }
+        drop(queue);
+        self.put_batch(ready)
+    }
+
+    /// Pass a batch of datagrams through the reorder buffer of the mailbox if any, returning
+    /// the ones ready in order.
+    fn reorder(&self, batch: Vec<RecvResult>) -> Result<Vec<RecvResult>> {
+        let mut queue = self.queue.lock().map_err(Error::from)?;
+        let Some(reorder) = queue.reorder.as_mut() else {
+            return Ok(batch);
+        };
+        let mut ready = Vec::with_capacity(batch.len());
+        for res in batch {
+            let (src, m) = match res {
+                Ok(datagram) => datagram,
+                Err(e) => {
+                    ready.push(Err(e));
+                    continue;
+                }
+            };
+            let seqn = m.dynfield(reorder.seqn);
+            match reorder.buf.insert(m) {
+                Ok(()) => {
+                    let _prev = reorder.srcs.insert(seqn, src);
+                }
+                // Too late or too early to be reordered, delivered as it is.
+                Err((_, m)) => ready.push(Ok((src, m))),
+            }
+        }
+        ready.extend(reorder.drain());
+        Ok(ready)
+    }
+}
+
+impl Reorder {
+    /// Take the datagrams ready in order.
+    fn drain(&mut self) -> Vec<RecvResult> {
+        self.buf
+            .drain_all()
+            .into_iter()
+            .filter_map(|m| {
+                let src = self.srcs.remove(&m.dynfield(self.seqn))?;
+                Some(Ok((src, m)))
+            })
+            .collect()
+    }
+}
+
+impl<T> MailboxQueue<T> {
+    /// Put a packet at the end of the receive buffer, applying the drop policy if it's full.
+    fn enqueue(&mut self, res: T) {
+        self.stats.received = self.stats.received.wrapping_add(1);
+        if self.received.len() >= self.capacity {
+            match self.policy {
+                DropPolicy::DropNewest => {
+                    self.stats.dropped = self.stats.dropped.wrapping_add(1);
+                    return;
+                }
+                DropPolicy::DropOldest => {
+                    let _dropped = self.received.pop_front();
+                    self.stats.dropped = self.stats.dropped.wrapping_add(1);
+                }
+                DropPolicy::Backpressure => {
+                    self.stats.overlimit = self.stats.overlimit.wrapping_add(1);
+                }
+            }
+        }
+        self.received.push_back(res);
+    }
 }
 
 /// Bind sockfd to a (ip, port) pair.
 pub(crate) fn bind_fd(addr: SocketAddr) -> Result<(i32, u16)> {
+    bind_fd_with(addr, SocketOptions::default())
+}
+
+/// Bind sockfd to a (ip, port) pair with the given options.
+pub(crate) fn bind_fd_with(addr: SocketAddr, opts: SocketOptions) -> Result<(i32, u16)> {
     let mut inner = SOCK_TABLE.inner.lock().map_err(Error::from)?;
     let fd = inner.free_fd.pop_front().ok_or(Error::NoBuf)?;
-    let port = bind_port(addr.port(), addr.ip(), fd)?;
+    let port = match bind_port(addr.port(), addr.ip(), fd, opts) {
+        Ok(port) => port,
+        Err(e) => {
+            inner.free_fd.push_front(fd);
+            return Err(e);
+        }
+    };
     let fd_idx: usize = fd.try_into().map_err(Error::from)?;
     *inner.open.get_mut(fd_idx).ok_or(Error::OutOfRange)? = SockState::InUse { port };
     Ok((fd, port))
 }
 
+/// Allocate a sockfd for an accepted connection.
+///
+/// The port stays owned by the listening socket, so it's not freed with this sockfd.
+pub(crate) fn alloc_fd() -> Result<i32> {
+    let mut inner = SOCK_TABLE.inner.lock().map_err(Error::from)?;
+    let fd = inner.free_fd.pop_front().ok_or(Error::NoBuf)?;
+    let fd_idx: usize = fd.try_into().map_err(Error::from)?;
+    *inner.open.get_mut(fd_idx).ok_or(Error::OutOfRange)? = SockState::Accepted;
+    Ok(fd)
+}
+
 /// Free the sockfd.
 pub(crate) fn free_fd(fd: i32) -> Result<()> {
     let mut inner = SOCK_TABLE.inner.lock().map_err(Error::from)?;
@@ -163,15 +717,15 @@ pub(crate) fn free_fd(fd: i32) -> Result<()> {
     let state = *inner.open.get(fd_idx).ok_or(Error::OutOfRange)?;
     let port = match state {
         SockState::InUse { port, .. } => port,
-        SockState::Unused => 0,
+        SockState::Unused | SockState::Accepted | SockState::Raw => 0,
     };
     *inner.open.get_mut(fd_idx).ok_or(Error::OutOfRange)? = SockState::Unused;
     inner.free_fd.push_front(fd);
-    free_port(port)
+    free_port(port, fd)
 }
 
 /// Bind sockfd to a port, and return the port number.
-fn bind_port(port: u16, addr: IpAddr, fd: i32) -> Result<u16> {
+fn bind_port(port: u16, addr: IpAddr, fd: i32, opts: SocketOptions) -> Result<u16> {
     let mut inner = PORT_TABLE.inner.lock().map_err(Error::from)?;
     if inner.info.len() == (u16::MAX as usize).saturating_sub(1) {
         error!("Socket number exceeds");
@@ -189,40 +743,141 @@ fn bind_port(port: u16, addr: IpAddr, fd: i32) -> Result<u16> {
         next_port
     } else {
         // check if this port is already bound
-        if inner.info.get(&port).is_some() {
-            error!("Port {port} already bound");
-            return Err(Error::InvalidArg);
+        if let Some(bound) = inner.info.get(&port) {
+            if !bound.iter().all(|info| info.shares_with(addr, opts)) {
+                error!("Port {port} already bound");
+                return Err(Error::InvalidArg);
+            }
         }
         port
     };
-    let info = PortInfo { fd, ip: addr };
-    let _prev = inner.info.insert(port, info);
+    let info = PortInfo {
+        fd,
+        ip: addr,
+        peer: None,
+        opts,
+        groups: Vec::new(),
+    };
+    inner.info.entry(port).or_default().push(info);
     Ok(port)
 }
 
-/// Find a free port.
-fn free_port(port: u16) -> Result<()> {
-    let _prev = PORT_TABLE
-        .inner
-        .lock()
-        .map_err(Error::from)?
-        .info
-        .remove(&port);
+/// Release the port bound by sockfd.
+fn free_port(port: u16, fd: i32) -> Result<()> {
+    let mut inner = PORT_TABLE.inner.lock().map_err(Error::from)?;
+    if let Some(bound) = inner.info.get_mut(&port) {
+        bound.retain(|info| info.fd != fd);
+        if bound.is_empty() {
+            let _prev = inner.info.remove(&port);
+        }
+    }
     Ok(())
 }
 
 /// Called by agent thread, find sockfd by (ip, port).
-pub(crate) fn addr_2_sockfd(dst_port: u16, dst_ip: IpAddr) -> Option<i32> {
+///
+/// Packets from `src_addr` are filtered out if the socket is connected to another peer. Sockets
+/// bound to `dst_ip` take precedence over the ones bound to the unspecified address, and packets
+/// are distributed across sockets sharing the port by the hash of `src_addr`.
+pub(crate) fn addr_2_sockfd(dst_port: u16, dst_ip: IpAddr, src_addr: SocketAddr) -> Option<i32> {
     let inner = PORT_TABLE.inner.lock().ok()?;
+    let bound = inner.info.get(&dst_port)?;
+    let exact = bound
+        .iter()
+        .any(|info| info.ip == dst_ip && info.accepts(dst_ip, src_addr));
+    let candidates = || {
+        bound
+            .iter()
+            .filter(move |info| info.accepts(dst_ip, src_addr) && (!exact || info.ip == dst_ip))
+    };
+    let idx = match candidates().count() {
+        0 => return None,
+        1 => 0,
+        n => {
+            let mut hasher = DefaultHasher::new();
+            src_addr.hash(&mut hasher);
+            #[allow(clippy::cast_possible_truncation)] // used as hash
+            let hash = hasher.finish() as usize;
+            hash.checked_rem(n)?
+        }
+    };
+    candidates().nth(idx).map(|info| info.fd)
+}
+
+/// Set the connected peer of a socket bound to a port, or clear it if `peer` is `None`.
+pub(crate) fn connect_port(port: u16, fd: i32, peer: Option<SocketAddr>) -> Result<()> {
+    let mut inner = PORT_TABLE.inner.lock().map_err(Error::from)?;
     inner
         .info
-        .get(&dst_port)
-        .and_then(|&PortInfo { ip, fd }| (ip.is_unspecified() || ip == dst_ip).then_some(fd))
+        .get_mut(&port)
+        .and_then(|bound| bound.iter_mut().find(|info| info.fd == fd))
+        .ok_or(Error::NotExist)?
+        .peer = peer;
+    Ok(())
+}
+
+/// Add a multicast group to the ones joined by a socket bound to a port.
+pub(crate) fn join_group(port: u16, fd: i32, group: IpAddr) -> Result<()> {
+    let mut inner = PORT_TABLE.inner.lock().map_err(Error::from)?;
+    let info = inner
+        .info
+        .get_mut(&port)
+        .and_then(|bound| bound.iter_mut().find(|info| info.fd == fd))
+        .ok_or(Error::NotExist)?;
+    if info.groups.contains(&group) {
+        return Err(Error::Exists);
+    }
+    info.groups.push(group);
+    Ok(())
+}
+
+/// Remove a multicast group from the ones joined by a socket bound to a port.
+pub(crate) fn leave_group(port: u16, fd: i32, group: IpAddr) -> Result<()> {
+    let mut inner = PORT_TABLE.inner.lock().map_err(Error::from)?;
+    let info = inner
+        .info
+        .get_mut(&port)
+        .and_then(|bound| bound.iter_mut().find(|info| info.fd == fd))
+        .ok_or(Error::NotExist)?;
+    let idx = info
+        .groups
+        .iter()
+        .position(|&g| g == group)
+        .ok_or(Error::NotExist)?;
+    let _prev = info.groups.swap_remove(idx);
+    Ok(())
+}
+
+/// Bind a connection identified by (local port, remote address) to a sockfd.
+pub(crate) fn bind_conn(port: u16, peer: SocketAddr, fd: i32) -> Result<()> {
+    let mut inner = CONN_TABLE.inner.lock().map_err(Error::from)?;
+    if inner.contains_key(&(port, peer)) {
+        error!("Connection {port} <-> {peer} already exists");
+        return Err(Error::Exists);
+    }
+    let _prev = inner.insert((port, peer), fd);
+    Ok(())
+}
+
+/// Remove a connection from the connection table.
+pub(crate) fn free_conn(port: u16, peer: SocketAddr) -> Result<()> {
+    let _prev = CONN_TABLE
+        .inner
+        .lock()
+        .map_err(Error::from)?
+        .remove(&(port, peer));
+    Ok(())
+}
+
+/// Called by agent thread, find the sockfd of an established connection.
+pub(crate) fn conn_2_sockfd(dst_port: u16, src_addr: SocketAddr) -> Option<i32> {
+    let inner = CONN_TABLE.inner.lock().ok()?;
+    inner.get(&(dst_port, src_addr)).copied()
 }
 
 /// Called by socket, create mailbox on creation.
-pub(crate) fn alloc_mailbox(sockfd: i32) -> Result<Arc<Mutex<Mailbox>>> {
-    let mailbox = Arc::new(Mutex::new(Mailbox::default()));
+pub(crate) fn alloc_mailbox(sockfd: i32) -> Result<Arc<Mailbox>> {
+    let mailbox = Arc::new(Mailbox::default());
     let _prev = MAILBOX_TABLE
         .inner
         .lock()
@@ -241,16 +896,300 @@ pub(crate) fn dealloc_mailbox(sockfd: i32) -> Result<()> {
     Ok(())
 }
 
-/// Called by the agent thread, put arrived packets into mailbox.
-pub(crate) fn put_mailbox(sockfd: i32, res: RecvResult) -> Result<()> {
-    if let Some(mailbox) = MAILBOX_TABLE
+/// Called by a socket, put a datagram from `src_addr` on this host into the mailbox of `sockfd`
+/// directly, bypassing the devices.
+pub(crate) fn put_local(sockfd: i32, src_addr: SocketAddr, m: Mbuf) -> Result<()> {
+    put_mailboxes(&mut vec![(sockfd, Ok((src_addr, m)))])
+}
+
+/// Called by the agent thread, put a burst of arrived packets into mailboxes.
+///
+/// Packets to the same socket are put in one shot, and packets to unknown sockets or rejected by
+/// the filter of the socket are dropped. Packets to a socket reordering them are held until the
+/// ones before them arrive.
+/// `delivered` is left empty for reuse.
+pub(crate) fn put_mailboxes(delivered: &mut Vec<(i32, RecvResult)>) -> Result<()> {
+    let mut batches: Vec<(i32, Vec<RecvResult>)> = Vec::new();
+    for (sockfd, res) in delivered.drain(..) {
+        match batches.iter_mut().find(|&&mut (fd, _)| fd == sockfd) {
+            Some(&mut (_, ref mut batch)) => batch.push(res),
+            None => batches.push((sockfd, vec![res])),
+        }
+    }
+    let table = MAILBOX_TABLE.inner.lock().map_err(Error::from)?;
+    for (sockfd, mut batch) in batches {
+        let Some(mailbox) = table.get(&sockfd) else {
+            warn!("{} packets to unknown socket {sockfd} dropped", batch.len());
+            continue;
+        };
+        if let Some(prog) = mailbox.filter()? {
+            let len = batch.len();
+            batch.retain(|res| {
+                res.as_ref()
+                    .map_or(true, |&(_, ref m)| prog.matches_mbuf(m))
+            });
+            mailbox.count_filtered(len.wrapping_sub(batch.len()))?;
+        }
+        let batch = mailbox.reorder(batch)?;
+        if batch.is_empty() {
+            continue;
+        }
+        mailbox.put_batch(batch)?;
+    }
+    Ok(())
+}
+
+/// Get the receive statistics of each bound socket, indexed by sockfd.
+#[cfg(feature = "metrics")]
+pub(crate) fn mailbox_stats() -> Result<BTreeMap<i32, SocketStats>> {
+    let table = MAILBOX_TABLE.inner.lock().map_err(Error::from)?;
+    table
+        .iter()
+        .map(|(&sockfd, mailbox)| Ok((sockfd, mailbox.stats()?)))
+        .collect()
+}
+
+/// Bind a raw socket to a device, receiving frames with `ether_type` or all frames if it's
+/// `None`. Returns the sockfd and the mailbox of the raw socket.
+pub(crate) fn bind_raw(port_id: u16, ether_type: Option<u16>) -> Result<(i32, Arc<Mailbox<Mbuf>>)> {
+    let fd = {
+        let mut inner = SOCK_TABLE.inner.lock().map_err(Error::from)?;
+        let fd = inner.free_fd.pop_front().ok_or(Error::NoBuf)?;
+        let fd_idx: usize = fd.try_into().map_err(Error::from)?;
+        *inner.open.get_mut(fd_idx).ok_or(Error::OutOfRange)? = SockState::Raw;
+        fd
+    };
+    let mp = match PktMempool::create(&format!("raw_{fd}"), RAW_POOL_SIZE) {
+        Ok(mp) => mp,
+        Err(e) => {
+            free_fd(fd)?;
+            return Err(e);
+        }
+    };
+    let mailbox = Arc::new(Mailbox::default());
+    RAW_TABLE
         .inner
         .lock()
         .map_err(Error::from)?
-        .get(&sockfd)
+        .entry(port_id)
+        .or_default()
+        .push(RawSock {
+            fd,
+            ether_type,
+            mp,
+            mailbox: Arc::clone(&mailbox),
+        });
+    let _prev = RAW_SOCK_NUM.fetch_add(1, Ordering::AcqRel);
+    Ok((fd, mailbox))
+}
+
+/// Unbind a raw socket and free its sockfd.
+pub(crate) fn unbind_raw(port_id: u16, fd: i32) -> Result<()> {
+    if let Some(socks) = RAW_TABLE
+        .inner
+        .lock()
+        .map_err(Error::from)?
+        .get_mut(&port_id)
     {
-        mailbox.lock().map_err(Error::from)?.put(res)?;
-        return Ok(());
+        let len = socks.len();
+        socks.retain(|sock| sock.fd != fd);
+        if socks.len() < len {
+            let _prev = RAW_SOCK_NUM.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+    free_fd(fd)
+}
+
+/// Called by the agent thread, put a clone of the arrived frame into the mailboxes of raw
+/// sockets bound to `port_id`.
+pub(crate) fn put_raw(port_id: u16, m: &Mbuf) {
+    if RAW_SOCK_NUM.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let Ok(table) = RAW_TABLE.inner.lock() else {
+        return;
+    };
+    let Some(socks) = table.get(&port_id) else {
+        return;
+    };
+    let ether_type = match *m.data_slice() {
+        [_, _, _, _, _, _, _, _, _, _, _, _, hi, lo, ..] => u16::from_be_bytes([hi, lo]),
+        _ => return,
+    };
+    for sock in socks {
+        if matches!(sock.ether_type, Some(t) if t != ether_type) {
+            continue;
+        }
+        if let Ok(Some(prog)) = sock.mailbox.filter() {
+            if !prog.matches_mbuf(m) {
+                let _res = sock.mailbox.count_filtered(1);
+                continue;
+            }
+        }
+        let res = m.clone(&sock.mp).and_then(|m| sock.mailbox.put(m));
+        if let Err(e) = res {
+            warn!("Failed to deliver a frame to raw socket {}: {e:?}", sock.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DropPolicy, Mailbox};
+    use crate::Error;
+    use std::{
+        future::{self, Future},
+        sync::Arc,
+        task::Poll,
+        time::Duration,
+    };
+    use tokio::{task, time};
+
+    #[tokio::test]
+    async fn test_mailbox() {
+        let mailbox = Arc::new(Mailbox::<u32>::default());
+        // Several tasks wait at the same time, and each packet is received once.
+        let receivers: Vec<_> = (0..4)
+            .map(|_| {
+                let mailbox = Arc::clone(&mailbox);
+                task::spawn(async move { mailbox.recv().await.unwrap() })
+            })
+            .collect();
+        task::yield_now().await;
+        mailbox.put_batch(vec![1, 2]).unwrap();
+        mailbox.put(3).unwrap();
+        mailbox.put(4).unwrap();
+        let mut received = Vec::new();
+        for receiver in receivers {
+            received.push(receiver.await.unwrap());
+        }
+        received.sort_unstable();
+        assert_eq!(received, [1, 2, 3, 4]);
+
+        // A cancelled receive doesn't lose the packet put afterwards.
+        assert!(matches!(
+            mailbox
+                .recv_timeout(Duration::from_millis(10))
+                .await
+                .unwrap_err(),
+            Error::TimedOut
+        ));
+        drop(time::timeout(Duration::from_millis(10), mailbox.recv()).await);
+        mailbox.put(5).unwrap();
+        assert_eq!(mailbox.recv().await.unwrap(), 5);
+
+        mailbox.set_capacity(2, DropPolicy::DropOldest).unwrap();
+        mailbox.put_batch(vec![6, 7, 8]).unwrap();
+        assert_eq!(mailbox.recv_batch(4).await.unwrap(), [7, 8]);
+        assert_eq!(mailbox.try_recv().unwrap(), None);
+        assert_eq!(mailbox.stats().unwrap().dropped, 1);
+
+        // Packets put back are taken first, and never dropped.
+        mailbox.put(9).unwrap();
+        mailbox.put_back(vec![7, 8]).unwrap();
+        assert_eq!(mailbox.recv_batch(4).await.unwrap(), [7, 8, 9]);
+        assert_eq!(mailbox.stats().unwrap().dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_recv() {
+        let mailbox = Arc::new(Mailbox::<u32>::default());
+        let mut first = Box::pin(mailbox.recv());
+        let mut second = Box::pin(mailbox.recv());
+        future::poll_fn(|cx| {
+            assert!(first.as_mut().poll(cx).is_pending());
+            assert!(second.as_mut().poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        // The packet wakes up the first receiver, which is cancelled before taking it.
+        mailbox.put(1).unwrap();
+        drop(first);
+        assert_eq!(
+            time::timeout(Duration::from_millis(10), second)
+                .await
+                .unwrap()
+                .unwrap(),
+            1
+        );
+
+        // Aborted receiving tasks leave the packets to the others.
+        let aborted = task::spawn({
+            let mailbox = Arc::clone(&mailbox);
+            async move { mailbox.recv().await.unwrap() }
+        });
+        task::yield_now().await;
+        aborted.abort();
+        assert!(aborted.await.unwrap_err().is_cancelled());
+        mailbox.put_batch(vec![2, 3]).unwrap();
+        assert_eq!(
+            tokio::select! {
+                res = mailbox.recv() => res.unwrap(),
+                () = time::sleep(Duration::from_millis(10)) => 0,
+            },
+            2
+        );
+        assert_eq!(mailbox.recv_batch(4).await.unwrap(), [3]);
+    }
+
+    #[tokio::test]
+    async fn test_ready() {
+        let mailbox = Arc::new(Mailbox::<u32>::default());
+        let (waiting, receiving) = {
+            let (m1, m2) = (Arc::clone(&mailbox), Arc::clone(&mailbox));
+            (
+                task::spawn(async move { m1.ready().await.unwrap() }),
+                task::spawn(async move { m2.recv().await.unwrap() }),
+            )
+        };
+        task::yield_now().await;
+        assert!(!mailbox.is_ready().unwrap());
+        mailbox.put(1).unwrap();
+        // The task waiting first takes the wakeup, and passes it on to the receiving one.
+        waiting.await.unwrap();
+        assert_eq!(receiving.await.unwrap(), 1);
+        assert!(!mailbox.is_ready().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_poll_recv() {
+        let mailbox = Arc::new(Mailbox::<u32>::default());
+        let receiver = {
+            let mailbox = Arc::clone(&mailbox);
+            task::spawn(async move { future::poll_fn(|cx| mailbox.poll_recv(cx)).await.unwrap() })
+        };
+        task::yield_now().await;
+        mailbox.put(1).unwrap();
+        assert_eq!(receiver.await.unwrap(), 1);
+        mailbox.put_batch(vec![2, 3]).unwrap();
+        assert_eq!(
+            future::poll_fn(|cx| mailbox.poll_recv(cx)).await.unwrap(),
+            2
+        );
+        assert_eq!(mailbox.recv().await.unwrap(), 3);
+
+        // Each polling task is woken up, instead of the latest one only.
+        let pollers: Vec<_> = (0..2)
+            .map(|_| {
+                let mailbox = Arc::clone(&mailbox);
+                task::spawn(
+                    async move { future::poll_fn(|cx| mailbox.poll_recv(cx)).await.unwrap() },
+                )
+            })
+            .collect();
+        task::yield_now().await;
+        mailbox.put(4).unwrap();
+        mailbox.put(5).unwrap();
+        let mut received = Vec::new();
+        for poller in pollers {
+            received.push(
+                time::timeout(Duration::from_millis(10), poller)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+            );
+        }
+        received.sort_unstable();
+        assert_eq!(received, [4, 5]);
     }
-    Err(Error::BadFd)
 }
@@ -0,0 +1,994 @@
+//! TCP implementation
+//!
+//! Segments are routed to sockets by the `RxAgent`, and the connection state is driven by the
+//! socket itself when it's polled. This is a minimal TCP: there's no retransmission, congestion
+//! control, or out-of-order reassembly, so it's only suited for lossless links for now.
+
+use crate::{
+    eth_dev::{
+        TxSender, RTE_ETH_TX_OFFLOAD_IPV4_CKSUM, RTE_ETH_TX_OFFLOAD_TCP_CKSUM,
+        RTE_ETH_TX_OFFLOAD_TCP_TSO,
+    },
+    headers::{EtherHdrMut, Ipv4Hdr, Ipv4HdrMut, TcpHdr, TcpHdrMut},
+    mbuf::Mbuf,
+    net_dev,
+    packet::Packet,
+    proto::arp,
+    proto::socket::{self, addr_2_sockfd, conn_2_sockfd, Mailbox, RecvResult, SendWait, IPID},
+    proto::{
+        cksum_add, cksum_fold, ipv4_pseudo_sum, L3Protocol, L4Protocol, Protocol, ETHER_HDR_LEN,
+        IP_NEXT_PROTO_TCP,
+    },
+    trace::{error, instrument, trace, warn},
+    Error, Result,
+};
+use bytes::{Buf, BytesMut};
+use dpdk_sys::{
+    rte_ether_addr, rte_rdtsc, RTE_ETHER_TYPE_IPV4, RTE_MBUF_F_TX_IPV4, RTE_MBUF_F_TX_IP_CKSUM,
+    RTE_MBUF_F_TX_TCP_CKSUM, RTE_MBUF_F_TX_TCP_SEG,
+};
+use std::{
+    fmt::Debug,
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs},
+    pin::Pin,
+    sync::{atomic::Ordering, Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// FIN flag in TCP header.
+const TCP_FIN: u8 = 0x01;
+
+/// SYN flag in TCP header.
+const TCP_SYN: u8 = 0x02;
+
+/// RST flag in TCP header.
+const TCP_RST: u8 = 0x04;
+
+/// PSH flag in TCP header.
+const TCP_PSH: u8 = 0x08;
+
+/// ACK flag in TCP header.
+const TCP_ACK: u8 = 0x10;
+
+/// Maximum segment size, so that a segment never needs IP fragmentation (MTU - 40).
+const TCP_MSS: usize = 1460;
+
+/// Maximum payload written in a segment split by the hardware with TCP segmentation offload,
+/// bounded by the IPv4 total length.
+const TCP_TSO_MAX: usize = 65_000;
+
+/// The receive window advertised to the peer.
+const TCP_WINDOW: u16 = u16::MAX;
+
+/// Connection state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TcpState {
+    /// SYN sent, waiting for SYN-ACK.
+    SynSent,
+    /// SYN received and SYN-ACK sent, waiting for ACK.
+    SynReceived,
+    /// Data can be sent and received.
+    Established,
+    /// FIN sent, waiting for the peer's FIN.
+    FinWait,
+    /// FIN received, data can still be sent.
+    CloseWait,
+    /// Both directions are closed.
+    Closed,
+}
+
+/// Transmission control block.
+#[derive(Debug)]
+struct Tcb {
+    /// Connection state.
+    state: TcpState,
+    /// The next sequence number to send.
+    snd_nxt: u32,
+    /// The next sequence number expected from the peer.
+    rcv_nxt: u32,
+    /// In-order data received but not read yet.
+    rx_buf: BytesMut,
+}
+
+impl Tcb {
+    /// Create a `Tcb` with a clock-based initial sequence number.
+    #[allow(unsafe_code)]
+    fn new(state: TcpState, rcv_nxt: u32) -> Self {
+        // SAFETY: ffi
+        #[allow(clippy::cast_possible_truncation)] // only the lower bits are used
+        let iss = unsafe { rte_rdtsc() } as u32;
+        Self {
+            state,
+            snd_nxt: iss,
+            rcv_nxt,
+            rx_buf: BytesMut::new(),
+        }
+    }
+}
+
+/// A parsed TCP segment.
+#[derive(Debug)]
+struct Segment {
+    /// Source address.
+    src: SocketAddrV4,
+    /// Destination address.
+    dst: SocketAddrV4,
+    /// Sequence number.
+    seq: u32,
+    /// Acknowledgment number.
+    ack: u32,
+    /// TCP flags.
+    flags: u8,
+    /// Segment data.
+    payload: BytesMut,
+}
+
+impl Segment {
+    /// Parse a segment from an `Mbuf` starting with its IPv4 header.
+    ///
+    /// This function returns `None` if the segment is malformed or its checksum is wrong.
+    fn parse(m: &Mbuf) -> Option<Self> {
+        let mut data = BytesMut::with_capacity(m.pkt_len());
+        for seg in m.iter() {
+            data.extend_from_slice(seg.data_slice());
+        }
+        let ipv4_hdr_len = L3Protocol::Ipv4.length() as usize;
+        let tcp_hdr_len = L4Protocol::Tcp.length() as usize;
+        if data.len() < ipv4_hdr_len.saturating_add(tcp_hdr_len) {
+            return None;
+        }
+
+        let ip_hdr = Ipv4Hdr::new(&data).ok()?;
+        let src_ip = ip_hdr.src_addr().octets();
+        let dst_ip = ip_hdr.dst_addr().octets();
+        data.advance(ipv4_hdr_len);
+
+        let l4_len: u16 = data.len().try_into().ok()?;
+        let sum = ipv4_pseudo_sum(src_ip, dst_ip, IP_NEXT_PROTO_TCP, l4_len);
+        if cksum_fold(cksum_add(sum, &data)) != 0 {
+            warn!("TCP checksum mismatch, segment dropped");
+            return None;
+        }
+
+        let tcp_hdr = TcpHdr::new(&data).ok()?;
+        let src = SocketAddrV4::new(Ipv4Addr::from(src_ip), tcp_hdr.src_port());
+        let dst = SocketAddrV4::new(Ipv4Addr::from(dst_ip), tcp_hdr.dst_port());
+        let seq = tcp_hdr.sent_seq();
+        let ack = tcp_hdr.recv_ack();
+        let flags = tcp_hdr.tcp_flags();
+        let data_off = tcp_hdr.header_len();
+        if data_off < tcp_hdr_len || data_off > data.len() {
+            return None;
+        }
+        data.advance(data_off);
+        Some(Self {
+            src,
+            dst,
+            seq,
+            ack,
+            flags,
+            payload: data,
+        })
+    }
+}
+
+/// A sockfd allocated for a connection, freed if it's dropped before being taken by a
+/// `TcpStream`, e.g. when opening the connection fails or is cancelled.
+#[derive(Debug)]
+struct FdGuard {
+    /// Socket fd.
+    sockfd: i32,
+    /// Whether the sockfd is still owned by this guard.
+    armed: bool,
+}
+
+impl FdGuard {
+    /// Guard an allocated sockfd.
+    fn new(sockfd: i32) -> Self {
+        Self {
+            sockfd,
+            armed: true,
+        }
+    }
+
+    /// Take the sockfd, which is no longer freed by this guard.
+    fn take(mut self) -> i32 {
+        self.armed = false;
+        self.sockfd
+    }
+}
+
+impl Drop for FdGuard {
+    fn drop(&mut self) {
+        if self.armed {
+            if let Err(e) = socket::free_fd(self.sockfd) {
+                warn!("Failed to free sockfd {}: {e}", self.sockfd);
+            }
+        }
+    }
+}
+
+/// A TCP socket server, listening for connections.
+#[allow(missing_copy_implementations, clippy::module_name_repetitions)]
+pub struct TcpListener {
+    /// Socket fd.
+    sockfd: i32,
+    /// The IP address that this socket is bound to.
+    ip: IpAddr,
+    /// The port that this socket is bound to.
+    port: u16,
+    /// A pointer to its mailbox.
+    mailbox: Arc<Mailbox>,
+}
+
+impl TcpListener {
+    /// Creates a new `TcpListener` bound to the given address.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Invalid socket address.
+    /// - Too much bound sockets.
+    #[inline]
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        #[allow(clippy::map_err_ignore)]
+        while let Some(addr) = addr
+            .to_socket_addrs()
+            .map_err(|_| Error::InvalidArg)?
+            .next()
+        {
+            // TODO: support ipv6
+            if addr.is_ipv6() {
+                return Err(Error::InvalidArg);
+            }
+            if let Ok((sockfd, port)) = socket::bind_fd(addr) {
+                if net_dev::find_dev_by_ip(addr.ip()).is_ok() {
+                    let mailbox = socket::alloc_mailbox(sockfd)?;
+                    return Ok(TcpListener {
+                        sockfd,
+                        ip: addr.ip(),
+                        port,
+                        mailbox,
+                    });
+                }
+                socket::free_fd(sockfd)?;
+                return Err(Error::InvalidArg);
+            }
+        }
+        Err(Error::NoBuf)
+    }
+
+    /// Accepts a new incoming connection from this listener.
+    ///
+    /// This function waits until a handshake with a remote peer is completed. On success,
+    /// returns the connected stream and the remote peer's address.
+    ///
+    /// If it's cancelled in the middle of a handshake, the half-open connection is dropped and
+    /// its resources are freed, the peer has to connect again.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    /// - Send agent not started.
+    #[inline]
+    pub async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        loop {
+            let (_, syn) = self.mailbox.recv().await??;
+            let Some(seg) = Segment::parse(&syn) else {
+                continue;
+            };
+            if seg.flags & (TCP_SYN | TCP_ACK | TCP_RST) != TCP_SYN {
+                trace!("Non-SYN segment to a listening socket dropped");
+                continue;
+            }
+            let sockfd = FdGuard::new(socket::alloc_fd()?);
+            let stream = match TcpStream::open(sockfd, seg.dst, seg.src, Some(seg.seq)).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to open a connection from {}: {e}", seg.src);
+                    continue;
+                }
+            };
+            let syn_ack = {
+                let mut tcb = stream.tcb.lock().map_err(Error::from)?;
+                let pkt = stream.segment(TCP_SYN | TCP_ACK, &tcb, &[])?;
+                tcb.snd_nxt = tcb.snd_nxt.wrapping_add(1);
+                pkt
+            };
+            stream.tx.send(syn_ack).await?;
+            match stream.handshake().await {
+                Ok(()) => return Ok((stream, SocketAddr::V4(seg.src))),
+                Err(e) => warn!("Handshake with {} failed: {e}", seg.src),
+            }
+        }
+    }
+
+    /// Returns the socket address that this listener is bound to, with the port chosen if it's
+    /// bound to port 0.
+    #[inline]
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.ip, self.port)
+    }
+}
+
+impl Debug for TcpListener {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpListener")
+            .field("sockfd", &self.sockfd)
+            .field("ip", &self.ip)
+            .field("port", &self.port)
+            .finish()
+    }
+}
+
+impl Drop for TcpListener {
+    #[inline]
+    fn drop(&mut self) {
+        #[allow(clippy::unwrap_used)] // used in drop
+        socket::dealloc_mailbox(self.sockfd).unwrap();
+        #[allow(clippy::unwrap_used)] // used in drop
+        socket::free_fd(self.sockfd).unwrap();
+    }
+}
+
+/// A TCP stream between a local and a remote socket.
+#[allow(missing_copy_implementations, clippy::module_name_repetitions)]
+pub struct TcpStream {
+    /// Socket fd.
+    sockfd: i32,
+    /// Local address of this connection.
+    local: SocketAddrV4,
+    /// Remote address of this connection.
+    peer: SocketAddrV4,
+    /// A channel to `TxAgent`.
+    tx: TxSender,
+    /// A pointer to its mailbox.
+    mailbox: Arc<Mailbox>,
+    /// Ether address of the device.
+    eth_addr: rte_ether_addr,
+    /// Ether address of the peer, resolved when the connection is opened.
+    peer_mac: rte_ether_addr,
+    /// Transmission control block.
+    tcb: Mutex<Tcb>,
+    /// What `AsyncWrite` waits for before trying to send again.
+    send_wait: Mutex<Option<SendWait>>,
+}
+
+impl TcpStream {
+    /// Opens a TCP connection to a remote host.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Invalid socket address.
+    /// - No running device.
+    /// - Too much bound sockets.
+    /// - `Error::ConnRefused`: the connection is refused by the peer.
+    /// - `Error::TimedOut`: the Ether address of the peer cannot be resolved.
+    #[inline]
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        #[allow(clippy::map_err_ignore)]
+        let peer = match addr
+            .to_socket_addrs()
+            .map_err(|_| Error::InvalidArg)?
+            .next()
+            .ok_or(Error::InvalidArg)?
+        {
+            SocketAddr::V4(peer) => peer,
+            // TODO: support ipv6
+            SocketAddr::V6(_) => return Err(Error::InvalidArg),
+        };
+        let local_ip = net_dev::local_ip_for(IpAddr::V4(*peer.ip()))?;
+        let (sockfd, port) = socket::bind_fd(SocketAddr::new(local_ip, 0))?;
+        let sockfd = FdGuard::new(sockfd);
+        let IpAddr::V4(ip) = local_ip else {
+            return Err(Error::InvalidArg);
+        };
+        let stream = Self::open(sockfd, SocketAddrV4::new(ip, port), peer, None).await?;
+        let syn = {
+            let mut tcb = stream.tcb.lock().map_err(Error::from)?;
+            let pkt = stream.segment(TCP_SYN, &tcb, &[])?;
+            tcb.snd_nxt = tcb.snd_nxt.wrapping_add(1);
+            pkt
+        };
+        stream.tx.send(syn).await?;
+        stream.handshake().await?;
+        Ok(stream)
+    }
+
+    /// Reads some bytes from the stream. On success, returns the number of bytes read.
+    ///
+    /// `Ok(0)` is returned once the peer has closed its sending side. This method is cancel
+    /// safe: the data received is kept in the stream until it's read.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    /// - `Error::ConnReset`: the connection is reset by the peer.
+    #[inline]
+    pub async fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if let Some(len) = self.read_buffered(buf)? {
+                return Ok(len);
+            }
+            instrument!(self.recv_segment(), "tcp_recv", sockfd = self.sockfd).await?;
+        }
+    }
+
+    /// Writes a buffer into the stream. On success, returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Send agent not started.
+    /// - `Error::NotConnected`: the handshake has not completed.
+    /// - `Error::BrokenPipe`: the sending side has been shut down.
+    #[inline]
+    pub async fn write(&self, buf: &[u8]) -> Result<usize> {
+        // Consume the acknowledgments queued up, so that the mailbox doesn't grow unboundedly.
+        loop {
+            let res = self.mailbox.try_recv()?;
+            match res {
+                Some(res) => self.process(res).await?,
+                None => break,
+            }
+        }
+        for chunk in buf.chunks(self.chunk_size()) {
+            let pkt = {
+                let mut tcb = self.tcb.lock().map_err(Error::from)?;
+                let pkt = self.data_segment(&tcb, chunk)?;
+                let len: u32 = chunk.len().try_into().map_err(Error::from)?;
+                tcb.snd_nxt = tcb.snd_nxt.wrapping_add(len);
+                pkt
+            };
+            instrument!(
+                self.tx.send(pkt),
+                "tcp_send",
+                sockfd = self.sockfd,
+                pkt_len = chunk.len()
+            )
+            .await?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Shuts down the sending side of the stream, a FIN is sent to the peer.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Send agent not started.
+    /// - `Error::NotConnected`: the handshake has not completed.
+    #[inline]
+    pub async fn shutdown(&self) -> Result<()> {
+        let fin = {
+            let mut tcb = self.tcb.lock().map_err(Error::from)?;
+            let Some((state, fin)) = self.fin(&tcb)? else {
+                return Ok(());
+            };
+            tcb.state = state;
+            tcb.snd_nxt = tcb.snd_nxt.wrapping_add(1);
+            fin
+        };
+        self.tx.send(fin).await
+    }
+
+    /// Returns the local socket address of this stream.
+    #[inline]
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        SocketAddr::V4(self.local)
+    }
+
+    /// Returns the socket address of the remote peer of this stream.
+    #[inline]
+    #[must_use]
+    pub fn peer_addr(&self) -> SocketAddr {
+        SocketAddr::V4(self.peer)
+    }
+
+    /// Create a `TcpStream` on an allocated sockfd and register the connection.
+    ///
+    /// `syn_seq` is the sequence number of the peer's SYN if it's received. The Ether address of
+    /// the peer is resolved here. The sockfd is freed if this function fails or is cancelled.
+    async fn open(
+        sockfd: FdGuard,
+        local: SocketAddrV4,
+        peer: SocketAddrV4,
+        syn_seq: Option<u32>,
+    ) -> Result<Self> {
+        let tcb = syn_seq.map_or_else(
+            || Tcb::new(TcpState::SynSent, 0),
+            |seq| Tcb::new(TcpState::SynReceived, seq.wrapping_add(1)),
+        );
+        let (tx, eth_addr) = net_dev::find_dev_by_ip(IpAddr::V4(*local.ip()))?;
+        let peer_mac = arp::resolve(*peer.ip(), *local.ip(), eth_addr, &tx).await?;
+        let fd = sockfd.sockfd;
+        let mailbox = socket::alloc_mailbox(fd)?;
+        if let Err(e) = socket::bind_conn(local.port(), SocketAddr::V4(peer), fd) {
+            socket::dealloc_mailbox(fd)?;
+            return Err(e);
+        }
+        Ok(Self {
+            sockfd: sockfd.take(),
+            local,
+            peer,
+            tx,
+            mailbox,
+            eth_addr,
+            peer_mac,
+            tcb: Mutex::new(tcb),
+            send_wait: Mutex::new(None),
+        })
+    }
+
+    /// Wait until the handshake is completed.
+    async fn handshake(&self) -> Result<()> {
+        loop {
+            let state = self.tcb.lock().map_err(Error::from)?.state;
+            match state {
+                TcpState::SynSent | TcpState::SynReceived => self.recv_segment().await?,
+                TcpState::Established
+                | TcpState::FinWait
+                | TcpState::CloseWait
+                | TcpState::Closed => return Ok(()),
+            }
+        }
+    }
+
+    /// Wait for a segment from the mailbox and process it.
+    async fn recv_segment(&self) -> Result<()> {
+        let res = self.mailbox.recv().await?;
+        self.process(res).await
+    }
+
+    /// Process a received segment, and send the reply if there's one.
+    async fn process(&self, res: RecvResult) -> Result<()> {
+        if let Some(ack) = self.on_recv(res)? {
+            self.tx.send(ack).await?;
+        }
+        Ok(())
+    }
+
+    /// Same as `process`, but the reply is dropped instead of waiting if the channel to the
+    /// `TxAgent` is full.
+    fn process_now(&self, res: RecvResult) -> Result<()> {
+        if let Some(ack) = self.on_recv(res)? {
+            if let Err(e) = self.tx.try_send(ack) {
+                warn!("Failed to send ACK to {}: {e}", self.peer);
+            }
+        }
+        Ok(())
+    }
+
+    /// Update the `Tcb` with a received segment, returning the reply to be sent.
+    fn on_recv(&self, res: RecvResult) -> Result<Option<Packet>> {
+        let (_, m) = res?;
+        match Segment::parse(&m) {
+            Some(seg) => {
+                let mut tcb = self.tcb.lock().map_err(Error::from)?;
+                self.on_segment(&mut tcb, &seg)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Read the data received so far into `buf`, returning the number of bytes read, or `None`
+    /// if there's no data yet. `Some(0)` is returned once the peer has closed its sending side.
+    fn read_buffered(&self, buf: &mut [u8]) -> Result<Option<usize>> {
+        let mut tcb = self.tcb.lock().map_err(Error::from)?;
+        if !tcb.rx_buf.is_empty() || buf.is_empty() {
+            let len = tcb.rx_buf.len().min(buf.len());
+            tcb.rx_buf
+                .copy_to_slice(buf.get_mut(..len).ok_or(Error::OutOfRange)?);
+            return Ok(Some(len));
+        }
+        match tcb.state {
+            TcpState::CloseWait | TcpState::Closed => Ok(Some(0)),
+            TcpState::SynSent
+            | TcpState::SynReceived
+            | TcpState::Established
+            | TcpState::FinWait => Ok(None),
+        }
+    }
+
+    /// Max payload written in a segment. The segmentation of large writes is left to the
+    /// hardware if supported.
+    fn chunk_size(&self) -> usize {
+        if self.tx.offloads() & RTE_ETH_TX_OFFLOAD_TCP_TSO == 0 {
+            TCP_MSS
+        } else {
+            TCP_TSO_MAX
+        }
+    }
+
+    /// Build a segment carrying `chunk`, which is only allowed while the sending side is open.
+    fn data_segment(&self, tcb: &Tcb, chunk: &[u8]) -> Result<Packet> {
+        match tcb.state {
+            TcpState::Established | TcpState::CloseWait => {}
+            TcpState::SynSent | TcpState::SynReceived => return Err(Error::NotConnected),
+            TcpState::FinWait | TcpState::Closed => return Err(Error::BrokenPipe),
+        }
+        self.segment(TCP_ACK | TCP_PSH, tcb, chunk)
+    }
+
+    /// The state after shutting down the sending side along with the FIN to send, or `None` if
+    /// it's shut down already.
+    fn fin(&self, tcb: &Tcb) -> Result<Option<(TcpState, Packet)>> {
+        let state = match tcb.state {
+            TcpState::Established => TcpState::FinWait,
+            TcpState::CloseWait => TcpState::Closed,
+            TcpState::FinWait | TcpState::Closed => return Ok(None),
+            TcpState::SynSent | TcpState::SynReceived => return Err(Error::NotConnected),
+        };
+        Ok(Some((state, self.segment(TCP_FIN | TCP_ACK, tcb, &[])?)))
+    }
+
+    /// Read some bytes from the stream without waiting, registering the waker of `cx` if there's
+    /// no data yet.
+    fn poll_recv_data(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        loop {
+            if let Some(len) = self.read_buffered(buf)? {
+                return Poll::Ready(Ok(len));
+            }
+            let res = ready!(self.mailbox.poll_recv(cx))?;
+            self.process_now(res)?;
+        }
+    }
+
+    /// Write a segment of `buf` into the stream without waiting, registering the waker of `cx`
+    /// if the channel to the `TxAgent` is full.
+    fn poll_send_data(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let chunk = buf
+            .get(..buf.len().min(self.chunk_size()))
+            .ok_or(Error::OutOfRange)?;
+        let len: u32 = chunk.len().try_into().map_err(Error::from)?;
+        // Consume the acknowledgments queued up, as `write` does.
+        while let Some(res) = self.mailbox.try_recv()? {
+            self.process_now(res)?;
+        }
+        self.poll_send(cx, |tcb| {
+            let pkt = self.data_segment(tcb, chunk)?;
+            Ok(Some((tcb.state, len, pkt)))
+        })
+        .map_ok(|()| chunk.len())
+    }
+
+    /// Shut down the sending side without waiting, registering the waker of `cx` if the channel
+    /// to the `TxAgent` is full.
+    fn poll_fin(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.poll_send(cx, |tcb| {
+            Ok(self.fin(tcb)?.map(|(state, fin)| (state, 1, fin)))
+        })
+    }
+
+    /// Send the segment built by `build` without waiting, then move to its state and advance
+    /// `snd_nxt` by its length. Nothing is sent if `build` returns `None`.
+    fn poll_send<F>(&self, cx: &mut Context<'_>, build: F) -> Poll<Result<()>>
+    where
+        F: Fn(&Tcb) -> Result<Option<(TcpState, u32, Packet)>>,
+    {
+        let mut wait = self.send_wait.lock().map_err(Error::from)?;
+        loop {
+            if let Some(fut) = wait.as_mut() {
+                let res = ready!(fut.as_mut().poll(cx));
+                *wait = None;
+                res?;
+            }
+            if self.tx.is_full() {
+                *wait = Some(Box::pin(self.tx.ready()));
+                continue;
+            }
+            let mut tcb = self.tcb.lock().map_err(Error::from)?;
+            let Some((state, len, pkt)) = build(&tcb)? else {
+                return Poll::Ready(Ok(()));
+            };
+            match self.tx.try_send(pkt) {
+                Ok(()) => {
+                    tcb.state = state;
+                    tcb.snd_nxt = tcb.snd_nxt.wrapping_add(len);
+                    return Poll::Ready(Ok(()));
+                }
+                // Another task takes the room in the channel first.
+                Err(Error::TempUnavail) => *wait = Some(Box::pin(self.tx.ready())),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+
+    /// Update the `Tcb` with a received segment, returning the reply to be sent.
+    fn on_segment(&self, tcb: &mut Tcb, seg: &Segment) -> Result<Option<Packet>> {
+        if seg.flags & TCP_RST != 0 {
+            let state = tcb.state;
+            tcb.state = TcpState::Closed;
+            return Err(if state == TcpState::SynSent {
+                Error::ConnRefused
+            } else {
+                Error::ConnReset
+            });
+        }
+        match tcb.state {
+            TcpState::SynSent => {
+                if seg.flags & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK && seg.ack == tcb.snd_nxt {
+                    tcb.rcv_nxt = seg.seq.wrapping_add(1);
+                    tcb.state = TcpState::Established;
+                    return self.segment(TCP_ACK, tcb, &[]).map(Some);
+                }
+                Ok(None)
+            }
+            TcpState::SynReceived => {
+                if seg.flags & TCP_ACK != 0 && seg.ack == tcb.snd_nxt {
+                    tcb.state = TcpState::Established;
+                    return self.on_data(tcb, seg);
+                }
+                Ok(None)
+            }
+            TcpState::Established | TcpState::FinWait | TcpState::CloseWait => {
+                self.on_data(tcb, seg)
+            }
+            TcpState::Closed => Ok(None),
+        }
+    }
+
+    /// Accept the data and FIN carried by an in-order segment.
+    fn on_data(&self, tcb: &mut Tcb, seg: &Segment) -> Result<Option<Packet>> {
+        let fin = seg.flags & TCP_FIN != 0;
+        if seg.payload.is_empty() && !fin {
+            return Ok(None); // pure ACK
+        }
+        if seg.seq != tcb.rcv_nxt {
+            trace!("Out-of-order segment dropped");
+            return self.segment(TCP_ACK, tcb, &[]).map(Some);
+        }
+        let len: u32 = seg.payload.len().try_into().map_err(Error::from)?;
+        tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(len);
+        tcb.rx_buf.extend_from_slice(&seg.payload);
+        if fin {
+            tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(1);
+            tcb.state = match tcb.state {
+                TcpState::Established => TcpState::CloseWait,
+                TcpState::FinWait => TcpState::Closed,
+                state @ (TcpState::SynSent
+                | TcpState::SynReceived
+                | TcpState::CloseWait
+                | TcpState::Closed) => state,
+            };
+        }
+        self.segment(TCP_ACK, tcb, &[]).map(Some)
+    }
+
+    /// Build a segment with the sequence numbers in `Tcb`.
+    ///
+    /// Checksums are left to the hardware if the device supports it, and a payload larger than
+    /// `TCP_MSS` is split into segments by the hardware, which must support TSO.
+    #[allow(clippy::cast_possible_truncation)]
+    fn segment(&self, flags: u8, tcb: &Tcb, payload: &[u8]) -> Result<Packet> {
+        let offloads = self.tx.offloads();
+        let tso = payload.len() > TCP_MSS;
+        if tso && offloads & RTE_ETH_TX_OFFLOAD_TCP_TSO == 0 {
+            return Err(Error::InvalidArg);
+        }
+        let l2_sz = ETHER_HDR_LEN;
+        let l3_sz = L3Protocol::Ipv4.length();
+        let l4_sz = L4Protocol::Tcp.length();
+        let payload_len: u16 = payload.len().try_into().map_err(Error::from)?;
+        let l4_len = payload_len.checked_add(l4_sz).ok_or(Error::InvalidArg)?;
+        let total_len = l4_len.checked_add(l3_sz).ok_or(Error::InvalidArg)?;
+
+        let l4_off = usize::from(l2_sz.wrapping_add(l3_sz));
+        let mut hdr = BytesMut::zeroed(l4_off.wrapping_add(l4_sz.into()));
+        let mut pkt = Packet::new(L3Protocol::Ipv4, L4Protocol::Tcp);
+
+        // fill l2 header
+        let mut ether_hdr = EtherHdrMut::new(&mut hdr)?;
+        ether_hdr.set_src_addr(self.eth_addr.addr_bytes);
+        ether_hdr.set_dst_addr(self.peer_mac.addr_bytes);
+        ether_hdr.set_ether_type(RTE_ETHER_TYPE_IPV4 as u16);
+
+        // fill l3 header
+        let mut ip_hdr = Ipv4HdrMut::new(hdr.get_mut(l2_sz.into()..).ok_or(Error::OutOfRange)?)?;
+        ip_hdr.set_version_ihl(0x45); // version = 4, ihl = 5
+        ip_hdr.set_total_length(total_len);
+        ip_hdr.set_packet_id(IPID.fetch_add(1, Ordering::AcqRel));
+        ip_hdr.set_time_to_live(64);
+        ip_hdr.set_next_proto_id(IP_NEXT_PROTO_TCP);
+        ip_hdr.set_src_addr(*self.local.ip());
+        ip_hdr.set_dst_addr(*self.peer.ip());
+        let mut ol_flags = if offloads & RTE_ETH_TX_OFFLOAD_IPV4_CKSUM == 0 {
+            ip_hdr.update_checksum();
+            0
+        } else {
+            RTE_MBUF_F_TX_IP_CKSUM
+        };
+
+        // fill l4 header
+        let mut tcp_hdr = TcpHdrMut::new(hdr.get_mut(l4_off..).ok_or(Error::OutOfRange)?)?;
+        tcp_hdr.set_src_port(self.local.port());
+        tcp_hdr.set_dst_port(self.peer.port());
+        tcp_hdr.set_sent_seq(tcb.snd_nxt);
+        tcp_hdr.set_recv_ack(if flags & TCP_ACK == 0 { 0 } else { tcb.rcv_nxt });
+        tcp_hdr.set_data_off(((l4_sz / 4) as u8).wrapping_shl(4));
+        tcp_hdr.set_tcp_flags(flags);
+        tcp_hdr.set_rx_win(TCP_WINDOW);
+
+        let pseudo_sum = |len| {
+            ipv4_pseudo_sum(
+                self.local.ip().octets(),
+                self.peer.ip().octets(),
+                IP_NEXT_PROTO_TCP,
+                len,
+            )
+        };
+        let cksum = if tso {
+            // The hardware computes the checksum of each segment seeded with the pseudo header
+            // sum, whose length is left as 0.
+            ol_flags |= RTE_MBUF_F_TX_TCP_SEG | RTE_MBUF_F_TX_TCP_CKSUM;
+            !cksum_fold(pseudo_sum(0))
+        } else if offloads & RTE_ETH_TX_OFFLOAD_TCP_CKSUM != 0 {
+            // The hardware computes the checksum seeded with the pseudo header sum.
+            ol_flags |= RTE_MBUF_F_TX_TCP_CKSUM;
+            !cksum_fold(pseudo_sum(l4_len))
+        } else {
+            let sum = cksum_add(
+                pseudo_sum(l4_len),
+                hdr.get(l4_off..).ok_or(Error::OutOfRange)?,
+            );
+            cksum_fold(cksum_add(sum, payload))
+        };
+        if ol_flags != 0 {
+            pkt.ol_flags = ol_flags | RTE_MBUF_F_TX_IPV4;
+        }
+        if tso {
+            pkt.tso_segsz = TCP_MSS as u16;
+        }
+        // The checksum lies at offset 16 of TCP header.
+        hdr.get_mut(l4_off.wrapping_add(16)..l4_off.wrapping_add(18))
+            .ok_or(Error::OutOfRange)?
+            .copy_from_slice(&cksum.to_be_bytes());
+
+        pkt.append(hdr);
+        if !payload.is_empty() {
+            pkt.append(BytesMut::from(payload));
+        }
+        Ok(pkt)
+    }
+}
+
+impl Debug for TcpStream {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpStream")
+            .field("sockfd", &self.sockfd)
+            .field("local", &self.local)
+            .field("peer", &self.peer)
+            .field("tx", &self.tx)
+            .finish()
+    }
+}
+
+impl AsyncRead for TcpStream {
+    /// Reads some bytes from the stream, `Ok(())` with nothing read is returned once the peer has
+    /// closed its sending side. The ACKs are dropped if the channel to the `TxAgent` is full.
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let len = ready!(self.poll_recv_data(cx, buf.initialize_unfilled()))?;
+        buf.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    /// Writes at most a segment of `buf` into the stream. Like `try_send_to` of `UdpSocket`,
+    /// buffering errors of the `TxAgent` are not reported.
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_send_data(cx, buf).map_err(io::Error::from)
+    }
+
+    /// Segments are handed to the `TxAgent` as they are written, so there's nothing to flush.
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Shuts down the sending side of the stream, a FIN is sent to the peer.
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_fin(cx).map_err(io::Error::from)
+    }
+}
+
+impl Drop for TcpStream {
+    #[inline]
+    fn drop(&mut self) {
+        // Send a FIN if the sending side is still open.
+        if let Ok(mut tcb) = self.tcb.lock() {
+            if matches!(tcb.state, TcpState::Established | TcpState::CloseWait) {
+                match self.segment(TCP_FIN | TCP_ACK, &tcb, &[]) {
+                    Ok(fin) => {
+                        if let Err(e) = self.tx.try_send(fin) {
+                            warn!("Failed to send FIN to {}: {e}", self.peer);
+                        }
+                    }
+                    Err(e) => warn!("Failed to build FIN: {e}"),
+                }
+                tcb.state = TcpState::Closed;
+            }
+        }
+        #[allow(clippy::unwrap_used)] // used in drop
+        socket::free_conn(self.local.port(), SocketAddr::V4(self.peer)).unwrap();
+        #[allow(clippy::unwrap_used)] // used in drop
+        socket::dealloc_mailbox(self.sockfd).unwrap();
+        #[allow(clippy::unwrap_used)] // used in drop
+        socket::free_fd(self.sockfd).unwrap();
+    }
+}
+
+/// Handle IPv4 & TCP packet.
+///
+/// The segment is routed to an established connection by its (ip, port) pairs, or to a
+/// listening socket by its destination port. The IPv4 header is kept in the packet so that
+/// the socket knows both ends of the connection.
+pub(crate) fn handle_ipv4_tcp(mut m: Mbuf) -> Option<(i32, RecvResult)> {
+    let ipv4_hdr_len = L3Protocol::Ipv4.length() as usize;
+    let tcp_hdr_len = L4Protocol::Tcp.length() as usize;
+    if m.pkt_len() < ipv4_hdr_len.saturating_add(tcp_hdr_len) {
+        error!("packet too short, less than IPv4 & TCP header");
+        return None;
+    }
+
+    if m.data_len() == 0 {
+        m = m.pop_mbuf()?;
+    }
+    if m.data_len() < ipv4_hdr_len.saturating_add(tcp_hdr_len) {
+        return None;
+    }
+
+    let ip_hdr = Ipv4Hdr::from_mbuf(&m, 0).ok()?;
+    let total_len = usize::from(ip_hdr.total_length());
+    let dst_ip = IpAddr::from(ip_hdr.dst_addr());
+    let src_ip = IpAddr::from(ip_hdr.src_addr());
+    let tcp_hdr = TcpHdr::from_mbuf(&m, ipv4_hdr_len).ok()?;
+    let dst_port = tcp_hdr.dst_port();
+    let src_port = tcp_hdr.src_port();
+    trace!("from {src_ip:?}:{src_port} to {dst_ip:?}:{dst_port}");
+
+    // Remove the Ethernet padding of short frames.
+    let padding = m.pkt_len().checked_sub(total_len)?;
+    if padding > 0 {
+        m.trim(padding).ok()?;
+    }
+
+    let src_addr = SocketAddr::new(src_ip, src_port);
+    if let Some(sockfd) =
+        conn_2_sockfd(dst_port, src_addr).or_else(|| addr_2_sockfd(dst_port, dst_ip, src_addr))
+    {
+        return Some((sockfd, Ok((src_addr, m))));
+    }
+    warn!("sockfd not found: {dst_ip:?}:{dst_port}");
+    None
+}
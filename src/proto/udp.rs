@@ -1,226 +1,1571 @@
 //! UDP implementation
 
 use crate::{
-    eth_dev::TxSender,
-    mbuf::Mbuf,
+    agent,
+    bpf::BpfProgram,
+    capture::{self, Target},
+    eth_dev::{TxSender, RTE_ETH_TX_OFFLOAD_IPV4_CKSUM, RTE_ETH_TX_OFFLOAD_UDP_CKSUM},
+    headers::{Ipv4Hdr, Ipv6Hdr, UdpHdr},
+    mbuf::{DynField, Mbuf},
+    meter::{PoliceAction, PoliceState, Policer},
     net_dev,
-    packet::Packet,
-    proto::socket::{self, addr_2_sockfd, Mailbox, RecvResult, IPID},
-    proto::{L3Protocol, L4Protocol, Protocol, ETHER_HDR_LEN, IP_NEXT_PROTO_UDP},
+    packet::{set_packet_type, set_tx_offload, Packet, PacketBuilder},
+    proto::arp,
+    proto::socket::{
+        self, addr_2_sockfd, DropPolicy, Mailbox, RecvResult, SendWait, SocketOptions, SocketStats,
+    },
+    proto::{
+        cksum_add_mbuf, cksum_fold, ipv4_pseudo_sum, ipv6_pseudo_sum, L3Protocol, L4Protocol,
+        Protocol, IP_NEXT_PROTO_UDP,
+    },
+    reorder::ReorderBuffer,
+    shaper::{RateLimit, Shaper},
+    timestamp::{self, Timestamp, TxStamps, TxTimestamp},
+    trace::{enter_span, error, instrument, trace, warn},
     Error, Result,
 };
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::BytesMut;
 use dpdk_sys::{
-    rte_ether_addr, rte_ether_hdr, rte_ipv4_cksum, rte_ipv4_hdr, rte_udp_hdr, RTE_ETHER_TYPE_IPV4,
+    rte_ether_addr, RTE_MBUF_F_RX_L4_CKSUM_BAD, RTE_MBUF_F_RX_L4_CKSUM_GOOD,
+    RTE_MBUF_F_RX_L4_CKSUM_MASK,
 };
+use lazy_static::lazy_static;
 use std::{
-    fmt::Debug,
-    net::{IpAddr, SocketAddr, ToSocketAddrs},
-    sync::{atomic::Ordering, Arc, Mutex},
+    fmt::{self, Debug, Display},
+    future::{self, Future},
+    mem,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
 };
+use tokio::time;
+
+/// Whether to validate checksums of received datagrams and drop corrupt ones.
+static RX_CKSUM_VALIDATE: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable the checksum validation of received datagrams.
+pub(crate) fn set_rx_cksum_validate(validate: bool) {
+    RX_CKSUM_VALIDATE.store(validate, Ordering::Relaxed);
+}
+
+/// Whether datagrams to sockets on this host are delivered directly.
+static LOOPBACK: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable the direct delivery of datagrams to sockets on this host.
+pub(crate) fn set_loopback(enable: bool) {
+    LOOPBACK.store(enable, Ordering::Relaxed);
+}
+
+/// The default IPv4 time to live or IPv6 hop limit of the datagrams sent.
+const DEFAULT_TTL: u8 = 64;
+
+/// Distinguishes the names of the reorder buffers of sockets, since a buffer is created before
+/// the previous one of the socket is freed.
+static REORDER_SEQ: AtomicU32 = AtomicU32::new(0);
+
+lazy_static! {
+    /// The dynamic field holding the time to live and type of service of a received datagram,
+    /// or `None` if no space is left in `rte_mbuf`.
+    static ref IP_META: Option<DynField<[u8; 2]>> = DynField::register("async_dpdk_ip_meta").ok();
+}
+
+/// Options of a single datagram sent by `UdpSocket::send_to_with`, overriding the ones of the
+/// socket.
+///
+/// ```
+/// use async_dpdk::udp::SendOptions;
+///
+/// let opts = SendOptions::new().ttl(1).tos(0xb8);
+/// ```
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SendOptions {
+    /// IPv4 time to live or IPv6 hop limit, or the one of the socket if it's `None`.
+    pub ttl: Option<u8>,
+    /// IPv4 type of service or IPv6 traffic class, or the one of the socket if it's `None`.
+    pub tos: Option<u8>,
+    /// Whether the completion timestamp of the datagram is reported.
+    pub tx_timestamp: bool,
+}
+
+impl SendOptions {
+    /// Create default options, following the socket.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the IPv4 time to live or IPv6 hop limit of the datagram.
+    #[inline]
+    #[must_use]
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Set the IPv4 type of service or IPv6 traffic class of the datagram.
+    #[inline]
+    #[must_use]
+    pub fn tos(mut self, tos: u8) -> Self {
+        self.tos = Some(tos);
+        self
+    }
+
+    /// Report when the device takes the datagram, which is taken by
+    /// `UdpSocket::recv_tx_timestamp`. Datagrams delivered on this host directly are not
+    /// reported.
+    #[inline]
+    #[must_use]
+    pub fn tx_timestamp(mut self) -> Self {
+        self.tx_timestamp = true;
+        self
+    }
+}
+
+/// Metadata of a datagram received by `UdpSocket::recv_from_with_meta`, taken from its IP
+/// header, along with when it's received.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecvMeta {
+    /// IPv4 time to live or IPv6 hop limit.
+    pub ttl: u8,
+    /// IPv4 type of service or IPv6 traffic class.
+    pub tos: u8,
+    /// When the datagram is received, if the device is set up with `DevConfig::rx_timestamp`.
+    /// Datagrams delivered on this host directly are not timestamped.
+    pub rx_timestamp: Option<Timestamp>,
+}
+
+impl RecvMeta {
+    /// Get the metadata recorded in `m` by `record`, which is all zeros if the dynamic field
+    /// is not available.
+    fn from_mbuf(m: &Mbuf) -> Self {
+        let [ttl, tos] = IP_META.map_or([0, 0], |field| m.dynfield(field));
+        Self {
+            ttl,
+            tos,
+            rx_timestamp: timestamp::rx_timestamp(m),
+        }
+    }
+
+    /// Record the metadata in `m`.
+    fn record(self, m: &mut Mbuf) {
+        if let Some(field) = *IP_META {
+            m.set_dynfield(field, [self.ttl, self.tos]);
+        }
+        timestamp::set_rx_timestamp(m, self.rx_timestamp);
+    }
+}
+
+/// Where a datagram goes out: the source and destination IP addresses chosen for it, and the
+/// device sending it.
+struct Egress {
+    /// The local address that the datagram is sent from.
+    src_ip: IpAddr,
+    /// The destination address.
+    dst_ip: IpAddr,
+    /// The device of `src_ip` along with its Ether address, or `None` for the device of the
+    /// socket.
+    dev: Option<(TxSender, rte_ether_addr)>,
+}
 
 /// A UDP socket.
+///
+/// A socket can be shared by tasks on any thread, e.g. through an `Arc`, or duplicated with
+/// `try_clone`. Any number of tasks may receive from it at the same time, where each datagram is
+/// taken by one of them, and send through it at the same time.
 #[allow(missing_copy_implementations, clippy::module_name_repetitions)]
 pub struct UdpSocket {
+    /// The state shared by the handles of the socket.
+    inner: Arc<UdpInner>,
+    /// What `poll_send_to` of this handle waits for before trying again.
+    send_wait: Mutex<Option<SendWait>>,
+}
+
+/// The state of a UDP socket, shared by its handles duplicated with `UdpSocket::try_clone`. The
+/// socket is closed once it's dropped with the last handle.
+struct UdpInner {
     /// Socket fd.
     sockfd: i32,
     /// The IP address that this socket is bound to.
-    ip: u32,
+    ip: IpAddr,
     /// The port that this socket is bound to.
     port: u16,
     /// A channel to `TxAgent`.
     tx: TxSender,
     /// A pointer to its mailbox.
-    mailbox: Arc<Mutex<Mailbox>>,
+    mailbox: Arc<Mailbox>,
     /// ether_addr for the device. TODO remove it
     eth_addr: rte_ether_addr,
+    /// The peer that this socket is connected to.
+    peer: Mutex<Option<SocketAddr>>,
+    /// Joined multicast groups, along with the addresses of the devices joining them.
+    groups: Mutex<Vec<(Ipv4Addr, IpAddr)>>,
+    /// Number of datagrams sent.
+    sent: AtomicU64,
+    /// Number of payload bytes sent.
+    sent_bytes: AtomicU64,
+    /// IPv4 type of service or IPv6 traffic class of the datagrams sent.
+    tos: AtomicU8,
+    /// IPv4 time to live or IPv6 hop limit of the datagrams sent.
+    ttl: AtomicU8,
+    /// Rate limit of the datagrams sent.
+    shaper: Shaper,
+    /// Policer of the datagrams sent.
+    policer: Mutex<Option<PoliceState>>,
+    /// Number of datagrams dropped by the policer.
+    policed: AtomicU64,
+    /// Completion timestamps of the datagrams sent.
+    tx_stamps: Arc<TxStamps>,
+}
+
+impl UdpInner {
+    /// What a capture of the socket mirrors.
+    fn capture_target(&self) -> Target {
+        Target::Socket(self.sockfd, SocketAddr::new(self.ip, self.port))
+    }
 }
 
-#[allow(unsafe_code)]
-unsafe impl Send for UdpSocket {}
+impl UdpSocket {
+    /// Creates a UDP socket from the given address.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Invalid socket address.
+    /// - Too much bound sockets.
+    #[inline]
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Self::bind_with(addr, SocketOptions::default())
+    }
+
+    /// Creates a UDP socket from the given address with the given options, e.g. sharing the port
+    /// with other sockets so that each of them can be served by a task.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Invalid socket address.
+    /// - The port is bound by a socket that doesn't share it.
+    /// - Too much bound sockets.
+    #[inline]
+    pub fn bind_with<A: ToSocketAddrs>(addr: A, opts: SocketOptions) -> Result<Self> {
+        #[allow(clippy::map_err_ignore)]
+        while let Some(addr) = addr
+            .to_socket_addrs()
+            .map_err(|_| Error::InvalidArg)?
+            .next()
+        {
+            if let Ok((sockfd, port)) = socket::bind_fd_with(addr, opts) {
+                if let Ok((tx, eth_addr)) = net_dev::find_dev_by_ip(addr.ip()) {
+                    let mailbox = socket::alloc_mailbox(sockfd)?;
+                    let inner = UdpInner {
+                        sockfd,
+                        ip: addr.ip(),
+                        port,
+                        tx,
+                        mailbox,
+                        eth_addr,
+                        peer: Mutex::new(None),
+                        groups: Mutex::new(Vec::new()),
+                        sent: AtomicU64::new(0),
+                        sent_bytes: AtomicU64::new(0),
+                        tos: AtomicU8::new(0),
+                        ttl: AtomicU8::new(DEFAULT_TTL),
+                        shaper: Shaper::default(),
+                        policer: Mutex::new(None),
+                        policed: AtomicU64::new(0),
+                        tx_stamps: TxStamps::new()?,
+                    };
+                    return Ok(UdpSocket {
+                        inner: Arc::new(inner),
+                        send_wait: Mutex::new(None),
+                    });
+                }
+                socket::free_fd(sockfd)?;
+                return Err(Error::InvalidArg);
+            }
+        }
+        Err(Error::NoBuf)
+    }
+
+    /// Creates a new handle of the socket, which shares everything with this one, e.g. the
+    /// received datagrams, the peer and the settings, except the state of `poll_send_to`. The
+    /// socket is closed once all of its handles are dropped.
+    ///
+    /// A handle for each task polling the socket with `poll_send_to` keeps them from taking the
+    /// wakeups of each other.
+    ///
+    /// # Errors
+    ///
+    /// It never fails for now, but returns a `Result` like `std::net::UdpSocket::try_clone`.
+    #[inline]
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            inner: Arc::clone(&self.inner),
+            send_wait: Mutex::new(None),
+        })
+    }
+
+    /// Connects the socket to a remote address, so that `send` and `recv` can be used. Datagrams
+    /// from other addresses are dropped afterwards.
+    ///
+    /// The Ether address of the peer is resolved on connecting.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Invalid socket address.
+    /// - `Error::TimedOut`: the Ether address of the peer cannot be resolved.
+    #[inline]
+    pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<()> {
+        #[allow(clippy::map_err_ignore)]
+        let addr = addr
+            .to_socket_addrs()
+            .map_err(|_| Error::InvalidArg)?
+            .next()
+            .ok_or(Error::InvalidArg)?;
+        let egress = self.egress(addr)?;
+        if let (IpAddr::V4(src), IpAddr::V4(dst)) = (egress.src_ip, egress.dst_ip) {
+            let (tx, eth_addr) = self.dev(&egress);
+            let _mac = arp::resolve(dst, src, eth_addr, tx).await?;
+        }
+        socket::connect_port(self.inner.port, self.inner.sockfd, Some(addr))?;
+        *self.inner.peer.lock().map_err(Error::from)? = Some(addr);
+        Ok(())
+    }
+
+    /// Receives a single datagram message on the socket from the connected peer. On success,
+    /// returns the number of bytes read.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::NotConnected`: the socket is not connected.
+    /// - Recv agent not started.
+    #[inline]
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        if self.inner.peer.lock().map_err(Error::from)?.is_none() {
+            return Err(Error::NotConnected);
+        }
+        self.recv_from(buf).await.map(|(len, _)| len)
+    }
+
+    /// Sends data on the socket to the connected peer. On success, returns the number of bytes
+    /// written.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::NotConnected`: the socket is not connected.
+    /// - Data to long.
+    /// - Send agent not started.
+    /// - `Error::NoBuf`: the tx buffer is full, try again later.
+    /// - `Error::Busy`: the device has taken no packet for a while, e.g. its link is down.
+    /// - `Error::MempoolExhausted`: no `Mbuf` is left to hold the data.
+    #[inline]
+    pub async fn send(&self, buf: &[u8]) -> Result<usize> {
+        let peer = self
+            .peer
+            .lock()
+            .map_err(Error::from)?
+            .ok_or(Error::NotConnected)?;
+        self.send_to(buf, peer).await
+    }
+
+    /// Receives a single datagram message on the socket. On success, returns
+    /// the number of bytes read and the origin.
+    ///
+    /// This method is cancel safe: if it's dropped before returning, e.g. in a branch of
+    /// `tokio::select!` not taken, no datagram is taken out of the socket.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    #[inline]
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        let (data, addr) = self.recv_mbuf().await?;
+        Ok((copy_to_buf(&data, buf), addr))
+    }
+
+    /// Receives a single datagram message on the socket along with the metadata of its IP
+    /// header. On success, returns the number of bytes read, the origin and the metadata.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    #[inline]
+    pub async fn recv_from_with_meta(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<(usize, SocketAddr, RecvMeta)> {
+        let (data, addr) = self.recv_mbuf().await?;
+        Ok((copy_to_buf(&data, buf), addr, RecvMeta::from_mbuf(&data)))
+    }
+
+    /// Receives a single datagram message on the socket, waiting for at most `timeout`. On
+    /// success, returns the number of bytes read and the origin.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    /// - `Error::TimedOut`: no datagram is received in `timeout`.
+    #[inline]
+    pub async fn recv_from_timeout(
+        &self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<(usize, SocketAddr)> {
+        let (addr, data) = instrument!(
+            self.inner.mailbox.recv_timeout(timeout),
+            "udp_recv",
+            sockfd = self.inner.sockfd
+        )
+        .await??;
+        Ok((copy_to_buf(&data, buf), addr))
+    }
+
+    /// Receives a single datagram message on the socket if there's one, without waiting. On
+    /// success, returns the number of bytes read and the origin.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::TempUnavail`: no datagram is received yet.
+    #[inline]
+    pub fn try_recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        enter_span!("udp_try_recv", sockfd = self.inner.sockfd);
+        let (addr, data) = self.inner.mailbox.try_recv()?.ok_or(Error::TempUnavail)??;
+        Ok((copy_to_buf(&data, buf), addr))
+    }
+
+    /// Waits until a datagram is received on the socket, without taking it out, so that a
+    /// following `try_recv_from` succeeds unless another task takes the datagram first.
+    ///
+    /// It's cancel safe like `recv_from`. `select_readable` waits for any of several sockets.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    #[inline]
+    pub async fn readable(&self) -> Result<()> {
+        instrument!(
+            self.inner.mailbox.ready(),
+            "udp_readable",
+            sockfd = self.inner.sockfd
+        )
+        .await
+    }
+
+    /// Receives a single datagram message on the socket without copying. On success, returns
+    /// the `Mbuf` holding the datagram and the origin.
+    ///
+    /// The returned `Mbuf` starts with the UDP payload, and its segments can be iterated with
+    /// `Mbuf::iter`. The `Mbuf` is given back to its mempool once dropped. It's cancel safe like
+    /// `recv_from`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    #[inline]
+    pub async fn recv_mbuf(&self) -> Result<(Mbuf, SocketAddr)> {
+        let (addr, data) = instrument!(
+            self.inner.mailbox.recv(),
+            "udp_recv",
+            sockfd = self.inner.sockfd
+        )
+        .await??;
+        Ok((data, addr))
+    }
+
+    /// Receives a batch of datagram messages on the socket, waiting for at least one. On
+    /// success, returns the number of bytes read and the origin of each datagram received, the
+    /// i-th of which is read into `bufs[i]`.
+    ///
+    /// At most `bufs.len()` datagrams are received. It's cancel safe like `recv_from`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `bufs` is empty.
+    /// - Recv agent not started.
+    #[inline]
+    pub async fn recv_mmsg(&self, bufs: &mut [&mut [u8]]) -> Result<Vec<(usize, SocketAddr)>> {
+        let batch = self.recv_mbuf_batch(bufs.len()).await?;
+        Ok(batch
+            .into_iter()
+            .zip(bufs.iter_mut())
+            .map(|((data, addr), buf)| (copy_to_buf(&data, buf), addr))
+            .collect())
+    }
+
+    /// Receives a batch of at most `max` datagram messages on the socket without copying,
+    /// waiting for at least one. On success, returns the `Mbuf`s holding the datagrams and their
+    /// origins.
+    ///
+    /// A batch ends before the first failed receipt, which is returned by the next call.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `max` is 0.
+    /// - Recv agent not started.
+    #[inline]
+    pub async fn recv_mbuf_batch(&self, max: usize) -> Result<Vec<(Mbuf, SocketAddr)>> {
+        let mut batch = self.inner.mailbox.recv_batch(max).await?;
+        let end = batch.iter().position(Result::is_err).unwrap_or(batch.len());
+        let mut rest = batch.split_off(end).into_iter();
+        if end == 0 {
+            if let Some(Err(e)) = rest.next() {
+                self.inner.mailbox.put_back(rest.collect())?;
+                return Err(e);
+            }
+        }
+        self.inner.mailbox.put_back(rest.collect())?;
+        Ok(batch
+            .into_iter()
+            .flatten()
+            .map(|(addr, data)| (data, addr))
+            .collect())
+    }
+
+    /// Sends a batch of datagram messages on the socket, each to its address, in one request to
+    /// the `TxAgent`. On success, returns the number of datagrams sent.
+    ///
+    /// Datagrams are sent in order, and the ones after the first failing one are dropped. For a
+    /// socket bound to an unspecified address, consecutive datagrams from the same local address
+    /// are sent in one request.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons are the same as `send_to`. It fails only if no datagram is sent.
+    #[inline]
+    pub async fn send_mmsg(&self, msgs: &[(&[u8], SocketAddr)]) -> Result<usize> {
+        let mut pkts = Vec::with_capacity(msgs.len());
+        let mut lens = Vec::with_capacity(msgs.len());
+        let mut run: Option<Egress> = None;
+        let mut n_sent = 0_usize;
+        for &(buf, addr) in msgs {
+            if let Some(local) = self.local_dst(addr)? {
+                self.send_local(local, buf, SendOptions::default())?;
+                n_sent = n_sent.wrapping_add(1);
+                continue;
+            }
+            let Some(opts) = self.police(buf.len(), SendOptions::default())? else {
+                n_sent = n_sent.wrapping_add(1);
+                continue;
+            };
+            let (builder, egress) = self.builder(addr, opts).await?;
+            let pkt = builder.build(buf)?;
+            self.inner.shaper.acquire(pkt.len()).await?;
+            if let Some(prev) = run.as_ref().filter(|prev| prev.src_ip != egress.src_ip) {
+                let n_pkts = pkts.len();
+                match self.send_run(prev, mem::take(&mut pkts), &lens).await {
+                    Ok(n) if n == n_pkts => n_sent = n_sent.wrapping_add(n),
+                    Ok(n) => return Ok(n_sent.wrapping_add(n)),
+                    Err(e) if n_sent == 0 => return Err(e),
+                    Err(_) => return Ok(n_sent),
+                }
+                lens.clear();
+                run = None;
+            }
+            if run.is_none() {
+                run = Some(egress);
+            }
+            pkts.push(pkt);
+            lens.push(buf.len());
+        }
+        let Some(egress) = run else {
+            return Ok(n_sent);
+        };
+        match self.send_run(&egress, pkts, &lens).await {
+            Ok(n) => Ok(n_sent.wrapping_add(n)),
+            Err(e) if n_sent == 0 => Err(e),
+            Err(_) => Ok(n_sent),
+        }
+    }
+
+    /// Send a run of datagrams going out through `egress` in one request, whose payloads are of
+    /// `lens`. Returns the number of datagrams sent.
+    async fn send_run(&self, egress: &Egress, pkts: Vec<Packet>, lens: &[usize]) -> Result<usize> {
+        let (tx, _) = self.dev(egress);
+        let pkt_lens: Vec<_> = pkts.iter().map(Packet::len).collect();
+        let res = tx.send_batch(pkts).await;
+        // The tokens of the datagrams not sent are given back.
+        let n = res.as_ref().copied().unwrap_or(0);
+        for &pkt_len in pkt_lens.get(n..).unwrap_or_default() {
+            self.inner.shaper.give_back(pkt_len)?;
+        }
+        let n = res?;
+        self.count_sent(n, lens.iter().take(n).sum());
+        Ok(n)
+    }
+
+    /// Give back the tokens of a frame of `pkt_len` bytes if `res` of sending it is an error.
+    fn give_back_on_err<T>(&self, res: Result<T>, pkt_len: usize) -> Result<T> {
+        if res.is_err() {
+            self.inner.shaper.give_back(pkt_len)?;
+        }
+        res
+    }
+
+    /// Sends data on the socket to the given address. On success, returns the
+    /// number of bytes written.
+    ///
+    /// With a rate limit set by `set_rate_limit`, it waits until the datagram conforms to it.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Invalid socket address.
+    /// - Data to long.
+    /// - Send agent not started.
+    /// - `Error::NoBuf`: the tx buffer is full, try again later.
+    /// - `Error::Busy`: the device has taken no packet for a while, e.g. its link is down.
+    /// - `Error::MempoolExhausted`: no `Mbuf` is left to hold the data.
+    /// - `Error::TimedOut`: the Ether address of the destination cannot be resolved.
+    #[inline]
+    pub async fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> Result<usize> {
+        self.send_to_with(buf, addr, SendOptions::default()).await
+    }
+
+    /// Sends data on the socket to the given address with the options of this datagram, e.g. a
+    /// time to live different from the one of the socket. On success, returns the number of
+    /// bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons are the same as `send_to`, and `Error::InvalidArg` if the time to live
+    /// is 0, or `Error::NoMem` if the completion timestamp is requested but no space is left in
+    /// `rte_mbuf` for the request.
+    #[inline]
+    pub async fn send_to_with<A: ToSocketAddrs>(
+        &self,
+        buf: &[u8],
+        addr: A,
+        opts: SendOptions,
+    ) -> Result<usize> {
+        #[allow(clippy::map_err_ignore)]
+        let addr = addr
+            .to_socket_addrs()
+            .map_err(|_| Error::InvalidArg)?
+            .next()
+            .ok_or(Error::InvalidArg)?;
+        // Checked before datagrams to local sockets are delivered without building packets.
+        if opts.ttl == Some(0) {
+            return Err(Error::InvalidArg);
+        }
+        if let Some(local) = self.local_dst(addr)? {
+            self.send_local(local, buf, opts)?;
+            return Ok(buf.len());
+        }
+        let Some(opts) = self.police(buf.len(), opts)? else {
+            return Ok(buf.len());
+        };
+        let (builder, egress) = self.builder(addr, opts).await?;
+        let pkt = builder.build(buf)?;
+        let pkt_len = pkt.len();
+        self.inner.shaper.acquire(pkt_len).await?;
+        let (tx, _) = self.dev(&egress);
+        let mut m = self.give_back_on_err(tx.packet_mbuf(pkt), pkt_len)?;
+        if opts.tx_timestamp {
+            let _id = self.give_back_on_err(self.inner.tx_stamps.request(&mut m), pkt_len)?;
+        }
+        let res = instrument!(
+            tx.send_mbuf(m),
+            "udp_send",
+            sockfd = self.inner.sockfd,
+            pkt_len
+        )
+        .await;
+        self.give_back_on_err(res, pkt_len)?;
+        self.count_sent(1, buf.len());
+        Ok(buf.len())
+    }
+
+    /// Sends data on the socket to the given address, waiting for at most `timeout`. On
+    /// success, returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons are the same as `send_to`, and `Error::TimedOut` if the datagram is not
+    /// buffered in `timeout`.
+    #[inline]
+    pub async fn send_to_timeout<A: ToSocketAddrs>(
+        &self,
+        buf: &[u8],
+        addr: A,
+        timeout: Duration,
+    ) -> Result<usize> {
+        time::timeout(timeout, self.send_to(buf, addr)).await?
+    }
+
+    /// Receives the completion timestamp of a datagram sent with `SendOptions::tx_timestamp`,
+    /// waiting for one if none is reported yet. Timestamps are reported in the order that the
+    /// datagrams are taken by the devices, and the oldest are dropped if more than 1024 of them
+    /// are not received.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    #[inline]
+    pub async fn recv_tx_timestamp(&self) -> Result<TxTimestamp> {
+        self.inner.tx_stamps.recv().await
+    }
+
+    /// Sends data on the socket to the given address without waiting. On success, returns the
+    /// number of bytes written.
+    ///
+    /// The datagram is handed to the `TxAgent` and buffering errors are not reported.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Invalid socket address.
+    /// - Data to long.
+    /// - Send agent not started.
+    /// - `Error::TempUnavail`: the channel to the `TxAgent` is full, or the Ether address of the
+    ///   destination is not resolved yet, in which case `connect` or `send_to` resolves it.
+    /// - `Error::RateLimited`: the datagram exceeds the rate limit of the socket.
+    #[inline]
+    pub fn try_send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> Result<usize> {
+        #[allow(clippy::map_err_ignore)]
+        let addr = addr
+            .to_socket_addrs()
+            .map_err(|_| Error::InvalidArg)?
+            .next()
+            .ok_or(Error::InvalidArg)?;
+        if let Some(local) = self.local_dst(addr)? {
+            self.send_local(local, buf, SendOptions::default())?;
+            return Ok(buf.len());
+        }
+        let Some(opts) = self.police(buf.len(), SendOptions::default())? else {
+            return Ok(buf.len());
+        };
+        let (builder, egress) = self.try_builder(addr, opts)?;
+        let pkt = builder.build(buf)?;
+        enter_span!(
+            "udp_try_send",
+            sockfd = self.inner.sockfd,
+            pkt_len = pkt.len()
+        );
+        let pkt_len = pkt.len();
+        self.inner.shaper.try_acquire(pkt_len)?;
+        self.give_back_on_err(self.dev(&egress).0.try_send(pkt), pkt_len)?;
+        self.count_sent(1, buf.len());
+        Ok(buf.len())
+    }
+
+    /// Attempts to receive a single datagram message on the socket. On success, returns the
+    /// number of bytes read and the origin.
+    ///
+    /// If no datagram is received yet, `Poll::Pending` is returned and the waker of `cx` is woken
+    /// up once one arrives, along with the wakers of other tasks polling the socket meanwhile.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Recv agent not started.
+    #[inline]
+    pub fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<(usize, SocketAddr)>> {
+        let (addr, data) = ready!(self.inner.mailbox.poll_recv(cx))??;
+        Poll::Ready(Ok((copy_to_buf(&data, buf), addr)))
+    }
+
+    /// Attempts to send data on the socket to the given address. On success, returns the number
+    /// of bytes written.
+    ///
+    /// If the datagram can't be handed to the `TxAgent` yet, i.e. the Ether address of the
+    /// destination is being resolved, the rate limit is exceeded or the channel to the `TxAgent`
+    /// is full, `Poll::Pending` is returned and the waker of `cx` is woken up once it's worth
+    /// trying again. Only the waker passed to the latest call on this handle is woken up, so tasks
+    /// polling at the same time should do it on their own handles from `try_clone`. Like
+    /// `try_send_to`, buffering errors of the `TxAgent` are not reported.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Invalid socket address.
+    /// - Data to long.
+    /// - Send agent not started.
+    /// - `Error::MempoolExhausted`: no `Mbuf` is left to hold the data.
+    /// - `Error::TimedOut`: the Ether address of the destination cannot be resolved.
+    #[inline]
+    pub fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<Result<usize>> {
+        if let Some(local) = self.local_dst(target)? {
+            self.send_local(local, buf, SendOptions::default())?;
+            return Poll::Ready(Ok(buf.len()));
+        }
+        let mut wait = self.send_wait.lock().map_err(Error::from)?;
+        loop {
+            if let Some(fut) = wait.as_mut() {
+                let res = ready!(fut.as_mut().poll(cx));
+                *wait = None;
+                res?;
+            }
+            match self.send_or_wait(buf, target)? {
+                Some(fut) => *wait = Some(fut),
+                None => return Poll::Ready(Ok(buf.len())),
+            }
+        }
+    }
+
+    /// Allocates an `Mbuf` from the mempool of the device, to be filled with the payload and
+    /// sent by `send_mbuf_to`.
+    ///
+    /// The headroom of the `Mbuf` is large enough to hold the protocol headers.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Mempool exhausted.
+    #[inline]
+    pub fn alloc_mbuf(&self) -> Result<Mbuf> {
+        self.inner.tx.alloc_mbuf()
+    }
+
+    /// Sends the payload held by an `Mbuf` to the given address without copying it. On success,
+    /// returns the number of bytes written.
+    ///
+    /// The protocol headers are built in the headroom of the `Mbuf`, e.g. one returned by
+    /// `alloc_mbuf`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Invalid socket address.
+    /// - Data to long.
+    /// - Not enough headroom for the protocol headers.
+    /// - Send agent not started.
+    /// - `Error::NoBuf`: the tx buffer is full, try again later.
+    /// - `Error::Busy`: the device has taken no packet for a while, e.g. its link is down.
+    /// - `Error::MempoolExhausted`: no `Mbuf` is left to hold the data.
+    /// - `Error::TimedOut`: the Ether address of the destination cannot be resolved.
+    #[inline]
+    pub async fn send_mbuf_to<A: ToSocketAddrs>(&self, mut m: Mbuf, addr: A) -> Result<usize> {
+        #[allow(clippy::map_err_ignore)]
+        let addr = addr
+            .to_socket_addrs()
+            .map_err(|_| Error::InvalidArg)?
+            .next()
+            .ok_or(Error::InvalidArg)?;
+        let len = m.pkt_len();
+        if let Some((sockfd, src_addr)) = self.local_dst(addr)? {
+            self.local_meta(SendOptions::default()).record(&mut m);
+            socket::put_local(sockfd, src_addr, m)?;
+            self.count_sent(1, len);
+            return Ok(len);
+        }
+        let Some(opts) = self.police(len, SendOptions::default())? else {
+            return Ok(len);
+        };
+        let (builder, egress) = self.builder(addr, opts).await?;
+        let (tx, _) = self.dev(&egress);
+        let payload_sum = (!udp_cksum_offload(tx)).then(|| cksum_add_mbuf(0, &m));
+        let (hdr, l3_proto, ol_flags) = builder.headers(len, payload_sum)?;
+        m.prepend(hdr.len())?.copy_from_slice(&hdr);
+        set_packet_type(&m, l3_proto, L4Protocol::Udp);
+        set_tx_offload(&m, ol_flags);
+        let pkt_len = m.pkt_len();
+        self.inner.shaper.acquire(pkt_len).await?;
+        let res = instrument!(
+            tx.send_mbuf(m),
+            "udp_send",
+            sockfd = self.inner.sockfd,
+            pkt_len
+        )
+        .await;
+        self.give_back_on_err(res, pkt_len)?;
+        self.count_sent(1, len);
+        Ok(len)
+    }
+
+    /// Waits until all datagrams sent before are put onto the wire.
+    ///
+    /// The `TxAgent` keeps retrying for a while if the device is busy, datagrams still unsent
+    /// after that are kept in its buffer.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Send agent not started.
+    /// - `Error::TimedOut`: some datagrams are still unsent after retrying.
+    #[inline]
+    pub async fn flush(&self) -> Result<()> {
+        if self.inner.tx.flush().await? > 0 {
+            return Err(Error::TimedOut);
+        }
+        Ok(())
+    }
+
+    /// Flushes the datagrams sent before and closes the socket, or only drops this handle if the
+    /// socket has other handles from `try_clone`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons are the same as `flush`. The handle is dropped anyway.
+    #[inline]
+    pub async fn shutdown(self) -> Result<()> {
+        self.flush().await
+    }
+
+    /// Joins the multicast group `multiaddr` on the device with address `interface`, so that
+    /// datagrams sent to the group are received by this socket.
+    ///
+    /// If `interface` is unspecified, the device that the socket is bound to is used, or the
+    /// first running device if the socket is bound to the unspecified address. The socket should
+    /// be bound to the unspecified address to receive them.
+    ///
+    /// If several sockets sharing a port join the same group, each datagram is still received by
+    /// only one of them.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `multiaddr` is not a multicast address.
+    /// - `Error::NoDev`: no device is bound to `interface`.
+    /// - `Error::Exists`: the group is already joined by this socket.
+    /// - Unable to update the multicast filter of the device.
+    #[inline]
+    pub fn join_multicast_v4(&self, multiaddr: &Ipv4Addr, interface: &Ipv4Addr) -> Result<()> {
+        if !multiaddr.is_multicast() {
+            return Err(Error::InvalidArg);
+        }
+        let iface = self.multicast_iface(*interface)?;
+        let mut groups = self.inner.groups.lock().map_err(Error::from)?;
+        if groups.iter().any(|&(group, _)| group == *multiaddr) {
+            return Err(Error::Exists);
+        }
+        net_dev::join_multicast(&iface, *multiaddr)?;
+        if let Err(e) =
+            socket::join_group(self.inner.port, self.inner.sockfd, IpAddr::V4(*multiaddr))
+        {
+            let _res = net_dev::leave_multicast(&iface, *multiaddr);
+            return Err(e);
+        }
+        groups.push((*multiaddr, iface));
+        Ok(())
+    }
+
+    /// Leaves the multicast group `multiaddr` joined by `join_multicast_v4`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::NotExist`: the group is not joined by this socket.
+    /// - Unable to update the multicast filter of the device.
+    #[inline]
+    pub fn leave_multicast_v4(&self, multiaddr: &Ipv4Addr, _interface: &Ipv4Addr) -> Result<()> {
+        let mut groups = self.inner.groups.lock().map_err(Error::from)?;
+        let idx = groups
+            .iter()
+            .position(|&(group, _)| group == *multiaddr)
+            .ok_or(Error::NotExist)?;
+        let (group, iface) = groups.swap_remove(idx);
+        socket::leave_group(self.inner.port, self.inner.sockfd, IpAddr::V4(group))?;
+        net_dev::leave_multicast(&iface, group)
+    }
 
-#[allow(unsafe_code)]
-unsafe impl Sync for UdpSocket {}
+    /// Returns the socket address that this socket is bound to, with the port chosen if it's
+    /// bound to port 0.
+    #[inline]
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.inner.ip, self.inner.port)
+    }
 
-impl UdpSocket {
-    /// Creates a UDP socket from the given address.
+    /// Returns the socket address of the peer that this socket is connected to.
     ///
     /// # Errors
     ///
     /// Possible reasons:
     ///
-    /// - Invalid socket address.
-    /// - Too much bound sockets.
+    /// - `Error::NotConnected`: the socket is not connected.
+    /// - Lock poisoned.
     #[inline]
-    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.inner
+            .peer
+            .lock()
+            .map_err(Error::from)?
+            .ok_or(Error::NotConnected)
+    }
+
+    /// Returns the local address that datagrams to `addr` are sent from.
+    ///
+    /// It's the bound address, unless the socket is bound to an unspecified address, in which
+    /// case the address is chosen for each datagram by the addresses and the routes of the
+    /// devices, and the datagram goes out through the device of the chosen address.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Invalid socket address, or of a family different from the bound address.
+    /// - `Error::NoDev`: no running device has an address of the family of `addr`.
+    #[inline]
+    pub fn local_addr_for<A: ToSocketAddrs>(&self, addr: A) -> Result<SocketAddr> {
         #[allow(clippy::map_err_ignore)]
-        while let Some(addr) = addr
+        let addr = addr
             .to_socket_addrs()
             .map_err(|_| Error::InvalidArg)?
             .next()
-        {
-            if let Ok((sockfd, port)) = socket::bind_fd(addr) {
-                if let Ok((tx, eth_addr)) = net_dev::find_dev_by_ip(addr.ip()) {
-                    let mailbox = socket::alloc_mailbox(sockfd)?;
-                    let ip = match addr.ip() {
-                        IpAddr::V4(addr) => Ok(u32::from_ne_bytes(addr.octets())),
-                        // TODO: support ipv6
-                        IpAddr::V6(_) => Err(Error::InvalidArg),
-                    }?;
-                    return Ok(UdpSocket {
-                        sockfd,
-                        ip,
-                        port,
-                        tx,
-                        mailbox,
-                        eth_addr,
-                    });
-                }
-                socket::free_fd(sockfd)?;
-                return Err(Error::InvalidArg);
-            }
+            .ok_or(Error::InvalidArg)?;
+        let (src_ip, _) = self.route(addr)?;
+        Ok(SocketAddr::new(src_ip, self.inner.port))
+    }
+
+    /// The address of the device to join a multicast group on.
+    fn multicast_iface(&self, interface: Ipv4Addr) -> Result<IpAddr> {
+        if !interface.is_unspecified() {
+            return Ok(IpAddr::V4(interface));
         }
-        Err(Error::NoBuf)
+        if self.inner.ip.is_unspecified() {
+            return net_dev::local_ip_for(self.inner.ip);
+        }
+        Ok(self.inner.ip)
     }
 
-    /// Receives a single datagram message on the socket. On success, returns
-    /// the number of bytes read and the origin.
+    /// Sets the IPv4 type of service or IPv6 traffic class of the datagrams sent by this socket.
+    ///
+    /// If the device is configured with `DevConfig::tx_weights`, the precedence bits, i.e. the
+    /// top 3 bits, also decide the tx class of the datagrams, e.g. `0xb8` (DSCP EF) is queued
+    /// ahead of the default `0`.
+    #[inline]
+    pub fn set_tos(&self, tos: u8) {
+        self.inner.tos.store(tos, Ordering::Relaxed);
+    }
+
+    /// The IPv4 type of service or IPv6 traffic class of the datagrams sent by this socket.
+    #[inline]
+    #[must_use]
+    pub fn tos(&self) -> u8 {
+        self.inner.tos.load(Ordering::Relaxed)
+    }
+
+    /// Sets the IPv4 time to live or IPv6 hop limit of the datagrams sent by this socket, which
+    /// is 64 by default.
     ///
     /// # Errors
     ///
     /// Possible reasons:
     ///
-    /// - Recv agent not started.
+    /// - `Error::InvalidArg`: `ttl` is 0.
     #[inline]
-    #[allow(clippy::indexing_slicing)]
-    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
-        let rx = self.mailbox.lock().map_err(Error::from)?.recv()?;
-        let (addr, data) = rx.await.map_err(Error::from)??;
-        let mut len: usize = 0;
-        let mut buf = buf;
-        for frag in data.frags {
-            let mut frag = frag.freeze();
-            let sz = frag.remaining().min(buf.len());
-            frag.copy_to_slice(&mut buf[..sz]); // TODO zero-copy
-            buf = &mut buf[sz..];
-            len = len.wrapping_add(sz);
-            if buf.is_empty() {
-                break;
-            }
+    pub fn set_ttl(&self, ttl: u8) -> Result<()> {
+        if ttl == 0 {
+            return Err(Error::InvalidArg);
         }
-        Ok((len, addr))
+        self.inner.ttl.store(ttl, Ordering::Relaxed);
+        Ok(())
     }
 
-    /// Sends data on the socket to the given address. On success, returns the
-    /// number of bytes written.
+    /// The IPv4 time to live or IPv6 hop limit of the datagrams sent by this socket.
+    #[inline]
+    #[must_use]
+    pub fn ttl(&self) -> u8 {
+        self.inner.ttl.load(Ordering::Relaxed)
+    }
+
+    /// Sets the rate limit of the datagrams sent by this socket to other hosts, or removes it with
+    /// `None`, which is the default.
+    ///
+    /// Sending waits until a datagram conforms to the limit, while `try_send_to` fails with
+    /// `Error::RateLimited` instead. Datagrams delivered to local sockets are not limited.
     ///
     /// # Errors
     ///
     /// Possible reasons:
     ///
-    /// - Invalid socket address.
-    /// - Data to long.
-    /// - Send agent not started.
+    /// - `Error::InvalidArg`: the rate is 0.
+    /// - Lock poisoned.
     #[inline]
-    #[allow(unsafe_code, clippy::cast_possible_truncation)]
-    pub async fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> Result<usize> {
-        #[allow(clippy::map_err_ignore)]
-        let addr = addr
-            .to_socket_addrs()
-            .map_err(|_| Error::InvalidArg)?
-            .next()
-            .ok_or(Error::InvalidArg)?;
+    pub fn set_rate_limit(&self, limit: Option<RateLimit>) -> Result<()> {
+        self.inner.shaper.set(limit)
+    }
 
-        let buf_len = buf.len();
-        let l2_sz = ETHER_HDR_LEN;
-        let l3_sz = L3Protocol::Ipv4.length();
-        let l4_sz = L4Protocol::Udp.length();
-        let payload_len: u16 = buf.len().try_into().map_err(Error::from)?;
-        let total_len = payload_len
-            .checked_add(l3_sz)
-            .ok_or(Error::InvalidArg)?
-            .checked_add(l4_sz)
-            .ok_or(Error::InvalidArg)?;
+    /// The rate limit of the datagrams sent by this socket, if any.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    #[inline]
+    pub fn rate_limit(&self) -> Result<Option<RateLimit>> {
+        self.inner.shaper.limit()
+    }
 
-        let mut hdr = BytesMut::with_capacity(l2_sz.wrapping_add(l3_sz).wrapping_add(l4_sz) as _);
-        let mut pkt = Packet::new(L3Protocol::Ipv4, L4Protocol::Udp);
+    /// Polices the datagrams sent by the socket by a meter over their payload bytes, or stops
+    /// policing with `None`.
+    ///
+    /// Dropped datagrams are counted in `SocketStats::policed` and reported as sent, as if they
+    /// were lost on the way. Demoted ones are sent with type of service 0, i.e. in the last
+    /// traffic class of the tx queue. Datagrams delivered to local sockets are not policed.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: the profile of `policer` is invalid.
+    #[inline]
+    pub fn set_policer(&self, policer: Option<Policer>) -> Result<()> {
+        let state = policer.map(PoliceState::new).transpose()?;
+        *self.inner.policer.lock().map_err(Error::from)? = state;
+        Ok(())
+    }
 
-        // make this function `Send`.
-        {
-            // fill l2 header
-            // SAFETY: hdr size = l2_sz + l3_sz + l4_sz
-            #[allow(clippy::cast_ptr_alignment)]
-            let ether_hdr =
-                unsafe { &mut *(hdr.chunk_mut()[..].as_mut_ptr().cast::<rte_ether_hdr>()) };
-            ether_hdr.src_addr = self.eth_addr;
-            // TODO send to real mac addr. implement ARP in the future!
-            ether_hdr.dst_addr.addr_bytes.copy_from_slice(&[0xff; 6]);
-            ether_hdr.ether_type = (RTE_ETHER_TYPE_IPV4 as u16).to_be();
-
-            // SAFETY: hdr size = l2_sz + l3_sz + l4_sz
-            unsafe {
-                hdr.advance_mut(l2_sz as _);
+    /// The policer of the datagrams sent by the socket, if any.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    #[inline]
+    pub fn policer(&self) -> Result<Option<Policer>> {
+        Ok(self
+            .policer
+            .lock()
+            .map_err(Error::from)?
+            .as_ref()
+            .map(PoliceState::policer))
+    }
+
+    /// Sets the max number of datagrams held by the receive buffer of the socket, and what to do
+    /// when it's full. It defaults to `DEFAULT_RECV_BUFFER_SIZE` and `DropPolicy::DropNewest`.
+    ///
+    /// Datagrams beyond the new size are dropped from the front unless under
+    /// `DropPolicy::Backpressure`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `size` is 0.
+    #[inline]
+    pub fn set_recv_buffer_size(&self, size: usize, policy: DropPolicy) -> Result<()> {
+        self.inner.mailbox.set_capacity(size, policy)
+    }
+
+    /// The max number of datagrams held by the receive buffer of the socket, and what to do
+    /// when it's full.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    #[inline]
+    pub fn recv_buffer_size(&self) -> Result<(usize, DropPolicy)> {
+        self.inner.mailbox.capacity()
+    }
+
+    /// Attaches a BPF program filtering the datagrams arriving at the socket, replacing the
+    /// previous one, or detaches it with `None`.
+    ///
+    /// The program runs on the payload of each datagram, which is dropped if the program returns
+    /// 0 and counted in `SocketStats::filtered`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    #[inline]
+    pub fn set_filter(&self, filter: Option<BpfProgram>) -> Result<()> {
+        self.inner.mailbox.set_filter(filter)
+    }
+
+    /// Puts the datagrams arriving at the socket back in the order of the sequence numbers in
+    /// `reorder::seqn_field` within a window of `size`, or stops reordering with `None`.
+    ///
+    /// Sequence numbers are set by the application before the datagrams reach the socket, e.g.
+    /// by a `dispatch` handler, or on the `Mbuf`s sent by `send_mbuf_to` between local sockets.
+    /// A datagram is held until the ones before it arrive, or until the window moves beyond
+    /// them. Datagrams too late or too early to be reordered are delivered as they are.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::InvalidArg`: `size` is not a power of 2.
+    /// - Unable to create the reorder buffer.
+    #[inline]
+    pub fn set_reorder(&self, size: Option<u32>) -> Result<()> {
+        let buf = match size {
+            Some(size) => {
+                let seq = REORDER_SEQ.fetch_add(1, Ordering::Relaxed);
+                let name = format!("udp_reorder_{}_{seq}", self.inner.sockfd);
+                Some(ReorderBuffer::create(&name, size)?)
             }
+            None => None,
+        };
+        self.inner.mailbox.set_reorder(buf)
+    }
 
-            // fill l3 header
-            // SAFETY: hdr size = l2_sz + l3_sz + l4_sz
-            let ip_hdr = unsafe { &mut *(hdr.chunk_mut()[..].as_mut_ptr().cast::<rte_ipv4_hdr>()) };
-            ip_hdr.version_ihl_union.version_ihl = 0x45; // version = 4, ihl = 5
-            ip_hdr.type_of_service = 0;
-            ip_hdr.total_length = total_len.to_be();
-
-            ip_hdr.packet_id = IPID.fetch_add(1, Ordering::AcqRel).to_be();
-            ip_hdr.fragment_offset = 0u16;
-            ip_hdr.time_to_live = 64;
-            ip_hdr.next_proto_id = IP_NEXT_PROTO_UDP;
-            ip_hdr.dst_addr = match addr.ip() {
-                IpAddr::V4(addr) => u32::from_ne_bytes(addr.octets()),
-                #[allow(clippy::unimplemented)]
-                IpAddr::V6(_) => unimplemented!(),
-            };
-            ip_hdr.src_addr = self.ip;
-            // SAFETY: ffi
-            ip_hdr.hdr_checksum = unsafe { rte_ipv4_cksum(ip_hdr).to_be() };
+    /// Statistics of the socket.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    #[inline]
+    pub fn stats(&self) -> Result<SocketStats> {
+        let stats = self.inner.mailbox.stats()?;
+        Ok(SocketStats {
+            sent: self.inner.sent.load(Ordering::Relaxed),
+            sent_bytes: self.inner.sent_bytes.load(Ordering::Relaxed),
+            policed: self.inner.policed.load(Ordering::Relaxed),
+            ..stats
+        })
+    }
+
+    /// Start capturing the frames carrying datagrams to and from the socket into a pcapng file at
+    /// `path`, which is truncated if it exists. The capture stops when the socket is dropped.
+    ///
+    /// Datagrams delivered between local sockets without the devices are not captured.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::Already`: the socket is being captured.
+    /// - Failed to create the file.
+    #[inline]
+    pub fn start_capture<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        capture::start(self.inner.capture_target(), path.as_ref())
+    }
+
+    /// Stop capturing the socket, flushing the pcapng file.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - Lock poisoned.
+    /// - `Error::NotExist`: the socket is not being captured.
+    /// - Failed to flush the file.
+    #[inline]
+    pub fn stop_capture(&self) -> Result<()> {
+        capture::stop(self.inner.capture_target())
+    }
+
+    /// The source and destination IP addresses of a datagram to `addr`.
+    ///
+    /// A socket bound to an unspecified address sends from the local address chosen for the
+    /// destination, i.e. the address whose subnet holds it, or the one of the route to it.
+    fn route(&self, addr: SocketAddr) -> Result<(IpAddr, IpAddr)> {
+        let dst_ip = addr.ip();
+        let src_ip = if self.inner.ip.is_unspecified() {
+            net_dev::local_ip_for(dst_ip)?
+        } else {
+            self.inner.ip
+        };
+        if src_ip.is_ipv4() != dst_ip.is_ipv4() {
+            return Err(Error::InvalidArg);
+        }
+        Ok((src_ip, dst_ip))
+    }
+
+    /// Choose the addresses and the device of a datagram to `addr`.
+    ///
+    /// Datagrams of a socket bound to an unspecified address go out through the device of the
+    /// local address chosen by `route`.
+    fn egress(&self, addr: SocketAddr) -> Result<Egress> {
+        let (src_ip, dst_ip) = self.route(addr)?;
+        let dev = if self.inner.ip.is_unspecified() {
+            Some(net_dev::find_dev_by_ip(src_ip)?)
+        } else {
+            None
+        };
+        Ok(Egress {
+            src_ip,
+            dst_ip,
+            dev,
+        })
+    }
+
+    /// The `TxSender` and the Ether address of the device that `egress` goes out through.
+    fn dev<'a>(&'a self, egress: &'a Egress) -> (&'a TxSender, rte_ether_addr) {
+        match egress.dev {
+            Some((ref tx, eth_addr)) => (tx, eth_addr),
+            None => (&self.inner.tx, self.inner.eth_addr),
+        }
+    }
+
+    /// The socket on this host bound to `addr`, along with the source address of datagrams
+    /// delivered to it, or `None` if `addr` is not local.
+    fn local_dst(&self, addr: SocketAddr) -> Result<Option<(i32, SocketAddr)>> {
+        if !LOOPBACK.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let (src_ip, dst_ip) = self.route(addr)?;
+        if dst_ip.is_multicast() || dst_ip.is_unspecified() || !net_dev::is_local_ip(dst_ip)? {
+            return Ok(None);
+        }
+        let src_addr = SocketAddr::new(src_ip, self.inner.port);
+        Ok(addr_2_sockfd(addr.port(), dst_ip, src_addr).map(|sockfd| (sockfd, src_addr)))
+    }
+
+    /// Deliver a datagram to a socket on this host found by `local_dst`, bypassing the devices.
+    fn send_local(
+        &self,
+        (sockfd, src_addr): (i32, SocketAddr),
+        buf: &[u8],
+        opts: SendOptions,
+    ) -> Result<()> {
+        let mut pkt = Packet::new(L3Protocol::Unknown, L4Protocol::Unknown);
+        pkt.append(BytesMut::from(buf));
+        let mut m = self.inner.tx.packet_mbuf(pkt)?;
+        self.local_meta(opts).record(&mut m);
+        socket::put_local(sockfd, src_addr, m)?;
+        self.count_sent(1, buf.len());
+        Ok(())
+    }
+
+    /// The metadata of a datagram delivered to a socket on this host, as if it's sent with
+    /// `opts` and received without passing a router.
+    fn local_meta(&self, opts: SendOptions) -> RecvMeta {
+        RecvMeta {
+            ttl: opts.ttl.unwrap_or_else(|| self.ttl()),
+            tos: opts.tos.unwrap_or_else(|| self.tos()),
+            rx_timestamp: None,
+        }
+    }
+
+    /// Meter a datagram with `len` payload bytes against the policer of the socket, returning
+    /// the options to send it with, or `None` if it's dropped.
+    fn police(&self, len: usize, opts: SendOptions) -> Result<Option<SendOptions>> {
+        let action = match self.inner.policer.lock().map_err(Error::from)?.as_mut() {
+            Some(state) => state.police(len),
+            None => return Ok(Some(opts)),
+        };
+        match action {
+            PoliceAction::Pass => Ok(Some(opts)),
+            PoliceAction::Demote => Ok(Some(opts.tos(0))),
+            PoliceAction::Drop => {
+                let _policed = self.inner.policed.fetch_add(1, Ordering::Relaxed);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Count `n` datagrams of `bytes` payload bytes in total as sent.
+    fn count_sent(&self, n: usize, bytes: usize) {
+        let _sent = self.inner.sent.fetch_add(n as u64, Ordering::Relaxed);
+        let _bytes = self
+            .inner
+            .sent_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
 
-            // SAFETY: hdr size = l2_sz + l3_sz + l4_sz
-            unsafe {
-                hdr.advance_mut(l3_sz as _);
+    /// Get a `PacketBuilder` of datagrams to `addr` with `opts`, resolving its Ether address,
+    /// along with where they go out.
+    async fn builder(
+        &self,
+        addr: SocketAddr,
+        opts: SendOptions,
+    ) -> Result<(PacketBuilder, Egress)> {
+        let egress = self.egress(addr)?;
+        let dst_mac = match (egress.src_ip, egress.dst_ip) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                let (tx, eth_addr) = self.dev(&egress);
+                arp::resolve(dst, src, eth_addr, tx).await?
             }
+            (_, IpAddr::V6(dst)) => arp::resolve_v6(dst)?,
+            (IpAddr::V6(_), IpAddr::V4(_)) => return Err(Error::InvalidArg),
+        };
+        let builder = self.builder_to(&egress, dst_mac, addr.port(), opts)?;
+        Ok((builder, egress))
+    }
+
+    /// Same as `builder`, but fails with `Error::TempUnavail` instead of waiting if the Ether
+    /// address of the destination is not resolved yet.
+    fn try_builder(&self, addr: SocketAddr, opts: SendOptions) -> Result<(PacketBuilder, Egress)> {
+        let egress = self.egress(addr)?;
+        let dst_mac = match (egress.src_ip, egress.dst_ip) {
+            (IpAddr::V4(_), IpAddr::V4(dst)) => arp::lookup(dst)?.ok_or(Error::TempUnavail)?,
+            (_, IpAddr::V6(dst)) => arp::resolve_v6(dst)?,
+            (IpAddr::V6(_), IpAddr::V4(_)) => return Err(Error::InvalidArg),
+        };
+        let builder = self.builder_to(&egress, dst_mac, addr.port(), opts)?;
+        Ok((builder, egress))
+    }
 
-            // SAFETY: hdr size = l2_sz + l3_sz + l4_sz
-            let udp_hdr = unsafe { &mut *(hdr.chunk_mut()[..].as_mut_ptr().cast::<rte_udp_hdr>()) };
-            udp_hdr.src_port = self.port;
-            udp_hdr.dst_port = addr.port();
-            udp_hdr.dgram_len = payload_len.wrapping_add(l4_sz).to_be();
-            udp_hdr.dgram_cksum = 0;
+    /// Try to hand a datagram to `target` to the `TxAgent` without waiting, returning what to wait
+    /// for before trying again if it can't be sent yet.
+    fn send_or_wait(&self, buf: &[u8], target: SocketAddr) -> Result<Option<SendWait>> {
+        let egress = self.egress(target)?;
+        let (tx, eth_addr) = self.dev(&egress);
+        let dst_mac = match (egress.src_ip, egress.dst_ip) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => match arp::lookup(dst)? {
+                Some(mac) => mac,
+                None => {
+                    let tx = tx.clone();
+                    return Ok(Some(Box::pin(async move {
+                        arp::resolve(dst, src, eth_addr, &tx).await.map(drop)
+                    })));
+                }
+            },
+            (_, IpAddr::V6(dst)) => arp::resolve_v6(dst)?,
+            (IpAddr::V6(_), IpAddr::V4(_)) => return Err(Error::InvalidArg),
+        };
+        if tx.is_full() {
+            return Ok(Some(Box::pin(tx.ready())));
+        }
+        let pkt = self
+            .builder_to(&egress, dst_mac, target.port(), SendOptions::default())?
+            .build(buf)?;
+        let pkt_len = pkt.len();
+        if let Some(delay) = self.inner.shaper.try_acquire_or_delay(pkt_len)? {
+            return Ok(Some(Box::pin(async move {
+                time::sleep(delay).await;
+                Ok(())
+            })));
+        }
+        // Policed after the waits, so that the datagram is metered once across retries.
+        let pkt = match self.police(buf.len(), SendOptions::default())? {
+            Some(opts) if opts == SendOptions::default() => pkt,
+            Some(opts) => self
+                .builder_to(&egress, dst_mac, target.port(), opts)?
+                .build(buf)?,
+            None => {
+                self.inner.shaper.give_back(pkt_len)?;
+                return Ok(None);
+            }
+        };
+        enter_span!("udp_poll_send", sockfd = self.inner.sockfd, pkt_len);
+        match tx.try_send(pkt) {
+            Ok(()) => {
+                self.count_sent(1, buf.len());
+                Ok(None)
+            }
+            // Another task takes the room in the channel first.
+            Err(Error::TempUnavail) => {
+                self.inner.shaper.give_back(pkt_len)?;
+                Ok(Some(Box::pin(tx.ready())))
+            }
+            Err(e) => self.give_back_on_err(Err(e), pkt_len),
+        }
+    }
 
-            // SAFETY: hdr size = l2_sz + l3_sz + l4_sz
-            unsafe {
-                hdr.advance_mut(l4_sz as _);
+    /// Get a `PacketBuilder` of datagrams going out through `egress` to `dst_mac` and `dst_port`
+    /// with `opts`.
+    fn builder_to(
+        &self,
+        egress: &Egress,
+        dst_mac: rte_ether_addr,
+        dst_port: u16,
+        opts: SendOptions,
+    ) -> Result<PacketBuilder> {
+        let (tx, eth_addr) = self.dev(egress);
+        let RecvMeta { ttl, tos, .. } = self.local_meta(opts);
+        if ttl == 0 {
+            return Err(Error::InvalidArg);
+        }
+        // Ports are populated in the same byte order as the socket addresses.
+        let builder = PacketBuilder::new()
+            .ethernet(eth_addr.addr_bytes, dst_mac.addr_bytes)
+            .udp(u16::from_be(self.inner.port), u16::from_be(dst_port))
+            .ip_cksum_offload(tx.offloads() & RTE_ETH_TX_OFFLOAD_IPV4_CKSUM != 0)
+            .udp_cksum_offload(udp_cksum_offload(tx))
+            .tos(tos);
+        match (egress.src_ip, egress.dst_ip) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => Ok(builder.ipv4(src, dst, ttl)),
+            (IpAddr::V6(src), IpAddr::V6(dst)) => Ok(builder.ipv6(src, dst, ttl)),
+            (IpAddr::V4(_), IpAddr::V6(_)) | (IpAddr::V6(_), IpAddr::V4(_)) => {
+                Err(Error::InvalidArg)
             }
-            pkt.append(hdr);
-            pkt.append(BytesMut::from(buf));
         }
-        self.tx.send(pkt).await?;
-        Ok(buf_len)
     }
 }
 
 impl Debug for UdpSocket {
     #[inline]
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("UdpSocket")
-            .field("sockfd", &self.sockfd)
-            .field("ip", &self.ip)
-            .field("port", &self.port)
-            .field("tx", &self.tx)
+            .field("sockfd", &self.inner.sockfd)
+            .field("ip", &self.inner.ip)
+            .field("port", &self.inner.port)
+            .field("peer", &self.inner.peer)
+            .field("tx", &self.inner.tx)
             .finish()
     }
 }
 
-impl Drop for UdpSocket {
+impl Display for UdpSocket {
+    /// Formats the socket as `udp <local address>`, followed by `-> <peer address>` if it's
+    /// connected.
     #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "udp {}", self.local_addr())?;
+        if let Ok(peer) = self.peer_addr() {
+            write!(f, " -> {peer}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UdpInner {
     fn drop(&mut self) {
+        // The socket may not be captured.
+        let _res = capture::stop(self.capture_target());
+        if let Ok(groups) = self.groups.get_mut() {
+            for &(group, iface) in groups.iter() {
+                if let Err(e) = net_dev::leave_multicast(&iface, group) {
+                    warn!("Failed to leave multicast group {group}: {e:?}");
+                }
+            }
+        }
         #[allow(clippy::unwrap_used)] // used in drop
         socket::dealloc_mailbox(self.sockfd).unwrap();
         #[allow(clippy::unwrap_used)] // used in drop
@@ -228,15 +1573,69 @@ impl Drop for UdpSocket {
     }
 }
 
+/// Waits until any of `sockets` is readable. On success, returns the indices of all sockets
+/// readable by then, in ascending order, so that none of them is starved by the ones before.
+///
+/// The datagrams are left in the sockets, to be taken out with `try_recv_from` and alike. The
+/// returned indices may be empty if another task takes the datagrams first.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - `Error::InvalidArg`: `sockets` is empty.
+/// - Recv agent not started.
+#[inline]
+pub async fn select_readable(sockets: &[&UdpSocket]) -> Result<Vec<usize>> {
+    if sockets.is_empty() {
+        return Err(Error::InvalidArg);
+    }
+    let mut waits: Vec<_> = sockets
+        .iter()
+        .map(|socket| Box::pin(socket.readable()))
+        .collect();
+    future::poll_fn(|cx| {
+        for wait in &mut waits {
+            if let Poll::Ready(res) = wait.as_mut().poll(cx) {
+                return Poll::Ready(res);
+            }
+        }
+        Poll::Pending
+    })
+    .await?;
+    let mut readable = Vec::new();
+    for (i, socket) in sockets.iter().enumerate() {
+        if socket.inner.mailbox.is_ready()? {
+            readable.push(i);
+        }
+    }
+    Ok(readable)
+}
+
+/// Whether the UDP checksum of datagrams sent through `tx` is computed by the hardware.
+fn udp_cksum_offload(tx: &TxSender) -> bool {
+    tx.offloads() & RTE_ETH_TX_OFFLOAD_UDP_CKSUM != 0
+}
+
+/// Copy the datagram held by `data` into `buf`, returning the number of bytes copied.
+///
+/// The datagram is truncated if `buf` is not large enough.
+fn copy_to_buf(data: &Mbuf, buf: &mut [u8]) -> usize {
+    data.read_at(0, buf)
+}
+
 /// Handle IPv4 & UDP packet.
 ///
 /// Information such as IP + port of source and destination will be parsed,
 /// and the packet will be put into the corresponding `Mailbox`.
 pub(crate) fn handle_ipv4_udp(mut m: Mbuf) -> Option<(i32, RecvResult)> {
+    // Taken before the first segment may be dropped.
+    let rx_timestamp = timestamp::rx_timestamp(&m);
     let ipv4_hdr_len = L3Protocol::Ipv4.length() as usize;
     let udp_hdr_len = L4Protocol::Udp.length() as usize;
     if m.pkt_len() < ipv4_hdr_len.saturating_add(udp_hdr_len) {
-        log::error!("packet too short, less than IPv4 & UDP header");
+        error!("packet too short, less than IPv4 & UDP header");
+        agent::classifier_dropped();
         return None;
     }
 
@@ -248,17 +1647,73 @@ pub(crate) fn handle_ipv4_udp(mut m: Mbuf) -> Option<(i32, RecvResult)> {
         }
     }
 
-    let ip_hdr = m.data_slice();
-    // SAFETY: remain size larger than `rte_ipv4_hdr`, which is checked in `handle_ether
-    #[allow(unsafe_code)]
-    let ip_hdr = unsafe { &*(ip_hdr.as_ptr().cast::<rte_ipv4_hdr>()) };
-    let dst_ip_bytes: [u8; 4] = ip_hdr.dst_addr.to_ne_bytes();
-    let dst_ip = IpAddr::from(dst_ip_bytes);
-    let src_ip_bytes: [u8; 4] = ip_hdr.src_addr.to_ne_bytes();
-    let src_ip = IpAddr::from(src_ip_bytes);
-    log::trace!("from {src_ip:?} to {dst_ip:?}");
-    m.adj(ipv4_hdr_len).ok()?;
+    let ip_hdr = Ipv4Hdr::from_mbuf(&m, 0).ok()?;
+    let dst_ip = IpAddr::from(ip_hdr.dst_addr());
+    let src_ip = IpAddr::from(ip_hdr.src_addr());
+    let meta = RecvMeta {
+        ttl: ip_hdr.time_to_live(),
+        tos: ip_hdr.type_of_service(),
+        rx_timestamp,
+    };
+    // Options are skipped.
+    let hdr_len = ip_hdr.header_len();
+    if hdr_len < ipv4_hdr_len || m.data_len() < hdr_len {
+        warn!("malformed IPv4 header from {src_ip:?}, dropped");
+        agent::classifier_dropped();
+        return None;
+    }
+    trace!("from {src_ip:?} to {dst_ip:?}");
+    m.adj(hdr_len).ok()?;
+    handle_udp(m, src_ip, dst_ip, meta)
+}
+
+/// Handle IPv6 & UDP packet.
+///
+/// Information such as IP + port of source and destination will be parsed,
+/// and the packet will be put into the corresponding `Mailbox`.
+pub(crate) fn handle_ipv6_udp(mut m: Mbuf) -> Option<(i32, RecvResult)> {
+    // Taken before the first segment may be dropped.
+    let rx_timestamp = timestamp::rx_timestamp(&m);
+    let ipv6_hdr_len = L3Protocol::Ipv6.length() as usize;
+    let udp_hdr_len = L4Protocol::Udp.length() as usize;
+    if m.pkt_len() < ipv6_hdr_len.saturating_add(udp_hdr_len) {
+        error!("packet too short, less than IPv6 & UDP header");
+        agent::classifier_dropped();
+        return None;
+    }
+
+    if m.data_len() < ipv6_hdr_len {
+        if m.data_len() == 0 {
+            m = m.pop_mbuf()?;
+        } else {
+            return None;
+        }
+    }
+
+    let ip_hdr = Ipv6Hdr::from_mbuf(&m, 0).ok()?;
+    let dst_ip = IpAddr::from(ip_hdr.dst_addr());
+    let src_ip = IpAddr::from(ip_hdr.src_addr());
+    #[allow(clippy::cast_possible_truncation)] // traffic class is 8 bits
+    let meta = RecvMeta {
+        ttl: ip_hdr.hop_limits(),
+        tos: ip_hdr.vtc_flow().wrapping_shr(20) as u8,
+        rx_timestamp,
+    };
+    trace!("from {src_ip:?} to {dst_ip:?}");
+    m.adj(ipv6_hdr_len).ok()?;
+    handle_udp(m, src_ip, dst_ip, meta)
+}
 
+/// Handle UDP packet whose IP header is stripped, recording `meta` of the IP header in it.
+fn handle_udp(
+    mut m: Mbuf,
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+    meta: RecvMeta,
+) -> Option<(i32, RecvResult)> {
+    let port_id = m.port();
+    enter_span!("udp_rx", pkt_len = m.pkt_len());
+    let udp_hdr_len = L4Protocol::Udp.length() as usize;
     if m.data_len() < udp_hdr_len {
         if m.data_len() == 0 {
             m = m.pop_mbuf()?;
@@ -267,24 +1722,78 @@ pub(crate) fn handle_ipv4_udp(mut m: Mbuf) -> Option<(i32, RecvResult)> {
         }
     }
 
-    let udp_hdr = m.data_slice();
-    // SAFETY: remain size larger than `rte_udp_hdr` size
-    #[allow(unsafe_code, trivial_casts)]
-    let udp_hdr = unsafe { &*(udp_hdr.as_ptr().cast::<rte_udp_hdr>()) };
-    let dst_port = udp_hdr.dst_port;
-    let src_port = udp_hdr.src_port;
-    let _len = udp_hdr.dgram_len.to_be();
+    let udp_hdr = UdpHdr::from_mbuf(&m, 0).ok()?;
+    // Ports are in the same byte order as the socket addresses.
+    let dst_port = udp_hdr.dst_port().to_be();
+    let src_port = udp_hdr.src_port().to_be();
+    let dgram_len = usize::from(udp_hdr.dgram_len());
+    let dgram_cksum = udp_hdr.dgram_cksum();
     let src_addr = SocketAddr::new(src_ip, src_port);
+    if dgram_len < udp_hdr_len || m.pkt_len() < dgram_len {
+        warn!("malformed UDP datagram from {src_addr:?}, dropped");
+        agent::classifier_dropped();
+        return None;
+    }
+    // Strip the Ethernet padding.
+    if m.pkt_len() > dgram_len {
+        m.trim(m.pkt_len().wrapping_sub(dgram_len)).ok()?;
+    }
+    if RX_CKSUM_VALIDATE.load(Ordering::Relaxed) && !cksum_valid(&m, src_ip, dst_ip, dgram_cksum) {
+        warn!("UDP checksum mismatch from {src_addr:?}, datagram dropped");
+        agent::classifier_dropped();
+        return None;
+    }
     m.adj(udp_hdr_len).ok()?;
 
     if m.data_len() == 0 {
         m = m.pop_mbuf()?;
     }
+    meta.record(&mut m);
 
-    let packet = Packet::from_mbuf(m);
-    if let Some(sockfd) = addr_2_sockfd(dst_port, dst_ip) {
-        return Some((sockfd, Ok((src_addr, packet))));
+    if let Some(sockfd) = addr_2_sockfd(dst_port, dst_ip, src_addr) {
+        return Some((sockfd, Ok((src_addr, m))));
     }
-    log::warn!("sockfd not found: {dst_ip:?}:{dst_port}");
+    // Broadcasts to a local subnet are also received by sockets bound to the local address.
+    if let IpAddr::V4(dst) = dst_ip {
+        if let Some(sockfd) = net_dev::broadcast_owner(port_id, dst)
+            .and_then(|local| addr_2_sockfd(dst_port, local, src_addr))
+        {
+            return Some((sockfd, Ok((src_addr, m))));
+        }
+    }
+    warn!("sockfd not found: {dst_ip:?}:{dst_port}");
+    agent::classifier_dropped();
     None
 }
+
+/// Whether the checksum of a UDP datagram `m`, which starts at the UDP header, is valid.
+fn cksum_valid(m: &Mbuf, src_ip: IpAddr, dst_ip: IpAddr, dgram_cksum: u16) -> bool {
+    let l4_cksum = m.ol_flags() & u64::from(RTE_MBUF_F_RX_L4_CKSUM_MASK);
+    if l4_cksum == u64::from(RTE_MBUF_F_RX_L4_CKSUM_BAD) {
+        return false;
+    }
+    if l4_cksum != 0 {
+        // Either good, or the data integrity is verified by the hardware.
+        return l4_cksum & u64::from(RTE_MBUF_F_RX_L4_CKSUM_GOOD) != 0;
+    }
+    // The hardware knows nothing about the checksum, verify it in software.
+    let sum = match (src_ip, dst_ip) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            if dgram_cksum == 0 {
+                return true; // optional for IPv4
+            }
+            let Ok(len) = m.pkt_len().try_into() else {
+                return false;
+            };
+            ipv4_pseudo_sum(src.octets(), dst.octets(), IP_NEXT_PROTO_UDP, len)
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            let Ok(len) = m.pkt_len().try_into() else {
+                return false;
+            };
+            ipv6_pseudo_sum(src.octets(), dst.octets(), IP_NEXT_PROTO_UDP, len)
+        }
+        (IpAddr::V4(_), IpAddr::V6(_)) | (IpAddr::V6(_), IpAddr::V4(_)) => return false,
+    };
+    cksum_fold(cksum_add_mbuf(sum, m)) == 0
+}
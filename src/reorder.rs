@@ -0,0 +1,274 @@
+//! Reorder buffers based on `rte_reorder`, which put packets back in sequence-number order, e.g.
+//! after a flow is spread across several queues or lcores. For more information, please refer to
+//! [`Reorder library document`].
+//!
+//! The sequence number of a packet is held by a dynamic field of its `Mbuf`, which is set by the
+//! application before the packet is inserted.
+//!
+//! # Examples
+//!
+//! ```
+//! # use async_dpdk::mbuf::Mbuf;
+//! # use async_dpdk::mempool::{Mempool, PktMempool};
+//! # use async_dpdk::reorder::{self, ReorderBuffer};
+//!
+//! # let _ = async_dpdk::eal::Config::new().enter();
+//! let mp = PktMempool::create("reorder_example", 64).unwrap();
+//! let mut buf = ReorderBuffer::create("example", 16).unwrap();
+//! let field = reorder::seqn_field().unwrap();
+//! for seqn in [0, 2, 1] {
+//!     let mut m = Mbuf::new(&mp).unwrap();
+//!     m.set_dynfield(field, seqn);
+//!     buf.insert(m).unwrap();
+//! }
+//! let seqns: Vec<u32> = buf.drain_all().iter().map(|m| m.dynfield(field)).collect();
+//! assert_eq!(seqns, [0, 1, 2]);
+//! ```
+//!
+//! [`Reorder library document`]: https://doc.dpdk.org/guides/prog_guide/reorder_lib.html
+
+#![allow(non_camel_case_types)]
+
+use crate::{
+    lcore,
+    mbuf::{DynField, Mbuf},
+    Error, Result,
+};
+use dpdk_sys::rte_mbuf;
+use std::{
+    ffi::CString,
+    mem::ManuallyDrop,
+    os::raw::{c_char, c_int, c_uint},
+    ptr::{self, NonNull},
+    result::Result as StdResult,
+};
+
+/// Name of the dynamic field registered by `rte_reorder` for sequence numbers.
+const SEQN_DYNFIELD_NAME: &str = "rte_reorder_seqn_dynfield";
+
+/// Number of packets drained from a reorder buffer at a time by `ReorderBuffer::drain_all`.
+const DRAIN_BURST: usize = 32;
+
+/// Opaque type of reorder buffers.
+#[repr(C)]
+struct rte_reorder_buffer {
+    /// Zero-sized private field.
+    _private: [u8; 0],
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Create a reorder buffer holding `size` packets in order, and as many ready ones.
+    fn rte_reorder_create(
+        name: *const c_char,
+        socket_id: c_uint,
+        size: c_uint,
+    ) -> *mut rte_reorder_buffer;
+
+    /// Free a reorder buffer along with the packets in it.
+    fn rte_reorder_free(b: *mut rte_reorder_buffer);
+
+    /// Drop the packets in a reorder buffer, and start over from the next packet inserted.
+    fn rte_reorder_reset(b: *mut rte_reorder_buffer);
+
+    /// Insert a packet by its sequence number.
+    fn rte_reorder_insert(b: *mut rte_reorder_buffer, mbuf: *mut rte_mbuf) -> c_int;
+
+    /// Take up to `max_mbufs` packets in order, skipping the gaps beyond the window.
+    fn rte_reorder_drain(
+        b: *mut rte_reorder_buffer,
+        mbufs: *mut *mut rte_mbuf,
+        max_mbufs: c_uint,
+    ) -> c_uint;
+}
+
+/// Get the dynamic field holding the sequence numbers of `Mbuf`s, which is registered on the
+/// first call if no `ReorderBuffer` is created before.
+///
+/// # Errors
+///
+/// Possible reasons:
+///
+/// - `Error::NoMem`: no space left in `rte_mbuf`.
+#[inline]
+pub fn seqn_field() -> Result<DynField<u32>> {
+    DynField::register(SEQN_DYNFIELD_NAME)
+}
+
+/// A buffer putting packets back in sequence-number order, which is a safe wrapper of
+/// `rte_reorder`.
+///
+/// The first packet inserted starts the window of `size` sequence numbers. Packets in order are
+/// ready to be drained, and once a packet falls beyond the window, the window moves forward,
+/// skipping the packets still missing. Packets left in the buffer are dropped with it.
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    /// A pointer to `rte_reorder_buffer`.
+    b: NonNull<rte_reorder_buffer>,
+    /// Size of the window.
+    size: u32,
+}
+
+// SAFETY: the buffer and the packets in it can be accessed from any thread
+#[allow(unsafe_code)]
+unsafe impl Send for ReorderBuffer {}
+
+// SAFETY: the buffer is only accessed through `&mut ReorderBuffer`
+#[allow(unsafe_code)]
+unsafe impl Sync for ReorderBuffer {}
+
+impl ReorderBuffer {
+    /// Create a reorder buffer named `name`, with a window of `size` sequence numbers.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `name` contains a nul byte.
+    /// - `Error::InvalidArg`: `size` is not a power of 2.
+    /// - `Error::NoMem`: no appropriate memory area left.
+    /// - A reorder buffer with the same name already exists.
+    #[inline]
+    pub fn create(name: &str, size: u32) -> Result<Self> {
+        if !size.is_power_of_two() {
+            return Err(Error::InvalidArg);
+        }
+        let name = CString::new(name).map_err(Error::from)?;
+        // `SOCKET_ID_ANY` is passed as it is.
+        #[allow(clippy::cast_sign_loss)]
+        let socket_id = lcore::socket_id() as c_uint;
+        // SAFETY: pointer checked later
+        #[allow(unsafe_code)]
+        let b = unsafe { rte_reorder_create(name.as_ptr(), socket_id, size) };
+        let b = NonNull::new(b).ok_or_else(|| Error::from_errno("rte_reorder_create"))?;
+        Ok(Self { b, size })
+    }
+
+    /// Size of the window.
+    #[inline]
+    #[must_use]
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Insert a packet by the sequence number in `seqn_field`. The packet is given back along
+    /// with the error if it can't be buffered.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::OutOfRange`: the packet is too late, i.e. the window has moved beyond it, or
+    ///   more than twice the window ahead.
+    /// - `Error::NoSpace`: the packet is ahead of the window, which can't move forward until the
+    ///   ready packets are drained.
+    #[inline]
+    pub fn insert(&mut self, m: Mbuf) -> StdResult<(), (Error, Mbuf)> {
+        // SAFETY: the buffer is checked upon `create`, and takes the ownership of `m` on success
+        #[allow(unsafe_code)]
+        let ret = unsafe { rte_reorder_insert(self.b.as_ptr(), m.as_ptr()) };
+        if ret != 0 {
+            return Err((Error::from_errno("rte_reorder_insert"), m));
+        }
+        let _m = ManuallyDrop::new(m);
+        Ok(())
+    }
+
+    /// Take up to `max` packets ready in order.
+    #[inline]
+    #[must_use]
+    pub fn drain(&mut self, max: usize) -> Vec<Mbuf> {
+        let mut ptrs = vec![ptr::null_mut(); max];
+        let n = c_uint::try_from(max).unwrap_or(c_uint::MAX);
+        // SAFETY: the buffer is checked upon `create`, and `ptrs` holds `n` pointers
+        #[allow(unsafe_code)]
+        let n = unsafe { rte_reorder_drain(self.b.as_ptr(), ptrs.as_mut_ptr(), n) };
+        ptrs.truncate(n as usize);
+        ptrs.into_iter()
+            .filter_map(|ptr| Mbuf::new_with_ptr(ptr).ok())
+            .collect()
+    }
+
+    /// Take all packets ready in order.
+    #[inline]
+    #[must_use]
+    pub fn drain_all(&mut self) -> Vec<Mbuf> {
+        let mut drained = Vec::new();
+        loop {
+            let batch = self.drain(DRAIN_BURST);
+            let len = batch.len();
+            drained.extend(batch);
+            if len < DRAIN_BURST {
+                return drained;
+            }
+        }
+    }
+
+    /// Drop the packets in the buffer, and start over from the next packet inserted.
+    #[inline]
+    pub fn reset(&mut self) {
+        // SAFETY: the buffer is checked upon `create`
+        #[allow(unsafe_code)]
+        unsafe {
+            rte_reorder_reset(self.b.as_ptr());
+        }
+    }
+}
+
+impl Drop for ReorderBuffer {
+    #[inline]
+    fn drop(&mut self) {
+        // SAFETY: the buffer is checked upon `create`
+        #[allow(unsafe_code)]
+        unsafe {
+            rte_reorder_free(self.b.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{seqn_field, ReorderBuffer};
+    use crate::{
+        mbuf::Mbuf,
+        mempool::{Mempool, PktMempool},
+        test_utils, Error,
+    };
+
+    #[test]
+    fn test() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("reorder_test", 64).unwrap();
+        let field = seqn_field().unwrap();
+        let mbuf = |seqn: u32| {
+            let mut m = Mbuf::new(&mp).unwrap();
+            m.set_dynfield(field, seqn);
+            m
+        };
+        assert_eq!(
+            ReorderBuffer::create("reorder", 6).unwrap_err(),
+            Error::InvalidArg
+        );
+        let mut buf = ReorderBuffer::create("reorder", 8).unwrap();
+        assert_eq!(buf.size(), 8);
+        for seqn in [0, 2, 3] {
+            buf.insert(mbuf(seqn)).unwrap();
+        }
+        let seqns = |pkts: Vec<Mbuf>| pkts.iter().map(|m| m.dynfield(field)).collect::<Vec<_>>();
+        assert_eq!(seqns(buf.drain_all()), [0]);
+        buf.insert(mbuf(1)).unwrap();
+        assert_eq!(seqns(buf.drain(2)), [1, 2]);
+        assert_eq!(seqns(buf.drain_all()), [3]);
+
+        // The window has moved beyond it.
+        let (err, m) = buf.insert(mbuf(0)).unwrap_err();
+        assert_eq!(err, Error::OutOfRange);
+        assert_eq!(m.dynfield(field), 0);
+
+        buf.insert(mbuf(5)).unwrap();
+        buf.reset();
+        assert!(buf.drain_all().is_empty());
+        drop(buf);
+        assert_eq!(mp.in_use(), 1);
+    }
+}
@@ -0,0 +1,477 @@
+//! Ring is a fixed-sized lockless FIFO queue of pointers. It's the primitive that DPDK apps use to
+//! pass objects between lcores, e.g. `Mbuf`s through the stages of a pipeline.
+//!
+//! Each side of a `Ring` is either multi-producer/multi-consumer, where concurrent accesses are
+//! synchronized with a compare-and-swap, or single-producer/single-consumer, which is faster but
+//! only serves one thread at a time.
+//!
+//! # Examples
+//!
+//! ```
+//! # use async_dpdk::mbuf::Mbuf;
+//! # use async_dpdk::mempool::{Mempool, PktMempool};
+//! # use async_dpdk::ring::{Ring, SyncMode};
+//!
+//! # let _ = async_dpdk::eal::Config::new().enter();
+//! let mp = PktMempool::create("ring_example", 64).unwrap();
+//! let ring: Ring<Mbuf> = Ring::create("example", 32, SyncMode::Multi, SyncMode::Single).unwrap();
+//!
+//! let mut pkts = vec![Mbuf::new(&mp).unwrap(), Mbuf::new(&mp).unwrap()];
+//! assert_eq!(ring.enqueue_burst(&mut pkts), 2);
+//! assert_eq!(ring.dequeue_burst(&mut pkts, 32), 2);
+//! ```
+
+use crate::{lcore, mempool::MempoolObj, Error, Result};
+use dpdk_sys::{
+    rte_ring, rte_ring_create, rte_ring_free, rte_ring_headtail,
+    rte_ring_sync_type_RTE_RING_SYNC_ST,
+};
+use std::{
+    ffi::CString,
+    fmt::Debug,
+    hint,
+    marker::PhantomData,
+    os::raw::c_void,
+    ptr::{self, NonNull},
+    sync::atomic::{self, AtomicBool, AtomicU32, Ordering},
+};
+
+/// The default enqueue is single-producer.
+const RING_F_SP_ENQ: u32 = 0x1;
+/// The default dequeue is single-consumer.
+const RING_F_SC_DEQ: u32 = 0x2;
+/// The ring holds exactly the requested number of objects, instead of one less than a power of 2.
+const RING_F_EXACT_SZ: u32 = 0x4;
+
+/// Synchronization mode of the producer or the consumer side of a `Ring`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Several threads may enqueue or dequeue concurrently.
+    Multi,
+    /// One thread enqueues or dequeues at a time. A call that overlaps with another one on the
+    /// same side moves no objects.
+    Single,
+}
+
+/// A lockless FIFO queue of `MempoolObj`s, which is a safe wrapper of `rte_ring`.
+///
+/// Objects left in the ring are dropped with it.
+pub struct Ring<T: MempoolObj> {
+    /// A pointer to `rte_ring`.
+    r: NonNull<rte_ring>,
+    /// Held by the single producer during an enqueue.
+    prod_busy: AtomicBool,
+    /// Held by the single consumer during a dequeue.
+    cons_busy: AtomicBool,
+    /// Placeholder for generic type.
+    _marker: PhantomData<T>,
+}
+
+// SAFETY: objects are moved through the ring between threads.
+#[allow(unsafe_code)]
+unsafe impl<T: MempoolObj + Send> Send for Ring<T> {}
+
+// SAFETY: concurrent accesses to the multi sides are synchronized by the ring, and those to the
+// single sides are serialized by the busy flags.
+#[allow(unsafe_code)]
+unsafe impl<T: MempoolObj + Send> Sync for Ring<T> {}
+
+impl<T: MempoolObj> Debug for Ring<T> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ring")
+            .field("capacity", &self.capacity())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<T: MempoolObj> Ring<T> {
+    /// Create a ring holding up to `count` objects, with `prod` and `cons` as the synchronization
+    /// modes of its producer and consumer sides.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `name` contains a nul byte.
+    /// - `count` is 0 or too large.
+    /// - No appropriate memory area left.
+    /// - A memzone with the same name already exists.
+    /// - The maximum number of memzones has already been allocated.
+    #[inline]
+    pub fn create(name: &str, count: u32, prod: SyncMode, cons: SyncMode) -> Result<Self> {
+        if count == 0 {
+            return Err(Error::InvalidArg);
+        }
+        let name = CString::new(name).map_err(Error::from)?;
+        let mut flags = RING_F_EXACT_SZ;
+        if prod == SyncMode::Single {
+            flags |= RING_F_SP_ENQ;
+        }
+        if cons == SyncMode::Single {
+            flags |= RING_F_SC_DEQ;
+        }
+        // SAFETY: pointer checked later
+        #[allow(unsafe_code)]
+        let ptr = unsafe { rte_ring_create(name.as_ptr(), count, lcore::socket_id(), flags) };
+        let r = NonNull::new(ptr).ok_or_else(|| Error::from_errno("rte_ring_create"))?;
+        Ok(Self {
+            r,
+            prod_busy: AtomicBool::new(false),
+            cons_busy: AtomicBool::new(false),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Enqueue as many objects from the front of `objs` as the ring has room for, and remove
+    /// them from `objs`. Returns the number of objects enqueued.
+    #[inline]
+    pub fn enqueue_burst(&self, objs: &mut Vec<T>) -> usize {
+        let n = u32::try_from(objs.len()).unwrap_or(u32::MAX);
+        self.produce(n, |n| objs.drain(..n as usize)) as usize
+    }
+
+    /// Enqueue an object, which is handed back if the ring is full.
+    ///
+    /// # Errors
+    ///
+    /// The object is returned if the ring has no room for it.
+    #[inline]
+    pub fn enqueue(&self, obj: T) -> std::result::Result<(), T> {
+        let mut obj = Some(obj);
+        let _n = self.produce(1, |_| obj.take().into_iter());
+        obj.map_or(Ok(()), Err)
+    }
+
+    /// Reserve slots for up to `n` objects, and fill them with the objects taken by `take` given
+    /// the number of slots reserved. Returns the number of objects enqueued.
+    fn produce<I: Iterator<Item = T>>(&self, n: u32, take: impl FnOnce(u32) -> I) -> u32 {
+        // SAFETY: the ring is alive
+        #[allow(unsafe_code)]
+        let (prod, cons) = unsafe { self.headtails() };
+        let Some(guard) = Guard::acquire(prod, &self.prod_busy) else {
+            return 0;
+        };
+        let capacity = self.capacity();
+        // SAFETY: the producer head and tail are only moved by producers
+        #[allow(unsafe_code)]
+        let (old_head, n) = unsafe {
+            move_head(prod, cons, guard.single, n, |head, cons_tail| {
+                capacity.wrapping_add(cons_tail).wrapping_sub(head)
+            })
+        };
+        if n == 0 {
+            return 0;
+        }
+        let ptrs = self.elems();
+        for (i, obj) in (0..n).zip(take(n)) {
+            let idx = old_head.wrapping_add(i) & self.mask();
+            // SAFETY: `idx` is masked into the ring, and the slots between the old and new heads
+            // are reserved for this producer.
+            #[allow(unsafe_code)]
+            unsafe {
+                ptrs.add(idx as usize).write(obj.into_raw());
+            }
+        }
+        // SAFETY: the slots are filled
+        #[allow(unsafe_code)]
+        unsafe {
+            update_tail(prod, old_head, n, guard.single);
+        }
+        n
+    }
+
+    /// Dequeue up to `max` objects to the back of `objs`. Returns the number of objects dequeued.
+    #[inline]
+    pub fn dequeue_burst(&self, objs: &mut Vec<T>, max: usize) -> usize {
+        let n = u32::try_from(max).unwrap_or(u32::MAX);
+        // SAFETY: the ring is alive
+        #[allow(unsafe_code)]
+        let (prod, cons) = unsafe { self.headtails() };
+        let Some(guard) = Guard::acquire(cons, &self.cons_busy) else {
+            return 0;
+        };
+        // SAFETY: the consumer head and tail are only moved by consumers
+        #[allow(unsafe_code)]
+        let (old_head, n) = unsafe {
+            move_head(cons, prod, guard.single, n, |head, prod_tail| {
+                prod_tail.wrapping_sub(head)
+            })
+        };
+        if n == 0 {
+            return 0;
+        }
+        let ptrs = self.elems();
+        objs.reserve(n as usize);
+        for i in 0..n {
+            let idx = old_head.wrapping_add(i) & self.mask();
+            // SAFETY: `idx` is masked into the ring, and the slots between the old and new heads
+            // are filled by producers and reserved for this consumer.
+            #[allow(unsafe_code)]
+            let ptr = unsafe { ptrs.add(idx as usize).read() };
+            if let Ok(obj) = T::from_raw(ptr) {
+                objs.push(obj);
+            }
+        }
+        // SAFETY: the slots are read
+        #[allow(unsafe_code)]
+        unsafe {
+            update_tail(cons, old_head, n, guard.single);
+        }
+        n as usize
+    }
+
+    /// The number of objects the ring can hold.
+    #[inline]
+    #[must_use]
+    pub fn capacity(&self) -> u32 {
+        // SAFETY: the ring is alive
+        #[allow(unsafe_code)]
+        unsafe {
+            self.r.as_ref().capacity
+        }
+    }
+
+    /// The number of objects in the ring.
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> u32 {
+        // SAFETY: the ring is alive
+        #[allow(unsafe_code)]
+        let (prod, cons) = unsafe { self.headtails() };
+        let prod_tail = tail(prod).load(Ordering::Acquire);
+        let cons_tail = tail(cons).load(Ordering::Acquire);
+        prod_tail.wrapping_sub(cons_tail).min(self.capacity())
+    }
+
+    /// The number of objects that can be enqueued.
+    #[inline]
+    #[must_use]
+    pub fn free_count(&self) -> u32 {
+        self.capacity().wrapping_sub(self.len())
+    }
+
+    /// Whether the ring is empty.
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the ring is full.
+    #[inline]
+    #[must_use]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// A pointer to the underlying `rte_ring`, for handing the ring over to DPDK.
+    #[cfg(feature = "harness")]
+    pub(crate) fn as_ptr(&self) -> *mut rte_ring {
+        self.r.as_ptr()
+    }
+
+    /// Index mask of the slots.
+    fn mask(&self) -> u32 {
+        // SAFETY: the ring is alive
+        #[allow(unsafe_code)]
+        unsafe {
+            self.r.as_ref().mask
+        }
+    }
+
+    /// The slots, which directly follow the `rte_ring`.
+    fn elems(&self) -> *mut *mut c_void {
+        // SAFETY: the slots are allocated with the ring
+        #[allow(unsafe_code)]
+        unsafe {
+            self.r.as_ptr().add(1).cast()
+        }
+    }
+
+    /// The producer and the consumer head and tail.
+    ///
+    /// # Safety
+    ///
+    /// The ring must be alive.
+    #[allow(unsafe_code)]
+    unsafe fn headtails(&self) -> (*mut rte_ring_headtail, *mut rte_ring_headtail) {
+        let ring = self.r.as_ptr();
+        // SAFETY: rings of this type are created with the default sync types, where the
+        // `rte_ring_headtail` variants are active.
+        unsafe {
+            (
+                ptr::addr_of_mut!((*ring).__bindgen_anon_1.prod),
+                ptr::addr_of_mut!((*ring).__bindgen_anon_2.cons),
+            )
+        }
+    }
+}
+
+impl<T: MempoolObj> Drop for Ring<T> {
+    #[inline]
+    fn drop(&mut self) {
+        let mut objs = Vec::new();
+        while self.dequeue_burst(&mut objs, 64) != 0 {
+            objs.clear();
+        }
+        // SAFETY: *rte_ring checked upon `create`
+        #[allow(unsafe_code)]
+        unsafe {
+            rte_ring_free(self.r.as_ptr());
+        }
+    }
+}
+
+/// Serializes the accesses to a single side of a `Ring`.
+struct Guard<'a> {
+    /// The busy flag held, if the side is single.
+    busy: Option<&'a AtomicBool>,
+    /// Whether the side is single.
+    single: bool,
+}
+
+impl<'a> Guard<'a> {
+    /// Acquire `busy` if the side of `ht` is single, which fails if it's held by another thread.
+    fn acquire(ht: *mut rte_ring_headtail, busy: &'a AtomicBool) -> Option<Self> {
+        // SAFETY: the sync type is set upon creation and never changes
+        #[allow(unsafe_code)]
+        let single = unsafe {
+            ptr::addr_of!((*ht).__bindgen_anon_1.sync_type).read()
+                == rte_ring_sync_type_RTE_RING_SYNC_ST
+        };
+        if !single {
+            return Some(Self { busy: None, single });
+        }
+        if busy.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        Some(Self {
+            busy: Some(busy),
+            single,
+        })
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        if let Some(busy) = self.busy {
+            busy.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// The head of `ht` as an atomic.
+fn head<'a>(ht: *mut rte_ring_headtail) -> &'a AtomicU32 {
+    // SAFETY: `head` is a properly aligned u32 in a live ring, and accessed atomically by DPDK too
+    #[allow(unsafe_code)]
+    unsafe {
+        &*ptr::addr_of_mut!((*ht).head).cast::<AtomicU32>()
+    }
+}
+
+/// The tail of `ht` as an atomic.
+fn tail<'a>(ht: *mut rte_ring_headtail) -> &'a AtomicU32 {
+    // SAFETY: `tail` is a properly aligned u32 in a live ring, and accessed atomically by DPDK too
+    #[allow(unsafe_code)]
+    unsafe {
+        &*ptr::addr_of_mut!((*ht).tail).cast::<AtomicU32>()
+    }
+}
+
+/// Move the head of `ht` by up to `n` slots, which are limited by `entries`, the number of slots
+/// available to this side given its head and the tail of `other`. Returns the old head and the
+/// number of slots reserved. This is `__rte_ring_move_prod_head` and `__rte_ring_move_cons_head`
+/// with `RTE_RING_QUEUE_VARIABLE`.
+///
+/// # Safety
+///
+/// `ht` and `other` must be the sides of a live ring.
+#[allow(unsafe_code)]
+unsafe fn move_head(
+    ht: *mut rte_ring_headtail,
+    other: *mut rte_ring_headtail,
+    single: bool,
+    n: u32,
+    entries: impl Fn(u32, u32) -> u32,
+) -> (u32, u32) {
+    let head = head(ht);
+    let mut old_head = head.load(Ordering::Relaxed);
+    loop {
+        atomic::fence(Ordering::Acquire);
+        let other_tail = tail(other).load(Ordering::Acquire);
+        let n = n.min(entries(old_head, other_tail));
+        if n == 0 {
+            return (old_head, 0);
+        }
+        let new_head = old_head.wrapping_add(n);
+        if single {
+            head.store(new_head, Ordering::Relaxed);
+            return (old_head, n);
+        }
+        match head.compare_exchange_weak(old_head, new_head, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return (old_head, n),
+            Err(cur) => old_head = cur,
+        }
+    }
+}
+
+/// Publish the `n` slots from `old_head` by moving the tail of `ht`, after the preceding
+/// reservations of other threads are published. This is `__rte_ring_update_tail`.
+///
+/// # Safety
+///
+/// `ht` must be a side of a live ring.
+#[allow(unsafe_code)]
+unsafe fn update_tail(ht: *mut rte_ring_headtail, old_head: u32, n: u32, single: bool) {
+    let tail = tail(ht);
+    if !single {
+        while tail.load(Ordering::Relaxed) != old_head {
+            hint::spin_loop();
+        }
+    }
+    tail.store(old_head.wrapping_add(n), Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ring, SyncMode};
+    use crate::mbuf::Mbuf;
+    use crate::mempool::{Mempool, PktMempool};
+    use crate::test_utils;
+
+    #[test]
+    fn test() {
+        test_utils::dpdk_setup();
+        let mp = PktMempool::create("ring_test", 64).unwrap();
+        let ring: Ring<Mbuf> = Ring::create("ring", 4, SyncMode::Single, SyncMode::Multi).unwrap();
+        assert_eq!(ring.capacity(), 4);
+        assert!(ring.is_empty());
+
+        let mut pkts: Vec<_> = (0..6).map(|_| Mbuf::new(&mp).unwrap()).collect();
+        let ptrs: Vec<_> = pkts.iter().map(Mbuf::as_ptr).collect();
+        assert_eq!(ring.enqueue_burst(&mut pkts), 4);
+        assert_eq!(pkts.len(), 2);
+        assert!(ring.is_full());
+        assert_eq!(ring.enqueue_burst(&mut pkts), 0);
+
+        let mut out = vec![];
+        assert_eq!(ring.dequeue_burst(&mut out, 3), 3);
+        assert_eq!(ring.len(), 1);
+        assert_eq!(ring.enqueue_burst(&mut pkts), 2);
+        assert_eq!(ring.dequeue_burst(&mut out, 8), 3);
+        assert!(ring.is_empty());
+        assert_eq!(out.iter().map(Mbuf::as_ptr).collect::<Vec<_>>(), ptrs);
+
+        assert_eq!(ring.enqueue_burst(&mut out), 4);
+        let m = ring.enqueue(Mbuf::new(&mp).unwrap()).unwrap_err();
+        assert_eq!(ring.dequeue_burst(&mut out, 1), 1);
+        ring.enqueue(m).unwrap();
+        drop(out);
+        assert_eq!(mp.in_use(), 4);
+        drop(ring);
+        assert_eq!(mp.in_use(), 0);
+    }
+}
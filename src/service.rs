@@ -0,0 +1,244 @@
+//! Background work run as DPDK services on service lcores, instead of threads spawned by this
+//! crate. Service lcores are reserved with `eal::Config::service_corelist`, and each of them runs
+//! the services mapped to it in turn. For more information, please refer to
+//! [`Service Cores document`].
+//!
+//! [`Service Cores document`]: https://doc.dpdk.org/guides/prog_guide/service_cores.html
+
+#![allow(non_camel_case_types)]
+
+use crate::{
+    lcore::{self, Role},
+    trace::error,
+    Error, Result,
+};
+use std::{
+    fmt::{self, Debug},
+    os::raw::{c_char, c_int, c_void},
+    ptr, thread,
+};
+
+/// Services without a preferred NUMA socket.
+pub(crate) const SOCKET_ID_ANY: c_int = -1;
+
+/// Maximum length of service names, including the trailing NUL.
+const RTE_SERVICE_NAME_MAX: usize = 32;
+
+/// Signature of service callbacks, which return 0 if some work is done, or `-EAGAIN` otherwise.
+type rte_service_func = Option<unsafe extern "C" fn(*mut c_void) -> i32>;
+
+/// Specification of a service.
+#[repr(C)]
+struct rte_service_spec {
+    /// Name of the service.
+    name: [c_char; RTE_SERVICE_NAME_MAX],
+    /// Callback run on each round of the service lcore.
+    callback: rte_service_func,
+    /// Argument of `callback`.
+    callback_userdata: *mut c_void,
+    /// Flags of `RTE_SERVICE_CAP_*`.
+    capabilities: u32,
+    /// NUMA socket the service prefers.
+    socket_id: c_int,
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Register a service. Returns 0, or a negative errno.
+    fn rte_service_component_register(spec: *const rte_service_spec, service_id: *mut u32) -> i32;
+
+    /// Unregister a stopped service. Returns 0, or a negative errno.
+    fn rte_service_component_unregister(id: u32) -> i32;
+
+    /// Set whether the component backing a service is ready to run.
+    fn rte_service_component_runstate_set(id: u32, runstate: u32) -> i32;
+
+    /// Set whether a service is started by the application.
+    fn rte_service_runstate_set(id: u32, runstate: u32) -> i32;
+
+    /// Map or unmap a service to a service lcore.
+    fn rte_service_map_lcore_set(service_id: u32, lcore: u32, enable: u32) -> i32;
+
+    /// Returns 1 if a service may be running on a service lcore, or 0 if it's surely not.
+    fn rte_service_may_be_active(id: u32) -> i32;
+
+    /// Start a service lcore. Returns `-EALREADY` if it's running.
+    fn rte_service_lcore_start(lcore_id: u32) -> i32;
+}
+
+/// Work run by a service lcore on each round, which returns whether it did something.
+pub(crate) type ServiceFn = dyn FnMut() -> bool + Send;
+
+/// A callback registered as a service and mapped to a service lcore, which is unregistered when
+/// dropped.
+pub(crate) struct Service {
+    /// `service_id` assigned by DPDK.
+    id: u32,
+    /// The service lcore running the service.
+    lcore: u32,
+    /// The callback, leaked while the service is registered.
+    callback: *mut Box<ServiceFn>,
+}
+
+// SAFETY: the callback is `Send`, and only run by the service lcore while registered.
+#[allow(unsafe_code)]
+unsafe impl Send for Service {}
+
+// SAFETY: the callback is never touched through `&Service`.
+#[allow(unsafe_code)]
+unsafe impl Sync for Service {}
+
+#[allow(unsafe_code)]
+impl Service {
+    /// Register `callback` as a service named `name`, and run it on service lcore `lcore`, which
+    /// is started if it's not running.
+    ///
+    /// # Errors
+    ///
+    /// - `Error::InvalidArg`: `lcore` is not a service lcore, or `name` is too long.
+    /// - Failed to register, map or start the service.
+    pub(crate) fn start(
+        name: &str,
+        lcore: u32,
+        socket_id: i32,
+        callback: Box<ServiceFn>,
+    ) -> Result<Self> {
+        if !matches!(lcore::role(lcore), Role::Service) || name.len() >= RTE_SERVICE_NAME_MAX {
+            return Err(Error::InvalidArg);
+        }
+        let callback = Box::into_raw(Box::new(callback));
+        let mut spec = rte_service_spec {
+            name: [0; RTE_SERVICE_NAME_MAX],
+            callback: Some(service_main),
+            callback_userdata: callback.cast(),
+            capabilities: 0,
+            socket_id,
+        };
+        for (dst, &src) in spec.name.iter_mut().zip(name.as_bytes()) {
+            #[allow(clippy::cast_possible_wrap)] // a byte of the name
+            {
+                *dst = src as c_char;
+            }
+        }
+        let mut id = 0;
+        // SAFETY: `spec` is copied by DPDK
+        let errno =
+            unsafe { rte_service_component_register(ptr::addr_of!(spec), ptr::addr_of_mut!(id)) };
+        if let Err(e) = Error::from_ret(errno, "rte_service_component_register") {
+            // SAFETY: the callback is not registered
+            drop(unsafe { Box::from_raw(callback) });
+            return Err(e);
+        }
+        // Unregistered on failures by the drop of `service`.
+        let service = Self {
+            id,
+            lcore,
+            callback,
+        };
+        // SAFETY: ffi
+        unsafe {
+            Error::from_ret(
+                rte_service_component_runstate_set(id, 1),
+                "rte_service_component_runstate_set",
+            )?;
+            Error::from_ret(
+                rte_service_map_lcore_set(id, lcore, 1),
+                "rte_service_map_lcore_set",
+            )?;
+            Error::from_ret(rte_service_runstate_set(id, 1), "rte_service_runstate_set")?;
+        }
+        // SAFETY: ffi
+        match unsafe { rte_service_lcore_start(lcore) } {
+            ret if ret == libc::EALREADY.saturating_neg() => {}
+            ret => Error::from_ret(ret, "rte_service_lcore_start")?,
+        }
+        Ok(service)
+    }
+}
+
+impl Debug for Service {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Service")
+            .field("id", &self.id)
+            .field("lcore", &self.lcore)
+            .finish()
+    }
+}
+
+#[allow(unsafe_code)]
+impl Drop for Service {
+    fn drop(&mut self) {
+        // SAFETY: ffi
+        unsafe {
+            let _stopped = rte_service_runstate_set(self.id, 0);
+            let _unready = rte_service_component_runstate_set(self.id, 0);
+            let _unmapped = rte_service_map_lcore_set(self.id, self.lcore, 0);
+            // The round running the callback is finished before it's freed.
+            while rte_service_may_be_active(self.id) == 1 {
+                thread::yield_now();
+            }
+            if let Err(e) = Error::from_ret(
+                rte_service_component_unregister(self.id),
+                "rte_service_component_unregister",
+            ) {
+                error!("Failed to unregister service {}: {e}", self.id);
+            }
+            // SAFETY: the callback is no longer run
+            drop(Box::from_raw(self.callback));
+        }
+    }
+}
+
+/// Run the service `id` registered by a driver, e.g. the scheduler of a software event device, on
+/// service lcore `lcore`, which is started if it's not running.
+///
+/// # Errors
+///
+/// - `Error::InvalidArg`: `lcore` is not a service lcore.
+/// - Failed to map or start the service.
+#[allow(unsafe_code)]
+pub(crate) fn run_driver_service(id: u32, lcore: u32) -> Result<()> {
+    if !matches!(lcore::role(lcore), Role::Service) {
+        return Err(Error::InvalidArg);
+    }
+    // SAFETY: ffi
+    unsafe {
+        Error::from_ret(
+            rte_service_map_lcore_set(id, lcore, 1),
+            "rte_service_map_lcore_set",
+        )?;
+        Error::from_ret(rte_service_runstate_set(id, 1), "rte_service_runstate_set")?;
+    }
+    // SAFETY: ffi
+    match unsafe { rte_service_lcore_start(lcore) } {
+        ret if ret == libc::EALREADY.saturating_neg() => Ok(()),
+        ret => Error::from_ret(ret, "rte_service_lcore_start"),
+    }
+}
+
+/// Stop the service `id` run by `run_driver_service`, waiting for the round in progress to finish.
+#[allow(unsafe_code)]
+pub(crate) fn stop_driver_service(id: u32, lcore: u32) {
+    // SAFETY: ffi
+    unsafe {
+        let _stopped = rte_service_runstate_set(id, 0);
+        let _unmapped = rte_service_map_lcore_set(id, lcore, 0);
+        while rte_service_may_be_active(id) == 1 {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Run a round of the callback of a service.
+#[allow(unsafe_code)]
+unsafe extern "C" fn service_main(arg: *mut c_void) -> i32 {
+    // SAFETY: `arg` is leaked from a `Box<ServiceFn>` in `Service::start`, and only run by one
+    // service lcore at a time
+    let callback = unsafe { &mut *arg.cast::<Box<ServiceFn>>() };
+    if callback() {
+        0
+    } else {
+        libc::EAGAIN.saturating_neg()
+    }
+}
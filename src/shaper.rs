@@ -0,0 +1,283 @@
+//! Token-bucket shaping of the packets sent by a socket or on a tx queue.
+//!
+//! Buckets are implemented with the generic cell rate algorithm (GCRA), which only keeps the time
+//! when the bucket would be full again, instead of refilling tokens on a timer.
+
+use crate::{Error, Result};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time;
+
+/// Number of packets sent at once by default under a limit in packets per second.
+const DEFAULT_BURST_PACKETS: u64 = 32;
+
+/// Number of bits sent at once by default under a limit in bits per second, i.e. 64 KiB.
+const DEFAULT_BURST_BITS: u64 = 64 * 1024 * 8;
+
+/// A rate limit of the packets sent, in packets or bits per second, which allows bursts of up to
+/// a given size.
+///
+/// Bits are counted over whole Ethernet frames without the FCS.
+///
+/// ```no_run
+/// use async_dpdk::net_dev::RateLimit;
+///
+/// // 100 Mbit/s with bursts of up to 128 KiB.
+/// let limit = RateLimit::bps(100_000_000).burst(128 * 1024 * 8);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RateLimit {
+    /// Packets or bits allowed per second.
+    rate: u64,
+    /// Whether `rate` and `burst` count packets instead of bits.
+    per_packet: bool,
+    /// Packets or bits which can be sent at once.
+    burst: u64,
+}
+
+impl RateLimit {
+    /// A limit of `rate` packets per second, with bursts of up to 32 packets.
+    #[inline]
+    #[must_use]
+    pub fn pps(rate: u64) -> Self {
+        Self {
+            rate,
+            per_packet: true,
+            burst: DEFAULT_BURST_PACKETS,
+        }
+    }
+
+    /// A limit of `rate` bits per second, with bursts of up to 64 KiB.
+    #[inline]
+    #[must_use]
+    pub fn bps(rate: u64) -> Self {
+        Self {
+            rate,
+            per_packet: false,
+            burst: DEFAULT_BURST_BITS,
+        }
+    }
+
+    /// Set the number of packets or bits which can be sent at once, in the unit of the rate. A
+    /// packet larger than the burst is still sent once the bucket is full.
+    #[inline]
+    #[must_use]
+    pub fn burst(mut self, burst: u64) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    /// Packets or bits allowed per second.
+    #[inline]
+    #[must_use]
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Whether the limit counts packets instead of bits.
+    #[inline]
+    #[must_use]
+    pub fn is_per_packet(&self) -> bool {
+        self.per_packet
+    }
+
+    /// Check the limit, which fails with `Error::InvalidArg` if the rate is 0.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.rate == 0 {
+            return Err(Error::InvalidArg);
+        }
+        Ok(())
+    }
+}
+
+/// A token bucket enforcing a `RateLimit`.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    /// The limit enforced.
+    limit: RateLimit,
+    /// When the bucket would be full again, i.e. the theoretical arrival time of GCRA.
+    full_at: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket.
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            full_at: Instant::now(),
+        }
+    }
+
+    /// The limit enforced.
+    pub(crate) fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Packets or bits taken by a frame of `len` bytes.
+    fn cost(&self, len: usize) -> u64 {
+        if self.limit.per_packet {
+            1
+        } else {
+            u64::try_from(len).unwrap_or(u64::MAX).saturating_mul(8)
+        }
+    }
+
+    /// Time taken to send `units` packets or bits at the rate.
+    fn interval(&self, units: u64) -> Duration {
+        let nanos = u128::from(units)
+            .saturating_mul(1_000_000_000)
+            .checked_div(u128::from(self.limit.rate))
+            .unwrap_or(u128::MAX);
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+
+    /// How long to wait from `now` before a frame of `len` bytes conforms to the limit, which is
+    /// zero if it can be sent right now.
+    pub(crate) fn delay(&self, now: Instant, len: usize) -> Duration {
+        let tolerance = self.interval(self.limit.burst.saturating_sub(self.cost(len)));
+        self.full_at
+            .checked_sub(tolerance)
+            .map_or(Duration::ZERO, |earliest| {
+                earliest.saturating_duration_since(now)
+            })
+    }
+
+    /// Take the tokens of a frame of `len` bytes sent at `now`.
+    pub(crate) fn take(&mut self, now: Instant, len: usize) {
+        let interval = self.interval(self.cost(len));
+        let start = self.full_at.max(now);
+        self.full_at = start.checked_add(interval).unwrap_or(start);
+    }
+
+    /// Take the tokens of a frame of `len` bytes if it conforms to the limit at `now`, which
+    /// fails with how long to wait otherwise.
+    pub(crate) fn try_take(
+        &mut self,
+        now: Instant,
+        len: usize,
+    ) -> std::result::Result<(), Duration> {
+        let delay = self.delay(now, len);
+        if delay.is_zero() {
+            self.take(now, len);
+            Ok(())
+        } else {
+            Err(delay)
+        }
+    }
+
+    /// Give back the tokens of a frame of `len` bytes taken but not sent.
+    pub(crate) fn give_back(&mut self, len: usize) {
+        let interval = self.interval(self.cost(len));
+        self.full_at = self.full_at.checked_sub(interval).unwrap_or(self.full_at);
+    }
+}
+
+/// An optional token bucket shared by the tasks sending on a socket.
+#[derive(Debug, Default)]
+pub(crate) struct Shaper(Mutex<Option<TokenBucket>>);
+
+impl Shaper {
+    /// Set the limit, or remove it with `None`.
+    pub(crate) fn set(&self, limit: Option<RateLimit>) -> Result<()> {
+        if let Some(limit) = limit {
+            limit.validate()?;
+        }
+        *self.0.lock().map_err(Error::from)? = limit.map(TokenBucket::new);
+        Ok(())
+    }
+
+    /// The limit enforced, if any.
+    pub(crate) fn limit(&self) -> Result<Option<RateLimit>> {
+        Ok(self
+            .0
+            .lock()
+            .map_err(Error::from)?
+            .as_ref()
+            .map(TokenBucket::limit))
+    }
+
+    /// Take the tokens of a frame of `len` bytes if it conforms to the limit, returning how long
+    /// to wait otherwise.
+    pub(crate) fn try_acquire_or_delay(&self, len: usize) -> Result<Option<Duration>> {
+        match self.0.lock().map_err(Error::from)?.as_mut() {
+            Some(bucket) => Ok(bucket.try_take(Instant::now(), len).err()),
+            None => Ok(None),
+        }
+    }
+
+    /// Take the tokens of a frame of `len` bytes, failing with `Error::RateLimited` if it
+    /// doesn't conform to the limit yet.
+    pub(crate) fn try_acquire(&self, len: usize) -> Result<()> {
+        match self.try_acquire_or_delay(len)? {
+            Some(_delay) => Err(Error::RateLimited),
+            None => Ok(()),
+        }
+    }
+
+    /// Take the tokens of a frame of `len` bytes, waiting until it conforms to the limit.
+    pub(crate) async fn acquire(&self, len: usize) -> Result<()> {
+        while let Some(delay) = self.try_acquire_or_delay(len)? {
+            time::sleep(delay).await;
+        }
+        Ok(())
+    }
+
+    /// Give back the tokens of a frame of `len` bytes taken but not sent.
+    pub(crate) fn give_back(&self, len: usize) -> Result<()> {
+        if let Some(bucket) = self.0.lock().map_err(Error::from)?.as_mut() {
+            bucket.give_back(len);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, Shaper, TokenBucket};
+    use crate::Error;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(RateLimit::pps(1000).burst(4));
+        for _ in 0..4 {
+            bucket.try_take(now, 1500).unwrap();
+        }
+        assert_eq!(bucket.try_take(now, 64), Err(Duration::from_millis(1)));
+        bucket.give_back(1500);
+        bucket.try_take(now, 64).unwrap();
+        let later = now + Duration::from_millis(1);
+        bucket.try_take(later, 64).unwrap();
+        assert!(bucket.try_take(later, 64).is_err());
+
+        // 1500 bytes take 1.2ms at 10 Mbit/s.
+        let mut bucket = TokenBucket::new(RateLimit::bps(10_000_000).burst(0));
+        bucket.try_take(now, 1500).unwrap();
+        assert_eq!(bucket.delay(now, 1500), Duration::from_micros(1200));
+    }
+
+    #[tokio::test]
+    async fn test_shaper() {
+        let shaper = Shaper::default();
+        assert!(matches!(
+            shaper.set(Some(RateLimit::bps(0))).unwrap_err(),
+            Error::InvalidArg
+        ));
+        shaper.try_acquire(1500).unwrap();
+        shaper.set(Some(RateLimit::pps(100).burst(1))).unwrap();
+        assert_eq!(shaper.limit().unwrap(), Some(RateLimit::pps(100).burst(1)));
+        shaper.try_acquire(1500).unwrap();
+        assert!(matches!(
+            shaper.try_acquire(1500).unwrap_err(),
+            Error::RateLimited
+        ));
+        let start = Instant::now();
+        shaper.acquire(1500).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(5));
+        shaper.set(None).unwrap();
+        shaper.try_acquire(1500).unwrap();
+    }
+}
@@ -10,7 +10,19 @@ pub(crate) fn dpdk_setup() {
         eal::Config::new()
             .no_hugepages(true)
             .vdev(Vdev::Null(0))
+            .vdev(Vdev::Null(1))
+            .vdev(Vdev::Null(2))
             .enter()
             .unwrap();
     })
 }
+
+/// An Ethernet frame carrying a UDP datagram from 10.0.0.1:1000 to 10.0.0.2:2000.
+pub(crate) fn udp_frame() -> Vec<u8> {
+    let mut frame = vec![0_u8; 12];
+    frame.extend_from_slice(&[0x08, 0x00]);
+    frame.extend_from_slice(&[0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+    frame.extend_from_slice(&[0x03, 0xe8, 0x07, 0xd0, 0, 8, 0, 0]);
+    frame
+}
@@ -0,0 +1,400 @@
+//! Timers based on `rte_timer`, which measure time with the TSC instead of the tokio time driver.
+//!
+//! The timers are managed by a background thread registered as a non-EAL lcore, started upon the
+//! first use. `Timer` only needs a waker to notify its task, so it works on any executor,
+//! including the runtime of the TX agent, where the tokio time driver is disabled.
+//!
+//! # Examples
+//!
+//! ```
+//! # use async_dpdk::timer::Timer;
+//! # use std::time::Duration;
+//!
+//! # let _ = async_dpdk::eal::Config::new().enter();
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+//! Timer::sleep(Duration::from_millis(1)).await.unwrap();
+//!
+//! let timer = Timer::interval(Duration::from_millis(1)).unwrap();
+//! for _ in 0..3 {
+//!     timer.tick().await;
+//! }
+//! # });
+//! ```
+
+#![allow(non_camel_case_types)]
+
+use crate::{lcore, trace::error, Error, Result};
+use dpdk_sys::{rte_get_tsc_hz, rte_thread_register};
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    os::raw::{c_int, c_uint, c_void},
+    ptr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    thread::{self, Thread},
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+lazy_static! {
+    /// The thread managing the timers, started upon the first use.
+    static ref MANAGER: Mutex<Option<Manager>> = Mutex::new(None);
+}
+
+/// ID of the next timer.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Maximum depth of the skiplist of timers.
+const MAX_SKIPLIST_DEPTH: usize = 10;
+
+/// Timers fired once.
+const SINGLE: c_uint = 0;
+
+/// Timers fired periodically.
+const PERIODICAL: c_uint = 1;
+
+/// Callback of expired timers.
+type rte_timer_cb_t = Option<unsafe extern "C" fn(*mut rte_timer, *mut c_void)>;
+
+/// A timer.
+#[repr(C)]
+struct rte_timer {
+    /// Time when the timer expires in ticks.
+    expire: u64,
+    /// Next timers in the skiplist.
+    sl_next: [*mut rte_timer; MAX_SKIPLIST_DEPTH],
+    /// State and owner lcore of the timer.
+    status: u32,
+    /// Period in ticks, or 0 if the timer is fired once.
+    period: u64,
+    /// Callback.
+    f: rte_timer_cb_t,
+    /// Argument of the callback.
+    arg: *mut c_void,
+}
+
+#[allow(unsafe_code)]
+extern "C" {
+    /// Initialize the timer library.
+    fn rte_timer_subsystem_init() -> c_int;
+
+    /// Initialize a timer.
+    fn rte_timer_init(tim: *mut rte_timer);
+
+    /// Start or restart a timer, which is fired on lcore `tim_lcore`.
+    fn rte_timer_reset(
+        tim: *mut rte_timer,
+        ticks: u64,
+        type_: c_uint,
+        tim_lcore: c_uint,
+        fct: rte_timer_cb_t,
+        arg: *mut c_void,
+    ) -> c_int;
+
+    /// Stop a timer.
+    fn rte_timer_stop(tim: *mut rte_timer) -> c_int;
+
+    /// Fire the expired timers of the current lcore.
+    fn rte_timer_manage() -> c_int;
+
+    /// Ticks until the next timer of the current lcore expires, or a negative errno if there's
+    /// none.
+    fn rte_timer_next_ticks() -> i64;
+}
+
+/// Shared by a `Timer` and its callback.
+#[derive(Debug, Default)]
+struct TimerState {
+    /// Notified when the timer expires.
+    notify: Notify,
+}
+
+/// Requests to the manager thread.
+#[derive(Debug)]
+enum Command {
+    /// Start a timer.
+    Start {
+        /// ID of the timer.
+        id: u64,
+        /// Ticks until the timer expires first.
+        ticks: u64,
+        /// Whether the timer is fired periodically.
+        periodic: bool,
+        /// State notified on expiry.
+        state: Arc<TimerState>,
+    },
+    /// Stop a timer.
+    Stop(u64),
+}
+
+/// The thread managing the timers.
+#[derive(Debug)]
+struct Manager {
+    /// Requests to the thread.
+    sender: Sender<Command>,
+    /// Handle to unpark the thread.
+    thread: Thread,
+}
+
+impl Manager {
+    /// Start the manager thread.
+    fn start() -> Result<Self> {
+        // SAFETY: ffi
+        #[allow(unsafe_code)]
+        let errno = unsafe { rte_timer_subsystem_init() };
+        if errno.saturating_neg() != libc::EALREADY {
+            Error::from_ret(errno, "rte_timer_subsystem_init")?;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::sync_channel(1);
+        let handle = thread::Builder::new()
+            .name("dpdk-timer".to_owned())
+            .spawn(move || {
+                // SAFETY: ffi
+                #[allow(unsafe_code)]
+                let errno = unsafe { rte_thread_register() };
+                let res = if errno < 0 {
+                    Err(Error::from_errno("rte_thread_register"))
+                } else {
+                    Ok(())
+                };
+                let ok = res.is_ok();
+                _ = done_tx.send(res);
+                if ok {
+                    Self::run(&receiver);
+                }
+            })
+            .map_err(|e| {
+                error!("Failed to spawn the timer thread: {e}");
+                Error::NoMem
+            })?;
+        done_rx.recv().map_err(Error::from)??;
+        Ok(Self {
+            sender,
+            thread: handle.thread().clone(),
+        })
+    }
+
+    /// Handle requests and fire expired timers, parking until the next one expires.
+    fn run(receiver: &Receiver<Command>) {
+        let hz = tsc_hz();
+        let lcore_id = lcore::id();
+        let mut timers = HashMap::new();
+        loop {
+            loop {
+                match receiver.try_recv() {
+                    Ok(Command::Start {
+                        id,
+                        ticks,
+                        periodic,
+                        state,
+                    }) => {
+                        let mut tim = Box::new(rte_timer {
+                            expire: 0,
+                            sl_next: [ptr::null_mut(); MAX_SKIPLIST_DEPTH],
+                            status: 0,
+                            period: 0,
+                            f: None,
+                            arg: ptr::null_mut(),
+                        });
+                        let type_ = if periodic { PERIODICAL } else { SINGLE };
+                        let arg = Arc::as_ptr(&state).cast_mut().cast();
+                        // SAFETY: the timer and its state live in `timers` until it's stopped
+                        #[allow(unsafe_code)]
+                        let errno = unsafe {
+                            rte_timer_init(ptr::addr_of_mut!(*tim));
+                            rte_timer_reset(
+                                ptr::addr_of_mut!(*tim),
+                                ticks,
+                                type_,
+                                lcore_id,
+                                Some(on_expire),
+                                arg,
+                            )
+                        };
+                        if let Err(e) = Error::from_ret(errno, "rte_timer_reset") {
+                            error!("Failed to start timer {id}: {e}");
+                        }
+                        let _prev = timers.insert(id, (tim, state));
+                    }
+                    Ok(Command::Stop(id)) => {
+                        if let Some((mut tim, _state)) = timers.remove(&id) {
+                            // SAFETY: the timer is not running out of `rte_timer_manage`
+                            #[allow(unsafe_code)]
+                            let _errno = unsafe { rte_timer_stop(ptr::addr_of_mut!(*tim)) };
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            // SAFETY: the thread is registered as an lcore
+            #[allow(unsafe_code)]
+            let (_errno, next) = unsafe { (rte_timer_manage(), rte_timer_next_ticks()) };
+            match u64::try_from(next) {
+                Ok(0) => {}
+                Ok(ticks) => thread::park_timeout(ticks_to_duration(ticks, hz)),
+                Err(_) => thread::park(),
+            }
+        }
+    }
+}
+
+/// Fire a timer.
+#[allow(unsafe_code)]
+unsafe extern "C" fn on_expire(_tim: *mut rte_timer, arg: *mut c_void) {
+    // SAFETY: the state is kept alive with the timer
+    let state = unsafe { &*arg.cast::<TimerState>() };
+    state.notify.notify_one();
+}
+
+/// The frequency of the TSC.
+fn tsc_hz() -> u64 {
+    // SAFETY: ffi
+    #[allow(unsafe_code)]
+    unsafe {
+        rte_get_tsc_hz()
+    }
+}
+
+/// Convert `duration` to TSC ticks.
+fn duration_to_ticks(duration: Duration, hz: u64) -> Result<u64> {
+    let ticks = duration
+        .as_nanos()
+        .checked_mul(u128::from(hz))
+        .ok_or(Error::Overflow)?
+        .checked_div(1_000_000_000)
+        .ok_or(Error::InvalidArg)?;
+    u64::try_from(ticks).map_err(|_e| Error::Overflow)
+}
+
+/// Convert TSC ticks to a duration.
+fn ticks_to_duration(ticks: u64, hz: u64) -> Duration {
+    let nanos = u128::from(ticks)
+        .saturating_mul(1_000_000_000)
+        .checked_div(u128::from(hz))
+        .unwrap_or(0);
+    Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+}
+
+/// Send `cmd` to the manager thread, starting it if it's not running.
+fn send(cmd: Command) -> Result<()> {
+    let mut manager = MANAGER.lock().map_err(Error::from)?;
+    if manager.is_none() {
+        *manager = Some(Manager::start()?);
+    }
+    let manager = manager.as_ref().ok_or(Error::NotStart)?;
+    manager.sender.send(cmd).map_err(Error::from)?;
+    manager.thread.unpark();
+    Ok(())
+}
+
+/// A timer managed by `rte_timer`, which is stopped when dropped.
+#[derive(Debug)]
+pub struct Timer {
+    /// ID of the timer.
+    id: u64,
+    /// State notified on expiry.
+    state: Arc<TimerState>,
+}
+
+impl Timer {
+    /// Start a timer expiring after `delay`, and then every `delay` if it's `periodic`.
+    fn start(delay: Duration, periodic: bool) -> Result<Self> {
+        let ticks = duration_to_ticks(delay, tsc_hz())?;
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let state = Arc::new(TimerState::default());
+        send(Command::Start {
+            id,
+            ticks,
+            periodic,
+            state: Arc::clone(&state),
+        })?;
+        Ok(Self { id, state })
+    }
+
+    /// Wait until `duration` has elapsed.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::Overflow`: `duration` is too long.
+    /// - The timer thread fails to start, e.g. there's no lcore left for it.
+    #[inline]
+    pub async fn sleep(duration: Duration) -> Result<()> {
+        let timer = Self::start(duration, false)?;
+        timer.tick().await;
+        Ok(())
+    }
+
+    /// Create a timer ticking every `period`, starting after one `period`.
+    ///
+    /// # Errors
+    ///
+    /// Possible reasons:
+    ///
+    /// - `Error::InvalidArg`: `period` is zero.
+    /// - `Error::Overflow`: `period` is too long.
+    /// - The timer thread fails to start, e.g. there's no lcore left for it.
+    #[inline]
+    pub fn interval(period: Duration) -> Result<Self> {
+        if period.is_zero() {
+            return Err(Error::InvalidArg);
+        }
+        Self::start(period, true)
+    }
+
+    /// Wait for the next tick. Ticks missed while nobody waits are merged into one.
+    #[inline]
+    pub async fn tick(&self) {
+        self.state.notify.notified().await;
+    }
+}
+
+impl Drop for Timer {
+    #[inline]
+    fn drop(&mut self) {
+        if let Err(e) = send(Command::Stop(self.id)) {
+            error!("Failed to stop timer {}: {e}", self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{duration_to_ticks, ticks_to_duration, Timer};
+    use crate::test_utils;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_ticks() {
+        let hz = 2_000_000_000;
+        assert_eq!(
+            duration_to_ticks(Duration::from_millis(3), hz).unwrap(),
+            6_000_000
+        );
+        assert_eq!(ticks_to_duration(6_000_000, hz), Duration::from_millis(3));
+        assert!(duration_to_ticks(Duration::MAX, hz).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_timer() {
+        test_utils::dpdk_setup();
+        let start = Instant::now();
+        Timer::sleep(Duration::from_millis(10)).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(10));
+
+        let timer = Timer::interval(Duration::from_millis(5)).unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            timer.tick().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(15));
+        assert!(Timer::interval(Duration::ZERO).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+//! Timestamps of packets, for measuring one-way and round-trip latency through the stack.
+//!
+//! Packets received on ports with `DevConfig::rx_timestamp` enabled are timestamped by the NIC if
+//! it supports timestamp offload, or by the rx agent with the TSC once they are polled otherwise.
+//! The timestamp of a datagram is given by `RecvMeta::rx_timestamp`.
+//!
+//! Datagrams sent with `SendOptions::tx_timestamp` are timestamped with the TSC once the device
+//! takes them, which is reported by `UdpSocket::recv_tx_timestamp`.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use async_dpdk::udp::{SendOptions, UdpSocket};
+//! # async fn ping(socket: UdpSocket) -> async_dpdk::Result<()> {
+//! let opts = SendOptions::new().tx_timestamp();
+//! let _ = socket.send_to_with(b"ping", "10.0.0.2:7", opts).await?;
+//! let sent = socket.recv_tx_timestamp().await?;
+//!
+//! let mut buf = [0; 4];
+//! let (_, _, meta) = socket.recv_from_with_meta(&mut buf).await?;
+//! if let Some(rtt) = meta.rx_timestamp.and_then(|ts| ts.duration_since(sent.timestamp)) {
+//!     println!("round trip in {rtt:?}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    mbuf::{DynField, DynFlag, Mbuf},
+    Error, Result,
+};
+use dpdk_sys::{rte_get_tsc_hz, rte_mbuf, rte_rdtsc};
+use lazy_static::lazy_static;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    time::Duration,
+};
+use tokio::{pin, sync::Notify};
+
+/// Name of the dynamic field of RX timestamps, which is shared with the drivers offloading them.
+const RX_TIMESTAMP_FIELD: &str = "rte_dynfield_timestamp";
+/// Name of the dynamic flag set by the drivers on packets timestamped by the NIC.
+const RX_TIMESTAMP_HW_FLAG: &str = "rte_dynflag_rx_timestamp";
+/// Name of the dynamic flag set on packets timestamped with the TSC.
+const RX_TIMESTAMP_TSC_FLAG: &str = "async_dpdk_rx_tsc";
+/// Name of the dynamic field and flag requesting the completion timestamp of a packet.
+const TX_REPORT_NAME: &str = "async_dpdk_tx_report";
+/// Max number of completion timestamps kept for a socket, beyond which the oldest are dropped.
+const MAX_TX_REPORTS: usize = 1024;
+
+/// Whether any port timestamps received packets.
+static RX_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Whether any completion timestamp is requested, before which sent packets are not checked.
+static TX_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Distinguishes the `TxStamps` of sockets.
+static TX_STAMPS_SEQ: AtomicU32 = AtomicU32::new(0);
+
+lazy_static! {
+    /// The dynamic field and flags of RX timestamps, or `None` if no space is left in `rte_mbuf`.
+    static ref RX_STAMP: Option<RxStamp> = RxStamp::register().ok();
+    /// The dynamic field and flag requesting completion timestamps, or `None` if no space is
+    /// left in `rte_mbuf`.
+    static ref TX_REPORT: Option<(DynField<u64>, DynFlag)> = DynField::register(TX_REPORT_NAME)
+        .and_then(|field| Ok((field, DynFlag::register(TX_REPORT_NAME)?)))
+        .ok();
+    /// Where the completion timestamps are reported, keyed by `TxStamps::key`.
+    static ref TX_STAMPS: RwLock<HashMap<u32, Weak<TxStamps>>> = RwLock::new(HashMap::new());
+}
+
+/// When a packet is received or sent.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Timestamp {
+    /// Taken by the NIC, in ticks of its clock, which is read by `net_dev::read_clock`.
+    Hardware(u64),
+    /// Taken by the stack, in TSC cycles.
+    Tsc(u64),
+}
+
+impl Timestamp {
+    /// The current time in TSC cycles, to compare with the timestamps taken by the stack.
+    #[inline]
+    #[must_use]
+    pub fn now() -> Self {
+        Self::Tsc(tsc())
+    }
+
+    /// The time elapsed from `earlier` to this timestamp, or `None` if `earlier` is later, or
+    /// either of them is taken by a NIC, whose clock ticks at a rate unknown to the stack.
+    #[inline]
+    #[must_use]
+    pub fn duration_since(&self, earlier: Self) -> Option<Duration> {
+        let (Self::Tsc(now), Self::Tsc(earlier)) = (*self, earlier) else {
+            return None;
+        };
+        // SAFETY: ffi
+        #[allow(unsafe_code)]
+        let hz = unsafe { rte_get_tsc_hz() };
+        let nanos = u128::from(now.checked_sub(earlier)?)
+            .saturating_mul(1_000_000_000)
+            .checked_div(u128::from(hz))?;
+        Some(Duration::from_nanos(
+            u64::try_from(nanos).unwrap_or(u64::MAX),
+        ))
+    }
+}
+
+/// The completion timestamp of a datagram sent with `SendOptions::tx_timestamp`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxTimestamp {
+    /// Counts the datagrams of the socket sent with the option from 0, in the order of sending.
+    pub id: u32,
+    /// When the device took the datagram.
+    pub timestamp: Timestamp,
+}
+
+/// The dynamic field holding RX timestamps, and the flags telling their sources.
+#[derive(Debug, Clone, Copy)]
+struct RxStamp {
+    /// The timestamp.
+    field: DynField<u64>,
+    /// Set by the drivers if the timestamp is taken by the NIC.
+    hw: DynFlag,
+    /// Set by the rx agent if the timestamp is taken with the TSC.
+    tsc: DynFlag,
+}
+
+impl RxStamp {
+    /// Register the field and the flags, or get them if they are registered by the drivers.
+    fn register() -> Result<Self> {
+        Ok(Self {
+            field: DynField::register(RX_TIMESTAMP_FIELD)?,
+            hw: DynFlag::register(RX_TIMESTAMP_HW_FLAG)?,
+            tsc: DynFlag::register(RX_TIMESTAMP_TSC_FLAG)?,
+        })
+    }
+}
+
+/// Current TSC cycles.
+pub(crate) fn tsc() -> u64 {
+    // SAFETY: ffi
+    #[allow(unsafe_code)]
+    unsafe {
+        rte_rdtsc()
+    }
+}
+
+/// Start looking for RX timestamps in received packets, once a port timestamps them.
+pub(crate) fn enable_rx() {
+    RX_ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Timestamp `m` received at `tsc`, unless it's timestamped by the NIC.
+pub(crate) fn stamp_rx(m: &mut Mbuf, tsc: u64) {
+    if let Some(stamp) = *RX_STAMP {
+        if !m.dynflag(stamp.hw) {
+            m.set_dynfield(stamp.field, tsc);
+            m.set_dynflag(stamp.tsc, true);
+        }
+    }
+}
+
+/// The RX timestamp of `m`, if any.
+pub(crate) fn rx_timestamp(m: &Mbuf) -> Option<Timestamp> {
+    if !RX_ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let stamp = (*RX_STAMP)?;
+    if m.dynflag(stamp.hw) {
+        Some(Timestamp::Hardware(m.dynfield(stamp.field)))
+    } else if m.dynflag(stamp.tsc) {
+        Some(Timestamp::Tsc(m.dynfield(stamp.field)))
+    } else {
+        None
+    }
+}
+
+/// Record `timestamp` in `m` as its RX timestamp, or clear the RX timestamp of `m` if `None`.
+pub(crate) fn set_rx_timestamp(m: &mut Mbuf, timestamp: Option<Timestamp>) {
+    if !RX_ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(stamp) = *RX_STAMP else {
+        return;
+    };
+    let (value, hw) = match timestamp {
+        Some(Timestamp::Hardware(value)) => (Some(value), true),
+        Some(Timestamp::Tsc(value)) => (Some(value), false),
+        None => (None, false),
+    };
+    if let Some(value) = value {
+        m.set_dynfield(stamp.field, value);
+    }
+    m.set_dynflag(stamp.hw, value.is_some() && hw);
+    m.set_dynflag(stamp.tsc, value.is_some() && !hw);
+}
+
+/// The completion timestamps of the datagrams sent by a socket.
+#[derive(Debug)]
+pub(crate) struct TxStamps {
+    /// Identifies the socket in the requests.
+    key: u32,
+    /// The id of the next datagram requesting its completion timestamp.
+    next_id: AtomicU32,
+    /// Timestamps reported and not taken yet.
+    reports: Mutex<VecDeque<TxTimestamp>>,
+    /// Wakes the tasks waiting for a report.
+    notify: Notify,
+}
+
+impl TxStamps {
+    /// Create the reports of a socket, which the tx agents find by its key.
+    pub(crate) fn new() -> Result<Arc<Self>> {
+        let stamps = Arc::new(Self {
+            key: TX_STAMPS_SEQ.fetch_add(1, Ordering::Relaxed),
+            next_id: AtomicU32::new(0),
+            reports: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+        });
+        let _prev = TX_STAMPS
+            .write()
+            .map_err(Error::from)?
+            .insert(stamps.key, Arc::downgrade(&stamps));
+        Ok(stamps)
+    }
+
+    /// Request the completion timestamp of `m`, returning the id it's reported with.
+    ///
+    /// It fails with `Error::NoMem` if no space is left in `rte_mbuf` for the request.
+    pub(crate) fn request(&self, m: &mut Mbuf) -> Result<u32> {
+        let (field, flag) = (*TX_REPORT).ok_or(Error::NoMem)?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        m.set_dynfield(field, u64::from(self.key).wrapping_shl(32) | u64::from(id));
+        m.set_dynflag(flag, true);
+        TX_REQUESTED.store(true, Ordering::Relaxed);
+        Ok(id)
+    }
+
+    /// Take the earliest completion timestamp, waiting for one if none is reported yet.
+    pub(crate) async fn recv(&self) -> Result<TxTimestamp> {
+        loop {
+            let notified = self.notify.notified();
+            pin!(notified);
+            // Registered before checking, so that a report right after is not missed.
+            _ = notified.as_mut().enable();
+            if let Some(report) = self.reports.lock().map_err(Error::from)?.pop_front() {
+                return Ok(report);
+            }
+            notified.await;
+        }
+    }
+
+    /// Report the completion timestamp of the datagram of `id`.
+    fn report(&self, id: u32, timestamp: Timestamp) -> Result<()> {
+        let mut reports = self.reports.lock().map_err(Error::from)?;
+        if reports.len() >= MAX_TX_REPORTS {
+            let _dropped = reports.pop_front();
+        }
+        reports.push_back(TxTimestamp { id, timestamp });
+        drop(reports);
+        self.notify.notify_waiters();
+        Ok(())
+    }
+}
+
+impl Drop for TxStamps {
+    fn drop(&mut self) {
+        if let Ok(mut stamps) = TX_STAMPS.write() {
+            let _stamps = stamps.remove(&self.key);
+        }
+    }
+}
+
+/// The request for the completion timestamp of a sent packet, if any.
+#[allow(unsafe_code)]
+pub(crate) fn tx_request(m: *mut rte_mbuf) -> Option<u64> {
+    if !TX_REQUESTED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let (field, flag) = (*TX_REPORT)?;
+    // SAFETY: `m` is valid until it's sent, and the field is registered at `offset` of every
+    // `rte_mbuf`
+    unsafe {
+        ((*m).ol_flags & 1_u64.wrapping_shl(flag.bit()) != 0).then(|| {
+            m.cast::<u8>()
+                .add(field.offset())
+                .cast::<u64>()
+                .read_unaligned()
+        })
+    }
+}
+
+/// Move `request` of a packet split into `pkts` to the last one of them, so that the packet is
+/// reported once all of them are sent.
+#[allow(unsafe_code)]
+pub(crate) fn pass_tx_request(request: Option<u64>, pkts: &[*mut rte_mbuf]) {
+    let (Some(request), Some((field, flag))) = (request, *TX_REPORT) else {
+        return;
+    };
+    let mask = 1_u64.wrapping_shl(flag.bit());
+    for (i, &m) in pkts.iter().enumerate() {
+        let last = i.wrapping_add(1) == pkts.len();
+        // SAFETY: the split packets are valid until sent, and the field is registered at
+        // `offset` of every `rte_mbuf`
+        unsafe {
+            if last {
+                (*m).ol_flags |= mask;
+                m.cast::<u8>()
+                    .add(field.offset())
+                    .cast::<u64>()
+                    .write_unaligned(request);
+            } else {
+                (*m).ol_flags &= !mask;
+            }
+        }
+    }
+}
+
+/// The requests of the packets in `pkts` along with their indexes, before they are sent.
+pub(crate) fn tx_requests(pkts: &[*mut rte_mbuf]) -> Vec<(usize, u64)> {
+    if !TX_REQUESTED.load(Ordering::Relaxed) {
+        return Vec::new();
+    }
+    pkts.iter()
+        .enumerate()
+        .filter_map(|(i, &m)| Some((i, tx_request(m)?)))
+        .collect()
+}
+
+/// Report the completion of the packets of `requests` with indexes less than `sent`, which are
+/// taken by the device just now.
+pub(crate) fn tx_complete(requests: &[(usize, u64)], sent: usize) {
+    let timestamp = Timestamp::now();
+    let Ok(stamps) = TX_STAMPS.read() else {
+        return;
+    };
+    for &(_, request) in requests.iter().take_while(|&&(i, _)| i < sent) {
+        #[allow(clippy::cast_possible_truncation)] // the key and the id of 32 bits each
+        let (key, id) = (request.wrapping_shr(32) as u32, request as u32);
+        if let Some(stamps) = stamps.get(&key).and_then(Weak::upgrade) {
+            let _res = stamps.report(id, timestamp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Timestamp, TxStamps};
+    use crate::{
+        mbuf::Mbuf,
+        mempool::{Mempool, PktMempool},
+        test_utils::dpdk_setup,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_duration_since() {
+        dpdk_setup();
+        let earlier = Timestamp::now();
+        std::thread::sleep(Duration::from_millis(2));
+        let later = Timestamp::now();
+        assert!(later.duration_since(earlier).unwrap() >= Duration::from_millis(1));
+        assert_eq!(earlier.duration_since(later), None);
+        assert_eq!(later.duration_since(Timestamp::Hardware(0)), None);
+    }
+
+    #[tokio::test]
+    async fn test_tx_stamps() {
+        dpdk_setup();
+        let mp = PktMempool::create("test_tx_stamps", 8).unwrap();
+        let stamps = TxStamps::new().unwrap();
+        let first = Mbuf::new(&mp).unwrap();
+        let mut second = Mbuf::new(&mp).unwrap();
+        assert_eq!(super::tx_request(first.as_ptr()), None);
+        assert_eq!(stamps.request(&mut second).unwrap(), 0);
+
+        let ptrs = [first.as_ptr(), second.as_ptr()];
+        let requests = super::tx_requests(&ptrs);
+        let request = super::tx_request(second.as_ptr()).unwrap();
+        assert_eq!(requests, [(1, request)]);
+        // Only packets taken by the device are reported.
+        super::tx_complete(&requests, 1);
+        assert!(stamps.reports.lock().unwrap().is_empty());
+        super::tx_complete(&requests, 2);
+        let report = stamps.recv().await.unwrap();
+        assert_eq!(report.id, 0);
+        assert!(matches!(report.timestamp, Timestamp::Tsc(_)));
+
+        // A split packet is reported with its last part.
+        super::pass_tx_request(Some(request), &[second.as_ptr(), first.as_ptr()]);
+        assert_eq!(super::tx_request(second.as_ptr()), None);
+        assert_eq!(super::tx_request(first.as_ptr()), Some(request));
+    }
+}
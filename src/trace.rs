@@ -0,0 +1,37 @@
+//! Diagnostics emitted through `tracing` with the `tracing` feature, or `log` otherwise.
+//!
+//! Events are logged with the macros re-exported here. Spans covering the handling of packets
+//! and socket operations are only created with the `tracing` feature, and cost nothing without
+//! it.
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use log::{debug, error, info, trace, warn};
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, error, info, trace, warn};
+
+/// Enter a trace-level span named `$name` with the given fields, e.g.
+/// `enter_span!("rx_burst", port = port_id, queue = queue_id)`, which lasts until the end of
+/// the enclosing block.
+macro_rules! enter_span {
+    ($name:expr $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::trace_span!($name $(, $($fields)*)?).entered();
+    };
+}
+
+/// Instrument the future `$fut` with a debug-level span named `$name` with the given fields,
+/// which is entered each time the future is polled.
+macro_rules! instrument {
+    ($fut:expr, $name:expr $(, $($fields:tt)*)?) => {{
+        #[cfg(feature = "tracing")]
+        let fut = ::tracing::Instrument::instrument(
+            $fut,
+            ::tracing::debug_span!($name $(, $($fields)*)?),
+        );
+        #[cfg(not(feature = "tracing"))]
+        let fut = $fut;
+        fut
+    }};
+}
+
+pub(crate) use {enter_span, instrument};
@@ -0,0 +1,197 @@
+/// Test crypto devices, and the ESP transform on top of them.
+use async_dpdk::{
+    crypto::{
+        esp::{Esp, SecurityAssociation},
+        AeadAlgo, AeadOp, AeadXform, CryptoDev, OpStatus,
+    },
+    eal::{self, *},
+    mbuf::Mbuf,
+    mempool::{Mempool, PktMempool},
+    Error,
+};
+use std::{net::Ipv4Addr, sync::Once};
+
+static SETUP: Once = Once::new();
+
+fn dpdk_setup() {
+    SETUP.call_once(|| {
+        env_logger::init();
+        eal::Config::new()
+            .no_hugepages(true)
+            .no_pci(true)
+            .vdev(Vdev::CryptoOpenssl(0))
+            .vdev(Vdev::CryptoOpenssl(1))
+            .enter()
+            .unwrap();
+    })
+}
+
+/// An IPv4 packet from 10.0.0.1 to 10.0.0.2, with a payload of `len` bytes.
+fn ipv4(mp: &PktMempool, len: u8) -> Mbuf {
+    let mut m = Mbuf::new(mp).unwrap();
+    let total = 20 + len;
+    let data = m.append(usize::from(total)).unwrap();
+    data[..20].copy_from_slice(&[
+        0x45, 0, 0, total, 0, 1, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+    ]);
+    for (byte, value) in data[20..].iter_mut().zip(0..) {
+        *byte = value;
+    }
+    m
+}
+
+#[test]
+fn test_crypto_dev() {
+    dpdk_setup();
+    let mp = PktMempool::create("crypto_test", 16).unwrap();
+    let dev = CryptoDev::open("crypto_openssl0").unwrap();
+    assert!(matches!(
+        CryptoDev::open("crypto_openssl0"),
+        Err(Error::Busy)
+    ));
+    assert!(dev.info().max_queue_pairs > 0);
+    assert!(matches!(dev.configure(0), Err(Error::InvalidArg)));
+    dev.configure(1).unwrap();
+    let mut qp = dev.queue_pair_setup(0).unwrap();
+    assert!(matches!(dev.queue_pair_setup(0), Err(Error::Exists)));
+    dev.start().unwrap();
+
+    let key = [7; 16];
+    let encrypt = AeadXform::new(AeadOp::Encrypt, AeadAlgo::AesGcm, &key).aad_len(8);
+    let decrypt = AeadXform::new(AeadOp::Decrypt, AeadAlgo::AesGcm, &key).aad_len(8);
+    assert!(matches!(
+        dev.aead_session(&encrypt.clone().iv_len(17)),
+        Err(Error::InvalidArg)
+    ));
+    let bad_key = AeadXform::new(AeadOp::Encrypt, AeadAlgo::AesGcm, &[7; 7]);
+    assert!(!dev.supports(&bad_key));
+    assert!(matches!(
+        dev.aead_session(&bad_key),
+        Err(Error::NotSupported)
+    ));
+    let sealer = dev.aead_session(&encrypt).unwrap();
+    let opener = dev.aead_session(&decrypt).unwrap();
+    assert_eq!(sealer.op(), AeadOp::Encrypt);
+    assert_eq!(sealer.digest_len(), 16);
+
+    // Encrypt 64 bytes, with the digest in the last 16 bytes.
+    let mut m = Mbuf::new(&mp).unwrap();
+    let data = m.append(64 + 16).unwrap();
+    data.fill(0x5a);
+    let plain = m.data_slice()[..64].to_vec();
+    let (aad, iv) = ([1; 8], [2; 12]);
+    let mut op = qp.op(&sealer, m).unwrap();
+    assert!(matches!(
+        op.aead(0, 64, 64, &aad, &[2; 8]),
+        Err(Error::InvalidArg)
+    ));
+    assert!(matches!(
+        op.aead(0, 80, 64, &aad, &iv),
+        Err(Error::OutOfRange)
+    ));
+    op.aead(0, 64, 64, &aad, &iv).unwrap();
+    let mut ops = qp.process(vec![op]);
+    assert_eq!(ops.len(), 1);
+    assert_eq!(qp.in_flight(), 0);
+    let op = ops.pop().unwrap();
+    assert_eq!(op.status(), OpStatus::Success);
+    let sealed = op.into_mbuf().unwrap();
+    assert_ne!(sealed.data_slice()[..64], plain[..]);
+
+    // Decrypted back, while a corrupted packet fails the authentication.
+    let mut corrupted = Mbuf::new(&mp).unwrap();
+    let data = corrupted.append(64 + 16).unwrap();
+    data.copy_from_slice(sealed.data_slice());
+    data[0] ^= 1;
+    let mut ops = Vec::new();
+    for m in [sealed, corrupted] {
+        let mut op = qp.op(&opener, m).unwrap();
+        op.aead(0, 64, 64, &aad, &iv).unwrap();
+        ops.push(op);
+    }
+    let ops = qp.process(ops);
+    let status: Vec<_> = ops.iter().map(|op| op.status()).collect();
+    assert_eq!(status, [OpStatus::Success, OpStatus::AuthFailed]);
+    let opened = ops.into_iter().next().unwrap().into_mbuf().unwrap();
+    assert_eq!(opened.data_slice()[..64], plain[..]);
+    drop(opened);
+
+    drop((sealer, opener, qp, dev));
+    assert_eq!(mp.in_use(), 0);
+    // Closed once dropped.
+    drop(CryptoDev::open("crypto_openssl0").unwrap());
+}
+
+#[test]
+fn test_esp() {
+    dpdk_setup();
+    let mp = PktMempool::create("esp_test", 16).unwrap();
+    let dev = CryptoDev::open("crypto_openssl1").unwrap();
+    dev.configure(1).unwrap();
+    let mut qp = dev.queue_pair_setup(0).unwrap();
+    dev.start().unwrap();
+
+    let local = Ipv4Addr::new(192, 168, 0, 1);
+    let remote = Ipv4Addr::new(192, 168, 0, 2);
+    let sa = || {
+        SecurityAssociation::new(0x1000, AeadAlgo::AesGcm, &[0x11; 20])
+            .unwrap()
+            .tunnel(local, remote)
+    };
+    let mut outbound = Esp::outbound(&dev, sa()).unwrap();
+    let mut inbound = Esp::inbound(&dev, sa()).unwrap();
+    assert!(outbound.is_outbound());
+    assert!(!inbound.is_outbound());
+
+    let inner: Vec<_> = [3, 32]
+        .into_iter()
+        .map(|len| ipv4(&mp, len).data_slice().to_vec())
+        .collect();
+    let encapsulated = outbound.process(&mut qp, vec![ipv4(&mp, 3), ipv4(&mp, 32)]);
+    assert_eq!(encapsulated.len(), 2);
+    assert_eq!(outbound.stats().packets, 2);
+    for m in &encapsulated {
+        let data = m.data_slice();
+        // ESP in an outer IPv4 header to `remote`.
+        assert_eq!(data[9], 50);
+        assert_eq!(data[16..20], remote.octets());
+        assert_eq!(data[20..24], 0x1000_u32.to_be_bytes());
+        assert_eq!(data.len() % 4, 0);
+    }
+    let copies: Vec<_> = encapsulated
+        .iter()
+        .map(|m| m.data_slice().to_vec())
+        .collect();
+
+    let decapsulated = inbound.process(&mut qp, encapsulated);
+    let decapsulated: Vec<_> = decapsulated
+        .iter()
+        .map(|m| m.data_slice().to_vec())
+        .collect();
+    assert_eq!(decapsulated, inner);
+    assert_eq!(inbound.stats().packets, 2);
+
+    // Replayed and corrupted packets are dropped.
+    let copy = |data: &[u8]| {
+        let mut m = Mbuf::new(&mp).unwrap();
+        m.append(data.len()).unwrap().copy_from_slice(data);
+        m
+    };
+    let fresh = outbound.process(&mut qp, vec![ipv4(&mp, 8)]);
+    let mut corrupted = copy(fresh[0].data_slice());
+    let last = corrupted.data_slice().len() - 1;
+    corrupted.data_slice_mut()[last] ^= 1;
+    let mut pkts = vec![copy(&copies[0]), corrupted];
+    pkts.extend(fresh);
+    let decapsulated = inbound.process(&mut qp, pkts);
+    assert_eq!(decapsulated.len(), 1);
+    assert_eq!(decapsulated[0].data_slice().len(), 28);
+    let stats = inbound.stats();
+    assert_eq!(stats.packets, 3);
+    assert_eq!(stats.replayed, 1);
+    assert_eq!(stats.auth_failed, 1);
+    drop(decapsulated);
+
+    drop((outbound, inbound, qp, dev));
+    assert_eq!(mp.in_use(), 0);
+}
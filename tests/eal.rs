@@ -0,0 +1,60 @@
+/// Test leaving the environment and entering it again.
+use async_dpdk::{
+    eal::{self, *},
+    net_dev,
+    udp::UdpSocket,
+    Error,
+};
+use std::time::Duration;
+use tokio::time;
+
+fn config() -> eal::Config {
+    eal::Config::new()
+        .no_hugepages(true)
+        .no_pci(true)
+        .vdev(Vdev::Ring(0))
+        .max_queues(1)
+        .device_probe(&["10.2.6.0"])
+        .unwrap()
+}
+
+async fn echo() {
+    let server = UdpSocket::bind("10.2.6.0:1234").unwrap();
+    let client = UdpSocket::bind("10.2.6.0:0").unwrap();
+    let sz = client.send_to(b"hello", "10.2.6.0:1234").await.unwrap();
+    assert_eq!(sz, 5);
+    let mut buffer = [0u8; 8];
+    let (sz, from) = time::timeout(Duration::from_secs(1), server.recv_from(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buffer[..sz], b"hello");
+    assert_eq!(from, client.local_addr());
+}
+
+#[tokio::test]
+async fn test_shutdown_enter() {
+    env_logger::init();
+    // Invalid settings, and devices probed in a secondary process, are rejected before EAL is
+    // initialized.
+    assert!(matches!(
+        config().rx_burst(0).enter(),
+        Err(Error::InvalidArg)
+    ));
+    assert!(matches!(
+        config().process_type(ProcessType::Secondary).enter(),
+        Err(Error::Secondary)
+    ));
+    config().enter().unwrap();
+    net_dev::device_start_all().unwrap();
+    echo().await;
+    eal::shutdown().unwrap();
+    assert!(eal::shutdown().is_err());
+
+    // The device removed is probed again, with the same port id.
+    config().enter().unwrap();
+    assert!(config().enter().is_err());
+    net_dev::device_start_all().unwrap();
+    echo().await;
+    eal::shutdown().unwrap();
+}
@@ -0,0 +1,132 @@
+/// Test event devices, and the pipeline processing packets on event workers.
+use async_dpdk::{
+    eal::{self, *},
+    eventdev::{Event, EventDev, Pipeline, PipelineConfig, SchedType},
+    mbuf::Mbuf,
+    mempool::{Mempool, PktMempool},
+    net_dev,
+    udp::UdpSocket,
+    Error, ErrorKind,
+};
+use std::{sync::Once, thread, time::Duration};
+use tokio::time;
+
+static SETUP: Once = Once::new();
+
+fn dpdk_setup() {
+    SETUP.call_once(|| {
+        env_logger::init();
+        eal::Config::new()
+            .no_hugepages(true)
+            .no_pci(true)
+            .corelist("0-1")
+            .unwrap()
+            .service_corelist("1")
+            .unwrap()
+            .vdev(Vdev::Ring(0))
+            .vdev(Vdev::EventSw(0))
+            .vdev(Vdev::EventSw(1))
+            .max_queues(1)
+            .device_probe(&["10.2.7.0"])
+            .unwrap()
+            .enter()
+            .unwrap();
+    })
+}
+
+async fn echo(server: &UdpSocket, client: &UdpSocket) {
+    let sz = client.send_to(b"hello", "10.2.7.0:1234").await.unwrap();
+    assert_eq!(sz, 5);
+    let mut buffer = [0u8; 8];
+    let (sz, from) = time::timeout(Duration::from_secs(1), server.recv_from(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buffer[..sz], b"hello");
+    assert_eq!(from, client.local_addr());
+}
+
+#[test]
+fn test_eventdev() {
+    dpdk_setup();
+    let mp = PktMempool::create("eventdev_test", 16).unwrap();
+    assert_eq!(
+        EventDev::open("event_none").unwrap_err().kind(),
+        ErrorKind::NoDev
+    );
+    let dev = EventDev::open("event_sw1").unwrap();
+    assert!(matches!(EventDev::open("event_sw1"), Err(Error::Busy)));
+    let info = dev.info().unwrap();
+    assert!(dev.configure(1, info.max_ports.saturating_add(1)).is_err());
+    dev.configure(1, 2).unwrap();
+    dev.queue_setup(0, SchedType::Atomic).unwrap();
+    let mut producer = dev.port_setup(0).unwrap();
+    let mut consumer = dev.port_setup(1).unwrap();
+    consumer.link(&[0]).unwrap();
+    // The main lcore is not a service lcore.
+    assert!(matches!(dev.run_scheduler(0), Err(Error::InvalidArg)));
+    dev.run_scheduler(1).unwrap();
+    assert!(matches!(dev.run_scheduler(1), Err(Error::Already)));
+    dev.start().unwrap();
+
+    let mut events: Vec<_> = [1, 2, 1]
+        .into_iter()
+        .map(|flow| Event::new(Mbuf::new(&mp).unwrap(), 0).flow_id(flow))
+        .collect();
+    assert_eq!(producer.enqueue(&mut events), 3);
+    assert!(events.is_empty());
+    for _ in 0..1000 {
+        let _n = consumer.dequeue(&mut events, 32);
+        if events.len() == 3 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    let mut flows: Vec<_> = events.iter().map(Event::flow).collect();
+    flows.sort_unstable();
+    assert_eq!(flows, [1, 1, 2]);
+    drop(events);
+    drop((producer, consumer, dev));
+    assert_eq!(mp.in_use(), 0);
+    // Closed once dropped, along with its ports.
+    drop(EventDev::open("event_sw1").unwrap());
+}
+
+#[tokio::test]
+async fn test_pipeline() {
+    dpdk_setup();
+    let config = PipelineConfig::new("event_sw0").workers(2).scheduler(1);
+    // No scheduler for a device scheduling events in software.
+    assert!(matches!(
+        Pipeline::start(PipelineConfig::new("event_sw0")),
+        Err(Error::InvalidArg)
+    ));
+    net_dev::device_start_all().unwrap();
+    let pipeline = Pipeline::start(config.clone()).unwrap();
+    assert!(matches!(
+        Pipeline::start(config.clone()),
+        Err(Error::Already)
+    ));
+    let server = UdpSocket::bind("10.2.7.0:1234").unwrap();
+    let client = UdpSocket::bind("10.2.7.0:0").unwrap();
+    // The packets received by the RxAgent are injected into the pipeline, and delivered by the
+    // workers.
+    for _ in 0..3 {
+        echo(&server, &client).await;
+    }
+    let stats = pipeline.stats();
+    assert!(stats.injected > 0);
+    assert!(stats.processed > 0);
+    assert_eq!(stats.rejected, 0);
+    pipeline.stop().unwrap();
+
+    // The RxAgent processes the packets by itself once the pipeline stops.
+    echo(&server, &client).await;
+    // The ports are given back by the RxAgent, so that the device is closed and started again.
+    let pipeline = Pipeline::start(config).unwrap();
+    echo(&server, &client).await;
+    assert!(pipeline.stats().processed > 0);
+    drop(pipeline);
+    echo(&server, &client).await;
+    net_dev::device_stop_all().unwrap();
+}
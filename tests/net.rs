@@ -2,6 +2,7 @@
 use async_dpdk::{
     eal::{self, *},
     net_dev,
+    raw::RawSocket,
     udp::UdpSocket,
 };
 use std::{env, sync::Once, time::Duration};
@@ -17,8 +18,10 @@ fn dpdk_setup() {
             .no_hugepages(true)
             .no_pci(true)
             .vdev(Vdev::Ring(0))
+            .vdev(Vdev::Ring(1))
             .max_queues(1)
-            .device_probe(&["10.2.3.0"])
+            .udp_rx_checksum(true)
+            .device_probe(&["10.2.3.0", "fd00::1"])
             .unwrap()
             .enter()
             .unwrap();
@@ -138,4 +141,1211 @@ mod test_fragmentation {
         server.await.unwrap();
         net_dev::device_stop_all().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_stats() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1266").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let reassembled = net_dev::frag_stats().reassembled;
+        let buffer = [2u8; LEN];
+        let sz = client.send_to(&buffer[..], "10.2.3.0:1266").await.unwrap();
+        assert_eq!(sz, LEN);
+        let mut recv_buf = [0u8; LEN];
+        let (sz, _addr) = server
+            .recv_from_timeout(&mut recv_buf, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(sz, LEN);
+        // Counted over all agents, along with the datagrams of other tests.
+        assert!(net_dev::frag_stats().reassembled > reassembled);
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_tcp {
+    use super::*;
+    use async_dpdk::tcp::{TcpListener, TcpStream};
+    use std::net::IpAddr;
+
+    const MSG: &str = "this is client message";
+    const ACK: &str = "this is ack message";
+
+    async fn server() {
+        let listener = TcpListener::bind("10.2.3.0:1234").unwrap();
+        let (stream, client_addr) = listener.accept().await.unwrap();
+        assert_eq!(client_addr.ip(), IpAddr::from([10, 2, 3, 0]));
+        let mut buffer = [0u8; 30];
+        let sz = stream.read(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        let sz = stream.write(ACK.as_bytes()).await.unwrap();
+        assert_eq!(sz, ACK.len());
+        // The client closes the connection.
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), 0);
+    }
+
+    async fn client() {
+        let stream = TcpStream::connect("10.2.3.0:1234").await.unwrap();
+        let sz = stream.write(MSG.as_bytes()).await.unwrap();
+        assert_eq!(sz, MSG.len());
+        let mut buffer = [0u8; 30];
+        let sz = stream.read(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..sz], ACK.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = task::spawn(server());
+        time::sleep(Duration::from_millis(5)).await;
+        client().await;
+        server.await.unwrap();
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_ipv6 {
+    use super::*;
+    use std::net::SocketAddr;
+
+    const MSG: &str = "this is client message";
+    const LEN: usize = 2000; // > Ethernet MTU
+
+    async fn server() {
+        let socket = UdpSocket::bind("[::]:1234").unwrap();
+        let mut buffer = [0u8; LEN];
+        let (sz, client_addr) = socket.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        let (sz, _addr) = socket.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(sz, LEN);
+        let sz = socket.send_to(MSG.as_bytes(), client_addr).await.unwrap();
+        assert_eq!(sz, MSG.len());
+    }
+
+    async fn client() {
+        let socket = UdpSocket::bind("[fd00::1]:0").unwrap();
+        let mut buffer = [0u8; LEN];
+        let _sz = socket
+            .send_to(MSG.as_bytes(), "[fd00::1]:1234")
+            .await
+            .unwrap();
+        let sz = socket.send_to(&buffer, "[fd00::1]:1234").await.unwrap();
+        assert_eq!(sz, LEN);
+        let (sz, server_addr) = socket.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        assert_eq!(server_addr, "[fd00::1]:1234".parse::<SocketAddr>().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = task::spawn(server());
+        time::sleep(Duration::from_millis(5)).await;
+        client().await;
+        server.await.unwrap();
+        net_dev::device_stop_all().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unspecified_source() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("[fd00::1]:1267").unwrap();
+        let client = UdpSocket::bind("[::]:0").unwrap();
+        let _sz = client
+            .send_to(MSG.as_bytes(), "[fd00::1]:1267")
+            .await
+            .unwrap();
+        let mut buffer = [0u8; LEN];
+        let (sz, client_addr) = server
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        // Sent from the address of the device, rather than the unspecified one bound to.
+        assert_eq!(
+            client_addr,
+            SocketAddr::new("fd00::1".parse().unwrap(), client.local_addr().port())
+        );
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_connected {
+    use super::*;
+
+    const MSG: &str = "this is client message";
+    const ACK: &str = "this is ack message";
+
+    async fn server() {
+        let socket = UdpSocket::bind("10.2.3.0:1234").unwrap();
+        let mut buffer = [0u8; 30];
+        let (sz, client_addr) = socket.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        let _sz = socket.send_to(ACK.as_bytes(), client_addr).await.unwrap();
+    }
+
+    async fn client() {
+        let socket = UdpSocket::bind("10.2.3.0:1235").unwrap();
+        let mut buffer = [0u8; 30];
+        assert!(socket.send(MSG.as_bytes()).await.is_err());
+        socket.connect("10.2.3.0:1234").await.unwrap();
+
+        // Datagrams from other addresses are dropped.
+        let stranger = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let _sz = stranger
+            .send_to(MSG.as_bytes(), "10.2.3.0:1235")
+            .await
+            .unwrap();
+
+        let sz = socket.send(MSG.as_bytes()).await.unwrap();
+        assert_eq!(sz, MSG.len());
+        let sz = socket.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..sz], ACK.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = task::spawn(server());
+        time::sleep(Duration::from_millis(5)).await;
+        client().await;
+        server.await.unwrap();
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_zero_copy {
+    use super::*;
+
+    const MSG: &str = "this is client message";
+
+    async fn server() {
+        let socket = UdpSocket::bind("10.2.3.0:1234").unwrap();
+        let (m, _addr) = socket.recv_mbuf().await.unwrap();
+        assert_eq!(m.pkt_len(), MSG.len());
+        let data: Vec<u8> = m.iter().flat_map(|seg| seg.data_slice().to_vec()).collect();
+        assert_eq!(&data[..], MSG.as_bytes());
+    }
+
+    async fn client() {
+        let socket = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let mut m = socket.alloc_mbuf().unwrap();
+        m.append(MSG.len()).unwrap().copy_from_slice(MSG.as_bytes());
+        let sz = socket.send_mbuf_to(m, "10.2.3.0:1234").await.unwrap();
+        assert_eq!(sz, MSG.len());
+    }
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = task::spawn(server());
+        time::sleep(Duration::from_millis(5)).await;
+        client().await;
+        server.await.unwrap();
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_raw {
+    use super::*;
+    use std::net::IpAddr;
+
+    const ETHER_TYPE: u16 = 0x88b5;
+    const MSG: &str = "this is raw message";
+
+    fn frame() -> Vec<u8> {
+        let mut frame = vec![0xff; 12];
+        frame.extend_from_slice(&ETHER_TYPE.to_be_bytes());
+        frame.extend_from_slice(MSG.as_bytes());
+        frame
+    }
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let addr = IpAddr::from([10, 2, 3, 0]);
+        let socket = RawSocket::bind(addr, Some(ETHER_TYPE)).unwrap();
+        assert_eq!(socket.ether_type(), Some(ETHER_TYPE));
+        let frame = frame();
+        let sz = socket.send(&frame).await.unwrap();
+        assert_eq!(sz, frame.len());
+        socket.flush().await.unwrap();
+        let mut buffer = [0u8; 64];
+        let sz = socket.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..sz], &frame[..]);
+        assert!(socket.send(&frame[..10]).await.is_err());
+        drop(socket);
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_hotplug {
+    use super::*;
+    use async_dpdk::Error;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test() {
+        dpdk_setup();
+        let addr = IpAddr::from([10, 2, 3, 9]);
+        net_dev::device_attach("net_ring9", addr).unwrap();
+        assert!(net_dev::device_attach("net_ring8", addr).is_err());
+        assert_eq!(net_dev::mtu(&addr).unwrap(), 1500);
+        let mut events = net_dev::subscribe_events(&addr).unwrap();
+        net_dev::device_detach(&addr).unwrap();
+        // Subscribers are dropped on detaching.
+        assert!(matches!(
+            events.try_recv(),
+            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected)
+        ));
+        assert!(net_dev::device_detach(&addr).is_err());
+    }
+
+    #[test]
+    fn test_close() {
+        dpdk_setup();
+        let addr = IpAddr::from([10, 2, 3, 7]);
+        net_dev::device_attach("net_ring7", addr).unwrap();
+        net_dev::device_start(&addr).unwrap();
+        // The device is stopped before it's closed.
+        net_dev::device_close(&addr).unwrap();
+        assert_eq!(net_dev::device_close(&addr).unwrap_err(), Error::NoDev);
+    }
+}
+
+#[cfg(test)]
+mod test_ping {
+    use super::*;
+    use async_dpdk::icmp;
+    use std::net::IpAddr;
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let rtt = icmp::ping(IpAddr::from([10, 2, 3, 0]), Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(rtt < Duration::from_secs(1));
+        assert!(
+            icmp::ping("fd00::1".parse().unwrap(), Duration::from_secs(1))
+                .await
+                .is_err()
+        );
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_timeout {
+    use super::*;
+    use async_dpdk::Error;
+
+    const MSG: &str = "this is a message";
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1240").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let mut buffer = [0u8; 64];
+        assert!(matches!(
+            server.try_recv_from(&mut buffer),
+            Err(Error::TempUnavail)
+        ));
+        assert!(matches!(
+            server
+                .recv_from_timeout(&mut buffer, Duration::from_millis(10))
+                .await,
+            Err(Error::TimedOut)
+        ));
+        // The datagram is not lost after the cancelled receiving.
+        let sz = client
+            .send_to_timeout(MSG.as_bytes(), "10.2.3.0:1240", Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(sz, MSG.len());
+        let (sz, _addr) = server
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        // Local addresses are resolved without waiting.
+        let sz = client.try_send_to(MSG.as_bytes(), "10.2.3.0:1240").unwrap();
+        assert_eq!(sz, MSG.len());
+        let (sz, _addr) = server
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_batch {
+    use super::*;
+    use std::net::SocketAddr;
+
+    const BATCH: usize = 8;
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1241").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let addr: SocketAddr = "10.2.3.0:1241".parse().unwrap();
+        let data: Vec<[u8; 4]> = (0..BATCH as u32).map(u32::to_be_bytes).collect();
+        let msgs: Vec<(&[u8], SocketAddr)> = data.iter().map(|d| (&d[..], addr)).collect();
+        assert_eq!(client.send_mmsg(&msgs).await.unwrap(), BATCH);
+        client.flush().await.unwrap();
+
+        let mut received = 0;
+        while received < BATCH {
+            let mut storage = [[0u8; 16]; BATCH];
+            let mut bufs: Vec<&mut [u8]> = storage.iter_mut().map(|b| &mut b[..]).collect();
+            let res = server.recv_mmsg(&mut bufs).await.unwrap();
+            assert!(!res.is_empty());
+            for (i, &(sz, _addr)) in res.iter().enumerate() {
+                assert_eq!(&bufs[i][..sz], &data[received + i][..]);
+            }
+            received += res.len();
+        }
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_recv_buffer {
+    use super::*;
+    use async_dpdk::socket::DropPolicy;
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1242").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        assert!(server
+            .set_recv_buffer_size(0, DropPolicy::DropOldest)
+            .is_err());
+        server
+            .set_recv_buffer_size(2, DropPolicy::DropOldest)
+            .unwrap();
+        assert_eq!(
+            server.recv_buffer_size().unwrap(),
+            (2, DropPolicy::DropOldest)
+        );
+        for i in 0..4u8 {
+            let _ = client.send_to(&[i], "10.2.3.0:1242").await.unwrap();
+        }
+        client.flush().await.unwrap();
+        // Wait for the datagrams to arrive.
+        while server.stats().unwrap().received < 4 {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+        let stats = server.stats().unwrap();
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(stats.queued, 2);
+        let stats = client.stats().unwrap();
+        assert_eq!((stats.sent, stats.sent_bytes), (4, 4));
+        let ip = "10.2.3.0".parse().unwrap();
+        let queue_stats = net_dev::queue_stats(&ip).unwrap();
+        assert!(queue_stats.values().map(|q| q.rx_packets).sum::<u64>() >= 4);
+        assert!(queue_stats.values().map(|q| q.tx_packets).sum::<u64>() >= 4);
+        // The oldest datagrams are dropped.
+        let mut buffer = [0u8; 4];
+        let _ = server.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(buffer[0], 2);
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_reuse_port {
+    use super::*;
+    use async_dpdk::socket::SocketOptions;
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let opts = SocketOptions::new().reuse_port(true);
+        let server1 = UdpSocket::bind_with("10.2.3.0:1243", opts).unwrap();
+        let server2 = UdpSocket::bind_with("10.2.3.0:1243", opts).unwrap();
+        assert!(UdpSocket::bind("10.2.3.0:1243").is_err());
+        let clients: Vec<_> = (0..8)
+            .map(|_| UdpSocket::bind("10.2.3.0:0").unwrap())
+            .collect();
+        for client in &clients {
+            let _ = client.send_to(&[1], "10.2.3.0:1243").await.unwrap();
+            client.flush().await.unwrap();
+        }
+        // Every datagram goes to exactly one of the sockets.
+        let received = || server1.stats().unwrap().received + server2.stats().unwrap().received;
+        while received() < 8 {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(received(), 8);
+        drop(server1);
+        drop(server2);
+        // The port is released once all the sockets are closed.
+        let _server = UdpSocket::bind("10.2.3.0:1243").unwrap();
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_multicast {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const GROUP: Ipv4Addr = Ipv4Addr::new(239, 1, 2, 3);
+    const MSG: &str = "this is a multicast message";
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("0.0.0.0:1244").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let iface = Ipv4Addr::new(10, 2, 3, 0);
+        assert!(server
+            .join_multicast_v4(&Ipv4Addr::new(10, 2, 3, 4), &iface)
+            .is_err());
+        server.join_multicast_v4(&GROUP, &iface).unwrap();
+        assert!(server.join_multicast_v4(&GROUP, &iface).is_err());
+        let _ = client.send_to(MSG.as_bytes(), (GROUP, 1244)).await.unwrap();
+        let mut buffer = [0u8; 64];
+        let (sz, _addr) = server
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        // Datagrams to groups not joined are dropped.
+        server.leave_multicast_v4(&GROUP, &iface).unwrap();
+        assert!(server.leave_multicast_v4(&GROUP, &iface).is_err());
+        let _ = client.send_to(MSG.as_bytes(), (GROUP, 1244)).await.unwrap();
+        assert!(server
+            .recv_from_timeout(&mut buffer, Duration::from_millis(100))
+            .await
+            .is_err());
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_gso {
+    use super::*;
+    use std::net::IpAddr;
+
+    const LEN: usize = 4000; // > Ethernet MTU
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let addr = IpAddr::from([10, 2, 3, 0]);
+        net_dev::set_gso(&addr, true).unwrap();
+        net_dev::set_gro(&addr, true).unwrap();
+        assert!(net_dev::gso(&addr).unwrap());
+        assert!(net_dev::gro(&addr).unwrap());
+        let server = UdpSocket::bind("10.2.3.0:1245").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let buffer: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+        let sz = client.send_to(&buffer, "10.2.3.0:1245").await.unwrap();
+        assert_eq!(sz, LEN);
+        let mut recv_buf = [0u8; LEN];
+        let (sz, _addr) = server
+            .recv_from_timeout(&mut recv_buf, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&recv_buf[..sz], &buffer[..]);
+        net_dev::set_gso(&addr, false).unwrap();
+        net_dev::set_gro(&addr, false).unwrap();
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_capture {
+    use super::*;
+
+    const MSG: &str = "captured";
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let dir = env::temp_dir();
+        let dev_path = dir.join("async-dpdk-test-dev.pcapng");
+        let sock_path = dir.join("async-dpdk-test-sock.pcapng");
+        let ip = "10.2.3.0".parse().unwrap();
+        net_dev::start_capture(&ip, &dev_path).unwrap();
+        assert!(net_dev::start_capture(&ip, &dev_path).is_err());
+        let server = UdpSocket::bind("10.2.3.0:1246").unwrap();
+        server.start_capture(&sock_path).unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let _ = client
+            .send_to(MSG.as_bytes(), "10.2.3.0:1246")
+            .await
+            .unwrap();
+        let mut buffer = [0u8; 16];
+        let _ = server.recv_from(&mut buffer).await.unwrap();
+        server.stop_capture().unwrap();
+        net_dev::stop_capture(&ip).unwrap();
+        assert!(net_dev::stop_capture(&ip).is_err());
+        // The headers take 48 bytes, and each frame is captured once or twice by the device.
+        let dev_len = std::fs::metadata(&dev_path).unwrap().len();
+        let sock_len = std::fs::metadata(&sock_path).unwrap().len();
+        assert!(sock_len > 48);
+        assert!(dev_len >= sock_len);
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_dump {
+    use super::*;
+    use async_dpdk::dump::{self, Direction, Filter, HookId};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    const MSG: &str = "dumped";
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1265").unwrap();
+        let dumped = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&dumped);
+        let filter = Filter::new().direction(Direction::Inbound).l4_port(1265);
+        let hook = dump::register(filter, move |info| {
+            assert_eq!(info.dst_port, Some(1265));
+            let _ = counter.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap();
+        // A hook removing itself once called.
+        let once: Arc<Mutex<Option<HookId>>> = Arc::default();
+        let id = Arc::clone(&once);
+        let oneshot = dump::register(filter, move |_| {
+            if let Some(id) = id.lock().unwrap().take() {
+                dump::unregister(id).unwrap();
+            }
+        })
+        .unwrap();
+        *once.lock().unwrap() = Some(oneshot);
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let mut buffer = [0u8; 16];
+        for _ in 0..2 {
+            let _ = client
+                .send_to(MSG.as_bytes(), "10.2.3.0:1265")
+                .await
+                .unwrap();
+            let _ = server.recv_from(&mut buffer).await.unwrap();
+        }
+        assert!(dump::unregister(oneshot).is_err());
+        dump::unregister(hook).unwrap();
+        assert!(dump::unregister(hook).is_err());
+        assert!(dumped.load(Ordering::Relaxed) >= 2);
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_address {
+    use super::*;
+    use async_dpdk::net_dev::IfAddr;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    const MSG: &str = "to alias";
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let addr = IpAddr::from([10, 2, 3, 0]);
+        let alias = IpAddr::from([10, 2, 3, 100]);
+        assert!(net_dev::add_address(&addr, addr).is_err());
+        net_dev::add_address(&addr, alias).unwrap();
+        assert_eq!(
+            net_dev::addresses(&alias).unwrap(),
+            vec![addr.into(), alias.into()]
+        );
+        let server = UdpSocket::bind("10.2.3.100:1247").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let _ = client
+            .send_to(MSG.as_bytes(), "10.2.3.100:1247")
+            .await
+            .unwrap();
+        let mut buffer = [0u8; 16];
+        let (sz, client_addr) = server
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        assert_eq!(client_addr.ip(), addr);
+        drop(server);
+        net_dev::remove_address(&alias).unwrap();
+        assert!(net_dev::remove_address(&alias).is_err());
+        assert_eq!(net_dev::addresses(&addr).unwrap(), vec![addr.into()]);
+        assert!(UdpSocket::bind(SocketAddr::new(alias, 1247)).is_err());
+        net_dev::device_stop_all().unwrap();
+    }
+
+    #[test]
+    fn test_if_addr() {
+        let subnet: IfAddr = "10.2.4.1/24".parse().unwrap();
+        assert_eq!(subnet.network(), IpAddr::from([10, 2, 4, 0]));
+        assert_eq!(subnet.broadcast(), Some(Ipv4Addr::new(10, 2, 4, 255)));
+        assert!(subnet.contains(IpAddr::from([10, 2, 4, 9])));
+        assert!(!subnet.contains(IpAddr::from([10, 2, 5, 9])));
+        let host: IfAddr = "10.2.4.1".parse().unwrap();
+        assert_eq!(host.prefix_len, 32);
+        assert_eq!(host.broadcast(), None);
+        assert!("10.2.4.1/33".parse::<IfAddr>().is_err());
+        assert!("fd00::1/129".parse::<IfAddr>().is_err());
+        assert_eq!("fd00::1/64".parse::<IfAddr>().unwrap().broadcast(), None);
+    }
+
+    #[tokio::test]
+    async fn test_subnet_broadcast() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let addr = IpAddr::from([10, 2, 3, 0]);
+        let local: IfAddr = "10.2.4.1/24".parse().unwrap();
+        net_dev::add_address(&addr, local).unwrap();
+        let server = UdpSocket::bind("10.2.4.1:1248").unwrap();
+        let client = UdpSocket::bind("10.2.4.1:0").unwrap();
+        let _ = client
+            .send_to(MSG.as_bytes(), "10.2.4.255:1248")
+            .await
+            .unwrap();
+        let mut buffer = [0u8; 16];
+        let (sz, _addr) = server
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        drop(server);
+        drop(client);
+        net_dev::remove_address(&local.ip).unwrap();
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_ip_options {
+    use super::*;
+    use async_dpdk::udp::SendOptions;
+
+    const MSG: &str = "with options";
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1249").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        assert_eq!(client.ttl(), 64);
+        assert!(client.set_ttl(0).is_err());
+        client.set_ttl(16).unwrap();
+        client.set_tos(0xb8);
+        let _ = client
+            .send_to(MSG.as_bytes(), "10.2.3.0:1249")
+            .await
+            .unwrap();
+        let mut buffer = [0u8; 16];
+        let (sz, _addr, meta) = server.recv_from_with_meta(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        assert_eq!((meta.ttl, meta.tos), (16, 0xb8));
+
+        let opts = SendOptions::new().ttl(1).tos(0x20);
+        let _ = client
+            .send_to_with(MSG.as_bytes(), "10.2.3.0:1249", opts)
+            .await
+            .unwrap();
+        let (_sz, _addr, meta) = server.recv_from_with_meta(&mut buffer).await.unwrap();
+        assert_eq!((meta.ttl, meta.tos), (1, 0x20));
+        assert!(client
+            .send_to_with(MSG.as_bytes(), "10.2.3.0:1249", SendOptions::new().ttl(0))
+            .await
+            .is_err());
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_source_address {
+    use super::*;
+    use std::net::{IpAddr, SocketAddr};
+
+    const MSG: &str = "from any";
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1250").unwrap();
+        let client = UdpSocket::bind("0.0.0.0:1251").unwrap();
+        assert_eq!(
+            client.local_addr_for("10.2.3.0:1250").unwrap(),
+            SocketAddr::from(([10, 2, 3, 0], 1251))
+        );
+        assert_eq!(
+            client.local_addr_for("[fd00::1]:1250").unwrap().ip(),
+            "fd00::1".parse::<IpAddr>().unwrap()
+        );
+        let _ = client
+            .send_to(MSG.as_bytes(), "10.2.3.0:1250")
+            .await
+            .unwrap();
+        let mut buffer = [0u8; 16];
+        let (sz, client_addr) = server
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        assert_eq!(client_addr, SocketAddr::from(([10, 2, 3, 0], 1251)));
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_socket_addr {
+    use super::*;
+    use std::net::SocketAddr;
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1252").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        assert_eq!(server.local_addr(), SocketAddr::from(([10, 2, 3, 0], 1252)));
+        assert_ne!(client.local_addr().port(), 0);
+        assert!(client.peer_addr().is_err());
+        assert_eq!(client.to_string(), format!("udp {}", client.local_addr()));
+
+        client.connect("10.2.3.0:1252").await.unwrap();
+        assert_eq!(client.peer_addr().unwrap(), server.local_addr());
+        assert_eq!(
+            client.to_string(),
+            format!("udp {} -> {}", client.local_addr(), server.local_addr())
+        );
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_poll {
+    use super::*;
+    use async_dpdk::tcp::{TcpListener, TcpStream};
+    use std::{future, net::SocketAddr};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const MSG: &str = "polled message";
+
+    #[tokio::test]
+    async fn test_udp() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1253").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let target = SocketAddr::from(([10, 2, 3, 0], 1253));
+        let sz = future::poll_fn(|cx| client.poll_send_to(cx, MSG.as_bytes(), target))
+            .await
+            .unwrap();
+        assert_eq!(sz, MSG.len());
+        let mut buffer = [0u8; 20];
+        let (sz, addr) = future::poll_fn(|cx| server.poll_recv_from(cx, &mut buffer))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        assert_eq!(addr, client.local_addr());
+        net_dev::device_stop_all().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tcp() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let listener = TcpListener::bind("10.2.3.0:1254").unwrap();
+        let server = task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0u8; MSG.len()];
+            stream.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(&buffer, MSG.as_bytes());
+            stream.write_all(&buffer).await.unwrap();
+            let mut rest = Vec::new();
+            assert_eq!(stream.read_to_end(&mut rest).await.unwrap(), 0);
+        });
+        time::sleep(Duration::from_millis(5)).await;
+        let mut stream = TcpStream::connect("10.2.3.0:1254").await.unwrap();
+        stream.write_all(MSG.as_bytes()).await.unwrap();
+        let mut buffer = [0u8; MSG.len()];
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, MSG.as_bytes());
+        AsyncWriteExt::shutdown(&mut stream).await.unwrap();
+        server.await.unwrap();
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_cancel {
+    use super::*;
+    use std::sync::Arc;
+
+    const MSG: &str = "after cancel";
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = Arc::new(UdpSocket::bind("10.2.3.0:1255").unwrap());
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let mut buffer = [0u8; 20];
+        // Receives cancelled by `select!` and timeouts.
+        for _ in 0..3 {
+            tokio::select! {
+                res = server.recv_from(&mut buffer) => panic!("unexpected {res:?}"),
+                () = time::sleep(Duration::from_millis(1)) => {}
+            }
+        }
+        assert!(server
+            .recv_from_timeout(&mut buffer, Duration::from_millis(1))
+            .await
+            .is_err());
+        // A receiving task aborted.
+        let aborted = task::spawn({
+            let server = Arc::clone(&server);
+            async move {
+                let mut buffer = [0u8; 20];
+                server.recv_from(&mut buffer).await.unwrap()
+            }
+        });
+        task::yield_now().await;
+        aborted.abort();
+        assert!(aborted.await.unwrap_err().is_cancelled());
+
+        let _ = client
+            .send_to(MSG.as_bytes(), "10.2.3.0:1255")
+            .await
+            .unwrap();
+        let (sz, _) = server
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], MSG.as_bytes());
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_select {
+    use super::*;
+    use async_dpdk::udp::select_readable;
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let sockets = [
+            UdpSocket::bind("10.2.3.0:1256").unwrap(),
+            UdpSocket::bind("10.2.3.0:1257").unwrap(),
+            UdpSocket::bind("10.2.3.0:1258").unwrap(),
+        ];
+        let refs: Vec<_> = sockets.iter().collect();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        assert!(select_readable(&[]).await.is_err());
+        assert!(
+            time::timeout(Duration::from_millis(10), select_readable(&refs))
+                .await
+                .is_err()
+        );
+
+        let _ = client.send_to(b"1", "10.2.3.0:1257").await.unwrap();
+        let _ = client.send_to(b"2", "10.2.3.0:1258").await.unwrap();
+        time::sleep(Duration::from_millis(10)).await;
+        let readable = time::timeout(Duration::from_secs(1), select_readable(&refs))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(readable, [1, 2]);
+        let mut buffer = [0u8; 4];
+        for i in readable {
+            let (sz, _) = sockets[i].try_recv_from(&mut buffer).unwrap();
+            assert_eq!(&buffer[..sz], i.to_string().as_bytes());
+        }
+        let _ = client.send_to(b"0", "10.2.3.0:1256").await.unwrap();
+        time::timeout(Duration::from_secs(1), sockets[0].readable())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(sockets[0].try_recv_from(&mut buffer).is_ok());
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_bpf {
+    use super::*;
+    use async_dpdk::bpf::{BpfInsn, BpfProgram};
+    use std::net::IpAddr;
+
+    /// Accepts packets starting with `first`.
+    fn starts_with(first: u8) -> BpfProgram {
+        BpfProgram::new(vec![
+            BpfInsn::new(0x30, 0, 0, 0),
+            BpfInsn::new(0x15, 0, 1, u32::from(first)),
+            BpfInsn::new(0x06, 0, 0, 0xffff),
+            BpfInsn::new(0x06, 0, 0, 0),
+        ])
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1259").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        server.set_filter(Some(starts_with(b'a'))).unwrap();
+        let _ = client.send_to(b"xyz", "10.2.3.0:1259").await.unwrap();
+        let _ = client.send_to(b"abc", "10.2.3.0:1259").await.unwrap();
+        let mut buffer = [0u8; 4];
+        let (sz, _) = time::timeout(Duration::from_secs(1), server.recv_from(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buffer[..sz], b"abc");
+        assert!(server.try_recv_from(&mut buffer).is_err());
+        assert_eq!(server.stats().unwrap().filtered, 1);
+
+        server.set_filter(None).unwrap();
+        let _ = client.send_to(b"xyz", "10.2.3.0:1259").await.unwrap();
+        let (sz, _) = time::timeout(Duration::from_secs(1), server.recv_from(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buffer[..sz], b"xyz");
+
+        let addr: IpAddr = "10.2.3.0".parse().unwrap();
+        net_dev::set_rx_filter(&addr, Some(starts_with(0))).unwrap();
+        net_dev::set_rx_filter(&addr, None).unwrap();
+        let unknown: IpAddr = "10.9.9.9".parse().unwrap();
+        assert!(net_dev::set_rx_filter(&unknown, None).is_err());
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_reorder {
+    use super::*;
+    use async_dpdk::reorder;
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1260").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        assert!(server.set_reorder(Some(6)).is_err());
+        server.set_reorder(Some(16)).unwrap();
+        let field = reorder::seqn_field().unwrap();
+        for seqn in [0u32, 2, 1] {
+            let mut m = client.alloc_mbuf().unwrap();
+            m.append(1).unwrap()[0] = b'0' + seqn as u8;
+            m.set_dynfield(field, seqn);
+            let _ = client.send_mbuf_to(m, "10.2.3.0:1260").await.unwrap();
+        }
+        let mut buffer = [0u8; 4];
+        for expected in [b"0", b"1", b"2"] {
+            let (sz, _) = time::timeout(Duration::from_secs(1), server.recv_from(&mut buffer))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(&buffer[..sz], expected);
+        }
+        // The buffer is replaced while reordering.
+        server.set_reorder(Some(32)).unwrap();
+        server.set_reorder(None).unwrap();
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+mod test_policer {
+    use super::*;
+    use async_dpdk::meter::{MeterProfile, PoliceAction, Policer};
+
+    #[tokio::test]
+    async fn test() {
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = UdpSocket::bind("10.2.3.0:1261").unwrap();
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+        let invalid = MeterProfile::SrTcm {
+            cir: 0,
+            cbs: 0,
+            ebs: 0,
+        };
+        assert!(client.set_policer(Some(Policer::new(invalid))).is_err());
+        assert_eq!(client.policer().unwrap(), None);
+
+        let profile = MeterProfile::SrTcm {
+            cir: 1,
+            cbs: 1,
+            ebs: 1,
+        };
+        let policer = Policer::new(profile).yellow(PoliceAction::Drop);
+        client.set_policer(Some(policer)).unwrap();
+        assert_eq!(client.policer().unwrap(), Some(policer));
+        // Datagrams delivered to local sockets are not policed.
+        let _ = client.send_to(b"hello", "10.2.3.0:1261").await.unwrap();
+        let mut buffer = [0u8; 8];
+        let (sz, _) = time::timeout(Duration::from_secs(1), server.recv_from(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buffer[..sz], b"hello");
+        assert_eq!(client.stats().unwrap().policed, 0);
+
+        client.set_policer(None).unwrap();
+        assert_eq!(client.policer().unwrap(), None);
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_share {
+    use super::*;
+    use async_dpdk::tcp::TcpStream;
+    use std::sync::Arc;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test() {
+        assert_send_sync::<UdpSocket>();
+        assert_send_sync::<RawSocket>();
+        assert_send_sync::<TcpStream>();
+        dpdk_setup();
+        net_dev::device_start_all().unwrap();
+        let server = Arc::new(UdpSocket::bind("10.2.3.0:1262").unwrap());
+        let cloned = server.try_clone().unwrap();
+        assert_eq!(cloned.local_addr(), server.local_addr());
+        let client = UdpSocket::bind("10.2.3.0:0").unwrap();
+
+        // Tasks on different threads receive through an `Arc` and a cloned handle at once.
+        let shared = task::spawn({
+            let server = Arc::clone(&server);
+            async move {
+                let mut buffer = [0u8; 8];
+                let (sz, _) = server.recv_from(&mut buffer).await.unwrap();
+                buffer[..sz].to_vec()
+            }
+        });
+        let cloned = task::spawn(async move {
+            let mut buffer = [0u8; 8];
+            let (sz, _) = cloned.recv_from(&mut buffer).await.unwrap();
+            (buffer[..sz].to_vec(), cloned)
+        });
+        time::sleep(Duration::from_millis(5)).await;
+        for msg in [b"first", b"other"] {
+            let _ = client.send_to(msg, "10.2.3.0:1262").await.unwrap();
+        }
+        let first = time::timeout(Duration::from_secs(1), shared)
+            .await
+            .unwrap()
+            .unwrap();
+        let (second, cloned) = time::timeout(Duration::from_secs(1), cloned)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut received = vec![first, second];
+        received.sort_unstable();
+        assert_eq!(received, [b"first".to_vec(), b"other".to_vec()]);
+
+        // The socket is closed with the last handle.
+        drop(server);
+        let _ = client.send_to(b"again", "10.2.3.0:1262").await.unwrap();
+        let mut buffer = [0u8; 8];
+        let (sz, _) = cloned
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], b"again");
+        assert!(UdpSocket::bind("10.2.3.0:1262").is_err());
+        drop(cloned);
+        drop(UdpSocket::bind("10.2.3.0:1262").unwrap());
+        net_dev::device_stop_all().unwrap();
+    }
+}
+
+#[cfg(feature = "harness")]
+mod test_harness {
+    use super::*;
+    use async_dpdk::harness::Link;
+    use std::net::{IpAddr, SocketAddr};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test() {
+        dpdk_setup();
+        let (a, b) = (IpAddr::from([10, 9, 0, 1]), IpAddr::from([10, 9, 0, 2]));
+        let addr = |s: &str| s.parse::<net_dev::IfAddr>().unwrap();
+        let link = Link::create("link0", 1, addr("10.9.0.1/24"), addr("10.9.0.2/24")).unwrap();
+        // Addresses assigned already are refused.
+        assert!(Link::create("link1", 1, a, addr("10.9.0.3/24")).is_err());
+        net_dev::device_start(&a).unwrap();
+        net_dev::device_start(&b).unwrap();
+
+        // Datagrams cross the link both ways, resolving the peer through ARP first.
+        let server = UdpSocket::bind("10.9.0.2:1263").unwrap();
+        let client = UdpSocket::bind("10.9.0.1:0").unwrap();
+        let _ = client.send_to(b"ping", "10.9.0.2:1263").await.unwrap();
+        let mut buffer = [0u8; 8];
+        let (sz, peer) = server
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], b"ping");
+        assert_eq!(peer, client.local_addr());
+        let _ = server.send_to(b"pong", peer).await.unwrap();
+        let (sz, peer) = client
+            .recv_from_timeout(&mut buffer, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(&buffer[..sz], b"pong");
+        assert_eq!(peer, SocketAddr::from(([10, 9, 0, 2], 1263)));
+
+        // Each port receives what the other one sends, but nothing it sends itself.
+        let (stats_a, stats_b) = (net_dev::stats(&a).unwrap(), net_dev::stats(&b).unwrap());
+        assert_eq!(stats_a.opackets, stats_b.ipackets);
+        assert_eq!(stats_b.opackets, stats_a.ipackets);
+        assert!(stats_a.opackets >= 2);
+
+        drop((client, server));
+        drop(link);
+        assert!(net_dev::stats(&a).is_err());
+        assert!(net_dev::stats(&b).is_err());
+    }
 }
@@ -0,0 +1,62 @@
+/// Test agents run as DPDK services on service lcores.
+use async_dpdk::{
+    eal::{self, *},
+    net_dev::{self, DevConfig, TxExec},
+    udp::UdpSocket,
+};
+use std::{sync::Once, time::Duration};
+use tokio::time;
+
+static SETUP: Once = Once::new();
+
+fn dpdk_setup() {
+    SETUP.call_once(|| {
+        env_logger::init();
+        eal::Config::new()
+            .no_hugepages(true)
+            .no_pci(true)
+            .corelist("0-1")
+            .unwrap()
+            .service_corelist("1")
+            .unwrap()
+            .vdev(Vdev::Ring(0))
+            .max_queues(1)
+            .dev_config(0, DevConfig::new().tx_exec(TxExec::Service(1)))
+            .device_probe(&["10.2.5.0"])
+            .unwrap()
+            .enter()
+            .unwrap();
+    })
+}
+
+#[tokio::test]
+async fn test_tx_service() {
+    dpdk_setup();
+    net_dev::device_start_all().unwrap();
+    let server = UdpSocket::bind("10.2.5.0:1234").unwrap();
+    let client = UdpSocket::bind("10.2.5.0:0").unwrap();
+    for _ in 0..3 {
+        let sz = client.send_to(b"ping", "10.2.5.0:1234").await.unwrap();
+        assert_eq!(sz, 4);
+        let mut buffer = [0u8; 8];
+        let (sz, from) = time::timeout(Duration::from_secs(1), server.recv_from(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buffer[..sz], b"ping");
+        assert_eq!(from, client.local_addr());
+    }
+    // The worker is dropped on the service lcore, and the devices are started again with a new
+    // one.
+    net_dev::device_stop_all().unwrap();
+    net_dev::device_start_all().unwrap();
+    let sz = client.send_to(b"pong", "10.2.5.0:1234").await.unwrap();
+    assert_eq!(sz, 4);
+    let mut buffer = [0u8; 8];
+    let (sz, _) = time::timeout(Duration::from_secs(1), server.recv_from(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buffer[..sz], b"pong");
+    net_dev::device_stop_all().unwrap();
+}
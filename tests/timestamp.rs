@@ -0,0 +1,66 @@
+/// Test timestamping received packets and sent datagrams.
+use async_dpdk::{
+    eal::{self, *},
+    net_dev::{self, DevConfig},
+    timestamp::Timestamp,
+    udp::{SendOptions, UdpSocket},
+};
+use std::{sync::Once, time::Duration};
+use tokio::time;
+
+static SETUP: Once = Once::new();
+
+fn dpdk_setup() {
+    SETUP.call_once(|| {
+        env_logger::init();
+        eal::Config::new()
+            .no_hugepages(true)
+            .no_pci(true)
+            .vdev(Vdev::Ring(0))
+            .max_queues(1)
+            .dev_config(0, DevConfig::new().rx_timestamp(true))
+            .device_probe(&["10.2.8.0"])
+            .unwrap()
+            .enter()
+            .unwrap();
+    })
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_timestamp() {
+    dpdk_setup();
+    net_dev::device_start_all().unwrap();
+    let server = UdpSocket::bind("10.2.8.0:1234").unwrap();
+    let client = UdpSocket::bind("10.2.8.0:0").unwrap();
+    let before = Timestamp::now();
+    for opts in [SendOptions::new().tx_timestamp(), SendOptions::new()] {
+        let _ = client
+            .send_to_with(b"stamped", "10.2.8.0:1234", opts)
+            .await
+            .unwrap();
+    }
+
+    // Both datagrams are timestamped on receipt, with the TSC as ring ports have no clock.
+    let mut buffer = [0u8; 8];
+    for _ in 0..2 {
+        let (_sz, _addr, meta) = server.recv_from_with_meta(&mut buffer).await.unwrap();
+        let received = meta.rx_timestamp.unwrap();
+        assert!(matches!(received, Timestamp::Tsc(_)));
+        assert!(received.duration_since(before).is_some());
+    }
+
+    // Only the datagram requesting it is reported once sent.
+    let sent = time::timeout(Duration::from_secs(1), client.recv_tx_timestamp())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(sent.id, 0);
+    assert!(sent.timestamp.duration_since(before).is_some());
+    assert!(
+        time::timeout(Duration::from_millis(50), client.recv_tx_timestamp())
+            .await
+            .is_err()
+    );
+    assert!(net_dev::read_clock(&"10.2.8.0".parse().unwrap()).is_err());
+    net_dev::device_stop_all().unwrap();
+}